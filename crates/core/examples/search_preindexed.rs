@@ -3,8 +3,8 @@ use rand::seq::SliceRandom;
 use stract::{
     bangs::Bangs,
     config::{
-        ApiConfig, ApiThresholds, CollectorConfig, CorrectionConfig, LLMConfig, SnippetConfig,
-        WidgetsConfig,
+        ApiConfig, ApiThresholds, CollectorConfig, CorrectionConfig, LLMConfig, RankingConfig,
+        SnippetConfig, ThreadsConfig, WidgetsConfig,
     },
     image_store::Image,
     index::Index,
@@ -16,22 +16,24 @@ use stract::{
 struct Searcher(LocalSearcher<Index>);
 
 impl stract::searcher::distributed::SearchClient for Searcher {
-    async fn search_initial(
-        &self,
-        query: &SearchQuery,
-    ) -> Vec<stract::searcher::InitialSearchResultShard> {
+    async fn search_initial(&self, query: &SearchQuery) -> stract::searcher::InitialSearchResults {
         let res = self.0.search_initial(query, true).unwrap();
 
-        vec![stract::searcher::InitialSearchResultShard {
-            local_result: res,
-            shard: ShardId::new(0),
-        }]
+        stract::searcher::InitialSearchResults {
+            shards: vec![stract::searcher::InitialSearchResultShard {
+                local_result: res,
+                shard: ShardId::new(0),
+            }],
+            failures: Vec::new(),
+            num_stages: 1,
+        }
     }
 
     async fn retrieve_webpages(
         &self,
         top_websites: &[(usize, stract::searcher::ScoredWebsitePointer)],
         query: &str,
+        snippet: &stract::snippet::SnippetOptions,
     ) -> Vec<(usize, stract::ranking::pipeline::RetrievedWebpageRanking)> {
         let pointers = top_websites
             .iter()
@@ -40,7 +42,7 @@ impl stract::searcher::distributed::SearchClient for Searcher {
 
         let res = self
             .0
-            .retrieve_websites(&pointers, query)
+            .retrieve_websites(&pointers, query, snippet)
             .unwrap()
             .into_iter()
             .zip(top_websites.iter().map(|(i, p)| (*i, p.website.clone())))
@@ -100,6 +102,8 @@ pub async fn main() {
         crossencoder_model_path: None,
         lambda_model_path: None,
         spell_checker_path: Some("data/web_spell".to_string()),
+        product_store_path: None,
+        geo_store_path: None,
         bangs_path: "data/bangs.json".to_string(),
         summarizer_path: "data/summarizer".to_string(),
         query_store_db_host: None,
@@ -113,11 +117,16 @@ pub async fn main() {
             calculator_fetch_currencies_exchange: false,
         },
         correction_config: CorrectionConfig::default(),
+        ranking: RankingConfig::default(),
+        abuse: None,
+        session: None,
+        instant: Default::default(),
         llm: LLMConfig {
             api_base: "http://localhost:4000/v1".to_string(),
             model: "data/mistral-7b-instruct-v0.2.Q4_K_M.gguf".to_string(),
             api_key: None,
         },
+        threads: ThreadsConfig::default(),
     };
 
     let mut queries = stract::autosuggest::Autosuggest::load_csv(&config.queries_csv_path)
@@ -142,7 +151,7 @@ pub async fn main() {
     let searcher = Searcher(searcher);
 
     let searcher: ApiSearcher<Searcher, LiveSearcher> =
-        ApiSearcher::new(searcher, None, None, None, bangs, config);
+        ApiSearcher::new(searcher, None, None, None, bangs, config).unwrap();
 
     for query in &queries {
         let mut desc = "search '".to_string();
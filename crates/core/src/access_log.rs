@@ -0,0 +1,124 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Sampled log of when documents were last shown in the search results.
+//!
+//! Only the node id of the page and the day it was last retrieved is stored.
+//! Nothing about the query or the user is recorded, and only a sample of the
+//! retrievals are logged. The log is used to demote documents that are never
+//! retrieved to the archive tier when the index is split into tiers.
+
+use std::path::Path;
+
+use url::Url;
+
+use crate::{
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    webgraph::{Node, NodeID},
+};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+pub struct AccessLog {
+    store: RocksDbStore<NodeID, u64>,
+    sample_rate: f64,
+}
+
+impl AccessLog {
+    /// Open the log for writing. Each retrieval is recorded with probability `sample_rate`.
+    pub fn open<P: AsRef<Path>>(path: P, sample_rate: f64) -> Self {
+        Self {
+            store: RocksDbStore::open(path),
+            sample_rate: sample_rate.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            store: RocksDbStore::open_read_only(path),
+            sample_rate: 0.0,
+        }
+    }
+
+    /// Record that the page was retrieved now, if it is sampled.
+    pub fn record(&self, url: &str) {
+        if self.sample_rate <= 0.0 || rand::random::<f64>() >= self.sample_rate {
+            return;
+        }
+
+        let Ok(url) = Url::parse(url) else {
+            return;
+        };
+
+        let now = chrono::Utc::now().timestamp().max(0) as u64;
+        self.record_at(Node::from(&url).id(), now);
+    }
+
+    fn record_at(&self, page: NodeID, timestamp: u64) {
+        let day = (timestamp / SECONDS_PER_DAY) * SECONDS_PER_DAY;
+
+        if self.store.get(&page).map(|prev| prev < day).unwrap_or(true) {
+            self.store.insert(page, day);
+        }
+    }
+
+    /// Unix timestamp of the start of the day where the page was last retrieved.
+    pub fn last_access(&self, page: &NodeID) -> Option<u64> {
+        self.store.get(page)
+    }
+
+    pub fn flush(&self) {
+        self.store.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_latest_day() {
+        let log = AccessLog::open(crate::gen_temp_path(), 1.0);
+        let page = Node::from("https://www.example.com/a").id();
+
+        log.record_at(page, 10 * SECONDS_PER_DAY + 100);
+        assert_eq!(log.last_access(&page), Some(10 * SECONDS_PER_DAY));
+
+        log.record_at(page, 5 * SECONDS_PER_DAY);
+        assert_eq!(log.last_access(&page), Some(10 * SECONDS_PER_DAY));
+
+        log.record_at(page, 12 * SECONDS_PER_DAY + 1);
+        assert_eq!(log.last_access(&page), Some(12 * SECONDS_PER_DAY));
+
+        log.record("https://www.example.com/b");
+        assert!(log
+            .last_access(&Node::from("https://www.example.com/b").id())
+            .is_some());
+        assert!(log
+            .last_access(&Node::from("https://www.example.com/c").id())
+            .is_none());
+    }
+
+    #[test]
+    fn not_sampled() {
+        let log = AccessLog::open(crate::gen_temp_path(), 0.0);
+        log.record("https://www.example.com/");
+
+        assert!(log
+            .last_access(&Node::from("https://www.example.com/").id())
+            .is_none());
+    }
+}
@@ -0,0 +1,543 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Detection of abusive clients of the search api, e.g. scraping farms.
+//!
+//! Every search request is attributed to the ip of the client and, if present,
+//! its api key. The recent requests of each client are checked against a set of
+//! signatures (request rate, query entropy, deep pagination and the same query
+//! being sent by many different clients) and the configured action of the most
+//! severe signature is applied to the request.
+//!
+//! Like the user count, neither ips, keys nor queries are stored in the clear.
+//! They are hashed with a random salt and only kept for the duration of the window.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use axum::{
+    body::Body,
+    extract,
+    http::{header, HeaderValue, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+};
+use ring::{digest, rand::SecureRandom};
+
+use crate::config::{AbuseAction, AbuseConfig};
+
+use super::{search::ApiSearchQuery, State};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Set on responses to clients that should solve a captcha.
+pub const CHALLENGE_HEADER: &str = "x-stract-challenge";
+
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signature {
+    Rate,
+    LowEntropy,
+    SharedQuery,
+    DeepPagination,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Verdict {
+    pub signatures: Vec<Signature>,
+    pub action: Option<AbuseAction>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ClientId {
+    Key(u64),
+    Ip(u64),
+}
+
+#[derive(Default)]
+struct ClientHistory {
+    queries: VecDeque<(Instant, u64)>,
+    blocked_until: Option<Instant>,
+}
+
+impl ClientHistory {
+    fn entropy(&self) -> f64 {
+        let mut counts: HashMap<u64, usize> = HashMap::new();
+        for (_, query) in &self.queries {
+            *counts.entry(*query).or_default() += 1;
+        }
+
+        let total = self.queries.len() as f64;
+
+        counts
+            .values()
+            .map(|count| {
+                let p = *count as f64 / total;
+                -p * p.log2()
+            })
+            .sum()
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    clients: HashMap<ClientId, ClientHistory>,
+    queries: HashMap<u64, VecDeque<(Instant, ClientId)>>,
+    last_cleanup: Option<Instant>,
+}
+
+pub struct AbuseDetector {
+    config: AbuseConfig,
+    window: Duration,
+    salt: [u8; digest::SHA256_OUTPUT_LEN],
+    trusted_keys: HashSet<u64>,
+    inner: Mutex<Inner>,
+}
+
+impl AbuseDetector {
+    pub fn new(config: AbuseConfig) -> Result<Self> {
+        let mut salt = [0u8; digest::SHA256_OUTPUT_LEN];
+        ring::rand::SystemRandom::new()
+            .fill(&mut salt)
+            .map_err(|_| anyhow!("failed to generate salt"))?;
+
+        let mut detector = Self {
+            window: Duration::from_secs(config.window_sec),
+            config,
+            salt,
+            trusted_keys: HashSet::new(),
+            inner: Mutex::new(Inner::default()),
+        };
+
+        detector.trusted_keys = detector
+            .config
+            .trusted_keys
+            .iter()
+            .map(|key| detector.hash(key.as_bytes()))
+            .collect();
+
+        Ok(detector)
+    }
+
+    fn hash(&self, bytes: &[u8]) -> u64 {
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        ctx.update(&self.salt);
+        ctx.update(bytes);
+        let digest = ctx.finish();
+
+        let mut res = [0u8; 8];
+        res.copy_from_slice(&digest.as_ref()[..8]);
+        u64::from_le_bytes(res)
+    }
+
+    fn hash_query(&self, query: &str) -> u64 {
+        let normalized = query
+            .split_whitespace()
+            .map(|term| term.to_lowercase())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        self.hash(normalized.as_bytes())
+    }
+
+    fn action(&self, signature: Signature) -> AbuseAction {
+        let actions = &self.config.actions;
+
+        match signature {
+            Signature::Rate => actions.rate,
+            Signature::LowEntropy => actions.low_entropy,
+            Signature::SharedQuery => actions.shared_query,
+            Signature::DeepPagination => actions.deep_pagination,
+        }
+    }
+
+    pub fn observe(&self, key: Option<&str>, ip: IpAddr, query: &str, page: usize) -> Verdict {
+        self.observe_at(key, ip, query, page, Instant::now())
+    }
+
+    fn observe_at(
+        &self,
+        key: Option<&str>,
+        ip: IpAddr,
+        query: &str,
+        page: usize,
+        now: Instant,
+    ) -> Verdict {
+        let key = key.map(|key| self.hash(key.as_bytes()));
+
+        if let Some(key) = key {
+            if self.trusted_keys.contains(&key) {
+                return Verdict::default();
+            }
+        }
+
+        let ip = ClientId::Ip(self.hash(ip.to_string().as_bytes()));
+        let clients: Vec<_> = key.map(ClientId::Key).into_iter().chain([ip]).collect();
+        // the same query from many keys is suspicious, but many
+        // users behind the same ip is not.
+        let sharing_client = clients[0];
+
+        let query = self.hash_query(query);
+
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        self.maybe_cleanup(&mut inner, now);
+
+        let mut verdict = Verdict::default();
+
+        for client in &clients {
+            let history = inner.clients.entry(*client).or_default();
+
+            if history.blocked_until.is_some_and(|until| until > now) {
+                verdict.action = Some(AbuseAction::Block);
+                return verdict;
+            }
+
+            while history
+                .queries
+                .front()
+                .is_some_and(|(time, _)| now.duration_since(*time) > self.window)
+            {
+                history.queries.pop_front();
+            }
+
+            history.queries.push_back((now, query));
+
+            if history.queries.len() > self.config.max_queries
+                && !verdict.signatures.contains(&Signature::Rate)
+            {
+                verdict.signatures.push(Signature::Rate);
+            }
+
+            if history.queries.len() >= self.config.min_queries_for_entropy
+                && history.entropy() < self.config.min_entropy
+                && !verdict.signatures.contains(&Signature::LowEntropy)
+            {
+                verdict.signatures.push(Signature::LowEntropy);
+            }
+        }
+
+        let senders = inner.queries.entry(query).or_default();
+        while senders
+            .front()
+            .is_some_and(|(time, _)| now.duration_since(*time) > self.window)
+        {
+            senders.pop_front();
+        }
+        senders.retain(|(_, client)| *client != sharing_client);
+        senders.push_back((now, sharing_client));
+
+        if senders.len() > self.config.max_clients_per_query {
+            verdict.signatures.push(Signature::SharedQuery);
+        }
+
+        if page > self.config.max_page {
+            verdict.signatures.push(Signature::DeepPagination);
+        }
+
+        verdict.action = verdict
+            .signatures
+            .iter()
+            .map(|signature| self.action(*signature))
+            .max_by_key(|action| action.severity());
+
+        if verdict.action == Some(AbuseAction::Block) {
+            let until = now + Duration::from_secs(self.config.block_sec);
+
+            for client in &clients {
+                if let Some(history) = inner.clients.get_mut(client) {
+                    history.blocked_until = Some(until);
+                }
+            }
+        }
+
+        verdict
+    }
+
+    /// Forget the clients and queries that have not been seen during the last window.
+    fn maybe_cleanup(&self, inner: &mut Inner, now: Instant) {
+        if inner
+            .last_cleanup
+            .is_some_and(|last| now.duration_since(last) < self.window)
+        {
+            return;
+        }
+
+        inner.last_cleanup = Some(now);
+
+        let window = self.window;
+        let is_recent = |time: &Instant| now.duration_since(*time) <= window;
+
+        inner.clients.retain(|_, history| {
+            history.blocked_until.is_some_and(|until| until > now)
+                || history
+                    .queries
+                    .back()
+                    .is_some_and(|(time, _)| is_recent(time))
+        });
+
+        inner
+            .queries
+            .retain(|_, senders| senders.back().is_some_and(|(time, _)| is_recent(time)));
+    }
+}
+
+/// The ip of the client. The `x-forwarded-for` header is only trusted for requests from
+/// the trusted proxies, and the client is the last address in it that is not a trusted proxy.
+fn client_ip(request: &extract::Request, addr: SocketAddr, trusted_proxies: &[IpAddr]) -> IpAddr {
+    let mut ip = addr.ip();
+
+    if !trusted_proxies.contains(&ip) {
+        return ip;
+    }
+
+    let Some(forwarded_for) = request
+        .headers()
+        .get("x-forwarded-for")
+        .and_then(|forwarded_for| forwarded_for.to_str().ok())
+    else {
+        return ip;
+    };
+
+    for hop in forwarded_for.split(',').rev() {
+        let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+
+        ip = hop;
+
+        if !trusted_proxies.contains(&hop) {
+            break;
+        }
+    }
+
+    ip
+}
+
+pub async fn middleware(
+    extract::State(state): extract::State<Arc<State>>,
+    extract::ConnectInfo(addr): extract::ConnectInfo<SocketAddr>,
+    request: extract::Request,
+    next: middleware::Next,
+) -> Response {
+    let Some(detector) = state.abuse_detector.as_ref() else {
+        return next.run(request).await;
+    };
+
+    let ip = client_ip(&request, addr, &detector.config.trusted_proxies);
+    let key = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|key| key.to_str().ok())
+        .map(|key| key.to_string());
+
+    let (parts, body) = request.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_BODY_BYTES).await else {
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    };
+
+    let (query, page) = serde_json::from_slice::<ApiSearchQuery>(&bytes)
+        .map(|query| (query.query, query.page.unwrap_or_default()))
+        .unwrap_or_default();
+
+    let verdict = detector.observe(key.as_deref(), ip, &query, page);
+    let request = extract::Request::from_parts(parts, Body::from(bytes));
+
+    if !verdict.signatures.is_empty() {
+        tracing::debug!("abusive request: {:?}", verdict);
+    }
+
+    match verdict.action {
+        Some(AbuseAction::Block) => {
+            state.counters.abuse_blocked.inc();
+            StatusCode::FORBIDDEN.into_response()
+        }
+        Some(AbuseAction::Throttle) => {
+            state.counters.abuse_throttled.inc();
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, detector.config.window_sec.to_string())],
+            )
+                .into_response()
+        }
+        Some(AbuseAction::Captcha) => {
+            state.counters.abuse_challenged.inc();
+            let mut response = next.run(request).await;
+            response
+                .headers_mut()
+                .insert(CHALLENGE_HEADER, HeaderValue::from_static("captcha"));
+            response
+        }
+        None => next.run(request).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::AbuseActions;
+
+    use super::*;
+
+    fn config() -> AbuseConfig {
+        AbuseConfig {
+            window_sec: 60,
+            max_queries: 10,
+            min_entropy: 1.0,
+            min_queries_for_entropy: 5,
+            max_clients_per_query: 3,
+            max_page: 5,
+            block_sec: 600,
+            actions: AbuseActions::default(),
+            trusted_keys: vec!["trusted".to_string()],
+            trusted_proxies: vec![ip(100)],
+        }
+    }
+
+    fn ip(i: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, i])
+    }
+
+    #[test]
+    fn rate_and_window() {
+        let detector = AbuseDetector::new(config()).unwrap();
+        let start = Instant::now();
+
+        for i in 0..10 {
+            let verdict = detector.observe_at(None, ip(1), &format!("query {i}"), 0, start);
+            assert_eq!(verdict.action, None);
+        }
+
+        let verdict = detector.observe_at(None, ip(1), "query 10", 0, start);
+        assert_eq!(verdict.signatures, vec![Signature::Rate]);
+        assert_eq!(verdict.action, Some(AbuseAction::Throttle));
+
+        // other clients are not affected
+        let verdict = detector.observe_at(None, ip(2), "query 11", 0, start);
+        assert_eq!(verdict.action, None);
+
+        // the old queries are no longer counted after the window
+        let later = start + Duration::from_secs(61);
+        let verdict = detector.observe_at(None, ip(1), "query 12", 0, later);
+        assert_eq!(verdict.action, None);
+    }
+
+    #[test]
+    fn low_entropy() {
+        let detector = AbuseDetector::new(config()).unwrap();
+        let now = Instant::now();
+
+        for _ in 0..4 {
+            detector.observe_at(None, ip(1), "same query", 0, now);
+        }
+
+        let verdict = detector.observe_at(None, ip(1), "Same  Query", 0, now);
+        assert_eq!(verdict.signatures, vec![Signature::LowEntropy]);
+        assert_eq!(verdict.action, Some(AbuseAction::Captcha));
+    }
+
+    #[test]
+    fn shared_query_across_keys() {
+        let detector = AbuseDetector::new(config()).unwrap();
+        let now = Instant::now();
+
+        // the clients are counted by their key, so the
+        // requests from the same ip count as different clients
+        for i in 0..3 {
+            let verdict = detector.observe_at(Some(&format!("key{i}")), ip(1), "q", 0, now);
+            assert_eq!(verdict.action, None);
+        }
+
+        let verdict = detector.observe_at(Some("key3"), ip(2), "q", 0, now);
+        assert_eq!(verdict.signatures, vec![Signature::SharedQuery]);
+
+        // the trusted key is never flagged
+        let verdict = detector.observe_at(Some("trusted"), ip(3), "q", 0, now);
+        assert_eq!(verdict, Verdict::default());
+    }
+
+    #[test]
+    fn block() {
+        let mut config = config();
+        config.actions.deep_pagination = AbuseAction::Block;
+        let detector = AbuseDetector::new(config).unwrap();
+        let now = Instant::now();
+
+        let verdict = detector.observe_at(Some("key"), ip(1), "q", 6, now);
+        assert_eq!(verdict.signatures, vec![Signature::DeepPagination]);
+        assert_eq!(verdict.action, Some(AbuseAction::Block));
+
+        // both the key and the ip stay blocked
+        let later = now + Duration::from_secs(120);
+        let verdict = detector.observe_at(Some("key"), ip(2), "other", 0, later);
+        assert_eq!(verdict.action, Some(AbuseAction::Block));
+        let verdict = detector.observe_at(None, ip(1), "other", 0, later);
+        assert_eq!(verdict.action, Some(AbuseAction::Block));
+
+        let after_block = now + Duration::from_secs(601);
+        let verdict = detector.observe_at(Some("key"), ip(1), "other", 0, after_block);
+        assert_eq!(verdict.action, None);
+    }
+
+    #[test]
+    fn most_severe_action() {
+        let mut config = config();
+        config.actions.rate = AbuseAction::Throttle;
+        config.actions.deep_pagination = AbuseAction::Captcha;
+        config.max_queries = 0;
+        let detector = AbuseDetector::new(config).unwrap();
+
+        let verdict = detector.observe_at(None, ip(1), "q", 6, Instant::now());
+        assert_eq!(
+            verdict.signatures,
+            vec![Signature::Rate, Signature::DeepPagination]
+        );
+        assert_eq!(verdict.action, Some(AbuseAction::Throttle));
+    }
+
+    #[test]
+    fn forwarded_for_from_trusted_proxies() {
+        let request = |forwarded_for: &str| {
+            extract::Request::builder()
+                .header("x-forwarded-for", forwarded_for)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let trusted = config().trusted_proxies;
+        let proxy = SocketAddr::new(ip(100), 443);
+
+        // the header of other clients is ignored
+        assert_eq!(
+            client_ip(&request("10.0.0.1"), SocketAddr::new(ip(2), 443), &trusted),
+            ip(2)
+        );
+
+        // the client can prepend any address, so the last untrusted address is used
+        assert_eq!(
+            client_ip(&request("10.0.0.1, 10.0.0.2"), proxy, &trusted),
+            ip(2)
+        );
+        assert_eq!(
+            client_ip(&request("10.0.0.1, 10.0.0.100"), proxy, &trusted),
+            ip(1)
+        );
+        assert_eq!(client_ip(&request("garbage"), proxy, &trusted), ip(100));
+    }
+}
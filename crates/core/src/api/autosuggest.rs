@@ -17,10 +17,14 @@
 use std::{collections::HashMap, sync::Arc};
 
 use axum::{extract, response::IntoResponse, Json};
+use http::HeaderMap;
 use serde::Serialize;
 use utoipa::{IntoParams, ToSchema};
 
-use super::State;
+use super::{
+    search::{request_locale, request_region},
+    State,
+};
 
 const HIGHLIGHTED_PREFIX: &str = "<b style=\"font-weight: 500;\">";
 const HIGHLIGHTED_POSTFIX: &str = "</b>";
@@ -50,6 +54,8 @@ pub struct Suggestion {
 #[serde(rename_all = "camelCase")]
 pub struct AutosuggestQuery {
     q: String,
+    /// Language tag like `de` or `de-CH` that selects the suggestions. Defaults to the `Accept-Language` header.
+    locale: Option<String>,
 }
 
 #[utoipa::path(
@@ -63,12 +69,21 @@ pub struct AutosuggestQuery {
 
 pub async fn route(
     extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
     extract::Query(params): extract::Query<HashMap<String, String>>,
 ) -> impl IntoResponse {
+    let tag = params.get("locale").map(String::as_str);
+    let locale = request_locale(tag, &headers);
+    let region = request_region(tag, &headers);
+
     if let Some(query) = params.get("q") {
         let mut suggestions = Vec::new();
 
-        for suggestion in state.autosuggest.suggestions(query).unwrap() {
+        for suggestion in state
+            .autosuggest
+            .regional_suggestions(query, locale, region.as_deref())
+            .unwrap()
+        {
             let highlighted = highlight(query, &suggestion);
             suggestions.push(Suggestion {
                 highlighted,
@@ -84,10 +99,21 @@ pub async fn route(
 
 pub async fn browser(
     extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
     extract::Query(params): extract::Query<HashMap<String, String>>,
 ) -> impl IntoResponse {
+    let tag = params.get("locale").map(String::as_str);
+    let locale = request_locale(tag, &headers);
+    let region = request_region(tag, &headers);
+
     if let Some(query) = params.get("q") {
-        Json((query.clone(), state.autosuggest.suggestions(query).unwrap()))
+        Json((
+            query.clone(),
+            state
+                .autosuggest
+                .regional_suggestions(query, locale, region.as_deref())
+                .unwrap(),
+        ))
     } else {
         Json((String::new(), Vec::new()))
     }
@@ -0,0 +1,198 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! HTTP caching headers for the api responses.
+//!
+//! Every route that is wrapped in the [`middleware`] gets a `cache-control` header from
+//! its [`CachePolicy`]. Successful `GET` responses with a public policy also get an `ETag`,
+//! so clients and proxies can revalidate them with `If-None-Match` and get an empty
+//! `304 Not Modified` instead of the full response. Public responses that depend on request
+//! headers list them in `Vary`, so proxies don't serve them to clients that sent other values.
+
+use axum::{
+    body::Body,
+    extract,
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+};
+
+/// Responses larger than this are not buffered to compute their `ETag`.
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// The response depends on the client or changes between requests, e.g. search results.
+    NoStore,
+    /// The response is the same for all clients that send the same `vary` headers and can be
+    /// cached by proxies.
+    Public {
+        max_age_secs: u64,
+        vary: Option<&'static str>,
+    },
+}
+
+impl CachePolicy {
+    pub const SEARCH: CachePolicy = CachePolicy::NoStore;
+    /// The suggestions default to the language from the `Accept-Language` header.
+    pub const AUTOSUGGEST: CachePolicy = CachePolicy::Public {
+        max_age_secs: 3600,
+        vary: Some("accept-language"),
+    };
+    pub const ENTITY_IMAGE: CachePolicy = CachePolicy::Public {
+        max_age_secs: 7 * 24 * 3600,
+        vary: None,
+    };
+    /// Screenshots are replaced when the page is recrawled.
+    pub const SCREENSHOT: CachePolicy = CachePolicy::Public {
+        max_age_secs: 24 * 3600,
+        vary: None,
+    };
+    pub const FAVICON: CachePolicy = CachePolicy::Public {
+        max_age_secs: 7 * 24 * 3600,
+        vary: None,
+    };
+
+    fn cache_control(&self) -> HeaderValue {
+        match self {
+            CachePolicy::NoStore => HeaderValue::from_static("no-store"),
+            CachePolicy::Public { max_age_secs, .. } => {
+                HeaderValue::from_str(&format!("public, max-age={max_age_secs}"))
+                    .expect("cache-control is valid ascii")
+            }
+        }
+    }
+
+    fn vary(&self) -> Option<HeaderValue> {
+        match self {
+            CachePolicy::NoStore => None,
+            CachePolicy::Public { vary, .. } => vary.map(HeaderValue::from_static),
+        }
+    }
+}
+
+/// The `ETag` is weak as the compression layer might change the encoding
+/// of the body, while the content stays semantically the same.
+fn etag(body: &[u8]) -> String {
+    format!("W/\"{:x}\"", md5::compute(body))
+}
+
+/// Whether the `If-None-Match` header of the request matches the `ETag`.
+/// Uses the weak comparison from RFC 9110, so `W/` prefixes are ignored.
+fn if_none_match(header: &HeaderValue, etag: &str) -> bool {
+    let Ok(header) = header.to_str() else {
+        return false;
+    };
+
+    let etag = etag.trim_start_matches("W/");
+
+    header
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+pub async fn middleware(
+    extract::State(policy): extract::State<CachePolicy>,
+    request: extract::Request,
+    next: middleware::Next,
+) -> Response {
+    let is_get = matches!(*request.method(), Method::GET | Method::HEAD);
+    let client_etag = request.headers().get(header::IF_NONE_MATCH).cloned();
+
+    let mut response = next.run(request).await;
+
+    if !response.status().is_success() {
+        return response;
+    }
+
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, policy.cache_control());
+
+    if let Some(vary) = policy.vary() {
+        response.headers_mut().append(header::VARY, vary);
+    }
+
+    if !is_get || policy == CachePolicy::NoStore {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_BODY_BYTES).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    let etag = etag(&bytes);
+
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        parts.headers.insert(header::ETAG, value);
+    }
+
+    if client_etag.is_some_and(|client_etag| if_none_match(&client_etag, &etag)) {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.remove(header::CONTENT_TYPE);
+
+        return Response::from_parts(parts, Body::empty());
+    }
+
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_control() {
+        assert_eq!(CachePolicy::SEARCH.cache_control(), "no-store");
+        assert_eq!(
+            CachePolicy::AUTOSUGGEST.cache_control(),
+            "public, max-age=3600"
+        );
+
+        assert_eq!(CachePolicy::SEARCH.vary(), None);
+        assert_eq!(CachePolicy::FAVICON.vary(), None);
+        assert_eq!(
+            CachePolicy::AUTOSUGGEST.vary(),
+            Some(HeaderValue::from_static("accept-language"))
+        );
+    }
+
+    #[test]
+    fn etag_matching() {
+        let tag = etag(b"hello world");
+
+        assert!(tag.starts_with("W/\""));
+        assert_eq!(tag, etag(b"hello world"));
+        assert_ne!(tag, etag(b"hello there"));
+
+        let strong = tag.trim_start_matches("W/").to_string();
+
+        assert!(if_none_match(&HeaderValue::from_str(&tag).unwrap(), &tag));
+        assert!(if_none_match(
+            &HeaderValue::from_str(&strong).unwrap(),
+            &tag
+        ));
+        assert!(if_none_match(
+            &HeaderValue::from_str(&format!("\"other\", {tag}")).unwrap(),
+            &tag
+        ));
+        assert!(if_none_match(&HeaderValue::from_static("*"), &tag));
+        assert!(!if_none_match(&HeaderValue::from_static("\"other\""), &tag));
+    }
+}
@@ -14,18 +14,30 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use super::{autosuggest, explore, hosts, search, summarize, webgraph};
+use super::{
+    autosuggest, explore, hosts, screenshots, search, summarize, urls, validation, webgraph,
+};
 use axum::Router;
 use utoipa::{Modify, OpenApi};
 use utoipa_swagger_ui::SwaggerUi;
 
+/// Version of the api specification. Bump the minor version when endpoints or
+/// fields are added and the major version when existing clients would break.
+pub const API_VERSION: &str = "0.5.0";
+
 #[derive(OpenApi)]
 #[openapi(
         paths(
             search::search,
+            search::videos,
+            search::products,
+            search::scholar,
             search::widget,
             search::sidebar,
+            search::places,
+            search::session_click,
             search::spellcheck,
+            search::instant,
             webgraph::host::similar,
             webgraph::host::knows,
             webgraph::host::ingoing_hosts,
@@ -35,18 +47,52 @@ use utoipa_swagger_ui::SwaggerUi;
             autosuggest::route,
             summarize::summarize_route,
             hosts::hosts_export_optic,
+            hosts::site_info,
+            urls::url_status,
             explore::explore_export_optic,
+            screenshots::screenshot,
         ),
         components(
             schemas(
                 crate::webpage::region::Region,
                 optics::HostRankings,
                 search::ApiSearchQuery,
+                crate::query::structured::StructuredQuery,
+                crate::query::structured::StructuredTerm,
+                crate::query::structured::SiteBoost,
                 search::ApiSearchResult,
                 search::WidgetQuery,
                 search::SidebarQuery,
+                search::PlacesQuery,
+                search::SessionClick,
+                validation::ValidationError,
                 search::SpellcheckQuery,
+                search::InstantQuery,
+                crate::searcher::InstantResult,
+                crate::searcher::InstantWebpage,
                 crate::searcher::WebsitesResult,
+                crate::searcher::TopicFacet,
+                crate::searcher::AuthorFacet,
+                crate::searcher::cost::SearchCost,
+                crate::searcher::planner::QueryPlan,
+                crate::searcher::planner::RetrievalStrategy,
+                crate::searcher::planner::QueryFeatures,
+                crate::searcher::planner::QueryHistory,
+                crate::searcher::ResultSource,
+                crate::webpage::topic_classifier::Topic,
+                crate::webpage::PreviewImage,
+                crate::webpage::VideoMetadata,
+                crate::webpage::Product,
+                crate::webpage::Availability,
+                crate::webpage::ScholarlyMetadata,
+                crate::webpage::ReadingLevel,
+                crate::security::SecurityAnnotations,
+                crate::blocklist::ThreatType,
+                crate::product_store::PriceDrop,
+                crate::webpage::GeoCoordinates,
+                crate::webpage::Place,
+                crate::geo_store::PlacesResult,
+                crate::geo_store::DisplayedPlace,
                 crate::search_prettifier::HighlightedSpellCorrection,
                 crate::search_prettifier::DisplayedWebpage,
                 crate::search_prettifier::DisplayedEntity,
@@ -56,6 +102,9 @@ use utoipa_swagger_ui::SwaggerUi;
                 crate::search_prettifier::StackOverflowAnswer,
                 crate::search_prettifier::StackOverflowQuestion,
                 crate::search_prettifier::CodeOrText,
+                crate::search_prettifier::RichResult,
+                crate::search_prettifier::FaqEntry,
+                crate::search_prettifier::SiteSearch,
 
                 crate::snippet::TextSnippet,
                 crate::snippet::TextSnippetFragment,
@@ -87,6 +136,10 @@ use utoipa_swagger_ui::SwaggerUi;
                 autosuggest::Suggestion,
 
                 hosts::HostsExportOpticParams,
+                hosts::SiteInfo,
+                urls::UrlStatusParams,
+                crate::searcher::api::UrlStatus,
+                crate::searcher::api::UrlExclusion,
                 explore::ExploreExportOpticParams,
 
                 crate::webgraph::Node,
@@ -110,9 +163,17 @@ The API might also change quite a bit during the beta period, but we will try to
 
 Remember to always give proper attributions to the sources you use from the search results."#.to_string(),
         );
+        openapi.info.version = API_VERSION.to_string();
     }
 }
 
+/// The OpenAPI specification of the api as pretty printed json.
+pub fn openapi_json() -> String {
+    ApiDoc::openapi()
+        .to_pretty_json()
+        .expect("the specification is serializable")
+}
+
 pub fn router<S: Clone + Send + Sync + 'static>() -> impl Into<Router<S>> {
     SwaggerUi::new("/beta/api/docs")
         .url("/beta/api/docs/openapi.json", ApiDoc::openapi())
@@ -122,3 +183,55 @@ pub fn router<S: Clone + Send + Sync + 'static>() -> impl Into<Router<S>> {
                 .default_models_expand_depth(0),
         )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refs<'a>(value: &'a serde_json::Value, refs: &mut Vec<&'a str>) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, value) in map {
+                    match (key.as_str(), value) {
+                        ("$ref", serde_json::Value::String(reference)) => refs.push(reference),
+                        _ => self::refs(value, refs),
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => {
+                values.iter().for_each(|value| self::refs(value, refs))
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn specification_is_versioned() {
+        let spec: serde_json::Value = serde_json::from_str(&openapi_json()).unwrap();
+
+        assert_eq!(spec["info"]["version"], API_VERSION);
+        assert!(spec["paths"]["/beta/api/search"]["post"].is_object());
+    }
+
+    /// Generated clients fail on references to schemas that are not part of the specification,
+    /// which happens when a type is added to a response but not registered in [`ApiDoc`].
+    #[test]
+    fn all_references_resolve() {
+        let spec: serde_json::Value = serde_json::from_str(&openapi_json()).unwrap();
+
+        let mut references = Vec::new();
+        refs(&spec, &mut references);
+        assert!(!references.is_empty());
+
+        for reference in references {
+            let name = reference
+                .strip_prefix("#/components/schemas/")
+                .unwrap_or_else(|| panic!("unexpected reference {reference}"));
+
+            assert!(
+                spec["components"]["schemas"][name].is_object(),
+                "{name} is referenced but not registered as a schema"
+            );
+        }
+    }
+}
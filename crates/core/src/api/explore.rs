@@ -14,11 +14,12 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use axum::extract;
 use http::StatusCode;
 use optics::{HostRankings, Optic};
 use utoipa::ToSchema;
 
+use super::validation::ValidatedJson;
+
 #[derive(serde::Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ExploreExportOpticParams {
@@ -35,10 +36,10 @@ pub struct ExploreExportOpticParams {
     )
 )]
 pub async fn explore_export_optic(
-    extract::Json(ExploreExportOpticParams {
+    ValidatedJson(ExploreExportOpticParams {
         chosen_hosts,
         similar_hosts,
-    }): extract::Json<ExploreExportOpticParams>,
+    }): ValidatedJson<ExploreExportOpticParams>,
 ) -> Result<String, StatusCode> {
     let matches = similar_hosts
         .into_iter()
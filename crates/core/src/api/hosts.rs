@@ -14,10 +14,64 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
 use axum::{extract, Json};
 use http::StatusCode;
 use optics::{HostRankings, Optic};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    distributed::{cluster::Cluster, member::Service, retry_strategy::ExponentialBackoff, sonic},
+    entrypoint::host_stats::GetHostStats,
+    host_stats::HostStats,
+    webpage::topic_classifier::{Topic, MAX_INDEXED_TOPICS},
+};
+
+use super::{validation::ValidatedJson, State};
+
+pub struct RemoteHostStats {
+    cluster: Arc<Cluster>,
+}
+
+impl RemoteHostStats {
+    pub fn new(cluster: Arc<Cluster>) -> Self {
+        Self { cluster }
+    }
+
+    async fn host(&self) -> Option<SocketAddr> {
+        self.cluster
+            .members()
+            .await
+            .iter()
+            .find_map(|member| match member.service {
+                Service::HostStats { host } => Some(host),
+                _ => None,
+            })
+    }
+
+    pub async fn get(&self, hosts: Vec<String>) -> anyhow::Result<Vec<Option<HostStats>>> {
+        let host = self
+            .host()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("no host stats server in the cluster"))?;
+
+        let retry = ExponentialBackoff::from_millis(30)
+            .with_limit(Duration::from_millis(200))
+            .take(5);
+
+        let conn = sonic::service::ResilientConnection::create_with_timeout(
+            &[host],
+            Duration::from_secs(30),
+            retry,
+        )
+        .await?;
+
+        Ok(conn
+            .send_with_timeout(&GetHostStats { hosts }, Duration::from_secs(10))
+            .await?)
+    }
+}
 
 #[derive(serde::Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
@@ -33,7 +87,7 @@ pub struct HostsExportOpticParams {
     )
 )]
 pub async fn hosts_export_optic(
-    extract::Json(HostsExportOpticParams { host_rankings }): extract::Json<HostsExportOpticParams>,
+    ValidatedJson(HostsExportOpticParams { host_rankings }): ValidatedJson<HostsExportOpticParams>,
 ) -> Result<Json<String>, StatusCode> {
     let optic = Optic {
         host_rankings,
@@ -42,3 +96,64 @@ pub async fn hosts_export_optic(
 
     Ok(Json(optic.to_string()))
 }
+
+#[derive(serde::Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+pub struct SiteInfoParams {
+    pub host: String,
+}
+
+#[derive(serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SiteInfo {
+    pub host: String,
+    pub num_pages: u64,
+    pub avg_quality: f64,
+    pub centrality: f64,
+    pub crawl_errors: u64,
+    pub topics: Vec<Topic>,
+}
+
+#[utoipa::path(post,
+    path = "/beta/api/hosts/info",
+    params(SiteInfoParams),
+    responses(
+        (status = 200, description = "Aggregated stats about the host", body = SiteInfo),
+        (status = 404, description = "The host is not in the index"),
+    )
+)]
+pub async fn site_info(
+    extract::State(state): extract::State<Arc<State>>,
+    extract::Query(params): extract::Query<SiteInfoParams>,
+) -> Result<Json<SiteInfo>, StatusCode> {
+    let stats = state
+        .remote_host_stats
+        .get(vec![params.host.clone()])
+        .await
+        .map_err(|err| {
+            tracing::error!("failed to get host stats: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .into_iter()
+        .next()
+        .flatten()
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(SiteInfo {
+        host: params.host,
+        num_pages: stats.num_pages,
+        avg_quality: stats.avg_quality,
+        centrality: stats.centrality,
+        crawl_errors: stats.crawl_errors,
+        topics: stats
+            .topics
+            .map(|topics| {
+                topics
+                    .top(MAX_INDEXED_TOPICS)
+                    .into_iter()
+                    .map(|(topic, _)| topic)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }))
+}
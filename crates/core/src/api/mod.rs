@@ -22,17 +22,28 @@ use tokio::sync::Mutex;
 use tower_http::compression::CompressionLayer;
 
 use crate::{
-    autosuggest::Autosuggest,
+    autosuggest::{Autosuggest, SafetyFilter},
     bangs::Bangs,
+    blocklist::LiveBlocklist,
     config::ApiConfig,
     distributed::{
         cluster::Cluster,
         member::{Member, Service},
+        sonic,
     },
+    entrypoint::{entity_search_server, host_stats, live_index, search_server, webgraph_server},
+    homograph::HomographDetector,
+    host_policy::HostPolicyStore,
     improvement::{store_improvements_loop, ImprovementEvent},
     leaky_queue::LeakyQueue,
+    liveness::LivenessStore,
+    memory_budget,
+    query_telemetry::{self, QueryTelemetry},
     ranking::models::lambdamart::LambdaMART,
+    screenshot_store::ScreenshotStore,
     searcher::{api::ApiSearcher, live::LiveSearcher, DistributedSearcher},
+    shard_router::ShardRouter,
+    threads,
 };
 
 use crate::{ranking::models::cross_encoder::CrossEncoderModel, summarizer::Summarizer};
@@ -41,6 +52,7 @@ use anyhow::Result;
 use std::{
     net::{IpAddr, SocketAddr},
     sync::Arc,
+    time::Duration,
 };
 
 use axum::{
@@ -50,35 +62,53 @@ use axum::{
     routing::post,
 };
 
-use self::webgraph::RemoteWebgraph;
+use self::{cache::CachePolicy, hosts::RemoteHostStats, webgraph::RemoteWebgraph};
 
+pub mod abuse;
 mod autosuggest;
+mod cache;
 mod docs;
 mod explore;
 mod hosts;
 pub mod improvement;
 mod metrics;
+mod screenshots;
 pub mod search;
+mod session;
 mod summarize;
+mod urls;
 pub mod user_count;
+mod validation;
 mod webgraph;
 
+pub use self::docs::{openapi_json, API_VERSION};
+
 pub struct Counters {
     pub search_counter_success: crate::metrics::Counter,
     pub search_counter_fail: crate::metrics::Counter,
     pub explore_counter: crate::metrics::Counter,
     pub daily_active_users: user_count::UserCount<user_count::Daily>,
+    pub abuse_throttled: crate::metrics::Counter,
+    pub abuse_challenged: crate::metrics::Counter,
+    pub abuse_blocked: crate::metrics::Counter,
+    pub search_cost: crate::searcher::cost::CostCounters,
 }
 
 pub struct State {
     pub config: ApiConfig,
     pub searcher: ApiSearcher<DistributedSearcher, LiveSearcher>,
     pub remote_webgraph: RemoteWebgraph,
+    pub remote_host_stats: RemoteHostStats,
     pub autosuggest: Autosuggest,
     pub counters: Counters,
     pub summarizer: Arc<Summarizer>,
     pub improvement_queue: Option<Arc<Mutex<LeakyQueue<ImprovementEvent>>>>,
     pub cluster: Arc<Cluster>,
+    pub abuse_detector: Option<abuse::AbuseDetector>,
+    pub session_tokens: Option<session::SessionTokens>,
+    pub instant_cache: Arc<search::InstantCache>,
+    pub query_telemetry: Option<Arc<QueryTelemetry>>,
+    pub screenshots: Option<Arc<ScreenshotStore>>,
 }
 
 pub async fn favicon() -> impl IntoResponse {
@@ -91,7 +121,17 @@ pub async fn favicon() -> impl IntoResponse {
 }
 
 pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
-    let autosuggest = Autosuggest::load_csv(&config.queries_csv_path)?;
+    memory_budget::global().set_limit(config.memory_budget_bytes);
+
+    let mut autosuggest = Autosuggest::load_csv(&config.queries_csv_path)?;
+
+    if let Some(path) = &config.localized_queries_path {
+        autosuggest = autosuggest.with_localized_dir(path)?;
+    }
+
+    if let Some(safety) = &config.autosuggest_safety {
+        autosuggest = autosuggest.with_safety_filter(SafetyFilter::open(safety)?);
+    }
 
     let lambda_model = match &config.lambda_model_path {
         Some(path) => Some(LambdaMART::open(path)?),
@@ -104,6 +144,39 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
         query_store_queue
     });
 
+    let query_telemetry = match config.telemetry.clone() {
+        Some(telemetry) => {
+            let telemetry = Arc::new(QueryTelemetry::open(telemetry)?);
+            tokio::spawn(query_telemetry::flush_loop(telemetry.clone()));
+            Some(telemetry)
+        }
+        None => None,
+    };
+
+    let screenshots = match config.screenshots.clone() {
+        Some(screenshots_config) => {
+            let screenshots = Arc::new(ScreenshotStore::open_reader(
+                &screenshots_config.store_path,
+                &screenshots_config.secondary_path,
+            ));
+            let refreshed = Arc::clone(&screenshots);
+
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(
+                    screenshots_config.refresh_interval_sec,
+                ));
+
+                loop {
+                    interval.tick().await;
+                    refreshed.refresh();
+                }
+            });
+
+            Some(screenshots)
+        }
+        None => None,
+    };
+
     let bangs = Bangs::from_path(&config.bangs_path);
 
     let cluster = Arc::new(
@@ -118,10 +191,66 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
         .await?,
     );
     let remote_webgraph = RemoteWebgraph::new(cluster.clone());
+    let remote_host_stats = RemoteHostStats::new(cluster.clone());
 
-    let dist_searcher = DistributedSearcher::new(Arc::clone(&cluster));
+    let abuse_detector = match config.abuse.clone() {
+        Some(abuse) => Some(abuse::AbuseDetector::new(abuse)?),
+        None => None,
+    };
+
+    let session_tokens = match &config.session {
+        Some(session) => Some(session::SessionTokens::open(session)?),
+        None => None,
+    };
+
+    sonic::service::pool().configure(&config.connection_pool);
+    if let Some(tls) = &config.tls {
+        sonic::tls::configure::<search_server::SearchService>(tls)?;
+        sonic::tls::configure::<webgraph_server::WebGraphService>(tls)?;
+    }
+    if let Some(tls) = &config.live_index_tls {
+        sonic::tls::configure::<live_index::SearchService>(tls)?;
+    }
+    if let Some(tls) = &config.entity_search_tls {
+        sonic::tls::configure::<entity_search_server::SearchService>(tls)?;
+    }
+    if let Some(tls) = &config.host_stats_tls {
+        sonic::tls::configure::<host_stats::HostStatsService>(tls)?;
+    }
+
+    let mut dist_searcher = DistributedSearcher::new(Arc::clone(&cluster));
+    dist_searcher.set_min_head_recall(config.thresholds.min_head_recall);
+    if let Some(routing) = config.routing {
+        dist_searcher.set_router(ShardRouter::try_from(routing)?);
+    }
+    if let Some(hedging) = config.hedging.as_ref() {
+        dist_searcher.set_hedging(hedging)?;
+    }
+    if let Some(quorum) = config.quorum.as_ref() {
+        dist_searcher.set_quorum(quorum)?;
+    }
     let live_searcher = LiveSearcher::new(Arc::clone(&cluster));
 
+    if let Some(collection_stats) = config.collection_stats.clone() {
+        let stats_searcher = DistributedSearcher::new(Arc::clone(&cluster));
+
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(collection_stats.interval_sec));
+
+            loop {
+                interval.tick().await;
+
+                if let Err(e) = stats_searcher
+                    .sync_collection_stats(&collection_stats)
+                    .await
+                {
+                    tracing::error!("failed to sync collection statistics: {:?}", e);
+                }
+            }
+        });
+    }
+
     let state = {
         let mut cross_encoder = None;
 
@@ -129,14 +258,70 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
             cross_encoder = Some(CrossEncoderModel::open(path)?);
         }
 
-        let searcher = ApiSearcher::new(
+        let mut searcher = ApiSearcher::new(
             dist_searcher,
             Some(live_searcher),
             cross_encoder,
             lambda_model,
             bangs,
             config.clone(),
-        );
+        )?;
+
+        if let Some(blocklist_config) = config.blocklist.clone() {
+            let refresh_interval = Duration::from_secs(blocklist_config.refresh_interval_sec);
+            let blocklist = Arc::new(LiveBlocklist::open(blocklist_config)?);
+            searcher.set_blocklist(Arc::clone(&blocklist));
+
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(refresh_interval);
+
+                loop {
+                    interval.tick().await;
+
+                    if let Err(e) = blocklist.refresh() {
+                        tracing::error!("failed to refresh blocklist: {:?}", e);
+                    }
+                }
+            });
+        }
+
+        if let Some(model_runner) = config.threads.model_runner.as_ref() {
+            searcher.set_model_runner(threads::rayon_pool(model_runner, "model-runner-")?);
+        }
+
+        if let Some(homograph_config) = config.homograph.as_ref() {
+            searcher.set_homograph_detector(HomographDetector::open(homograph_config)?);
+        }
+
+        if let Some(liveness_config) = config.liveness.as_ref() {
+            let refresh_interval = Duration::from_secs(liveness_config.refresh_interval_sec);
+            let liveness = Arc::new(LivenessStore::open_reader(liveness_config));
+            searcher.set_liveness(Arc::clone(&liveness));
+
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(refresh_interval);
+
+                loop {
+                    interval.tick().await;
+                    liveness.refresh();
+                }
+            });
+        }
+
+        if let Some(host_policy_config) = config.host_policy.as_ref() {
+            let refresh_interval = Duration::from_secs(host_policy_config.refresh_interval_sec);
+            let host_policy = Arc::new(HostPolicyStore::open_reader(host_policy_config));
+            searcher.set_host_policy(Arc::clone(&host_policy));
+
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(refresh_interval);
+
+                loop {
+                    interval.tick().await;
+                    host_policy.refresh();
+                }
+            });
+        }
 
         Arc::new(State {
             config: config.clone(),
@@ -144,6 +329,7 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
             autosuggest,
             counters,
             remote_webgraph,
+            remote_host_stats,
             summarizer: Arc::new(Summarizer::new(
                 &config.summarizer_path,
                 config.llm.api_base.clone(),
@@ -152,6 +338,14 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
             )?),
             improvement_queue: query_store_queue,
             cluster,
+            abuse_detector,
+            session_tokens,
+            instant_cache: search::InstantCache::new(
+                Duration::from_millis(config.instant.cache_ttl_ms),
+                config.instant.cache_size,
+            ),
+            query_telemetry,
+            screenshots,
         })
     };
 
@@ -159,26 +353,57 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
         .merge(
             Router::new()
                 .route("/beta/api/search", post(search::search))
+                .route("/beta/api/search/videos", post(search::videos))
+                .route("/beta/api/search/products", post(search::products))
+                .route("/beta/api/search/scholar", post(search::scholar))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    abuse::middleware,
+                ))
                 .route_layer(middleware::from_fn_with_state(state.clone(), search_metric))
+                .route_layer(middleware::from_fn_with_state(
+                    CachePolicy::SEARCH,
+                    cache::middleware,
+                ))
                 .layer(cors_layer()),
         )
-        .route("/favicon.ico", get(favicon))
+        .route(
+            "/favicon.ico",
+            get(favicon).route_layer(middleware::from_fn_with_state(
+                CachePolicy::FAVICON,
+                cache::middleware,
+            )),
+        )
         .merge(
             Router::new()
                 .route("/improvement/click", post(improvement::click))
                 .route("/improvement/store", post(improvement::store))
                 .layer(cors_layer()),
         )
-        .layer(CompressionLayer::new())
         .merge(docs::router())
         .nest(
             "/beta",
             Router::new()
                 .route("/api/search/widget", post(search::widget))
                 .route("/api/search/sidebar", post(search::sidebar))
+                .route("/api/search/places", post(search::places))
+                .route("/api/search/session/click", post(search::session_click))
                 .route("/api/search/spellcheck", post(search::spellcheck))
-                .route("/api/autosuggest", post(autosuggest::route))
-                .route("/api/autosuggest/browser", get(autosuggest::browser))
+                .route("/api/search/instant", post(search::instant))
+                .route(
+                    "/api/autosuggest",
+                    post(autosuggest::route).route_layer(middleware::from_fn_with_state(
+                        CachePolicy::AUTOSUGGEST,
+                        cache::middleware,
+                    )),
+                )
+                .route(
+                    "/api/autosuggest/browser",
+                    get(autosuggest::browser).route_layer(middleware::from_fn_with_state(
+                        CachePolicy::AUTOSUGGEST,
+                        cache::middleware,
+                    )),
+                )
                 .route("/api/summarize", get(summarize::summarize_route))
                 .route("/api/webgraph/host/similar", post(webgraph::host::similar))
                 .route("/api/webgraph/host/knows", post(webgraph::host::knows))
@@ -199,10 +424,26 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
                     post(webgraph::page::outgoing_pages),
                 )
                 .route("/api/hosts/export", post(hosts::hosts_export_optic))
+                .route("/api/hosts/info", post(hosts::site_info))
+                .route("/api/urls/status", post(urls::url_status))
                 .route("/api/explore/export", post(explore::explore_export_optic))
-                .route("/api/entity_image", get(search::entity_image))
+                .route(
+                    "/api/entity_image",
+                    get(search::entity_image).route_layer(middleware::from_fn_with_state(
+                        CachePolicy::ENTITY_IMAGE,
+                        cache::middleware,
+                    )),
+                )
+                .route(
+                    "/api/screenshot",
+                    get(screenshots::screenshot).route_layer(middleware::from_fn_with_state(
+                        CachePolicy::SCREENSHOT,
+                        cache::middleware,
+                    )),
+                )
                 .layer(cors_layer()),
         )
+        .layer(CompressionLayer::new())
         .with_state(state))
 }
 
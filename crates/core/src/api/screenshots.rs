@@ -0,0 +1,53 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+
+use axum::{extract, response::IntoResponse};
+use http::StatusCode;
+use utoipa::IntoParams;
+
+use super::State;
+
+#[derive(serde::Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+pub struct ScreenshotParams {
+    pub url: String,
+}
+
+#[utoipa::path(get,
+    path = "/beta/api/screenshot",
+    params(ScreenshotParams),
+    responses(
+        (status = 200, description = "Jpeg thumbnail of the page", content_type = "image/jpeg"),
+        (status = 404, description = "No screenshot of the page has been captured"),
+    )
+)]
+pub async fn screenshot(
+    extract::Query(ScreenshotParams { url }): extract::Query<ScreenshotParams>,
+    extract::State(state): extract::State<Arc<State>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let screenshot = state
+        .screenshots
+        .as_ref()
+        .and_then(|screenshots| screenshots.get(&url))
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, "image/jpeg")],
+        screenshot.jpeg,
+    ))
+}
@@ -15,9 +15,12 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::config::defaults;
-use http::StatusCode;
-use optics::{HostRankings, Optic};
-use std::sync::Arc;
+use http::{header, HeaderMap, StatusCode};
+use optics::{
+    ast::{RankingCoeff, RankingTarget},
+    HostRankings, Optic,
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use utoipa::ToSchema;
 
 use axum::Json;
@@ -25,11 +28,22 @@ use axum_macros::debug_handler;
 
 use crate::{
     bangs::BangHit,
-    searcher::{self, SearchQuery, SearchResult, WebsitesResult},
+    distributed::sonic::service::Context,
+    locale::Locale,
+    memory_budget::{self, MemoryConsumer},
+    query::{
+        parser::Term,
+        structured::{self, StructuredQuery},
+    },
+    query_telemetry::QueryFeatures,
+    searcher::{
+        self, InstantResult, InstantWebpage, SearchQuery, SearchResult, Vertical, WebsitesResult,
+    },
+    ttl_cache::TTLCache,
     webpage::region::Region,
 };
 
-use super::State;
+use super::{validation::ValidatedJson, State};
 
 use axum::{extract, response::IntoResponse};
 
@@ -37,13 +51,19 @@ use axum::{extract, response::IntoResponse};
 #[serde(rename_all = "camelCase")]
 #[schema(title = "SearchQuery", example = json!({"query": "hello world"}))]
 pub struct ApiSearchQuery {
+    #[serde(default)]
     pub query: String,
+    /// A query tree that is searched instead of parsing `query`. Use it to search
+    /// for terms that would otherwise be read as operators, e.g. `site:`.
+    pub structured: Option<StructuredQuery>,
     pub page: Option<usize>,
     pub num_results: Option<usize>,
     pub selected_region: Option<Region>,
     pub optic: Option<String>,
     pub host_rankings: Option<HostRankings>,
     pub safe_search: Option<bool>,
+    /// Re-run the query scoped to this site, e.g. from a result's site search box.
+    pub site: Option<String>,
 
     #[serde(default = "defaults::SearchQuery::return_ranking_signals")]
     pub return_ranking_signals: bool,
@@ -53,22 +73,137 @@ pub struct ApiSearchQuery {
 
     #[serde(default = "defaults::SearchQuery::count_results")]
     pub count_results: bool,
+
+    /// Rank the results with this variant of the ranking pipeline instead of the default.
+    pub ranking_variant: Option<String>,
+
+    /// Token from the previous search, used to resolve follow-ups like `its population`.
+    /// It is replaced by a token for this query in the result.
+    pub session: Option<String>,
+
+    /// Language tag like `de-DE` used to understand localized operators.
+    /// Defaults to the `Accept-Language` header.
+    pub locale: Option<String>,
+
+    /// Prefer pages that are easy to read.
+    #[serde(default)]
+    pub prefer_simple_content: bool,
+
+    /// Prefer long-form pages over short ones.
+    #[serde(default)]
+    pub prefer_long_form: bool,
+
+    /// Demote pages that are not served securely or are flagged as malware.
+    #[serde(default)]
+    pub demote_insecure: bool,
+
+    /// Demote pages that are likely machine generated.
+    #[serde(default)]
+    pub demote_generated: bool,
+}
+
+/// Coefficient added for the signals of the content preferences in the search query.
+const CONTENT_PREFERENCE_COEFFICIENT: f64 = 2.0;
+
+/// Coefficient of the security risk when insecure results are demoted.
+const INSECURE_DEMOTION_COEFFICIENT: f64 = -2.0;
+
+/// Coefficient of the generated content likelihood when generated results are demoted.
+const GENERATED_DEMOTION_COEFFICIENT: f64 = -2.0;
+
+impl ApiSearchQuery {
+    fn ranking_preferences(&self) -> Vec<RankingCoeff> {
+        [
+            (
+                self.prefer_simple_content,
+                "reading_ease",
+                CONTENT_PREFERENCE_COEFFICIENT,
+            ),
+            (
+                self.prefer_long_form,
+                "content_length",
+                CONTENT_PREFERENCE_COEFFICIENT,
+            ),
+            (
+                self.demote_insecure,
+                "security_risk",
+                INSECURE_DEMOTION_COEFFICIENT,
+            ),
+            (
+                self.demote_generated,
+                "generated_content",
+                GENERATED_DEMOTION_COEFFICIENT,
+            ),
+        ]
+        .into_iter()
+        .filter(|(preferred, _, _)| *preferred)
+        .map(|(_, signal, value)| RankingCoeff {
+            target: RankingTarget::Signal(signal.to_string()),
+            value,
+        })
+        .collect()
+    }
 }
 
 impl TryFrom<ApiSearchQuery> for SearchQuery {
     type Error = anyhow::Error;
 
     fn try_from(api: ApiSearchQuery) -> Result<Self, Self::Error> {
-        let optic = if let Some(optic) = &api.optic {
+        let mut optic = if let Some(optic) = &api.optic {
             Some(Optic::parse(optic)?)
         } else {
             None
         };
 
+        let preferences = api.ranking_preferences();
+        if !preferences.is_empty() {
+            optic
+                .get_or_insert_with(Optic::default)
+                .rankings
+                .extend(preferences);
+        }
+
+        let site = match &api.site {
+            Some(site) => {
+                let site = site.trim();
+
+                if site.is_empty() || site.contains(char::is_whitespace) {
+                    anyhow::bail!("invalid site: {site:?}");
+                }
+
+                Some(site.to_string())
+            }
+            None => None,
+        };
+
+        let (query, terms) = match api.structured {
+            Some(structured) => {
+                if !api.query.trim().is_empty() {
+                    anyhow::bail!("a search can't have both a query and a structured query");
+                }
+
+                let rules = structured.rules();
+                if !rules.is_empty() {
+                    optic.get_or_insert_with(Optic::default).rules.extend(rules);
+                }
+
+                let mut terms = structured.terms();
+                if let Some(site) = site {
+                    terms.push(Term::Site(site.to_lowercase()));
+                }
+
+                (structured::to_text(&terms), Some(terms))
+            }
+            None => match site {
+                Some(site) => (format!("{} site:{}", api.query, site), None),
+                None => (api.query, None),
+            },
+        };
+
         let default = SearchQuery::default();
 
         Ok(SearchQuery {
-            query: api.query,
+            query,
             page: api.page.unwrap_or(default.page),
             num_results: api.num_results.unwrap_or(default.num_results),
             selected_region: api.selected_region,
@@ -76,7 +211,14 @@ impl TryFrom<ApiSearchQuery> for SearchQuery {
             host_rankings: api.host_rankings,
             return_ranking_signals: api.return_ranking_signals,
             safe_search: api.safe_search.unwrap_or(default.safe_search),
+            vertical: default.vertical,
             count_results: api.count_results,
+            ranking_variant: api.ranking_variant,
+            signal_coefficients: None,
+            bm25f: None,
+            snippet: default.snippet,
+            max_docs_considered: None,
+            terms,
         })
     }
 }
@@ -104,14 +246,31 @@ impl From<SearchResult> for ApiSearchResult {
     request_body(content = ApiSearchQuery),
     responses(
         (status = 200, description = "Search results", body = ApiSearchResult),
+        (status = 403, description = "The client has been blocked for abusing the api"),
+        (status = 422, description = "The request does not match the schema", body = ValidationError),
+        (status = 429, description = "Too many requests from the client"),
     )
 )]
 pub async fn search(
     extract::State(state): extract::State<Arc<State>>,
-    extract::Json(query): extract::Json<ApiSearchQuery>,
+    headers: HeaderMap,
+    ValidatedJson(mut query): ValidatedJson<ApiSearchQuery>,
 ) -> Result<impl IntoResponse, StatusCode> {
     tracing::debug!(?query);
     let flatten_result = query.flatten_response;
+
+    // follow-ups are searched with the subject of the session, e.g. `its population` after `norway`
+    let session = match &state.session_tokens {
+        Some(tokens) => {
+            let (resolved, session) = tokens.search(&query.query, query.session.as_deref());
+            query.query = resolved;
+            Some(session)
+        }
+        None => None,
+    };
+
+    let locale = request_locale(query.locale.as_deref(), &headers);
+    query.query = locale.canonical_operators(&query.query);
     let query = SearchQuery::try_from(query);
 
     if let Err(err) = query {
@@ -122,8 +281,44 @@ pub async fn search(
 
     query.num_results = query.num_results.min(100);
 
-    match state.searcher.search(&query).await {
-        Ok(result) => {
+    let segmented_query = if query.terms.is_none() {
+        state.searcher.segment_query(&query.query, locale)
+    } else {
+        None
+    };
+
+    if let Some(segmented_query) = &segmented_query {
+        query.query = segmented_query.clone();
+    }
+
+    let ctx = match state.config.search_deadline_ms {
+        Some(deadline_ms) => Context::with_timeout(Duration::from_millis(deadline_ms)),
+        None => Context::default(),
+    };
+
+    match ctx.scope(state.searcher.search(&query)).await {
+        Ok(mut result) => {
+            if let SearchResult::Websites(websites) = &mut result {
+                state.counters.search_cost.record(&websites.cost);
+
+                websites.session = session;
+                websites.segmented_query = segmented_query;
+            }
+
+            if let Some(telemetry) = &state.query_telemetry {
+                let zero_results = match &result {
+                    SearchResult::Websites(websites) => websites.webpages.is_empty(),
+                    SearchResult::Bang(_) => false,
+                };
+
+                telemetry.record(QueryFeatures::search(
+                    &query.query,
+                    query.terms.as_deref(),
+                    query.optic.is_some(),
+                    zero_results,
+                ));
+            }
+
             if flatten_result {
                 Ok(Json(ApiSearchResult::from(result)).into_response())
             } else {
@@ -137,6 +332,47 @@ pub async fn search(
                     .to_string()
                     .into_response())
             }
+            Some(searcher::distributed::Error::UnknownRankingVariant(_)) => {
+                Err(StatusCode::BAD_REQUEST)
+            }
+            _ => {
+                tracing::error!("{:?}", err);
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        },
+    }
+}
+
+async fn search_vertical(
+    state: Arc<State>,
+    headers: HeaderMap,
+    mut query: ApiSearchQuery,
+    vertical: Vertical,
+) -> Result<impl IntoResponse, StatusCode> {
+    tracing::debug!(?query, ?vertical);
+    query.query =
+        request_locale(query.locale.as_deref(), &headers).canonical_operators(&query.query);
+    let mut query = SearchQuery::try_from(query).map_err(|err| {
+        tracing::error!("{:?}", err);
+        StatusCode::BAD_REQUEST
+    })?;
+
+    query.num_results = query.num_results.min(100);
+
+    match state.searcher.vertical(&query, vertical).await {
+        Ok(result) => {
+            state.counters.search_cost.record(&result.cost);
+            Ok(Json(result).into_response())
+        }
+        Err(err) => match err.downcast_ref() {
+            Some(searcher::distributed::Error::EmptyQuery) => {
+                Ok(searcher::distributed::Error::EmptyQuery
+                    .to_string()
+                    .into_response())
+            }
+            Some(searcher::distributed::Error::UnknownRankingVariant(_)) => {
+                Err(StatusCode::BAD_REQUEST)
+            }
             _ => {
                 tracing::error!("{:?}", err);
                 Err(StatusCode::INTERNAL_SERVER_ERROR)
@@ -145,9 +381,108 @@ pub async fn search(
     }
 }
 
+#[debug_handler]
+#[utoipa::path(
+    post,
+    path = "/beta/api/search/videos",
+    request_body(content = ApiSearchQuery),
+    responses(
+        (status = 200, description = "Video watch pages matching the query", body = WebsitesResult),
+        (status = 403, description = "The client has been blocked for abusing the api"),
+        (status = 422, description = "The request does not match the schema", body = ValidationError),
+        (status = 429, description = "Too many requests from the client"),
+    )
+)]
+pub async fn videos(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+    ValidatedJson(query): ValidatedJson<ApiSearchQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    search_vertical(state, headers, query, Vertical::Videos).await
+}
+
+#[debug_handler]
+#[utoipa::path(
+    post,
+    path = "/beta/api/search/products",
+    request_body(content = ApiSearchQuery),
+    responses(
+        (status = 200, description = "Product pages matching the query", body = WebsitesResult),
+        (status = 403, description = "The client has been blocked for abusing the api"),
+        (status = 422, description = "The request does not match the schema", body = ValidationError),
+        (status = 429, description = "Too many requests from the client"),
+    )
+)]
+pub async fn products(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+    ValidatedJson(query): ValidatedJson<ApiSearchQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    search_vertical(state, headers, query, Vertical::Products).await
+}
+
+#[debug_handler]
+#[utoipa::path(
+    post,
+    path = "/beta/api/search/scholar",
+    request_body(content = ApiSearchQuery),
+    responses(
+        (status = 200, description = "Scholarly articles matching the query", body = WebsitesResult),
+        (status = 403, description = "The client has been blocked for abusing the api"),
+        (status = 422, description = "The request does not match the schema", body = ValidationError),
+        (status = 429, description = "Too many requests from the client"),
+    )
+)]
+pub async fn scholar(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+    ValidatedJson(query): ValidatedJson<ApiSearchQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    search_vertical(state, headers, query, Vertical::Scholar).await
+}
+
+/// The locale from the request, or the `Accept-Language` header if the request doesn't specify it.
+pub(super) fn request_locale(locale: Option<&str>, headers: &HeaderMap) -> Locale {
+    locale
+        .and_then(Locale::from_tag)
+        .or_else(|| {
+            headers
+                .get(header::ACCEPT_LANGUAGE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(Locale::from_accept_language)
+        })
+        .unwrap_or_default()
+}
+
+/// The region of the language tag from the request, or the `Accept-Language` header
+/// if the request doesn't specify a supported language, e.g. `CH` for `de-CH`.
+pub(super) fn request_region(locale: Option<&str>, headers: &HeaderMap) -> Option<String> {
+    match locale.filter(|tag| Locale::from_tag(tag).is_some()) {
+        Some(tag) => Locale::region_from_tag(tag),
+        None => headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Locale::preferred_tag)
+            .and_then(Locale::region_from_tag),
+    }
+}
+
+/// Resolve follow-up queries using the session token if sessions are enabled.
+fn contextualize(state: &State, query: &str, session: Option<&str>) -> String {
+    match &state.session_tokens {
+        Some(tokens) => tokens.contextualize(query, session),
+        None => query.to_string(),
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize, ToSchema)]
 pub struct WidgetQuery {
     pub query: String,
+    /// Token from the previous search to resolve follow-ups like `its population`.
+    pub session: Option<String>,
+    /// Language tag like `de-DE` used to understand localized widget queries such as
+    /// `3,5 mal 2`. Defaults to the `Accept-Language` header.
+    pub locale: Option<String>,
 }
 
 #[debug_handler]
@@ -161,14 +496,25 @@ pub struct WidgetQuery {
 )]
 pub async fn widget(
     extract::State(state): extract::State<Arc<State>>,
-    extract::Json(req): extract::Json<WidgetQuery>,
+    headers: HeaderMap,
+    ValidatedJson(req): ValidatedJson<WidgetQuery>,
 ) -> impl IntoResponse {
-    Json(state.searcher.widget(&req.query).await)
+    let query = contextualize(&state, &req.query, req.session.as_deref());
+    let locale = request_locale(req.locale.as_deref(), &headers);
+    let widget = state.searcher.widget(&query, locale).await;
+
+    if let Some(telemetry) = &state.query_telemetry {
+        telemetry.record(QueryFeatures::widget(&query, widget.as_ref()));
+    }
+
+    Json(widget)
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, ToSchema)]
 pub struct SidebarQuery {
     pub query: String,
+    /// Token from the previous search to resolve follow-ups like `its population`.
+    pub session: Option<String>,
 }
 
 #[debug_handler]
@@ -182,14 +528,68 @@ pub struct SidebarQuery {
 )]
 pub async fn sidebar(
     extract::State(state): extract::State<Arc<State>>,
-    extract::Json(req): extract::Json<SidebarQuery>,
+    ValidatedJson(req): ValidatedJson<SidebarQuery>,
+) -> impl IntoResponse {
+    let query = contextualize(&state, &req.query, req.session.as_deref());
+    Json(state.searcher.sidebar(&query).await)
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, ToSchema)]
+pub struct PlacesQuery {
+    pub query: String,
+    /// Token from the previous search to resolve follow-ups like `its population`.
+    pub session: Option<String>,
+}
+
+#[debug_handler]
+#[utoipa::path(
+    post,
+    path = "/beta/api/search/places",
+    request_body(content = PlacesQuery),
+    responses(
+        (status = 200, description = "Places with coordinates for a map panel if the query has a local intent, e.g. 'pizza near nørrebro'", body = Option<PlacesResult>),
+    )
+)]
+pub async fn places(
+    extract::State(state): extract::State<Arc<State>>,
+    ValidatedJson(req): ValidatedJson<PlacesQuery>,
+) -> impl IntoResponse {
+    let query = contextualize(&state, &req.query, req.session.as_deref());
+    Json(state.searcher.places(&query))
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, ToSchema)]
+pub struct SessionClick {
+    pub session: String,
+    pub url: String,
+}
+
+#[debug_handler]
+#[utoipa::path(
+    post,
+    path = "/beta/api/search/session/click",
+    request_body(content = SessionClick),
+    responses(
+        (status = 200, description = "The session token with the clicked result added. Empty if the token is invalid or has expired.", body = Option<String>),
+    )
+)]
+pub async fn session_click(
+    extract::State(state): extract::State<Arc<State>>,
+    ValidatedJson(req): ValidatedJson<SessionClick>,
 ) -> impl IntoResponse {
-    Json(state.searcher.sidebar(&req.query).await)
+    Json(
+        state
+            .session_tokens
+            .as_ref()
+            .and_then(|tokens| tokens.click(&req.session, &req.url)),
+    )
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, ToSchema)]
 pub struct SpellcheckQuery {
     pub query: String,
+    /// Language tag like `de` that selects the spell checker. Defaults to the `Accept-Language` header.
+    pub locale: Option<String>,
 }
 
 #[debug_handler]
@@ -203,9 +603,228 @@ pub struct SpellcheckQuery {
 )]
 pub async fn spellcheck(
     extract::State(state): extract::State<Arc<State>>,
-    extract::Json(req): extract::Json<SpellcheckQuery>,
+    headers: HeaderMap,
+    ValidatedJson(req): ValidatedJson<SpellcheckQuery>,
 ) -> impl IntoResponse {
-    Json(state.searcher.spell_check(&req.query))
+    let locale = request_locale(req.locale.as_deref(), &headers);
+    Json(state.searcher.spell_check(&req.query, locale))
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InstantQuery {
+    /// The query as it has been typed so far. The last word is completed
+    /// from the popular queries unless the query ends with a space.
+    pub query: String,
+    pub selected_region: Option<Region>,
+    pub num_results: Option<usize>,
+}
+
+pub type InstantCacheKey = (String, Option<Region>, usize);
+
+/// The results of the instant searches. The cache is accounted for by the memory budget.
+pub struct InstantCache {
+    cache: std::sync::Mutex<TTLCache<InstantCacheKey, InstantResult>>,
+}
+
+impl InstantCache {
+    pub fn new(ttl: Duration, max_size: usize) -> Arc<Self> {
+        let cache = Arc::new(Self {
+            cache: std::sync::Mutex::new(TTLCache::with_ttl_and_max_size(ttl, Some(max_size))),
+        });
+        memory_budget::global().register(cache.clone());
+
+        cache
+    }
+
+    fn get(&self, key: &InstantCacheKey) -> Option<InstantResult> {
+        self.cache.lock().unwrap().get(key).cloned()
+    }
+
+    fn insert(&self, key: InstantCacheKey, result: InstantResult) {
+        self.cache.lock().unwrap().insert(key, result);
+        memory_budget::global().enforce();
+    }
+}
+
+fn instant_result_size_bytes(result: &InstantResult) -> usize {
+    std::mem::size_of::<InstantResult>()
+        + result.query.len()
+        + result
+            .webpages
+            .iter()
+            .map(|webpage| {
+                std::mem::size_of::<InstantWebpage>()
+                    + webpage.title.len()
+                    + webpage.url.len()
+                    + webpage.site.len()
+            })
+            .sum::<usize>()
+}
+
+impl MemoryConsumer for InstantCache {
+    fn name(&self) -> &str {
+        "instant_cache"
+    }
+
+    fn size_bytes(&self) -> usize {
+        self.cache
+            .lock()
+            .unwrap()
+            .values()
+            .map(instant_result_size_bytes)
+            .sum()
+    }
+
+    fn evict(&self, bytes: usize) -> usize {
+        let mut cache = self.cache.lock().unwrap();
+        let mut freed = 0;
+
+        while freed < bytes {
+            let Some(result) = cache.pop_oldest() else {
+                break;
+            };
+
+            freed += instant_result_size_bytes(&result);
+        }
+
+        freed
+    }
+}
+
+/// Complete the last word of the query with its most common completion among the suggestions.
+fn complete_last_word(query: &str, suggestions: &[String]) -> String {
+    if query.is_empty() || query.ends_with(char::is_whitespace) {
+        return query.to_string();
+    }
+
+    let prefix = query.to_ascii_lowercase();
+    let mut completions: HashMap<&str, usize> = HashMap::new();
+
+    for suggestion in suggestions {
+        if let Some(rest) = suggestion.strip_prefix(prefix.as_str()) {
+            let completion = rest.split(char::is_whitespace).next().unwrap_or_default();
+            *completions.entry(completion).or_default() += 1;
+        }
+    }
+
+    completions
+        .into_iter()
+        .max_by(|(a, a_count), (b, b_count)| {
+            a_count
+                .cmp(b_count)
+                .then_with(|| b.len().cmp(&a.len()))
+                .then_with(|| b.cmp(a))
+        })
+        .map(|(completion, _)| format!("{query}{completion}"))
+        .unwrap_or_else(|| query.to_string())
+}
+
+fn truncate_title(title: String, max_chars: usize) -> String {
+    if title.chars().count() <= max_chars {
+        return title;
+    }
+
+    let mut truncated: String = title.chars().take(max_chars.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+#[debug_handler]
+#[utoipa::path(
+    post,
+    path = "/beta/api/search/instant",
+    request_body(content = InstantQuery),
+    responses(
+        (status = 200, description = "Results for the query while it is being typed. The results are empty if the search did not finish in time.", body = InstantResult),
+    )
+)]
+pub async fn instant(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+    ValidatedJson(req): ValidatedJson<InstantQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let config = &state.config.instant;
+
+    let suggestions = state
+        .autosuggest
+        .regional_suggestions(
+            &req.query,
+            request_locale(None, &headers),
+            request_region(None, &headers).as_deref(),
+        )
+        .unwrap_or_default();
+    let query = complete_last_word(&req.query, &suggestions);
+    let num_results = req
+        .num_results
+        .unwrap_or(config.max_results)
+        .min(config.max_results);
+
+    let key: InstantCacheKey = (query.to_lowercase(), req.selected_region, num_results);
+
+    if let Some(result) = state.instant_cache.get(&key) {
+        return Ok(Json(result));
+    }
+
+    let search_query = SearchQuery {
+        query: query.clone(),
+        num_results,
+        selected_region: req.selected_region,
+        count_results: false,
+        return_ranking_signals: false,
+        ..Default::default()
+    };
+
+    // the search servers stop working on the search once it has timed out
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let res = tokio::time::timeout(
+        timeout,
+        Context::with_timeout(timeout).scope(state.searcher.instant(&search_query)),
+    )
+    .await;
+
+    let result = match res {
+        Ok(Ok(webpages)) => {
+            let webpages = webpages
+                .into_iter()
+                .map(|webpage| InstantWebpage {
+                    title: truncate_title(webpage.title, config.max_title_chars),
+                    ..webpage
+                })
+                .collect();
+
+            let result = InstantResult {
+                query,
+                webpages,
+                timed_out: false,
+            };
+
+            state.instant_cache.insert(key, result.clone());
+
+            result
+        }
+        Ok(Err(err)) => match err.downcast_ref() {
+            Some(searcher::distributed::Error::EmptyQuery) => InstantResult {
+                query,
+                webpages: Vec::new(),
+                timed_out: false,
+            },
+            Some(searcher::distributed::Error::UnknownRankingVariant(_)) => {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            _ => {
+                tracing::error!("{:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        },
+        Err(_) => InstantResult {
+            query,
+            webpages: Vec::new(),
+            timed_out: true,
+        },
+    };
+
+    Ok(Json(result))
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, ToSchema)]
@@ -249,3 +868,145 @@ pub async fn entity_image(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_word_completion() {
+        let suggestions: Vec<_> = [
+            "weather",
+            "weather forecast",
+            "weather tomorrow",
+            "web browser",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+        assert_eq!(complete_last_word("wea", &suggestions), "weather");
+        assert_eq!(complete_last_word("We", &suggestions), "Weather");
+        assert_eq!(
+            complete_last_word("weather f", &suggestions),
+            "weather forecast"
+        );
+        assert_eq!(complete_last_word("wea ", &suggestions), "wea ");
+        assert_eq!(complete_last_word("xyz", &suggestions), "xyz");
+        assert_eq!(complete_last_word("", &suggestions), "");
+    }
+
+    #[test]
+    fn site_scopes_the_query() {
+        let search = |site: &str| -> anyhow::Result<SearchQuery> {
+            let api: ApiSearchQuery =
+                serde_json::from_value(serde_json::json!({"query": "rust", "site": site})).unwrap();
+
+            SearchQuery::try_from(api)
+        };
+
+        assert_eq!(search("docs.rs").unwrap().query, "rust site:docs.rs");
+        assert_eq!(search(" docs.rs ").unwrap().query, "rust site:docs.rs");
+        assert!(search("").is_err());
+        assert!(search("docs.rs other").is_err());
+    }
+
+    #[test]
+    fn instant_cache_evicts_oldest() {
+        let cache = InstantCache::new(Duration::from_secs(60), 10);
+        let result = |query: &str| InstantResult {
+            query: query.to_string(),
+            webpages: Vec::new(),
+            timed_out: false,
+        };
+
+        cache.insert(("a".to_string(), None, 10), result("a"));
+        cache.insert(("b".to_string(), None, 10), result("b"));
+        let size = cache.size_bytes();
+
+        assert!(cache.evict(1) > 0);
+        assert!(cache.size_bytes() < size);
+        assert!(cache.get(&("a".to_string(), None, 10)).is_none());
+        assert!(cache.get(&("b".to_string(), None, 10)).is_some());
+    }
+
+    #[test]
+    fn title_truncation() {
+        assert_eq!(truncate_title("short".to_string(), 10), "short");
+        assert_eq!(truncate_title("a long title".to_string(), 5), "a lo…");
+    }
+
+    #[test]
+    fn ranking_preferences() {
+        let api: ApiSearchQuery =
+            serde_json::from_str(r#"{"query": "test", "preferSimpleContent": true}"#).unwrap();
+        let query = SearchQuery::try_from(api).unwrap();
+
+        assert_eq!(
+            query.optic.unwrap().rankings,
+            vec![RankingCoeff {
+                target: RankingTarget::Signal("reading_ease".to_string()),
+                value: CONTENT_PREFERENCE_COEFFICIENT,
+            }]
+        );
+
+        let api: ApiSearchQuery =
+            serde_json::from_str(r#"{"query": "test", "demoteInsecure": true}"#).unwrap();
+        let query = SearchQuery::try_from(api).unwrap();
+
+        assert_eq!(
+            query.optic.unwrap().rankings,
+            vec![RankingCoeff {
+                target: RankingTarget::Signal("security_risk".to_string()),
+                value: INSECURE_DEMOTION_COEFFICIENT,
+            }]
+        );
+
+        let api: ApiSearchQuery =
+            serde_json::from_str(r#"{"query": "test", "demoteGenerated": true}"#).unwrap();
+        let query = SearchQuery::try_from(api).unwrap();
+
+        assert_eq!(
+            query.optic.unwrap().rankings,
+            vec![RankingCoeff {
+                target: RankingTarget::Signal("generated_content".to_string()),
+                value: GENERATED_DEMOTION_COEFFICIENT,
+            }]
+        );
+
+        let api: ApiSearchQuery = serde_json::from_str(r#"{"query": "test"}"#).unwrap();
+        assert!(SearchQuery::try_from(api).unwrap().optic.is_none());
+    }
+
+    #[test]
+    fn structured_query() {
+        let api: ApiSearchQuery = serde_json::from_str(
+            r#"{
+                "structured": {
+                    "terms": [{"type": "term", "value": "-1 site:x"}],
+                    "boosts": [{"site": "docs.rs", "boost": 2}]
+                },
+                "site": "example.com"
+            }"#,
+        )
+        .unwrap();
+        let query = SearchQuery::try_from(api).unwrap();
+
+        assert_eq!(
+            query.terms,
+            Some(vec![
+                Term::Simple("-1".to_string().into()),
+                Term::Simple("site:x".to_string().into()),
+                Term::Site("example.com".to_string()),
+            ])
+        );
+        assert_eq!(query.query, r#""-1" "site:x" site:example.com"#);
+        assert_eq!(query.optic.unwrap().rules.len(), 1);
+
+        let api: ApiSearchQuery = serde_json::from_str(
+            r#"{"query": "test", "structured": {"terms": [{"type": "phrase", "value": "test"}]}}"#,
+        )
+        .unwrap();
+        assert!(SearchQuery::try_from(api).is_err());
+    }
+}
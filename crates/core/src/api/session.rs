@@ -0,0 +1,337 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Short-lived session context for follow-up queries.
+//!
+//! A search response contains an opaque token with the query, and the client can
+//! add the result it clicked. When the token is passed along with the next query,
+//! follow-ups like `its population` after `norway` are resolved to `norway population`
+//! before the query is searched and before the widgets, places and entity sidebar look at it.
+//!
+//! The context only lives in the token. It is signed so it can't be forged and
+//! expires after the configured ttl, but nothing about the session is stored on the server.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD as BASE64_ENGINE, Engine};
+use itertools::Itertools;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::config::SessionConfig;
+
+/// Personal pronouns that refer back to the subject of the previous query.
+/// The possessive forms are replaced by the subject without a genitive,
+/// e.g. `its population` becomes `norway population`. Words like `there` and
+/// `that` are left out, since they are mostly used without referring to anything.
+const REFERRING_WORDS: [&str; 10] = [
+    "it", "its", "they", "their", "them", "he", "his", "him", "she", "her",
+];
+
+/// Queries longer than this are unlikely to be follow-ups even if they contain a referring word.
+const MAX_FOLLOW_UP_WORDS: usize = 8;
+
+/// Tokens issued slightly in the future are accepted to allow for clock skew between api servers.
+const MAX_CLOCK_SKEW_SECS: u64 = 30;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionContext {
+    pub previous_query: String,
+    pub clicked_url: Option<String>,
+    /// Unix timestamp in seconds of when the previous query was searched.
+    pub issued_at: u64,
+}
+
+impl SessionContext {
+    /// What follow-up queries refer to. A clicked wikipedia article names the entity
+    /// the user was interested in, otherwise it is the previous query.
+    fn subject(&self) -> String {
+        self.clicked_url
+            .as_deref()
+            .and_then(wikipedia_title)
+            .unwrap_or_else(|| self.previous_query.trim().to_string())
+    }
+
+    /// Rewrite a follow-up query so it no longer depends on the previous query.
+    /// Returns `None` if the query is not a follow-up.
+    pub fn resolve(&self, query: &str) -> Option<String> {
+        let words: Vec<_> = query.split_whitespace().collect();
+
+        if words.is_empty() || words.len() > MAX_FOLLOW_UP_WORDS {
+            return None;
+        }
+
+        let subject = self.subject();
+        if subject.is_empty() {
+            return None;
+        }
+
+        let mut is_follow_up = false;
+        let resolved = words
+            .into_iter()
+            .map(|word| {
+                if REFERRING_WORDS.contains(&word.to_lowercase().as_str()) {
+                    is_follow_up = true;
+                    subject.as_str()
+                } else {
+                    word
+                }
+            })
+            .join(" ");
+
+        if is_follow_up {
+            Some(resolved)
+        } else {
+            None
+        }
+    }
+}
+
+/// The title of a wikipedia article, e.g. `https://en.wikipedia.org/wiki/New_York_City` is `New York City`.
+fn wikipedia_title(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+
+    if !url.host_str()?.ends_with("wikipedia.org") {
+        return None;
+    }
+
+    let title = url.path().strip_prefix("/wiki/")?;
+    let title = urlencoding::decode(title).ok()?.replace('_', " ");
+
+    if title.is_empty() || title.contains(':') {
+        None
+    } else {
+        Some(title)
+    }
+}
+
+pub struct SessionTokens {
+    key: hmac::Key,
+    ttl_seconds: u64,
+}
+
+impl SessionTokens {
+    pub fn new(secret: &[u8], ttl_seconds: u64) -> Self {
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+            ttl_seconds,
+        }
+    }
+
+    pub fn open(config: &SessionConfig) -> Result<Self> {
+        let secret = std::fs::read_to_string(&config.secret_path)?;
+        Ok(Self::new(secret.trim().as_bytes(), config.ttl_seconds))
+    }
+
+    fn encode(&self, context: &SessionContext) -> String {
+        let payload = serde_json::to_vec(context).expect("session context is serializable");
+        let signature = hmac::sign(&self.key, &payload);
+
+        format!(
+            "{}.{}",
+            BASE64_ENGINE.encode(&payload),
+            BASE64_ENGINE.encode(signature.as_ref())
+        )
+    }
+
+    /// A token for the query that was just searched.
+    pub fn issue(&self, query: &str) -> String {
+        self.encode(&SessionContext {
+            previous_query: query.to_string(),
+            clicked_url: None,
+            issued_at: now(),
+        })
+    }
+
+    /// Add the clicked result to the session. The token still expires
+    /// relative to when the query was searched.
+    pub fn click(&self, token: &str, url: &str) -> Option<String> {
+        let mut context = self.verify(token)?;
+        context.clicked_url = Some(url.to_string());
+
+        Some(self.encode(&context))
+    }
+
+    pub fn verify(&self, token: &str) -> Option<SessionContext> {
+        self.verify_at(token, now())
+    }
+
+    fn verify_at(&self, token: &str, now: u64) -> Option<SessionContext> {
+        let (payload, signature) = token.split_once('.')?;
+        let payload = BASE64_ENGINE.decode(payload).ok()?;
+        let signature = BASE64_ENGINE.decode(signature).ok()?;
+
+        hmac::verify(&self.key, &payload, &signature).ok()?;
+
+        let context: SessionContext = serde_json::from_slice(&payload).ok()?;
+
+        if context.issued_at > now + MAX_CLOCK_SKEW_SECS
+            || now.saturating_sub(context.issued_at) > self.ttl_seconds
+        {
+            return None;
+        }
+
+        Some(context)
+    }
+
+    /// Resolve the query that is searched and issue the token for the next search.
+    /// The token of a follow-up keeps the subject of the session, so `its capital`
+    /// after `its population` still refers to `norway`.
+    pub fn search(&self, query: &str, token: Option<&str>) -> (String, String) {
+        let follow_up = token
+            .and_then(|token| self.verify(token))
+            .and_then(|context| {
+                let resolved = context.resolve(query)?;
+                Some((resolved, context.subject()))
+            });
+
+        match follow_up {
+            Some((resolved, subject)) => (resolved, self.issue(&subject)),
+            None => (query.to_string(), self.issue(query)),
+        }
+    }
+
+    /// The query with references to the previous query resolved if the
+    /// token is valid and the query is a follow-up.
+    pub fn contextualize(&self, query: &str, token: Option<&str>) -> String {
+        token
+            .and_then(|token| self.verify(token))
+            .and_then(|context| context.resolve(query))
+            .unwrap_or_else(|| query.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(previous_query: &str, clicked_url: Option<&str>) -> SessionContext {
+        SessionContext {
+            previous_query: previous_query.to_string(),
+            clicked_url: clicked_url.map(|url| url.to_string()),
+            issued_at: 0,
+        }
+    }
+
+    #[test]
+    fn resolve_follow_up() {
+        let ctx = context("norway", None);
+
+        assert_eq!(
+            ctx.resolve("its population"),
+            Some("norway population".to_string())
+        );
+        assert_eq!(
+            ctx.resolve("when was it founded"),
+            Some("when was norway founded".to_string())
+        );
+        assert_eq!(ctx.resolve("sweden population"), None);
+        assert_eq!(ctx.resolve("what is that"), None);
+        assert_eq!(ctx.resolve("how to get there"), None);
+        assert_eq!(ctx.resolve(""), None);
+    }
+
+    #[test]
+    fn clicked_wikipedia_article() {
+        let ctx = context(
+            "big apple",
+            Some("https://en.wikipedia.org/wiki/New_York_City"),
+        );
+        assert_eq!(
+            ctx.resolve("its population"),
+            Some("New York City population".to_string())
+        );
+
+        let ctx = context("big apple", Some("https://example.com/big-apple"));
+        assert_eq!(
+            ctx.resolve("its population"),
+            Some("big apple population".to_string())
+        );
+    }
+
+    #[test]
+    fn tokens() {
+        let tokens = SessionTokens::new(b"test-secret", 60);
+        let token = tokens.issue("norway");
+
+        let context = tokens.verify(&token).unwrap();
+        assert_eq!(context.previous_query, "norway");
+        assert_eq!(context.clicked_url, None);
+
+        assert_eq!(
+            tokens.contextualize("its population", Some(&token)),
+            "norway population"
+        );
+        assert_eq!(
+            tokens.contextualize("its population", None),
+            "its population"
+        );
+
+        let (query, next) = tokens.search("its population", Some(&token));
+        assert_eq!(query, "norway population");
+        assert_eq!(
+            tokens.search("its capital", Some(&next)).0,
+            "norway capital"
+        );
+
+        let (query, next) = tokens.search("sweden", Some(&token));
+        assert_eq!(query, "sweden");
+        assert_eq!(tokens.verify(&next).unwrap().previous_query, "sweden");
+
+        let clicked = tokens
+            .click(&token, "https://en.wikipedia.org/wiki/Norway")
+            .unwrap();
+        assert_eq!(
+            tokens.verify(&clicked).unwrap().clicked_url.as_deref(),
+            Some("https://en.wikipedia.org/wiki/Norway")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_tokens() {
+        let tokens = SessionTokens::new(b"test-secret", 60);
+        let token = tokens.encode(&SessionContext {
+            issued_at: 1000,
+            ..context("norway", None)
+        });
+
+        assert!(tokens.verify_at(&token, 1030).is_some());
+        assert!(tokens.verify_at(&token, 1061).is_none());
+        assert!(tokens.verify_at(&token, 900).is_none());
+
+        let other = SessionTokens::new(b"other-secret", 60);
+        assert!(other.verify_at(&token, 1030).is_none());
+
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{}",
+            BASE64_ENGINE
+                .encode(br#"{"previous_query":"sweden","clicked_url":null,"issued_at":1000}"#),
+            signature
+        );
+        assert!(tokens.verify_at(&forged, 1030).is_none());
+        assert!(tokens.verify_at("not a token", 1030).is_none());
+    }
+}
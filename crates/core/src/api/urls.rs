@@ -0,0 +1,61 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+
+use axum::{extract, Json};
+use http::StatusCode;
+use utoipa::ToSchema;
+
+use crate::searcher::api::UrlStatus;
+
+use super::{validation::ValidatedJson, State};
+
+/// Maximum number of urls that can be looked up in a single request.
+const MAX_URLS: usize = 1_000;
+
+#[derive(serde::Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UrlStatusParams {
+    pub urls: Vec<String>,
+}
+
+#[utoipa::path(post,
+    path = "/beta/api/urls/status",
+    request_body(content = UrlStatusParams),
+    responses(
+        (status = 200, description = "The index status of the urls in the same order as the requested urls", body = Vec<UrlStatus>),
+        (status = 400, description = "Too many urls in the request"),
+    )
+)]
+pub async fn url_status(
+    extract::State(state): extract::State<Arc<State>>,
+    ValidatedJson(UrlStatusParams { urls }): ValidatedJson<UrlStatusParams>,
+) -> Result<Json<Vec<UrlStatus>>, StatusCode> {
+    if urls.len() > MAX_URLS {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    state
+        .searcher
+        .url_statuses(&urls)
+        .await
+        .map(Json)
+        .map_err(|err| {
+            tracing::error!("failed to look up url statuses: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
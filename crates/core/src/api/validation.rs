@@ -0,0 +1,163 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Validation of json request bodies against the types they are documented with
+//! in the OpenAPI specification.
+//!
+//! Axum's `Json` extractor only responds with a plain text error when the body
+//! doesn't match the type. [`ValidatedJson`] instead responds with a [`ValidationError`]
+//! that names the schema from the specification and the path of the offending field,
+//! so clients generated from the specification can surface what is wrong.
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::de::DeserializeOwned;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationError {
+    /// The name of the schema in the OpenAPI specification the body should match.
+    pub schema: String,
+    /// The path to the field that failed validation, e.g. `hostRankings.liked[0]`.
+    /// Empty if the body itself is invalid.
+    pub path: String,
+    pub message: String,
+}
+
+impl IntoResponse for ValidationError {
+    fn into_response(self) -> Response {
+        (StatusCode::UNPROCESSABLE_ENTITY, Json(self)).into_response()
+    }
+}
+
+/// A json body that is validated against the schema of `T`.
+pub struct ValidatedJson<T>(pub T);
+
+fn validate<T>(bytes: &[u8]) -> Result<T, ValidationError>
+where
+    T: DeserializeOwned + ToSchema<'static>,
+{
+    let schema = || T::schema().0.to_string();
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|err| {
+        let path = err.path().to_string();
+
+        ValidationError {
+            schema: schema(),
+            path: if path == "." { String::new() } else { path },
+            message: err.into_inner().to_string(),
+        }
+    })?;
+
+    // only whitespace may follow the json value
+    deserializer.end().map_err(|err| ValidationError {
+        schema: schema(),
+        path: String::new(),
+        message: err.to_string(),
+    })?;
+
+    Ok(value)
+}
+
+fn is_json(req: &Request) -> bool {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.parse::<mime::Mime>().ok())
+        .is_some_and(|mime| {
+            mime.type_() == "application"
+                && (mime.subtype() == "json" || mime.suffix().is_some_and(|name| name == "json"))
+        })
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + ToSchema<'static>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_json(&req) {
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected request with `Content-Type: application/json`",
+            )
+                .into_response());
+        }
+
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        validate(&bytes)
+            .map(ValidatedJson)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::search::ApiSearchQuery;
+
+    use super::*;
+
+    #[test]
+    fn valid_body() {
+        let query: ApiSearchQuery = validate(br#"{"query": "hello world"}"#).unwrap();
+        assert_eq!(query.query, "hello world");
+    }
+
+    #[test]
+    fn invalid_field() {
+        let err = validate::<ApiSearchQuery>(br#"{"query": "hello", "numResults": "ten"}"#)
+            .err()
+            .unwrap();
+
+        assert_eq!(err.schema, "ApiSearchQuery");
+        assert_eq!(err.path, "numResults");
+    }
+
+    #[test]
+    fn missing_field() {
+        let err = validate::<ApiSearchQuery>(br#"{"page": 1}"#).err().unwrap();
+
+        assert_eq!(err.path, "");
+        assert!(err.message.contains("query"), "{}", err.message);
+    }
+
+    #[test]
+    fn trailing_characters() {
+        let err = validate::<ApiSearchQuery>(br#"{"query": "hello"} garbage"#)
+            .err()
+            .unwrap();
+
+        assert_eq!(err.schema, "ApiSearchQuery");
+        assert_eq!(err.path, "");
+        assert!(err.message.contains("trailing"), "{}", err.message);
+
+        assert!(validate::<ApiSearchQuery>(b"{\"query\": \"hello\"}\n").is_ok());
+    }
+}
@@ -17,16 +17,26 @@
 use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use axum::{extract, response::IntoResponse, Json};
+use futures::TryStreamExt;
 use http::StatusCode;
 use utoipa::{IntoParams, ToSchema};
 
 use crate::{
     config::WebgraphGranularity,
-    distributed::{cluster::Cluster, member::Service, retry_strategy::ExponentialBackoff, sonic},
+    distributed::{
+        cluster::Cluster,
+        member::Service,
+        retry_strategy::ExponentialBackoff,
+        sonic::{
+            self,
+            replication::{LatencyAwareReplicaSelector, RemoteClient, ReplicatedClient},
+        },
+    },
+    entrypoint::webgraph_server::{Knows, SimilarHosts, WebGraphService},
     webgraph::{FullEdge, Node},
 };
 
-use super::State;
+use super::{validation::ValidatedJson, State};
 
 pub struct RemoteWebgraph {
     cluster: Arc<Cluster>,
@@ -47,6 +57,24 @@ impl RemoteWebgraph {
                 _ => None,
             })
     }
+
+    /// A client for all the webgraph servers of the granularity.
+    async fn client(&self, level: WebgraphGranularity) -> ReplicatedClient<WebGraphService> {
+        let replicas = self
+            .cluster
+            .members()
+            .await
+            .into_iter()
+            .filter_map(|member| match member.service {
+                Service::Webgraph { host, granularity } if granularity == level => {
+                    Some(RemoteClient::new(host))
+                }
+                _ => None,
+            })
+            .collect();
+
+        ReplicatedClient::new(replicas)
+    }
 }
 
 pub mod host {
@@ -82,38 +110,49 @@ pub mod host {
     )]
     pub async fn similar(
         extract::State(state): extract::State<Arc<State>>,
-        extract::Json(params): extract::Json<SimilarHostsParams>,
+        ValidatedJson(params): ValidatedJson<SimilarHostsParams>,
     ) -> std::result::Result<impl IntoResponse, StatusCode> {
         state.counters.explore_counter.inc();
-        let host = state
+        let client = state
             .remote_webgraph
-            .host(WebgraphGranularity::Host)
+            .client(WebgraphGranularity::Host)
+            .await;
+
+        // the hosts are looked up in one batch, so hosts the webgraph does not
+        // know are left out and the rest are normalized like the nodes
+        let lookups: Vec<_> = params
+            .hosts
+            .into_iter()
+            .map(|host| Knows { host })
+            .collect();
+        let hosts = match client
+            .send_batch(&lookups, &LatencyAwareReplicaSelector)
             .await
-            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
-
-        let retry = ExponentialBackoff::from_millis(30)
-            .with_limit(Duration::from_millis(200))
-            .take(5);
-
-        let conn = sonic::service::ResilientConnection::create_with_timeout(
-            host,
-            Duration::from_secs(30),
-            retry,
-        )
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        {
+            Ok(mut res) => res
+                .pop()
+                .unwrap_or_default()
+                .into_iter()
+                .flatten()
+                .map(|node| node.name)
+                .collect(),
+            Err(err) => {
+                tracing::error!("Failed to send request to webgraph: {}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
 
-        match conn
-            .send_with_timeout(
-                &crate::entrypoint::webgraph_server::SimilarHosts {
-                    hosts: params.hosts,
+        match client
+            .send(
+                &SimilarHosts {
+                    hosts,
                     top_n: params.top_n,
                 },
-                Duration::from_secs(60),
+                &LatencyAwareReplicaSelector,
             )
             .await
         {
-            Ok(nodes) => Ok(Json(nodes)),
+            Ok(mut res) => Ok(Json(res.pop().unwrap_or_default())),
             Err(err) => {
                 tracing::error!("Failed to send request to webgraph: {}", err);
                 Err(StatusCode::INTERNAL_SERVER_ERROR)
@@ -143,7 +182,7 @@ pub mod host {
             .take(5);
 
         let conn = sonic::service::ResilientConnection::create_with_timeout(
-            host,
+            &[host],
             Duration::from_secs(30),
             retry,
         )
@@ -151,10 +190,7 @@ pub mod host {
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
         match conn
-            .send_with_timeout(
-                &crate::entrypoint::webgraph_server::Knows { host: params.host },
-                Duration::from_secs(60),
-            )
+            .send_with_timeout(&Knows { host: params.host }, Duration::from_secs(60))
             .await
         {
             Ok(Some(node)) => Ok(Json(KnowsHost::Known { host: node.name })),
@@ -287,17 +323,19 @@ async fn ingoing_links(
         .take(5);
 
     let conn = sonic::service::ResilientConnection::create_with_timeout(
-        host,
+        &[host],
         Duration::from_secs(30),
         retry,
     )
     .await?;
 
     Ok(conn
-        .send_with_timeout(
+        .send_streaming(
             &crate::entrypoint::webgraph_server::IngoingLinks { node },
             Duration::from_secs(60),
         )
+        .await?
+        .try_concat()
         .await?)
 }
 
@@ -319,17 +357,19 @@ async fn outgoing_links(
         .take(5);
 
     let conn = sonic::service::ResilientConnection::create_with_timeout(
-        host,
+        &[host],
         Duration::from_secs(30),
         retry,
     )
     .await?;
 
     Ok(conn
-        .send_with_timeout(
+        .send_streaming(
             &crate::entrypoint::webgraph_server::OutgoingLinks { node },
             Duration::from_secs(60),
         )
+        .await?
+        .try_concat()
         .await?)
 }
 
@@ -0,0 +1,594 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Append-only audit log of administrative actions.
+//!
+//! Every entry records who performed which action, when and whether it
+//! succeeded. Entries are stored as json lines and signed with HMAC-SHA256.
+//! The signature of an entry also covers the signature of the previous entry,
+//! so removing, reordering or modifying entries breaks the chain and is
+//! detected by [`verify`].
+//!
+//! The actions are requested with an [`AdminRequest`] signed with the key of the
+//! operator, so the actor in the log is the operator whose key signed the request.
+//! The servers [`perform`] the index reloads, host blocks, optic overrides and deletion
+//! requests, which records each action with its outcome once it has been carried out.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, bail};
+use base64::{prelude::BASE64_STANDARD as BASE64_ENGINE, Engine};
+use chrono::{DateTime, Utc};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::AuditLogConfig, Result};
+
+/// Requests signed longer ago than this are rejected.
+const MAX_REQUEST_AGE_SECS: i64 = 5 * 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuditAction {
+    IndexReload {
+        target: String,
+    },
+    HostBlock {
+        host: String,
+    },
+    HostUnblock {
+        host: String,
+    },
+    /// The optic used for searches without an optic of their own. `None` removes the override.
+    OpticOverride {
+        optic: Option<String>,
+    },
+    DeletionRequest {
+        url: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Succeeded,
+    Failed { error: String },
+}
+
+impl AuditOutcome {
+    pub fn of<T, E: Display>(res: &std::result::Result<T, E>) -> Self {
+        match res {
+            Ok(_) => AuditOutcome::Succeeded,
+            Err(e) => AuditOutcome::Failed {
+                error: e.to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub actor: String,
+    pub action: AuditAction,
+    pub outcome: AuditOutcome,
+    prev_signature: String,
+    signature: String,
+}
+
+#[derive(Serialize)]
+struct SignedPart<'a> {
+    seq: u64,
+    timestamp: &'a DateTime<Utc>,
+    actor: &'a str,
+    action: &'a AuditAction,
+    outcome: &'a AuditOutcome,
+    prev_signature: &'a str,
+}
+
+impl AuditEntry {
+    fn signed_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&SignedPart {
+            seq: self.seq,
+            timestamp: &self.timestamp,
+            actor: &self.actor,
+            action: &self.action,
+            outcome: &self.outcome,
+            prev_signature: &self.prev_signature,
+        })?)
+    }
+
+    fn sign(&self, key: &hmac::Key) -> Result<String> {
+        let signature = hmac::sign(key, &self.signed_bytes()?);
+        Ok(BASE64_ENGINE.encode(signature.as_ref()))
+    }
+}
+
+fn key(secret: &[u8]) -> hmac::Key {
+    hmac::Key::new(hmac::HMAC_SHA256, secret)
+}
+
+/// An administrative action signed by the operator who requested it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdminRequest {
+    pub actor: String,
+    pub timestamp: DateTime<Utc>,
+    pub action: AuditAction,
+    signature: String,
+}
+
+#[derive(Serialize)]
+struct SignedRequest<'a> {
+    actor: &'a str,
+    timestamp: &'a DateTime<Utc>,
+    action: &'a AuditAction,
+}
+
+impl AdminRequest {
+    /// Sign the action with the secret of the operator.
+    pub fn new(actor: &str, action: AuditAction, secret: &[u8]) -> Result<Self> {
+        let mut request = Self {
+            actor: actor.to_string(),
+            timestamp: Utc::now(),
+            action,
+            signature: String::new(),
+        };
+
+        let signature = hmac::sign(&key(secret), &request.signed_bytes()?);
+        request.signature = BASE64_ENGINE.encode(signature.as_ref());
+
+        Ok(request)
+    }
+
+    /// Sign the action with the secret of the operator stored in the file at `secret_path`.
+    pub fn with_secret_file<P: AsRef<Path>>(
+        actor: &str,
+        action: AuditAction,
+        secret_path: P,
+    ) -> Result<Self> {
+        let secret = std::fs::read_to_string(secret_path)?;
+        Self::new(actor, action, secret.trim().as_bytes())
+    }
+
+    fn signed_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&SignedRequest {
+            actor: &self.actor,
+            timestamp: &self.timestamp,
+            action: &self.action,
+        })?)
+    }
+}
+
+/// The operators who may perform administrative actions and their keys.
+pub struct Operators {
+    keys: HashMap<String, hmac::Key>,
+    /// Timestamp of the last accepted request of each operator, so requests can't be replayed.
+    last_requests: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+impl Operators {
+    pub fn new<'a>(secrets: impl IntoIterator<Item = (&'a str, &'a [u8])>) -> Self {
+        Self {
+            keys: secrets
+                .into_iter()
+                .map(|(actor, secret)| (actor.to_string(), key(secret)))
+                .collect(),
+            last_requests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Read the operators from a file with a line `<actor> <secret>` for each operator.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let mut secrets = Vec::new();
+
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (actor, secret) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| anyhow!("expected '<actor> <secret>' but found '{line}'"))?;
+
+            secrets.push((actor, secret.trim().as_bytes()));
+        }
+
+        Ok(Self::new(secrets))
+    }
+
+    /// Check that the request is signed by the operator and is newer than their last request.
+    pub fn authenticate(&self, request: &AdminRequest) -> Result<()> {
+        let key = self
+            .keys
+            .get(&request.actor)
+            .ok_or_else(|| anyhow!("{} is not an operator", request.actor))?;
+
+        let signature = BASE64_ENGINE.decode(&request.signature)?;
+
+        hmac::verify(key, &request.signed_bytes()?, &signature)
+            .map_err(|_| anyhow!("request of {} has an invalid signature", request.actor))?;
+
+        if (Utc::now() - request.timestamp).num_seconds().abs() > MAX_REQUEST_AGE_SECS {
+            bail!("request of {} has expired", request.actor);
+        }
+
+        let mut last_requests = self.last_requests.lock().unwrap();
+
+        if last_requests
+            .get(&request.actor)
+            .map_or(false, |last| request.timestamp <= *last)
+        {
+            bail!("request of {} has already been received", request.actor);
+        }
+
+        last_requests.insert(request.actor.clone(), request.timestamp);
+
+        Ok(())
+    }
+}
+
+/// Read all entries of the log without verifying them.
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<AuditEntry>> {
+    let file = File::open(path)?;
+    let mut entries = Vec::new();
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        entries.push(serde_json::from_str(&line)?);
+    }
+
+    Ok(entries)
+}
+
+/// Verify that the entries form an unbroken chain signed with `secret`.
+pub fn verify(entries: &[AuditEntry], secret: &[u8]) -> Result<()> {
+    let key = key(secret);
+    let mut prev_signature = String::new();
+
+    for (expected_seq, entry) in entries.iter().enumerate() {
+        if entry.seq != expected_seq as u64 {
+            bail!(
+                "expected entry {} but found entry {}",
+                expected_seq,
+                entry.seq
+            );
+        }
+
+        if entry.prev_signature != prev_signature {
+            bail!("entry {} does not follow the previous entry", entry.seq);
+        }
+
+        let signature = BASE64_ENGINE.decode(&entry.signature)?;
+
+        hmac::verify(&key, &entry.signed_bytes()?, &signature)
+            .map_err(|_| anyhow!("entry {} has an invalid signature", entry.seq))?;
+
+        prev_signature = entry.signature.clone();
+    }
+
+    Ok(())
+}
+
+struct Tail {
+    file: File,
+    next_seq: u64,
+    prev_signature: String,
+}
+
+pub struct AuditLog {
+    key: hmac::Key,
+    operators: Operators,
+    tail: Mutex<Tail>,
+}
+
+impl AuditLog {
+    /// Open the log at `path`, creating it if it does not exist. Existing
+    /// entries are verified before new entries can be appended.
+    pub fn open<P: AsRef<Path>>(path: P, secret: &[u8], operators: Operators) -> Result<Self> {
+        let path = path.as_ref();
+
+        let entries = if path.exists() {
+            read(path)?
+        } else {
+            Vec::new()
+        };
+
+        verify(&entries, secret)?;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        let (next_seq, prev_signature) = match entries.last() {
+            Some(last) => (last.seq + 1, last.signature.clone()),
+            None => (0, String::new()),
+        };
+
+        Ok(Self {
+            key: key(secret),
+            operators,
+            tail: Mutex::new(Tail {
+                file,
+                next_seq,
+                prev_signature,
+            }),
+        })
+    }
+
+    pub fn open_with_config(config: &AuditLogConfig) -> Result<Self> {
+        let secret = std::fs::read_to_string(&config.secret_path)?;
+        let operators = Operators::open(&config.operators_path)?;
+
+        Self::open(&config.path, secret.trim().as_bytes(), operators)
+    }
+
+    /// Check that the request is signed by one of the operators. The action must not be
+    /// performed if this fails.
+    pub fn authenticate(&self, request: &AdminRequest) -> Result<()> {
+        self.operators.authenticate(request)
+    }
+
+    /// Append the action of an authenticated request with its outcome to the log.
+    pub fn record(&self, request: &AdminRequest, outcome: AuditOutcome) -> Result<AuditEntry> {
+        self.append(&request.actor, request.action.clone(), outcome)
+    }
+
+    /// Append an entry and flush it to disk before returning.
+    fn append(
+        &self,
+        actor: &str,
+        action: AuditAction,
+        outcome: AuditOutcome,
+    ) -> Result<AuditEntry> {
+        let mut tail = self.tail.lock().unwrap();
+
+        let mut entry = AuditEntry {
+            seq: tail.next_seq,
+            timestamp: Utc::now(),
+            actor: actor.to_string(),
+            action,
+            outcome,
+            prev_signature: tail.prev_signature.clone(),
+            signature: String::new(),
+        };
+        entry.signature = entry.sign(&self.key)?;
+
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+
+        tail.file.write_all(line.as_bytes())?;
+        tail.file.sync_data()?;
+
+        tail.next_seq += 1;
+        tail.prev_signature = entry.signature.clone();
+
+        tracing::info!(
+            "audit: {} performed {:?} with outcome {:?} (entry {})",
+            entry.actor,
+            entry.action,
+            entry.outcome,
+            entry.seq
+        );
+
+        Ok(entry)
+    }
+}
+
+/// Perform the action of the request from an async handler and record it with its outcome.
+/// The action is only performed if there is a log and the request is authenticated, so
+/// administrative actions are never performed without being recorded. The entry is synced
+/// to disk on a blocking thread once the action has been carried out.
+pub async fn perform<T, E: Display>(
+    log: Option<&Arc<AuditLog>>,
+    request: AdminRequest,
+    action: impl FnOnce() -> std::result::Result<T, E>,
+) -> std::result::Result<T, String> {
+    let Some(log) = log.cloned() else {
+        return Err("administrative rpcs require an audit log".to_string());
+    };
+
+    log.authenticate(&request).map_err(|e| e.to_string())?;
+
+    let res = action();
+    let outcome = AuditOutcome::of(&res);
+
+    match tokio::task::spawn_blocking(move || log.record(&request, outcome)).await {
+        Ok(Ok(_)) => res.map_err(|e| e.to_string()),
+        Ok(Err(e)) => Err(format!("the action was performed but not recorded: {e}")),
+        Err(e) => Err(format!("the action was performed but not recorded: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"test-secret";
+
+    fn operators() -> Operators {
+        Operators::new([("alice", b"alice-key".as_slice()), ("bob", b"bob-key")])
+    }
+
+    fn deletion(url: &str) -> AuditAction {
+        AuditAction::DeletionRequest {
+            url: url.to_string(),
+        }
+    }
+
+    #[test]
+    fn append_and_verify() {
+        let path = crate::gen_temp_path().join("audit.log");
+
+        {
+            let log = AuditLog::open(&path, SECRET, operators()).unwrap();
+            let request =
+                AdminRequest::new("alice", deletion("https://spam.com/"), b"alice-key").unwrap();
+            log.authenticate(&request).unwrap();
+            log.record(&request, AuditOutcome::Succeeded).unwrap();
+        }
+
+        // reopening continues the chain
+        let log = AuditLog::open(&path, SECRET, operators()).unwrap();
+        let action = AuditAction::HostBlock {
+            host: "spam.com".to_string(),
+        };
+        let request = AdminRequest::new("bob", action, b"bob-key").unwrap();
+        log.authenticate(&request).unwrap();
+        log.record(
+            &request,
+            AuditOutcome::Failed {
+                error: "disk full".to_string(),
+            },
+        )
+        .unwrap();
+
+        let entries = read(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].actor, "alice");
+        assert_eq!(entries[0].outcome, AuditOutcome::Succeeded);
+        assert_eq!(entries[1].seq, 1);
+        assert!(matches!(entries[1].outcome, AuditOutcome::Failed { .. }));
+
+        assert!(verify(&entries, SECRET).is_ok());
+        assert!(verify(&entries, b"wrong-secret").is_err());
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let path = crate::gen_temp_path().join("audit.log");
+        let log = AuditLog::open(&path, SECRET, operators()).unwrap();
+
+        for url in ["https://a.com/", "https://b.com/", "https://c.com/"] {
+            log.append("alice", deletion(url), AuditOutcome::Succeeded)
+                .unwrap();
+        }
+
+        let entries = read(&path).unwrap();
+
+        let mut modified = entries.clone();
+        modified[1].actor = "mallory".to_string();
+        assert!(verify(&modified, SECRET).is_err());
+
+        let mut modified = entries.clone();
+        modified[1].outcome = AuditOutcome::Failed {
+            error: "hidden".to_string(),
+        };
+        assert!(verify(&modified, SECRET).is_err());
+
+        let mut removed = entries.clone();
+        removed.remove(1);
+        assert!(verify(&removed, SECRET).is_err());
+
+        let mut reordered = entries;
+        reordered.swap(1, 2);
+        assert!(verify(&reordered, SECRET).is_err());
+    }
+
+    #[test]
+    fn requests_are_authenticated() {
+        let path = crate::gen_temp_path().join("audit.log");
+        let log = AuditLog::open(&path, SECRET, operators()).unwrap();
+
+        // the actor must be the operator whose key signed the request
+        let mut forged =
+            AdminRequest::new("alice", deletion("https://a.com/"), b"alice-key").unwrap();
+        forged.actor = "bob".to_string();
+        assert!(log.authenticate(&forged).is_err());
+
+        let wrong_key = AdminRequest::new("alice", deletion("https://a.com/"), b"bob-key").unwrap();
+        assert!(log.authenticate(&wrong_key).is_err());
+
+        let unknown = AdminRequest::new("mallory", deletion("https://a.com/"), b"key").unwrap();
+        assert!(log.authenticate(&unknown).is_err());
+
+        let mut expired =
+            AdminRequest::new("alice", deletion("https://a.com/"), b"alice-key").unwrap();
+        expired.timestamp -= chrono::Duration::seconds(MAX_REQUEST_AGE_SECS + 1);
+        let signature = hmac::sign(&key(b"alice-key"), &expired.signed_bytes().unwrap());
+        expired.signature = BASE64_ENGINE.encode(signature.as_ref());
+        assert!(log.authenticate(&expired).is_err());
+
+        let request = AdminRequest::new("alice", deletion("https://a.com/"), b"alice-key").unwrap();
+        log.authenticate(&request).unwrap();
+
+        // a request can't be replayed
+        assert!(log.authenticate(&request).is_err());
+    }
+
+    #[tokio::test]
+    async fn actions_are_recorded_after_they_are_performed() {
+        let path = crate::gen_temp_path().join("audit.log");
+        let log = Arc::new(AuditLog::open(&path, SECRET, operators()).unwrap());
+
+        let request = AdminRequest::new("alice", deletion("https://a.com/"), b"alice-key").unwrap();
+        let res = perform(Some(&log), request, || {
+            // the entry is written once the action is done
+            assert!(read(&path).unwrap().is_empty());
+            Ok::<_, String>(1)
+        })
+        .await;
+        assert_eq!(res, Ok(1));
+
+        let request = AdminRequest::new("bob", deletion("https://b.com/"), b"bob-key").unwrap();
+        let res = perform(Some(&log), request, || {
+            Err::<(), _>("not in the index".to_string())
+        })
+        .await;
+        assert_eq!(res, Err("not in the index".to_string()));
+
+        // the action is not performed if the request is not authenticated
+        let request = AdminRequest::new("bob", deletion("https://c.com/"), b"alice-key").unwrap();
+        let res = perform(Some(&log), request, || -> Result<()> {
+            panic!("unauthenticated action was performed")
+        })
+        .await;
+        assert!(res.is_err());
+
+        let request = AdminRequest::new("alice", deletion("https://c.com/"), b"alice-key").unwrap();
+        let res = perform(None, request, || -> Result<()> {
+            panic!("unaudited action was performed")
+        })
+        .await;
+        assert!(res.is_err());
+
+        let entries = read(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].outcome, AuditOutcome::Succeeded);
+        assert_eq!(
+            entries[1].outcome,
+            AuditOutcome::Failed {
+                error: "not in the index".to_string()
+            }
+        );
+        assert!(verify(&entries, SECRET).is_ok());
+    }
+}
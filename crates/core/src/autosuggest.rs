@@ -18,50 +18,364 @@
 //! when you type something into the search bar and queries are suggested.
 //! It uses a finite state transducer (fst) to store popular queries
 //! and performs a prefix search on the fst to find suggestions.
+//! Popular queries are not necessarily safe to suggest, so the suggestions
+//! can be filtered with the safety classifier and a denylist of patterns.
 
-use fst::{automaton::Str, Automaton, IntoStreamer};
+use fst::{automaton::Str, Automaton, IntoStreamer, Streamer};
+use regex::{RegexSet, RegexSetBuilder};
 
-use crate::Result;
-use std::path::Path;
+use crate::{config::AutosuggestSafetyConfig, locale::Locale, webpage::safety_classifier, Result};
+use std::{collections::HashMap, path::Path};
+
+const NUM_SUGGESTIONS: usize = 10;
+
+/// The maximum number of completions that are checked by the safety filter for
+/// a query, so a prefix with many unsafe completions does not scan its whole subtree.
+const MAX_CANDIDATES: usize = 100;
 
 pub struct Autosuggest {
     queries: fst::Set<Vec<u8>>,
+    /// The queries for each language, and for the regions of a language
+    /// that have their own queries.
+    localized: HashMap<(Locale, Option<String>), fst::Set<Vec<u8>>>,
+    safety: Option<SafetyFilter>,
+}
+
+fn load_queries<P: AsRef<Path>>(path: P) -> Result<fst::Set<Vec<u8>>> {
+    let mut queries: Vec<String> = Vec::new();
+
+    let mut rdr = csv::Reader::from_path(path)?;
+    for result in rdr.records() {
+        let record = result?;
+        if let Some(query) = record.get(0) {
+            queries.push(query.to_string());
+        }
+    }
+
+    queries.sort();
+    queries.dedup();
+
+    Ok(fst::Set::from_iter(queries)?)
+}
+
+/// Read a denylist with a pattern on each line. Empty lines and lines
+/// starting with `#` are ignored.
+fn load_denylist<P: AsRef<Path>>(path: P) -> Result<RegexSet> {
+    let content = std::fs::read_to_string(path)?;
+    let patterns = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+    Ok(RegexSetBuilder::new(patterns)
+        .case_insensitive(true)
+        .build()?)
+}
+
+/// Removes the completions that are not safe to suggest. Whether a completion
+/// is safe depends on its language, as the denylists are per language.
+pub struct SafetyFilter {
+    classifier: Option<safety_classifier::Model>,
+    min_confidence: f32,
+    denylist: Option<RegexSet>,
+    localized_denylists: HashMap<Locale, RegexSet>,
+}
+
+impl SafetyFilter {
+    pub fn open(config: &AutosuggestSafetyConfig) -> Result<Self> {
+        let classifier = match &config.classifier_path {
+            Some(path) => Some(safety_classifier::Model::open(path)?),
+            None => None,
+        };
+
+        let mut denylist = None;
+        let mut localized_denylists = HashMap::new();
+
+        if let Some(dir) = &config.denylist_path {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+
+                if path.extension().and_then(|ext| ext.to_str()) != Some("txt") {
+                    continue;
+                }
+
+                let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                    continue;
+                };
+
+                if stem == "all" {
+                    denylist = Some(load_denylist(&path)?);
+                    continue;
+                }
+
+                match Locale::from_tag(stem) {
+                    Some(locale) => {
+                        localized_denylists.insert(locale, load_denylist(&path)?);
+                    }
+                    None => tracing::warn!("no supported language for {}", path.display()),
+                }
+            }
+        }
+
+        Ok(Self {
+            classifier,
+            min_confidence: config.min_confidence,
+            denylist,
+            localized_denylists,
+        })
+    }
+
+    pub fn is_safe(&self, suggestion: &str, locale: Locale) -> bool {
+        let denylists = self
+            .denylist
+            .iter()
+            .chain(self.localized_denylists.get(&locale));
+
+        for denylist in denylists {
+            if denylist.is_match(suggestion) {
+                return false;
+            }
+        }
+
+        match &self.classifier {
+            Some(classifier) => {
+                let prediction = classifier.predict_text(suggestion);
+                !(prediction.label == safety_classifier::Label::NSFW
+                    && prediction.confidence >= self.min_confidence)
+            }
+            None => true,
+        }
+    }
 }
 
 impl Autosuggest {
     pub fn load_csv<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let mut queries: Vec<String> = Vec::new();
+        Ok(Self {
+            queries: load_queries(path)?,
+            localized: HashMap::new(),
+            safety: None,
+        })
+    }
 
-        let mut rdr = csv::Reader::from_path(path)?;
-        for result in rdr.records() {
-            let record = result?;
-            if let Some(query) = record.get(0) {
-                queries.push(query.to_string());
+    pub fn with_safety_filter(mut self, filter: SafetyFilter) -> Self {
+        self.safety = Some(filter);
+        self
+    }
+
+    /// Load a csv of queries for each language in the directory. The files are named
+    /// after the language tag of their queries, e.g. `de.csv` as built by
+    /// [`crate::entrypoint::localized_queries`], or `de-CH.csv` for the queries of a region.
+    pub fn with_localized_dir<P: AsRef<Path>>(mut self, path: P) -> Result<Self> {
+        for entry in std::fs::read_dir(path)? {
+            let path = entry?.path();
+
+            if path.extension().and_then(|ext| ext.to_str()) != Some("csv") {
+                continue;
             }
+
+            let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+
+            let Some(locale) = Locale::from_tag(stem) else {
+                tracing::warn!("no supported language for {}", path.display());
+                continue;
+            };
+
+            self.localized.insert(
+                (locale, Locale::region_from_tag(stem)),
+                load_queries(&path)?,
+            );
         }
 
-        queries.sort();
+        Ok(self)
+    }
 
-        let queries = fst::Set::from_iter(queries)?;
+    /// The queries of the region if it has its own, otherwise the queries of the language.
+    fn queries(&self, locale: Locale, region: Option<&str>) -> &fst::Set<Vec<u8>> {
+        region
+            .and_then(|region| self.localized.get(&(locale, Some(region.to_string()))))
+            .or_else(|| self.localized.get(&(locale, None)))
+            .unwrap_or(&self.queries)
+    }
 
-        Ok(Self { queries })
+    pub fn suggestions(&self, query: &str, locale: Locale) -> Result<Vec<String>> {
+        self.regional_suggestions(query, locale, None)
     }
 
-    pub fn suggestions(&self, query: &str) -> Result<Vec<String>> {
-        let query = query.to_ascii_lowercase();
+    /// Suggestions for the query in the language of the locale. `region` is the
+    /// region of the request's language tag, e.g. `CH` for `de-CH`.
+    pub fn regional_suggestions(
+        &self,
+        query: &str,
+        locale: Locale,
+        region: Option<&str>,
+    ) -> Result<Vec<String>> {
+        let query = query.to_lowercase();
         let q = Str::new(query.as_str()).starts_with();
 
-        Ok(self
-            .queries
-            .search(q)
-            .into_stream()
-            .into_strs()?
-            .into_iter()
-            .take(10)
-            .collect())
+        let mut stream = self.queries(locale, region).search(q).into_stream();
+        let mut suggestions = Vec::new();
+
+        let mut candidates = 0;
+
+        // the stream is read lazily, so only the completions up to the
+        // last suggestion are checked by the safety filter.
+        while let Some(suggestion) = stream.next() {
+            if candidates >= MAX_CANDIDATES {
+                break;
+            }
+            candidates += 1;
+
+            let suggestion = String::from_utf8(suggestion.to_vec())?;
+
+            if let Some(safety) = &self.safety {
+                if !safety.is_safe(&suggestion, locale) {
+                    continue;
+                }
+            }
+
+            suggestions.push(suggestion);
+
+            if suggestions.len() >= NUM_SUGGESTIONS {
+                break;
+            }
+        }
+
+        Ok(suggestions)
     }
 
     pub fn all(&self) -> Result<Vec<String>> {
         Ok(self.queries.into_stream().into_strs()?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_csv(path: &Path, queries: &[&str]) {
+        let mut wtr = csv::Writer::from_path(path).unwrap();
+        wtr.write_record(["query"]).unwrap();
+        for query in queries {
+            wtr.write_record([query]).unwrap();
+        }
+        wtr.flush().unwrap();
+    }
+
+    #[test]
+    fn localized_suggestions() {
+        let dir = crate::gen_temp_path();
+        std::fs::create_dir_all(dir.join("localized")).unwrap();
+
+        write_csv(&dir.join("queries.csv"), &["bread recipe", "brexit"]);
+        write_csv(
+            &dir.join("localized").join("de.csv"),
+            &["brot backen", "brötchen"],
+        );
+        write_csv(&dir.join("localized").join("de-CH.csv"), &["brötli"]);
+
+        let autosuggest = Autosuggest::load_csv(dir.join("queries.csv"))
+            .unwrap()
+            .with_localized_dir(dir.join("localized"))
+            .unwrap();
+
+        assert_eq!(
+            autosuggest.suggestions("bre", Locale::English).unwrap(),
+            vec!["bread recipe".to_string(), "brexit".to_string()]
+        );
+        assert_eq!(
+            autosuggest.suggestions("Brö", Locale::German).unwrap(),
+            vec!["brötchen".to_string()]
+        );
+        assert_eq!(
+            autosuggest
+                .regional_suggestions("Brö", Locale::German, Some("CH"))
+                .unwrap(),
+            vec!["brötli".to_string()]
+        );
+        assert_eq!(
+            autosuggest
+                .regional_suggestions("Brö", Locale::German, Some("AT"))
+                .unwrap(),
+            vec!["brötchen".to_string()]
+        );
+        assert_eq!(
+            autosuggest.suggestions("bre", Locale::French).unwrap(),
+            vec!["bread recipe".to_string(), "brexit".to_string()]
+        );
+    }
+
+    #[test]
+    fn denylisted_suggestions() {
+        let dir = crate::gen_temp_path();
+        std::fs::create_dir_all(dir.join("denylist")).unwrap();
+
+        write_csv(
+            &dir.join("queries.csv"),
+            &["how to bake bread", "how to build a bomb", "how to hurt"],
+        );
+        std::fs::write(
+            dir.join("denylist").join("all.txt"),
+            "# weapons\n\\bbomb\\b\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("denylist").join("en.txt"), "HURT\n").unwrap();
+
+        let filter = SafetyFilter::open(&AutosuggestSafetyConfig {
+            classifier_path: None,
+            min_confidence: 0.8,
+            denylist_path: Some(dir.join("denylist").to_str().unwrap().to_string()),
+        })
+        .unwrap();
+
+        let autosuggest = Autosuggest::load_csv(dir.join("queries.csv"))
+            .unwrap()
+            .with_safety_filter(filter);
+
+        assert_eq!(
+            autosuggest.suggestions("how to", Locale::English).unwrap(),
+            vec!["how to bake bread".to_string()]
+        );
+        // the english denylist does not apply to other languages
+        assert_eq!(
+            autosuggest.suggestions("how to", Locale::German).unwrap(),
+            vec!["how to bake bread".to_string(), "how to hurt".to_string()]
+        );
+    }
+
+    #[test]
+    fn candidates_are_capped() {
+        let dir = crate::gen_temp_path();
+        std::fs::create_dir_all(dir.join("denylist")).unwrap();
+
+        let mut queries: Vec<_> = (0..MAX_CANDIDATES)
+            .map(|i| format!("a bomb {i:03}"))
+            .collect();
+        queries.push("a cake".to_string());
+        let queries: Vec<_> = queries.iter().map(String::as_str).collect();
+
+        write_csv(&dir.join("queries.csv"), &queries);
+        std::fs::write(dir.join("denylist").join("all.txt"), "\\bbomb\\b\n").unwrap();
+
+        let filter = SafetyFilter::open(&AutosuggestSafetyConfig {
+            classifier_path: None,
+            min_confidence: 0.8,
+            denylist_path: Some(dir.join("denylist").to_str().unwrap().to_string()),
+        })
+        .unwrap();
+
+        let autosuggest = Autosuggest::load_csv(dir.join("queries.csv"))
+            .unwrap()
+            .with_safety_filter(filter);
+
+        assert!(autosuggest
+            .suggestions("a", Locale::English)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            autosuggest.suggestions("a c", Locale::English).unwrap(),
+            vec!["a cake".to_string()]
+        );
+    }
+}
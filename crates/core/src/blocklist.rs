@@ -0,0 +1,402 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Lists of malware and phishing urls in the style of Safe Browsing.
+//!
+//! The lists contain the SHA-256 hashes of url expressions, i.e. combinations
+//! of host suffixes and path prefixes like `evil.com/` or `a.evil.com/login.html`.
+//! A url is looked up by hashing each of its expressions, so the lists never
+//! contain the urls themselves and everything is done locally.
+//!
+//! The 4 byte prefixes of the hashes are added to a bloom filter, so the full
+//! hashes only have to be compared for the rare expressions that pass the filter.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+    time::SystemTime,
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use url::Url;
+use utoipa::ToSchema;
+
+use crate::{bloom::BloomFilter, config::BlocklistConfig, Result};
+
+const BLOOM_FALSE_POSITIVE_RATE: f64 = 0.001;
+const MAX_HOST_SUFFIXES: usize = 5;
+const MAX_PATH_PREFIXES: usize = 4;
+
+type FullHash = [u8; 32];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ThreatType {
+    Malware,
+    Phishing,
+    Unwanted,
+}
+
+/// What to do with results that match the blocklist.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlocklistAction {
+    /// Keep the results but annotate them with the threat.
+    #[default]
+    Flag,
+    /// Remove the results.
+    Filter,
+}
+
+fn sha256(expression: &str) -> FullHash {
+    let digest = ring::digest::digest(&ring::digest::SHA256, expression.as_bytes());
+    digest.as_ref().try_into().unwrap()
+}
+
+fn prefix(hash: &FullHash) -> u64 {
+    u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]) as u64
+}
+
+fn parse_hash(hex: &str) -> Option<FullHash> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut hash = [0; 32];
+
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(hash)
+}
+
+/// The host suffixes to look up for a host: the host itself and the hosts formed by
+/// removing leading components, starting from the last 5 components and excluding the tld.
+fn host_suffixes(url: &Url) -> Vec<String> {
+    let Some(host) = url.host_str() else {
+        return Vec::new();
+    };
+
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let mut suffixes = vec![host.clone()];
+
+    if matches!(url.host(), Some(url::Host::Domain(_))) {
+        let components: Vec<_> = host.split('.').collect();
+
+        let start = components.len().saturating_sub(MAX_HOST_SUFFIXES).max(1);
+        for i in start..components.len().saturating_sub(1) {
+            suffixes.push(components[i..].join("."));
+        }
+    }
+
+    suffixes
+}
+
+/// The path prefixes to look up: the exact path with and without the query
+/// and up to 4 prefixes of the path starting from the root.
+fn path_prefixes(url: &Url) -> Vec<String> {
+    let path = url.path();
+    let mut prefixes = Vec::new();
+
+    if let Some(query) = url.query() {
+        prefixes.push(format!("{path}?{query}"));
+    }
+
+    prefixes.push(path.to_string());
+
+    let mut prefix = String::from("/");
+    let components: Vec<_> = path
+        .trim_start_matches('/')
+        .split('/')
+        .filter(|c| !c.is_empty())
+        .collect();
+
+    for (i, component) in components.iter().enumerate() {
+        if prefixes.len() > MAX_PATH_PREFIXES + 1 {
+            break;
+        }
+
+        if !prefixes.contains(&prefix) {
+            prefixes.push(prefix.clone());
+        }
+
+        if i + 1 < components.len() {
+            prefix.push_str(component);
+            prefix.push('/');
+        }
+    }
+
+    if !prefixes.contains(&prefix) {
+        prefixes.push(prefix);
+    }
+
+    prefixes
+}
+
+/// All the url expressions to look up for the url, e.g. `a.b.com/1/2.html`,
+/// `a.b.com/1/`, `b.com/` etc.
+pub fn url_expressions(url: &Url) -> Vec<String> {
+    let paths = path_prefixes(url);
+
+    host_suffixes(url)
+        .into_iter()
+        .flat_map(|host| paths.iter().map(move |path| format!("{host}{path}")))
+        .collect()
+}
+
+/// The hash of an url expression as it appears in the blocklist files.
+#[cfg(test)]
+pub fn expression_hash(expression: &str) -> String {
+    sha256(expression)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+pub struct Blocklist {
+    bloom: BloomFilter,
+    hashes: HashMap<FullHash, ThreatType>,
+}
+
+impl Blocklist {
+    pub fn new(hashes: HashMap<FullHash, ThreatType>) -> Self {
+        let mut bloom = BloomFilter::new(hashes.len().max(1) as u64, BLOOM_FALSE_POSITIVE_RATE);
+
+        for hash in hashes.keys() {
+            bloom.insert(prefix(hash));
+        }
+
+        Self { bloom, hashes }
+    }
+
+    /// Parse a list with a hex encoded SHA-256 hash on each line. Empty lines
+    /// and lines starting with `#` are ignored.
+    fn parse_list(
+        list: &str,
+        threat: ThreatType,
+        hashes: &mut HashMap<FullHash, ThreatType>,
+    ) -> Result<()> {
+        for (i, line) in list.lines().map(str::trim).enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let hash = parse_hash(line)
+                .ok_or_else(|| anyhow!("invalid hash on line {}: {:?}", i + 1, line))?;
+
+            hashes.insert(hash, threat);
+        }
+
+        Ok(())
+    }
+
+    pub fn open(config: &BlocklistConfig) -> Result<Self> {
+        let mut hashes = HashMap::new();
+
+        for list in &config.lists {
+            Self::parse_list(&fs::read_to_string(&list.path)?, list.threat, &mut hashes)?;
+        }
+
+        Ok(Self::new(hashes))
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    pub fn lookup(&self, url: &Url) -> Option<ThreatType> {
+        url_expressions(url).into_iter().find_map(|expression| {
+            let hash = sha256(&expression);
+
+            if !self.bloom.contains(&prefix(&hash)) {
+                return None;
+            }
+
+            self.hashes.get(&hash).copied()
+        })
+    }
+}
+
+/// A blocklist that is reloaded when the list files change.
+pub struct LiveBlocklist {
+    config: BlocklistConfig,
+    /// Modification time and size of each list when it was loaded.
+    modified: Mutex<Vec<Option<(SystemTime, u64)>>>,
+    current: RwLock<Arc<Blocklist>>,
+}
+
+impl LiveBlocklist {
+    pub fn open(config: BlocklistConfig) -> Result<Self> {
+        let modified = Self::modified(&config);
+        let blocklist = Blocklist::open(&config)?;
+
+        Ok(Self {
+            config,
+            modified: Mutex::new(modified),
+            current: RwLock::new(Arc::new(blocklist)),
+        })
+    }
+
+    fn modified(config: &BlocklistConfig) -> Vec<Option<(SystemTime, u64)>> {
+        config
+            .lists
+            .iter()
+            .map(|list| {
+                let meta = fs::metadata(Path::new(&list.path)).ok()?;
+                Some((meta.modified().ok()?, meta.len()))
+            })
+            .collect()
+    }
+
+    pub fn config(&self) -> &BlocklistConfig {
+        &self.config
+    }
+
+    pub fn load(&self) -> Arc<Blocklist> {
+        Arc::clone(&self.current.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Reload the lists if any of the files have changed since they were loaded.
+    /// Returns whether the blocklist was reloaded.
+    pub fn refresh(&self) -> Result<bool> {
+        let modified = Self::modified(&self.config);
+        let mut current_modified = self.modified.lock().unwrap_or_else(|e| e.into_inner());
+
+        if modified == *current_modified {
+            return Ok(false);
+        }
+
+        let blocklist = Blocklist::open(&self.config)?;
+        tracing::info!("reloaded blocklist with {} hashes", blocklist.len());
+
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(blocklist);
+        *current_modified = modified;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::BlocklistListConfig;
+
+    use super::*;
+
+    #[test]
+    fn expressions() {
+        let url = Url::parse("http://a.b.c/1/2.html?param=1").unwrap();
+
+        let mut expressions = url_expressions(&url);
+        expressions.sort();
+
+        let mut expected = vec![
+            "a.b.c/1/2.html?param=1",
+            "a.b.c/1/2.html",
+            "a.b.c/",
+            "a.b.c/1/",
+            "b.c/1/2.html?param=1",
+            "b.c/1/2.html",
+            "b.c/",
+            "b.c/1/",
+        ];
+        expected.sort();
+
+        assert_eq!(expressions, expected);
+
+        let url = Url::parse("https://example.com").unwrap();
+        assert_eq!(url_expressions(&url), vec!["example.com/"]);
+
+        let url = Url::parse("http://1.2.3.4/1/").unwrap();
+        assert_eq!(url_expressions(&url), vec!["1.2.3.4/1/", "1.2.3.4/"]);
+    }
+
+    #[test]
+    fn lookup() {
+        let mut hashes = HashMap::new();
+        hashes.insert(
+            parse_hash(&expression_hash("evil.com/")).unwrap(),
+            ThreatType::Malware,
+        );
+        hashes.insert(
+            parse_hash(&expression_hash("example.com/login/")).unwrap(),
+            ThreatType::Phishing,
+        );
+
+        let blocklist = Blocklist::new(hashes);
+
+        assert_eq!(
+            blocklist.lookup(&Url::parse("https://www.evil.com/download.exe").unwrap()),
+            Some(ThreatType::Malware)
+        );
+        assert_eq!(
+            blocklist.lookup(&Url::parse("https://example.com/login/account").unwrap()),
+            Some(ThreatType::Phishing)
+        );
+        assert_eq!(
+            blocklist.lookup(&Url::parse("https://example.com/about").unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn refresh() {
+        let path = crate::gen_temp_path();
+        fs::create_dir_all(&path).unwrap();
+        let list_path = path.join("phishing.txt");
+
+        fs::write(&list_path, "# phishing\n").unwrap();
+
+        let config = BlocklistConfig {
+            lists: vec![BlocklistListConfig {
+                path: list_path.to_str().unwrap().to_string(),
+                threat: ThreatType::Phishing,
+            }],
+            action: BlocklistAction::Flag,
+            refresh_interval_sec: 60,
+        };
+
+        let live = LiveBlocklist::open(config).unwrap();
+        let url = Url::parse("https://phishing.example.com/").unwrap();
+
+        assert!(live.load().is_empty());
+        assert!(!live.refresh().unwrap());
+
+        // make sure the modification time changes
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        fs::write(
+            &list_path,
+            format!("{}\n", expression_hash("phishing.example.com/")),
+        )
+        .unwrap();
+
+        assert!(live.refresh().unwrap());
+        assert_eq!(live.load().lookup(&url), Some(ThreatType::Phishing));
+    }
+
+    #[test]
+    fn invalid_hash() {
+        let mut hashes = HashMap::new();
+        assert!(Blocklist::parse_list("not a hash", ThreatType::Malware, &mut hashes).is_err());
+    }
+}
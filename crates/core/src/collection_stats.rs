@@ -0,0 +1,264 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Collection statistics that are merged across the shards of the index.
+//!
+//! BM25 uses the number of documents, the number of tokens of each field and the
+//! document frequency of each term. When each shard uses its own statistics, the
+//! same page gets different scores depending on the shard it is in. The shards
+//! report their statistics, which are merged and sent back to every searcher.
+//!
+//! Only the terms with the highest document frequencies are kept for each field.
+//! The document frequency of a term that is not in the statistics is estimated from
+//! the local document frequency, scaled by the size of the collection.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use serde::{Deserialize, Serialize};
+use tantivy::{query::Bm25StatisticsProvider, Term};
+
+use crate::{entrypoint::term_export, Result};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FieldStats {
+    pub num_tokens: u64,
+    pub doc_freqs: HashMap<Vec<u8>, u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CollectionStats {
+    pub num_docs: u64,
+    /// Statistics of each text field by the name of the field.
+    pub fields: HashMap<String, FieldStats>,
+}
+
+impl CollectionStats {
+    /// Statistics of the index. Only the `max_terms_per_field` terms with the highest document
+    /// frequencies, and at least `min_doc_freq`, are kept for each field.
+    pub fn from_searcher(
+        searcher: &tantivy::Searcher,
+        min_doc_freq: u64,
+        max_terms_per_field: usize,
+    ) -> Result<Self> {
+        let mut fields = HashMap::new();
+
+        for field in term_export::fields(&[])? {
+            let mut top = BinaryHeap::with_capacity(max_terms_per_field + 1);
+
+            term_export::for_each_term(searcher, field, |term, doc_freq| {
+                if doc_freq >= min_doc_freq {
+                    top.push(Reverse((doc_freq, term.to_vec())));
+
+                    if top.len() > max_terms_per_field {
+                        top.pop();
+                    }
+                }
+
+                Ok(())
+            })?;
+
+            fields.insert(
+                field.name().to_string(),
+                FieldStats {
+                    num_tokens: term_export::total_num_tokens(searcher, field)?,
+                    doc_freqs: top
+                        .into_iter()
+                        .map(|Reverse((doc_freq, term))| (term, doc_freq))
+                        .collect(),
+                },
+            );
+        }
+
+        Ok(Self {
+            num_docs: searcher.num_docs(),
+            fields,
+        })
+    }
+
+    /// Add the statistics of another shard.
+    pub fn merge(&mut self, other: &CollectionStats) {
+        self.num_docs += other.num_docs;
+
+        for (name, other) in &other.fields {
+            let field = self.fields.entry(name.clone()).or_default();
+            field.num_tokens += other.num_tokens;
+
+            for (term, doc_freq) in &other.doc_freqs {
+                *field.doc_freqs.entry(term.clone()).or_default() += doc_freq;
+            }
+        }
+    }
+}
+
+/// Statistics for BM25 that use the global statistics where they are
+/// known, and the statistics of the local index otherwise.
+pub struct StatisticsProvider<'a> {
+    searcher: &'a tantivy::Searcher,
+    global: Option<&'a CollectionStats>,
+}
+
+impl<'a> StatisticsProvider<'a> {
+    pub fn new(searcher: &'a tantivy::Searcher, global: Option<&'a CollectionStats>) -> Self {
+        Self { searcher, global }
+    }
+
+    fn field_stats(&self, field: tantivy::schema::Field) -> Option<&'a FieldStats> {
+        let name = self.searcher.schema().get_field_name(field);
+        self.global?.fields.get(name)
+    }
+}
+
+impl Bm25StatisticsProvider for StatisticsProvider<'_> {
+    fn total_num_tokens(&self, field: tantivy::schema::Field) -> tantivy::Result<u64> {
+        match self.field_stats(field) {
+            Some(stats) => Ok(stats.num_tokens),
+            None => self.searcher.total_num_tokens(field),
+        }
+    }
+
+    fn total_num_docs(&self) -> tantivy::Result<u64> {
+        match self.global {
+            Some(global) => Ok(global.num_docs.max(self.searcher.num_docs())),
+            None => self.searcher.total_num_docs(),
+        }
+    }
+
+    fn doc_freq(&self, term: &Term) -> tantivy::Result<u64> {
+        let local = self.searcher.doc_freq(term)?;
+
+        let Some(global) = self.global else {
+            return Ok(local);
+        };
+
+        let num_docs = self.total_num_docs()?;
+
+        if let Some(doc_freq) = self
+            .field_stats(term.field())
+            .and_then(|stats| stats.doc_freqs.get(term.serialized_value_bytes()))
+        {
+            return Ok((*doc_freq).min(num_docs));
+        }
+
+        let local_num_docs = self.searcher.num_docs().max(1);
+        let scaled = (local as f64 * global.num_docs as f64 / local_num_docs as f64).round() as u64;
+
+        Ok(scaled.min(num_docs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{index::Index, schema::TextField, webpage::Webpage};
+
+    use super::*;
+
+    fn index(titles: &[&str]) -> Index {
+        let mut index = Index::temporary().unwrap();
+
+        for (i, title) in titles.iter().enumerate() {
+            index
+                .insert(
+                    Webpage::new(
+                        &format!(
+                            r#"
+                    <html>
+                        <head>
+                            <title>{title}</title>
+                        </head>
+                        <body>
+                            {}
+                        </body>
+                    </html>
+                    "#,
+                            crate::rand_words(20)
+                        ),
+                        &format!("https://example{i}.com/"),
+                    )
+                    .unwrap(),
+                )
+                .unwrap();
+        }
+
+        index.commit().unwrap();
+        index
+    }
+
+    fn title_term(searcher: &tantivy::Searcher, text: &str) -> Term {
+        let field = searcher
+            .schema()
+            .get_field(TextField::Title.name())
+            .unwrap();
+
+        Term::from_field_text(field, text)
+    }
+
+    #[test]
+    fn merged_doc_freqs() {
+        let a = index(&["rare word", "common word"]);
+        let b = index(&["common", "common", "common", "common"]);
+
+        let a_stats =
+            CollectionStats::from_searcher(&a.inverted_index.tv_searcher(), 1, 1000).unwrap();
+        let b_stats =
+            CollectionStats::from_searcher(&b.inverted_index.tv_searcher(), 1, 1000).unwrap();
+
+        let mut global = CollectionStats::default();
+        global.merge(&a_stats);
+        global.merge(&b_stats);
+        assert_eq!(global.num_docs, 6);
+
+        let searcher = a.inverted_index.tv_searcher();
+        let local = StatisticsProvider::new(&searcher, None);
+        let merged = StatisticsProvider::new(&searcher, Some(&global));
+
+        let common = title_term(&searcher, "common");
+        assert_eq!(local.doc_freq(&common).unwrap(), 1);
+        assert_eq!(merged.doc_freq(&common).unwrap(), 5);
+        assert_eq!(merged.total_num_docs().unwrap(), 6);
+
+        // the common term is less important in the global statistics
+        let rare = title_term(&searcher, "rare");
+        assert_eq!(merged.doc_freq(&rare).unwrap(), 1);
+        assert!(
+            crate::ranking::bm25::idf(merged.doc_freq(&common).unwrap(), 6)
+                < crate::ranking::bm25::idf(local.doc_freq(&common).unwrap(), 2)
+        );
+    }
+
+    #[test]
+    fn only_top_terms_are_kept() {
+        let index = index(&["common rare", "common", "common"]);
+        let searcher = index.inverted_index.tv_searcher();
+
+        let stats = CollectionStats::from_searcher(&searcher, 1, 1).unwrap();
+        let title = &stats.fields[TextField::Title.name()];
+        assert_eq!(title.doc_freqs.len(), 1);
+        assert_eq!(title.doc_freqs.get("common".as_bytes()), Some(&3));
+
+        let mut global = stats.clone();
+        global.merge(&stats);
+
+        // the rare term is estimated from the local document frequency
+        let provider = StatisticsProvider::new(&searcher, Some(&global));
+        assert_eq!(
+            provider.doc_freq(&title_term(&searcher, "rare")).unwrap(),
+            2
+        );
+    }
+}
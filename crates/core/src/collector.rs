@@ -14,7 +14,14 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
 use min_max_heap::MinMaxHeap;
 use serde::{Deserialize, Serialize};
@@ -32,10 +39,14 @@ use crate::{
     ranking::initial::{InitialScoreTweaker, Score},
     schema::FastField,
     simhash,
+    tombstone::Tombstones,
 };
 
 pub type MainCollector = TweakedScoreTopCollector<InitialScoreTweaker>;
 
+/// Number of documents the segment collectors take between checks of the deadline.
+const DEADLINE_CHECK_INTERVAL: usize = 256;
+
 #[derive(Clone, Debug)]
 pub struct MaxDocsConsidered {
     pub total_docs: usize,
@@ -49,6 +60,9 @@ pub struct Hashes {
     pub url: Prehashed,
     pub url_without_tld: Prehashed,
     pub simhash: simhash::HashType,
+    /// Hash of the DOI of the page, or 0 if it has none. Pages with the same DOI,
+    /// like the mirrors of a preprint, are collapsed into a single result.
+    pub doi: u64,
 }
 
 pub trait Doc: Clone {
@@ -63,6 +77,10 @@ pub struct TopDocs {
     fastfield_reader: fastfield_reader::FastFieldReader,
     de_rank_similar: bool,
     collector_config: CollectorConfig,
+    tombstones: Option<Arc<Tombstones>>,
+    deadline: Option<Instant>,
+    truncated: Arc<AtomicBool>,
+    docs_scored: Arc<AtomicU64>,
 }
 
 impl TopDocs {
@@ -74,6 +92,10 @@ impl TopDocs {
             de_rank_similar: false,
             fastfield_reader,
             collector_config: CollectorConfig::default(),
+            tombstones: None,
+            deadline: None,
+            truncated: Arc::new(AtomicBool::new(false)),
+            docs_scored: Arc::new(AtomicU64::new(0)),
         }
     }
 
@@ -101,6 +123,28 @@ impl TopDocs {
         self
     }
 
+    /// Skip the tombstoned documents.
+    pub fn and_tombstones(mut self, tombstones: Arc<Tombstones>) -> Self {
+        self.tombstones = Some(tombstones);
+        self
+    }
+
+    /// Stop collecting documents at the deadline, and keep the documents collected until then.
+    pub fn and_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Whether a segment stopped collecting documents because the deadline passed.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated.load(Ordering::Relaxed)
+    }
+
+    /// Number of documents the segment collectors have scored.
+    pub fn num_docs_scored(&self) -> u64 {
+        self.docs_scored.load(Ordering::Relaxed)
+    }
+
     pub fn main_collector(self, score_tweaker: InitialScoreTweaker) -> MainCollector {
         TweakedScoreTopCollector::new(score_tweaker, self)
     }
@@ -121,7 +165,16 @@ impl TopDocs {
             fastfield_segment_reader: self.fastfield_reader.get_segment(&segment.segment_id()),
             max_docs,
             num_docs_taken: 0,
+            deadline: self.deadline,
+            timed_out: false,
+            truncated: Arc::clone(&self.truncated),
+            num_docs_scored: 0,
+            docs_scored: Arc::clone(&self.docs_scored),
             segment_ord: segment_local_id,
+            tombstoned: self
+                .tombstones
+                .as_ref()
+                .and_then(|tombstones| tombstones.segment(segment.segment_id())),
             bucket_collector: BucketCollector::new(
                 self.top_n + self.offset,
                 self.collector_config.clone(),
@@ -134,7 +187,13 @@ pub struct TopSegmentCollector {
     fastfield_segment_reader: Arc<fastfield_reader::SegmentReader>,
     max_docs: Option<usize>,
     num_docs_taken: usize,
+    deadline: Option<Instant>,
+    timed_out: bool,
+    truncated: Arc<AtomicBool>,
+    num_docs_scored: u64,
+    docs_scored: Arc<AtomicU64>,
     segment_ord: SegmentOrdinal,
+    tombstoned: Option<Arc<BTreeSet<DocId>>>,
     bucket_collector: BucketCollector<SegmentDoc>,
 }
 
@@ -149,6 +208,10 @@ impl TopSegmentCollector {
 
 impl TopSegmentCollector {
     fn is_done(&self) -> bool {
+        if self.timed_out {
+            return true;
+        }
+
         if let Some(max_docs) = &self.max_docs {
             self.num_docs_taken >= *max_docs
         } else {
@@ -156,11 +219,32 @@ impl TopSegmentCollector {
         }
     }
 
+    fn check_deadline(&mut self) {
+        if let Some(deadline) = self.deadline {
+            if self.num_docs_taken % DEADLINE_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+                self.timed_out = true;
+                self.truncated.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
     fn collect(&mut self, doc: DocId, score: Score) {
+        self.check_deadline();
+
         if self.is_done() {
             return;
         }
 
+        self.num_docs_scored += 1;
+
+        if self
+            .tombstoned
+            .as_ref()
+            .is_some_and(|tombstoned| tombstoned.contains(&doc))
+        {
+            return;
+        }
+
         self.num_docs_taken += 1;
 
         let simhash: Option<u64> = self
@@ -168,6 +252,11 @@ impl TopSegmentCollector {
             .get_field_reader(&doc)
             .get(&FastField::SimHash)
             .into();
+        let doi: Option<u64> = self
+            .fastfield_segment_reader
+            .get_field_reader(&doc)
+            .get(&FastField::DoiHash)
+            .into();
 
         self.bucket_collector.insert(SegmentDoc {
             hashes: Hashes {
@@ -180,6 +269,7 @@ impl TopSegmentCollector {
                     &FastField::UrlWithoutTldHash2,
                 ),
                 simhash: simhash.unwrap(),
+                doi: doi.unwrap_or_default(),
             },
             id: doc,
             segment: self.segment_ord,
@@ -188,6 +278,8 @@ impl TopSegmentCollector {
     }
 
     fn harvest(self) -> Vec<SegmentDoc> {
+        self.docs_scored
+            .fetch_add(self.num_docs_scored, Ordering::Relaxed);
         self.bucket_collector.into_sorted_vec(true)
     }
 }
@@ -309,10 +401,15 @@ impl<T: Doc> BucketCollector<T> {
         let mut res = Vec::new();
         let mut simhash_dups = Vec::new();
         let mut simhash = simhash::Table::default();
+        let mut dois = HashSet::new();
 
         while let Some(best_doc) = self.documents.pop_max() {
             let hashes = best_doc.doc.hashes();
 
+            if hashes.doi != 0 && de_rank_similar && !dois.insert(hashes.doi) {
+                continue;
+            }
+
             if hashes.simhash != 0 && de_rank_similar {
                 if simhash.contains(&hashes.simhash) {
                     simhash_dups.push(best_doc.doc);
@@ -454,6 +551,8 @@ where
     type Fruit = Vec<SegmentDoc>;
 
     fn collect(&mut self, doc: DocId, score: tantivy::Score) {
+        self.segment_collector.check_deadline();
+
         if self.segment_collector.is_done() {
             return;
         }
@@ -504,6 +603,7 @@ mod tests {
                         url: 1.into(),
                         url_without_tld: 1.into(),
                         simhash: 12,
+                        doi: 0,
                     },
                     123,
                     1.0,
@@ -515,6 +615,7 @@ mod tests {
                         url: 2.into(),
                         url_without_tld: 2.into(),
                         simhash: 123,
+                        doi: 0,
                     },
                     124,
                     2.0,
@@ -526,6 +627,7 @@ mod tests {
                         url: 3.into(),
                         url_without_tld: 3.into(),
                         simhash: 1234,
+                        doi: 0,
                     },
                     125,
                     3.0,
@@ -537,6 +639,7 @@ mod tests {
                         url: 4.into(),
                         url_without_tld: 4.into(),
                         simhash: 12345,
+                        doi: 0,
                     },
                     126,
                     4.0,
@@ -548,6 +651,7 @@ mod tests {
                         url: 5.into(),
                         url_without_tld: 5.into(),
                         simhash: 123456,
+                        doi: 0,
                     },
                     127,
                     5.0,
@@ -569,6 +673,7 @@ mod tests {
                         url: 3.into(),
                         url_without_tld: 3.into(),
                         simhash: 12,
+                        doi: 0,
                     },
                     125,
                     3.0,
@@ -580,6 +685,7 @@ mod tests {
                         url: 4.into(),
                         url_without_tld: 4.into(),
                         simhash: 123,
+                        doi: 0,
                     },
                     126,
                     4.0,
@@ -591,6 +697,7 @@ mod tests {
                         url: 5.into(),
                         url_without_tld: 5.into(),
                         simhash: 1234,
+                        doi: 0,
                     },
                     127,
                     5.0,
@@ -612,6 +719,7 @@ mod tests {
                         url: 1.into(),
                         url_without_tld: 1.into(),
                         simhash: 12,
+                        doi: 0,
                     },
                     125,
                     3.0,
@@ -623,6 +731,7 @@ mod tests {
                         url: 2.into(),
                         url_without_tld: 2.into(),
                         simhash: 123,
+                        doi: 0,
                     },
                     126,
                     3.1,
@@ -634,6 +743,7 @@ mod tests {
                         url: 2.into(),
                         url_without_tld: 2.into(),
                         simhash: 1234,
+                        doi: 0,
                     },
                     127,
                     5.0,
@@ -652,6 +762,7 @@ mod tests {
                         url: 1.into(),
                         url_without_tld: 1.into(),
                         simhash: 12,
+                        doi: 0,
                     },
                     125,
                     3.0,
@@ -663,6 +774,7 @@ mod tests {
                         url: 2.into(),
                         url_without_tld: 2.into(),
                         simhash: 123,
+                        doi: 0,
                     },
                     126,
                     3.1,
@@ -674,6 +786,7 @@ mod tests {
                         url: 2.into(),
                         url_without_tld: 2.into(),
                         simhash: 1234,
+                        doi: 0,
                     },
                     127,
                     5.0,
@@ -695,6 +808,7 @@ mod tests {
                         url: 1.into(),
                         url_without_tld: 1.into(),
                         simhash: 1234,
+                        doi: 0,
                     },
                     125,
                     3.0,
@@ -706,6 +820,7 @@ mod tests {
                         url: 2.into(),
                         url_without_tld: 2.into(),
                         simhash: 1234,
+                        doi: 0,
                     },
                     126,
                     3.1,
@@ -717,6 +832,7 @@ mod tests {
                         url: 3.into(),
                         url_without_tld: 3.into(),
                         simhash: 1,
+                        doi: 0,
                     },
                     127,
                     5.0,
@@ -725,4 +841,50 @@ mod tests {
             &[(5.0, 127), (3.1, 126), (3.0, 125)],
         );
     }
+
+    #[test]
+    fn doi_dedup() {
+        test(
+            10,
+            &[
+                (
+                    Hashes {
+                        site: 1.into(),
+                        title: 1.into(),
+                        url: 1.into(),
+                        url_without_tld: 1.into(),
+                        simhash: 1,
+                        doi: 42,
+                    },
+                    125,
+                    3.0,
+                ),
+                (
+                    Hashes {
+                        site: 2.into(),
+                        title: 2.into(),
+                        url: 2.into(),
+                        url_without_tld: 2.into(),
+                        simhash: 2,
+                        doi: 42,
+                    },
+                    126,
+                    4.0,
+                ),
+                (
+                    Hashes {
+                        site: 3.into(),
+                        title: 3.into(),
+                        url: 3.into(),
+                        url_without_tld: 3.into(),
+                        simhash: 3,
+                        doi: 0,
+                    },
+                    127,
+                    5.0,
+                ),
+            ],
+            &[(5.0, 127), (4.0, 126)],
+        );
+    }
 }
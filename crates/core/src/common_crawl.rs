@@ -0,0 +1,361 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Reading the segments published by [Common Crawl](https://commoncrawl.org/).
+//!
+//! Common Crawl publishes every crawl in three formats:
+//! * WARC files with the raw responses, which have the same layout as our own warc files.
+//! * WET files with the plain text extracted from each html response.
+//! * WAT files with json metadata about each response, including the links on the page.
+//!
+//! The segments are streamed, so they are never fully decompressed in memory.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use url::Url;
+
+use crate::{
+    config::WarcSource,
+    warc::{RawWarcRecord, WarcFile},
+    webpage::Link,
+    Result,
+};
+
+/// The only links from the WAT metadata that are used, i.e. `<a href="...">`.
+const ANCHOR_LINK_PATH: &str = "A@/href";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    Warc,
+    Wat,
+    Wet,
+}
+
+impl SegmentKind {
+    /// The kind of segment from its file name, e.g. `CC-MAIN-20231211-00000.warc.wat.gz`.
+    pub fn from_path(path: &str) -> Option<Self> {
+        if path.ends_with(".wat.gz") {
+            Some(Self::Wat)
+        } else if path.ends_with(".wet.gz") {
+            Some(Self::Wet)
+        } else if path.ends_with(".warc.gz") {
+            Some(Self::Warc)
+        } else {
+            None
+        }
+    }
+}
+
+/// A segment that has been downloaded to a temporary file. The file is removed when dropped.
+struct DownloadedSegment {
+    path: PathBuf,
+    reader: BufReader<File>,
+}
+
+impl Read for DownloadedSegment {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Drop for DownloadedSegment {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+/// Open the compressed segment for reading. Local segments are read directly from disk,
+/// while remote segments are first downloaded to a temporary file.
+pub fn open_segment(source: &WarcSource, path: &str) -> Result<Box<dyn Read + Send>> {
+    match source {
+        WarcSource::Local(config) => Ok(Box::new(BufReader::new(File::open(
+            Path::new(&config.folder).join(path),
+        )?))),
+        WarcSource::HTTP(_) | WarcSource::S3(_) => {
+            let temp_path = crate::gen_temp_path();
+            let mut file = File::options()
+                .create(true)
+                .truncate(true)
+                .read(true)
+                .write(true)
+                .open(&temp_path)?;
+
+            let downloaded = WarcFile::download_into_buf(source, path, &mut file).and_then(|_| {
+                file.rewind()?;
+                Ok(())
+            });
+
+            if let Err(err) = downloaded {
+                std::fs::remove_file(&temp_path).ok();
+                return Err(err);
+            }
+
+            Ok(Box::new(DownloadedSegment {
+                path: temp_path,
+                reader: BufReader::new(file),
+            }))
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn fetch_time_ms(record: &RawWarcRecord) -> u64 {
+    record
+        .header("WARC-Date")
+        .and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok())
+        .map(|date| date.timestamp_millis().max(0) as u64)
+        .unwrap_or_default()
+}
+
+/// The extracted text of a page from a WET `conversion` record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WetPage {
+    pub url: String,
+    pub text: String,
+    pub fetch_time_ms: u64,
+}
+
+impl WetPage {
+    pub fn from_record(record: &RawWarcRecord) -> Option<Self> {
+        if record.warc_type()? != "conversion" {
+            return None;
+        }
+
+        Some(Self {
+            url: record.header("WARC-Target-URI")?.to_string(),
+            text: String::from_utf8_lossy(record.content()).to_string(),
+            fetch_time_ms: fetch_time_ms(record),
+        })
+    }
+
+    /// A minimal html document with the text, so the page can be indexed like any other page.
+    /// Common Crawl puts the title of the page on the first line of the text.
+    pub fn to_html(&self) -> String {
+        let mut lines = self
+            .text
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty());
+
+        let title = lines.next().unwrap_or_default();
+
+        let mut html = format!(
+            "<!DOCTYPE html><html><head><title>{}</title></head><body>",
+            escape_html(title)
+        );
+
+        for line in lines {
+            html.push_str("<p>");
+            html.push_str(&escape_html(line));
+            html.push_str("</p>");
+        }
+
+        html.push_str("</body></html>");
+
+        html
+    }
+}
+
+/// The anchor links of the page from a WAT `metadata` record.
+pub fn wat_links(record: &RawWarcRecord) -> Option<Vec<Link>> {
+    if record.warc_type()? != "metadata" {
+        return None;
+    }
+
+    let metadata: serde_json::Value = serde_json::from_slice(record.content()).ok()?;
+    let envelope = metadata.get("Envelope")?;
+
+    let warc_header = envelope.get("WARC-Header-Metadata")?;
+    if warc_header.get("WARC-Type")?.as_str()? != "response" {
+        return None;
+    }
+
+    let source = Url::parse(warc_header.get("WARC-Target-URI")?.as_str()?).ok()?;
+
+    let html = envelope
+        .get("Payload-Metadata")?
+        .get("HTTP-Response-Metadata")?
+        .get("HTML-Metadata")?;
+
+    let base = html
+        .get("Head")
+        .and_then(|head| head.get("Base"))
+        .and_then(|base| base.as_str())
+        .and_then(|base| source.join(base).ok())
+        .unwrap_or_else(|| source.clone());
+
+    let links = html
+        .get("Links")?
+        .as_array()?
+        .iter()
+        .filter(|link| link.get("path").and_then(|path| path.as_str()) == Some(ANCHOR_LINK_PATH))
+        .filter_map(|link| {
+            let destination = base.join(link.get("url")?.as_str()?).ok()?;
+
+            if !matches!(destination.scheme(), "http" | "https") {
+                return None;
+            }
+
+            Some(Link {
+                source: source.clone(),
+                destination,
+                text: link
+                    .get("text")
+                    .and_then(|text| text.as_str())
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+                rel: link
+                    .get("rel")
+                    .and_then(|rel| rel.as_str())
+                    .map(String::from),
+            })
+        })
+        .collect();
+
+    Some(links)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use crate::warc::RecordIterator;
+
+    use super::*;
+
+    fn record(warc_type: &str, target: &str, content: &str) -> String {
+        format!(
+            "WARC/1.0\r\nWARC-Type: {}\r\nWARC-Target-URI: {}\r\nWARC-Date: 2023-12-11T10:00:00Z\r\nContent-Length: {}\r\n\r\n{}\r\n\r\n",
+            warc_type,
+            target,
+            content.len(),
+            content
+        )
+    }
+
+    fn raw_records(records: &[String]) -> Vec<RawWarcRecord> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(records.concat().as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        RecordIterator::new(&compressed[..])
+            .raw()
+            .map(|record| record.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn segment_kind() {
+        assert_eq!(
+            SegmentKind::from_path(
+                "crawl-data/CC-MAIN-2023-50/segments/1/wat/CC-MAIN-1-00000.warc.wat.gz"
+            ),
+            Some(SegmentKind::Wat)
+        );
+        assert_eq!(
+            SegmentKind::from_path("CC-MAIN-1-00000.warc.wet.gz"),
+            Some(SegmentKind::Wet)
+        );
+        assert_eq!(
+            SegmentKind::from_path("CC-MAIN-1-00000.warc.gz"),
+            Some(SegmentKind::Warc)
+        );
+        assert_eq!(SegmentKind::from_path("wat.paths.gz"), None);
+    }
+
+    #[test]
+    fn wet_page() {
+        let records = raw_records(&[
+            record("warcinfo", "", "software: test"),
+            record(
+                "conversion",
+                "https://example.com/",
+                "Example <Domain>\nThis domain is for use in examples.\n\nMore information...",
+            ),
+        ]);
+
+        assert_eq!(WetPage::from_record(&records[0]), None);
+
+        let page = WetPage::from_record(&records[1]).unwrap();
+        assert_eq!(page.url, "https://example.com/");
+        assert_eq!(page.fetch_time_ms, 1702288800000);
+        assert_eq!(
+            page.to_html(),
+            "<!DOCTYPE html><html><head><title>Example &lt;Domain&gt;</title></head><body><p>This domain is for use in examples.</p><p>More information...</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn wat_anchor_links() {
+        let metadata = serde_json::json!({
+            "Envelope": {
+                "WARC-Header-Metadata": {
+                    "WARC-Type": "response",
+                    "WARC-Target-URI": "https://example.com/blog/post"
+                },
+                "Payload-Metadata": {
+                    "HTTP-Response-Metadata": {
+                        "HTML-Metadata": {
+                            "Links": [
+                                {"path": "A@/href", "url": "/about", "text": " About us "},
+                                {"path": "A@/href", "url": "https://other.org/page"},
+                                {"path": "A@/href", "url": "mailto:hello@example.com"},
+                                {"path": "IMG@/src", "url": "/logo.png"}
+                            ]
+                        }
+                    }
+                }
+            }
+        })
+        .to_string();
+
+        let records = raw_records(&[record(
+            "metadata",
+            "https://example.com/blog/post",
+            &metadata,
+        )]);
+
+        let links = wat_links(&records[0]).unwrap();
+
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].destination.as_str(), "https://example.com/about");
+        assert_eq!(links[0].text, "About us");
+        assert_eq!(links[1].destination.as_str(), "https://other.org/page");
+        assert_eq!(links[1].source.as_str(), "https://example.com/blog/post");
+    }
+}
@@ -0,0 +1,156 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Deserialize socket addresses from ip literals (`0.0.0.0:3000`, `[::]:3000`)
+//! or hostnames (`search-0.internal:3000`). Hostnames are resolved when the
+//! config is loaded. Addresses to bind to use the first resolved address, while
+//! the servers to connect to keep all of them with [`deserialize_hosts`], so the
+//! connections can fall back between them. Formats that are not human readable
+//! (e.g. bincode) use the regular representation of the addresses.
+
+use std::net::SocketAddr;
+
+use serde::{de, Deserialize, Deserializer};
+
+use crate::distributed::net;
+
+/// All the addresses of the host.
+pub fn resolve<E: de::Error>(host: &str) -> Result<Vec<SocketAddr>, E> {
+    net::resolve(host).map_err(|e| E::custom(format!("failed to resolve '{host}': {e}")))
+}
+
+fn resolve_first<E: de::Error>(host: &str) -> Result<SocketAddr, E> {
+    resolve(host).map(|addrs| addrs[0])
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<SocketAddr, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    if !deserializer.is_human_readable() {
+        return SocketAddr::deserialize(deserializer);
+    }
+
+    resolve_first(&String::deserialize(deserializer)?)
+}
+
+pub fn deserialize_opt<'de, D>(deserializer: D) -> Result<Option<SocketAddr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    if !deserializer.is_human_readable() {
+        return Option::<SocketAddr>::deserialize(deserializer);
+    }
+
+    Option::<String>::deserialize(deserializer)?
+        .map(|host| resolve_first(&host))
+        .transpose()
+}
+
+pub fn deserialize_vec<'de, D>(deserializer: D) -> Result<Vec<SocketAddr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    if !deserializer.is_human_readable() {
+        return Vec::<SocketAddr>::deserialize(deserializer);
+    }
+
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|host| resolve_first(host))
+        .collect()
+}
+
+/// A list of hosts with all the addresses of each host.
+pub fn deserialize_hosts<'de, D>(deserializer: D) -> Result<Vec<Vec<SocketAddr>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    if !deserializer.is_human_readable() {
+        return Vec::<Vec<SocketAddr>>::deserialize(deserializer);
+    }
+
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|host| resolve(host))
+        .collect()
+}
+
+pub fn deserialize_opt_vec<'de, D>(deserializer: D) -> Result<Option<Vec<SocketAddr>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    if !deserializer.is_human_readable() {
+        return Option::<Vec<SocketAddr>>::deserialize(deserializer);
+    }
+
+    Option::<Vec<String>>::deserialize(deserializer)?
+        .map(|hosts| hosts.iter().map(|host| resolve_first(host)).collect())
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Config {
+        #[serde(deserialize_with = "deserialize")]
+        host: SocketAddr,
+        #[serde(default, deserialize_with = "deserialize_opt_vec")]
+        seeds: Option<Vec<SocketAddr>>,
+        #[serde(default, deserialize_with = "deserialize_hosts")]
+        servers: Vec<Vec<SocketAddr>>,
+    }
+
+    #[test]
+    fn literals_and_hostnames() {
+        let config: Config = toml::from_str(
+            r#"
+            host = "[::]:3000"
+            seeds = ["127.0.0.1:3001", "[::1]:3002", "localhost:3003"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.host, "[::]:3000".parse::<SocketAddr>().unwrap());
+
+        let seeds = config.seeds.unwrap();
+        assert_eq!(seeds.len(), 3);
+        assert_eq!(seeds[1], "[::1]:3002".parse::<SocketAddr>().unwrap());
+        assert!(seeds[2].ip().is_loopback());
+        assert_eq!(seeds[2].port(), 3003);
+
+        let config: Config = toml::from_str(r#"host = "127.0.0.1:3000""#).unwrap();
+        assert!(config.seeds.is_none());
+        assert!(config.servers.is_empty());
+
+        let config: Config = toml::from_str(
+            r#"
+            host = "127.0.0.1:3000"
+            servers = ["[::1]:3001", "localhost:3002"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.servers[0],
+            vec!["[::1]:3001".parse::<SocketAddr>().unwrap()]
+        );
+        assert_eq!(config.servers[1], net::resolve("localhost:3002").unwrap());
+
+        assert!(toml::from_str::<Config>(r#"host = "not an address""#).is_err());
+    }
+}
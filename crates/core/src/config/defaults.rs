@@ -14,6 +14,8 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use super::AbuseAction;
+
 pub struct Collector;
 
 impl Collector {
@@ -48,6 +50,254 @@ impl Api {
     pub fn entity_sidebar() -> f64 {
         10.0
     }
+
+    pub fn min_head_recall() -> f64 {
+        1.0
+    }
+}
+
+pub struct InstantSearch;
+
+impl InstantSearch {
+    pub fn timeout_ms() -> u64 {
+        150
+    }
+
+    pub fn cache_ttl_ms() -> u64 {
+        30_000
+    }
+
+    pub fn cache_size() -> usize {
+        10_000
+    }
+
+    pub fn max_results() -> usize {
+        5
+    }
+
+    pub fn max_title_chars() -> usize {
+        80
+    }
+}
+
+pub struct ConnectionPool;
+
+impl ConnectionPool {
+    pub fn max_connections() -> usize {
+        64
+    }
+
+    pub fn idle_timeout_ms() -> u64 {
+        30_000
+    }
+}
+
+pub struct ConcurrencyLimit;
+
+impl ConcurrencyLimit {
+    pub fn max_queued() -> usize {
+        0
+    }
+
+    pub fn queue_timeout_ms() -> u64 {
+        100
+    }
+}
+
+pub struct CollectionStats;
+
+impl CollectionStats {
+    pub fn interval_sec() -> u64 {
+        60 * 60
+    }
+
+    pub fn min_doc_freq() -> u64 {
+        2
+    }
+
+    pub fn max_terms_per_field() -> usize {
+        100_000
+    }
+}
+
+pub struct QueryPlanner;
+
+impl QueryPlanner {
+    pub fn min_searches() -> u64 {
+        5
+    }
+
+    pub fn head_only_fraction() -> f64 {
+        0.99
+    }
+
+    pub fn all_shards_fraction() -> f64 {
+        0.1
+    }
+
+    pub fn explore_interval() -> u64 {
+        20
+    }
+
+    pub fn long_query_terms() -> usize {
+        6
+    }
+
+    pub fn rare_query_max_docs() -> usize {
+        1_000_000
+    }
+
+    pub fn history_ttl_sec() -> u64 {
+        24 * 60 * 60
+    }
+
+    pub fn max_history_size() -> usize {
+        10_000
+    }
+}
+
+pub struct DocumentExpansion;
+
+impl DocumentExpansion {
+    pub fn max_terms() -> usize {
+        32
+    }
+}
+
+pub struct Indexing;
+
+impl Indexing {
+    pub fn head_fraction() -> f64 {
+        0.1
+    }
+
+    pub fn demotion_max_idle_days() -> u64 {
+        180
+    }
+
+    pub fn demotion_max_host_centrality() -> f64 {
+        0.01
+    }
+
+    pub fn other_languages_group() -> String {
+        "other".to_string()
+    }
+}
+
+pub struct Abuse;
+
+impl Abuse {
+    pub fn window_sec() -> u64 {
+        60
+    }
+
+    pub fn max_queries() -> usize {
+        60
+    }
+
+    pub fn min_entropy() -> f64 {
+        1.0
+    }
+
+    pub fn min_queries_for_entropy() -> usize {
+        20
+    }
+
+    pub fn max_clients_per_query() -> usize {
+        50
+    }
+
+    pub fn max_page() -> usize {
+        20
+    }
+
+    pub fn block_sec() -> u64 {
+        3600
+    }
+
+    pub fn rate_action() -> AbuseAction {
+        AbuseAction::Throttle
+    }
+
+    pub fn low_entropy_action() -> AbuseAction {
+        AbuseAction::Captcha
+    }
+
+    pub fn shared_query_action() -> AbuseAction {
+        AbuseAction::Captcha
+    }
+
+    pub fn deep_pagination_action() -> AbuseAction {
+        AbuseAction::Throttle
+    }
+}
+
+pub struct Session;
+
+impl Session {
+    pub fn ttl_seconds() -> u64 {
+        600
+    }
+}
+
+pub struct Blocklist;
+
+impl Blocklist {
+    pub fn refresh_interval_sec() -> u64 {
+        60 * 60
+    }
+}
+
+pub struct QueryTelemetry;
+
+impl QueryTelemetry {
+    pub fn sample_rate() -> f64 {
+        0.01
+    }
+
+    pub fn retention_days() -> u64 {
+        90
+    }
+
+    pub fn max_rows_per_day() -> usize {
+        1_000_000
+    }
+}
+
+pub struct IndexNow;
+
+impl IndexNow {
+    pub fn timeout_seconds() -> u64 {
+        10
+    }
+
+    pub fn key_cache_seconds() -> u64 {
+        60 * 60
+    }
+
+    pub fn max_pending_urls_per_domain() -> usize {
+        10_000
+    }
+}
+
+pub struct Snapshot;
+
+impl Snapshot {
+    pub fn keep_generations() -> usize {
+        3
+    }
+}
+
+pub struct WebSub;
+
+impl WebSub {
+    pub fn lease_seconds() -> u64 {
+        7 * 24 * 60 * 60
+    }
+
+    pub fn max_pushes_per_hour() -> usize {
+        60
+    }
 }
 
 pub struct Snippet;
@@ -80,6 +330,22 @@ impl Snippet {
     pub fn min_body_length_homepage() -> usize {
         1024
     }
+
+    pub fn sentence_boundaries() -> bool {
+        true
+    }
+
+    pub fn adaptive_length() -> bool {
+        true
+    }
+
+    pub fn definition_length_factor() -> f64 {
+        1.5
+    }
+
+    pub fn navigational_length_factor() -> f64 {
+        0.6
+    }
 }
 
 pub struct Crawler;
@@ -116,6 +382,66 @@ impl Crawler {
     pub fn dry_run() -> bool {
         false
     }
+
+    pub fn trap_max_urls_per_template() -> usize {
+        100
+    }
+
+    pub fn trap_max_repeated_segments() -> usize {
+        3
+    }
+
+    pub fn max_host_error_streak() -> u64 {
+        10
+    }
+
+    pub fn base_probe_interval_sec() -> u64 {
+        60 * 60 * 24
+    }
+
+    pub fn max_probe_interval_sec() -> u64 {
+        60 * 60 * 24 * 32
+    }
+
+    pub fn probe_budget() -> u64 {
+        5
+    }
+}
+
+pub struct Bm25F;
+
+impl Bm25F {
+    pub fn k1() -> f64 {
+        1.2
+    }
+
+    pub fn weight() -> f64 {
+        1.0
+    }
+
+    pub fn b() -> f64 {
+        0.75
+    }
+}
+
+pub struct Shutdown;
+
+impl Shutdown {
+    pub fn drain_timeout_ms() -> u64 {
+        30_000
+    }
+}
+
+pub struct SearchServer;
+
+impl SearchServer {
+    pub fn signal_store_refresh_interval_sec() -> u64 {
+        60
+    }
+
+    pub fn access_log_sample_rate() -> f64 {
+        0.01
+    }
 }
 
 pub struct WebgraphServer;
@@ -160,6 +486,34 @@ impl Correction {
     pub fn lm_prob_weight() -> f64 {
         5.77
     }
+
+    pub fn segmentation_threshold() -> f64 {
+        10.0 // logprob difference
+    }
+
+    pub fn auto_segmentation_threshold() -> f64 {
+        30.0 // logprob difference
+    }
+}
+
+pub struct EventLog;
+
+impl EventLog {
+    pub fn segment_size() -> u64 {
+        100_000
+    }
+
+    pub fn batch_size() -> usize {
+        10_000
+    }
+}
+
+pub struct GoldenQueries;
+
+impl GoldenQueries {
+    pub fn spam_depth() -> usize {
+        10
+    }
 }
 
 pub struct Widgets;
@@ -169,3 +523,183 @@ impl Widgets {
         true
     }
 }
+
+pub struct Liveness;
+
+impl Liveness {
+    pub fn archive_url_prefix() -> String {
+        "https://web.archive.org/web/".to_string()
+    }
+
+    pub fn refresh_interval_sec() -> u64 {
+        300
+    }
+}
+
+pub struct HostPolicy;
+
+impl HostPolicy {
+    pub fn min_directive_fraction() -> f64 {
+        0.5
+    }
+
+    pub fn refresh_interval_sec() -> u64 {
+        300
+    }
+}
+
+pub struct CrawlSeeder;
+
+impl CrawlSeeder {
+    pub fn top_host_fraction() -> f64 {
+        0.1
+    }
+
+    pub fn max_coverage() -> f64 {
+        0.1
+    }
+
+    pub fn min_linking_hosts() -> usize {
+        5
+    }
+
+    pub fn max_seeds() -> usize {
+        10_000
+    }
+
+    pub fn seed_budget() -> u64 {
+        10
+    }
+}
+
+pub struct IndexPrune;
+
+impl IndexPrune {
+    pub fn top_k() -> usize {
+        10
+    }
+}
+
+pub struct TermExport;
+
+impl TermExport {
+    pub fn min_doc_freq() -> u64 {
+        1
+    }
+}
+
+pub struct LocalizedQueries;
+
+impl LocalizedQueries {
+    pub fn max_queries() -> usize {
+        100_000
+    }
+
+    pub fn min_doc_freq() -> u64 {
+        10
+    }
+}
+
+pub struct Federation;
+
+impl Federation {
+    pub fn live_weight() -> f64 {
+        1.0
+    }
+
+    pub fn vertical_weight() -> f64 {
+        0.5
+    }
+}
+
+pub struct Hedging;
+
+impl Hedging {
+    pub fn quantile() -> f64 {
+        0.99
+    }
+
+    pub fn window() -> usize {
+        1_000
+    }
+
+    pub fn min_samples() -> usize {
+        100
+    }
+
+    pub fn initial_delay_ms() -> u64 {
+        100
+    }
+
+    pub fn min_delay_ms() -> u64 {
+        5
+    }
+}
+
+pub struct Quorum;
+
+impl Quorum {
+    pub fn replicas() -> usize {
+        3
+    }
+
+    pub fn quorum() -> usize {
+        2
+    }
+}
+
+pub struct EdgeWeights;
+
+impl EdgeWeights {
+    pub fn unit() -> f64 {
+        1.0
+    }
+}
+
+pub struct AutosuggestSafety;
+
+impl AutosuggestSafety {
+    pub fn min_confidence() -> f32 {
+        0.8
+    }
+}
+
+pub struct Screenshot;
+
+impl Screenshot {
+    pub fn user_agent_token() -> String {
+        "StractPreview".to_string()
+    }
+
+    pub fn render_timeout_sec() -> u64 {
+        30
+    }
+
+    pub fn thumbnail_width() -> u32 {
+        320
+    }
+
+    pub fn thumbnail_height() -> u32 {
+        240
+    }
+
+    pub fn jpeg_quality() -> u8 {
+        70
+    }
+
+    pub fn max_thumbnail_bytes() -> u64 {
+        64 * 1024 // 64 KiB
+    }
+
+    pub fn max_host_bytes() -> u64 {
+        16 * 1024 * 1024 // 16 MiB
+    }
+
+    pub fn max_total_bytes() -> u64 {
+        64 * 1024 * 1024 * 1024 // 64 GiB
+    }
+
+    pub fn refresh_interval_sec() -> u64 {
+        60
+    }
+}
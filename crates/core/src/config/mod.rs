@@ -14,15 +14,18 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod addr;
 pub mod defaults;
 
 use super::Result;
 use crate::feed::scheduler::SplitId;
-use crate::searcher::ShardId;
+use crate::searcher::{ShardId, Vertical};
+use crate::snippet::SnippetOptions;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{self, BufRead};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct IndexingLocalConfig {
@@ -37,7 +40,158 @@ pub struct IndexingLocalConfig {
     pub host_centrality_store_path: String,
     pub page_centrality_store_path: Option<String>,
     pub safety_classifier_path: Option<String>,
+    pub topic_classifier_path: Option<String>,
     pub minimum_clean_words: Option<usize>,
+    pub signal_store_path: Option<String>,
+    /// Index terms predicted by a document expansion model. Disabled if not set.
+    pub document_expansion: Option<DocumentExpansionConfig>,
+
+    /// Only index the urls owned by `shard_id`.
+    pub routing: Option<RoutingConfig>,
+    pub shard_id: Option<ShardId>,
+
+    #[serde(default)]
+    pub threads: ThreadsConfig,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DocumentExpansionConfig {
+    /// Tab separated `term, expansion, weight` rows exported from the expansion model.
+    pub model_path: String,
+    /// The maximum number of expansion terms that are indexed for each page.
+    #[serde(default = "defaults::DocumentExpansion::max_terms")]
+    pub max_terms: usize,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct IndexTiersConfig {
+    pub index_path: String,
+    pub head_output_path: String,
+    pub archive_output_path: String,
+    /// Fraction of the pages with the highest pre-computed score that are put in the head tier.
+    #[serde(default = "defaults::Indexing::head_fraction")]
+    pub head_fraction: f64,
+
+    /// Demote documents that are rarely retrieved to the archive tier.
+    pub demotion: Option<DemotionConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DemotionConfig {
+    /// The access log written by the search servers.
+    pub access_log_path: String,
+
+    /// Documents that have not been retrieved for this many days are demoted.
+    #[serde(default = "defaults::Indexing::demotion_max_idle_days")]
+    pub max_idle_days: u64,
+
+    /// Only documents from hosts with a lower centrality are demoted.
+    #[serde(default = "defaults::Indexing::demotion_max_host_centrality")]
+    pub max_host_centrality: f64,
+
+    /// Remove the demoted documents entirely instead of moving them to the archive tier.
+    #[serde(default)]
+    pub prune: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct IndexLanguagesConfig {
+    pub index_path: String,
+    /// Each group is written to a sub-folder with the name of the group.
+    pub output_path: String,
+    pub groups: Vec<LanguageGroupConfig>,
+    /// Name of the group with the pages that are not in any of the other groups.
+    #[serde(default = "defaults::Indexing::other_languages_group")]
+    pub other_group: String,
+}
+
+/// Route each url to one of `num_shards` shards by the hash of the normalized url.
+/// See `shard_router` for details.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct RoutingConfig {
+    pub num_shards: u64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct IndexShardsConfig {
+    pub index_path: String,
+    /// Each shard is written to a sub-folder with the id of the shard.
+    pub output_path: String,
+    pub routing: RoutingConfig,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TermExportConfig {
+    pub shards: Vec<TermExportShardConfig>,
+    /// Each shard is written to a sub-folder with the id of the shard.
+    pub output_path: String,
+
+    /// Names of the text fields to export. All searchable text fields are exported if empty.
+    #[serde(default)]
+    pub fields: Vec<String>,
+
+    /// Skip terms that are in fewer documents than this.
+    #[serde(default = "defaults::TermExport::min_doc_freq")]
+    pub min_doc_freq: u64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TermExportShardConfig {
+    pub id: ShardId,
+    pub index_path: String,
+}
+
+/// Build the csvs of popular queries for each language that autosuggest loads
+/// from `localized_queries_path`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct LocalizedQueriesConfig {
+    pub shards: Vec<LocalizedQueriesShardConfig>,
+    /// A csv is written here for each language, named after its language tag, e.g. `de.csv`.
+    pub output_path: String,
+
+    /// Maximum number of queries for each language.
+    #[serde(default = "defaults::LocalizedQueries::max_queries")]
+    pub max_queries: usize,
+
+    /// Skip queries that are in the titles of fewer documents than this.
+    #[serde(default = "defaults::LocalizedQueries::min_doc_freq")]
+    pub min_doc_freq: u64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LocalizedQueriesShardConfig {
+    pub index_path: String,
+    /// The languages of the pages in the shard, like the `languages` of the search server.
+    pub languages: Vec<whatlang::Lang>,
+}
+
+/// Tombstone the documents of a search index that are below the quality thresholds.
+#[derive(Debug, Deserialize, Clone)]
+pub struct IndexPruneConfig {
+    pub index_path: String,
+
+    /// Prune the documents with a pre-computed score below this.
+    pub min_pre_computed_score: Option<f64>,
+
+    /// Prune the documents on hosts with a centrality below this.
+    pub min_host_centrality: Option<f64>,
+
+    /// File with a query on each line. Used to estimate how many of the top results are pruned.
+    pub queries_path: Option<String>,
+
+    /// Number of top results of each query to estimate the recall with.
+    #[serde(default = "defaults::IndexPrune::top_k")]
+    pub top_k: usize,
+
+    /// Only compute the statistics without tombstoning the documents.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LanguageGroupConfig {
+    pub name: String,
+    pub languages: Vec<whatlang::Lang>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -48,6 +202,96 @@ pub struct WebgraphConstructConfig {
     pub limit_warc_files: Option<usize>,
     pub skip_warc_files: Option<usize>,
     pub batch_size: Option<usize>,
+    /// The weights are stored with the edges. Links with a weight of 0 are left out of the graphs.
+    #[serde(default)]
+    pub edge_weights: EdgeWeightConfig,
+}
+
+/// Weights of the links in the webgraph. Each property of a link multiplies its
+/// weight, so by default every link has a weight of 1. The weights are calculated
+/// when the graph is constructed and the centralities use the stored weights.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EdgeWeightConfig {
+    /// Links get the full weight when their anchor text has at least this many words
+    /// and proportionally less when it has fewer. Disabled if not set.
+    pub anchor_words: Option<usize>,
+
+    /// The weight of the n'th link on a page is divided by `1 + position_decay * n`.
+    #[serde(default)]
+    pub position_decay: f64,
+
+    /// Weight of links between pages on the same host.
+    #[serde(default = "defaults::EdgeWeights::unit")]
+    pub same_host: f64,
+
+    /// Weights of links with the `nofollow`, `ugc` and `sponsored` rel attributes.
+    #[serde(default = "defaults::EdgeWeights::unit")]
+    pub nofollow: f64,
+
+    #[serde(default = "defaults::EdgeWeights::unit")]
+    pub ugc: f64,
+
+    #[serde(default = "defaults::EdgeWeights::unit")]
+    pub sponsored: f64,
+}
+
+impl Default for EdgeWeightConfig {
+    fn default() -> Self {
+        Self {
+            anchor_words: None,
+            position_decay: 0.0,
+            same_host: defaults::EdgeWeights::unit(),
+            nofollow: defaults::EdgeWeights::unit(),
+            ugc: defaults::EdgeWeights::unit(),
+            sponsored: defaults::EdgeWeights::unit(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CommonCrawlConfig {
+    /// The WARC, WAT and WET segments to ingest. The kind of each segment is determined
+    /// by its file name, e.g. `.warc.wat.gz` for WAT segments.
+    pub warc_source: WarcSource,
+    pub limit_warc_files: Option<usize>,
+    pub skip_warc_files: Option<usize>,
+
+    /// Each segment is processed into this folder before it is merged into the index or webgraphs.
+    pub staging_path: String,
+
+    /// Pages from the WARC and WET segments are merged into this index. It is created if it doesn't exist.
+    pub index_path: String,
+    /// Links from the WAT segments are merged into these webgraphs. They are created if they don't exist.
+    pub host_graph_path: String,
+    pub page_graph_path: String,
+
+    // indexer
+    pub host_centrality_store_path: String,
+    pub page_centrality_store_path: Option<String>,
+    pub safety_classifier_path: Option<String>,
+    pub host_centrality_threshold: Option<f64>,
+    pub minimum_clean_words: Option<usize>,
+
+    /// The weights are stored with the edges. Links with a weight of 0 are left out of the webgraphs.
+    #[serde(default)]
+    pub edge_weights: EdgeWeightConfig,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebgraphCompactionConfig {
+    pub graph_path: String,
+    /// The mapping from node ids to dense ids is read from this path if it exists.
+    /// Otherwise it is created from the nodes in the graph.
+    pub mapping_path: String,
+    /// The rewritten signal store contains the mapping, so it is still read with the
+    /// hashed node ids. Only the mapping is created if not set.
+    pub signal_store: Option<RemapStoreConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RemapStoreConfig {
+    pub input_path: String,
+    pub output_path: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -102,7 +346,10 @@ impl WarcSource {
                     .collect::<Vec<_>>();
 
                 for p in objects.into_iter().filter_map(|o| {
-                    if o.key.ends_with("warc.gz") {
+                    if o.key.ends_with("warc.gz")
+                        || o.key.ends_with("warc.wat.gz")
+                        || o.key.ends_with("warc.wet.gz")
+                    {
                         Some(o.key)
                     } else {
                         None
@@ -138,6 +385,29 @@ pub struct S3Config {
     pub endpoint: String,
 }
 
+/// Where large artifacts like index snapshots and webgraphs are stored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum ObjectStoreConfig {
+    S3(S3Config),
+    Local(LocalObjectStoreConfig),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LocalObjectStoreConfig {
+    pub folder: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SnapshotConfig {
+    /// Name of the artifact in the store, e.g. `index-shard-0`.
+    pub name: String,
+    pub store: ObjectStoreConfig,
+    /// Older generations are removed when a new snapshot is uploaded.
+    #[serde(default = "defaults::Snapshot::keep_generations")]
+    pub keep_generations: usize,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CollectorConfig {
     #[serde(default = "defaults::Collector::site_penalty")]
@@ -154,6 +424,10 @@ pub struct CollectorConfig {
 
     #[serde(default = "defaults::Collector::max_docs_considered")]
     pub max_docs_considered: usize,
+
+    /// Searches stop collecting documents after this long and return the best results
+    /// found until then. Disabled if not set.
+    pub time_budget_ms: Option<u64>,
 }
 
 impl Default for CollectorConfig {
@@ -164,6 +438,7 @@ impl Default for CollectorConfig {
             url_penalty: defaults::Collector::url_penalty(),
             url_without_tld_penalty: defaults::Collector::url_without_tld_penalty(),
             max_docs_considered: defaults::Collector::max_docs_considered(),
+            time_budget_ms: None,
         }
     }
 }
@@ -175,6 +450,11 @@ pub struct ApiThresholds {
 
     #[serde(default = "defaults::Api::entity_sidebar")]
     pub entity_sidebar: f64,
+
+    /// The archive shards, and the shards for other languages than the query, are only searched
+    /// if the preceding shards return fewer than this fraction of the results needed for the requested page.
+    #[serde(default = "defaults::Api::min_head_recall")]
+    pub min_head_recall: f64,
 }
 
 impl Default for ApiThresholds {
@@ -182,10 +462,37 @@ impl Default for ApiThresholds {
         Self {
             stackoverflow: defaults::Api::stackoverflow(),
             entity_sidebar: defaults::Api::entity_sidebar(),
+            min_head_recall: defaults::Api::min_head_recall(),
         }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ThreadPoolConfig {
+    /// Defaults to the number of pinned cores, or all cores if the pool is not pinned.
+    pub num_threads: Option<usize>,
+    /// Restrict the threads of the pool to these cores. Pools that should not
+    /// contend with each other can be given disjoint sets of cores, e.g. the
+    /// cores of a single NUMA node.
+    pub pin_cores: Option<Vec<usize>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ThreadsConfig {
+    /// The tokio runtime serving requests.
+    #[serde(default)]
+    pub runtime: ThreadPoolConfig,
+
+    /// The global rayon pool. This is used for parallel iterators and model
+    /// inference.
+    #[serde(default)]
+    pub rayon: ThreadPoolConfig,
+
+    /// Run the cross encoder and the ranking models of the api on a dedicated pool
+    /// instead of the global rayon pool. Only used by the api.
+    pub model_runner: Option<ThreadPoolConfig>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LLMConfig {
     pub api_base: String,
@@ -197,29 +504,665 @@ pub struct LLMConfig {
 pub struct ApiConfig {
     pub summarizer_path: String,
     pub queries_csv_path: String,
+    /// Directory with a csv of popular queries for each language, named after the
+    /// language tag, e.g. `de.csv`, or `de-CH.csv` for the queries of a region. The csvs
+    /// are built with `indexer localized-queries`. Requests for other languages use
+    /// `queries_csv_path`. Disabled if not set.
+    pub localized_queries_path: Option<String>,
+    /// Remove unsafe completions from the suggestions. Disabled if not set.
+    pub autosuggest_safety: Option<AutosuggestSafetyConfig>,
+    #[serde(deserialize_with = "addr::deserialize")]
     pub host: SocketAddr,
+    #[serde(deserialize_with = "addr::deserialize")]
     pub prometheus_host: SocketAddr,
     pub crossencoder_model_path: Option<String>,
     pub lambda_model_path: Option<String>,
     pub spell_checker_path: Option<String>,
+    /// Price history of products used to annotate results with price drops.
+    pub product_store_path: Option<String>,
+    /// Places with geo coordinates used to answer local intent queries.
+    pub geo_store_path: Option<String>,
     pub bangs_path: String,
     pub query_store_db_host: Option<String>,
     pub cluster_id: String,
+    #[serde(default, deserialize_with = "addr::deserialize_opt_vec")]
     pub gossip_seed_nodes: Option<Vec<SocketAddr>>,
+    #[serde(deserialize_with = "addr::deserialize")]
     pub gossip_addr: SocketAddr,
 
-    pub llm: LLMConfig,
+    pub llm: LLMConfig,
+
+    #[serde(default)]
+    pub collector: CollectorConfig,
+
+    #[serde(default)]
+    pub thresholds: ApiThresholds,
+
+    pub widgets: WidgetsConfig,
+
+    #[serde(default)]
+    pub correction_config: CorrectionConfig,
+
+    #[serde(default)]
+    pub ranking: RankingConfig,
+
+    /// Detect and act on scraping of the search api. Disabled if not set.
+    pub abuse: Option<AbuseConfig>,
+
+    /// Context for follow-up queries. Disabled if not set.
+    pub session: Option<SessionConfig>,
+
+    /// Hash lists of malware and phishing urls to check the results against. Disabled if not set.
+    pub blocklist: Option<BlocklistConfig>,
+
+    /// Annotate or demote results with domains that spoof popular hosts. Disabled if not set.
+    pub homograph: Option<HomographConfig>,
+
+    /// Look up webpages by url on the shard that owns the url instead of all shards.
+    pub routing: Option<RoutingConfig>,
+
+    /// Record sampled features of the queries. Disabled if not set.
+    pub telemetry: Option<QueryTelemetryConfig>,
+
+    /// Link to archived copies of results whose latest fetch failed. Disabled if not set.
+    pub liveness: Option<LivenessConfig>,
+
+    /// Hide snippets and archive links for hosts that ask for it. Disabled if not set.
+    pub host_policy: Option<HostPolicyReaderConfig>,
+
+    #[serde(default)]
+    pub instant: InstantSearchConfig,
+
+    /// Fusion of the results from the web index, the live index and the verticals. The results
+    /// are fused on their unnormalized scores if not set.
+    pub federation: Option<FederationConfig>,
+
+    /// Send searches to a second replica of a shard when the first is slow. Disabled if not set.
+    pub hedging: Option<HedgingConfig>,
+
+    /// Look up the index status of urls on several replicas of each shard, and only use the
+    /// statuses that a quorum of the replicas agree on. Disabled if not set.
+    pub quorum: Option<QuorumConfig>,
+
+    /// Reuse the connections to the search servers between requests.
+    #[serde(default)]
+    pub connection_pool: ConnectionPoolConfig,
+
+    /// Searches are given this long, and the search and entity servers stop working on
+    /// a search once it has passed. Disabled if not set.
+    pub search_deadline_ms: Option<u64>,
+
+    /// Periodically merge the collection statistics of the shards so their BM25 scores
+    /// are comparable. Disabled if not set.
+    pub collection_stats: Option<CollectionStatsConfig>,
+
+    /// Connect to the search and webgraph servers over TLS. Disabled if not set.
+    pub tls: Option<TlsConfig>,
+    /// TLS and the shared secret of the connections to the live index servers. Disabled if not set.
+    pub live_index_tls: Option<TlsConfig>,
+    /// TLS and the shared secret of the connections to the entity search servers. Disabled if not set.
+    pub entity_search_tls: Option<TlsConfig>,
+    /// TLS and the shared secret of the connections to the host stats servers. Disabled if not set.
+    pub host_stats_tls: Option<TlsConfig>,
+
+    /// Choose how the shards are searched for each query. The shards are always
+    /// searched in stages if not set.
+    pub query_planner: Option<QueryPlannerConfig>,
+
+    /// Serve the screenshot thumbnails captured by the crawler. Disabled if not set.
+    pub screenshots: Option<ScreenshotReaderConfig>,
+
+    /// Limit for the combined size of all caches in the api.
+    pub memory_budget_bytes: Option<usize>,
+
+    #[serde(default)]
+    pub threads: ThreadsConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScreenshotReaderConfig {
+    /// The store the crawler writes the screenshots to.
+    pub store_path: String,
+
+    /// The api keeps its own logs of the store here.
+    pub secondary_path: String,
+
+    /// The screenshots captured since the last refresh are served after this many seconds.
+    #[serde(default = "defaults::Screenshot::refresh_interval_sec")]
+    pub refresh_interval_sec: u64,
+}
+
+/// Connections to the sonic servers are kept open and reused for later requests.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConnectionPoolConfig {
+    /// Maximum number of connections to each server. Requests wait for a free connection above this.
+    #[serde(default = "defaults::ConnectionPool::max_connections")]
+    pub max_connections: usize,
+
+    /// Idle connections are closed after this long.
+    #[serde(default = "defaults::ConnectionPool::idle_timeout_ms")]
+    pub idle_timeout_ms: u64,
+
+    /// Compress the requests to the servers and ask for compressed responses. Servers
+    /// without compression reject the requests, so only enable this once all of them
+    /// are upgraded.
+    #[serde(default)]
+    pub compression: bool,
+}
+
+impl Default for ConnectionPoolConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: defaults::ConnectionPool::max_connections(),
+            idle_timeout_ms: defaults::ConnectionPool::idle_timeout_ms(),
+            compression: false,
+        }
+    }
+}
+
+/// Limit how many requests a sonic server handles at once. Requests above the limit wait
+/// in a queue, and the server responds that it is overloaded to the requests that do
+/// not fit in the queue, so the clients can try another replica.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct ConcurrencyLimitConfig {
+    /// Maximum number of requests that are handled at once.
+    pub max_inflight: usize,
+
+    /// Maximum number of requests that wait for one of the others to finish.
+    #[serde(default = "defaults::ConcurrencyLimit::max_queued")]
+    pub max_queued: usize,
+
+    /// Requests that have waited in the queue for this long are rejected.
+    #[serde(default = "defaults::ConcurrencyLimit::queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+}
+
+/// The query planner chooses how the shards are searched for each query, based on the
+/// features of the query and how often the first shards had enough results for it before.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QueryPlannerConfig {
+    /// Number of earlier searches of a query before its history is used.
+    #[serde(default = "defaults::QueryPlanner::min_searches")]
+    pub min_searches: u64,
+
+    /// Only the first shards are searched for queries where they had enough results
+    /// for at least this fraction of the earlier searches.
+    #[serde(default = "defaults::QueryPlanner::head_only_fraction")]
+    pub head_only_fraction: f64,
+
+    /// All shards are searched at once for queries where the first shards had enough
+    /// results for at most this fraction of the earlier searches.
+    #[serde(default = "defaults::QueryPlanner::all_shards_fraction")]
+    pub all_shards_fraction: f64,
+
+    /// Queries that search all shards at once are searched in stages after this many
+    /// searches, so their history is kept up to date.
+    #[serde(default = "defaults::QueryPlanner::explore_interval")]
+    pub explore_interval: u64,
+
+    /// Queries with at least this many words are expected to be rare.
+    #[serde(default = "defaults::QueryPlanner::long_query_terms")]
+    pub long_query_terms: usize,
+
+    /// Number of documents each search server scores for rare queries before it stops early.
+    #[serde(default = "defaults::QueryPlanner::rare_query_max_docs")]
+    pub rare_query_max_docs: usize,
+
+    #[serde(default = "defaults::QueryPlanner::history_ttl_sec")]
+    pub history_ttl_sec: u64,
+
+    #[serde(default = "defaults::QueryPlanner::max_history_size")]
+    pub max_history_size: usize,
+}
+
+impl Default for QueryPlannerConfig {
+    fn default() -> Self {
+        Self {
+            min_searches: defaults::QueryPlanner::min_searches(),
+            head_only_fraction: defaults::QueryPlanner::head_only_fraction(),
+            all_shards_fraction: defaults::QueryPlanner::all_shards_fraction(),
+            explore_interval: defaults::QueryPlanner::explore_interval(),
+            long_query_terms: defaults::QueryPlanner::long_query_terms(),
+            rare_query_max_docs: defaults::QueryPlanner::rare_query_max_docs(),
+            history_ttl_sec: defaults::QueryPlanner::history_ttl_sec(),
+            max_history_size: defaults::QueryPlanner::max_history_size(),
+        }
+    }
+}
+
+/// TLS for the sonic connections between the services. A service with a certificate and key
+/// only accepts TLS connections, and a service with a CA connects to the other services with TLS.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TlsConfig {
+    /// Certificate chain that the server presents, in PEM.
+    /// It is also presented to the servers that require client certificates.
+    pub cert_path: Option<String>,
+    /// Private key of the certificate, in PEM.
+    pub key_path: Option<String>,
+
+    /// The certificates of the servers are verified against the CA certificates in this PEM file.
+    pub ca_path: Option<String>,
+    /// Name that the certificates of the servers must be valid for.
+    /// The ip address of the server is verified if not set.
+    pub server_name: Option<String>,
+
+    /// Only accept clients with a certificate that is signed by the CA certificates
+    /// in this PEM file. Disabled if not set.
+    pub client_ca_path: Option<String>,
+    /// File with a secret that is shared by the services of the cluster. It is sent with
+    /// every request, and requests without it are rejected. Disabled if not set.
+    pub shared_secret_path: Option<String>,
+}
+
+/// The document frequencies of the terms are merged across the shards and sent back to the search servers.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CollectionStatsConfig {
+    #[serde(default = "defaults::CollectionStats::interval_sec")]
+    pub interval_sec: u64,
+
+    /// Terms in fewer documents than this in a shard are not reported by the shard.
+    #[serde(default = "defaults::CollectionStats::min_doc_freq")]
+    pub min_doc_freq: u64,
+
+    /// Only the terms with the highest document frequencies are reported for each field.
+    #[serde(default = "defaults::CollectionStats::max_terms_per_field")]
+    pub max_terms_per_field: usize,
+}
+
+impl Default for CollectionStatsConfig {
+    fn default() -> Self {
+        Self {
+            interval_sec: defaults::CollectionStats::interval_sec(),
+            min_doc_freq: defaults::CollectionStats::min_doc_freq(),
+            max_terms_per_field: defaults::CollectionStats::max_terms_per_field(),
+        }
+    }
+}
+
+/// Search-as-you-type where results are returned for every keystroke.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InstantSearchConfig {
+    /// Searches that take longer than this return no results.
+    #[serde(default = "defaults::InstantSearch::timeout_ms")]
+    pub timeout_ms: u64,
+
+    /// Results are cached for this long so the same prefix is only searched once.
+    #[serde(default = "defaults::InstantSearch::cache_ttl_ms")]
+    pub cache_ttl_ms: u64,
+
+    #[serde(default = "defaults::InstantSearch::cache_size")]
+    pub cache_size: usize,
+
+    #[serde(default = "defaults::InstantSearch::max_results")]
+    pub max_results: usize,
+
+    #[serde(default = "defaults::InstantSearch::max_title_chars")]
+    pub max_title_chars: usize,
+}
+
+impl Default for InstantSearchConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: defaults::InstantSearch::timeout_ms(),
+            cache_ttl_ms: defaults::InstantSearch::cache_ttl_ms(),
+            cache_size: defaults::InstantSearch::cache_size(),
+            max_results: defaults::InstantSearch::max_results(),
+            max_title_chars: defaults::InstantSearch::max_title_chars(),
+        }
+    }
+}
+
+/// The indexes that are searched together with the web index. The scores of each index
+/// are normalized before the results are fused, and then weighted by the weight of the index.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FederationConfig {
+    #[serde(default = "defaults::Federation::live_weight")]
+    pub live_weight: f64,
+
+    /// Verticals to search together with the web index. Queries for a vertical only search the vertical.
+    #[serde(default)]
+    pub verticals: Vec<FederatedVerticalConfig>,
+}
+
+impl Default for FederationConfig {
+    fn default() -> Self {
+        Self {
+            live_weight: defaults::Federation::live_weight(),
+            verticals: Vec::new(),
+        }
+    }
+}
+
+impl FederationConfig {
+    pub fn vertical_weight(&self, vertical: Vertical) -> f64 {
+        self.verticals
+            .iter()
+            .find(|config| config.vertical == vertical)
+            .map(|config| config.weight)
+            .unwrap_or(defaults::Federation::vertical_weight())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FederatedVerticalConfig {
+    pub vertical: Vertical,
+
+    #[serde(default = "defaults::Federation::vertical_weight")]
+    pub weight: f64,
+}
+
+/// A search is sent to a second replica of the shard if the first has not responded
+/// within the quantile of the recent response times of the replicas.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HedgingConfig {
+    #[serde(default = "defaults::Hedging::quantile")]
+    pub quantile: f64,
+
+    /// Number of recent response times the quantile is estimated from.
+    #[serde(default = "defaults::Hedging::window")]
+    pub window: usize,
+
+    /// The initial delay is used until this many response times have been recorded.
+    #[serde(default = "defaults::Hedging::min_samples")]
+    pub min_samples: usize,
+
+    #[serde(default = "defaults::Hedging::initial_delay_ms")]
+    pub initial_delay_ms: u64,
+
+    /// Lower bound on the delay, so fast replicas don't cause every search to be sent twice.
+    #[serde(default = "defaults::Hedging::min_delay_ms")]
+    pub min_delay_ms: u64,
+}
+
+/// A request is sent to `replicas` random replicas of a shard, and the first `quorum`
+/// equal responses are used.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QuorumConfig {
+    #[serde(default = "defaults::Quorum::replicas")]
+    pub replicas: usize,
+
+    #[serde(default = "defaults::Quorum::quorum")]
+    pub quorum: usize,
+}
+
+/// What to do with a request from a client that looks abusive.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AbuseAction {
+    /// Reject the request with `429 Too Many Requests`.
+    Throttle,
+    /// Answer the request, but flag in the response that the client
+    /// should solve a captcha before continuing.
+    Captcha,
+    /// Reject all requests from the client for `block_sec` seconds.
+    Block,
+}
+
+impl AbuseAction {
+    /// The action of the most severe signature of a request is applied. A captcha still
+    /// answers the request, so it is less severe than rejecting it.
+    pub fn severity(self) -> u8 {
+        match self {
+            AbuseAction::Captcha => 0,
+            AbuseAction::Throttle => 1,
+            AbuseAction::Block => 2,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AbuseActions {
+    #[serde(default = "defaults::Abuse::rate_action")]
+    pub rate: AbuseAction,
+
+    #[serde(default = "defaults::Abuse::low_entropy_action")]
+    pub low_entropy: AbuseAction,
+
+    #[serde(default = "defaults::Abuse::shared_query_action")]
+    pub shared_query: AbuseAction,
+
+    #[serde(default = "defaults::Abuse::deep_pagination_action")]
+    pub deep_pagination: AbuseAction,
+}
+
+impl Default for AbuseActions {
+    fn default() -> Self {
+        Self {
+            rate: defaults::Abuse::rate_action(),
+            low_entropy: defaults::Abuse::low_entropy_action(),
+            shared_query: defaults::Abuse::shared_query_action(),
+            deep_pagination: defaults::Abuse::deep_pagination_action(),
+        }
+    }
+}
+
+/// Clients are identified by their api key (`x-api-key` header) and their ip.
+/// All thresholds are measured over the last `window_sec` seconds.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AbuseConfig {
+    #[serde(default = "defaults::Abuse::window_sec")]
+    pub window_sec: u64,
+
+    /// Maximum number of queries from a single client.
+    #[serde(default = "defaults::Abuse::max_queries")]
+    pub max_queries: usize,
+
+    /// Minimum entropy (in bits) of the queries of a client. Clients that repeat
+    /// the same few queries over and over have a low entropy.
+    #[serde(default = "defaults::Abuse::min_entropy")]
+    pub min_entropy: f64,
+
+    /// The entropy is only checked for clients with at least this many queries.
+    #[serde(default = "defaults::Abuse::min_queries_for_entropy")]
+    pub min_queries_for_entropy: usize,
+
+    /// Maximum number of distinct clients sending the same query. Scraping
+    /// farms often distribute the same query list over many keys.
+    #[serde(default = "defaults::Abuse::max_clients_per_query")]
+    pub max_clients_per_query: usize,
+
+    /// Requests for pages after this are considered scraping.
+    #[serde(default = "defaults::Abuse::max_page")]
+    pub max_page: usize,
+
+    #[serde(default = "defaults::Abuse::block_sec")]
+    pub block_sec: u64,
+
+    #[serde(default)]
+    pub actions: AbuseActions,
+
+    /// Api keys that are never flagged.
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+
+    /// Proxies in front of the api. The `x-forwarded-for` header is only used to find
+    /// the ip of the client for requests from these proxies.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+/// Session tokens with the context of the previous query, used to resolve follow-up queries.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SessionConfig {
+    /// File containing the secret used to sign the tokens. All api servers must use the same secret.
+    pub secret_path: String,
+
+    /// Tokens older than this are ignored.
+    #[serde(default = "defaults::Session::ttl_seconds")]
+    pub ttl_seconds: u64,
+}
+
+/// Safe Browsing-style lists of url expression hashes.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BlocklistConfig {
+    pub lists: Vec<BlocklistListConfig>,
+
+    #[serde(default)]
+    pub action: crate::blocklist::BlocklistAction,
+
+    /// The lists are reloaded if they have changed on disk.
+    #[serde(default = "defaults::Blocklist::refresh_interval_sec")]
+    pub refresh_interval_sec: u64,
+}
+
+/// Filtering of the autosuggest completions. A completion is removed if it matches
+/// one of the denylisted patterns or the safety classifier labels it as NSFW.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AutosuggestSafetyConfig {
+    /// Safety classifier the completions are classified with. Completions are only
+    /// matched against the denylists if not set.
+    pub classifier_path: Option<String>,
+
+    /// Completions that are labelled as NSFW with at least this confidence are removed.
+    #[serde(default = "defaults::AutosuggestSafety::min_confidence")]
+    pub min_confidence: f32,
+
+    /// Directory with text files of case-insensitive regular expressions, one on each line.
+    /// The patterns in `all.txt` apply to all languages, and the files named after a
+    /// language tag, e.g. `de.txt`, to the suggestions in that language. Disabled if not set.
+    pub denylist_path: Option<String>,
+}
+
+/// Detection of internationalized domains that look like popular hosts.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HomographConfig {
+    /// Text file with a popular host on each line. A built-in list is used if not set.
+    pub popular_hosts_path: Option<String>,
+
+    #[serde(default)]
+    pub action: crate::homograph::HomographAction,
+}
+
+/// Mark results whose latest fetch failed and link to an archived copy of the page.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LivenessConfig {
+    /// The store the planner writes the liveness to.
+    pub store_path: String,
+
+    /// The api keeps its own logs of the store here.
+    pub secondary_path: String,
+
+    /// The outcomes recorded since the last refresh are used after this many seconds.
+    #[serde(default = "defaults::Liveness::refresh_interval_sec")]
+    pub refresh_interval_sec: u64,
+
+    /// The url of the page is appended to this prefix, optionally after the
+    /// timestamp of its latest successful fetch, like the wayback machine expects.
+    #[serde(default = "defaults::Liveness::archive_url_prefix")]
+    pub archive_url_prefix: String,
+}
+
+/// Sampled telemetry of the query features. See `query_telemetry` for what is stored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QueryTelemetryConfig {
+    pub path: String,
+
+    /// Fraction of the queries that are recorded.
+    #[serde(default = "defaults::QueryTelemetry::sample_rate")]
+    pub sample_rate: f64,
+
+    /// Days of telemetry that are kept.
+    #[serde(default = "defaults::QueryTelemetry::retention_days")]
+    pub retention_days: u64,
+
+    /// Queries after this many in a day are not recorded.
+    #[serde(default = "defaults::QueryTelemetry::max_rows_per_day")]
+    pub max_rows_per_day: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BlocklistListConfig {
+    /// Text file with a hex encoded SHA-256 hash of an url expression on each line.
+    pub path: String,
+    pub threat: crate::blocklist::ThreatType,
+}
+
+/// The ranking pipelines of the api. Queries can select one of the variants,
+/// e.g. for an experiment, and otherwise use the default pipeline.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RankingConfig {
+    /// Uses the built-in pipeline if not set.
+    pub default: Option<RankingPipelineConfig>,
+
+    #[serde(default)]
+    pub variants: BTreeMap<String, RankingPipelineConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RankingPipelineConfig {
+    /// The stages in the order they are applied. A pipeline has a recall
+    /// stage optionally followed by a rerank stage.
+    pub stages: Vec<RankingStageConfig>,
+
+    /// Score the query terms across the fields with BM25F as the `bm25f` signal, instead of
+    /// scoring each of the fields with its own BM25 signal. Disabled if not set.
+    pub bm25f: Option<Bm25FConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Bm25FField {
+    Title,
+    Body,
+    /// The anchor text of the links to the page.
+    Anchor,
+    Url,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Bm25FConfig {
+    /// Term frequency saturation of the combined fields.
+    #[serde(default = "defaults::Bm25F::k1")]
+    pub k1: f64,
+
+    pub fields: BTreeMap<Bm25FField, Bm25FFieldConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Bm25FFieldConfig {
+    /// How much a term occurrence in the field counts compared to the other fields.
+    #[serde(default = "defaults::Bm25F::weight")]
+    pub weight: f64,
+
+    /// Length normalization of the field, from 0 (none) to 1 (full).
+    #[serde(default = "defaults::Bm25F::b")]
+    pub b: f64,
+}
 
-    #[serde(default)]
-    pub collector: CollectorConfig,
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RankingStageKind {
+    /// Ranks the candidates returned by the search servers.
+    Recall,
+    /// Ranks the retrieved webpages, e.g. with the cross encoder.
+    Rerank,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RankingModel {
+    LambdaMart,
+    CrossEncoder,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RankingStageConfig {
+    pub kind: RankingStageKind,
 
+    /// Number of candidates considered by the stage. Defaults to the number of results.
+    pub top_n: Option<usize>,
+
+    /// Coefficients of the signals (e.g. `bm25_title = 0.5`). Signals that are not
+    /// listed keep their default coefficient, and coefficients from optics take precedence.
     #[serde(default)]
-    pub thresholds: ApiThresholds,
+    pub weights: BTreeMap<String, f64>,
 
-    pub widgets: WidgetsConfig,
+    /// Results with a lower score are removed by the stage.
+    pub min_score: Option<f64>,
 
+    /// The models used by the stage. They must also be configured for the api.
     #[serde(default)]
-    pub correction_config: CorrectionConfig,
+    pub models: Vec<RankingModel>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -244,6 +1187,19 @@ pub struct SnippetConfig {
     pub min_body_length: usize,
     #[serde(default = "defaults::Snippet::min_body_length_homepage")]
     pub min_body_length_homepage: usize,
+
+    /// End the snippet at the end of a sentence when one is close to the desired length.
+    #[serde(default = "defaults::Snippet::sentence_boundaries")]
+    pub sentence_boundaries: bool,
+    /// Adapt the length of the snippet to the kind of query.
+    #[serde(default = "defaults::Snippet::adaptive_length")]
+    pub adaptive_length: bool,
+    /// The desired length is multiplied by this for definition queries like `what is rust`.
+    #[serde(default = "defaults::Snippet::definition_length_factor")]
+    pub definition_length_factor: f64,
+    /// The desired length is multiplied by this for navigational queries like `github.com`.
+    #[serde(default = "defaults::Snippet::navigational_length_factor")]
+    pub navigational_length_factor: f64,
 }
 
 impl Default for SnippetConfig {
@@ -258,42 +1214,177 @@ impl Default for SnippetConfig {
             min_description_words: defaults::Snippet::min_description_words(),
             min_body_length: defaults::Snippet::min_body_length(),
             min_body_length_homepage: defaults::Snippet::min_body_length_homepage(),
+            sentence_boundaries: defaults::Snippet::sentence_boundaries(),
+            adaptive_length: defaults::Snippet::adaptive_length(),
+            definition_length_factor: defaults::Snippet::definition_length_factor(),
+            navigational_length_factor: defaults::Snippet::navigational_length_factor(),
+        }
+    }
+}
+
+impl SnippetConfig {
+    /// Override the config with the options from the retrieval request.
+    pub fn with_options(mut self, options: &SnippetOptions) -> Self {
+        if let Some(desired_num_chars) = options.desired_num_chars {
+            self.desired_num_chars = desired_num_chars;
+        }
+
+        if let Some(sentence_boundaries) = options.sentence_boundaries {
+            self.sentence_boundaries = sentence_boundaries;
+        }
+
+        if let Some(adaptive_length) = options.adaptive_length {
+            self.adaptive_length = adaptive_length;
         }
+
+        self
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SearchServerConfig {
     pub cluster_id: String,
+    #[serde(default, deserialize_with = "addr::deserialize_opt_vec")]
     pub gossip_seed_nodes: Option<Vec<SocketAddr>>,
+    #[serde(deserialize_with = "addr::deserialize")]
     pub gossip_addr: SocketAddr,
     pub shard_id: ShardId,
+    #[serde(default)]
+    pub tier: ShardTier,
+    /// The languages of the pages in the shard. Queries in other languages only
+    /// search the shard if the shards for their language has too few results.
+    /// An empty list means that the shard has pages in all languages.
+    #[serde(default)]
+    pub languages: Vec<whatlang::Lang>,
     pub index_path: String,
     pub host_centrality_store_path: Option<String>,
     pub linear_model_path: Option<String>,
     pub lambda_model_path: Option<String>,
+    /// The centrality signals are read from this store instead of the index, so they can be
+    /// refreshed without reindexing. The page signals are looked up by the `page_node_id`
+    /// field, which indexes built before the field was added do not have.
+    pub signal_store_path: Option<String>,
+    /// Classifier used to predict the topic of queries for the topic centrality signal.
+    pub topic_classifier_path: Option<String>,
+    #[serde(deserialize_with = "addr::deserialize")]
     pub host: SocketAddr,
+    /// Expose the metrics of the requests for prometheus on this address. Disabled if not set.
+    #[serde(default, deserialize_with = "addr::deserialize_opt")]
+    pub prometheus_host: Option<SocketAddr>,
+
+    /// Run the blocking index reads on a dedicated pool instead of the tokio workers.
+    pub io_pool: Option<ThreadPoolConfig>,
+
+    /// Shed the requests above this limit. Disabled if not set.
+    pub concurrency_limit: Option<ConcurrencyLimitConfig>,
+
+    /// How long the server waits for the requests it is handling to finish when it shuts down.
+    #[serde(default = "defaults::Shutdown::drain_timeout_ms")]
+    pub drain_timeout_ms: u64,
+
+    /// Limit for the combined size of all caches in the search server.
+    pub memory_budget_bytes: Option<usize>,
+
+    /// Administrative rpcs are rejected unless an audit log is configured.
+    pub audit_log: Option<AuditLogConfig>,
+
+    /// File where the hosts blocked and the optic set through the administrative rpcs are
+    /// stored. The overrides are lost when the server restarts if not set.
+    pub overrides_path: Option<String>,
+
+    /// Record when documents were last retrieved so documents that are
+    /// never shown can be demoted to the archive tier.
+    pub access_log: Option<AccessLogConfig>,
+
+    #[serde(default = "defaults::SearchServer::signal_store_refresh_interval_sec")]
+    pub signal_store_refresh_interval_sec: u64,
 
     #[serde(default)]
     pub collector: CollectorConfig,
 
     #[serde(default)]
     pub snippet: SnippetConfig,
+
+    #[serde(default)]
+    pub threads: ThreadsConfig,
+
+    /// Restore the latest snapshot of the index if `index_path` doesn't exist.
+    pub snapshot: Option<SnapshotConfig>,
+
+    /// Only accept TLS connections. Disabled if not set.
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccessLogConfig {
+    pub path: String,
+
+    /// Fraction of the retrievals that are recorded.
+    #[serde(default = "defaults::SearchServer::access_log_sample_rate")]
+    pub sample_rate: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditLogConfig {
+    pub path: String,
+
+    /// File containing the secret used to sign the entries.
+    pub secret_path: String,
+
+    /// File with a line `<actor> <secret>` for each operator who may perform administrative
+    /// actions. The operators sign their requests with their secret.
+    pub operators_path: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct EntitySearchServerConfig {
     pub cluster_id: String,
+    #[serde(default, deserialize_with = "addr::deserialize_opt_vec")]
     pub gossip_seed_nodes: Option<Vec<SocketAddr>>,
+    #[serde(deserialize_with = "addr::deserialize")]
     pub gossip_addr: SocketAddr,
     pub index_path: String,
+    #[serde(deserialize_with = "addr::deserialize")]
     pub host: SocketAddr,
+    /// Expose the metrics of the requests for prometheus on this address. Disabled if not set.
+    #[serde(default, deserialize_with = "addr::deserialize_opt")]
+    pub prometheus_host: Option<SocketAddr>,
+    /// TLS and the shared secret of the cluster for the connections to the server. Disabled if not set.
+    pub tls: Option<TlsConfig>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CrawlCoordinatorConfig {
     pub job_queue: String,
+    #[serde(deserialize_with = "addr::deserialize")]
+    pub host: SocketAddr,
+    /// Expose the metrics of the requests for prometheus on this address. Disabled if not set.
+    #[serde(default, deserialize_with = "addr::deserialize_opt")]
+    pub prometheus_host: Option<SocketAddr>,
+
+    /// Let sites submit changed urls with the IndexNow protocol. Disabled if not set.
+    pub index_now: Option<IndexNowConfig>,
+    /// TLS and the shared secret of the cluster for the connections to the server. Disabled if not set.
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IndexNowConfig {
+    /// Address the submission endpoint listens on.
+    #[serde(deserialize_with = "addr::deserialize")]
     pub host: SocketAddr,
+    /// User agent used when fetching the key files.
+    pub user_agent: UserAgent,
+    #[serde(default = "defaults::IndexNow::timeout_seconds")]
+    pub timeout_seconds: u64,
+    #[serde(default = "defaults::Crawler::max_redirects")]
+    pub max_redirects: usize,
+    /// How long a verified key file is trusted before it is fetched again.
+    #[serde(default = "defaults::IndexNow::key_cache_seconds")]
+    pub key_cache_seconds: u64,
+    /// Submissions are rejected while this many submitted urls are waiting to be crawled for the domain.
+    #[serde(default = "defaults::IndexNow::max_pending_urls_per_domain")]
+    pub max_pending_urls_per_domain: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -334,12 +1425,212 @@ pub struct CrawlerConfig {
     pub timeout_seconds: u64,
     pub s3: S3Config,
     pub router_hosts: Vec<String>,
+
+    #[serde(default)]
+    pub trap_detection: TrapDetectionConfig,
+
+    /// Stop crawling a host in a job after this many consecutive server errors or timeouts.
+    #[serde(default = "defaults::Crawler::max_host_error_streak")]
+    pub max_host_error_streak: u64,
+
+    /// The number of successful and failed urls for each host in a job is appended to
+    /// this file. The planner uses it to demote hosts with sustained errors.
+    pub host_outcomes_path: Option<String>,
+
+    /// The outcome of every fetched url is appended to this file. The planner
+    /// uses it to track which urls are no longer available.
+    pub url_outcomes_path: Option<String>,
+
+    /// Append an event for every fetched page to this log. Disabled if not set.
+    pub event_log: Option<EventLogConfig>,
+
+    /// Capture a screenshot thumbnail of every fetched page with a render service.
+    /// Disabled if not set.
+    pub screenshots: Option<ScreenshotConfig>,
+
+    /// TLS and the shared secret of the cluster for the connections to the routers. Disabled if not set.
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScreenshotConfig {
+    /// The page is rendered by a GET request to this url with the url of the page in
+    /// the `url` parameter. The response is the rendered page as an image.
+    pub render_service_url: String,
+    pub store_path: String,
+
+    /// Hosts opt out of the screenshots by disallowing this user agent in their robots.txt.
+    /// Pages with a `noarchive` robots meta tag are never captured.
+    #[serde(default = "defaults::Screenshot::user_agent_token")]
+    pub user_agent_token: String,
+
+    #[serde(default = "defaults::Screenshot::render_timeout_sec")]
+    pub render_timeout_sec: u64,
+
+    #[serde(default = "defaults::Screenshot::thumbnail_width")]
+    pub thumbnail_width: u32,
+
+    #[serde(default = "defaults::Screenshot::thumbnail_height")]
+    pub thumbnail_height: u32,
+
+    #[serde(default = "defaults::Screenshot::jpeg_quality")]
+    pub jpeg_quality: u8,
+
+    #[serde(default = "defaults::Screenshot::max_thumbnail_bytes")]
+    pub max_thumbnail_bytes: u64,
+
+    #[serde(default = "defaults::Screenshot::max_host_bytes")]
+    pub max_host_bytes: u64,
+
+    #[serde(default = "defaults::Screenshot::max_total_bytes")]
+    pub max_total_bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventLogConfig {
+    pub path: String,
+
+    /// Number of events in each segment of the log.
+    #[serde(default = "defaults::EventLog::segment_size")]
+    pub segment_size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventLogConsumerConfig {
+    pub event_log: EventLogConfig,
+
+    /// Number of events between each commit of the consumers.
+    #[serde(default = "defaults::EventLog::batch_size")]
+    pub batch_size: usize,
+
+    /// Parse the fetched pages and append their links. Disabled if not set.
+    #[serde(default)]
+    pub extractor: bool,
+
+    /// Index the fetched pages. Disabled if not set.
+    pub indexer: Option<EventLogIndexerConfig>,
+
+    /// Insert the extracted links into the webgraphs. Disabled if not set.
+    pub webgraph: Option<EventLogWebgraphConfig>,
+
+    /// Update the security signals of the fetched pages. Disabled if not set.
+    pub signal_store: Option<EventLogSignalStoreConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventLogIndexerConfig {
+    pub index_path: String,
+    pub host_centrality_store_path: String,
+    pub page_centrality_store_path: Option<String>,
+    pub page_webgraph_path: Option<String>,
+    pub topics_path: Option<String>,
+    pub safety_classifier_path: Option<String>,
+
+    /// Only index and delete the urls owned by `shard_id`.
+    pub routing: Option<RoutingConfig>,
+    pub shard_id: Option<ShardId>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventLogWebgraphConfig {
+    pub host_graph_path: String,
+    pub page_graph_path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventLogSignalStoreConfig {
+    pub store_path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HostReputationConfig {
+    pub store_path: String,
+
+    /// Demote a host once this many of its urls in a row has failed with
+    /// server errors or timeouts, possibly across several jobs.
+    #[serde(default = "defaults::Crawler::max_host_error_streak")]
+    pub max_error_streak: u64,
+
+    /// Time until a demoted host is probed for the first time. The interval
+    /// doubles every time a probe fails.
+    #[serde(default = "defaults::Crawler::base_probe_interval_sec")]
+    pub base_probe_interval_sec: u64,
+
+    #[serde(default = "defaults::Crawler::max_probe_interval_sec")]
+    pub max_probe_interval_sec: u64,
+
+    /// Maximum number of urls crawled for a host when it is probed.
+    #[serde(default = "defaults::Crawler::probe_budget")]
+    pub probe_budget: u64,
+}
+
+/// Indexing directives of each host, like how many of its pages are marked noarchive or nosnippet.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HostPolicyConfig {
+    pub store_path: String,
+
+    /// A directive applies to the whole host once this fraction of its crawled pages has it.
+    #[serde(default = "defaults::HostPolicy::min_directive_fraction")]
+    pub min_directive_fraction: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HostPolicyReaderConfig {
+    /// The store the planner writes the policies to.
+    pub store_path: String,
+
+    /// The api keeps its own logs of the store here.
+    pub secondary_path: String,
+
+    /// A directive applies to the whole host once this fraction of its crawled pages has it.
+    #[serde(default = "defaults::HostPolicy::min_directive_fraction")]
+    pub min_directive_fraction: f64,
+
+    /// The policies recorded since the last refresh are used after this many seconds.
+    #[serde(default = "defaults::HostPolicy::refresh_interval_sec")]
+    pub refresh_interval_sec: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrapDetectionConfig {
+    /// A url template is considered a trap once a job has discovered more
+    /// than this many distinct urls matching it.
+    #[serde(default = "defaults::Crawler::trap_max_urls_per_template")]
+    pub max_urls_per_template: usize,
+
+    /// Paths where a segment is repeated more than this many times are considered traps.
+    #[serde(default = "defaults::Crawler::trap_max_repeated_segments")]
+    pub max_repeated_segments: usize,
+
+    /// Generated suppression rules are appended to this file for review. The approved
+    /// rules in the file are applied to all jobs.
+    pub rules_path: Option<String>,
+}
+
+impl Default for TrapDetectionConfig {
+    fn default() -> Self {
+        Self {
+            max_urls_per_template: defaults::Crawler::trap_max_urls_per_template(),
+            max_repeated_segments: defaults::Crawler::trap_max_repeated_segments(),
+            rules_path: None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CrawlRouterConfig {
+    #[serde(deserialize_with = "addr::deserialize")]
     pub host: SocketAddr,
-    pub coordinator_addrs: Vec<SocketAddr>,
+    /// Expose the metrics of the requests for prometheus on this address. Disabled if not set.
+    #[serde(default, deserialize_with = "addr::deserialize_opt")]
+    pub prometheus_host: Option<SocketAddr>,
+    /// All the resolved addresses of each coordinator.
+    #[serde(deserialize_with = "addr::deserialize_hosts")]
+    pub coordinator_addrs: Vec<Vec<SocketAddr>>,
+
+    /// TLS and the shared secret of the cluster for the connections to the router and
+    /// from the router to the coordinators. Disabled if not set.
+    pub tls: Option<TlsConfig>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -350,6 +1641,18 @@ pub enum AcceleratorDevice {
     Mps,
 }
 
+/// The tier of the index served by a search server.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ShardTier {
+    /// Small shards with the most popular and highest quality pages. These are always searched.
+    #[default]
+    Head,
+    /// Shards with the remaining pages. These are only searched when
+    /// the head shards does not return enough results.
+    Archive,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum WebgraphGranularity {
@@ -359,17 +1662,58 @@ pub enum WebgraphGranularity {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct WebgraphServerConfig {
+    #[serde(deserialize_with = "addr::deserialize")]
     pub host: SocketAddr,
+    /// Expose the metrics of the requests for prometheus on this address. Disabled if not set.
+    #[serde(default, deserialize_with = "addr::deserialize_opt")]
+    pub prometheus_host: Option<SocketAddr>,
     pub graph_path: String,
     pub granularity: WebgraphGranularity,
     pub inbound_similarity_path: Option<String>,
 
     pub cluster_id: String,
+    #[serde(default, deserialize_with = "addr::deserialize_opt_vec")]
     pub gossip_seed_nodes: Option<Vec<SocketAddr>>,
+    #[serde(deserialize_with = "addr::deserialize")]
     pub gossip_addr: SocketAddr,
 
     #[serde(default = "defaults::WebgraphServer::max_similar_hosts")]
     pub max_similar_hosts: usize,
+
+    /// How long the server waits for the requests it is handling to finish when it shuts down.
+    #[serde(default = "defaults::Shutdown::drain_timeout_ms")]
+    pub drain_timeout_ms: u64,
+
+    /// Restore the latest snapshot of the graph if `graph_path` doesn't exist.
+    pub snapshot: Option<SnapshotConfig>,
+
+    /// Only accept TLS connections. Disabled if not set.
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GoldenQueriesConfig {
+    pub index_path: String,
+    pub signal_store_path: Option<String>,
+
+    /// Toml file with the golden queries and their expectations.
+    pub suite_path: String,
+
+    /// Write a junit report of the run to this path.
+    pub report_path: Option<String>,
+
+    /// Number of top results that must not contain any of the spam hosts.
+    #[serde(default = "defaults::GoldenQueries::spam_depth")]
+    pub spam_depth: usize,
+
+    /// Widgets to check for widget expectations. Only the calculator is available if not set.
+    pub widgets: Option<WidgetsConfig>,
+
+    #[serde(default)]
+    pub collector: CollectorConfig,
+
+    #[serde(default)]
+    pub snippet: SnippetConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -394,12 +1738,73 @@ pub struct CrawlPlannerConfig {
     pub top_host_fraction: f64,
     pub wander_fraction: f64,
     pub top_n_hosts_surplus: usize,
+
+    /// Scale the budget of each host by the fraction of its pages
+    /// that were crawled without errors according to the host stats.
+    pub host_stats_path: Option<String>,
+
+    /// Urls matching the approved crawl trap suppression rules in this file are not scheduled.
+    pub trap_rules_path: Option<String>,
+
+    /// Limit the budget of hosts that has been demoted because of sustained crawl errors.
+    pub host_reputation: Option<HostReputationConfig>,
+
+    /// Host outcome logs from the crawl workers that are recorded in the host reputation store.
+    #[serde(default)]
+    pub host_outcome_paths: Vec<String>,
+
+    /// Record the indexing directives observed in the host outcome logs.
+    pub host_policy: Option<HostPolicyConfig>,
+
+    /// Store of the latest fetch of each url. Updated with the url outcome logs from the crawl workers.
+    pub liveness_store_path: Option<String>,
+
+    #[serde(default)]
+    pub url_outcome_paths: Vec<String>,
+
+    /// Seed files from the crawl seeder. The seeded hosts are planned together with the top hosts.
+    #[serde(default)]
+    pub seed_paths: Vec<String>,
+
+    /// Minimum budget of each seeded host.
+    #[serde(default = "defaults::CrawlSeeder::seed_budget")]
+    pub seed_budget: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CrawlSeederConfig {
+    pub host_harmonic_path: String,
+    pub host_graph_path: String,
+    pub page_graph_path: String,
+    pub host_stats_path: String,
+
+    /// The seeds are written to this file as json lines.
+    pub output_path: String,
+
+    /// Fraction of the hosts with the highest centrality that are checked for low index coverage.
+    #[serde(default = "defaults::CrawlSeeder::top_host_fraction")]
+    pub top_host_fraction: f64,
+
+    /// Hosts where the index has less than this fraction of the pages in the page graph are seeded.
+    #[serde(default = "defaults::CrawlSeeder::max_coverage")]
+    pub max_coverage: f64,
+
+    /// Hosts that are not in the index are seeded if this many other hosts link to them.
+    #[serde(default = "defaults::CrawlSeeder::min_linking_hosts")]
+    pub min_linking_hosts: usize,
+
+    /// Maximum number of seeds for each of the reasons.
+    #[serde(default = "defaults::CrawlSeeder::max_seeds")]
+    pub max_seeds: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LiveIndexConfig {
     pub split_path: String,
     pub downloaded_db_path: String,
+    /// Urls from the feeds and pushes are kept here until their pages have been committed to the index.
+    /// Defaults to `ingest_queue` next to the downloaded db.
+    pub ingest_queue_path: Option<String>,
 
     // crawler
     pub user_agent: UserAgent,
@@ -418,6 +1823,10 @@ pub struct LiveIndexConfig {
     #[serde(default = "defaults::Crawler::max_redirects")]
     pub max_redirects: usize,
     pub timeout_seconds: u64,
+    #[serde(default = "defaults::Crawler::max_host_error_streak")]
+    pub max_host_error_streak: u64,
+    /// Feeds are not checked for hosts that has been demoted because of sustained crawl errors.
+    pub host_reputation: Option<HostReputationConfig>,
 
     // indexer
     pub host_centrality_store_path: String,
@@ -426,20 +1835,53 @@ pub struct LiveIndexConfig {
     pub safety_classifier_path: Option<String>,
     pub host_centrality_threshold: Option<f64>,
     pub minimum_clean_words: Option<usize>,
+    /// Only crawl and index the urls owned by `shard_id`. The live indexes of all the
+    /// shards should then check the same feeds, so every url is indexed by its owner.
+    pub routing: Option<RoutingConfig>,
+    pub shard_id: Option<ShardId>,
 
     // search
     pub cluster_id: String,
+    #[serde(default, deserialize_with = "addr::deserialize_opt_vec")]
     pub gossip_seed_nodes: Option<Vec<SocketAddr>>,
+    #[serde(deserialize_with = "addr::deserialize")]
     pub gossip_addr: SocketAddr,
     pub split_id: SplitId,
     pub index_path: String,
     pub linear_model_path: Option<String>,
     pub lambda_model_path: Option<String>,
+    #[serde(deserialize_with = "addr::deserialize")]
     pub host: SocketAddr,
+    /// Expose the metrics of the requests for prometheus on this address. Disabled if not set.
+    #[serde(default, deserialize_with = "addr::deserialize_opt")]
+    pub prometheus_host: Option<SocketAddr>,
     #[serde(default)]
     pub collector: CollectorConfig,
     #[serde(default)]
     pub snippet: SnippetConfig,
+
+    /// Subscribe to the WebSub hubs advertised by the feeds, so publishers can push
+    /// updates instead of waiting for the feeds to be polled. Disabled if not set.
+    pub websub: Option<WebSubConfig>,
+    /// Administrative rpcs are rejected unless an audit log is configured.
+    pub audit_log: Option<AuditLogConfig>,
+    /// TLS and the shared secret of the cluster for the connections to the server. Disabled if not set.
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebSubConfig {
+    /// Address the callback server listens on.
+    #[serde(deserialize_with = "addr::deserialize")]
+    pub host: SocketAddr,
+    /// The public url the hubs can reach the callback server on.
+    pub callback_base_url: String,
+    pub subscriptions_path: String,
+    #[serde(default = "defaults::WebSub::lease_seconds")]
+    pub lease_seconds: u64,
+    /// Pushes from a publisher are rejected once it has pushed this many times within the last hour.
+    #[serde(default = "defaults::WebSub::max_pushes_per_hour")]
+    pub max_pushes_per_hour: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -458,6 +1900,166 @@ pub struct FeedIndexingConfig {
     pub skip_warc_files: Option<usize>,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct SignalCsvConfig {
+    pub signal: crate::signal_store::StoredSignal,
+    /// csv file without header where each row is `url,value`
+    pub path: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SignalStoreConfig {
+    pub output_path: String,
+    /// defaults to the current unix timestamp
+    pub version: Option<u64>,
+    pub host_centrality_store_path: Option<String>,
+    pub page_centrality_store_path: Option<String>,
+    #[serde(default)]
+    pub csv_signals: Vec<SignalCsvConfig>,
+    pub topics: Option<TopicSignalsConfig>,
+    pub security: Option<SecuritySignalsConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SecuritySignalsConfig {
+    /// Warc files from our crawl with the security properties of the pages.
+    pub warc_source: Option<WarcSource>,
+    pub limit_warc_files: Option<usize>,
+    pub skip_warc_files: Option<usize>,
+    /// Text file with a known malware host or url on each line.
+    pub malware_list_path: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TopicSignalsConfig {
+    pub model_path: String,
+    /// csv file without header where each row is `url,text`
+    pub pages_path: String,
+    /// Calculate topic-sensitive centralities on this host graph
+    /// using the topics of the hosts.
+    pub host_graph_path: Option<String>,
+}
+
+/// An extraction stage that can be re-run on the stored raw pages.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackfillStage {
+    /// https, HSTS and mixed content in the signal store.
+    Security,
+    /// The topics of the pages in the signal store.
+    Topics,
+    /// Reading ease and number of words in the index.
+    Readability,
+    /// Generated content likelihood in the index.
+    GeneratedContent,
+    /// schema.org items in the index.
+    StructuredData,
+}
+
+impl BackfillStage {
+    pub fn patches_index(&self) -> bool {
+        matches!(
+            self,
+            BackfillStage::Readability
+                | BackfillStage::GeneratedContent
+                | BackfillStage::StructuredData
+        )
+    }
+
+    pub fn patches_signal_store(&self) -> bool {
+        matches!(self, BackfillStage::Security | BackfillStage::Topics)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BackfillConfig {
+    /// Warc files with the raw responses from our crawl.
+    pub warc_source: WarcSource,
+    pub limit_warc_files: Option<usize>,
+    pub skip_warc_files: Option<usize>,
+
+    pub stages: Vec<BackfillStage>,
+
+    /// Only count the pages that would be patched.
+    #[serde(default)]
+    pub dry_run: bool,
+
+    /// Required by the index stages. Only pages already in the index are patched.
+    pub index: Option<BackfillIndexConfig>,
+
+    /// Required by the signal store stages. The patched store is written to
+    /// `output_path` with the next version, or replaces the store if not set.
+    pub signal_store: Option<BackfillSignalStoreConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BackfillIndexConfig {
+    pub index_path: String,
+    pub host_centrality_store_path: String,
+    pub page_centrality_store_path: Option<String>,
+    pub page_webgraph_path: Option<String>,
+    pub topics_path: Option<String>,
+    pub safety_classifier_path: Option<String>,
+    pub signal_store_path: Option<String>,
+    pub topic_classifier_path: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BackfillSignalStoreConfig {
+    pub path: String,
+    pub output_path: Option<String>,
+    /// Required by the topics stage.
+    pub topic_classifier_path: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HostStatsConfig {
+    pub output_path: String,
+    pub index_path: String,
+    /// Centrality and topics of the hosts are taken from the signal store if set.
+    pub signal_store_path: Option<String>,
+    /// csv files without header where each row is `url,status_code` of a failed fetch
+    #[serde(default)]
+    pub crawl_error_paths: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HostStatsServerConfig {
+    pub cluster_id: String,
+    #[serde(default, deserialize_with = "addr::deserialize_opt_vec")]
+    pub gossip_seed_nodes: Option<Vec<SocketAddr>>,
+    #[serde(deserialize_with = "addr::deserialize")]
+    pub gossip_addr: SocketAddr,
+    #[serde(deserialize_with = "addr::deserialize")]
+    pub host: SocketAddr,
+    /// Expose the metrics of the requests for prometheus on this address. Disabled if not set.
+    #[serde(default, deserialize_with = "addr::deserialize_opt")]
+    pub prometheus_host: Option<SocketAddr>,
+    pub store_path: String,
+    /// TLS and the shared secret of the cluster for the connections to the server. Disabled if not set.
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CentralityServerConfig {
+    pub cluster_id: String,
+    #[serde(default, deserialize_with = "addr::deserialize_opt_vec")]
+    pub gossip_seed_nodes: Option<Vec<SocketAddr>>,
+    #[serde(deserialize_with = "addr::deserialize")]
+    pub gossip_addr: SocketAddr,
+    #[serde(deserialize_with = "addr::deserialize")]
+    pub host: SocketAddr,
+    /// Expose the metrics of the requests for prometheus on this address. Disabled if not set.
+    #[serde(default, deserialize_with = "addr::deserialize_opt")]
+    pub prometheus_host: Option<SocketAddr>,
+    /// The output of `centrality host`.
+    pub host_centrality_path: String,
+    /// The output of `centrality page`. The centrality of pages is not served if not set.
+    pub page_centrality_path: Option<String>,
+    /// Limit for the combined size of the caches of the centrality stores.
+    pub memory_budget_bytes: Option<usize>,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct WebSpellConfig {
     pub output_path: String,
@@ -482,6 +2084,17 @@ pub struct CorrectionConfig {
     /// corrected
     #[serde(default = "defaults::Correction::correction_threshold")]
     pub correction_threshold: f64,
+
+    /// The threshold that the difference between the log probability of the best
+    /// segmentation of a term and the term as an unknown word must be above for
+    /// the segmentation to be suggested, e.g. `rustasyncruntime` -> `rust async runtime`
+    #[serde(default = "defaults::Correction::segmentation_threshold")]
+    pub segmentation_threshold: f64,
+
+    /// The threshold above which the segmentation is applied to the query
+    /// instead of only being suggested
+    #[serde(default = "defaults::Correction::auto_segmentation_threshold")]
+    pub auto_segmentation_threshold: f64,
 }
 
 impl Default for CorrectionConfig {
@@ -490,6 +2103,8 @@ impl Default for CorrectionConfig {
             misspelled_prob: defaults::Correction::misspelled_prob(),
             lm_prob_weight: defaults::Correction::lm_prob_weight(),
             correction_threshold: defaults::Correction::correction_threshold(),
+            segmentation_threshold: defaults::Correction::segmentation_threshold(),
+            auto_segmentation_threshold: defaults::Correction::auto_segmentation_threshold(),
         }
     }
 }
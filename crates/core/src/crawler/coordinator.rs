@@ -14,21 +14,160 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use super::{file_queue::FileQueue, Job, Result};
-use std::{path::Path, sync::Mutex};
+use super::{file_queue::FileQueue, Domain, Job, Result, WeightedUrl};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+    sync::Mutex,
+};
+use url::Url;
+
+/// Weight of the urls that sites have submitted themselves.
+const SUBMITTED_URL_WEIGHT: f64 = 1.0;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("too many submitted urls are waiting to be crawled for the domain")]
+pub struct TooManyPendingUrls;
+
+/// Jobs for urls that sites have submitted, e.g. through IndexNow.
+/// There is at most one job per domain, so new submissions for a domain
+/// are added to its pending job.
+#[derive(Default)]
+struct SubmittedJobs {
+    order: VecDeque<Domain>,
+    jobs: HashMap<Domain, Job>,
+}
+
+impl SubmittedJobs {
+    fn submit(
+        &mut self,
+        urls: Vec<Url>,
+        max_pending_per_domain: usize,
+    ) -> Result<(), TooManyPendingUrls> {
+        let mut by_domain: HashMap<Domain, Vec<Url>> = HashMap::new();
+
+        for url in urls {
+            by_domain.entry(Domain::from(&url)).or_default().push(url);
+        }
+
+        if by_domain.iter().any(|(domain, urls)| {
+            let pending = self.jobs.get(domain).map(|job| job.urls.len()).unwrap_or(0);
+            pending + urls.len() > max_pending_per_domain
+        }) {
+            return Err(TooManyPendingUrls);
+        }
+
+        for (domain, urls) in by_domain {
+            let job = self.jobs.entry(domain.clone()).or_insert_with(|| {
+                self.order.push_back(domain.clone());
+
+                Job {
+                    domain,
+                    urls: VecDeque::new(),
+                    wandering_urls: 0,
+                }
+            });
+
+            for url in urls {
+                if !job.urls.iter().any(|existing| existing.url == url) {
+                    job.urls.push_back(WeightedUrl {
+                        url,
+                        weight: SUBMITTED_URL_WEIGHT,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<Job> {
+        let domain = self.order.pop_front()?;
+        self.jobs.remove(&domain)
+    }
+}
 
 pub struct CrawlCoordinator {
     jobs: Mutex<FileQueue<Job>>,
+    submitted: Mutex<SubmittedJobs>,
 }
 
 impl CrawlCoordinator {
     pub fn new<P: AsRef<Path>>(jobs_queue: P) -> Result<Self> {
         Ok(Self {
             jobs: Mutex::new(FileQueue::new(jobs_queue)?),
+            submitted: Mutex::new(SubmittedJobs::default()),
         })
     }
 
+    /// Submitted urls are crawled before the planned jobs. They are only kept
+    /// in memory, so sites must resubmit urls that are pending when the coordinator restarts.
+    pub fn submit(
+        &self,
+        urls: Vec<Url>,
+        max_pending_per_domain: usize,
+    ) -> Result<(), TooManyPendingUrls> {
+        self.submitted
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .submit(urls, max_pending_per_domain)
+    }
+
     pub fn sample_job(&self) -> Result<Option<Job>> {
+        if let Some(job) = self
+            .submitted
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+        {
+            return Ok(Some(job));
+        }
+
         self.jobs.lock().unwrap_or_else(|e| e.into_inner()).pop()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(urls: &[&str]) -> Vec<Url> {
+        urls.iter().map(|url| Url::parse(url).unwrap()).collect()
+    }
+
+    #[test]
+    fn submitted_jobs() {
+        let mut submitted = SubmittedJobs::default();
+
+        submitted
+            .submit(
+                urls(&[
+                    "https://a.example.com/1",
+                    "https://b.example.com/1",
+                    "https://other.org/1",
+                ]),
+                3,
+            )
+            .unwrap();
+        submitted
+            .submit(
+                urls(&["https://example.com/2", "https://a.example.com/1"]),
+                3,
+            )
+            .unwrap();
+
+        assert_eq!(
+            submitted.submit(urls(&["https://example.com/3"]), 3),
+            Err(TooManyPendingUrls)
+        );
+
+        let job = submitted.pop().unwrap();
+        assert_eq!(job.domain, Domain::from("example.com".to_string()));
+        assert_eq!(job.urls.len(), 3);
+
+        let job = submitted.pop().unwrap();
+        assert_eq!(job.domain, Domain::from("other.org".to_string()));
+
+        assert!(submitted.pop().is_none());
+    }
+}
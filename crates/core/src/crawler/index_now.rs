@@ -0,0 +1,453 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! [IndexNow](https://www.indexnow.org/documentation) url submissions.
+//!
+//! Sites notify us about changed urls either with a single url in a `GET` request
+//! or a batch of urls in a `POST` request. To prove that the site owner made the
+//! submission, the site hosts a key file (`https://<host>/<key>.txt` unless another
+//! location is given) that contains the key from the submission. The submitted
+//! urls are added to the crawl coordinator, which hands them out before the planned jobs.
+//!
+//! The key files are fetched from the host named in the submission, so the host must be
+//! a domain that resolves to public addresses. Otherwise anyone could make the coordinator
+//! send requests to the services in its own network.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use tokio::net::TcpListener;
+use url::Url;
+
+use crate::{config::IndexNowConfig, ttl_cache::TTLCache};
+
+use super::{
+    coordinator::TooManyPendingUrls,
+    public_addr::{is_fetchable, PublicResolver},
+    CrawlCoordinator, MAX_URL_LEN_BYTES,
+};
+
+/// Maximum number of urls in a single submission as defined by the protocol.
+const MAX_URLS_PER_SUBMISSION: usize = 10_000;
+
+const MIN_KEY_LEN: usize = 8;
+const MAX_KEY_LEN: usize = 128;
+
+/// Key files larger than this are not valid.
+const MAX_KEY_FILE_BYTES: usize = 1024;
+
+/// Maximum number of verified key files that are remembered.
+const MAX_VERIFIED_KEY_FILES: usize = 100_000;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum SubmissionError {
+    #[error("the submission is not valid")]
+    BadRequest,
+    #[error("the key file could not be found or does not match the key")]
+    InvalidKey,
+    #[error("the urls do not belong to the host or the key is not valid")]
+    Unprocessable,
+    #[error("too many urls are waiting to be crawled for the host")]
+    TooManyRequests,
+}
+
+impl From<TooManyPendingUrls> for SubmissionError {
+    fn from(_: TooManyPendingUrls) -> Self {
+        SubmissionError::TooManyRequests
+    }
+}
+
+impl IntoResponse for SubmissionError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            SubmissionError::BadRequest => StatusCode::BAD_REQUEST,
+            SubmissionError::InvalidKey => StatusCode::FORBIDDEN,
+            SubmissionError::Unprocessable => StatusCode::UNPROCESSABLE_ENTITY,
+            SubmissionError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+        };
+
+        (status, self.to_string()).into_response()
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Submission {
+    pub host: String,
+    pub key: String,
+    pub key_location: Option<String>,
+    pub url_list: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SingleSubmission {
+    pub url: String,
+    pub key: String,
+    pub key_location: Option<String>,
+}
+
+impl SingleSubmission {
+    fn into_submission(self) -> Result<Submission, SubmissionError> {
+        let host = Url::parse(&self.url)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| host.to_string()))
+            .ok_or(SubmissionError::BadRequest)?;
+
+        Ok(Submission {
+            host,
+            key: self.key,
+            key_location: self.key_location,
+            url_list: vec![self.url],
+        })
+    }
+}
+
+fn is_valid_key(key: &str) -> bool {
+    (MIN_KEY_LEN..=MAX_KEY_LEN).contains(&key.len())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// A submission where the urls has been checked against the host and the location of the key file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ValidSubmission {
+    key: String,
+    key_file: Url,
+    urls: Vec<Url>,
+}
+
+impl TryFrom<Submission> for ValidSubmission {
+    type Error = SubmissionError;
+
+    fn try_from(submission: Submission) -> Result<Self, Self::Error> {
+        let host = submission.host.trim().to_lowercase();
+
+        if host.is_empty()
+            || submission.url_list.is_empty()
+            || submission.url_list.len() > MAX_URLS_PER_SUBMISSION
+        {
+            return Err(SubmissionError::BadRequest);
+        }
+
+        if !is_valid_key(&submission.key) {
+            return Err(SubmissionError::Unprocessable);
+        }
+
+        let key_file = match &submission.key_location {
+            Some(location) => Url::parse(location).map_err(|_| SubmissionError::BadRequest)?,
+            None => Url::parse(&format!("https://{}/{}.txt", host, submission.key))
+                .map_err(|_| SubmissionError::BadRequest)?,
+        };
+
+        if key_file.host_str() != Some(host.as_str()) || !is_fetchable(&key_file) {
+            return Err(SubmissionError::Unprocessable);
+        }
+
+        // a key file in a folder only proves ownership of the urls in that folder.
+        let key_file_folder = &key_file.path()[..=key_file.path().rfind('/').unwrap_or(0)];
+
+        let urls = submission
+            .url_list
+            .iter()
+            .map(|url| {
+                let url = Url::parse(url).map_err(|_| SubmissionError::BadRequest)?;
+
+                if !matches!(url.scheme(), "http" | "https")
+                    || url.as_str().len() > MAX_URL_LEN_BYTES
+                {
+                    return Err(SubmissionError::BadRequest);
+                }
+
+                if url.host_str() != Some(host.as_str()) || !url.path().starts_with(key_file_folder)
+                {
+                    return Err(SubmissionError::Unprocessable);
+                }
+
+                Ok(url)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            key: submission.key,
+            key_file,
+            urls,
+        })
+    }
+}
+
+pub struct IndexNow {
+    config: IndexNowConfig,
+    coordinator: Arc<CrawlCoordinator>,
+    client: reqwest::Client,
+    /// Key files that has recently been verified, so they are not fetched for every submission.
+    verified: Mutex<TTLCache<(Url, String), ()>>,
+}
+
+impl IndexNow {
+    pub fn new(config: IndexNowConfig, coordinator: Arc<CrawlCoordinator>) -> anyhow::Result<Self> {
+        let max_redirects = config.max_redirects;
+
+        // the key file can only redirect to another location on the same host
+        let redirect = reqwest::redirect::Policy::custom(move |attempt| {
+            let same_host = attempt
+                .previous()
+                .first()
+                .is_some_and(|first| first.host_str() == attempt.url().host_str());
+
+            if attempt.previous().len() > max_redirects {
+                attempt.error("too many redirects")
+            } else if same_host && is_fetchable(attempt.url()) {
+                attempt.follow()
+            } else {
+                attempt.stop()
+            }
+        });
+
+        let client = reqwest::Client::builder()
+            .user_agent(&config.user_agent.full)
+            .timeout(Duration::from_secs(config.timeout_seconds))
+            .redirect(redirect)
+            .dns_resolver(Arc::new(PublicResolver))
+            .build()?;
+
+        Ok(Self {
+            coordinator,
+            client,
+            verified: Mutex::new(TTLCache::with_ttl_and_max_size(
+                Duration::from_secs(config.key_cache_seconds),
+                Some(MAX_VERIFIED_KEY_FILES),
+            )),
+            config,
+        })
+    }
+
+    fn is_cached(&self, key_file: &Url, key: &str) -> bool {
+        self.verified
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&(key_file.clone(), key.to_string()))
+            .is_some()
+    }
+
+    async fn verify_key(&self, key_file: &Url, key: &str) -> Result<(), SubmissionError> {
+        if self.is_cached(key_file, key) {
+            return Ok(());
+        }
+
+        let mut res = self
+            .client
+            .get(key_file.as_str())
+            .send()
+            .await
+            .map_err(|_| SubmissionError::InvalidKey)?;
+
+        if !res.status().is_success() {
+            return Err(SubmissionError::InvalidKey);
+        }
+
+        // stop reading as soon as the file is too large to be a key file
+        let mut content = Vec::new();
+        while let Some(chunk) = res.chunk().await.map_err(|_| SubmissionError::InvalidKey)? {
+            if content.len() + chunk.len() > MAX_KEY_FILE_BYTES {
+                return Err(SubmissionError::InvalidKey);
+            }
+
+            content.extend_from_slice(&chunk);
+        }
+
+        if String::from_utf8_lossy(&content).trim() != key {
+            return Err(SubmissionError::InvalidKey);
+        }
+
+        self.verified
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert((key_file.clone(), key.to_string()), ());
+
+        Ok(())
+    }
+
+    pub async fn submit(&self, submission: Submission) -> Result<(), SubmissionError> {
+        let submission = ValidSubmission::try_from(submission)?;
+
+        self.verify_key(&submission.key_file, &submission.key)
+            .await?;
+
+        tracing::debug!(
+            "{} urls submitted for {}",
+            submission.urls.len(),
+            submission.key_file
+        );
+
+        self.coordinator
+            .submit(submission.urls, self.config.max_pending_urls_per_domain)?;
+
+        Ok(())
+    }
+}
+
+async fn submit_single(
+    extract::State(index_now): extract::State<Arc<IndexNow>>,
+    extract::Query(submission): extract::Query<SingleSubmission>,
+) -> Result<StatusCode, SubmissionError> {
+    index_now.submit(submission.into_submission()?).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn submit_batch(
+    extract::State(index_now): extract::State<Arc<IndexNow>>,
+    Json(submission): Json<Submission>,
+) -> Result<StatusCode, SubmissionError> {
+    index_now.submit(submission).await?;
+    Ok(StatusCode::OK)
+}
+
+pub async fn serve(host: SocketAddr, index_now: Arc<IndexNow>) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route("/indexnow", get(submit_single).post(submit_batch))
+        .with_state(index_now);
+
+    tracing::info!("IndexNow submissions listening on {}", host);
+    axum::serve(TcpListener::bind(&host).await?, app.into_make_service()).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submission(key_location: Option<&str>, urls: &[&str]) -> Submission {
+        Submission {
+            host: "www.example.com".to_string(),
+            key: "a1b2c3d4e5f6".to_string(),
+            key_location: key_location.map(|location| location.to_string()),
+            url_list: urls.iter().map(|url| url.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn valid_submission() {
+        let valid = ValidSubmission::try_from(submission(
+            None,
+            &["https://www.example.com/new", "http://www.example.com/"],
+        ))
+        .unwrap();
+
+        assert_eq!(
+            valid.key_file,
+            Url::parse("https://www.example.com/a1b2c3d4e5f6.txt").unwrap()
+        );
+        assert_eq!(valid.urls.len(), 2);
+    }
+
+    #[test]
+    fn invalid_submissions() {
+        assert_eq!(
+            ValidSubmission::try_from(submission(None, &[])),
+            Err(SubmissionError::BadRequest)
+        );
+        assert_eq!(
+            ValidSubmission::try_from(submission(None, &["not a url"])),
+            Err(SubmissionError::BadRequest)
+        );
+        assert_eq!(
+            ValidSubmission::try_from(submission(None, &["https://other.example.com/"])),
+            Err(SubmissionError::Unprocessable)
+        );
+        assert_eq!(
+            ValidSubmission::try_from(Submission {
+                key: "short".to_string(),
+                ..submission(None, &["https://www.example.com/"])
+            }),
+            Err(SubmissionError::Unprocessable)
+        );
+        assert_eq!(
+            ValidSubmission::try_from(submission(
+                Some("https://other.example.com/key.txt"),
+                &["https://www.example.com/"]
+            )),
+            Err(SubmissionError::Unprocessable)
+        );
+    }
+
+    #[test]
+    fn internal_hosts_are_rejected() {
+        for host in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "[::1]",
+            "localhost",
+            "api.localhost",
+        ] {
+            assert_eq!(
+                ValidSubmission::try_from(Submission {
+                    host: host.to_string(),
+                    ..submission(None, &[&format!("https://{host}/")])
+                }),
+                Err(SubmissionError::Unprocessable),
+                "{host}"
+            );
+        }
+
+        assert_eq!(
+            ValidSubmission::try_from(submission(
+                Some("ftp://www.example.com/key.txt"),
+                &["https://www.example.com/"]
+            )),
+            Err(SubmissionError::Unprocessable)
+        );
+    }
+
+    #[test]
+    fn key_location_limits_urls() {
+        let location = Some("https://www.example.com/blog/key.txt");
+
+        assert!(ValidSubmission::try_from(submission(
+            location,
+            &["https://www.example.com/blog/post"]
+        ))
+        .is_ok());
+        assert_eq!(
+            ValidSubmission::try_from(submission(location, &["https://www.example.com/shop/item"])),
+            Err(SubmissionError::Unprocessable)
+        );
+    }
+
+    #[test]
+    fn single_submission() {
+        let submission = SingleSubmission {
+            url: "https://www.example.com/page".to_string(),
+            key: "a1b2c3d4e5f6".to_string(),
+            key_location: None,
+        }
+        .into_submission()
+        .unwrap();
+
+        assert_eq!(submission.host, "www.example.com");
+        assert_eq!(submission.url_list, vec!["https://www.example.com/page"]);
+    }
+}
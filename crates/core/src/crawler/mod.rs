@@ -20,17 +20,29 @@ use hashbrown::HashMap;
 
 use url::Url;
 
-use crate::{config::CrawlerConfig, warc, webpage::url_ext::UrlExt};
-
-use self::{warc_writer::WarcWriter, worker::WorkerThread};
+use crate::{
+    config::CrawlerConfig, distributed::net, event_log::EventLog, liveness::UrlOutcomeLog,
+    security::SecurityProperties, warc, webpage::url_ext::UrlExt,
+};
+
+use self::{
+    reputation::HostOutcomeLog, screenshot::Screenshotter, trap_detector::SuppressionRules,
+    warc_writer::WarcWriter, worker::WorkerThread,
+};
 pub use worker::JobExecutor;
 
 pub mod coordinator;
+pub mod index_now;
+pub mod reputation;
 mod robots_txt;
 pub mod router;
+pub mod screenshot;
+pub mod seeder;
+pub mod trap_detector;
 pub use router::Router;
 mod file_queue;
 pub mod planner;
+pub mod public_addr;
 mod wander_prirotiser;
 mod warc_writer;
 mod worker;
@@ -74,7 +86,13 @@ pub struct Domain(String);
 
 impl From<&Url> for Domain {
     fn from(url: &Url) -> Self {
-        Self(url.icann_domain().unwrap_or_default().to_string())
+        match url.host() {
+            // ip literals have no icann domain, so each address is its own domain
+            Some(url::Host::Ipv4(_)) | Some(url::Host::Ipv6(_)) => {
+                Self(url.host_str().unwrap_or_default().to_string())
+            }
+            _ => Self(url.icann_domain().unwrap_or_default().to_string()),
+        }
     }
 }
 
@@ -113,9 +131,19 @@ pub struct Job {
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum UrlResponse {
-    Success { url: Url },
-    Failed { url: Url, status_code: Option<u16> },
-    Redirected { url: Url, new_url: Url },
+    Success {
+        url: Url,
+    },
+    Failed {
+        url: Url,
+        status_code: Option<u16>,
+        /// The name of the host does not exist.
+        host_not_found: bool,
+    },
+    Redirected {
+        url: Url,
+        new_url: Url,
+    },
 }
 
 #[derive(
@@ -202,33 +230,75 @@ pub struct CrawlDatum {
     pub payload_type: warc::PayloadType,
     pub body: String,
     pub fetch_time_ms: u64,
+    pub security: SecurityProperties,
 }
 
 pub struct Crawler {
     writer: Arc<WarcWriter>,
+    screenshots: Option<Arc<Screenshotter>>,
     handles: Vec<tokio::task::JoinHandle<()>>,
 }
 
 impl Crawler {
     pub async fn new(config: CrawlerConfig) -> Result<Self> {
-        let writer = Arc::new(WarcWriter::new(config.s3.clone()));
+        let event_log = match &config.event_log {
+            Some(event_log) => Some(Arc::new(EventLog::open(event_log)?)),
+            None => None,
+        };
+
+        let writer = Arc::new(WarcWriter::new(config.s3.clone(), event_log));
         let mut handles = Vec::new();
         let mut router_hosts = Vec::new();
 
         for host in &config.router_hosts {
-            router_hosts.push(host.parse()?);
+            router_hosts.push(net::resolve(host)?);
         }
 
+        let suppression_rules = match &config.trap_detection.rules_path {
+            Some(path) => {
+                let rules = SuppressionRules::open(path)?;
+                tracing::info!("loaded {} approved crawl trap rules", rules.num_approved());
+                Some(Arc::new(rules))
+            }
+            None => None,
+        };
+
+        let host_outcomes = match &config.host_outcomes_path {
+            Some(path) => Some(Arc::new(HostOutcomeLog::open(path)?)),
+            None => None,
+        };
+
+        let url_outcomes = match &config.url_outcomes_path {
+            Some(path) => Some(Arc::new(UrlOutcomeLog::open(path)?)),
+            None => None,
+        };
+
+        let screenshots = match &config.screenshots {
+            Some(screenshots) => Some(Arc::new(Screenshotter::open(screenshots)?)),
+            None => None,
+        };
+
         for _ in 0..config.num_worker_threads {
-            let worker =
-                WorkerThread::new(Arc::clone(&writer), config.clone(), router_hosts.clone())?;
+            let worker = WorkerThread::new(
+                Arc::clone(&writer),
+                config.clone(),
+                router_hosts.clone(),
+                suppression_rules.clone(),
+                host_outcomes.clone(),
+                url_outcomes.clone(),
+                screenshots.clone(),
+            )?;
 
             handles.push(tokio::spawn(async move {
                 worker.run().await;
             }));
         }
 
-        Ok(Self { writer, handles })
+        Ok(Self {
+            writer,
+            screenshots,
+            handles,
+        })
     }
 
     pub async fn run(self) {
@@ -237,6 +307,10 @@ impl Crawler {
         }
 
         self.writer.finish().await.unwrap();
+
+        if let Some(screenshots) = &self.screenshots {
+            screenshots.flush();
+        }
     }
 }
 
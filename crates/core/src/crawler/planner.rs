@@ -19,6 +19,7 @@ use indicatif::ProgressIterator;
 use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::{
     collections::VecDeque,
@@ -27,13 +28,19 @@ use std::{
 };
 use url::Url;
 
+use crate::crawler::reputation::{self, read_outcomes, HostReputationStore};
+use crate::crawler::seeder::read_seeds;
+use crate::crawler::trap_detector::{approved_templates, is_trapped};
 use crate::crawler::WeightedUrl;
+use crate::host_policy::HostPolicyStore;
+use crate::liveness::{self, LivenessStore};
 use crate::webgraph::centrality::{top_hosts, TopHosts};
 use crate::{
     config::CrawlPlannerConfig,
     crawler::{file_queue::FileQueueWriter, Job},
+    host_stats::HostStatsStore,
     kv::{rocksdb_store::RocksDbStore, Kv},
-    webgraph::{NodeID, Webgraph},
+    webgraph::{Node, NodeID, Webgraph},
 };
 
 use super::Domain;
@@ -99,13 +106,91 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
     let queue_path = output.as_ref().join("job_queue");
     std::fs::create_dir_all(&queue_path)?;
 
-    let hosts = top_hosts(
+    let mut hosts = top_hosts(
         &host_centrality,
         TopHosts::Fraction(config.top_host_fraction),
     );
+
+    let mut seed_hosts = BTreeSet::new();
+    for path in &config.seed_paths {
+        for seed in read_seeds(path)? {
+            let host = Node::from(seed.host.as_str()).into_host().id();
+
+            if host_graph.id2node(&host).is_some() {
+                seed_hosts.insert(host);
+            }
+        }
+    }
+
+    let top: BTreeSet<_> = hosts.iter().copied().collect();
+    hosts.extend(seed_hosts.iter().filter(|host| !top.contains(host)));
+    tracing::info!("seeded {} hosts", seed_hosts.len());
+
     let num_hosts = hosts.len();
     tracing::info!("found {} hosts", num_hosts);
 
+    let host_stats = config
+        .host_stats_path
+        .as_ref()
+        .map(HostStatsStore::open)
+        .transpose()?;
+
+    let trapped = match &config.trap_rules_path {
+        Some(path) => approved_templates(path)?,
+        None => Default::default(),
+    };
+    tracing::info!("loaded {} crawl trap rules", trapped.len());
+
+    let reputation = config
+        .host_reputation
+        .clone()
+        .map(HostReputationStore::open);
+
+    if let Some(reputation) = &reputation {
+        for path in &config.host_outcome_paths {
+            reputation.record(read_outcomes(path)?);
+        }
+
+        reputation.flush();
+    }
+
+    if let Some(host_policy) = &config.host_policy {
+        let store = HostPolicyStore::open(host_policy);
+
+        for path in &config.host_outcome_paths {
+            store.record(read_outcomes(path)?);
+        }
+
+        store.flush();
+    }
+
+    if let Some(path) = &config.liveness_store_path {
+        let store = LivenessStore::open(path);
+
+        for path in &config.url_outcome_paths {
+            store.record(liveness::read_outcomes(path)?);
+        }
+
+        store.flush();
+    }
+
+    let now = reputation::now_ms();
+    let max_host_budgets: BTreeMap<_, _> = match &reputation {
+        Some(reputation) => hosts
+            .iter()
+            .filter_map(|host| {
+                let node = host_graph.id2node(host)?;
+                let max_budget = reputation.max_budget(&node.name, now)?;
+                Some((*host, max_budget))
+            })
+            .collect(),
+        None => BTreeMap::new(),
+    };
+    tracing::info!(
+        "limiting budget of {} hosts because of crawl errors",
+        max_host_budgets.len()
+    );
+
     let mut total_host_centrality = hosts
         .iter()
         .filter_map(|v| host_centrality.get(v))
@@ -149,14 +234,28 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
             .map(|host| {
                 let host_centrality = host_centrality.get(host).unwrap_or_default();
 
-                let host_budget = ((config.crawl_budget as f64 * host_centrality)
+                let success_rate = host_stats
+                    .as_ref()
+                    .and_then(|stats| stats.get(host))
+                    .map(|stats| stats.crawl_success_rate())
+                    .unwrap_or(1.0);
+
+                let host_budget = ((config.crawl_budget as f64 * host_centrality * success_rate)
                     / total_host_centrality)
                     .round()
                     .max(0.0) as u64;
 
                 let num_pages = host_pages.get(host).copied().unwrap_or_default();
+                let max_budget = max_host_budgets.get(host).copied().unwrap_or(u64::MAX);
 
-                (*host, host_budget.min(num_pages as u64))
+                // seeded hosts that are not in the page graph get their homepage crawled
+                let (host_budget, num_pages) = if seed_hosts.contains(host) {
+                    (host_budget.max(config.seed_budget), num_pages.max(1))
+                } else {
+                    (host_budget, num_pages)
+                };
+
+                (*host, host_budget.min(num_pages as u64).min(max_budget))
             })
             .collect();
 
@@ -181,6 +280,10 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
                     break;
                 }
 
+                if max_host_budgets.contains_key(host) {
+                    continue;
+                }
+
                 let num_pages = host_pages.get(host).copied().unwrap_or_default();
                 let host_budget = host_budgets.get_mut(host).unwrap();
 
@@ -232,6 +335,18 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
                     total_known_urls += pages.len();
 
                     if pages.is_empty() {
+                        let has_budget = host_budgets.get(host).is_some_and(|budget| *budget > 0);
+
+                        if seed_hosts.contains(host) && has_budget {
+                            if let Some(url) = host_graph
+                                .id2node(host)
+                                .and_then(|n| Url::parse(&format!("http://{}", n.name)).ok())
+                            {
+                                urls.push_back(WeightedUrl { url, weight: 0.0 });
+                                total_scheduled_urls += 1;
+                            }
+                        }
+
                         continue;
                     }
 
@@ -262,6 +377,7 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
                             .filter_map(|(n, score)| {
                                 Url::parse(&format!("http://{n}")).ok().map(|u| (u, score))
                             })
+                            .filter(|(url, _)| !is_trapped(&trapped, url))
                             .map(|(url, score)| WeightedUrl { url, weight: score })
                             .take(schedule_budget as usize),
                     );
@@ -0,0 +1,136 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Guards for requests to urls from untrusted sources, such as the key files of
+//! IndexNow submissions or the hubs advertised by feeds. The hosts must be domains
+//! that resolve to public addresses, so they can't be used to make us send
+//! requests to the services in our own network.
+
+use std::net::{IpAddr, SocketAddr};
+
+use url::{Host, Url};
+
+/// Whether the address can be reached from the public internet.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || a == 0
+                // shared address space of carrier-grade NATs
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    // unique local addresses
+                    || (first & 0xfe00) == 0xfc00
+                    // link local addresses
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Only urls on domains are fetched, since ip literals
+/// are not resolved by the [`PublicResolver`].
+pub fn is_fetchable(url: &Url) -> bool {
+    let Some(Host::Domain(domain)) = url.host() else {
+        return false;
+    };
+
+    matches!(url.scheme(), "http" | "https")
+        && domain != "localhost"
+        && !domain.ends_with(".localhost")
+}
+
+/// Resolves hosts to their public addresses only.
+pub struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+
+            if addrs.is_empty() {
+                return Err(
+                    format!("{} does not resolve to a public address", name.as_str()).into(),
+                );
+            }
+
+            let addrs: reqwest::dns::Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_addresses() {
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn fetchable_urls() {
+        assert!(is_fetchable(
+            &Url::parse("https://www.example.com/key.txt").unwrap()
+        ));
+
+        for url in [
+            "https://127.0.0.1/",
+            "https://[::1]/",
+            "http://localhost/",
+            "http://hub.localhost/",
+            "ftp://www.example.com/",
+        ] {
+            assert!(!is_fetchable(&Url::parse(url).unwrap()), "{url}");
+        }
+    }
+}
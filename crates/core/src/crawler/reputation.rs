@@ -0,0 +1,367 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Reputation of hosts based on sustained crawl errors.
+//!
+//! Workers stop crawling a host within a job after a streak of server errors or
+//! timeouts, and log the outcome for each host they crawled. The outcomes are folded
+//! into a [`HostReputationStore`] where a host is demoted once its error streak spans
+//! enough urls. Demoted hosts are not crawled until they are due for a probe, where
+//! they only get a small budget to detect if they have recovered. The interval between
+//! probes doubles every time a probe fails.
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    config::HostReputationConfig,
+    host_policy::HostDirectives,
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    webpage::url_ext::UrlExt,
+};
+
+use super::Result;
+
+/// Server errors and failed requests, such as timeouts, count towards the error streak of
+/// a host. Client errors like 404 only say something about the url and not the host.
+pub fn is_host_error(status_code: Option<u16>) -> bool {
+    status_code.map(|code| code >= 500).unwrap_or(true)
+}
+
+/// The urls crawled for a host in a single job. The host is normalized
+/// the same way as the names of the nodes in the host graph.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostOutcome {
+    pub host: String,
+    /// Unix timestamp in milliseconds of when the job finished.
+    pub timestamp: u64,
+    pub successes: u64,
+    pub errors: u64,
+    #[serde(default)]
+    pub directives: HostDirectives,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReputationStatus {
+    Healthy,
+    /// The host should not be crawled.
+    Demoted,
+    /// The host is demoted but due for a probe to see if it has recovered.
+    Probe,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostReputation {
+    pub error_streak: u64,
+    pub failed_probes: u32,
+    /// Unix timestamp in milliseconds.
+    pub demoted_until: Option<u64>,
+    /// Timestamp of the last outcome that was recorded.
+    pub last_update: u64,
+}
+
+impl HostReputation {
+    /// Update the reputation with the outcome of a job. Outcomes that are not newer
+    /// than the last recorded outcome are ignored, so the same log can be folded
+    /// into the reputation more than once.
+    pub fn record(&mut self, outcome: &HostOutcome, config: &HostReputationConfig) {
+        if outcome.timestamp <= self.last_update {
+            return;
+        }
+
+        self.last_update = outcome.timestamp;
+
+        if outcome.successes > 0 {
+            self.error_streak = 0;
+            self.failed_probes = 0;
+            self.demoted_until = None;
+            return;
+        }
+
+        self.error_streak += outcome.errors;
+
+        if self.error_streak >= config.max_error_streak {
+            if self.demoted_until.is_some() {
+                self.failed_probes += 1;
+            }
+
+            let interval_ms = (config.base_probe_interval_sec * 1000)
+                .saturating_mul(1 << self.failed_probes.min(32))
+                .min(config.max_probe_interval_sec * 1000);
+
+            self.demoted_until = Some(outcome.timestamp + interval_ms);
+        }
+    }
+
+    pub fn status(&self, now_ms: u64) -> ReputationStatus {
+        match self.demoted_until {
+            None => ReputationStatus::Healthy,
+            Some(until) if now_ms < until => ReputationStatus::Demoted,
+            Some(_) => ReputationStatus::Probe,
+        }
+    }
+}
+
+pub fn now_ms() -> u64 {
+    chrono::Utc::now().timestamp_millis().max(0) as u64
+}
+
+pub fn read_outcomes<P: AsRef<Path>>(path: P) -> Result<Vec<HostOutcome>> {
+    let file = File::open(path)?;
+    let mut outcomes = Vec::new();
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        outcomes.push(serde_json::from_str(&line)?);
+    }
+
+    Ok(outcomes)
+}
+
+/// Log of host outcomes shared by the worker threads.
+pub struct HostOutcomeLog {
+    file: Mutex<File>,
+}
+
+impl HostOutcomeLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn append(&self, outcomes: &[HostOutcome]) -> Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+
+        for outcome in outcomes {
+            writeln!(file, "{}", serde_json::to_string(outcome)?)?;
+        }
+
+        file.flush()?;
+
+        Ok(())
+    }
+}
+
+pub struct HostReputationStore {
+    store: RocksDbStore<String, HostReputation>,
+    config: HostReputationConfig,
+}
+
+impl HostReputationStore {
+    pub fn open(config: HostReputationConfig) -> Self {
+        Self {
+            store: RocksDbStore::open(&config.store_path),
+            config,
+        }
+    }
+
+    pub fn get(&self, host: &str) -> Option<HostReputation> {
+        self.store.get(&host.to_string())
+    }
+
+    pub fn status(&self, host: &str, now_ms: u64) -> ReputationStatus {
+        self.get(host)
+            .map(|reputation| reputation.status(now_ms))
+            .unwrap_or(ReputationStatus::Healthy)
+    }
+
+    /// The maximum number of urls that should be crawled for the host,
+    /// or `None` if the host has no limit.
+    pub fn max_budget(&self, host: &str, now_ms: u64) -> Option<u64> {
+        match self.status(host, now_ms) {
+            ReputationStatus::Healthy => None,
+            ReputationStatus::Demoted => Some(0),
+            ReputationStatus::Probe => Some(self.config.probe_budget),
+        }
+    }
+
+    /// Drop the urls of the demoted hosts, and the urls of the hosts
+    /// that are due for a probe beyond their probe budget.
+    pub fn within_budget(&self, urls: Vec<Url>, now_ms: u64) -> Vec<Url> {
+        let mut budgets: HashMap<String, Option<u64>> = HashMap::new();
+
+        urls.into_iter()
+            .filter(|url| {
+                let host = url.normalized_host().unwrap_or_default();
+                let budget = budgets
+                    .entry(host.to_string())
+                    .or_insert_with(|| self.max_budget(host, now_ms));
+
+                match budget {
+                    None => true,
+                    Some(0) => false,
+                    Some(remaining) => {
+                        *remaining -= 1;
+                        true
+                    }
+                }
+            })
+            .collect()
+    }
+
+    pub fn record(&self, outcomes: impl IntoIterator<Item = HostOutcome>) {
+        for outcome in outcomes {
+            let mut reputation = self.get(&outcome.host).unwrap_or_default();
+            let was_demoted = reputation.demoted_until.is_some();
+
+            reputation.record(&outcome, &self.config);
+
+            if !was_demoted && reputation.demoted_until.is_some() {
+                tracing::info!(
+                    "demoting {} after {} errors",
+                    outcome.host,
+                    reputation.error_streak
+                );
+            } else if was_demoted && reputation.demoted_until.is_none() {
+                tracing::info!("{} has recovered", outcome.host);
+            }
+
+            self.store.insert(outcome.host, reputation);
+        }
+    }
+
+    pub fn flush(&self) {
+        self.store.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_MS: u64 = 60 * 60 * 1000;
+
+    fn config() -> HostReputationConfig {
+        HostReputationConfig {
+            store_path: crate::gen_temp_path().to_str().unwrap().to_string(),
+            max_error_streak: 10,
+            base_probe_interval_sec: 60 * 60,
+            max_probe_interval_sec: 4 * 60 * 60,
+            probe_budget: 2,
+        }
+    }
+
+    fn outcome(timestamp: u64, successes: u64, errors: u64) -> HostOutcome {
+        HostOutcome {
+            host: "example.com".to_string(),
+            timestamp,
+            successes,
+            errors,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn host_errors() {
+        assert!(is_host_error(None));
+        assert!(is_host_error(Some(503)));
+        assert!(!is_host_error(Some(404)));
+        assert!(!is_host_error(Some(200)));
+    }
+
+    #[test]
+    fn exponential_probing() {
+        let config = config();
+        let mut reputation = HostReputation::default();
+
+        reputation.record(&outcome(1, 0, 5), &config);
+        assert_eq!(reputation.status(1), ReputationStatus::Healthy);
+
+        // the streak continues across jobs
+        reputation.record(&outcome(2, 0, 5), &config);
+        assert_eq!(reputation.status(2), ReputationStatus::Demoted);
+        assert_eq!(reputation.status(2 + HOUR_MS), ReputationStatus::Probe);
+
+        // failed probes double the interval until the maximum is reached
+        let mut now = 2 + HOUR_MS;
+        for expected_interval in [2, 4, 4] {
+            reputation.record(&outcome(now, 0, 1), &config);
+            assert_eq!(
+                reputation.demoted_until,
+                Some(now + expected_interval * HOUR_MS)
+            );
+            now += expected_interval * HOUR_MS;
+        }
+
+        // old outcomes are ignored
+        reputation.record(&outcome(3, 1, 0), &config);
+        assert_eq!(reputation.status(3), ReputationStatus::Demoted);
+
+        reputation.record(&outcome(now, 1, 0), &config);
+        assert_eq!(reputation.status(now), ReputationStatus::Healthy);
+        assert_eq!(reputation.error_streak, 0);
+        assert_eq!(reputation.failed_probes, 0);
+    }
+
+    #[test]
+    fn store() {
+        let store = HostReputationStore::open(config());
+
+        assert_eq!(store.max_budget("example.com", 0), None);
+
+        store.record([outcome(1, 0, 10)]);
+        assert_eq!(store.max_budget("example.com", 1), Some(0));
+        assert_eq!(store.max_budget("example.com", 1 + HOUR_MS), Some(2));
+        assert_eq!(store.max_budget("other.com", 1), None);
+
+        let urls: Vec<_> = [
+            "https://example.com/1",
+            "https://www.example.com/2",
+            "https://example.com/3",
+            "https://other.com/1",
+        ]
+        .into_iter()
+        .map(|url| Url::parse(url).unwrap())
+        .collect();
+
+        assert_eq!(
+            store
+                .within_budget(urls.clone(), 1)
+                .iter()
+                .map(Url::as_str)
+                .collect::<Vec<_>>(),
+            vec!["https://other.com/1"]
+        );
+        assert_eq!(
+            store
+                .within_budget(urls, 1 + HOUR_MS)
+                .iter()
+                .map(Url::as_str)
+                .collect::<Vec<_>>(),
+            vec![
+                "https://example.com/1",
+                "https://www.example.com/2",
+                "https://other.com/1"
+            ]
+        );
+    }
+}
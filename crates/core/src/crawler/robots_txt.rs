@@ -14,7 +14,11 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{collections::BTreeMap, panic, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    panic,
+    time::Duration,
+};
 
 use robotstxt_with_cache::matcher::{
     CachingRobotsMatcher, LongestMatchRobotsMatchStrategy, RobotsMatcher,
@@ -146,12 +150,69 @@ impl RobotsTxtManager {
             Lookup::NotFound => None,
         }
     }
+
+    pub async fn crawl_delay(&mut self, url: &Url, user_agent: &str) -> Option<Duration> {
+        match self.get_mut(url).await {
+            Lookup::Found(robotstxt) => robotstxt.crawl_delay(user_agent),
+            Lookup::NotFound => None,
+        }
+    }
+}
+
+/// The crawl-delay of each user-agent group in robots.txt, keyed by the lowercase user-agent.
+/// Groups without a crawl-delay are kept, so they are not overridden by the `*` group.
+fn parse_crawl_delays(body: &str) -> HashMap<String, Option<Duration>> {
+    let mut delays = HashMap::new();
+    let mut agents: Vec<String> = Vec::new();
+    let mut in_rules = false;
+
+    for line in body.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+
+        match key.trim().to_ascii_lowercase().as_str() {
+            "user-agent" => {
+                // consecutive user-agent lines share the rules that follow them
+                if in_rules {
+                    agents.clear();
+                    in_rules = false;
+                }
+
+                let agent = value.trim().to_ascii_lowercase();
+                delays.entry(agent.clone()).or_insert(None);
+                agents.push(agent);
+            }
+            "crawl-delay" => {
+                in_rules = true;
+
+                let delay = value
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                    .map(Duration::from_secs_f64);
+
+                for agent in &agents {
+                    if let Some(existing) = delays.get_mut(agent) {
+                        *existing = existing.or(delay);
+                    }
+                }
+            }
+            _ => in_rules = true,
+        }
+    }
+
+    delays
 }
 
 struct RobotsTxt {
     download_time: std::time::Instant,
     matcher: CachingRobotsMatcher<LongestMatchRobotsMatchStrategy>,
     sitemap: Option<Url>,
+    crawl_delays: HashMap<String, Option<Duration>>,
 }
 
 impl RobotsTxt {
@@ -159,6 +220,7 @@ impl RobotsTxt {
         let mut s = Self {
             matcher: CachingRobotsMatcher::new(RobotsMatcher::default()),
             sitemap: None,
+            crawl_delays: HashMap::new(),
             download_time: std::time::Instant::now(),
         };
 
@@ -171,6 +233,15 @@ impl RobotsTxt {
         self.download_time.elapsed() > *expiration
     }
 
+    /// The crawl-delay of the group that matches the user agent, or of the `*` group
+    /// if no group matches it.
+    fn crawl_delay(&self, user_agent: &str) -> Option<Duration> {
+        match self.crawl_delays.get(&user_agent.to_ascii_lowercase()) {
+            Some(delay) => *delay,
+            None => self.crawl_delays.get("*").copied().flatten(),
+        }
+    }
+
     fn update(&mut self, body: String) {
         self.matcher.parse(&body.to_lowercase());
 
@@ -181,6 +252,8 @@ impl RobotsTxt {
             .map(|line| line.split(':').nth(1).unwrap().trim())
             .and_then(|s| Url::parse(s).ok());
 
+        self.crawl_delays = parse_crawl_delays(&body);
+
         self.download_time = std::time::Instant::now();
     }
 }
@@ -222,4 +295,65 @@ mod tests {
             .matcher
             .one_agent_allowed_by_robots(ua_token, "http://example.com/example"));
     }
+
+    #[test]
+    fn crawl_delay() {
+        let robots_txt = RobotsTxt::new(
+            r#"User-agent: *
+            Crawl-delay: 2.5
+            Disallow: /test"#
+                .to_string(),
+        );
+
+        assert_eq!(
+            robots_txt.crawl_delay("StractBot"),
+            Some(Duration::from_millis(2_500))
+        );
+
+        let robots_txt = RobotsTxt::new(
+            r#"User-agent: *
+            Crawl-delay: soon"#
+                .to_string(),
+        );
+
+        assert_eq!(robots_txt.crawl_delay("StractBot"), None);
+    }
+
+    #[test]
+    fn crawl_delay_of_matching_group() {
+        let robots_txt = RobotsTxt::new(
+            r#"User-agent: Googlebot
+            Crawl-delay: 30
+
+            User-agent: Bingbot
+            User-agent: StractBot
+            Disallow: /private
+            Crawl-delay: 5
+
+            User-agent: *
+            Crawl-delay: 10"#
+                .to_string(),
+        );
+
+        assert_eq!(
+            robots_txt.crawl_delay("StractBot"),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            robots_txt.crawl_delay("OtherBot"),
+            Some(Duration::from_secs(10))
+        );
+
+        // the matching group has no crawl-delay, so the delay of the * group does not apply
+        let robots_txt = RobotsTxt::new(
+            r#"User-agent: *
+            Crawl-delay: 10
+
+            User-agent: stractbot
+            Disallow: /private"#
+                .to_string(),
+        );
+
+        assert_eq!(robots_txt.crawl_delay("StractBot"), None);
+    }
 }
@@ -11,7 +11,8 @@ use crate::{
 use super::Job;
 
 struct RemoteCoordinator {
-    addr: SocketAddr,
+    /// All the resolved addresses of the coordinator.
+    addrs: Vec<SocketAddr>,
 }
 
 impl RemoteCoordinator {
@@ -19,7 +20,7 @@ impl RemoteCoordinator {
         let retry = ExponentialBackoff::from_millis(1_000).with_limit(Duration::from_secs(10));
 
         Ok(sonic::service::ResilientConnection::create_with_timeout(
-            self.addr,
+            &self.addrs,
             Duration::from_secs(60),
             retry,
         )
@@ -42,11 +43,11 @@ struct InnerRouter {
 }
 
 impl InnerRouter {
-    async fn new(coordinator_addrs: Vec<SocketAddr>) -> Result<Self> {
+    async fn new(coordinator_addrs: Vec<Vec<SocketAddr>>) -> Result<Self> {
         Ok(Self {
             coordinators: coordinator_addrs
                 .into_iter()
-                .map(|addr| RemoteCoordinator { addr })
+                .map(|addrs| RemoteCoordinator { addrs })
                 .collect(),
         })
     }
@@ -72,7 +73,7 @@ pub struct Router {
 }
 
 impl Router {
-    pub async fn new(coordinator_addrs: Vec<SocketAddr>) -> Result<Self> {
+    pub async fn new(coordinator_addrs: Vec<Vec<SocketAddr>>) -> Result<Self> {
         Ok(Self {
             inner: Mutex::new(InnerRouter::new(coordinator_addrs).await?),
         })
@@ -0,0 +1,208 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Capture of screenshot thumbnails of the fetched pages. The crawler does not render
+//! pages itself, so the pages are rendered by an external service behind [`Renderer`].
+
+use std::{sync::Arc, time::Duration};
+
+use futures::{future::BoxFuture, FutureExt};
+use tokio::sync::Semaphore;
+use url::Url;
+
+use crate::{
+    config::ScreenshotConfig,
+    screenshot_store::{self, Quotas, Screenshot, ScreenshotStore},
+};
+
+use super::Result;
+
+/// The number of pages that are rendered at the same time. Pages are not
+/// captured while this many renders are in flight.
+const MAX_CONCURRENT_RENDERS: usize = 16;
+
+pub trait Renderer: Send + Sync {
+    /// Render the page and return it as an image.
+    fn render<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<Vec<u8>>>;
+}
+
+/// Renders the pages with a GET request to a render service.
+pub struct HttpRenderer {
+    client: reqwest::Client,
+    service_url: Url,
+}
+
+impl HttpRenderer {
+    pub fn new(service_url: &str, timeout: Duration) -> Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+            service_url: Url::parse(service_url)?,
+        })
+    }
+}
+
+impl Renderer for HttpRenderer {
+    fn render<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<Vec<u8>>> {
+        async move {
+            let mut request = self.service_url.clone();
+            request.query_pairs_mut().append_pair("url", url.as_str());
+
+            let res = self.client.get(request).send().await?.error_for_status()?;
+
+            Ok(res.bytes().await?.to_vec())
+        }
+        .boxed()
+    }
+}
+
+pub struct Screenshotter {
+    renderer: Arc<dyn Renderer>,
+    store: Arc<ScreenshotStore>,
+    config: ScreenshotConfig,
+    renders: Arc<Semaphore>,
+}
+
+impl Screenshotter {
+    pub fn open(config: &ScreenshotConfig) -> Result<Self> {
+        let renderer = HttpRenderer::new(
+            &config.render_service_url,
+            Duration::from_secs(config.render_timeout_sec),
+        )?;
+
+        Ok(Self::new(Arc::new(renderer), config.clone()))
+    }
+
+    pub fn new(renderer: Arc<dyn Renderer>, config: ScreenshotConfig) -> Self {
+        let store = ScreenshotStore::open(&config.store_path, Quotas::from(&config));
+
+        Self {
+            renderer,
+            store: Arc::new(store),
+            config,
+            renders: Arc::new(Semaphore::new(MAX_CONCURRENT_RENDERS)),
+        }
+    }
+
+    /// Hosts opt out by disallowing this user agent in their robots.txt.
+    pub fn user_agent_token(&self) -> &str {
+        &self.config.user_agent_token
+    }
+
+    /// Render the page in the background and store its thumbnail. The page
+    /// is skipped if too many pages are being rendered already.
+    pub fn capture(&self, url: Url, generation: u64) {
+        let Ok(permit) = Arc::clone(&self.renders).try_acquire_owned() else {
+            return;
+        };
+
+        let renderer = Arc::clone(&self.renderer);
+        let store = Arc::clone(&self.store);
+        let config = self.config.clone();
+
+        tokio::spawn(async move {
+            let _permit = permit;
+
+            if let Err(err) = capture(renderer.as_ref(), store, &config, url, generation).await {
+                tracing::debug!("failed to capture screenshot: {}", err);
+            }
+        });
+    }
+
+    pub fn flush(&self) {
+        self.store.flush();
+    }
+}
+
+async fn capture(
+    renderer: &dyn Renderer,
+    store: Arc<ScreenshotStore>,
+    config: &ScreenshotConfig,
+    url: Url,
+    generation: u64,
+) -> Result<()> {
+    let image = renderer.render(&url).await?;
+    let (width, height, quality) = (
+        config.thumbnail_width,
+        config.thumbnail_height,
+        config.jpeg_quality,
+    );
+
+    tokio::task::spawn_blocking(move || {
+        let jpeg = screenshot_store::thumbnail(&image, width, height, quality)?;
+        store.insert(url.as_str(), Screenshot { generation, jpeg })?;
+
+        Ok(())
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::config::defaults;
+
+    struct SolidRenderer;
+
+    impl Renderer for SolidRenderer {
+        fn render<'a>(&'a self, _: &'a Url) -> BoxFuture<'a, Result<Vec<u8>>> {
+            async move {
+                let image = image::RgbImage::from_pixel(1024, 768, image::Rgb([0, 0, 255]));
+                let mut png = Vec::new();
+                image.write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)?;
+
+                Ok(png)
+            }
+            .boxed()
+        }
+    }
+
+    fn config() -> ScreenshotConfig {
+        ScreenshotConfig {
+            render_service_url: "http://localhost/render".to_string(),
+            store_path: crate::gen_temp_path().to_str().unwrap().to_string(),
+            user_agent_token: defaults::Screenshot::user_agent_token(),
+            render_timeout_sec: defaults::Screenshot::render_timeout_sec(),
+            thumbnail_width: defaults::Screenshot::thumbnail_width(),
+            thumbnail_height: defaults::Screenshot::thumbnail_height(),
+            jpeg_quality: defaults::Screenshot::jpeg_quality(),
+            max_thumbnail_bytes: defaults::Screenshot::max_thumbnail_bytes(),
+            max_host_bytes: defaults::Screenshot::max_host_bytes(),
+            max_total_bytes: defaults::Screenshot::max_total_bytes(),
+        }
+    }
+
+    #[tokio::test]
+    async fn captured_thumbnails_are_stored() {
+        let config = config();
+        let store = Arc::new(ScreenshotStore::open(
+            &config.store_path,
+            Quotas::from(&config),
+        ));
+        let url = Url::parse("https://example.com/").unwrap();
+
+        capture(&SolidRenderer, Arc::clone(&store), &config, url, 1)
+            .await
+            .unwrap();
+
+        let screenshot = store.get("https://example.com/").unwrap();
+        let thumbnail = image::load_from_memory(&screenshot.jpeg).unwrap();
+
+        assert_eq!(screenshot.generation, 1);
+        assert_eq!((thumbnail.width(), thumbnail.height()), (320, 240));
+    }
+}
@@ -0,0 +1,305 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Crawl seeds selected from the webgraph.
+//!
+//! The crawl planner only plans the hosts with the highest centrality, so hosts that
+//! are important but mostly missing from the index, and hosts that were recently
+//! discovered through links, can go a long time without being crawled. The seeder finds
+//! these hosts and writes them to a seed file that the planner schedules together with
+//! the top hosts.
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::CrawlSeederConfig,
+    host_stats::HostStatsStore,
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    webgraph::{
+        centrality::{top_hosts, TopHosts},
+        NodeID, Webgraph,
+    },
+    Result,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeedReason {
+    /// A host with high centrality where only few of the known pages are in the index.
+    LowCoverage,
+    /// A host that is not in the index but is linked to from many other hosts.
+    NewHost,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Seed {
+    pub host: String,
+    pub reason: SeedReason,
+    /// Seeds with higher scores are selected first.
+    pub score: f64,
+}
+
+/// The fraction of the known pages of a host that are in the index.
+fn coverage(indexed_pages: u64, known_pages: u64) -> f64 {
+    if known_pages == 0 {
+        return 1.0;
+    }
+
+    (indexed_pages as f64 / known_pages as f64).min(1.0)
+}
+
+/// The number of other hosts that link to the host.
+fn linking_hosts(host_graph: &Webgraph, host: &NodeID) -> usize {
+    host_graph
+        .raw_ingoing_edges(host)
+        .into_iter()
+        .map(|edge| edge.from)
+        .filter(|from| from != host)
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+/// Keep the `max_seeds` seeds with the highest scores.
+fn top_seeds(mut seeds: Vec<Seed>, max_seeds: usize) -> Vec<Seed> {
+    seeds.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.host.cmp(&b.host)));
+    seeds.truncate(max_seeds);
+    seeds
+}
+
+/// Hosts among the top hosts where the index covers less than `max_coverage`
+/// of the known pages. The score is the centrality of the host weighted by
+/// the fraction of its pages that are missing from the index.
+fn low_coverage_seeds(
+    host_centrality: &RocksDbStore<NodeID, f64>,
+    host_graph: &Webgraph,
+    page_graph: &Webgraph,
+    host_stats: &HostStatsStore,
+    config: &CrawlSeederConfig,
+) -> Vec<Seed> {
+    let hosts = top_hosts(
+        host_centrality,
+        TopHosts::Fraction(config.top_host_fraction),
+    );
+    let num_hosts = hosts.len() as u64;
+
+    let seeds = hosts
+        .into_par_iter()
+        .progress_count(num_hosts)
+        .filter_map(|host| {
+            let known_pages = page_graph.pages_by_host(&host).len() as u64;
+            let indexed_pages = host_stats
+                .get(&host)
+                .map(|stats| stats.num_pages)
+                .unwrap_or_default();
+            let coverage = coverage(indexed_pages, known_pages);
+
+            if coverage >= config.max_coverage {
+                return None;
+            }
+
+            let centrality = host_centrality.get(&host).unwrap_or_default();
+            let node = host_graph.id2node(&host)?;
+
+            Some(Seed {
+                host: node.name,
+                reason: SeedReason::LowCoverage,
+                score: centrality * (1.0 - coverage),
+            })
+        })
+        .collect();
+
+    top_seeds(seeds, config.max_seeds)
+}
+
+/// Hosts that are not in the index and have links from at least
+/// `min_linking_hosts` other hosts. The score is the number of linking hosts.
+fn new_host_seeds(
+    host_graph: &Webgraph,
+    host_stats: &HostStatsStore,
+    config: &CrawlSeederConfig,
+) -> Vec<Seed> {
+    let hosts: Vec<_> = host_graph
+        .nodes()
+        .filter(|host| host_stats.get(host).is_none())
+        .collect();
+    let num_hosts = hosts.len() as u64;
+
+    let seeds = hosts
+        .into_par_iter()
+        .progress_count(num_hosts)
+        .filter_map(|host| {
+            let linking_hosts = linking_hosts(host_graph, &host);
+
+            if linking_hosts < config.min_linking_hosts {
+                return None;
+            }
+
+            let node = host_graph.id2node(&host)?;
+
+            Some(Seed {
+                host: node.name,
+                reason: SeedReason::NewHost,
+                score: linking_hosts as f64,
+            })
+        })
+        .collect();
+
+    top_seeds(seeds, config.max_seeds)
+}
+
+pub fn select_seeds(
+    host_centrality: &RocksDbStore<NodeID, f64>,
+    host_graph: &Webgraph,
+    page_graph: &Webgraph,
+    host_stats: &HostStatsStore,
+    config: &CrawlSeederConfig,
+) -> Result<Vec<Seed>> {
+    if !(0.0..=1.0).contains(&config.top_host_fraction) {
+        return Err(anyhow::anyhow!(
+            "top_host_fraction must be in range [0.0, 1.0]"
+        ));
+    }
+
+    if !(0.0..=1.0).contains(&config.max_coverage) {
+        return Err(anyhow::anyhow!("max_coverage must be in range [0.0, 1.0]"));
+    }
+
+    let mut seeds = low_coverage_seeds(host_centrality, host_graph, page_graph, host_stats, config);
+    tracing::info!("found {} hosts with low index coverage", seeds.len());
+
+    let new_hosts = new_host_seeds(host_graph, host_stats, config);
+    tracing::info!("found {} new hosts", new_hosts.len());
+
+    seeds.extend(new_hosts);
+
+    Ok(seeds)
+}
+
+pub fn write_seeds<P: AsRef<Path>>(path: P, seeds: &[Seed]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    for seed in seeds {
+        serde_json::to_writer(&mut writer, seed)?;
+        writer.write_all(b"\n")?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+pub fn read_seeds<P: AsRef<Path>>(path: P) -> Result<Vec<Seed>> {
+    let file = File::open(path)?;
+    let mut seeds = Vec::new();
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        seeds.push(serde_json::from_str(&line)?);
+    }
+
+    Ok(seeds)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::webgraph::{Node, WebgraphWriter};
+
+    use super::*;
+
+    fn seed(host: &str, score: f64) -> Seed {
+        Seed {
+            host: host.to_string(),
+            reason: SeedReason::NewHost,
+            score,
+        }
+    }
+
+    #[test]
+    fn coverage_of_hosts() {
+        assert_eq!(coverage(5, 10), 0.5);
+        assert_eq!(coverage(0, 10), 0.0);
+        // pages can be indexed without being in the page graph
+        assert_eq!(coverage(20, 10), 1.0);
+        assert_eq!(coverage(0, 0), 1.0);
+    }
+
+    #[test]
+    fn top_seeds_by_score() {
+        let seeds = top_seeds(
+            vec![seed("b.com", 1.0), seed("a.com", 3.0), seed("c.com", 1.0)],
+            2,
+        );
+
+        assert_eq!(seeds, vec![seed("a.com", 3.0), seed("b.com", 1.0)]);
+    }
+
+    #[test]
+    fn count_linking_hosts() {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        for (from, to) in [
+            ("a.com", "new.com"),
+            ("b.com", "new.com"),
+            ("c.com", "new.com"),
+            ("new.com", "new.com"),
+            ("a.com", "b.com"),
+        ] {
+            writer.insert(Node::from(from), Node::from(to), String::new());
+        }
+
+        let graph = writer.finalize();
+
+        assert_eq!(linking_hosts(&graph, &Node::from("new.com").id()), 3);
+        assert_eq!(linking_hosts(&graph, &Node::from("b.com").id()), 1);
+        assert_eq!(linking_hosts(&graph, &Node::from("a.com").id()), 0);
+    }
+
+    #[test]
+    fn write_and_read() {
+        let path = crate::gen_temp_path().join("seeds.jsonl");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let seeds = vec![
+            Seed {
+                host: "a.com".to_string(),
+                reason: SeedReason::LowCoverage,
+                score: 0.5,
+            },
+            seed("b.com", 3.0),
+        ];
+
+        write_seeds(&path, &seeds).unwrap();
+        assert_eq!(read_seeds(&path).unwrap(), seeds);
+    }
+}
@@ -0,0 +1,617 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Detection of crawler traps such as infinite calendars, session ids in urls and
+//! faceted navigation where every combination of filters is a new url.
+//!
+//! Urls are clustered by a [`UrlTemplate`] where the variable parts of the path and the
+//! values of the query parameters are replaced by placeholders. When a job discovers more
+//! distinct urls for a template than allowed, a [`SuppressionRule`] is generated for the
+//! template and no more urls matching it are crawled in the job. Generated rules are
+//! appended to a log where they can be reviewed, and the approved rules are applied by
+//! the planner and the workers to all future jobs.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use hashbrown::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::config::TrapDetectionConfig;
+
+use super::Result;
+
+/// Query parameters that are commonly used for session ids.
+const SESSION_PARAMS: [&str; 9] = [
+    "sid",
+    "sessid",
+    "sessionid",
+    "session_id",
+    "phpsessid",
+    "jsessionid",
+    "aspsessionid",
+    "cfid",
+    "cftoken",
+];
+
+/// Query parameters that are commonly used to navigate calendars.
+const CALENDAR_PARAMS: [&str; 7] = ["date", "day", "week", "month", "year", "cal", "calendar"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrapKind {
+    Calendar,
+    SessionId,
+    FacetedNavigation,
+    RepeatedPath,
+    ExplodingPath,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleStatus {
+    #[default]
+    Pending,
+    Approved,
+    Rejected,
+}
+
+fn is_year(segment: &str) -> bool {
+    segment.len() == 4
+        && segment
+            .parse::<u32>()
+            .map(|year| (1900..=2100).contains(&year))
+            .unwrap_or(false)
+}
+
+fn is_date(segment: &str) -> bool {
+    let parts: Vec<_> = segment.split(['-', '_']).collect();
+
+    parts.len() >= 2
+        && parts.len() <= 3
+        && is_year(parts[0])
+        && parts[1..]
+            .iter()
+            .all(|part| (1..=2).contains(&part.len()) && part.parse::<u32>().is_ok())
+}
+
+fn is_id(segment: &str) -> bool {
+    segment.len() >= 16
+        && segment.chars().any(|c| c.is_ascii_digit())
+        && segment.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+}
+
+fn normalize_segment(segment: &str) -> &str {
+    if is_year(segment) {
+        "{year}"
+    } else if is_date(segment) {
+        "{date}"
+    } else if !segment.is_empty() && segment.chars().all(|c| c.is_ascii_digit()) {
+        "{num}"
+    } else if is_id(segment) {
+        "{id}"
+    } else {
+        segment
+    }
+}
+
+fn path_template(url: &Url) -> String {
+    let mut res = url.host_str().unwrap_or_default().to_string();
+
+    for segment in url.path_segments().into_iter().flatten() {
+        let mut parts = segment.split(';');
+
+        res.push('/');
+        res.push_str(normalize_segment(parts.next().unwrap_or_default()));
+
+        // matrix parameters such as `;jsessionid=...`
+        for param in parts {
+            let key = param.split('=').next().unwrap_or_default();
+            res.push_str(&format!(";{}=*", key.to_lowercase()));
+        }
+    }
+
+    res
+}
+
+fn query_keys(url: &Url) -> Vec<String> {
+    let mut keys: Vec<_> = url
+        .query_pairs()
+        .map(|(key, _)| key.to_lowercase())
+        .collect();
+
+    keys.sort();
+    keys.dedup();
+
+    keys
+}
+
+fn has_session_id(url: &Url) -> bool {
+    url.path().to_lowercase().contains("sessionid=")
+        || query_keys(url)
+            .iter()
+            .any(|key| SESSION_PARAMS.contains(&key.as_str()))
+}
+
+fn has_repeated_segments(url: &Url, max_repeats: usize) -> bool {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+
+    for segment in url.path_segments().into_iter().flatten() {
+        if segment.is_empty() {
+            continue;
+        }
+
+        let count = counts.entry(segment).or_default();
+        *count += 1;
+
+        if *count > max_repeats {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// The shape of a url, e.g. `example.com/events/{year}/{num}?view=*` for
+/// `https://example.com/events/2024/05?view=month`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct UrlTemplate(String);
+
+impl UrlTemplate {
+    pub fn new(url: &Url) -> Self {
+        let mut res = path_template(url);
+        let keys = query_keys(url);
+
+        if !keys.is_empty() {
+            res.push('?');
+            res.push_str(
+                &keys
+                    .iter()
+                    .map(|key| format!("{key}=*"))
+                    .collect::<Vec<_>>()
+                    .join("&"),
+            );
+        }
+
+        Self(res)
+    }
+
+    /// The template of the url where any query matches. This clusters all the
+    /// combinations of parameters used by faceted navigation on the same path.
+    pub fn with_any_query(url: &Url) -> Option<Self> {
+        url.query()
+            .filter(|query| !query.is_empty())
+            .map(|_| Self(format!("{}?*", path_template(url))))
+    }
+
+    /// All the templates the url belongs to.
+    pub fn all(url: &Url) -> impl Iterator<Item = Self> {
+        std::iter::once(Self::new(url)).chain(Self::with_any_query(url))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn host(&self) -> &str {
+        self.0.split(['/', '?']).next().unwrap_or_default()
+    }
+
+    fn kind(&self) -> TrapKind {
+        let (path, query) = self.0.split_once('?').unwrap_or((self.0.as_str(), ""));
+
+        let calendar_query = query
+            .split('&')
+            .filter_map(|param| param.split('=').next())
+            .any(|key| CALENDAR_PARAMS.contains(&key));
+
+        if path.contains("{year}") || path.contains("{date}") || calendar_query {
+            TrapKind::Calendar
+        } else if !query.is_empty() {
+            TrapKind::FacetedNavigation
+        } else {
+            TrapKind::ExplodingPath
+        }
+    }
+}
+
+impl std::fmt::Display for UrlTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Returns true if any of the templates of the url is in `templates`.
+pub fn is_trapped(templates: &HashSet<UrlTemplate>, url: &Url) -> bool {
+    UrlTemplate::all(url).any(|template| templates.contains(&template))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SuppressionRule {
+    pub template: UrlTemplate,
+    pub kind: TrapKind,
+    /// Number of distinct urls discovered for the template before it was suppressed.
+    pub num_urls: usize,
+    pub example: String,
+    #[serde(default)]
+    pub status: RuleStatus,
+}
+
+/// Read all the rules in the log, including duplicates.
+pub fn read_rules<P: AsRef<Path>>(path: P) -> Result<Vec<SuppressionRule>> {
+    let file = File::open(path)?;
+    let mut rules = Vec::new();
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        rules.push(serde_json::from_str(&line)?);
+    }
+
+    Ok(rules)
+}
+
+/// Overwrite the log with `rules`. The rules are written to a temporary file that then
+/// replaces the log, so readers never see a partially written log. Rules appended by a
+/// [`SuppressionRules`] that was opened before the log was replaced are not in the new log.
+pub fn write_rules<P: AsRef<Path>>(path: P, rules: &[SuppressionRule]) -> Result<()> {
+    let mut tmp_path = path.as_ref().as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut file = File::create(&tmp_path)?;
+
+    for rule in rules {
+        writeln!(file, "{}", serde_json::to_string(rule)?)?;
+    }
+
+    file.sync_data()?;
+    std::fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+/// Merge the rules that has been generated for the same template, possibly by different
+/// jobs. A reviewed status takes precedence over pending, so a template that has been
+/// rejected is not brought back for review when it is generated again.
+pub fn merge_rules(rules: Vec<SuppressionRule>) -> Vec<SuppressionRule> {
+    let mut merged: HashMap<UrlTemplate, SuppressionRule> = HashMap::new();
+
+    for rule in rules {
+        match merged.get_mut(&rule.template) {
+            Some(existing) => {
+                existing.num_urls = existing.num_urls.max(rule.num_urls);
+
+                if rule.status != RuleStatus::Pending {
+                    existing.status = rule.status;
+                }
+            }
+            None => {
+                merged.insert(rule.template.clone(), rule);
+            }
+        }
+    }
+
+    let mut res: Vec<_> = merged.into_values().collect();
+    res.sort_by(|a, b| a.template.cmp(&b.template));
+
+    res
+}
+
+/// The templates of the approved rules in the log.
+pub fn approved_templates<P: AsRef<Path>>(path: P) -> Result<HashSet<UrlTemplate>> {
+    if !path.as_ref().exists() {
+        return Ok(HashSet::new());
+    }
+
+    Ok(merge_rules(read_rules(path)?)
+        .into_iter()
+        .filter(|rule| rule.status == RuleStatus::Approved)
+        .map(|rule| rule.template)
+        .collect())
+}
+
+/// The approved rules shared by all jobs of a worker, and the log that
+/// newly generated rules are appended to.
+pub struct SuppressionRules {
+    approved: HashSet<UrlTemplate>,
+    log: Mutex<File>,
+}
+
+impl SuppressionRules {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let approved = approved_templates(path.as_ref())?;
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_ref())?;
+
+        Ok(Self {
+            approved,
+            log: Mutex::new(log),
+        })
+    }
+
+    pub fn num_approved(&self) -> usize {
+        self.approved.len()
+    }
+
+    pub fn append(&self, rules: &[SuppressionRule]) -> Result<()> {
+        let mut log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+
+        for rule in rules {
+            writeln!(log, "{}", serde_json::to_string(rule)?)?;
+        }
+
+        log.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Detects traps among the urls discovered during a single job.
+pub struct TrapDetector {
+    max_urls_per_template: usize,
+    max_repeated_segments: usize,
+    clusters: HashMap<UrlTemplate, HashSet<Url>>,
+    suppressed: HashSet<UrlTemplate>,
+    rules: Option<Arc<SuppressionRules>>,
+    new_rules: Vec<SuppressionRule>,
+}
+
+impl TrapDetector {
+    pub fn new(config: &TrapDetectionConfig) -> Self {
+        Self {
+            max_urls_per_template: config.max_urls_per_template,
+            max_repeated_segments: config.max_repeated_segments,
+            clusters: HashMap::new(),
+            suppressed: HashSet::new(),
+            rules: None,
+            new_rules: Vec::new(),
+        }
+    }
+
+    pub fn set_rules(&mut self, rules: Arc<SuppressionRules>) {
+        self.rules = Some(rules);
+    }
+
+    pub fn is_suppressed(&self, url: &Url) -> bool {
+        is_trapped(&self.suppressed, url)
+            || self
+                .rules
+                .as_ref()
+                .map(|rules| is_trapped(&rules.approved, url))
+                .unwrap_or(false)
+    }
+
+    fn suppress(&mut self, template: UrlTemplate, kind: TrapKind, num_urls: usize, url: &Url) {
+        tracing::debug!("suppressing {:?} trap: {}", kind, template);
+
+        self.clusters.remove(&template);
+        self.suppressed.insert(template.clone());
+        self.new_rules.push(SuppressionRule {
+            template,
+            kind,
+            num_urls,
+            example: url.to_string(),
+            status: RuleStatus::Pending,
+        });
+    }
+
+    /// Register a discovered url. Returns false if the url should not be crawled.
+    pub fn observe(&mut self, url: &Url) -> bool {
+        if self.is_suppressed(url) {
+            return false;
+        }
+
+        if has_session_id(url) {
+            self.suppress(UrlTemplate::new(url), TrapKind::SessionId, 1, url);
+            return false;
+        }
+
+        if has_repeated_segments(url, self.max_repeated_segments) {
+            self.suppress(UrlTemplate::new(url), TrapKind::RepeatedPath, 1, url);
+            return false;
+        }
+
+        for template in UrlTemplate::all(url) {
+            let cluster = self.clusters.entry(template.clone()).or_default();
+            cluster.insert(url.clone());
+            let num_urls = cluster.len();
+
+            if num_urls > self.max_urls_per_template {
+                let kind = template.kind();
+                self.suppress(template, kind, num_urls, url);
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// The rules generated since the last call.
+    pub fn take_rules(&mut self) -> Vec<SuppressionRule> {
+        std::mem::take(&mut self.new_rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector(max_urls_per_template: usize) -> TrapDetector {
+        TrapDetector::new(&TrapDetectionConfig {
+            max_urls_per_template,
+            max_repeated_segments: 3,
+            rules_path: None,
+        })
+    }
+
+    #[test]
+    fn templates() {
+        let url = Url::parse("https://example.com/events/2024/05/12?view=month&Cat=1").unwrap();
+        assert_eq!(
+            UrlTemplate::new(&url).as_str(),
+            "example.com/events/{year}/{num}/{num}?cat=*&view=*"
+        );
+        assert_eq!(
+            UrlTemplate::with_any_query(&url).unwrap().as_str(),
+            "example.com/events/{year}/{num}/{num}?*"
+        );
+        assert_eq!(UrlTemplate::new(&url).host(), "example.com");
+
+        let url = Url::parse("https://example.com/a;jsessionid=123").unwrap();
+        assert_eq!(
+            UrlTemplate::new(&url).as_str(),
+            "example.com/a;jsessionid=*"
+        );
+
+        let url = Url::parse("https://example.com/archive/2024-05-12/").unwrap();
+        assert_eq!(
+            UrlTemplate::new(&url).as_str(),
+            "example.com/archive/{date}/"
+        );
+        assert!(UrlTemplate::with_any_query(&url).is_none());
+    }
+
+    #[test]
+    fn calendar() {
+        let mut detector = detector(10);
+
+        let mut allowed = 0;
+        for day in 0..100 {
+            let url = Url::parse(&format!("https://example.com/calendar/2024/{day}")).unwrap();
+            if detector.observe(&url) {
+                allowed += 1;
+            }
+        }
+
+        assert_eq!(allowed, 10);
+
+        let rules = detector.take_rules();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].kind, TrapKind::Calendar);
+        assert_eq!(
+            rules[0].template.as_str(),
+            "example.com/calendar/{year}/{num}"
+        );
+
+        assert!(detector.observe(&Url::parse("https://example.com/about").unwrap()));
+    }
+
+    #[test]
+    fn session_ids() {
+        let mut detector = detector(10);
+
+        assert!(!detector.observe(&Url::parse("https://example.com/a?PHPSESSID=123").unwrap()));
+        assert!(!detector.observe(&Url::parse("https://example.com/a?phpsessid=456").unwrap()));
+        assert!(!detector.observe(&Url::parse("https://example.com/a;jsessionid=789").unwrap()));
+        assert!(detector.observe(&Url::parse("https://example.com/a").unwrap()));
+
+        let rules = detector.take_rules();
+        assert_eq!(rules.len(), 2);
+        assert!(rules.iter().all(|rule| rule.kind == TrapKind::SessionId));
+    }
+
+    #[test]
+    fn faceted_navigation() {
+        let mut detector = detector(10);
+        let facets = ["color", "size", "brand", "price"];
+
+        for i in 0..100 {
+            let query = facets
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| i & (1 << j) != 0)
+                .map(|(_, facet)| format!("{facet}={i}"))
+                .collect::<Vec<_>>()
+                .join("&");
+
+            detector.observe(&Url::parse(&format!("https://shop.com/shoes?{query}")).unwrap());
+        }
+
+        let rules = detector.take_rules();
+        assert!(rules
+            .iter()
+            .any(|rule| rule.kind == TrapKind::FacetedNavigation
+                && rule.template.as_str() == "shop.com/shoes?*"));
+
+        assert!(!detector.observe(&Url::parse("https://shop.com/shoes?color=red").unwrap()));
+        assert!(detector.observe(&Url::parse("https://shop.com/shoes").unwrap()));
+    }
+
+    #[test]
+    fn repeated_path() {
+        let mut detector = detector(10);
+
+        assert!(detector.observe(&Url::parse("https://example.com/a/b/a/b").unwrap()));
+        assert!(!detector.observe(&Url::parse("https://example.com/a/b/a/b/a/b/a/b").unwrap()));
+        assert_eq!(detector.take_rules()[0].kind, TrapKind::RepeatedPath);
+    }
+
+    #[test]
+    fn review() {
+        let path = crate::gen_temp_path();
+
+        let rule = |template: &str, status| SuppressionRule {
+            template: UrlTemplate(template.to_string()),
+            kind: TrapKind::Calendar,
+            num_urls: 11,
+            example: String::new(),
+            status,
+        };
+
+        let rules = SuppressionRules::open(&path).unwrap();
+        rules
+            .append(&[
+                rule("a.com/{year}", RuleStatus::Pending),
+                rule("b.com/{year}", RuleStatus::Pending),
+            ])
+            .unwrap();
+
+        let mut merged = merge_rules(read_rules(&path).unwrap());
+        assert_eq!(merged.len(), 2);
+        merged[0].status = RuleStatus::Approved;
+        write_rules(&path, &merged).unwrap();
+
+        // the rule is generated again after it has been reviewed
+        let rules = SuppressionRules::open(&path).unwrap();
+        assert_eq!(rules.num_approved(), 1);
+        rules
+            .append(&[rule("a.com/{year}", RuleStatus::Pending)])
+            .unwrap();
+
+        let mut detector = detector(10);
+        detector.set_rules(Arc::new(rules));
+        assert!(!detector.observe(&Url::parse("https://a.com/2024").unwrap()));
+        assert!(detector.observe(&Url::parse("https://b.com/2024").unwrap()));
+
+        assert_eq!(
+            approved_templates(&path).unwrap(),
+            [UrlTemplate("a.com/{year}".to_string())]
+                .into_iter()
+                .collect()
+        );
+    }
+}
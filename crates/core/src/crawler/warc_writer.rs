@@ -14,10 +14,12 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::time::Duration;
+use std::sync::Arc;
 
 use crate::{
     config::{self, S3Config},
+    event_log::{Event, EventLog},
+    object_store::ObjectStore,
     warc,
 };
 
@@ -58,33 +60,9 @@ async fn commit(writer: warc::DeduplicatedWarcWriter, s3: config::S3Config) {
     );
     let data = writer.finish().unwrap();
 
-    match s3::Bucket::new(
-        &s3.bucket,
-        s3::Region::Custom {
-            region: "".to_string(),
-            endpoint: s3.endpoint.clone(),
-        },
-        s3::creds::Credentials {
-            access_key: Some(s3.access_key.clone()),
-            secret_key: Some(s3.secret_key.clone()),
-            security_token: None,
-            session_token: None,
-            expiration: None,
-        },
-    ) {
-        Ok(bucket) => {
-            let bucket = bucket
-                .with_path_style()
-                .with_request_timeout(Duration::from_secs(30 * 60));
-
-            if let Err(err) = bucket
-                .put_object_with_content_type(
-                    &format!("{}/{}", &s3.folder, filename),
-                    &data,
-                    "application/warc",
-                )
-                .await
-            {
+    match ObjectStore::open(&config::ObjectStoreConfig::S3(s3)) {
+        Ok(store) => {
+            if let Err(err) = store.put(&filename, &data).await {
                 tracing::error!("failed to upload to bucket: {:?}", err);
             }
         }
@@ -92,12 +70,35 @@ async fn commit(writer: warc::DeduplicatedWarcWriter, s3: config::S3Config) {
     }
 }
 
-async fn writer_task(mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>, s3: S3Config) {
+fn append_fetched(event_log: &EventLog, datum: &CrawlDatum) {
+    let event = Event::Fetched {
+        url: datum.url.to_string(),
+        status_code: datum.status_code,
+        payload_type: datum.payload_type.clone(),
+        body: datum.body.clone(),
+        fetch_time_ms: datum.fetch_time_ms,
+        security: Some(datum.security),
+    };
+
+    if let Err(err) = event_log.append(event) {
+        tracing::error!("failed to append to event log: {:?}", err);
+    }
+}
+
+async fn writer_task(
+    mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>,
+    s3: S3Config,
+    event_log: Option<Arc<EventLog>>,
+) {
     let mut writer = warc::DeduplicatedWarcWriter::new();
 
     while let Some(message) = rx.recv().await {
         match message {
             WarcWriterMessage::Crawl(datum) => {
+                if let Some(event_log) = &event_log {
+                    append_fetched(event_log, &datum);
+                }
+
                 let w = &mut writer;
                 let (send, recv) = tokio::sync::oneshot::channel();
 
@@ -113,6 +114,7 @@ async fn writer_task(mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>, s3:
                             },
                             metadata: warc::Metadata {
                                 fetch_time_ms: datum.fetch_time_ms,
+                                security: Some(datum.security),
                             },
                         };
 
@@ -132,6 +134,12 @@ async fn writer_task(mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>, s3:
                 if writer.num_writes() > 0 {
                     commit(writer, s3.clone()).await;
                 }
+
+                if let Some(event_log) = &event_log {
+                    if let Err(err) = event_log.sync() {
+                        tracing::error!("failed to sync event log: {:?}", err);
+                    }
+                }
                 break;
             }
         }
@@ -139,10 +147,10 @@ async fn writer_task(mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>, s3:
 }
 
 impl WarcWriter {
-    pub fn new(s3: S3Config) -> Self {
+    pub fn new(s3: S3Config, event_log: Option<Arc<EventLog>>) -> Self {
         let (tx, rx) = tokio::sync::mpsc::channel(1);
 
-        tokio::spawn(writer_task(rx, s3));
+        tokio::spawn(writer_task(rx, s3, event_log));
 
         Self { tx }
     }
@@ -36,14 +36,21 @@ use crate::{
     crawler::MAX_URL_LEN_BYTES,
     distributed::{retry_strategy::ExponentialBackoff, sonic},
     entrypoint::crawler::router::{NewJob, RouterService},
+    liveness::{self, UrlOutcome, UrlOutcomeLog},
+    security::SecurityProperties,
     warc,
     webpage::{url_ext::UrlExt, Html},
 };
 
 use super::{
-    reqwest_client, robots_txt::RobotsTxtManager, wander_prirotiser::WanderPrioritiser, CrawlDatum,
-    DatumStream, Domain, Error, Result, RetrieableUrl, Site, UrlResponse, WarcWriter, WeightedUrl,
-    WorkerJob,
+    reputation::{self, HostOutcome, HostOutcomeLog},
+    reqwest_client,
+    robots_txt::RobotsTxtManager,
+    screenshot::Screenshotter,
+    trap_detector::{SuppressionRules, TrapDetector},
+    wander_prirotiser::WanderPrioritiser,
+    CrawlDatum, DatumStream, Domain, Error, Result, RetrieableUrl, Site, UrlResponse, WarcWriter,
+    WeightedUrl, WorkerJob,
 };
 
 const MAX_CONTENT_LENGTH: usize = 32 * 1024 * 1024; // 32 MB
@@ -51,20 +58,31 @@ const MAX_CONTENT_LENGTH: usize = 32 * 1024 * 1024; // 32 MB
 struct ProcessedUrl {
     new_urls: Vec<Url>,
     response: UrlResponse,
+    /// The fetched page, unless it asks not to be archived.
+    archivable: Option<Url>,
 }
 
 pub struct WorkerThread {
     writer: Arc<WarcWriter>,
     client: reqwest::Client,
     config: Arc<CrawlerConfig>,
-    router_hosts: Vec<SocketAddr>,
+    /// All the resolved addresses of each router.
+    router_hosts: Vec<Vec<SocketAddr>>,
+    suppression_rules: Option<Arc<SuppressionRules>>,
+    host_outcomes: Option<Arc<HostOutcomeLog>>,
+    url_outcomes: Option<Arc<UrlOutcomeLog>>,
+    screenshots: Option<Arc<Screenshotter>>,
 }
 
 impl WorkerThread {
     pub fn new(
         writer: Arc<WarcWriter>,
         config: CrawlerConfig,
-        router_hosts: Vec<SocketAddr>,
+        router_hosts: Vec<Vec<SocketAddr>>,
+        suppression_rules: Option<Arc<SuppressionRules>>,
+        host_outcomes: Option<Arc<HostOutcomeLog>>,
+        url_outcomes: Option<Arc<UrlOutcomeLog>>,
+        screenshots: Option<Arc<Screenshotter>>,
     ) -> Result<Self> {
         let client = reqwest_client(&config)?;
 
@@ -73,13 +91,17 @@ impl WorkerThread {
             client,
             config: Arc::new(config),
             router_hosts,
+            suppression_rules,
+            host_outcomes,
+            url_outcomes,
+            screenshots,
         })
     }
 
     async fn router_conn(&self) -> Result<sonic::service::ResilientConnection<RouterService>> {
         let retry = ExponentialBackoff::from_millis(1_000).with_limit(Duration::from_secs(10));
 
-        let router = *self.router_hosts.choose(&mut rand::thread_rng()).unwrap();
+        let router = self.router_hosts.choose(&mut rand::thread_rng()).unwrap();
 
         Ok(sonic::service::ResilientConnection::create_with_timeout(
             router,
@@ -98,13 +120,32 @@ impl WorkerThread {
 
             match res {
                 Ok(Some(job)) => {
-                    let executor = JobExecutor::new(
+                    let mut executor = JobExecutor::new(
                         job.into(),
                         self.client.clone(),
                         self.config.clone(),
                         self.writer.clone(),
                     );
-                    executor.run().await;
+
+                    if let Some(rules) = &self.suppression_rules {
+                        executor.set_suppression_rules(Arc::clone(rules));
+                    }
+
+                    if let Some(log) = &self.url_outcomes {
+                        executor.set_url_outcome_log(Arc::clone(log));
+                    }
+
+                    if let Some(screenshots) = &self.screenshots {
+                        executor.set_screenshots(Arc::clone(screenshots));
+                    }
+
+                    let outcomes = executor.run().await;
+
+                    if let Some(log) = &self.host_outcomes {
+                        if let Err(err) = log.append(&outcomes) {
+                            tracing::error!("failed to save host outcomes: {}", err);
+                        }
+                    }
                 }
                 Ok(None) => {
                     return;
@@ -128,6 +169,13 @@ pub struct JobExecutor<S: DatumStream> {
     sitemap_urls: HashSet<Url>,
     config: Arc<CrawlerConfig>,
     wander_prioritiser: WanderPrioritiser,
+    trap_detector: TrapDetector,
+    suppression_rules: Option<Arc<SuppressionRules>>,
+    url_outcome_log: Option<Arc<UrlOutcomeLog>>,
+    url_outcomes: Vec<UrlOutcome>,
+    screenshots: Option<Arc<Screenshotter>>,
+    host_outcomes: HashMap<String, HostOutcome>,
+    host_error_streaks: HashMap<String, u64>,
     job: WorkerJob,
 }
 
@@ -149,13 +197,39 @@ impl<S: DatumStream> JobExecutor<S> {
             crawled_urls: HashSet::new(),
             crawled_sitemaps: HashSet::new(),
             sitemap_urls: HashSet::new(),
+            trap_detector: TrapDetector::new(&config.trap_detection),
+            suppression_rules: None,
+            url_outcome_log: None,
+            url_outcomes: Vec::new(),
+            screenshots: None,
+            host_outcomes: HashMap::new(),
+            host_error_streaks: HashMap::new(),
             config,
             wander_prioritiser: WanderPrioritiser::new(),
             job,
         }
     }
 
-    pub async fn run(mut self) {
+    /// Skip urls matching the approved rules and append the rules
+    /// generated for crawl traps found during the job.
+    pub fn set_suppression_rules(&mut self, rules: Arc<SuppressionRules>) {
+        self.trap_detector.set_rules(Arc::clone(&rules));
+        self.suppression_rules = Some(rules);
+    }
+
+    /// Append the outcome of every fetched url to the log when the job is done.
+    pub fn set_url_outcome_log(&mut self, log: Arc<UrlOutcomeLog>) {
+        self.url_outcome_log = Some(log);
+    }
+
+    /// Capture a screenshot thumbnail of the fetched pages.
+    pub fn set_screenshots(&mut self, screenshots: Arc<Screenshotter>) {
+        self.screenshots = Some(screenshots);
+    }
+
+    /// Crawl the urls of the job and return the number of
+    /// successful and failed urls for each host.
+    pub async fn run(mut self) -> Vec<HostOutcome> {
         tracing::info!("Processing job: {:?}", self.job.domain);
 
         self.scheduled_urls().await;
@@ -163,6 +237,152 @@ impl<S: DatumStream> JobExecutor<S> {
         if self.job.wandering_urls > 0 {
             self.wander().await;
         }
+
+        let new_rules = self.trap_detector.take_rules();
+
+        if !new_rules.is_empty() {
+            tracing::info!(
+                "found {} crawl traps for {:?}",
+                new_rules.len(),
+                self.job.domain
+            );
+
+            if let Some(rules) = &self.suppression_rules {
+                if let Err(err) = rules.append(&new_rules) {
+                    tracing::error!("failed to save crawl trap rules: {}", err);
+                }
+            }
+        }
+
+        if let Some(log) = &self.url_outcome_log {
+            if let Err(err) = log.append(&self.url_outcomes) {
+                tracing::error!("failed to save url outcomes: {}", err);
+            }
+        }
+
+        let timestamp = reputation::now_ms();
+
+        self.host_outcomes
+            .into_values()
+            .map(|outcome| HostOutcome {
+                timestamp,
+                ..outcome
+            })
+            .collect()
+    }
+
+    fn is_host_failing(&self, url: &Url) -> bool {
+        self.host_error_streaks
+            .get(url.normalized_host().unwrap_or_default())
+            .map(|streak| *streak >= self.config.max_host_error_streak)
+            .unwrap_or(false)
+    }
+
+    fn host_outcome(&mut self, url: &Url) -> &mut HostOutcome {
+        let host = url.normalized_host().unwrap_or_default();
+
+        self.host_outcomes
+            .entry(host.to_string())
+            .or_insert_with(|| HostOutcome {
+                host: host.to_string(),
+                ..Default::default()
+            })
+    }
+
+    /// Count the pages of the host that ask not to be archived or shown with a snippet.
+    fn record_robots_meta(&mut self, url: &Url, html: &Html) {
+        if self.config.dry_run {
+            return;
+        }
+
+        let (noarchive, nosnippet) = (html.is_no_archive(), html.is_no_snippet());
+        let directives = &mut self.host_outcome(url).directives;
+
+        directives.pages += 1;
+        directives.noarchive += noarchive as u64;
+        directives.nosnippet += nosnippet as u64;
+    }
+
+    /// Capture a screenshot of the page unless the host disallows
+    /// our screenshot user agent in its robots.txt.
+    async fn capture_screenshot(&mut self, url: &Url) {
+        if self.config.dry_run {
+            return;
+        }
+
+        let Some(screenshots) = self.screenshots.clone() else {
+            return;
+        };
+
+        if self
+            .robotstxt
+            .is_allowed(url, screenshots.user_agent_token())
+            .await
+        {
+            screenshots.capture(url.clone(), reputation::now_ms());
+        }
+    }
+
+    fn record_outcome(&mut self, url: &Url, response: &UrlResponse) {
+        if self.config.dry_run {
+            return;
+        }
+
+        if self.url_outcome_log.is_some() {
+            let (status_code, conclusive) = match response {
+                UrlResponse::Failed {
+                    status_code,
+                    host_not_found,
+                    ..
+                } => (
+                    *status_code,
+                    liveness::is_conclusive(*status_code, *host_not_found),
+                ),
+                UrlResponse::Success { .. } => (Some(200), true),
+                UrlResponse::Redirected { .. } => (None, true),
+            };
+
+            if conclusive {
+                self.url_outcomes.push(UrlOutcome {
+                    url: url.to_string(),
+                    timestamp: reputation::now_ms(),
+                    status_code,
+                    ok: !matches!(response, UrlResponse::Failed { .. }),
+                });
+            }
+        }
+
+        let host = url.normalized_host().unwrap_or_default();
+
+        let outcome = self
+            .host_outcomes
+            .entry(host.to_string())
+            .or_insert_with(|| HostOutcome {
+                host: host.to_string(),
+                ..Default::default()
+            });
+        let streak = self.host_error_streaks.entry(host.to_string()).or_default();
+
+        match response {
+            UrlResponse::Success { .. } | UrlResponse::Redirected { .. } => {
+                outcome.successes += 1;
+                *streak = 0;
+            }
+            UrlResponse::Failed { status_code, .. } => {
+                if reputation::is_host_error(*status_code) {
+                    outcome.errors += 1;
+                    *streak += 1;
+
+                    if *streak == self.config.max_host_error_streak {
+                        tracing::warn!(
+                            "stopped crawling {} after {} errors in a row",
+                            host,
+                            streak
+                        );
+                    }
+                }
+            }
+        }
     }
 
     async fn scheduled_urls(&mut self) {
@@ -217,6 +437,10 @@ impl<S: DatumStream> JobExecutor<S> {
                 continue;
             }
 
+            if self.is_host_failing(retryable_url.url()) {
+                continue;
+            }
+
             if !self.config.dry_run
                 && !self
                     .robotstxt
@@ -236,13 +460,28 @@ impl<S: DatumStream> JobExecutor<S> {
             if !self.config.dry_run && fetch_sitemap && !self.crawled_sitemaps.contains(&site) {
                 self.crawled_sitemaps.insert(site.clone());
 
-                if let Some(sitemap) = self.robotstxt.sitemap(retryable_url.url()).await {
+                let sitemap = self.robotstxt.sitemap(retryable_url.url()).await;
+                let crawl_delay = self
+                    .robotstxt
+                    .crawl_delay(retryable_url.url(), &self.config.user_agent.token)
+                    .await;
+
+                let directives = &mut self.host_outcome(retryable_url.url()).directives;
+                directives.has_sitemap = Some(sitemap.is_some());
+                directives.crawl_delay_ms = crawl_delay.map(|delay| delay.as_millis() as u64);
+
+                if let Some(sitemap) = sitemap {
                     self.sitemap_urls
                         .extend(self.urls_from_sitemap(sitemap, 0, 5).await);
                 }
             }
 
             let res = self.process_url(retryable_url.url().clone()).await;
+            self.record_outcome(retryable_url.url(), &res.response);
+
+            if let Some(page) = &res.archivable {
+                self.capture_screenshot(page).await;
+            }
 
             match res.response {
                 UrlResponse::Success { url: _ } => {
@@ -257,12 +496,17 @@ impl<S: DatumStream> JobExecutor<S> {
                             continue;
                         }
 
+                        if !self.trap_detector.observe(&new_url) {
+                            continue;
+                        }
+
                         self.wander_prioritiser.inc(new_url, weight);
                     }
                 }
                 UrlResponse::Failed {
                     url: _,
                     status_code,
+                    host_not_found: _,
                 } => {
                     if matches!(status_code, Some(429)) {
                         let mut retryable_url = retryable_url;
@@ -287,6 +531,8 @@ impl<S: DatumStream> JobExecutor<S> {
 
                         match Html::parse(&datum.body, datum.url.as_str()) {
                             Ok(html) => {
+                                self.record_robots_meta(&url, &html);
+
                                 let new_urls = html
                                     .all_links()
                                     .into_iter()
@@ -323,11 +569,13 @@ impl<S: DatumStream> JobExecutor<S> {
                                     })
                                     .collect();
 
+                                let archivable = (!html.is_no_archive()).then(|| datum.url.clone());
                                 let url_res = UrlResponse::Success { url: datum.url };
 
                                 ProcessedUrl {
                                     new_urls,
                                     response: url_res,
+                                    archivable,
                                 }
                             }
                             Err(_) => ProcessedUrl {
@@ -335,7 +583,9 @@ impl<S: DatumStream> JobExecutor<S> {
                                 response: UrlResponse::Failed {
                                     url,
                                     status_code: None,
+                                    host_not_found: false,
                                 },
+                                archivable: None,
                             },
                         }
                     } else {
@@ -347,6 +597,7 @@ impl<S: DatumStream> JobExecutor<S> {
                         ProcessedUrl {
                             new_urls: Vec::new(),
                             response: url_res,
+                            archivable: None,
                         }
                     }
                 } else {
@@ -370,7 +621,9 @@ impl<S: DatumStream> JobExecutor<S> {
                         response: UrlResponse::Failed {
                             url,
                             status_code: Some(datum.status_code),
+                            host_not_found: false,
                         },
+                        archivable: None,
                     }
                 }
             }
@@ -382,7 +635,9 @@ impl<S: DatumStream> JobExecutor<S> {
                     response: UrlResponse::Failed {
                         url,
                         status_code: None,
+                        host_not_found: liveness::is_host_not_found(&err),
                     },
+                    archivable: None,
                 }
             }
         }
@@ -495,10 +750,12 @@ impl<S: DatumStream> JobExecutor<S> {
                 payload_type,
                 body: String::new(),
                 fetch_time_ms: fetch_time.as_millis() as u64,
+                security: SecurityProperties::default(),
             });
         }
 
         let res_url = res.url().clone();
+        let hsts = headers.get("strict-transport-security").cloned();
 
         let content_type = res
             .headers()
@@ -530,6 +787,7 @@ impl<S: DatumStream> JobExecutor<S> {
 
         let (text, _, _) = encoding.decode(&bytes);
         let body = text.to_string();
+        let security = SecurityProperties::from_response(&res_url, hsts.as_deref(), &body);
 
         Ok(CrawlDatum {
             url: res_url,
@@ -537,6 +795,7 @@ impl<S: DatumStream> JobExecutor<S> {
             body,
             payload_type,
             fetch_time_ms: fetch_time.as_millis() as u64,
+            security,
         })
     }
 
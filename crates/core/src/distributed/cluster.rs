@@ -14,11 +14,11 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 use chitchat::{
-    spawn_chitchat, transport::UdpTransport, ChitchatConfig, ChitchatHandle, FailureDetectorConfig,
-    NodeId,
+    spawn_chitchat, transport::UdpTransport, Chitchat, ChitchatConfig, ChitchatHandle,
+    FailureDetectorConfig, NodeId, NodeState,
 };
 use std::{collections::HashSet, net::SocketAddr, sync::Arc, time::Duration};
-use tokio::sync::RwLock;
+use tokio::sync::{watch, Mutex, RwLock};
 use tokio_stream::StreamExt;
 use tracing::error;
 
@@ -27,11 +27,17 @@ use crate::distributed::member::{Member, Service};
 const CLUSTER_ID: &str = "stract-cluster";
 const GOSSIP_INTERVAL: Duration = Duration::from_secs(1);
 const SERVICE_KEY: &str = "service";
+/// Set by nodes that are shutting down. The other members no longer see them as alive.
+const LEAVING_KEY: &str = "leaving";
+/// How long a leaving node waits for the other members to learn that it is leaving.
+const LEAVE_PROPAGATION: Duration = Duration::from_secs(3 * GOSSIP_INTERVAL.as_secs());
 
 type Result<T> = std::result::Result<T, anyhow::Error>;
 
 pub struct Cluster {
     alive_nodes: Arc<RwLock<HashSet<Member>>>,
+    membership: watch::Receiver<HashSet<Member>>,
+    chitchat: Arc<Mutex<Chitchat>>,
     // dropping the handle leaves the cluster
     _chitchat_handle: ChitchatHandle,
 }
@@ -65,7 +71,9 @@ impl Cluster {
                 .map(|addr| addr.to_string())
                 .collect(),
             failure_detector_config,
-            is_ready_predicate: None,
+            is_ready_predicate: Some(Box::new(|state: &NodeState| {
+                state.get(LEAVING_KEY).is_none()
+            })),
         };
 
         let chitchat_handle = spawn_chitchat(
@@ -78,9 +86,11 @@ impl Cluster {
         )
         .await?;
         let chitchat = chitchat_handle.chitchat();
+        let self_chitchat = Arc::clone(&chitchat);
 
         let alive_nodes = Arc::new(RwLock::new(HashSet::new()));
         let alive_nodes_ref = alive_nodes.clone();
+        let (membership_tx, membership) = watch::channel(HashSet::new());
 
         tokio::spawn(async move {
             let mut node_change_receiver = chitchat.lock().await.ready_nodes_watcher();
@@ -122,12 +132,16 @@ impl Cluster {
                     for member in new_members {
                         write.insert(member);
                     }
+
+                    membership_tx.send_replace(write.clone());
                 }
             }
         });
 
         Ok(Self {
             alive_nodes,
+            membership,
+            chitchat: self_chitchat,
             _chitchat_handle: chitchat_handle,
         })
     }
@@ -142,4 +156,21 @@ impl Cluster {
 
         res
     }
+
+    /// Tell the other members that the node is leaving, so they stop sending it new requests
+    /// before it shuts down. Waits until the other members have had time to learn about it.
+    pub async fn leave(&self) {
+        self.chitchat
+            .lock()
+            .await
+            .self_node_state()
+            .set(LEAVING_KEY, "true");
+
+        tokio::time::sleep(LEAVE_PROPAGATION).await;
+    }
+
+    /// Get notified with the new members whenever nodes join or leave the cluster.
+    pub fn subscribe(&self) -> watch::Receiver<HashSet<Member>> {
+        self.membership.clone()
+    }
 }
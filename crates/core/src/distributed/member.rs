@@ -18,13 +18,20 @@ use std::net::SocketAddr;
 
 use serde::{Deserialize, Serialize};
 
-use crate::{config::WebgraphGranularity, searcher::ShardId};
+use crate::{
+    config::{ShardTier, WebgraphGranularity},
+    searcher::ShardId,
+};
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Debug)]
 pub enum Service {
     Searcher {
         host: SocketAddr,
         shard: ShardId,
+        #[serde(default)]
+        tier: ShardTier,
+        #[serde(default)]
+        languages: Vec<whatlang::Lang>,
     },
     EntitySearcher {
         host: SocketAddr,
@@ -40,6 +47,12 @@ pub enum Service {
         host: SocketAddr,
         granularity: WebgraphGranularity,
     },
+    HostStats {
+        host: SocketAddr,
+    },
+    Centrality {
+        host: SocketAddr,
+    },
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
@@ -15,5 +15,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 pub mod cluster;
 pub mod member;
+pub mod net;
+pub mod probe;
 pub mod retry_strategy;
 pub mod sonic;
@@ -0,0 +1,201 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! IPv6 and dual-stack aware helpers for binding and connecting tcp sockets.
+//!
+//! Connections use happy eyeballs (RFC 8305): the resolved addresses are
+//! interleaved by address family and a new attempt is started every
+//! [`CONNECTION_ATTEMPT_DELAY`] until one of them succeeds. This makes
+//! connections to hosts reachable on only one of the families fast, instead of
+//! waiting for the attempts on the unreachable family to time out.
+
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs as _},
+    pin::Pin,
+    time::Duration,
+};
+
+use futures::{stream::FuturesUnordered, Future, StreamExt};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{lookup_host, TcpListener, TcpStream, ToSocketAddrs};
+
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Resolve `host` synchronously. Accepts ip literals (`127.0.0.1:80`,
+/// `[::1]:80`) as well as hostnames (`localhost:80`).
+pub fn resolve(host: &str) -> io::Result<Vec<SocketAddr>> {
+    let addrs: Vec<_> = host.to_socket_addrs()?.collect();
+
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{host} did not resolve to any address"),
+        ));
+    }
+
+    Ok(addrs)
+}
+
+/// Order the addresses so they alternate between address families, starting
+/// with the family of the first address.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_is_v6 = first.is_ipv6();
+
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|a| a.is_ipv6() == first_is_v6);
+    preferred.reverse();
+    other.reverse();
+
+    let mut res = Vec::with_capacity(preferred.len() + other.len());
+
+    loop {
+        match (preferred.pop(), other.pop()) {
+            (None, None) => break,
+            (a, b) => res.extend(a.into_iter().chain(b)),
+        }
+    }
+
+    res
+}
+
+type Attempt = Pin<Box<dyn Future<Output = io::Result<TcpStream>> + Send>>;
+
+/// Connect to the first reachable address of `addr`.
+pub async fn connect(addr: impl ToSocketAddrs) -> io::Result<TcpStream> {
+    let addrs = interleave(lookup_host(addr).await?.collect());
+
+    let mut remaining = addrs.into_iter();
+    let mut attempts: FuturesUnordered<Attempt> = FuturesUnordered::new();
+    let mut last_err = None;
+
+    loop {
+        if attempts.is_empty() {
+            match remaining.next() {
+                Some(addr) => attempts.push(Box::pin(TcpStream::connect(addr))),
+                None => break,
+            }
+        }
+
+        tokio::select! {
+            res = attempts.next() => match res {
+                Some(Ok(stream)) => return Ok(stream),
+                Some(Err(err)) => last_err = Some(err),
+                None => {}
+            },
+            _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if !remaining.as_slice().is_empty() => {
+                if let Some(addr) = remaining.next() {
+                    attempts.push(Box::pin(TcpStream::connect(addr)));
+                }
+            }
+        }
+    }
+
+    Err(last_err
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to")))
+}
+
+fn bind_std(addr: SocketAddr) -> io::Result<std::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    // binding to the unspecified v6 address also accepts v4 connections,
+    // regardless of the bindv6only setting of the host.
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    Ok(socket.into())
+}
+
+/// Bind a listener to the first address of `addr` that can be bound.
+pub async fn bind(addr: impl ToSocketAddrs) -> io::Result<TcpListener> {
+    let mut last_err = None;
+
+    for addr in lookup_host(addr).await? {
+        match bind_std(addr) {
+            Ok(listener) => return TcpListener::from_std(listener),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses to bind to")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interleave_families() {
+        let addrs: Vec<SocketAddr> = vec![
+            "[::1]:1".parse().unwrap(),
+            "[::2]:1".parse().unwrap(),
+            "[::3]:1".parse().unwrap(),
+            "127.0.0.1:1".parse().unwrap(),
+        ];
+
+        let expected: Vec<SocketAddr> = vec![
+            "[::1]:1".parse().unwrap(),
+            "127.0.0.1:1".parse().unwrap(),
+            "[::2]:1".parse().unwrap(),
+            "[::3]:1".parse().unwrap(),
+        ];
+
+        assert_eq!(interleave(addrs), expected);
+        assert!(interleave(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn resolve_literals() {
+        assert_eq!(
+            resolve("[::1]:3000").unwrap(),
+            vec!["[::1]:3000".parse::<SocketAddr>().unwrap()]
+        );
+        assert_eq!(
+            resolve("127.0.0.1:3000").unwrap(),
+            vec!["127.0.0.1:3000".parse::<SocketAddr>().unwrap()]
+        );
+        assert!(resolve("not an address").is_err());
+    }
+
+    #[tokio::test]
+    async fn connect_skips_unreachable() {
+        let listener = bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // nothing listens on the first address, so the connection
+        // should fall through to the second one.
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+
+        let (stream, accepted) = tokio::join!(connect(&[closed_addr, addr][..]), listener.accept());
+
+        assert_eq!(stream.unwrap().peer_addr().unwrap(), addr);
+        assert!(accepted.is_ok());
+    }
+}
@@ -0,0 +1,207 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Health probes of the cluster members over udp.
+//!
+//! A member is in the cluster as soon as it joins the gossip, but it might still be
+//! loading its index before it can handle requests. Members answer probes on the udp
+//! port with the same number as the tcp port of their service, so clients can check
+//! whether a member is ready without a new connection or any configuration.
+//!
+//! A probe is the magic bytes followed by a random nonce, and the answer repeats them
+//! followed by the health of the member. Members that do not answer, e.g. because they
+//! predate the probes, are treated as ready by the clients, since the gossip says they are alive.
+
+use std::{
+    collections::HashSet,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::future::join_all;
+use tokio::{net::UdpSocket, task::JoinHandle};
+
+const MAGIC: &[u8; 4] = b"SPRB";
+const PROBE_LEN: usize = MAGIC.len() + 8;
+
+/// How long a client waits for the answer to a probe.
+pub const PROBE_TIMEOUT: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// The member is alive but not ready to handle requests, e.g. because it is loading its index.
+    Loading,
+    Ready,
+}
+
+impl Health {
+    fn to_byte(self) -> u8 {
+        match self {
+            Health::Loading => 0,
+            Health::Ready => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Health::Loading),
+            1 => Some(Health::Ready),
+            _ => None,
+        }
+    }
+}
+
+/// Answers the probes of the clients until it is dropped.
+pub struct ProbeResponder {
+    ready: Arc<AtomicBool>,
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl ProbeResponder {
+    pub async fn bind(addr: SocketAddr, health: Health) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr).await?;
+        let addr = socket.local_addr()?;
+        let ready = Arc::new(AtomicBool::new(health == Health::Ready));
+        let ready_ref = Arc::clone(&ready);
+
+        let task = tokio::spawn(async move {
+            let mut buf = [0; PROBE_LEN];
+
+            loop {
+                let (len, client) = match socket.recv_from(&mut buf).await {
+                    Ok(res) => res,
+                    Err(e) => {
+                        tracing::debug!("failed to receive probe: {}", e);
+                        continue;
+                    }
+                };
+
+                if len != PROBE_LEN || &buf[..MAGIC.len()] != MAGIC {
+                    continue;
+                }
+
+                let health = if ready_ref.load(Ordering::Relaxed) {
+                    Health::Ready
+                } else {
+                    Health::Loading
+                };
+
+                let mut answer = [0; PROBE_LEN + 1];
+                answer[..PROBE_LEN].copy_from_slice(&buf);
+                answer[PROBE_LEN] = health.to_byte();
+
+                if let Err(e) = socket.send_to(&answer, client).await {
+                    tracing::debug!("failed to answer probe from {}: {}", client, e);
+                }
+            }
+        });
+
+        Ok(Self { ready, addr, task })
+    }
+
+    pub fn set_health(&self, health: Health) {
+        self.ready.store(health == Health::Ready, Ordering::Relaxed);
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for ProbeResponder {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Probe the member at `addr`. `None` if it did not answer within the timeout.
+pub async fn probe(addr: SocketAddr, timeout: Duration) -> Option<Health> {
+    let local: SocketAddr = if addr.is_ipv6() {
+        "[::]:0".parse().unwrap()
+    } else {
+        "0.0.0.0:0".parse().unwrap()
+    };
+
+    let socket = UdpSocket::bind(local).await.ok()?;
+    socket.connect(addr).await.ok()?;
+
+    let mut probe = [0; PROBE_LEN];
+    probe[..MAGIC.len()].copy_from_slice(MAGIC);
+    probe[MAGIC.len()..].copy_from_slice(&rand::random::<u64>().to_le_bytes());
+
+    let answer = async {
+        socket.send(&probe).await.ok()?;
+        let mut buf = [0; PROBE_LEN + 1];
+
+        loop {
+            let len = socket.recv(&mut buf).await.ok()?;
+
+            // datagrams that do not answer this probe are ignored
+            if len == buf.len() && buf[..PROBE_LEN] == probe {
+                return Health::from_byte(buf[PROBE_LEN]);
+            }
+        }
+    };
+
+    tokio::time::timeout(timeout, answer).await.ok().flatten()
+}
+
+/// The members that answered that they are still loading.
+pub async fn loading(addrs: impl IntoIterator<Item = SocketAddr>) -> HashSet<SocketAddr> {
+    let addrs: HashSet<_> = addrs.into_iter().collect();
+
+    join_all(
+        addrs
+            .into_iter()
+            .map(|addr| async move { (addr, probe(addr, PROBE_TIMEOUT).await) }),
+    )
+    .await
+    .into_iter()
+    .filter(|(_, health)| *health == Some(Health::Loading))
+    .map(|(addr, _)| addr)
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn probe_health() {
+        let responder = ProbeResponder::bind("127.0.0.1:0".parse().unwrap(), Health::Loading)
+            .await
+            .unwrap();
+        let addr = responder.local_addr();
+
+        assert_eq!(probe(addr, PROBE_TIMEOUT).await, Some(Health::Loading));
+        assert_eq!(loading([addr]).await, HashSet::from([addr]));
+
+        responder.set_health(Health::Ready);
+        assert_eq!(probe(addr, PROBE_TIMEOUT).await, Some(Health::Ready));
+        assert!(loading([addr]).await.is_empty());
+
+        // members that do not answer are not loading
+        drop(responder);
+        assert_eq!(probe(addr, PROBE_TIMEOUT).await, None);
+        assert!(loading([addr]).await.is_empty());
+    }
+}
@@ -0,0 +1,338 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The serialization formats of the bodies of the sonic frames.
+//!
+//! Like the [`compression`](super::compression) flags, the format is stored in the high
+//! bits of the body size in the frame header. Bincode has no flag, so the frames of peers
+//! that only know bincode are read as bincode. The client picks the format of its requests,
+//! and the server responds in the format of the request.
+//!
+//! Servers read the requests in all the formats they are built with, and reject the requests
+//! in other formats. Servers that predate the formats close the connection instead, as the
+//! flags look like an invalid body size to them. When a server rejects the first request on
+//! a new connection, the client falls back to bincode, which all servers can read, for all
+//! later connections to the server. This way the clients can be upgraded before the servers.
+//!
+//! Postcard and MessagePack are behind the `postcard` and `msgpack` features. MessagePack
+//! encodes the names of the fields, so fields can be added to the messages without breaking
+//! the peers that have not been upgraded yet.
+
+use std::{collections::HashSet, net::SocketAddr, sync::Mutex};
+
+use anyhow::anyhow;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Error, Result};
+
+const POSTCARD: usize = 1 << 57;
+const MESSAGE_PACK: usize = 1 << 56;
+pub(super) const FLAGS: usize = POSTCARD | MESSAGE_PACK;
+
+/// The servers that rejected a request in another format than bincode.
+static BINCODE_ONLY: once_cell::sync::Lazy<Mutex<HashSet<SocketAddr>>> =
+    once_cell::sync::Lazy::new(|| Mutex::new(HashSet::new()));
+
+pub trait Codec {
+    fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>>;
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T>;
+}
+
+pub struct Bincode;
+
+impl Codec for Bincode {
+    fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+#[cfg(feature = "postcard")]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Codec for Postcard {
+    fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+        postcard::to_stdvec(value).map_err(|e| anyhow!("failed to serialize postcard: {e}").into())
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        postcard::from_bytes(bytes).map_err(|e| super::Error::Decode(format!("postcard: {e}")))
+    }
+}
+
+#[cfg(feature = "msgpack")]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+        rmp_serde::to_vec_named(value)
+            .map_err(|e| anyhow!("failed to serialize messagepack: {e}").into())
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        rmp_serde::from_slice(bytes).map_err(|e| super::Error::Decode(format!("messagepack: {e}")))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Bincode,
+    #[cfg(feature = "postcard")]
+    Postcard,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+}
+
+impl Format {
+    /// The format the services should prefer. MessagePack encodes the names of the fields,
+    /// so it is preferred over bincode when the `msgpack` feature is enabled.
+    #[cfg(feature = "msgpack")]
+    pub const fn preferred() -> Self {
+        Format::MessagePack
+    }
+
+    /// The format the services should prefer. MessagePack encodes the names of the fields,
+    /// so it is preferred over bincode when the `msgpack` feature is enabled.
+    #[cfg(not(feature = "msgpack"))]
+    pub const fn preferred() -> Self {
+        Format::Bincode
+    }
+
+    /// The format to send the requests to the server in. Bincode is used for the
+    /// servers that have rejected the other formats.
+    pub(super) fn negotiate(self, server: Option<SocketAddr>) -> Self {
+        let Some(server) = server else {
+            return self;
+        };
+
+        if self != Format::Bincode
+            && BINCODE_ONLY
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .contains(&server)
+        {
+            Format::Bincode
+        } else {
+            self
+        }
+    }
+
+    /// Check whether the error shows that the server could not read a request in this format.
+    /// Older servers close the connection on the first request instead of rejecting it, which
+    /// can only be told apart from a connection the server closed while it was idle if the
+    /// connection is new. The server is sent bincode from then on, and the error is turned
+    /// into [`Error::UnsupportedFormat`] so the request can be retried.
+    pub(super) fn check_rejection(
+        self,
+        server: Option<SocketAddr>,
+        is_new_connection: bool,
+        err: Error,
+    ) -> Error {
+        if self == Format::Bincode {
+            return err;
+        }
+
+        let rejected = match &err {
+            Error::UnsupportedFormat => true,
+            Error::IO(e) => {
+                is_new_connection
+                    && matches!(
+                        e.kind(),
+                        std::io::ErrorKind::UnexpectedEof
+                            | std::io::ErrorKind::ConnectionReset
+                            | std::io::ErrorKind::BrokenPipe
+                    )
+            }
+            _ => false,
+        };
+
+        if !rejected {
+            return err;
+        }
+
+        if let Some(server) = server {
+            tracing::warn!("{server} rejected a request in {self:?}, falling back to bincode");
+            BINCODE_ONLY
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(server);
+        }
+
+        Error::UnsupportedFormat
+    }
+
+    /// The flags for the header of the frames in this format.
+    pub(super) fn flags(self) -> usize {
+        match self {
+            Format::Bincode => 0,
+            #[cfg(feature = "postcard")]
+            Format::Postcard => POSTCARD,
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => MESSAGE_PACK,
+        }
+    }
+
+    /// The format of a frame according to the flags of its header.
+    pub(super) fn from_flags(flags: usize) -> Result<Self> {
+        match flags & FLAGS {
+            0 => Ok(Format::Bincode),
+            #[cfg(feature = "postcard")]
+            POSTCARD => Ok(Format::Postcard),
+            #[cfg(feature = "msgpack")]
+            MESSAGE_PACK => Ok(Format::MessagePack),
+            other => {
+                Err(anyhow!("the frame has an unsupported serialization format: {other:#x}").into())
+            }
+        }
+    }
+
+    pub(super) fn serialize<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            Format::Bincode => Bincode::serialize(value),
+            #[cfg(feature = "postcard")]
+            Format::Postcard => Postcard::serialize(value),
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => MessagePack::serialize(value),
+        }
+    }
+
+    pub(super) fn deserialize<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T> {
+        match self {
+            Format::Bincode => Bincode::deserialize(bytes),
+            #[cfg(feature = "postcard")]
+            Format::Postcard => Postcard::deserialize(bytes),
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => MessagePack::deserialize(bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Message {
+        text: String,
+        scores: HashMap<String, f64>,
+        next: Option<Box<Message>>,
+    }
+
+    fn formats() -> Vec<Format> {
+        #[allow(unused_mut)]
+        let mut formats = vec![Format::Bincode];
+        #[cfg(feature = "postcard")]
+        formats.push(Format::Postcard);
+        #[cfg(feature = "msgpack")]
+        formats.push(Format::MessagePack);
+
+        formats
+    }
+
+    #[test]
+    fn roundtrip() {
+        let message = Message {
+            text: "stract".to_string(),
+            scores: [("bm25".to_string(), 1.5)].into_iter().collect(),
+            next: Some(Box::new(Message {
+                text: "next".to_string(),
+                scores: HashMap::new(),
+                next: None,
+            })),
+        };
+
+        for format in formats() {
+            let bytes = format.serialize(&message).unwrap();
+            let flags = format.flags();
+
+            assert_eq!(flags & !FLAGS, 0);
+            assert_eq!(Format::from_flags(flags).unwrap(), format);
+            assert_eq!(
+                Format::from_flags(flags)
+                    .unwrap()
+                    .deserialize::<Message>(&bytes)
+                    .unwrap(),
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn bincode_is_default() {
+        assert_eq!(Format::default(), Format::Bincode);
+        assert_eq!(Format::Bincode.flags(), 0);
+
+        // the flags of the other parts of the header do not change the format
+        assert_eq!(
+            Format::from_flags(super::super::compression::FLAGS).unwrap(),
+            Format::Bincode
+        );
+    }
+
+    #[test]
+    fn unsupported_format() {
+        #[cfg(not(feature = "postcard"))]
+        assert!(Format::from_flags(POSTCARD).is_err());
+        #[cfg(not(feature = "msgpack"))]
+        assert!(Format::from_flags(MESSAGE_PACK).is_err());
+
+        assert!(Format::from_flags(POSTCARD | MESSAGE_PACK).is_err());
+    }
+
+    #[test]
+    fn bincode_is_never_rejected() {
+        let server = Some("127.0.0.1:1".parse().unwrap());
+        let eof = || Error::IO(std::io::ErrorKind::UnexpectedEof.into());
+
+        assert!(matches!(
+            Format::Bincode.check_rejection(server, true, eof()),
+            Error::IO(_)
+        ));
+        assert_eq!(Format::Bincode.negotiate(server), Format::Bincode);
+    }
+
+    #[test]
+    #[cfg(feature = "msgpack")]
+    fn fall_back_to_bincode() {
+        let server = Some("127.0.0.1:2".parse().unwrap());
+        let eof = || Error::IO(std::io::ErrorKind::UnexpectedEof.into());
+
+        assert_eq!(Format::MessagePack.negotiate(server), Format::MessagePack);
+
+        // a reused connection might have been closed while it was idle
+        assert!(matches!(
+            Format::MessagePack.check_rejection(server, false, eof()),
+            Error::IO(_)
+        ));
+        assert_eq!(Format::MessagePack.negotiate(server), Format::MessagePack);
+
+        assert!(matches!(
+            Format::MessagePack.check_rejection(server, true, eof()),
+            Error::UnsupportedFormat
+        ));
+        assert_eq!(Format::MessagePack.negotiate(server), Format::Bincode);
+    }
+}
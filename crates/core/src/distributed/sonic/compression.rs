@@ -0,0 +1,215 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Compression of the bodies of the sonic frames.
+//!
+//! The flags are stored in the high bits of the body size in the frame header. These
+//! bits are always zero for peers without compression, as the body size is limited.
+//! A client with compression sets [`ACCEPTS_COMPRESSION`] on its requests, and the
+//! server only compresses the response if the flag is set, so clients without compression
+//! keep getting uncompressed responses. Servers decompress requests regardless of their
+//! own settings.
+//!
+//! Servers without compression reject frames with the flags, so the clients only compress
+//! once it is enabled with [`set_client_compression`], after all the servers are upgraded.
+
+use std::{
+    io::Read,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::anyhow;
+
+use super::{Error, Result};
+
+/// The sender can decompress the bodies of the frames it receives.
+pub(super) const ACCEPTS_COMPRESSION: usize = 1 << 63;
+const ZSTD: usize = 1 << 62;
+const LZ4: usize = 1 << 61;
+pub(super) const FLAGS: usize = ACCEPTS_COMPRESSION | ZSTD | LZ4;
+
+const ZSTD_LEVEL: i32 = 1;
+const DEFAULT_MIN_SIZE_BYTES: usize = 16 * 1024;
+
+/// Bodies that decompress to more than this are rejected, so a small
+/// frame cannot make the peer allocate an unbounded amount of memory.
+const MAX_DECOMPRESSED_SIZE_BYTES: usize = 1024 * 1024 * 1024; // 1GB
+
+static CLIENT_COMPRESSION: AtomicBool = AtomicBool::new(false);
+
+/// Let the clients of services with compression compress their requests
+/// and ask for compressed responses.
+pub fn set_client_compression(enabled: bool) {
+    CLIENT_COMPRESSION.store(enabled, Ordering::Relaxed);
+}
+
+/// The compression a client of a service with the compression uses.
+pub(super) fn client_compression(compression: Option<Compression>) -> Option<Compression> {
+    if CLIENT_COMPRESSION.load(Ordering::Relaxed) {
+        compression
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Zstd,
+    Lz4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub algorithm: Algorithm,
+    /// Bodies smaller than this are sent uncompressed.
+    pub min_size_bytes: usize,
+}
+
+impl Compression {
+    pub const fn zstd() -> Self {
+        Self {
+            algorithm: Algorithm::Zstd,
+            min_size_bytes: DEFAULT_MIN_SIZE_BYTES,
+        }
+    }
+
+    pub const fn lz4() -> Self {
+        Self {
+            algorithm: Algorithm::Lz4,
+            min_size_bytes: DEFAULT_MIN_SIZE_BYTES,
+        }
+    }
+
+    pub const fn with_min_size_bytes(self, min_size_bytes: usize) -> Self {
+        Self {
+            min_size_bytes,
+            ..self
+        }
+    }
+}
+
+/// Compress the body if it is large enough. Returns the body together with the flags for the header.
+pub(super) fn compress(
+    bytes: Vec<u8>,
+    compression: Option<Compression>,
+) -> Result<(Vec<u8>, usize)> {
+    match compression {
+        Some(compression) if bytes.len() >= compression.min_size_bytes => {
+            match compression.algorithm {
+                Algorithm::Zstd => Ok((zstd::bulk::compress(&bytes, ZSTD_LEVEL)?, ZSTD)),
+                Algorithm::Lz4 => Ok((lz4_flex::compress_prepend_size(&bytes), LZ4)),
+            }
+        }
+        _ => Ok((bytes, 0)),
+    }
+}
+
+/// Decompress the body according to the flags of its header.
+pub(super) fn decompress(bytes: Vec<u8>, flags: usize) -> Result<Vec<u8>> {
+    decompress_with_limit(bytes, flags, MAX_DECOMPRESSED_SIZE_BYTES)
+}
+
+fn too_large(limit: usize) -> Error {
+    Error::Other(anyhow!(
+        "decompressed body is too large (max: {limit} bytes)"
+    ))
+}
+
+fn decompress_with_limit(bytes: Vec<u8>, flags: usize, limit: usize) -> Result<Vec<u8>> {
+    if flags & ZSTD != 0 {
+        let mut decompressed = Vec::new();
+        zstd::stream::Decoder::new(bytes.as_slice())?
+            .take(limit as u64 + 1)
+            .read_to_end(&mut decompressed)?;
+
+        if decompressed.len() > limit {
+            return Err(too_large(limit));
+        }
+
+        Ok(decompressed)
+    } else if flags & LZ4 != 0 {
+        // the decompressed size is prepended as a little endian u32
+        let size = bytes
+            .get(..4)
+            .map(|size| u32::from_le_bytes(size.try_into().unwrap()) as usize)
+            .ok_or_else(|| Error::Other(anyhow!("lz4 body is missing its size")))?;
+
+        if size > limit {
+            return Err(too_large(limit));
+        }
+
+        lz4_flex::decompress_size_prepended(&bytes)
+            .map_err(|e| Error::Other(anyhow!("failed to decompress lz4 body: {e}")))
+    } else {
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let bytes = "stract ".repeat(10_000).into_bytes();
+
+        for compression in [Compression::zstd(), Compression::lz4()] {
+            let (compressed, flags) = compress(bytes.clone(), Some(compression)).unwrap();
+            assert_ne!(flags, 0);
+            assert_eq!(flags & ACCEPTS_COMPRESSION, 0);
+            assert!(compressed.len() < bytes.len());
+
+            assert_eq!(decompress(compressed, flags).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn small_bodies_are_not_compressed() {
+        let bytes = b"stract".to_vec();
+
+        let (body, flags) = compress(bytes.clone(), Some(Compression::zstd())).unwrap();
+        assert_eq!(flags, 0);
+        assert_eq!(body, bytes);
+
+        let (_, flags) = compress(
+            bytes.clone(),
+            Some(Compression::zstd().with_min_size_bytes(0)),
+        )
+        .unwrap();
+        assert_ne!(flags, 0);
+
+        let (body, flags) = compress(bytes.clone(), None).unwrap();
+        assert_eq!(flags, 0);
+        assert_eq!(decompress(body, flags).unwrap(), bytes);
+    }
+
+    #[test]
+    fn decompressed_size_is_limited() {
+        let bytes = vec![0; 100_000];
+
+        for compression in [Compression::zstd(), Compression::lz4()] {
+            let (compressed, flags) = compress(bytes.clone(), Some(compression)).unwrap();
+
+            assert!(decompress_with_limit(compressed.clone(), flags, 99_999).is_err());
+            assert_eq!(
+                decompress_with_limit(compressed, flags, 100_000).unwrap(),
+                bytes
+            );
+        }
+
+        assert!(decompress_with_limit(vec![1, 2], LZ4, 100).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Limits how many requests a sonic server handles at once. Requests above the
+//! limit wait in a bounded queue for a free slot, and the requests that do not fit
+//! in the queue or wait too long are shed, so a burst of requests is rejected quickly
+//! instead of piling up until the server runs out of memory.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::ConcurrencyLimitConfig;
+
+pub struct ConcurrencyLimit {
+    inflight: Arc<Semaphore>,
+    queue: Semaphore,
+    queue_timeout: Duration,
+}
+
+impl From<ConcurrencyLimitConfig> for ConcurrencyLimit {
+    fn from(config: ConcurrencyLimitConfig) -> Self {
+        Self {
+            inflight: Arc::new(Semaphore::new(config.max_inflight)),
+            queue: Semaphore::new(config.max_queued),
+            queue_timeout: Duration::from_millis(config.queue_timeout_ms),
+        }
+    }
+}
+
+impl ConcurrencyLimit {
+    /// Wait for a free slot. The request holds the slot until the permit is dropped.
+    ///
+    /// Returns `None` if the request should be shed: the queue is full, or no slot became
+    /// free before the queue timeout or the deadline of the request.
+    pub async fn acquire(&self, deadline: Option<Instant>) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = Arc::clone(&self.inflight).try_acquire_owned() {
+            return Some(permit);
+        }
+
+        let _queued = self.queue.try_acquire().ok()?;
+
+        let timeout = match deadline {
+            Some(deadline) => self
+                .queue_timeout
+                .min(deadline.saturating_duration_since(Instant::now())),
+            None => self.queue_timeout,
+        };
+
+        tokio::time::timeout(timeout, Arc::clone(&self.inflight).acquire_owned())
+            .await
+            .ok()?
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(max_inflight: usize, max_queued: usize, queue_timeout_ms: u64) -> ConcurrencyLimit {
+        ConcurrencyLimit::from(ConcurrencyLimitConfig {
+            max_inflight,
+            max_queued,
+            queue_timeout_ms,
+        })
+    }
+
+    #[tokio::test]
+    async fn sheds_requests_above_the_limit() {
+        let limit = limit(2, 0, 1_000);
+
+        let first = limit.acquire(None).await;
+        let second = limit.acquire(None).await;
+        assert!(first.is_some());
+        assert!(second.is_some());
+
+        // the queue is empty, so the request is shed right away
+        assert!(limit.acquire(None).await.is_none());
+
+        drop(first);
+        assert!(limit.acquire(None).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn queued_requests_wait_for_a_slot() {
+        let limit = Arc::new(limit(1, 1, 10_000));
+        let permit = limit.acquire(None).await.unwrap();
+
+        let queued = tokio::spawn({
+            let limit = Arc::clone(&limit);
+            async move { limit.acquire(None).await.is_some() }
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // the queue is full
+        assert!(limit.acquire(None).await.is_none());
+
+        drop(permit);
+        assert!(queued.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn queued_requests_time_out() {
+        let short = limit(1, 1, 50);
+        let _permit = short.acquire(None).await.unwrap();
+
+        assert!(short.acquire(None).await.is_none());
+
+        // the deadline is earlier than the queue timeout
+        let long = limit(1, 1, 60_000);
+        let _permit = long.acquire(None).await.unwrap();
+        let start = Instant::now();
+        assert!(long
+            .acquire(Some(Instant::now() + Duration::from_millis(50)))
+            .await
+            .is_none());
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,260 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Metrics of the requests that a sonic server handles, labelled by the
+//! service, the message and the shard of the server, and of the responses
+//! that the clients in the process receive.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::metrics::{self, Counter, Label, PrometheusRegistry};
+
+/// The name and help of the prometheus groups, in the order of [`MessageMetrics::counters`].
+const GROUPS: [(&str, &str); 6] = [
+    ("stract_sonic_requests", "Number of handled sonic requests."),
+    (
+        "stract_sonic_request_errors",
+        "Number of sonic requests that failed.",
+    ),
+    (
+        "stract_sonic_request_bytes_in",
+        "Total size of the sonic requests in bytes.",
+    ),
+    (
+        "stract_sonic_request_bytes_out",
+        "Total size of the sonic responses in bytes.",
+    ),
+    (
+        "stract_sonic_request_latency_microseconds",
+        "Total time spent handling and responding to sonic requests.",
+    ),
+    (
+        "stract_sonic_requests_shed",
+        "Number of sonic requests rejected because the server was overloaded.",
+    ),
+];
+
+#[derive(Default)]
+struct MessageMetrics {
+    requests: Counter,
+    errors: Counter,
+    bytes_in: Counter,
+    bytes_out: Counter,
+    latency_micros: Counter,
+    shed: Counter,
+}
+
+impl MessageMetrics {
+    fn counters(&self) -> [&Counter; 6] {
+        [
+            &self.requests,
+            &self.errors,
+            &self.bytes_in,
+            &self.bytes_out,
+            &self.latency_micros,
+            &self.shed,
+        ]
+    }
+}
+
+pub struct ServerMetrics {
+    service: &'static str,
+    shard: Option<String>,
+    messages: BTreeMap<&'static str, MessageMetrics>,
+}
+
+impl ServerMetrics {
+    pub fn new(service: &'static str, messages: &[&'static str]) -> Self {
+        Self {
+            service,
+            shard: None,
+            messages: messages
+                .iter()
+                .map(|message| (*message, MessageMetrics::default()))
+                .collect(),
+        }
+    }
+
+    pub fn with_shard(mut self, shard: String) -> Self {
+        self.shard = Some(shard);
+        self
+    }
+
+    pub fn service(&self) -> &'static str {
+        self.service
+    }
+
+    pub fn shard(&self) -> Option<&str> {
+        self.shard.as_deref()
+    }
+
+    /// Record a handled request. `bytes_out` is 0 if the request failed before
+    /// the response was written.
+    pub fn record(
+        &self,
+        message: &str,
+        bytes_in: usize,
+        bytes_out: usize,
+        latency: Duration,
+        is_error: bool,
+    ) {
+        let Some(metrics) = self.messages.get(message) else {
+            return;
+        };
+
+        metrics.requests.inc();
+        metrics.bytes_in.add(bytes_in as u64);
+        metrics.bytes_out.add(bytes_out as u64);
+        metrics.latency_micros.add(latency.as_micros() as u64);
+
+        if is_error {
+            metrics.errors.inc();
+        }
+    }
+
+    /// Record a request that was rejected without being handled.
+    pub fn record_shed(&self, message: &str) {
+        if let Some(metrics) = self.messages.get(message) {
+            metrics.shed.inc();
+        }
+    }
+
+    fn labels(&self, message: &str) -> Vec<Label> {
+        let mut labels = vec![
+            Label {
+                key: "service".to_string(),
+                val: self.service.to_string(),
+            },
+            Label {
+                key: "message".to_string(),
+                val: message.to_string(),
+            },
+        ];
+
+        if let Some(shard) = &self.shard {
+            labels.push(Label {
+                key: "shard".to_string(),
+                val: shard.clone(),
+            });
+        }
+
+        labels
+    }
+
+    pub fn register(&self, registry: &mut PrometheusRegistry) -> Result<(), metrics::Error> {
+        for (i, (name, help)) in GROUPS.iter().enumerate() {
+            let group = registry.new_group(name.to_string(), Some(help.to_string()))?;
+
+            for (message, metrics) in &self.messages {
+                group.register(metrics.counters()[i].clone(), self.labels(message));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Metrics of the responses from the sonic servers, shared by all the clients in the process.
+#[derive(Default)]
+pub struct ClientMetrics {
+    deserialization_failures: Counter,
+}
+
+static CLIENT_METRICS: once_cell::sync::Lazy<ClientMetrics> =
+    once_cell::sync::Lazy::new(ClientMetrics::default);
+
+pub fn client() -> &'static ClientMetrics {
+    &CLIENT_METRICS
+}
+
+impl ClientMetrics {
+    pub fn record_deserialization_failure(&self) {
+        self.deserialization_failures.inc();
+    }
+
+    pub fn register(&self, registry: &mut PrometheusRegistry) -> Result<(), metrics::Error> {
+        registry
+            .new_group(
+                "stract_sonic_client_deserialization_failures".to_string(),
+                Some("Number of sonic responses that could not be deserialized.".to_string()),
+            )?
+            .register(self.deserialization_failures.clone(), vec![]);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_requests_by_message() {
+        let metrics = ServerMetrics::new("SearchService", &["Search", "RetrieveWebsites"])
+            .with_shard("3".to_string());
+
+        metrics.record("Search", 100, 1_000, Duration::from_millis(2), false);
+        metrics.record("Search", 50, 0, Duration::from_millis(1), true);
+        metrics.record("Unknown", 10, 10, Duration::from_millis(1), false);
+        metrics.record_shed("Search");
+
+        let mut registry = PrometheusRegistry::default();
+        metrics.register(&mut registry).unwrap();
+        let output = registry.to_string();
+
+        let value = |name: &str, message: &str| {
+            let prefix =
+                format!("{name}{{service=\"SearchService\",message=\"{message}\",shard=\"3\"}} ");
+
+            output
+                .lines()
+                .find_map(|line| line.strip_prefix(&prefix))
+                .and_then(|rest| rest.split(' ').next())
+                .map(|val| val.parse::<u64>().unwrap())
+        };
+
+        assert_eq!(value("stract_sonic_requests", "Search"), Some(2));
+        assert_eq!(value("stract_sonic_request_errors", "Search"), Some(1));
+        assert_eq!(value("stract_sonic_request_bytes_in", "Search"), Some(150));
+        assert_eq!(
+            value("stract_sonic_request_bytes_out", "Search"),
+            Some(1_000)
+        );
+        assert_eq!(
+            value("stract_sonic_request_latency_microseconds", "Search"),
+            Some(3_000)
+        );
+        assert_eq!(value("stract_sonic_requests_shed", "Search"), Some(1));
+        assert_eq!(value("stract_sonic_requests", "RetrieveWebsites"), Some(0));
+
+        assert!(metrics.register(&mut registry).is_err());
+    }
+
+    #[test]
+    fn client_metrics_are_registered() {
+        let metrics = ClientMetrics::default();
+        metrics.record_deserialization_failure();
+        metrics.record_deserialization_failure();
+
+        let mut registry = PrometheusRegistry::default();
+        metrics.register(&mut registry).unwrap();
+
+        assert!(registry
+            .to_string()
+            .lines()
+            .any(|line| line.starts_with("stract_sonic_client_deserialization_failures 2")));
+    }
+}
@@ -14,21 +14,63 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+pub mod codec;
+pub mod compression;
+pub mod limit;
+pub mod metrics;
 pub mod replication;
 pub mod service;
+pub mod tls;
 
-use std::{marker::PhantomData, time::Duration};
+use std::{
+    marker::PhantomData,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
 
+use futures::StreamExt;
 use serde::{de::DeserializeOwned, Serialize};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::{TcpListener, TcpStream, ToSocketAddrs},
 };
+use tokio_rustls::TlsAcceptor;
+
+use self::{
+    codec::Format,
+    compression::Compression,
+    tls::{SharedSecret, Stream, Tls, TlsClient},
+};
+use super::net;
 
 pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
 
 const MAX_BODY_SIZE_BYTES: usize = 1024 * 1024 * 1024 * 1024; // 1TB
 
+/// The header of a request is followed by the milliseconds the client waits for the response.
+const DEADLINE: usize = 1 << 60;
+/// The header of a request is followed by the digest of the shared secret, after the deadline.
+const SECRET: usize = 1 << 59;
+/// A response without a body, sent instead of the response when the server sheds the request.
+const OVERLOADED: usize = 1 << 58;
+/// The header of a request is followed by the schema version of the messages of the client,
+/// after the secret. Requests without a version have version 0.
+const SCHEMA: usize = 1 << 55;
+/// A response with the schema versions the server can read as its body, sent instead of
+/// the response when the server cannot read the version of the request.
+const INCOMPATIBLE: usize = 1 << 54;
+/// A response without a body, sent instead of the response when the server cannot read
+/// the serialization format of the request.
+const UNSUPPORTED_FORMAT: usize = 1 << 53;
+const FLAGS: usize = compression::FLAGS
+    | codec::FLAGS
+    | DEADLINE
+    | SECRET
+    | OVERLOADED
+    | SCHEMA
+    | INCOMPATIBLE
+    | UNSUPPORTED_FORMAT;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Got an IO error")]
@@ -37,6 +79,9 @@ pub enum Error {
     #[error("Error while serializing/deserializing to/from bytes")]
     Serialization(#[from] bincode::Error),
 
+    #[error("Failed to decode the message: {0}")]
+    Decode(String),
+
     #[error("Failed to connect to peer: connection timeout")]
     ConnectionTimeout,
 
@@ -49,12 +94,64 @@ pub enum Error {
     #[error("The request could not be processed")]
     BadRequest,
 
+    #[error("Not enough replicas agreed on a response")]
+    NoQuorum,
+
+    #[error("The deadline of the request has passed")]
+    DeadlineExceeded,
+
+    #[error("The request did not have the shared secret of the cluster")]
+    Unauthorized,
+
+    #[error("The server is overloaded and rejected the request")]
+    Overloaded,
+
+    #[error("The server only reads message schema versions {min} to {max}, which does not include the version of the request")]
+    IncompatibleSchema { min: u32, max: u32 },
+
+    #[error("The server cannot read the serialization format of the request")]
+    UnsupportedFormat,
+
+    #[error("TLS error")]
+    Tls(#[from] tokio_rustls::rustls::Error),
+
     #[error("Other")]
     Other(#[from] anyhow::Error),
 }
 
+impl Error {
+    /// Whether the message could not be deserialized, e.g. because the peer
+    /// runs a different version of the service or the message was corrupted.
+    pub fn is_deserialization(&self) -> bool {
+        matches!(
+            self,
+            Error::Serialization(_) | Error::Decode(_) | Error::IncompatibleSchema { .. }
+        )
+    }
+}
+
+/// The versions of the message schema that a server can read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SchemaVersions {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl SchemaVersions {
+    fn contains(&self, version: u32) -> bool {
+        (self.min..=self.max).contains(&version)
+    }
+}
+
 pub struct Connection<Req, Res> {
-    stream: TcpStream,
+    stream: Stream,
+    codec: Format,
+    /// Whether the connection has not been used for a request before.
+    is_new: bool,
+    schema_version: u32,
+    compression: Option<Compression>,
+    deadline: Option<Instant>,
+    secret: Option<SharedSecret>,
     marker: PhantomData<(Req, Res)>,
 }
 
@@ -71,53 +168,205 @@ where
         server: impl ToSocketAddrs,
         timeout: Duration,
     ) -> Result<Self> {
-        match tokio::time::timeout(timeout, TcpStream::connect(server)).await {
-            Ok(stream) => {
-                let stream = stream?;
-                Ok(Connection {
-                    stream,
-                    marker: PhantomData,
-                })
-            }
+        Self::create_with_tls(server, timeout, None).await
+    }
+
+    /// Connect with TLS if `tls` is set.
+    pub async fn create_with_tls(
+        server: impl ToSocketAddrs,
+        timeout: Duration,
+        tls: Option<&TlsClient>,
+    ) -> Result<Self> {
+        let connect = async {
+            let stream = net::connect(server).await?;
+            tls::connect(tls, stream).await
+        };
+
+        match tokio::time::timeout(timeout, connect).await {
+            Ok(stream) => Ok(Connection::from_stream(stream?)),
             Err(_) => Err(Error::ConnectionTimeout),
         }
     }
 
-    async fn send_without_timeout(mut self, request: &Req) -> Result<Res> {
-        let bytes = bincode::serialize(&request).unwrap();
+    pub(super) fn from_stream(stream: Stream) -> Self {
+        Connection {
+            stream,
+            codec: Format::default(),
+            is_new: true,
+            schema_version: 0,
+            compression: None,
+            deadline: None,
+            secret: None,
+            marker: PhantomData,
+        }
+    }
+
+    /// Send the shared secret with the requests.
+    pub fn with_secret(mut self, secret: Option<SharedSecret>) -> Self {
+        self.secret = secret;
+        self
+    }
+
+    /// Serialize the requests in the format. The server responds in the format of the request.
+    /// Requests to servers that have rejected the format are serialized with bincode instead.
+    pub fn with_codec(mut self, codec: Format) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Whether the connection has been used for an earlier request, e.g. when it is taken from a pool.
+    pub(super) fn with_reused(mut self, is_reused: bool) -> Self {
+        self.is_new = !is_reused;
+        self
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.stream.tcp().peer_addr().ok()
+    }
+
+    /// Send the schema version of the messages with the requests, so servers that cannot
+    /// read the messages reject them instead of failing to deserialize them. Version 0 is
+    /// not sent, so clients without a version can still talk to servers that predate versions.
+    pub fn with_schema_version(mut self, version: u32) -> Self {
+        self.schema_version = version;
+        self
+    }
+
+    /// Compress the requests and accept compressed responses. Only servers that
+    /// understand compressed frames can be sent compressed requests, so the servers
+    /// must be upgraded before compression is enabled on the clients.
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Tell the server how long the client waits for the responses, so it can give up early.
+    /// Only servers that understand deadlines can be sent one, so the servers must be
+    /// upgraded before deadlines are set on the clients.
+    pub fn with_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    pub(super) fn into_stream(self) -> Stream {
+        self.stream
+    }
+
+    async fn exchange(&mut self, request: &Req) -> Result<Res> {
+        let server = self.peer_addr();
+        let codec = self.codec.negotiate(server);
+
+        let res = async {
+            write_frame(
+                &mut self.stream,
+                request,
+                codec,
+                self.compression,
+                self.compression.is_some(),
+                self.deadline,
+                self.secret.as_ref(),
+                self.schema_version,
+            )
+            .await?;
+
+            let header = read_header(&mut self.stream).await?;
+            read_body(&mut self.stream, header).await
+        }
+        .await
+        .map_err(|e| codec.check_rejection(server, self.is_new, e))?;
+
+        self.is_new = false;
+
+        tracing::debug!("deserializing {:?}", std::any::type_name::<(Req, Res)>());
+        Ok(res)
+    }
+
+    /// Write all the requests before the responses have arrived. The server answers the
+    /// requests on a connection in order, so the responses are read in the same order
+    /// while the remaining requests are written.
+    async fn exchange_batch(&mut self, requests: &[Req]) -> Result<Vec<Res>> {
+        let server = self.peer_addr();
+        let is_new = self.is_new;
+        let codec = self.codec.negotiate(server);
+        let compression = self.compression;
+        let deadline = self.deadline;
+        let secret = self.secret.as_ref();
+        let schema_version = self.schema_version;
+        let (mut reader, mut writer) = tokio::io::split(&mut self.stream);
+
+        let write = async {
+            for request in requests {
+                write_frame(
+                    &mut writer,
+                    request,
+                    codec,
+                    compression,
+                    compression.is_some(),
+                    deadline,
+                    secret,
+                    schema_version,
+                )
+                .await?;
+            }
+
+            Ok::<_, Error>(())
+        };
+
+        let read = async {
+            let mut responses = Vec::with_capacity(requests.len());
 
+            for _ in requests {
+                let header = read_header(&mut reader).await?;
+                responses.push(read_body(&mut reader, header).await?);
+            }
+
+            Ok::<_, Error>(responses)
+        };
+
+        let ((), responses) = futures::try_join!(write, read)
+            .map_err(|e| codec.check_rejection(server, is_new, e))?;
+
+        self.is_new = false;
+
+        Ok(responses)
+    }
+
+    pub async fn send_without_timeout(mut self, request: &Req) -> Result<Res> {
         // disable linger to avoid TIME_WAIT.
         // should be safe since the connection is closed from the client side.
         // No stray packets should therefore find its way to the socket.
-        self.stream.set_linger(Some(Duration::from_secs(0)))?;
+        self.stream.tcp().set_linger(Some(Duration::from_secs(0)))?;
 
-        let header = Header {
-            body_size: bytes.len(),
-        };
+        let res = self.exchange(request).await?;
 
-        self.stream.write_all(bytemuck::bytes_of(&header)).await?;
-        self.stream.write_all(&bytes).await?;
         self.stream.flush().await?;
+        self.stream.shutdown().await?;
 
-        let mut header_buf = vec![0; std::mem::size_of::<Header>()];
-        self.stream.read_exact(&mut header_buf).await?;
-        let header: Header = *bytemuck::from_bytes(&header_buf);
+        Ok(res)
+    }
 
-        if header.body_size > MAX_BODY_SIZE_BYTES {
-            return Err(Error::Other(anyhow::anyhow!(
-                "body size too large: {} (max: {})",
-                header.body_size,
-                MAX_BODY_SIZE_BYTES
-            )));
+    /// Send the request without closing the connection afterwards, so it can be
+    /// used for the next request. The server must keep the connection open between requests.
+    pub async fn send_keepalive(&mut self, request: &Req, timeout: Duration) -> Result<Res> {
+        match tokio::time::timeout(timeout, self.exchange(request)).await {
+            Ok(res) => res,
+            Err(_) => Err(Error::RequestTimeout),
         }
+    }
 
-        let mut buf = vec![0; header.body_size];
-        self.stream.read_exact(&mut buf).await?;
-        self.stream.flush().await?;
-        self.stream.shutdown().await?;
-
-        tracing::debug!("deserializing {:?}", std::any::type_name::<(Req, Res)>());
-        Ok(bincode::deserialize(&buf).unwrap())
+    /// Send all the requests on the connection without waiting for the response to each
+    /// request before sending the next, and keep the connection open like
+    /// [`Connection::send_keepalive`]. The responses are in the order of the requests,
+    /// and `timeout` applies to the whole batch.
+    pub async fn send_batch_keepalive(
+        &mut self,
+        requests: &[Req],
+        timeout: Duration,
+    ) -> Result<Vec<Res>> {
+        match tokio::time::timeout(timeout, self.exchange_batch(requests)).await {
+            Ok(res) => res,
+            Err(_) => Err(Error::RequestTimeout),
+        }
     }
 
     pub async fn send(self, request: &Req) -> Result<Res> {
@@ -131,16 +380,351 @@ where
             Err(_) => Err(Error::RequestTimeout),
         }
     }
+
+    /// Send a request that the server responds to in chunks with [`Request::write_stream`].
+    /// The chunks are read as the stream is polled, and `timeout` applies to each chunk.
+    /// The connection is closed when the stream is dropped, since unread chunks would be
+    /// mistaken for the response to the next request.
+    pub async fn send_streaming(
+        mut self,
+        request: &Req,
+        timeout: Duration,
+    ) -> Result<impl futures::Stream<Item = Result<Res>>> {
+        let server = self.peer_addr();
+        let codec = self.codec.negotiate(server);
+
+        tokio::time::timeout(
+            timeout,
+            write_frame(
+                &mut self.stream,
+                request,
+                codec,
+                self.compression,
+                self.compression.is_some(),
+                self.deadline,
+                self.secret.as_ref(),
+                self.schema_version,
+            ),
+        )
+        .await
+        .map_err(|_| Error::RequestTimeout)?
+        .map_err(|e| codec.check_rejection(server, self.is_new, e))?;
+
+        Ok(futures::stream::unfold(
+            Some(self),
+            move |conn| async move {
+                let mut conn = conn?;
+
+                match tokio::time::timeout(timeout, conn.read_chunk()).await {
+                    Ok(Ok(Some(chunk))) => Some((Ok(chunk), Some(conn))),
+                    Ok(Ok(None)) => None,
+                    Ok(Err(e)) => Some((Err(e), None)),
+                    Err(_) => Some((Err(Error::RequestTimeout), None)),
+                }
+            },
+        ))
+    }
+
+    /// Read the next chunk of a streamed response. `None` marks the end of the stream.
+    async fn read_chunk(&mut self) -> Result<Option<Res>> {
+        let header = read_header(&mut self.stream).await?;
+        read_body(&mut self.stream, header).await
+    }
 }
 
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 #[repr(C)]
 struct Header {
+    /// The high bits are the flags for compression, see [`compression`], the serialization
+    /// format, see [`codec`], and deadlines.
     body_size: usize,
 }
 
+impl Header {
+    fn size(&self) -> usize {
+        self.body_size & !FLAGS
+    }
+
+    fn flags(&self) -> usize {
+        self.body_size & FLAGS
+    }
+
+    fn has_deadline(&self) -> bool {
+        self.body_size & DEADLINE != 0
+    }
+
+    fn has_secret(&self) -> bool {
+        self.body_size & SECRET != 0
+    }
+
+    fn is_overloaded(&self) -> bool {
+        self.body_size & OVERLOADED != 0
+    }
+
+    fn has_schema(&self) -> bool {
+        self.body_size & SCHEMA != 0
+    }
+
+    fn is_incompatible(&self) -> bool {
+        self.body_size & INCOMPATIBLE != 0
+    }
+
+    fn is_unsupported_format(&self) -> bool {
+        self.body_size & UNSUPPORTED_FORMAT != 0
+    }
+
+    fn codec(&self) -> Result<Format> {
+        Format::from_flags(self.flags())
+    }
+
+    fn accepts_compression(&self) -> bool {
+        self.body_size & compression::ACCEPTS_COMPRESSION != 0
+    }
+
+    /// Number of bytes of the frame, including the header.
+    fn frame_size(&self) -> usize {
+        let deadline = if self.has_deadline() { 8 } else { 0 };
+        let secret = if self.has_secret() { 32 } else { 0 };
+        let schema = if self.has_schema() { 4 } else { 0 };
+        std::mem::size_of::<Header>() + deadline + secret + schema + self.size()
+    }
+}
+
+async fn write_frame<W: AsyncWrite + Unpin, T: Serialize>(
+    stream: &mut W,
+    value: &T,
+    codec: Format,
+    compression: Option<Compression>,
+    accepts_compression: bool,
+    deadline: Option<Instant>,
+    secret: Option<&SharedSecret>,
+    schema_version: u32,
+) -> Result<usize> {
+    let (bytes, mut flags) = compression::compress(codec.serialize(value)?, compression)?;
+    flags |= codec.flags();
+
+    if accepts_compression {
+        flags |= compression::ACCEPTS_COMPRESSION;
+    }
+
+    if deadline.is_some() {
+        flags |= DEADLINE;
+    }
+
+    if secret.is_some() {
+        flags |= SECRET;
+    }
+
+    if schema_version != 0 {
+        flags |= SCHEMA;
+    }
+
+    let header = Header {
+        body_size: bytes.len() | flags,
+    };
+
+    stream.write_all(bytemuck::bytes_of(&header)).await?;
+
+    // the remaining time is sent instead of the deadline, as the clocks of the peers can differ
+    if let Some(deadline) = deadline {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        stream
+            .write_all(&(remaining.as_millis() as u64).to_le_bytes())
+            .await?;
+    }
+    if let Some(secret) = secret {
+        stream.write_all(secret.as_bytes()).await?;
+    }
+    if schema_version != 0 {
+        stream.write_all(&schema_version.to_le_bytes()).await?;
+    }
+    stream.write_all(&bytes).await?;
+    stream.flush().await?;
+
+    Ok(header.frame_size())
+}
+
+async fn read_header<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Header> {
+    let mut header_buf = vec![0; std::mem::size_of::<Header>()];
+    stream.read_exact(&mut header_buf).await?;
+    let header: Header = *bytemuck::from_bytes(&header_buf);
+
+    if header.size() > MAX_BODY_SIZE_BYTES {
+        return Err(Error::Other(anyhow::anyhow!(
+            "body size too large: {} (max: {})",
+            header.size(),
+            MAX_BODY_SIZE_BYTES
+        )));
+    }
+
+    Ok(header)
+}
+
+async fn read_body<R: AsyncRead + Unpin, T: DeserializeOwned>(
+    stream: &mut R,
+    header: Header,
+) -> Result<T> {
+    if header.is_overloaded() {
+        return Err(Error::Overloaded);
+    }
+
+    if header.is_unsupported_format() {
+        return Err(Error::UnsupportedFormat);
+    }
+
+    if header.is_incompatible() {
+        let mut buf = [0; 8];
+        stream.read_exact(&mut buf).await?;
+        let (min, max) = buf.split_at(4);
+
+        return Err(Error::IncompatibleSchema {
+            min: u32::from_le_bytes(min.try_into().unwrap()),
+            max: u32::from_le_bytes(max.try_into().unwrap()),
+        });
+    }
+
+    let mut buf = vec![0; header.size()];
+    stream.read_exact(&mut buf).await?;
+    let buf = compression::decompress(buf, header.flags())?;
+
+    header.codec()?.deserialize(&buf)
+}
+
+/// Read the body of a request together with its deadline, if the client set one.
+/// The request is rejected without reading the body if the server has a secret that
+/// the client did not send. Requests with a schema version the server cannot read are
+/// answered with the versions the server can read instead of being deserialized, and
+/// requests in a format the server is not built with are rejected.
+async fn read_request<T: DeserializeOwned>(
+    stream: &mut Stream,
+    header: Header,
+    secret: Option<&SharedSecret>,
+    schema: SchemaVersions,
+) -> Result<(T, Option<Instant>)> {
+    let deadline = if header.has_deadline() {
+        let mut buf = [0; 8];
+        stream.read_exact(&mut buf).await?;
+        Some(Instant::now() + Duration::from_millis(u64::from_le_bytes(buf)))
+    } else {
+        None
+    };
+
+    let digest = if header.has_secret() {
+        let mut buf = [0; 32];
+        stream.read_exact(&mut buf).await?;
+        Some(buf)
+    } else {
+        None
+    };
+
+    if let Some(secret) = secret {
+        if !digest.is_some_and(|digest| secret.verify(&digest)) {
+            return Err(Error::Unauthorized);
+        }
+    }
+
+    let version = if header.has_schema() {
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await?;
+        u32::from_le_bytes(buf)
+    } else {
+        0
+    };
+
+    if !schema.contains(version) {
+        reject_incompatible(stream, header, schema).await?;
+        return Err(Error::IncompatibleSchema {
+            min: schema.min,
+            max: schema.max,
+        });
+    }
+
+    if header.codec().is_err() {
+        reject_unsupported_format(stream, header).await?;
+        return Err(Error::UnsupportedFormat);
+    }
+
+    Ok((read_body(stream, header).await?, deadline))
+}
+
+/// Tell the client which schema versions the server can read. The body of the request is
+/// read first, so the client is not blocked on sending it.
+async fn reject_incompatible(
+    stream: &mut Stream,
+    header: Header,
+    schema: SchemaVersions,
+) -> Result<()> {
+    tokio::io::copy(
+        &mut (&mut *stream).take(header.size() as u64),
+        &mut tokio::io::sink(),
+    )
+    .await?;
+
+    let mut body = Vec::with_capacity(8);
+    body.extend_from_slice(&schema.min.to_le_bytes());
+    body.extend_from_slice(&schema.max.to_le_bytes());
+
+    let header = Header {
+        body_size: body.len() | INCOMPATIBLE,
+    };
+
+    stream.write_all(bytemuck::bytes_of(&header)).await?;
+    stream.write_all(&body).await?;
+    stream.flush().await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+/// Tell the client that the server cannot read the format of the request, so it falls
+/// back to bincode. Like [`reject_incompatible`], the body of the request is read first.
+async fn reject_unsupported_format(stream: &mut Stream, header: Header) -> Result<()> {
+    tokio::io::copy(
+        &mut (&mut *stream).take(header.size() as u64),
+        &mut tokio::io::sink(),
+    )
+    .await?;
+
+    let header = Header {
+        body_size: UNSUPPORTED_FORMAT,
+    };
+
+    stream.write_all(bytemuck::bytes_of(&header)).await?;
+    stream.flush().await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+/// Completes when the process is asked to stop with SIGINT or SIGTERM, so the
+/// servers can shut down gracefully instead of dropping the requests they are handling.
+pub async fn shutdown_signal() {
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+
+    tracing::info!("received shutdown signal");
+}
+
 pub struct Server<Req, Res> {
     pub(super) listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    compression: Option<Compression>,
+    secret: Option<SharedSecret>,
+    schema: SchemaVersions,
     marker: PhantomData<(Req, Res)>,
 }
 
@@ -149,35 +733,57 @@ where
     Req: DeserializeOwned,
 {
     pub async fn bind(addr: impl ToSocketAddrs) -> Result<Self> {
-        let listener = TcpListener::bind(addr).await?;
+        Self::bind_with_tls(addr, None).await
+    }
+
+    /// Only accept TLS connections if `tls` is set.
+    pub async fn bind_with_tls(addr: impl ToSocketAddrs, tls: Option<TlsAcceptor>) -> Result<Self> {
+        let listener = net::bind(addr).await?;
         Ok(Server {
             listener,
+            tls,
+            compression: None,
+            secret: None,
+            schema: SchemaVersions::default(),
             marker: PhantomData,
         })
     }
 
-    async fn parse_incoming_stream(&self, mut stream: TcpStream) -> Result<Request<Req, Res>> {
-        let mut header_buf = vec![0; std::mem::size_of::<Header>()];
-        stream.read_exact(&mut header_buf).await?;
-        let header: Header = *bytemuck::from_bytes(&header_buf);
-
-        if header.body_size > MAX_BODY_SIZE_BYTES {
-            return Err(Error::Other(anyhow::anyhow!(
-                "body size too large: {} (max: {})",
-                header.body_size,
-                MAX_BODY_SIZE_BYTES
-            )));
-        }
+    /// Reject the requests that do not have the secret.
+    pub fn with_secret(mut self, secret: Option<SharedSecret>) -> Self {
+        self.secret = secret;
+        self
+    }
 
-        let mut buf = vec![0; header.body_size];
+    /// Reject the requests with a schema version the server cannot read.
+    pub fn with_schema_versions(mut self, schema: SchemaVersions) -> Self {
+        self.schema = schema;
+        self
+    }
 
-        stream.read_exact(&mut buf).await?;
+    /// Compress the responses to the clients that accept compressed frames.
+    /// Compressed requests are always accepted.
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
 
-        let body = Some(bincode::deserialize(&buf).unwrap());
+    async fn parse_incoming_stream(&self, stream: TcpStream) -> Result<Request<Req, Res>> {
+        let mut stream = tls::accept(self.tls.as_ref(), stream).await?;
+        let header = read_header(&mut stream).await?;
+        let (body, deadline) =
+            read_request(&mut stream, header, self.secret.as_ref(), self.schema).await?;
 
         Ok(Request {
             stream,
-            body,
+            body: Some(body),
+            codec: header.codec()?,
+            compression: self.compression,
+            accepts_compression: header.accepts_compression(),
+            deadline,
+            bytes_in: header.frame_size(),
+            secret: self.secret,
+            schema: self.schema,
             marker: PhantomData,
         })
     }
@@ -193,8 +799,16 @@ where
 }
 
 pub struct Request<Req, Res> {
-    stream: TcpStream,
+    stream: Stream,
     body: Option<Req>,
+    /// The format of the request, which the response is written in.
+    codec: Format,
+    compression: Option<Compression>,
+    accepts_compression: bool,
+    deadline: Option<Instant>,
+    bytes_in: usize,
+    secret: Option<SharedSecret>,
+    schema: SchemaVersions,
     marker: PhantomData<(Req, Res)>,
 }
 
@@ -202,15 +816,24 @@ impl<Req, Res> Request<Req, Res>
 where
     Res: Serialize,
 {
-    async fn respond_without_timeout(mut self, response: Res) -> Result<()> {
-        let bytes = bincode::serialize(&response).unwrap();
-        let header = Header {
-            body_size: bytes.len(),
-        };
+    /// The response is only compressed if the client accepts compressed frames.
+    fn response_compression(&self) -> Option<Compression> {
+        self.compression.filter(|_| self.accepts_compression)
+    }
 
-        self.stream.write_all(bytemuck::bytes_of(&header)).await?;
-        self.stream.write_all(&bytes).await?;
-        self.stream.flush().await?;
+    async fn respond_without_timeout(mut self, response: Res) -> Result<()> {
+        let compression = self.response_compression();
+        write_frame(
+            &mut self.stream,
+            &response,
+            self.codec,
+            compression,
+            false,
+            None,
+            None,
+            0,
+        )
+        .await?;
 
         // wait for client to close connection
         let mut buf: [u8; 1] = [0];
@@ -230,15 +853,180 @@ where
         .map_err(|_| Error::RequestTimeout)?
     }
 
+    /// Respond to the request and keep the connection open, so the next request on the same
+    /// connection can be read with [`Request::next_request`]. Returns the number of bytes written.
+    pub async fn write_response(&mut self, response: &Res) -> Result<usize> {
+        let compression = self.response_compression();
+        tokio::time::timeout(
+            Duration::from_secs(90),
+            write_frame(
+                &mut self.stream,
+                response,
+                self.codec,
+                compression,
+                false,
+                None,
+                None,
+                0,
+            ),
+        )
+        .await
+        .map_err(|_| Error::RequestTimeout)?
+    }
+
+    /// Respond with the chunks one at a time followed by the end of the stream, and keep the
+    /// connection open like [`Request::write_response`]. The client reads the chunks with
+    /// [`Connection::send_streaming`].
+    pub async fn write_stream(
+        &mut self,
+        chunks: impl futures::Stream<Item = Result<Res>>,
+    ) -> Result<usize> {
+        let compression = self.response_compression();
+        let mut chunks = std::pin::pin!(chunks);
+        let mut bytes_out = 0;
+
+        while let Some(chunk) = chunks.next().await {
+            bytes_out += tokio::time::timeout(
+                Duration::from_secs(90),
+                write_frame(
+                    &mut self.stream,
+                    &Some(chunk?),
+                    self.codec,
+                    compression,
+                    false,
+                    None,
+                    None,
+                    0,
+                ),
+            )
+            .await
+            .map_err(|_| Error::RequestTimeout)??;
+        }
+
+        bytes_out += tokio::time::timeout(
+            Duration::from_secs(90),
+            write_frame(
+                &mut self.stream,
+                &None::<Res>,
+                self.codec,
+                compression,
+                false,
+                None,
+                None,
+                0,
+            ),
+        )
+        .await
+        .map_err(|_| Error::RequestTimeout)??;
+
+        Ok(bytes_out)
+    }
+
+    /// Tell the client that the server is overloaded instead of responding to the request.
+    /// The connection is closed afterwards.
+    pub async fn reject_overloaded(mut self) -> Result<usize> {
+        let header = Header {
+            body_size: OVERLOADED,
+        };
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            self.stream.write_all(bytemuck::bytes_of(&header)).await?;
+            self.stream.flush().await?;
+            self.stream.shutdown().await
+        })
+        .await
+        .map_err(|_| Error::RequestTimeout)??;
+
+        Ok(header.frame_size())
+    }
+
+    /// Wait for the next request on the same connection for at most `idle_timeout`. `None` if
+    /// the client closed the connection or sent nothing before the idle timeout.
+    pub async fn next_request(mut self, idle_timeout: Duration) -> Result<Option<Self>>
+    where
+        Req: DeserializeOwned,
+    {
+        let header = match tokio::time::timeout(idle_timeout, read_header(&mut self.stream)).await {
+            Ok(Ok(header)) => header,
+            // clients that do not reuse the connection close it after the response
+            Ok(Err(Error::IO(e)))
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::ConnectionReset
+                ) =>
+            {
+                return Ok(None)
+            }
+            Ok(Err(e)) => return Err(e),
+            Err(_) => return Ok(None),
+        };
+
+        let (body, deadline) = tokio::time::timeout(
+            Duration::from_secs(60),
+            read_request(&mut self.stream, header, self.secret.as_ref(), self.schema),
+        )
+        .await
+        .map_err(|_| Error::ConnectionTimeout)??;
+
+        Ok(Some(Request {
+            stream: self.stream,
+            body: Some(body),
+            codec: header.codec()?,
+            compression: self.compression,
+            accepts_compression: header.accepts_compression(),
+            deadline,
+            bytes_in: header.frame_size(),
+            secret: self.secret,
+            schema: self.schema,
+            marker: PhantomData,
+        }))
+    }
+
+    /// When the client stops waiting for the response. `None` if the client did not set a deadline.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
     pub fn body(&self) -> &Req {
         self.body.as_ref().unwrap()
     }
 
+    /// Number of bytes the client sent for the request.
+    pub fn bytes_in(&self) -> usize {
+        self.bytes_in
+    }
+
     fn take_body(&mut self) -> Req {
         self.body.take().expect("body was taken twice")
     }
 }
 
+/// Connect to the server, and retry after each of the durations in `retry` if it fails.
+/// The TLS handshake is done after the connection is made if `tls` is set.
+pub(super) async fn connect_with_retry(
+    server: impl ToSocketAddrs + Clone,
+    timeout: Duration,
+    retry: impl Iterator<Item = Duration>,
+    tls: Option<&TlsClient>,
+) -> Result<Stream> {
+    let mut retry = retry;
+
+    let stream = loop {
+        match tokio::time::timeout(timeout, net::connect(server.clone())).await {
+            Ok(Ok(stream)) => break stream,
+            _ => match retry.next() {
+                Some(backoff) => tokio::time::sleep(backoff).await,
+                None => return Err(Error::ConnectionTimeout),
+            },
+        }
+    };
+
+    match tokio::time::timeout(timeout, tls::connect(tls, stream)).await {
+        Ok(stream) => stream,
+        Err(_) => Err(Error::ConnectionTimeout),
+    }
+}
+
 pub struct ResilientConnection<Req, Res> {
     conn: Connection<Req, Res>,
 }
@@ -252,28 +1040,51 @@ where
         server: impl ToSocketAddrs + Clone,
         timeout: Duration,
         retry: impl Iterator<Item = Duration>,
+        tls: &Tls,
     ) -> Result<Self> {
-        let mut conn = Connection::create_with_timeout(server.clone(), timeout).await;
-        let mut retry = retry;
-
-        loop {
-            match conn {
-                Ok(conn) => return Ok(ResilientConnection { conn }),
-                Err(_) => {
-                    if let Some(timeout) = retry.next() {
-                        tokio::time::sleep(timeout).await;
-                        conn = Connection::create_with_timeout(server.clone(), timeout).await;
-                    } else {
-                        return Err(Error::ConnectionTimeout);
-                    }
-                }
-            }
+        let stream = connect_with_retry(server, timeout, retry, tls.client.as_ref()).await?;
+
+        Ok(ResilientConnection {
+            conn: Connection::from_stream(stream).with_secret(tls.secret),
+        })
+    }
+
+    pub fn with_codec(self, codec: Format) -> Self {
+        ResilientConnection {
+            conn: self.conn.with_codec(codec),
+        }
+    }
+
+    pub fn with_schema_version(self, version: u32) -> Self {
+        ResilientConnection {
+            conn: self.conn.with_schema_version(version),
+        }
+    }
+
+    pub fn with_compression(self, compression: Option<Compression>) -> Self {
+        ResilientConnection {
+            conn: self.conn.with_compression(compression),
+        }
+    }
+
+    pub fn with_deadline(self, deadline: Option<Instant>) -> Self {
+        ResilientConnection {
+            conn: self.conn.with_deadline(deadline),
         }
     }
 
     pub async fn send_with_timeout(self, request: &Req, timeout: Duration) -> Result<Res> {
         self.conn.send_with_timeout(request, timeout).await
     }
+
+    /// See [`Connection::send_streaming`].
+    pub async fn send_streaming(
+        self,
+        request: &Req,
+        timeout: Duration,
+    ) -> Result<impl futures::Stream<Item = Result<Res>>> {
+        self.conn.send_streaming(request, timeout).await
+    }
 }
 
 #[cfg(test)]
@@ -344,4 +1155,272 @@ mod tests {
             con_res?;
         }
     }
+
+    /// Send a large and a small message between a client and a server with the compression settings.
+    fn compressed_exchange(
+        svr_compression: Option<Compression>,
+        con_compression: Option<Compression>,
+    ) {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async move {
+                let server = Server::<String, String>::bind(("127.0.0.1", 0))
+                    .await
+                    .unwrap()
+                    .with_compression(svr_compression);
+                let addr = server.listener.local_addr().unwrap();
+
+                tokio::spawn(async move {
+                    while let Ok(req) = server.accept().await {
+                        let res = req.body().repeat(2);
+                        req.respond(res).await.ok();
+                    }
+                });
+
+                for msg in ["stract ".repeat(10_000), "stract".to_string()] {
+                    let res = Connection::<String, String>::create(addr)
+                        .await
+                        .unwrap()
+                        .with_compression(con_compression)
+                        .send(&msg)
+                        .await
+                        .unwrap();
+
+                    assert_eq!(res, msg.repeat(2));
+                }
+            });
+    }
+
+    #[test]
+    fn compression() {
+        compressed_exchange(Some(Compression::zstd()), Some(Compression::zstd()));
+        compressed_exchange(Some(Compression::lz4()), Some(Compression::zstd()));
+    }
+
+    #[test]
+    fn compression_with_uncompressed_peers() {
+        // clients without compression get uncompressed responses
+        compressed_exchange(Some(Compression::zstd()), None);
+        // servers without compression still decompress the requests
+        compressed_exchange(None, Some(Compression::lz4()));
+    }
+
+    #[test]
+    fn clients_with_different_codecs() {
+        #[allow(unused_mut)]
+        let mut codecs = vec![Format::Bincode];
+        #[cfg(feature = "postcard")]
+        codecs.push(Format::Postcard);
+        #[cfg(feature = "msgpack")]
+        codecs.push(Format::MessagePack);
+
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async move {
+                let server = Server::<String, String>::bind(("127.0.0.1", 0))
+                    .await
+                    .unwrap()
+                    .with_compression(Some(Compression::zstd()));
+                let addr = server.listener.local_addr().unwrap();
+
+                tokio::spawn(async move {
+                    while let Ok(req) = server.accept().await {
+                        let res = req.body().repeat(2);
+                        req.respond(res).await.ok();
+                    }
+                });
+
+                // the server responds to each client in the format of its requests
+                for codec in codecs {
+                    for msg in ["stract ".repeat(10_000), "stract".to_string()] {
+                        let res = Connection::<String, String>::create(addr)
+                            .await
+                            .unwrap()
+                            .with_codec(codec)
+                            .with_compression(Some(Compression::lz4()))
+                            .send(&msg)
+                            .await
+                            .unwrap();
+
+                        assert_eq!(res, msg.repeat(2));
+                    }
+                }
+            });
+    }
+
+    #[test]
+    #[cfg(not(feature = "postcard"))]
+    fn unsupported_format_is_rejected() {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async move {
+                let server = Server::<String, String>::bind(("127.0.0.1", 0))
+                    .await
+                    .unwrap();
+                let addr = server.listener.local_addr().unwrap();
+
+                let handle = tokio::spawn(async move { server.accept().await.map(|_| ()) });
+
+                // a postcard request to a server that is built without postcard
+                let body = bincode::serialize("stract").unwrap();
+                let header = Header {
+                    body_size: body.len() | (1 << 57),
+                };
+
+                let mut stream = TcpStream::connect(addr).await.unwrap();
+                stream.write_all(bytemuck::bytes_of(&header)).await.unwrap();
+                stream.write_all(&body).await.unwrap();
+
+                let header = read_header(&mut stream).await.unwrap();
+                assert!(matches!(
+                    read_body::<_, String>(&mut stream, header).await,
+                    Err(Error::UnsupportedFormat)
+                ));
+                assert!(matches!(
+                    handle.await.unwrap(),
+                    Err(Error::UnsupportedFormat)
+                ));
+            });
+    }
+
+    #[test]
+    fn schema_versions() {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async move {
+                let server = Server::<String, String>::bind(("127.0.0.1", 0))
+                    .await
+                    .unwrap()
+                    .with_schema_versions(SchemaVersions { min: 0, max: 1 });
+                let addr = server.listener.local_addr().unwrap();
+
+                tokio::spawn(async move {
+                    loop {
+                        if let Ok(req) = server.accept().await {
+                            let res = req.body().repeat(2);
+                            req.respond(res).await.ok();
+                        }
+                    }
+                });
+
+                let send = |version: u32, msg: String| async move {
+                    Connection::<String, String>::create(addr)
+                        .await
+                        .unwrap()
+                        .with_schema_version(version)
+                        .send(&msg)
+                        .await
+                };
+
+                // clients without a version and older clients can still be read
+                assert_eq!(send(0, "stract".to_string()).await.unwrap(), "stractstract");
+                assert_eq!(send(1, "stract".to_string()).await.unwrap(), "stractstract");
+
+                // newer clients are told which versions the server reads, also
+                // when their request is larger than the socket buffers
+                for msg in ["stract".to_string(), "stract ".repeat(100_000)] {
+                    assert!(matches!(
+                        send(2, msg).await,
+                        Err(Error::IncompatibleSchema { min: 0, max: 1 })
+                    ));
+                }
+            });
+    }
+
+    #[test]
+    fn pipelined_batch() {
+        // the requests and responses are large enough to fill the socket buffers,
+        // so the batch only completes if the responses are read while writing
+        let requests: Vec<String> = (0..64)
+            .map(|i| format!("{i} {}", "stract ".repeat(10_000)))
+            .collect();
+        let expected = requests.clone();
+
+        let (svr_res, con_res) = fixture(
+            |svr: Server<String, String>| async move {
+                let mut req = svr.accept().await?;
+                loop {
+                    let res = req.body().repeat(2);
+                    req.write_response(&res).await?;
+
+                    match req.next_request(Duration::from_secs(5)).await? {
+                        Some(next) => req = next,
+                        None => break,
+                    }
+                }
+                Ok(())
+            },
+            |mut con| async move {
+                let res = con
+                    .send_batch_keepalive(&requests, Duration::from_secs(30))
+                    .await?;
+                prop_assert_eq!(res.len(), expected.len());
+                for (res, req) in res.iter().zip(&expected) {
+                    prop_assert_eq!(res, &req.repeat(2));
+                }
+
+                // the connection can be used after the batch
+                let res = con.send(&"after".to_string()).await?;
+                prop_assert_eq!(res, "afterafter");
+                Ok(())
+            },
+        );
+        svr_res.unwrap();
+        con_res.unwrap();
+    }
+
+    #[test]
+    fn deadline() {
+        let (svr_res, con_res) = fixture(
+            |svr: Server<String, String>| async move {
+                let mut req = svr.accept().await?;
+                prop_assert_eq!(req.deadline(), None);
+                let bytes_out = req.write_response(&"no deadline".to_string()).await?;
+                prop_assert!(bytes_out > 0);
+                let req = req.next_request(Duration::from_secs(5)).await?.unwrap();
+                prop_assert!(req.bytes_in() > 8);
+
+                let remaining = req.deadline().unwrap() - Instant::now();
+                prop_assert!(remaining <= Duration::from_secs(10));
+                prop_assert!(remaining > Duration::from_secs(5));
+                prop_assert_eq!(req.body(), "deadline");
+                req.respond("ok".to_string()).await?;
+                Ok(())
+            },
+            |mut con| async move {
+                let res = con
+                    .send_keepalive(&"no deadline".to_string(), Duration::from_secs(5))
+                    .await?;
+                prop_assert_eq!(res, "no deadline");
+
+                let res = con
+                    .with_deadline(Some(Instant::now() + Duration::from_secs(10)))
+                    .send(&"deadline".to_string())
+                    .await?;
+                prop_assert_eq!(res, "ok");
+                Ok(())
+            },
+        );
+        svr_res.unwrap();
+        con_res.unwrap();
+    }
+
+    #[test]
+    fn deserialization_errors() {
+        let bincode_err = bincode::deserialize::<u64>(&[]).unwrap_err();
+
+        assert!(Error::Serialization(bincode_err).is_deserialization());
+        assert!(Error::Decode("postcard: unexpected end".to_string()).is_deserialization());
+        assert!(Error::IncompatibleSchema { min: 0, max: 1 }.is_deserialization());
+        assert!(!Error::RequestTimeout.is_deserialization());
+        assert!(!Error::Other(anyhow::anyhow!("other")).is_deserialization());
+    }
 }
@@ -14,81 +14,650 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use futures::future::join_all;
-use rand::seq::IteratorRandom;
+use futures::{future::join_all, stream::FuturesUnordered, StreamExt};
+use rand::{
+    seq::{IteratorRandom, SliceRandom},
+    Rng,
+};
 
-use super::Result;
-use crate::distributed::{retry_strategy::ExponentialBackoff, sonic};
-use std::{net::SocketAddr, time::Duration};
+use super::{Error, Result};
+use crate::{
+    config::{HedgingConfig, QuorumConfig},
+    distributed::{
+        cluster::Cluster, member::Member, probe, retry_strategy::ExponentialBackoff, sonic,
+    },
+    searcher::ShardId,
+    shard_router::ShardRouter,
+};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    hash::Hash,
+    net::SocketAddr,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
 
-#[derive(Debug, Clone)]
+/// Consecutive failures before the circuit breaker of a replica opens.
+const CIRCUIT_BREAKER_THRESHOLD: usize = 5;
+/// How long a replica is skipped after its circuit breaker opens.
+const CIRCUIT_BREAKER_COOLDOWN: Duration = Duration::from_secs(10);
+/// Weight of the latest response time in the moving average of a replica.
+const LATENCY_EWMA_ALPHA: f64 = 0.2;
+/// The moving average of a replica is halved for each half-life without a response,
+/// so a replica that was slow for a while is tried again once it has been avoided.
+const LATENCY_HALF_LIFE: Duration = Duration::from_secs(30);
+/// Probability that the latency aware selector ignores the response times of the
+/// replicas, so their response times stay up to date.
+const LATENCY_EXPLORATION: f64 = 0.02;
+/// How often the members are probed for whether they are still loading.
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+enum BreakerState {
+    /// Requests are sent to the replica.
+    Closed { failures: usize },
+    /// The replica failed repeatedly and is skipped until the cooldown has passed.
+    Open { until: Instant },
+    /// The cooldown has passed and a single request is sent to the replica to
+    /// find out whether it has recovered.
+    HalfOpen { since: Instant },
+}
+
+/// Keeps track of the failures of a replica, so a replica that cannot be reached
+/// is skipped for a while instead of being tried by every request. Slow replicas are
+/// avoided by their response times instead, since a request can time out because
+/// its deadline was short.
+#[derive(Debug)]
+struct CircuitBreaker {
+    state: Mutex<BreakerState>,
+    threshold: usize,
+    cooldown: Duration,
+}
+
+impl CircuitBreaker {
+    fn new(threshold: usize, cooldown: Duration) -> Self {
+        Self {
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+            threshold: threshold.max(1),
+            cooldown,
+        }
+    }
+
+    /// Whether the replica can be selected. Only one probe is sent while the breaker
+    /// is half-open, unless the probe has not finished within the cooldown.
+    fn is_available(&self) -> bool {
+        match *self.state.lock().unwrap_or_else(|e| e.into_inner()) {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } => Instant::now() >= until,
+            BreakerState::HalfOpen { since } => since.elapsed() >= self.cooldown,
+        }
+    }
+
+    /// Called when a request is sent to the replica. A request after the cooldown is the probe.
+    fn on_send(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        if matches!(*state, BreakerState::Open { until } if Instant::now() >= until) {
+            *state = BreakerState::HalfOpen {
+                since: Instant::now(),
+            };
+        }
+    }
+
+    fn on_success(&self) {
+        *self.state.lock().unwrap_or_else(|e| e.into_inner()) =
+            BreakerState::Closed { failures: 0 };
+    }
+
+    fn on_failure(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        let failures = match *state {
+            BreakerState::Closed { failures } => failures + 1,
+            // the probe failed, so the replica has not recovered
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => self.threshold,
+        };
+
+        *state = if failures >= self.threshold {
+            BreakerState::Open {
+                until: Instant::now() + self.cooldown,
+            }
+        } else {
+            BreakerState::Closed { failures }
+        };
+    }
+}
+
+/// The circuit breakers of the replicas by their address. The clients are created for
+/// each request from the members of the cluster, so the breakers are kept here to
+/// remember the failures between requests.
+static CIRCUIT_BREAKERS: once_cell::sync::Lazy<Mutex<HashMap<SocketAddr, Arc<CircuitBreaker>>>> =
+    once_cell::sync::Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn circuit_breaker(addr: SocketAddr) -> Arc<CircuitBreaker> {
+    let mut breakers = CIRCUIT_BREAKERS.lock().unwrap_or_else(|e| e.into_inner());
+
+    Arc::clone(breakers.entry(addr).or_insert_with(|| {
+        Arc::new(CircuitBreaker::new(
+            CIRCUIT_BREAKER_THRESHOLD,
+            CIRCUIT_BREAKER_COOLDOWN,
+        ))
+    }))
+}
+
+/// Exponentially weighted moving average of the response times of a replica,
+/// which decays while the replica does not respond.
+#[derive(Debug, Default)]
+struct LatencyEwma {
+    /// The average in microseconds and when it was last updated.
+    micros: Mutex<Option<(f64, Instant)>>,
+}
+
+impl LatencyEwma {
+    fn decay(micros: f64, updated: Instant, now: Instant) -> f64 {
+        let half_lives =
+            now.saturating_duration_since(updated).as_secs_f64() / LATENCY_HALF_LIFE.as_secs_f64();
+
+        micros * 0.5f64.powf(half_lives)
+    }
+
+    fn record(&self, latency: Duration) {
+        let mut micros = self.micros.lock().unwrap_or_else(|e| e.into_inner());
+        let latency = latency.as_micros() as f64;
+        let now = Instant::now();
+
+        *micros = Some(match *micros {
+            Some((avg, updated)) => {
+                let avg = Self::decay(avg, updated, now);
+                (
+                    LATENCY_EWMA_ALPHA * latency + (1.0 - LATENCY_EWMA_ALPHA) * avg,
+                    now,
+                )
+            }
+            None => (latency, now),
+        });
+    }
+
+    fn get_at(&self, now: Instant) -> Option<Duration> {
+        self.micros
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .map(|(micros, updated)| {
+                Duration::from_micros(Self::decay(micros, updated, now).round() as u64)
+            })
+    }
+
+    fn get(&self) -> Option<Duration> {
+        self.get_at(Instant::now())
+    }
+}
+
+/// The response times of the replicas by their address, kept between requests like
+/// the circuit breakers.
+static LATENCIES: once_cell::sync::Lazy<Mutex<HashMap<SocketAddr, Arc<LatencyEwma>>>> =
+    once_cell::sync::Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn latency_ewma(addr: SocketAddr) -> Arc<LatencyEwma> {
+    let mut latencies = LATENCIES.lock().unwrap_or_else(|e| e.into_inner());
+
+    Arc::clone(latencies.entry(addr).or_default())
+}
+
+#[derive(Debug)]
 pub struct RemoteClient<S: sonic::service::Service> {
     addr: SocketAddr,
+    /// All the addresses of the server, starting with `addr`.
+    addrs: Arc<[SocketAddr]>,
+    breaker: Arc<CircuitBreaker>,
+    latency: Arc<LatencyEwma>,
     _phantom: std::marker::PhantomData<S>,
 }
 
+// not derived, as the derive would require the service to be `Clone`
+impl<S: sonic::service::Service> Clone for RemoteClient<S> {
+    fn clone(&self) -> Self {
+        Self {
+            addr: self.addr,
+            addrs: Arc::clone(&self.addrs),
+            breaker: Arc::clone(&self.breaker),
+            latency: Arc::clone(&self.latency),
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
 impl<S> RemoteClient<S>
 where
     S: sonic::service::Service,
 {
     pub fn new(addr: SocketAddr) -> Self {
+        Self::with_addrs(vec![addr])
+    }
+
+    /// A client for a server with several addresses, e.g. all the resolved addresses of its
+    /// hostname. Connections are made to the first reachable address, while the circuit
+    /// breaker and the latency are tracked for the server by its first address.
+    /// `addrs` must not be empty.
+    pub fn with_addrs(addrs: Vec<SocketAddr>) -> Self {
+        let addr = addrs[0];
+
         Self {
             addr,
+            addrs: addrs.into(),
+            breaker: circuit_breaker(addr),
+            latency: latency_ewma(addr),
             _phantom: std::marker::PhantomData,
         }
     }
+
+    /// Moving average of the response times of the replica, if it has responded before.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency.get()
+    }
 }
 
 impl<S> RemoteClient<S>
 where
     S: sonic::service::Service,
 {
-    async fn conn(&self) -> Result<sonic::service::ResilientConnection<S>> {
+    async fn conn(&self) -> Result<sonic::service::PooledConnection<S>> {
         let retry = ExponentialBackoff::from_millis(30)
             .with_limit(Duration::from_millis(200))
             .take(5);
 
-        sonic::service::ResilientConnection::create_with_timeout(
-            self.addr,
-            Duration::from_secs(30),
-            retry,
-        )
-        .await
+        sonic::service::pool()
+            .get(&self.addrs, Duration::from_secs(30), retry)
+            .await
     }
 
     async fn send<R: sonic::service::Wrapper<S>>(&self, req: &R) -> Result<R::Response> {
+        self.breaker.on_send();
+        let start = Instant::now();
+        let res = self.send_without_breaker(req).await;
+        self.observe(start.elapsed(), &res);
+
+        res
+    }
+
+    async fn send_batch<R: sonic::service::Wrapper<S>>(
+        &self,
+        reqs: &[R],
+    ) -> Result<Vec<R::Response>> {
+        self.breaker.on_send();
+        let start = Instant::now();
+        let res = self.send_batch_without_breaker(reqs).await;
+        // the server answers the requests of a batch one at a time
+        self.observe(start.elapsed() / reqs.len().max(1) as u32, &res);
+
+        res
+    }
+
+    /// Update the circuit breaker and the latency of the replica with the outcome of a request.
+    fn observe<T>(&self, latency: Duration, res: &Result<T>) {
+        match res {
+            Ok(_) => {
+                self.latency.record(latency);
+                self.breaker.on_success()
+            }
+            // the request can time out because of its deadline, so only the
+            // response time counts against the replica
+            Err(Error::RequestTimeout) => self.latency.record(latency),
+            Err(Error::ConnectionTimeout | Error::IO(_)) => self.breaker.on_failure(),
+            // the replica is reachable, so the breaker is left alone and the request is
+            // retried on another replica by the callers
+            Err(e) if e.is_deserialization() => {
+                sonic::metrics::client().record_deserialization_failure();
+                tracing::warn!("failed to deserialize response from {}: {:?}", self.addr, e);
+            }
+            // the replica responded, or the request failed for reasons unrelated to the replica
+            Err(_) => {}
+        }
+    }
+
+    async fn send_without_breaker<R: sonic::service::Wrapper<S>>(
+        &self,
+        req: &R,
+    ) -> Result<R::Response> {
+        let conn = self.conn().await?;
+        let is_reused = conn.is_reused();
+
+        match conn.send_with_timeout(req, Duration::from_secs(60)).await {
+            // the server might have closed the connection while it was idle, or
+            // rejected the format of the request, which falls back to bincode
+            Err(e)
+                if (is_reused && matches!(e, Error::IO(_)))
+                    || matches!(e, Error::UnsupportedFormat) =>
+            {
+                self.conn()
+                    .await?
+                    .send_with_timeout(req, Duration::from_secs(60))
+                    .await
+            }
+            res => res,
+        }
+    }
+
+    async fn send_batch_without_breaker<R: sonic::service::Wrapper<S>>(
+        &self,
+        reqs: &[R],
+    ) -> Result<Vec<R::Response>> {
         let conn = self.conn().await?;
-        conn.send_with_timeout(req, Duration::from_secs(60)).await
+        let is_reused = conn.is_reused();
+
+        match conn
+            .send_batch_with_timeout(reqs, Duration::from_secs(60))
+            .await
+        {
+            // the server might have closed the connection while it was idle, or
+            // rejected the format of the request, which falls back to bincode
+            Err(e)
+                if (is_reused && matches!(e, Error::IO(_)))
+                    || matches!(e, Error::UnsupportedFormat) =>
+            {
+                self.conn()
+                    .await?
+                    .send_batch_with_timeout(reqs, Duration::from_secs(60))
+                    .await
+            }
+            res => res,
+        }
+    }
+
+    async fn send_timed<R: sonic::service::Wrapper<S>>(
+        &self,
+        req: &R,
+    ) -> (Duration, Result<R::Response>) {
+        let start = Instant::now();
+        let res = self.send(req).await;
+
+        (start.elapsed(), res)
     }
 }
 
+/// Label the output of the future, so the futures in a [`FuturesUnordered`] can be told apart.
+async fn tagged<F: std::future::Future>(tag: usize, fut: F) -> (usize, F::Output) {
+    (tag, fut.await)
+}
+
 pub trait ReplicaSelector<S: sonic::service::Service> {
     fn select<'a>(&self, replicas: &'a [RemoteClient<S>]) -> Vec<&'a RemoteClient<S>>;
 }
 
-pub struct RandomReplicaSelector;
+pub struct AllReplicaSelector;
 
-impl<S> ReplicaSelector<S> for RandomReplicaSelector
+impl<S> ReplicaSelector<S> for AllReplicaSelector
+where
+    S: sonic::service::Service,
+{
+    fn select<'a>(&self, replicas: &'a [RemoteClient<S>]) -> Vec<&'a RemoteClient<S>> {
+        replicas.iter().collect()
+    }
+}
+
+/// Sends the request to the faster of two random replicas (power of two choices),
+/// based on the moving average of their response times. Replicas that have not
+/// responded before are preferred, so their response times get measured. Once in a
+/// while, one of the two is picked regardless of its response time.
+pub struct LatencyAwareReplicaSelector;
+
+impl LatencyAwareReplicaSelector {
+    fn select_with<'a, S: sonic::service::Service>(
+        replicas: &'a [RemoteClient<S>],
+        rng: &mut impl Rng,
+        exploration: f64,
+    ) -> Vec<&'a RemoteClient<S>> {
+        let mut candidates = replicas.iter().choose_multiple(rng, 2);
+
+        // `choose_multiple` does not randomize the order of the chosen replicas,
+        // which would otherwise decide ties
+        candidates.shuffle(rng);
+
+        if rng.gen_bool(exploration) {
+            candidates.truncate(1);
+            return candidates;
+        }
+
+        candidates
+            .into_iter()
+            .min_by_key(|replica| replica.latency())
+            .into_iter()
+            .collect()
+    }
+}
+
+impl<S> ReplicaSelector<S> for LatencyAwareReplicaSelector
+where
+    S: sonic::service::Service,
+{
+    fn select<'a>(&self, replicas: &'a [RemoteClient<S>]) -> Vec<&'a RemoteClient<S>> {
+        Self::select_with(replicas, &mut rand::thread_rng(), LATENCY_EXPLORATION)
+    }
+}
+
+/// Sends the request to a number of random replicas, and only waits until a quorum
+/// of them have responded consistently. Use together with [`ReplicatedClient::send_quorum`].
+pub struct QuorumReplicaSelector {
+    replicas: usize,
+    quorum: usize,
+}
+
+impl QuorumReplicaSelector {
+    pub fn new(config: &QuorumConfig) -> crate::Result<Self> {
+        if config.quorum == 0 {
+            anyhow::bail!("the quorum must be at least one");
+        }
+
+        if config.quorum > config.replicas {
+            anyhow::bail!("the quorum can not be larger than the number of replicas");
+        }
+
+        Ok(Self {
+            replicas: config.replicas,
+            quorum: config.quorum,
+        })
+    }
+
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+}
+
+impl<S> ReplicaSelector<S> for QuorumReplicaSelector
 where
     S: sonic::service::Service,
 {
     fn select<'a>(&self, replicas: &'a [RemoteClient<S>]) -> Vec<&'a RemoteClient<S>> {
         let mut rng = rand::thread_rng();
-        replicas.iter().choose_multiple(&mut rng, 1)
+        replicas.iter().choose_multiple(&mut rng, self.replicas)
     }
 }
 
-pub struct AllReplicaSelector;
+/// Sends the request to a random replica, and to a second random replica if the first
+/// has not responded within a quantile of the recent response times. Use together with
+/// [`ReplicatedClient::send_hedged`]. The selector keeps the response times, so the
+/// same selector should be used for all requests to the replicas.
+pub struct HedgedReplicaSelector {
+    latencies: Mutex<VecDeque<LatencySample>>,
+    window: usize,
+    quantile: f64,
+    min_samples: usize,
+    initial_delay: Duration,
+    min_delay: Duration,
+}
 
-impl<S> ReplicaSelector<S> for AllReplicaSelector
+/// A response time of a replica. The response time of a replica that lost to the other
+/// replica is censored: it is only known to be at least `latency`.
+#[derive(Debug, Clone, Copy)]
+struct LatencySample {
+    latency: Duration,
+    censored: bool,
+}
+
+impl HedgedReplicaSelector {
+    pub fn new(config: &HedgingConfig) -> crate::Result<Self> {
+        if !(0.0..=1.0).contains(&config.quantile) {
+            anyhow::bail!("the quantile must be between 0 and 1");
+        }
+
+        Ok(Self {
+            latencies: Mutex::new(VecDeque::with_capacity(config.window)),
+            window: config.window.max(1),
+            quantile: config.quantile,
+            min_samples: config.min_samples,
+            initial_delay: Duration::from_millis(config.initial_delay_ms),
+            min_delay: Duration::from_millis(config.min_delay_ms),
+        })
+    }
+
+    /// Record the response time of a replica.
+    pub fn record(&self, latency: Duration) {
+        self.push(LatencySample {
+            latency,
+            censored: false,
+        });
+    }
+
+    /// Record that a replica had not responded after `latency` when the other replica
+    /// responded. Leaving out the slow replicas would bias the quantile low.
+    pub fn record_censored(&self, latency: Duration) {
+        self.push(LatencySample {
+            latency,
+            censored: true,
+        });
+    }
+
+    fn push(&self, sample: LatencySample) {
+        let mut latencies = self.latencies.lock().unwrap_or_else(|e| e.into_inner());
+
+        if latencies.len() >= self.window {
+            latencies.pop_front();
+        }
+
+        latencies.push_back(sample);
+    }
+
+    /// How long to wait for the first replica before the request is sent to the second.
+    ///
+    /// The quantile is estimated with the Kaplan-Meier estimator, so the censored response
+    /// times count as at least as slow as their latency. If too many of the response times
+    /// are censored to reach the quantile, the largest response time is used.
+    pub fn delay(&self) -> Duration {
+        let mut latencies: Vec<_> = self
+            .latencies
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .copied()
+            .collect();
+
+        if latencies.is_empty() || latencies.len() < self.min_samples {
+            return self.initial_delay;
+        }
+
+        // responses come before the censored samples of the same latency
+        latencies.sort_by_key(|sample| (sample.latency, sample.censored));
+
+        let mut at_risk = latencies.len() as f64;
+        let mut survival = 1.0;
+
+        for sample in &latencies {
+            if !sample.censored {
+                survival *= 1.0 - 1.0 / at_risk;
+
+                // allow for the rounding errors of the product
+                if 1.0 - survival >= self.quantile - 1e-9 {
+                    return sample.latency.max(self.min_delay);
+                }
+            }
+
+            at_risk -= 1.0;
+        }
+
+        latencies
+            .last()
+            .map_or(self.initial_delay, |sample| sample.latency)
+            .max(self.min_delay)
+    }
+}
+
+impl<S> ReplicaSelector<S> for HedgedReplicaSelector
 where
     S: sonic::service::Service,
 {
     fn select<'a>(&self, replicas: &'a [RemoteClient<S>]) -> Vec<&'a RemoteClient<S>> {
-        replicas.iter().collect()
+        let mut rng = rand::thread_rng();
+        let mut selected = replicas.iter().choose_multiple(&mut rng, 2);
+
+        // `choose_multiple` does not randomize the order of the chosen replicas
+        selected.shuffle(&mut rng);
+
+        selected
     }
 }
 
+/// Groups the responses from the replicas that are consistent with each other.
+pub struct QuorumResponses<T, F> {
+    groups: Vec<Vec<T>>,
+    quorum: usize,
+    consistent: F,
+}
+
+impl<T, F> QuorumResponses<T, F>
+where
+    F: Fn(&T, &T) -> bool,
+{
+    pub fn new(quorum: usize, consistent: F) -> Self {
+        Self {
+            groups: Vec::new(),
+            quorum,
+            consistent,
+        }
+    }
+
+    /// Add a response. Returns the consistent responses once the quorum is reached.
+    pub fn push(&mut self, response: T) -> Option<Vec<T>> {
+        let idx = match self
+            .groups
+            .iter()
+            .position(|group| (self.consistent)(&group[0], &response))
+        {
+            Some(idx) => idx,
+            None => {
+                self.groups.push(Vec::new());
+                self.groups.len() - 1
+            }
+        };
+
+        let group = &mut self.groups[idx];
+        group.push(response);
+
+        if group.len() >= self.quorum {
+            Some(std::mem::take(group))
+        } else {
+            None
+        }
+    }
+}
+
+/// Up to `n` random replicas that have not been tried yet.
+fn alternate_replicas<'a, S: sonic::service::Service>(
+    available: &'a [RemoteClient<S>],
+    tried: &[&RemoteClient<S>],
+    n: usize,
+) -> Vec<&'a RemoteClient<S>> {
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let mut rng = rand::thread_rng();
+
+    available
+        .iter()
+        .filter(|client| !tried.iter().any(|tried| tried.addr == client.addr))
+        .choose_multiple(&mut rng, n)
+}
+
 pub struct ReplicatedClient<S: sonic::service::Service> {
     clients: Vec<RemoteClient<S>>,
 }
@@ -101,27 +670,230 @@ where
         Self { clients }
     }
 
+    /// The replicas that the selectors choose from. Replicas with an open circuit breaker
+    /// are skipped, unless the breakers of all replicas are open.
+    fn available(&self) -> Vec<RemoteClient<S>> {
+        let available: Vec<_> = self
+            .clients
+            .iter()
+            .filter(|client| client.breaker.is_available())
+            .cloned()
+            .collect();
+
+        if available.is_empty() {
+            tracing::debug!("all replicas have open circuit breakers");
+            return self.clients.clone();
+        }
+
+        available
+    }
+
     pub async fn send<Req, Rep>(&self, req: &Req, selector: &Rep) -> Result<Vec<Req::Response>>
     where
         Req: sonic::service::Wrapper<S>,
         Rep: ReplicaSelector<S>,
     {
+        let available = self.available();
+        let selected = selector.select(&available);
+        let futures: Vec<_> = selected.iter().map(|client| client.send(req)).collect();
+
+        let mut results = Vec::new();
+        let mut last_err = None;
+        let mut num_undeserializable = 0;
+        for r in join_all(futures).await {
+            match r {
+                Ok(r) => results.push(r),
+                Err(e) => {
+                    tracing::error!("Failed to send request: {:?}", e);
+
+                    if e.is_deserialization() {
+                        num_undeserializable += 1;
+                    }
+
+                    last_err = Some(e);
+                }
+            }
+        }
+
+        // responses that could not be deserialized are requested from other replicas
+        // instead, since the replica might run another version or the response was corrupted
+        let alternates = alternate_replicas(&available, &selected, num_undeserializable);
+        for (client, r) in alternates
+            .iter()
+            .zip(join_all(alternates.iter().map(|client| client.send(req))).await)
+        {
+            match r {
+                Ok(r) => {
+                    tracing::debug!("retried request on {}", client.addr);
+                    results.push(r)
+                }
+                Err(e) => {
+                    tracing::error!("Failed to retry request on {}: {:?}", client.addr, e);
+                    last_err = Some(e);
+                }
+            }
+        }
+
+        // the request only fails if none of the selected replicas responded
+        match last_err {
+            Some(e) if results.is_empty() => Err(e),
+            _ => Ok(results),
+        }
+    }
+
+    /// Send a batch of requests to each of the selected replicas. The requests are pipelined
+    /// on a single connection to each replica instead of doing a round-trip per request, and
+    /// each replica's responses are in the order of the requests.
+    pub async fn send_batch<Req, Rep>(
+        &self,
+        reqs: &[Req],
+        selector: &Rep,
+    ) -> Result<Vec<Vec<Req::Response>>>
+    where
+        Req: sonic::service::Wrapper<S>,
+        Rep: ReplicaSelector<S>,
+    {
+        let available = self.available();
         let mut futures = Vec::new();
-        for client in selector.select(&self.clients) {
-            futures.push(client.send(req));
+        for client in selector.select(&available) {
+            futures.push(client.send_batch(reqs));
         }
 
         let mut results = Vec::new();
+        let mut last_err = None;
         for r in join_all(futures).await {
             match r {
                 Ok(r) => results.push(r),
+                Err(e) => {
+                    tracing::error!("Failed to send batch: {:?}", e);
+                    last_err = Some(e);
+                }
+            }
+        }
+
+        match last_err {
+            Some(e) if results.is_empty() => Err(e),
+            _ => Ok(results),
+        }
+    }
+
+    /// Returns the responses from the first replicas that reach the quorum with equal responses.
+    pub async fn send_quorum<Req>(
+        &self,
+        req: &Req,
+        selector: &QuorumReplicaSelector,
+    ) -> Result<Vec<Req::Response>>
+    where
+        Req: sonic::service::Wrapper<S>,
+        Req::Response: PartialEq,
+    {
+        self.send_quorum_with(req, selector, |a, b| a == b).await
+    }
+
+    /// Returns the responses from the first replicas that reach the quorum with
+    /// responses that are consistent according to `consistent`. Fails if the selected
+    /// replicas have all responded without reaching the quorum.
+    pub async fn send_quorum_with<Req, F>(
+        &self,
+        req: &Req,
+        selector: &QuorumReplicaSelector,
+        consistent: F,
+    ) -> Result<Vec<Req::Response>>
+    where
+        Req: sonic::service::Wrapper<S>,
+        F: Fn(&Req::Response, &Req::Response) -> bool,
+    {
+        let available = self.available();
+        let mut futures: FuturesUnordered<_> = selector
+            .select(&available)
+            .into_iter()
+            .map(|client| client.send(req))
+            .collect();
+
+        let mut responses = QuorumResponses::new(selector.quorum(), consistent);
+
+        while let Some(res) = futures.next().await {
+            match res {
+                Ok(res) => {
+                    if let Some(agreed) = responses.push(res) {
+                        return Ok(agreed);
+                    }
+                }
                 Err(e) => {
                     tracing::error!("Failed to send request: {:?}", e);
                 }
             }
         }
 
-        Ok(results)
+        Err(Error::NoQuorum)
+    }
+
+    /// Returns the first response from the selected replicas. The request is only sent to the
+    /// second replica if the first has not responded within the delay of the selector, or failed.
+    pub async fn send_hedged<Req>(
+        &self,
+        req: &Req,
+        selector: &HedgedReplicaSelector,
+    ) -> Result<Vec<Req::Response>>
+    where
+        Req: sonic::service::Wrapper<S>,
+    {
+        let available = self.available();
+        let mut replicas = selector.select(&available).into_iter();
+
+        let Some(first) = replicas.next() else {
+            return Ok(Vec::new());
+        };
+        let mut hedge = replicas.next();
+
+        // when each of the requests still in flight was sent
+        let mut in_flight = [Some(Instant::now()), None];
+        let mut pending = FuturesUnordered::new();
+        pending.push(tagged(0, first.send_timed(req)));
+        let mut last_err = None;
+
+        let delay = tokio::time::sleep(selector.delay());
+        tokio::pin!(delay);
+
+        loop {
+            tokio::select! {
+                res = pending.next() => match res {
+                    Some((idx, (latency, Ok(res)))) => {
+                        in_flight[idx] = None;
+                        selector.record(latency);
+
+                        for sent in in_flight.iter().flatten() {
+                            selector.record_censored(sent.elapsed());
+                        }
+
+                        return Ok(vec![res]);
+                    }
+                    Some((idx, (_, Err(e)))) => {
+                        tracing::error!("Failed to send request: {:?}", e);
+                        in_flight[idx] = None;
+                        last_err = Some(e);
+
+                        if let Some(client) = hedge.take() {
+                            in_flight[1] = Some(Instant::now());
+                            pending.push(tagged(1, client.send_timed(req)));
+                        }
+                    }
+                    None => {
+                        return match last_err {
+                            Some(e) => Err(e),
+                            None => Ok(Vec::new()),
+                        }
+                    }
+                },
+                _ = &mut delay, if hedge.is_some() => {
+                    if let Some(client) = hedge.take() {
+                        tracing::debug!("hedging request to {}", client.addr);
+                        in_flight[1] = Some(Instant::now());
+                        pending.push(tagged(1, client.send_timed(req)));
+                    }
+                }
+            }
+        }
     }
 }
 
@@ -168,6 +940,44 @@ where
     }
 }
 
+pub struct SpecificShardsSelector<Id: ShardIdentifier>(pub Vec<Id>);
+
+impl<S, Id> ShardSelector<S, Id> for SpecificShardsSelector<Id>
+where
+    S: sonic::service::Service,
+    Id: ShardIdentifier,
+{
+    fn select<'a>(&self, shards: &'a [Shard<S, Id>]) -> Vec<&'a Shard<S, Id>> {
+        shards.iter().filter(|s| self.0.contains(&s.id)).collect()
+    }
+}
+
+/// Routes the request to the shard that owns the url of the request, as decided by the
+/// [`ShardRouter`], so callers don't have to compute the shard id themselves.
+pub struct HashShardSelector {
+    shard: ShardId,
+}
+
+impl HashShardSelector {
+    pub fn new(router: &ShardRouter, url: &str) -> Self {
+        Self {
+            shard: router.shard_of_str(url),
+        }
+    }
+}
+
+impl<S> ShardSelector<S, ShardId> for HashShardSelector
+where
+    S: sonic::service::Service,
+{
+    fn select<'a>(&self, shards: &'a [Shard<S, ShardId>]) -> Vec<&'a Shard<S, ShardId>> {
+        shards
+            .iter()
+            .filter(|shard| shard.id == self.shard)
+            .collect()
+    }
+}
+
 pub struct Shard<S: sonic::service::Service, Id: ShardIdentifier> {
     replicas: ReplicatedClient<S>,
     id: Id,
@@ -183,6 +993,57 @@ where
     }
 }
 
+/// A shard that did not respond to a request.
+#[derive(Debug)]
+pub struct ShardFailure<Id> {
+    pub shard: Id,
+    pub error: Error,
+}
+
+/// The responses from the shards that a request was sent to, together with the
+/// shards that failed to respond.
+#[derive(Debug)]
+pub struct ShardedResponses<Id, T> {
+    pub responses: Vec<(Id, Vec<T>)>,
+    pub failures: Vec<ShardFailure<Id>>,
+}
+
+impl<Id, T> ShardedResponses<Id, T> {
+    fn collect(results: Vec<(Id, Result<Vec<T>>)>) -> Self {
+        let mut responses = Vec::new();
+        let mut failures = Vec::new();
+
+        for (shard, res) in results {
+            match res {
+                Ok(res) => responses.push((shard, res)),
+                Err(error) => {
+                    tracing::error!("Failed to send request: {:?}", error);
+                    failures.push(ShardFailure { shard, error });
+                }
+            }
+        }
+
+        Self {
+            responses,
+            failures,
+        }
+    }
+
+    /// Whether some of the shards failed, so the responses are incomplete.
+    pub fn is_partial(&self) -> bool {
+        !self.failures.is_empty()
+    }
+}
+
+impl<Id, T> IntoIterator for ShardedResponses<Id, T> {
+    type Item = (Id, Vec<T>);
+    type IntoIter = std::vec::IntoIter<(Id, Vec<T>)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.responses.into_iter()
+    }
+}
+
 pub struct ShardedClient<S: sonic::service::Service, Id: ShardIdentifier> {
     shards: Vec<Shard<S, Id>>,
 }
@@ -201,23 +1062,25 @@ where
         req: &Req,
         shard: &Shard<S, Id>,
         replica_selector: &RSel,
-    ) -> Result<(Id, Vec<Req::Response>)>
+    ) -> (Id, Result<Vec<Req::Response>>)
     where
         Req: sonic::service::Wrapper<S>,
         RSel: ReplicaSelector<S>,
     {
-        Ok((
+        (
             shard.id.clone(),
-            shard.replicas.send(req, replica_selector).await?,
-        ))
+            shard.replicas.send(req, replica_selector).await,
+        )
     }
 
+    /// Send the request to the selected shards. The shards that failed to respond are
+    /// reported in the failures of the responses.
     pub async fn send<Req, SSel, RSel>(
         &self,
         req: &Req,
         shard_selector: &SSel,
         replica_selector: &RSel,
-    ) -> Result<Vec<(Id, Vec<Req::Response>)>>
+    ) -> Result<ShardedResponses<Id, Req::Response>>
     where
         Req: sonic::service::Wrapper<S>,
         SSel: ShardSelector<S, Id>,
@@ -228,16 +1091,665 @@ where
             futures.push(self.send_single(req, shard, replica_selector));
         }
 
-        let mut results = Vec::new();
-        for r in join_all(futures).await {
-            match r {
-                Ok(r) => results.push(r),
-                Err(e) => {
-                    tracing::error!("Failed to send request: {:?}", e);
+        Ok(ShardedResponses::collect(join_all(futures).await))
+    }
+
+    /// Like [`ReplicatedClient::send_batch`] for each of the selected shards.
+    pub async fn send_batch<Req, SSel, RSel>(
+        &self,
+        reqs: &[Req],
+        shard_selector: &SSel,
+        replica_selector: &RSel,
+    ) -> Result<ShardedResponses<Id, Vec<Req::Response>>>
+    where
+        Req: sonic::service::Wrapper<S>,
+        SSel: ShardSelector<S, Id>,
+        RSel: ReplicaSelector<S>,
+    {
+        let mut futures = Vec::new();
+        for shard in shard_selector.select(&self.shards) {
+            futures.push(async move {
+                let res = shard.replicas.send_batch(reqs, replica_selector).await;
+                (shard.id.clone(), res)
+            });
+        }
+
+        Ok(ShardedResponses::collect(join_all(futures).await))
+    }
+
+    /// Like [`ReplicatedClient::send_hedged`] for each of the selected shards.
+    pub async fn send_hedged<Req, SSel>(
+        &self,
+        req: &Req,
+        shard_selector: &SSel,
+        replica_selector: &HedgedReplicaSelector,
+    ) -> Result<ShardedResponses<Id, Req::Response>>
+    where
+        Req: sonic::service::Wrapper<S>,
+        SSel: ShardSelector<S, Id>,
+    {
+        let mut futures = Vec::new();
+        for shard in shard_selector.select(&self.shards) {
+            futures.push(async move {
+                let res = shard.replicas.send_hedged(req, replica_selector).await;
+                (shard.id.clone(), res)
+            });
+        }
+
+        Ok(ShardedResponses::collect(join_all(futures).await))
+    }
+
+    /// Like [`ReplicatedClient::send_quorum`] for each of the selected shards.
+    /// Shards where the replicas did not reach the quorum are reported as failed.
+    pub async fn send_quorum<Req, SSel>(
+        &self,
+        req: &Req,
+        shard_selector: &SSel,
+        replica_selector: &QuorumReplicaSelector,
+    ) -> Result<ShardedResponses<Id, Req::Response>>
+    where
+        Req: sonic::service::Wrapper<S>,
+        Req::Response: PartialEq,
+        SSel: ShardSelector<S, Id>,
+    {
+        let mut futures = Vec::new();
+        for shard in shard_selector.select(&self.shards) {
+            futures.push(async move {
+                let res = shard.replicas.send_quorum(req, replica_selector).await;
+                (shard.id.clone(), res)
+            });
+        }
+
+        Ok(ShardedResponses::collect(join_all(futures).await))
+    }
+}
+
+/// A [`ShardedClient`] that follows the membership of the cluster. The shards and their
+/// replicas are updated in the background as nodes join or leave the cluster, instead
+/// of building a new client from the members for every request. Members that answer
+/// the [`probe`]s that they are still loading are left out until they are ready.
+pub struct LiveShardedClient<S: sonic::service::Service, Id: ShardIdentifier> {
+    client: Arc<RwLock<Arc<ShardedClient<S, Id>>>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl<S, Id> LiveShardedClient<S, Id>
+where
+    S: sonic::service::Service + Send + Sync + 'static,
+    Id: ShardIdentifier + Hash + Send + Sync + 'static,
+{
+    /// `shard_of` returns the shard and address of the members that serve the shards.
+    pub fn new<F>(cluster: &Cluster, shard_of: F) -> Self
+    where
+        F: Fn(&Member) -> Option<(Id, SocketAddr)> + Send + Sync + 'static,
+    {
+        Self::with_membership(cluster.subscribe(), shard_of)
+    }
+
+    fn with_membership<F>(mut membership: watch::Receiver<HashSet<Member>>, shard_of: F) -> Self
+    where
+        F: Fn(&Member) -> Option<(Id, SocketAddr)> + Send + Sync + 'static,
+    {
+        let client = Arc::new(RwLock::new(Arc::new(Self::build(
+            &membership.borrow_and_update(),
+            &shard_of,
+            &HashSet::new(),
+        ))));
+        let client_ref = Arc::clone(&client);
+
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PROBE_INTERVAL);
+            let mut loading = HashSet::new();
+
+            loop {
+                let changed = tokio::select! {
+                    res = membership.changed() => {
+                        if res.is_err() {
+                            break;
+                        }
+                        true
+                    }
+                    _ = interval.tick() => false,
+                };
+
+                let members = membership.borrow_and_update().clone();
+                let now_loading =
+                    probe::loading(members.iter().filter_map(&shard_of).map(|(_, host)| host))
+                        .await;
+
+                // the client is only rebuilt on changes, so the pooled connections are kept
+                if !changed && now_loading == loading {
+                    continue;
                 }
+                loading = now_loading;
+
+                let sharded = Arc::new(Self::build(&members, &shard_of, &loading));
+                tracing::debug!(
+                    "updated client with {} shards ({} members loading)",
+                    sharded.shards.len(),
+                    loading.len()
+                );
+
+                *client_ref.write().unwrap_or_else(|e| e.into_inner()) = sharded;
             }
+        });
+
+        Self { client, task }
+    }
+
+    fn build<F>(
+        members: &HashSet<Member>,
+        shard_of: &F,
+        loading: &HashSet<SocketAddr>,
+    ) -> ShardedClient<S, Id>
+    where
+        F: Fn(&Member) -> Option<(Id, SocketAddr)>,
+    {
+        let mut shards: HashMap<Id, Vec<SocketAddr>> = HashMap::new();
+
+        for (id, host) in members
+            .iter()
+            .filter_map(shard_of)
+            .filter(|(_, host)| !loading.contains(host))
+        {
+            shards.entry(id).or_default().push(host);
         }
 
-        Ok(results)
+        ShardedClient::new(
+            shards
+                .into_iter()
+                .map(|(id, mut replicas)| {
+                    replicas.sort();
+                    Shard::new(
+                        id,
+                        ReplicatedClient::new(
+                            replicas.into_iter().map(RemoteClient::new).collect(),
+                        ),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// The client for the current members of the cluster.
+    pub fn client(&self) -> Arc<ShardedClient<S, Id>> {
+        Arc::clone(&self.client.read().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl<S: sonic::service::Service, Id: ShardIdentifier> Drop for LiveShardedClient<S, Id> {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entrypoint::search_server::SearchService;
+
+    use super::*;
+
+    fn shards(ids: impl IntoIterator<Item = u64>) -> Vec<Shard<SearchService, ShardId>> {
+        ids.into_iter()
+            .map(|id| Shard::new(ShardId::new(id), ReplicatedClient::new(Vec::new())))
+            .collect()
+    }
+
+    fn selected(url: &str, shards: &[Shard<SearchService, ShardId>]) -> Vec<ShardId> {
+        let router = ShardRouter::new(4).unwrap();
+
+        ShardSelector::<SearchService, ShardId>::select(
+            &HashShardSelector::new(&router, url),
+            shards,
+        )
+        .into_iter()
+        .map(|shard| shard.id)
+        .collect()
+    }
+
+    #[test]
+    fn partial_responses() {
+        let responses = ShardedResponses::collect(vec![
+            (ShardId::new(0), Ok(vec![1])),
+            (ShardId::new(1), Err(Error::RequestTimeout)),
+            (ShardId::new(2), Ok(vec![2, 3])),
+        ]);
+
+        assert!(responses.is_partial());
+        assert_eq!(responses.failures.len(), 1);
+        assert_eq!(responses.failures[0].shard, ShardId::new(1));
+        assert!(matches!(responses.failures[0].error, Error::RequestTimeout));
+
+        assert_eq!(
+            responses.into_iter().collect::<Vec<_>>(),
+            vec![(ShardId::new(0), vec![1]), (ShardId::new(2), vec![2, 3])]
+        );
+
+        let complete =
+            ShardedResponses::<ShardId, i32>::collect(vec![(ShardId::new(0), Ok(vec![]))]);
+        assert!(!complete.is_partial());
+    }
+
+    #[test]
+    fn quorum_responses() {
+        let mut responses = QuorumResponses::new(2, |a: &i32, b: &i32| a == b);
+
+        assert_eq!(responses.push(1), None);
+        assert_eq!(responses.push(2), None);
+        assert_eq!(responses.push(2), Some(vec![2, 2]));
+
+        let mut single = QuorumResponses::new(1, |a: &i32, b: &i32| a == b);
+        assert_eq!(single.push(3), Some(vec![3]));
+    }
+
+    #[test]
+    fn consistency_by_key() {
+        let mut responses = QuorumResponses::new(2, |a: &(u64, &str), b: &(u64, &str)| a.0 == b.0);
+
+        assert_eq!(responses.push((1, "first")), None);
+        assert_eq!(
+            responses.push((1, "second")),
+            Some(vec![(1, "first"), (1, "second")])
+        );
+    }
+
+    fn hedging_config() -> HedgingConfig {
+        HedgingConfig {
+            quantile: 0.9,
+            window: 10,
+            min_samples: 5,
+            initial_delay_ms: 100,
+            min_delay_ms: 2,
+        }
+    }
+
+    #[test]
+    fn hedging_delay() {
+        let selector = HedgedReplicaSelector::new(&hedging_config()).unwrap();
+
+        for ms in 1..=4 {
+            selector.record(Duration::from_millis(ms));
+        }
+        // too few samples to estimate the quantile
+        assert_eq!(selector.delay(), Duration::from_millis(100));
+
+        for ms in 5..=10 {
+            selector.record(Duration::from_millis(ms));
+        }
+        assert_eq!(selector.delay(), Duration::from_millis(9));
+
+        // only the latest responses are kept
+        for _ in 0..10 {
+            selector.record(Duration::from_millis(1));
+        }
+        assert_eq!(selector.delay(), Duration::from_millis(2));
+    }
+
+    #[test]
+    fn hedging_delay_with_censored_samples() {
+        let selector = HedgedReplicaSelector::new(&hedging_config()).unwrap();
+
+        for ms in 1..=8 {
+            selector.record(Duration::from_millis(ms));
+        }
+        // the first replicas were still waiting for responses when the hedges won
+        selector.record_censored(Duration::from_millis(20));
+        selector.record_censored(Duration::from_millis(20));
+
+        // without the censored samples, the quantile would be 8ms
+        assert_eq!(selector.delay(), Duration::from_millis(20));
+
+        for _ in 0..10 {
+            selector.record(Duration::from_millis(3));
+        }
+        selector.record_censored(Duration::from_millis(1));
+        assert_eq!(selector.delay(), Duration::from_millis(3));
+    }
+
+    #[test]
+    fn invalid_hedging_quantile() {
+        for quantile in [f64::NAN, -0.1, 1.5] {
+            let config = HedgingConfig {
+                quantile,
+                ..hedging_config()
+            };
+
+            assert!(HedgedReplicaSelector::new(&config).is_err());
+        }
+    }
+
+    #[test]
+    fn hash_selector_routes_like_router() {
+        let router = ShardRouter::new(4).unwrap();
+        let forward = shards(0..4);
+        let backward = shards((0..4).rev());
+
+        for i in 0..100 {
+            let url = format!("https://example{i}.com/");
+            let owner = router.shard_of_str(&url);
+
+            assert_eq!(selected(&url, &forward), vec![owner]);
+            assert_eq!(selected(&url, &backward), vec![owner]);
+        }
+
+        assert_eq!(selected("not a url", &forward), vec![ShardId::new(0)]);
+        assert!(selected("https://example.com/", &shards([])).is_empty());
+    }
+
+    #[test]
+    fn circuit_breaker_opens_after_repeated_failures() {
+        let breaker = CircuitBreaker::new(3, Duration::from_millis(20));
+
+        breaker.on_failure();
+        breaker.on_failure();
+        breaker.on_success();
+        breaker.on_failure();
+        breaker.on_failure();
+        assert!(breaker.is_available());
+
+        breaker.on_failure();
+        assert!(!breaker.is_available());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.is_available());
+
+        // only the probe is sent while the breaker is half-open
+        breaker.on_send();
+        assert!(!breaker.is_available());
+
+        // the probe failed
+        breaker.on_failure();
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(!breaker.is_available());
+
+        std::thread::sleep(Duration::from_millis(20));
+        breaker.on_send();
+        breaker.on_success();
+        assert!(breaker.is_available());
+
+        // the failures are counted from zero after a success
+        breaker.on_failure();
+        breaker.on_failure();
+        assert!(breaker.is_available());
+    }
+
+    #[test]
+    fn open_replicas_are_skipped() {
+        let addrs: Vec<SocketAddr> = vec![
+            "127.0.0.1:40001".parse().unwrap(),
+            "127.0.0.1:40002".parse().unwrap(),
+        ];
+        let client = ReplicatedClient::<SearchService>::new(
+            addrs.iter().copied().map(RemoteClient::new).collect(),
+        );
+
+        for _ in 0..CIRCUIT_BREAKER_THRESHOLD {
+            client.clients[0].breaker.on_failure();
+        }
+        assert!(!client.clients[0].breaker.is_available());
+
+        // the state is shared by the clients of the same replica
+        assert!(!RemoteClient::<SearchService>::new(addrs[0])
+            .breaker
+            .is_available());
+
+        let available = client.available();
+        assert_eq!(available.len(), 1);
+        assert_eq!(available[0].addr, addrs[1]);
+
+        // all replicas are tried when every breaker is open
+        for _ in 0..CIRCUIT_BREAKER_THRESHOLD {
+            client.clients[1].breaker.on_failure();
+        }
+        assert_eq!(client.available().len(), 2);
+    }
+
+    #[test]
+    fn latency_aware_selector_prefers_fast_replicas() {
+        let addrs: Vec<SocketAddr> = vec![
+            "127.0.0.1:40011".parse().unwrap(),
+            "127.0.0.1:40012".parse().unwrap(),
+            "127.0.0.1:40013".parse().unwrap(),
+        ];
+        let replicas: Vec<RemoteClient<SearchService>> =
+            addrs.iter().copied().map(RemoteClient::new).collect();
+
+        replicas[0].latency.record(Duration::from_millis(10));
+        replicas[1].latency.record(Duration::from_millis(20));
+        replicas[2].latency.record(Duration::from_millis(500));
+
+        let counts = |exploration: f64| {
+            let mut rng = rand::thread_rng();
+            let mut counts = [0; 3];
+            for _ in 0..300 {
+                let selected =
+                    LatencyAwareReplicaSelector::select_with(&replicas, &mut rng, exploration);
+                assert_eq!(selected.len(), 1);
+
+                let idx = addrs.iter().position(|a| *a == selected[0].addr).unwrap();
+                counts[idx] += 1;
+            }
+            counts
+        };
+
+        let without_exploration = counts(0.0);
+        assert_eq!(without_exploration[2], 0);
+        assert!(without_exploration[0] > without_exploration[1]);
+        assert!(without_exploration[1] > 0);
+
+        // the slow replica is still tried when the response times are ignored
+        assert!(counts(1.0)[2] > 0);
+
+        // replicas without response times are tried first
+        let new = RemoteClient::<SearchService>::new("127.0.0.1:40014".parse().unwrap());
+        let replicas = vec![replicas[0].clone(), new];
+        for _ in 0..10 {
+            assert_eq!(
+                LatencyAwareReplicaSelector.select(&replicas)[0].addr,
+                replicas[1].addr
+            );
+        }
+    }
+
+    #[test]
+    fn latencies_decay_without_responses() {
+        let ewma = LatencyEwma::default();
+        ewma.record(Duration::from_millis(400));
+
+        let now = Instant::now();
+        assert_eq!(ewma.get_at(now), Some(Duration::from_millis(400)));
+        assert_eq!(
+            ewma.get_at(now + 2 * LATENCY_HALF_LIFE),
+            Some(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn deserialization_failures_are_recorded() {
+        let addr: SocketAddr = "127.0.0.1:40021".parse().unwrap();
+        let replica = RemoteClient::<SearchService>::new(addr);
+
+        for _ in 0..CIRCUIT_BREAKER_THRESHOLD {
+            let err = bincode::deserialize::<u64>(&[]).unwrap_err();
+            replica.observe::<()>(Duration::from_millis(1), &Err(Error::Serialization(err)));
+        }
+
+        // the replica responded, so it is not skipped
+        assert!(replica.breaker.is_available());
+    }
+
+    #[test]
+    fn timeouts_do_not_open_the_breaker() {
+        let addr: SocketAddr = "127.0.0.1:40022".parse().unwrap();
+        let replica = RemoteClient::<SearchService>::new(addr);
+
+        for _ in 0..CIRCUIT_BREAKER_THRESHOLD {
+            replica.observe::<()>(Duration::from_millis(50), &Err(Error::RequestTimeout));
+            replica.observe::<()>(Duration::from_millis(1), &Err(Error::DeadlineExceeded));
+        }
+
+        assert!(replica.breaker.is_available());
+        // the slow responses still count against the replica
+        assert_eq!(replica.latency(), Some(Duration::from_millis(50)));
+
+        for _ in 0..CIRCUIT_BREAKER_THRESHOLD {
+            replica.observe::<()>(Duration::from_millis(1), &Err(Error::ConnectionTimeout));
+        }
+
+        assert!(!replica.breaker.is_available());
+    }
+
+    #[test]
+    fn alternate_replicas_are_untried() {
+        let replicas: Vec<RemoteClient<SearchService>> =
+            ["127.0.0.1:40031", "127.0.0.1:40032", "127.0.0.1:40033"]
+                .into_iter()
+                .map(|addr| RemoteClient::new(addr.parse().unwrap()))
+                .collect();
+
+        let tried = vec![&replicas[1]];
+
+        let alternates = alternate_replicas(&replicas, &tried, 1);
+        assert_eq!(alternates.len(), 1);
+        assert_ne!(alternates[0].addr, replicas[1].addr);
+
+        let alternates = alternate_replicas(&replicas, &tried, 5);
+        assert_eq!(alternates.len(), 2);
+        assert!(alternates
+            .iter()
+            .all(|client| client.addr != replicas[1].addr));
+
+        assert!(alternate_replicas(&replicas, &tried, 0).is_empty());
+        assert!(
+            alternate_replicas(&replicas, &[&replicas[0], &replicas[1], &replicas[2]], 1)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn latency_ewma() {
+        let ewma = LatencyEwma::default();
+        assert_eq!(ewma.get(), None);
+
+        ewma.record(Duration::from_millis(100));
+        assert_eq!(ewma.get(), Some(Duration::from_millis(100)));
+
+        ewma.record(Duration::from_millis(200));
+        assert_eq!(ewma.get(), Some(Duration::from_millis(120)));
+    }
+
+    fn searcher(id: &str, host: &str, shard: u64) -> Member {
+        Member {
+            id: id.to_string(),
+            service: crate::distributed::member::Service::Searcher {
+                host: host.parse().unwrap(),
+                shard: ShardId::new(shard),
+                tier: Default::default(),
+                languages: Vec::new(),
+            },
+        }
+    }
+
+    fn shard_of(member: &Member) -> Option<(ShardId, SocketAddr)> {
+        match member.service {
+            crate::distributed::member::Service::Searcher { host, shard, .. } => {
+                Some((shard, host))
+            }
+            _ => None,
+        }
+    }
+
+    fn num_replicas(client: &ShardedClient<SearchService, ShardId>) -> Vec<(u64, usize)> {
+        let mut res: Vec<_> = client
+            .shards
+            .iter()
+            .map(|shard| (shard.id.as_u64(), shard.replicas.clients.len()))
+            .collect();
+        res.sort();
+        res
+    }
+
+    async fn wait_for(
+        live: &LiveShardedClient<SearchService, ShardId>,
+        expected: Vec<(u64, usize)>,
+    ) {
+        let start = Instant::now();
+
+        while num_replicas(&live.client()) != expected {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn live_sharded_client_follows_membership() {
+        let (tx, rx) = watch::channel(HashSet::from([
+            searcher("a", "127.0.0.1:40021", 1),
+            searcher("b", "127.0.0.1:40022", 1),
+        ]));
+
+        let live = LiveShardedClient::with_membership(rx, shard_of);
+        assert_eq!(num_replicas(&live.client()), vec![(1, 2)]);
+
+        // a new shard joins
+        tx.send_replace(HashSet::from([
+            searcher("a", "127.0.0.1:40021", 1),
+            searcher("b", "127.0.0.1:40022", 1),
+            searcher("c", "127.0.0.1:40023", 2),
+        ]));
+        wait_for(&live, vec![(1, 2), (2, 1)]).await;
+
+        // a replica leaves
+        tx.send_replace(HashSet::from([
+            searcher("a", "127.0.0.1:40021", 1),
+            searcher("c", "127.0.0.1:40023", 2),
+        ]));
+        wait_for(&live, vec![(1, 1), (2, 1)]).await;
+
+        // clients that are in use are not affected by the update
+        let client = live.client();
+        tx.send_replace(HashSet::new());
+        wait_for(&live, vec![]).await;
+        assert_eq!(num_replicas(&client), vec![(1, 1), (2, 1)]);
+    }
+
+    #[tokio::test]
+    async fn live_sharded_client_skips_loading_members() {
+        let responder =
+            probe::ProbeResponder::bind("127.0.0.1:0".parse().unwrap(), probe::Health::Loading)
+                .await
+                .unwrap();
+        let loading = responder.local_addr().to_string();
+
+        let (_tx, rx) = watch::channel(HashSet::from([
+            searcher("a", "127.0.0.1:40031", 1),
+            searcher("b", &loading, 1),
+        ]));
+
+        let live = LiveShardedClient::with_membership(rx, shard_of);
+        wait_for(&live, vec![(1, 1)]).await;
+
+        // the member is added once it is ready
+        responder.set_health(probe::Health::Ready);
+        wait_for(&live, vec![(1, 2)]).await;
+    }
+
+    #[test]
+    fn invalid_quorum() {
+        assert!(QuorumReplicaSelector::new(&QuorumConfig {
+            replicas: 1,
+            quorum: 2
+        })
+        .is_err());
+        assert!(QuorumReplicaSelector::new(&QuorumConfig {
+            replicas: 1,
+            quorum: 0
+        })
+        .is_err());
+        assert!(QuorumReplicaSelector::new(&QuorumConfig {
+            replicas: 3,
+            quorum: 2
+        })
+        .is_ok());
     }
 }
@@ -14,63 +14,400 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
-use tokio::net::ToSocketAddrs;
+use futures::{stream::BoxStream, StreamExt};
+use tokio::{
+    net::ToSocketAddrs,
+    sync::{watch, OwnedSemaphorePermit, Semaphore},
+};
+use tracing::Instrument;
 
-use super::Result;
+use crate::config::{defaults, ConcurrencyLimitConfig, ConnectionPoolConfig};
+use crate::metrics::PrometheusRegistry;
+
+use super::{
+    codec::Format,
+    compression::{self, Compression},
+    limit::ConcurrencyLimit,
+    metrics::ServerMetrics,
+    tls::{self, Stream},
+    Error, Result, SchemaVersions,
+};
+
+/// How long the server keeps a connection open while waiting for the next request.
+/// This is longer than the idle timeout of the pool, so the clients close idle connections first.
+const SERVER_KEEPALIVE: Duration = Duration::from_secs(90);
 
 pub trait Service: Sized + Send + Sync + 'static {
     type Request: serde::de::DeserializeOwned + Send + Sync;
     type RequestRef<'a>: serde::Serialize + Send + Sync;
     type Response: serde::Serialize + serde::de::DeserializeOwned + Send + Sync;
 
+    /// Compression of the large requests and responses of the service.
+    const COMPRESSION: Option<Compression> = None;
+
+    /// Serialization format of the requests the clients of the service send.
+    /// The server responds in the format of each request, and the clients fall back
+    /// to bincode for the servers that reject the format, see [`codec`](super::codec).
+    const CODEC: Format = Format::Bincode;
+
+    /// Version of the messages of the service. It must be bumped when the servers with the
+    /// earlier version cannot read the messages, e.g. when a message is added to the service.
+    /// The servers reject the requests with a newer version with
+    /// [`Error::IncompatibleSchema`] instead of failing to deserialize them.
+    const SCHEMA_VERSION: u32 = 0;
+
+    /// The oldest version of the messages the servers of the service can still read.
+    const MIN_SCHEMA_VERSION: u32 = 0;
+
+    /// Name of the service in the traces and metrics of its requests.
+    const NAME: &'static str;
+
+    /// Names of the messages the service handles.
+    const MESSAGES: &'static [&'static str];
+
+    /// Name of the message of the request.
+    fn message(req: &Self::Request) -> &'static str;
+
     fn handle(
         req: Self::Request,
         server: &Self,
-    ) -> impl std::future::Future<Output = Result<Self::Response>> + Send + '_;
+        ctx: Context,
+    ) -> impl std::future::Future<Output = Result<Reply<'_, Self::Response>>> + Send + '_;
+}
+
+tokio::task_local! {
+    static CONTEXT: Context;
+}
+
+/// The context of the request that is being handled.
+///
+/// The server runs the handlers inside the context of their request, so the requests
+/// the handlers send to other servers get the deadline of the original request. This also
+/// holds for code that runs a [`Context::scope`], like the api when it searches.
+/// Tasks that are spawned from the handlers do not inherit the context.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Context {
+    deadline: Option<Instant>,
+}
+
+impl Context {
+    pub fn new(deadline: Option<Instant>) -> Self {
+        Self { deadline }
+    }
+
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self::new(Some(Instant::now() + timeout))
+    }
+
+    /// The context of the current task, or an empty context outside of any scope.
+    pub fn current() -> Self {
+        CONTEXT.try_with(|ctx| *ctx).unwrap_or_default()
+    }
+
+    /// Run the future inside the context.
+    pub async fn scope<F: Future>(self, fut: F) -> F::Output {
+        CONTEXT.scope(self, fut).await
+    }
+
+    /// When the client stops waiting for the response.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining()
+            .is_some_and(|remaining| remaining.is_zero())
+    }
+
+    /// Fails with [`Error::DeadlineExceeded`] if the deadline has passed.
+    pub fn check(&self) -> Result<()> {
+        if self.is_expired() {
+            Err(Error::DeadlineExceeded)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The `timeout` shortened to the remaining time of the context.
+    pub fn timeout(&self, timeout: Duration) -> Duration {
+        match self.remaining() {
+            Some(remaining) => remaining.min(timeout),
+            None => timeout,
+        }
+    }
+}
+
+/// The response of a service to a request.
+pub enum Reply<'a, R> {
+    Single(R),
+    /// The response is sent in chunks as they are ready, instead of being buffered in memory.
+    Stream(BoxStream<'a, Result<R>>),
 }
 
 pub trait Message<S: Service> {
     type Response;
-    fn handle(self, server: &S) -> impl std::future::Future<Output = Result<Self::Response>>;
+    fn handle(
+        self,
+        server: &S,
+        ctx: Context,
+    ) -> impl std::future::Future<Output = Result<Self::Response>>;
 }
 pub trait Wrapper<S: Service>: Message<S> {
     fn wrap_request_ref(req: &Self) -> S::RequestRef<'_>;
     fn unwrap_response(res: S::Response) -> Option<Self::Response>;
 }
 
+/// A message that the service responds to with a stream of items.
+pub trait StreamingMessage<S: Service> {
+    type Item;
+    fn handle(
+        self,
+        server: &S,
+        ctx: Context,
+    ) -> impl std::future::Future<Output = Result<BoxStream<'_, Result<Self::Item>>>>;
+}
+pub trait StreamingWrapper<S: Service>: StreamingMessage<S> {
+    fn wrap_request_ref(req: &Self) -> S::RequestRef<'_>;
+    fn unwrap_item(res: S::Response) -> Option<Self::Item>;
+}
+
+/// Counts the open connections of a server, so a shutdown can wait for them to close.
+struct ConnectionGuard(Arc<watch::Sender<usize>>);
+
+impl ConnectionGuard {
+    fn new(connections: &Arc<watch::Sender<usize>>) -> Self {
+        connections.send_modify(|n| *n += 1);
+        Self(Arc::clone(connections))
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.send_modify(|n| *n -= 1);
+    }
+}
+
 pub struct Server<S: Service> {
     inner: super::Server<S::Request, S::Response>,
     service: Arc<S>,
+    metrics: Arc<ServerMetrics>,
+    limit: Option<Arc<ConcurrencyLimit>>,
+    connections: Arc<watch::Sender<usize>>,
+    closing: watch::Sender<bool>,
 }
 
 impl<S: Service> Server<S> {
+    /// Bind the server with the TLS settings of the service, see [`tls::configure`].
     pub async fn bind(service: S, addr: impl ToSocketAddrs) -> Result<Self> {
+        let tls = tls::service::<S>();
+
         Ok(Server {
-            inner: super::Server::bind(addr).await?,
+            inner: super::Server::bind_with_tls(addr, tls.acceptor)
+                .await?
+                .with_secret(tls.secret)
+                .with_schema_versions(SchemaVersions {
+                    min: S::MIN_SCHEMA_VERSION,
+                    max: S::SCHEMA_VERSION,
+                })
+                .with_compression(S::COMPRESSION),
             service: Arc::new(service),
+            metrics: Arc::new(ServerMetrics::new(S::NAME, S::MESSAGES)),
+            limit: None,
+            connections: Arc::new(watch::Sender::new(0)),
+            closing: watch::Sender::new(false),
         })
     }
+
+    /// The service that handles the requests of the server.
+    pub fn service(&self) -> Arc<S> {
+        Arc::clone(&self.service)
+    }
+
+    /// Shed the requests above the limit by responding that the server is overloaded.
+    pub fn with_concurrency_limit(mut self, config: Option<ConcurrencyLimitConfig>) -> Self {
+        self.limit = config.map(|config| Arc::new(ConcurrencyLimit::from(config)));
+        self
+    }
+
+    /// Label the traces and metrics of the requests with the shard of the server.
+    pub fn with_shard(mut self, shard: impl ToString) -> Self {
+        self.metrics =
+            Arc::new(ServerMetrics::new(S::NAME, S::MESSAGES).with_shard(shard.to_string()));
+        self
+    }
+
+    /// Register the metrics of the requests the server handles.
+    pub fn register_metrics(
+        &self,
+        registry: &mut PrometheusRegistry,
+    ) -> std::result::Result<(), crate::metrics::Error> {
+        self.metrics.register(registry)
+    }
+
     pub async fn accept(&self) -> Result<()> {
         let mut req = self.inner.accept().await?;
 
         let service = Arc::clone(&self.service);
+        let metrics = Arc::clone(&self.metrics);
+        let limit = self.limit.clone();
+        let mut closing = self.closing.subscribe();
+        let guard = ConnectionGuard::new(&self.connections);
         tokio::spawn(async move {
-            match S::handle(req.take_body(), &service).await {
-                Ok(res) => {
-                    if let Err(e) = req.respond(res).await {
-                        tracing::error!("failed to respond to request: {}", e);
+            let _guard = guard;
+
+            loop {
+                let start = Instant::now();
+                let message = S::message(req.body());
+                let bytes_in = req.bytes_in();
+                let span = tracing::info_span!(
+                    "sonic_request",
+                    service = metrics.service(),
+                    message,
+                    shard = metrics.shard(),
+                    bytes_in,
+                    bytes_out = tracing::field::Empty,
+                    latency_ms = tracing::field::Empty,
+                );
+
+                let record = |bytes_out: usize, is_error: bool| {
+                    let latency = start.elapsed();
+                    span.record("bytes_out", bytes_out);
+                    span.record("latency_ms", latency.as_secs_f64() * 1000.0);
+                    metrics.record(message, bytes_in, bytes_out, latency, is_error);
+                };
+
+                let permit = match &limit {
+                    Some(limit) => match limit.acquire(req.deadline()).await {
+                        Some(permit) => Some(permit),
+                        None => {
+                            metrics.record_shed(message);
+                            span.in_scope(|| tracing::debug!("shedding request"));
+                            req.reject_overloaded().await.ok();
+                            break;
+                        }
+                    },
+                    None => None,
+                };
+
+                let ctx = Context::new(req.deadline());
+                let res = match ctx
+                    .scope(S::handle(req.take_body(), &service, ctx))
+                    .instrument(span.clone())
+                    .await
+                {
+                    Ok(res) => res,
+                    // the client has stopped waiting, so the connection is closed without a response
+                    Err(Error::DeadlineExceeded) => {
+                        record(0, true);
+                        span.in_scope(|| tracing::debug!("request exceeded its deadline"));
+                        break;
+                    }
+                    Err(e) => {
+                        record(0, true);
+                        span.in_scope(|| tracing::error!("failed to handle request: {}", e));
+                        break;
+                    }
+                };
+
+                let written = match res {
+                    Reply::Single(res) => req.write_response(&res).instrument(span.clone()).await,
+                    Reply::Stream(chunks) => {
+                        req.write_stream(chunks).instrument(span.clone()).await
+                    }
+                };
+
+                // the slot is free while the connection waits for the next request
+                drop(permit);
+
+                match written {
+                    Ok(bytes_out) => record(bytes_out, false),
+                    Err(e) => {
+                        record(0, true);
+                        span.in_scope(|| tracing::error!("failed to respond to request: {}", e));
+                        break;
                     }
                 }
-                Err(e) => {
-                    tracing::error!("failed to handle request: {}", e);
+
+                // connections that wait for their next request are closed during a shutdown
+                let next = tokio::select! {
+                    biased;
+                    _ = closing.wait_for(|closing| *closing) => break,
+                    next = req.next_request(SERVER_KEEPALIVE) => next,
+                };
+
+                match next {
+                    Ok(Some(next)) => req = next,
+                    Ok(None) => break,
+                    Err(e) => {
+                        tracing::debug!("failed to read the next request: {}", e);
+                        break;
+                    }
                 }
             }
         });
 
         Ok(())
     }
+
+    /// Handle requests until `signal` completes, and then shut the server down gracefully.
+    ///
+    /// The server stops accepting connections, closes the connections that are waiting for
+    /// their next request and waits up to `drain_timeout` for the requests that are being
+    /// handled to finish. Clients retry the requests of closed idle connections on a new
+    /// connection, so no requests are lost as long as they finish within the timeout.
+    pub async fn serve_until(self, signal: impl Future<Output = ()>, drain_timeout: Duration) {
+        tokio::pin!(signal);
+
+        loop {
+            tokio::select! {
+                res = self.accept() => {
+                    if let Err(e) = res {
+                        tracing::error!("{:?}", e);
+                    }
+                }
+                _ = &mut signal => break,
+            }
+        }
+
+        let Server {
+            inner,
+            connections,
+            closing,
+            ..
+        } = self;
+
+        // new connections are refused once the listener is closed
+        drop(inner);
+        closing.send_replace(true);
+
+        let open = *connections.borrow();
+        tracing::info!("shutting down, waiting for {} connections to close", open);
+
+        let mut connections = connections.subscribe();
+        match tokio::time::timeout(drain_timeout, connections.wait_for(|n| *n == 0)).await {
+            Ok(_) => tracing::info!("all connections are closed"),
+            Err(_) => tracing::warn!(
+                "{} connections are still open after the drain timeout",
+                *connections.borrow()
+            ),
+        }
+    }
 }
 
 pub struct Connection<'a, S: Service> {
@@ -80,31 +417,47 @@ pub struct Connection<'a, S: Service> {
 impl<'a, S: Service> Connection<'a, S> {
     #[allow(dead_code)]
     pub async fn create(server: impl ToSocketAddrs) -> Result<Connection<'a, S>> {
-        Ok(Connection {
-            inner: super::Connection::create(server).await?,
-        })
+        Self::create_with_timeout(server, Duration::from_secs(30)).await
     }
+    /// Connect with the TLS settings of the service, see [`tls::configure`].
     #[allow(dead_code)]
     pub async fn create_with_timeout(
         server: impl ToSocketAddrs,
         timeout: Duration,
     ) -> Result<Connection<'a, S>> {
+        let tls = tls::service::<S>();
+
         Ok(Connection {
-            inner: super::Connection::create_with_timeout(server, timeout).await?,
+            inner: super::Connection::create_with_tls(server, timeout, tls.client.as_ref())
+                .await?
+                .with_secret(tls.secret)
+                .with_codec(S::CODEC)
+                .with_schema_version(S::SCHEMA_VERSION)
+                .with_compression(compression::client_compression(S::COMPRESSION)),
         })
     }
+    /// Attach the deadline of the current [`Context`] to the connection.
+    fn in_context(self, ctx: Context) -> Result<super::Connection<S::RequestRef<'a>, S::Response>> {
+        ctx.check()?;
+        Ok(self.inner.with_deadline(ctx.deadline()))
+    }
     #[allow(dead_code)]
     pub async fn send_without_timeout<R: Wrapper<S>>(self, request: &'a R) -> Result<R::Response> {
-        Ok(R::unwrap_response(
-            self.inner
-                .send_without_timeout(&R::wrap_request_ref(request))
-                .await?,
-        )
-        .unwrap())
+        let ctx = Context::current();
+        let inner = self.in_context(ctx)?;
+        let req = R::wrap_request_ref(request);
+
+        let res = match ctx.remaining() {
+            Some(remaining) => inner.send_with_timeout(&req, remaining).await?,
+            None => inner.send_without_timeout(&req).await?,
+        };
+
+        Ok(R::unwrap_response(res).unwrap())
     }
     #[allow(dead_code)]
     pub async fn send<R: Wrapper<S>>(self, request: &'a R) -> Result<R::Response> {
-        Ok(R::unwrap_response(self.inner.send(&R::wrap_request_ref(request)).await?).unwrap())
+        self.send_with_timeout(request, Duration::from_secs(90))
+            .await
     }
     #[allow(dead_code)]
     pub async fn send_with_timeout<R: Wrapper<S>>(
@@ -112,13 +465,31 @@ impl<'a, S: Service> Connection<'a, S> {
         request: &'a R,
         timeout: Duration,
     ) -> Result<R::Response> {
+        let ctx = Context::current();
         Ok(R::unwrap_response(
-            self.inner
-                .send_with_timeout(&R::wrap_request_ref(request), timeout)
+            self.in_context(ctx)?
+                .send_with_timeout(&R::wrap_request_ref(request), ctx.timeout(timeout))
                 .await?,
         )
         .unwrap())
     }
+
+    /// Send a streaming message and read the items as the stream is polled.
+    /// `timeout` applies to each item.
+    #[allow(dead_code)]
+    pub async fn send_streaming<R: StreamingWrapper<S>>(
+        self,
+        request: &'a R,
+        timeout: Duration,
+    ) -> Result<impl futures::Stream<Item = Result<R::Item>> + 'a> {
+        let ctx = Context::current();
+        let chunks = self
+            .in_context(ctx)?
+            .send_streaming(&R::wrap_request_ref(request), ctx.timeout(timeout))
+            .await?;
+
+        Ok(chunks.map(|chunk| chunk.map(|res| R::unwrap_item(res).unwrap())))
+    }
 }
 
 pub struct ResilientConnection<'a, S: Service> {
@@ -126,13 +497,23 @@ pub struct ResilientConnection<'a, S: Service> {
 }
 
 impl<'a, S: Service> ResilientConnection<'a, S> {
+    /// Connect to the first reachable of the addresses of the server.
     pub async fn create_with_timeout<Rt: Iterator<Item = Duration>>(
-        addr: SocketAddr,
+        addrs: &[SocketAddr],
         timeout: Duration,
         retry: Rt,
     ) -> Result<ResilientConnection<'a, S>> {
         Ok(Self {
-            inner: super::ResilientConnection::create_with_timeout(addr, timeout, retry).await?,
+            inner: super::ResilientConnection::create_with_timeout(
+                addrs,
+                timeout,
+                retry,
+                &tls::service::<S>(),
+            )
+            .await?
+            .with_codec(S::CODEC)
+            .with_schema_version(S::SCHEMA_VERSION)
+            .with_compression(compression::client_compression(S::COMPRESSION)),
         })
     }
 
@@ -141,32 +522,315 @@ impl<'a, S: Service> ResilientConnection<'a, S> {
         request: &'a R,
         timeout: Duration,
     ) -> Result<R::Response> {
+        let ctx = Context::current();
+        ctx.check()?;
+
         let req_ref = R::wrap_request_ref(request);
-        Ok(R::unwrap_response(self.inner.send_with_timeout(&req_ref, timeout).await?).unwrap())
+        Ok(R::unwrap_response(
+            self.inner
+                .with_deadline(ctx.deadline())
+                .send_with_timeout(&req_ref, ctx.timeout(timeout))
+                .await?,
+        )
+        .unwrap())
+    }
+
+    /// Send a streaming message and read the items as the stream is polled.
+    /// `timeout` applies to each item.
+    pub async fn send_streaming<R: StreamingWrapper<S>>(
+        self,
+        request: &'a R,
+        timeout: Duration,
+    ) -> Result<impl futures::Stream<Item = Result<R::Item>> + 'a> {
+        let ctx = Context::current();
+        ctx.check()?;
+
+        let chunks = self
+            .inner
+            .with_deadline(ctx.deadline())
+            .send_streaming(&R::wrap_request_ref(request), ctx.timeout(timeout))
+            .await?;
+
+        Ok(chunks.map(|chunk| chunk.map(|res| R::unwrap_item(res).unwrap())))
+    }
+}
+
+struct IdleConnection {
+    stream: Stream,
+    since: Instant,
+}
+
+impl IdleConnection {
+    /// The connection can be reused if it has not been idle for too long, and the server
+    /// has neither closed it nor sent anything on it since the last response.
+    fn is_healthy(&self, idle_timeout: Duration) -> bool {
+        if self.since.elapsed() > idle_timeout {
+            return false;
+        }
+
+        let mut buf = [0; 1];
+        matches!(
+            self.stream.tcp().try_read(&mut buf),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock
+        )
+    }
+}
+
+struct AddrPool {
+    idle: Mutex<Vec<IdleConnection>>,
+    permits: Arc<Semaphore>,
+}
+
+/// Open connections to the sonic servers, so requests to the same address can reuse a
+/// connection instead of doing a new handshake. At most `max_connections` requests are
+/// in flight to each address; later requests wait for a connection to be returned.
+pub struct ConnectionPool {
+    addrs: Mutex<HashMap<SocketAddr, Arc<AddrPool>>>,
+    max_connections: AtomicUsize,
+    idle_timeout_ms: AtomicU64,
+}
+
+static POOL: once_cell::sync::Lazy<ConnectionPool> = once_cell::sync::Lazy::new(|| {
+    ConnectionPool::new(&ConnectionPoolConfig {
+        max_connections: defaults::ConnectionPool::max_connections(),
+        idle_timeout_ms: defaults::ConnectionPool::idle_timeout_ms(),
+        compression: false,
+    })
+});
+
+/// The connection pool that is shared by all clients in the process.
+pub fn pool() -> &'static ConnectionPool {
+    &POOL
+}
+
+impl ConnectionPool {
+    pub fn new(config: &ConnectionPoolConfig) -> Self {
+        Self {
+            addrs: Mutex::new(HashMap::new()),
+            max_connections: AtomicUsize::new(config.max_connections.max(1)),
+            idle_timeout_ms: AtomicU64::new(config.idle_timeout_ms),
+        }
+    }
+
+    /// Apply the config. This closes the idle connections of the pool.
+    pub fn configure(&self, config: &ConnectionPoolConfig) {
+        self.max_connections
+            .store(config.max_connections.max(1), Ordering::Relaxed);
+        self.idle_timeout_ms
+            .store(config.idle_timeout_ms, Ordering::Relaxed);
+        compression::set_client_compression(config.compression);
+        self.addrs.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    fn idle_timeout(&self) -> Duration {
+        Duration::from_millis(self.idle_timeout_ms.load(Ordering::Relaxed))
+    }
+
+    fn addr_pool(&self, addr: SocketAddr) -> Arc<AddrPool> {
+        let mut addrs = self.addrs.lock().unwrap_or_else(|e| e.into_inner());
+
+        Arc::clone(addrs.entry(addr).or_insert_with(|| {
+            Arc::new(AddrPool {
+                idle: Mutex::new(Vec::new()),
+                permits: Arc::new(Semaphore::new(self.max_connections.load(Ordering::Relaxed))),
+            })
+        }))
+    }
+
+    /// Number of idle connections to the address.
+    pub fn num_idle(&self, addr: SocketAddr) -> usize {
+        self.addr_pool(addr)
+            .idle
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .len()
+    }
+
+    /// Borrow a connection to the server with the addresses. The connections are pooled under
+    /// the first address. An idle connection is reused if there is a healthy one, otherwise
+    /// a new connection is created to the first reachable address with the `timeout` and
+    /// `retry` of [`ResilientConnection`].
+    pub async fn get<S: Service>(
+        &self,
+        addrs: &[SocketAddr],
+        timeout: Duration,
+        retry: impl Iterator<Item = Duration>,
+    ) -> Result<PooledConnection<S>> {
+        let Some(addr) = addrs.first() else {
+            return Err(super::Error::IO(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "no addresses to connect to",
+            )));
+        };
+
+        let pool = self.addr_pool(*addr);
+        let permit = Arc::clone(&pool.permits)
+            .acquire_owned()
+            .await
+            .map_err(|_| super::Error::PoolCreation)?;
+
+        let idle_timeout = self.idle_timeout();
+        loop {
+            let idle = pool.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+
+            match idle {
+                Some(idle) if idle.is_healthy(idle_timeout) => {
+                    return Ok(PooledConnection::new(idle.stream, permit, pool, true));
+                }
+                Some(_) => continue,
+                None => break,
+            }
+        }
+
+        let tls = tls::service::<S>();
+        let stream = super::connect_with_retry(addrs, timeout, retry, tls.client.as_ref()).await?;
+        stream.tcp().set_nodelay(true)?;
+        socket2::SockRef::from(stream.tcp())
+            .set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(Duration::from_secs(30)))?;
+
+        Ok(PooledConnection::new(stream, permit, pool, false))
+    }
+}
+
+/// A connection that is borrowed from the [`ConnectionPool`]. It is returned to the pool
+/// after a successful request, and closed if the request fails.
+pub struct PooledConnection<S: Service> {
+    stream: Stream,
+    _permit: OwnedSemaphorePermit,
+    pool: Arc<AddrPool>,
+    is_reused: bool,
+    marker: std::marker::PhantomData<S>,
+}
+
+impl<S: Service> PooledConnection<S> {
+    fn new(
+        stream: Stream,
+        permit: OwnedSemaphorePermit,
+        pool: Arc<AddrPool>,
+        is_reused: bool,
+    ) -> Self {
+        Self {
+            stream,
+            _permit: permit,
+            pool,
+            is_reused,
+            marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Whether the connection has been used for an earlier request.
+    /// The server might have closed it in the meantime.
+    pub fn is_reused(&self) -> bool {
+        self.is_reused
+    }
+
+    pub async fn send_with_timeout<R: Wrapper<S>>(
+        self,
+        request: &R,
+        timeout: Duration,
+    ) -> Result<R::Response> {
+        let ctx = Context::current();
+        ctx.check()?;
+
+        let mut conn =
+            super::Connection::<S::RequestRef<'_>, S::Response>::from_stream(self.stream)
+                .with_secret(tls::service::<S>().secret)
+                .with_codec(S::CODEC)
+                .with_reused(self.is_reused)
+                .with_schema_version(S::SCHEMA_VERSION)
+                .with_compression(compression::client_compression(S::COMPRESSION))
+                .with_deadline(ctx.deadline());
+        let res = conn
+            .send_keepalive(&R::wrap_request_ref(request), ctx.timeout(timeout))
+            .await?;
+
+        self.pool
+            .idle
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(IdleConnection {
+                stream: conn.into_stream(),
+                since: Instant::now(),
+            });
+
+        Ok(R::unwrap_response(res).unwrap())
+    }
+
+    /// Pipeline the requests on the connection and return the responses in the order of
+    /// the requests. `timeout` applies to the whole batch.
+    pub async fn send_batch_with_timeout<R: Wrapper<S>>(
+        self,
+        requests: &[R],
+        timeout: Duration,
+    ) -> Result<Vec<R::Response>> {
+        let ctx = Context::current();
+        ctx.check()?;
+
+        let mut conn =
+            super::Connection::<S::RequestRef<'_>, S::Response>::from_stream(self.stream)
+                .with_secret(tls::service::<S>().secret)
+                .with_codec(S::CODEC)
+                .with_reused(self.is_reused)
+                .with_schema_version(S::SCHEMA_VERSION)
+                .with_compression(compression::client_compression(S::COMPRESSION))
+                .with_deadline(ctx.deadline());
+        let requests: Vec<_> = requests.iter().map(R::wrap_request_ref).collect();
+        let res = conn
+            .send_batch_keepalive(&requests, ctx.timeout(timeout))
+            .await?;
+
+        self.pool
+            .idle
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(IdleConnection {
+                stream: conn.into_stream(),
+                since: Instant::now(),
+            });
+
+        Ok(res
+            .into_iter()
+            .map(|res| R::unwrap_response(res).unwrap())
+            .collect())
     }
 }
 
 #[macro_export]
 macro_rules! sonic_service {
-    ($service:ident, [$($req:ident),*$(,)?]) => {
+    (@option compression = $value:expr) => {
+        const COMPRESSION: Option<$crate::distributed::sonic::compression::Compression> = $value;
+    };
+    (@option codec = $value:expr) => {
+        const CODEC: $crate::distributed::sonic::codec::Format = $value;
+    };
+    (@option schema_version = $value:expr) => {
+        const SCHEMA_VERSION: u32 = $value;
+    };
+    (@option min_schema_version = $value:expr) => {
+        const MIN_SCHEMA_VERSION: u32 = $value;
+    };
+    ($service:ident, [$($req:ident),*$(,)?], streaming = [$($sreq:ident),*$(,)?] $(, $option:ident = $value:expr)*) => {
         mod service_impl__ {
             #![allow(dead_code)]
 
-            use super::{$service, $($req),*};
+            use super::{$service, $($req,)* $($sreq),*};
 
             use $crate::distributed::sonic;
 
             #[derive(Debug, Clone, ::serde::Deserialize)]
             pub enum Request {
                 $($req($req),)*
+                $($sreq($sreq),)*
             }
             #[derive(Debug, Clone, ::serde::Serialize)]
             pub enum RequestRef<'a> {
                 $($req(&'a $req),)*
+                $($sreq(&'a $sreq),)*
             }
             #[derive(::serde::Serialize, ::serde::Deserialize)]
             pub enum Response {
                 $($req(<$req as sonic::service::Message<$service>>::Response),)*
+                $($sreq(<$sreq as sonic::service::StreamingMessage<$service>>::Item),)*
             }
             $(
                 impl sonic::service::Wrapper<$service> for $req {
@@ -183,20 +847,54 @@ macro_rules! sonic_service {
                     }
                 }
             )*
+            $(
+                impl sonic::service::StreamingWrapper<$service> for $sreq {
+                    fn wrap_request_ref(req: &Self) -> RequestRef {
+                        RequestRef::$sreq(req)
+                    }
+                    fn unwrap_item(res: <$service as sonic::service::Service>::Response) -> Option<Self::Item> {
+                        #[allow(irrefutable_let_patterns)]
+                        if let Response::$sreq(value) = res {
+                            Some(value)
+                        } else {
+                            None
+                        }
+                    }
+                }
+            )*
             impl sonic::service::Service for $service {
                 type Request = Request;
                 type RequestRef<'a> = RequestRef<'a>;
                 type Response = Response;
 
+                $($crate::sonic_service!(@option $option = $value);)*
+
+                const NAME: &'static str = stringify!($service);
+
+                const MESSAGES: &'static [&'static str] = &[$(stringify!($req),)* $(stringify!($sreq),)*];
+
+                fn message(req: &Request) -> &'static str {
+                    match req {
+                        $(Request::$req(_) => stringify!($req),)*
+                        $(Request::$sreq(_) => stringify!($sreq),)*
+                    }
+                }
+
                 // NOTE: This is a workaround for the fact that async functions
                 // don't have a Send bound by default, and there's currently no
                 // way of specifying that.
                 #[allow(clippy::manual_async_fn)]
-                fn handle(req: Request, server: &Self) -> impl std::future::Future<Output = sonic::Result<Self::Response>> + Send + '_ {
+                fn handle(req: Request, server: &Self, ctx: sonic::service::Context) -> impl std::future::Future<Output = sonic::Result<sonic::service::Reply<'_, Self::Response>>> + Send + '_ {
                     async move {
                         match req {
                             $(
-                                Request::$req(value) => Ok(Response::$req(sonic::service::Message::handle(value, server).await?)),
+                                Request::$req(value) => Ok(sonic::service::Reply::Single(Response::$req(sonic::service::Message::handle(value, server, ctx).await?))),
+                            )*
+                            $(
+                                Request::$sreq(value) => {
+                                    let items = sonic::service::StreamingMessage::handle(value, server, ctx).await?;
+                                    Ok(sonic::service::Reply::Stream(::futures::StreamExt::boxed(::futures::StreamExt::map(items, |item| item.map(Response::$sreq)))))
+                                }
                             )*
                         }
                     }
@@ -209,16 +907,21 @@ macro_rules! sonic_service {
             }
         }
     };
+    ($service:ident, [$($req:ident),*$(,)?] $(, $option:ident = $value:expr)*) => {
+        $crate::sonic_service!($service, [$($req),*], streaming = [] $(, $option = $value)*);
+    };
 }
 
 #[cfg(test)]
 mod tests {
     use proptest::prelude::*;
 
-    use std::{marker::PhantomData, net::SocketAddr, sync::atomic::AtomicI32};
+    use std::{marker::PhantomData, net::SocketAddr, sync::atomic::AtomicI32, time::Duration};
+
+    use crate::config::{ConcurrencyLimitConfig, ConnectionPoolConfig};
 
-    use super::{Server, Service, Wrapper};
-    use futures::Future;
+    use super::{ConnectionPool, Context, Server, Service, Wrapper};
+    use futures::{Future, StreamExt, TryStreamExt};
 
     struct ConnectionBuilder<S> {
         addr: SocketAddr,
@@ -242,6 +945,22 @@ mod tests {
         service: S,
         con_fn: impl FnOnce(ConnectionBuilder<S>) -> Y + Send + 'static,
     ) -> Result<B, TestCaseError>
+    where
+        S::Request: Send + Sync + 'static,
+        S::Response: Send + Sync + 'static,
+    {
+        fixture_with_limit(service, None, con_fn)
+    }
+
+    fn fixture_with_limit<
+        S: Service + Send + Sync + 'static,
+        B: Send + Sync + 'static,
+        Y: Future<Output = Result<B, TestCaseError>> + Send,
+    >(
+        service: S,
+        limit: Option<ConcurrencyLimitConfig>,
+        con_fn: impl FnOnce(ConnectionBuilder<S>) -> Y + Send + 'static,
+    ) -> Result<B, TestCaseError>
     where
         S::Request: Send + Sync + 'static,
         S::Response: Send + Sync + 'static,
@@ -251,7 +970,10 @@ mod tests {
             .build()
             .unwrap()
             .block_on(async move {
-                let server = Server::bind(service, ("127.0.0.1", 0)).await.unwrap();
+                let server = Server::bind(service, ("127.0.0.1", 0))
+                    .await
+                    .unwrap()
+                    .with_concurrency_limit(limit);
                 let addr = server.inner.listener.local_addr().unwrap();
 
                 let svr_task: tokio::task::JoinHandle<Result<(), anyhow::Error>> =
@@ -277,18 +999,23 @@ mod tests {
     mod counter_service {
         use std::sync::atomic::AtomicI32;
 
+        use futures::{stream::BoxStream, StreamExt};
         use proptest_derive::Arbitrary;
         use serde::{Deserialize, Serialize};
 
         use crate::distributed::sonic;
 
-        use super::super::Message;
+        use super::super::{Message, StreamingMessage};
 
         pub struct CounterService {
             pub counter: AtomicI32,
         }
 
-        sonic_service!(CounterService, [Change, Reset]);
+        sonic_service!(
+            CounterService,
+            [Change, Reset, RemainingMs, Sleep],
+            streaming = [CountUp]
+        );
 
         #[derive(Debug, Clone, Serialize, Deserialize, Arbitrary)]
         pub struct Change {
@@ -296,11 +1023,28 @@ mod tests {
         }
         #[derive(Debug, Clone, Serialize, Deserialize)]
         pub struct Reset;
+        /// The milliseconds that are left before the deadline of the request.
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        pub struct RemainingMs;
+        /// Wait before responding, so the request occupies the server.
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        pub struct Sleep {
+            pub ms: u64,
+        }
+        /// Add one to the counter `times` times, and stream the counter after each addition.
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        pub struct CountUp {
+            pub times: usize,
+        }
 
         impl Message<CounterService> for Change {
             type Response = i32;
 
-            async fn handle(self, server: &CounterService) -> sonic::Result<Self::Response> {
+            async fn handle(
+                self,
+                server: &CounterService,
+                _ctx: sonic::service::Context,
+            ) -> sonic::Result<Self::Response> {
                 let prev = server
                     .counter
                     .fetch_add(self.amount, std::sync::atomic::Ordering::SeqCst);
@@ -311,11 +1055,59 @@ mod tests {
         impl Message<CounterService> for Reset {
             type Response = ();
 
-            async fn handle(self, server: &CounterService) -> sonic::Result<Self::Response> {
+            async fn handle(
+                self,
+                server: &CounterService,
+                _ctx: sonic::service::Context,
+            ) -> sonic::Result<Self::Response> {
                 server.counter.store(0, std::sync::atomic::Ordering::SeqCst);
                 Ok(())
             }
         }
+
+        impl Message<CounterService> for RemainingMs {
+            type Response = Option<u128>;
+
+            async fn handle(
+                self,
+                _server: &CounterService,
+                ctx: sonic::service::Context,
+            ) -> sonic::Result<Self::Response> {
+                Ok(ctx.remaining().map(|remaining| remaining.as_millis()))
+            }
+        }
+
+        impl Message<CounterService> for Sleep {
+            type Response = ();
+
+            async fn handle(
+                self,
+                _server: &CounterService,
+                _ctx: sonic::service::Context,
+            ) -> sonic::Result<Self::Response> {
+                tokio::time::sleep(std::time::Duration::from_millis(self.ms)).await;
+                Ok(())
+            }
+        }
+
+        impl StreamingMessage<CounterService> for CountUp {
+            type Item = i32;
+
+            async fn handle(
+                self,
+                server: &CounterService,
+                _ctx: sonic::service::Context,
+            ) -> sonic::Result<BoxStream<'_, sonic::Result<Self::Item>>> {
+                Ok(futures::stream::iter(0..self.times)
+                    .map(|_| {
+                        Ok(server
+                            .counter
+                            .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+                            + 1)
+                    })
+                    .boxed())
+            }
+        }
     }
 
     use counter_service::*;
@@ -352,6 +1144,342 @@ mod tests {
         Ok(())
     }
 
+    fn pool(max_connections: usize, idle_timeout_ms: u64) -> ConnectionPool {
+        ConnectionPool::new(&ConnectionPoolConfig {
+            max_connections,
+            idle_timeout_ms,
+            compression: false,
+        })
+    }
+
+    #[test]
+    fn pooled_connections_are_reused() -> Result<(), TestCaseError> {
+        fixture(
+            CounterService {
+                counter: AtomicI32::new(0),
+            },
+            |b| async move {
+                let pool = pool(1, 60_000);
+
+                for i in 1..=3 {
+                    let conn = pool
+                        .get::<CounterService>(
+                            &[b.addr],
+                            Duration::from_secs(1),
+                            std::iter::empty(),
+                        )
+                        .await
+                        .unwrap();
+                    assert_eq!(conn.is_reused(), i > 1);
+
+                    let val = conn
+                        .send_with_timeout(&Change { amount: 1 }, Duration::from_secs(1))
+                        .await
+                        .unwrap();
+                    assert_eq!(val, i);
+                    assert_eq!(pool.num_idle(b.addr), 1);
+                }
+
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn pooled_connections_fall_back_to_other_addresses() -> Result<(), TestCaseError> {
+        fixture(
+            CounterService {
+                counter: AtomicI32::new(0),
+            },
+            |b| async move {
+                let pool = pool(1, 60_000);
+                let unreachable: SocketAddr = "127.0.0.1:1".parse().unwrap();
+
+                let conn = pool
+                    .get::<CounterService>(
+                        &[unreachable, b.addr],
+                        Duration::from_secs(1),
+                        std::iter::empty(),
+                    )
+                    .await
+                    .unwrap();
+                let val = conn
+                    .send_with_timeout(&Change { amount: 1 }, Duration::from_secs(1))
+                    .await
+                    .unwrap();
+                assert_eq!(val, 1);
+
+                // the connection is pooled under the first address of the server
+                assert_eq!(pool.num_idle(unreachable), 1);
+
+                assert!(pool
+                    .get::<CounterService>(&[], Duration::from_secs(1), std::iter::empty())
+                    .await
+                    .is_err());
+
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn pool_limits() -> Result<(), TestCaseError> {
+        fixture(
+            CounterService {
+                counter: AtomicI32::new(0),
+            },
+            |b| async move {
+                let pool = pool(1, 0);
+
+                let conn = pool
+                    .get::<CounterService>(&[b.addr], Duration::from_secs(1), std::iter::empty())
+                    .await
+                    .unwrap();
+
+                // the only connection is in use
+                assert!(tokio::time::timeout(
+                    Duration::from_millis(50),
+                    pool.get::<CounterService>(
+                        &[b.addr],
+                        Duration::from_secs(1),
+                        std::iter::empty()
+                    ),
+                )
+                .await
+                .is_err());
+
+                conn.send_with_timeout(&Reset, Duration::from_secs(1))
+                    .await
+                    .unwrap();
+                tokio::time::sleep(Duration::from_millis(5)).await;
+
+                // the idle connection has expired
+                let conn = pool
+                    .get::<CounterService>(&[b.addr], Duration::from_secs(1), std::iter::empty())
+                    .await
+                    .unwrap();
+                assert!(!conn.is_reused());
+
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn batched_requests() -> Result<(), TestCaseError> {
+        fixture(
+            CounterService {
+                counter: AtomicI32::new(0),
+            },
+            |b| async move {
+                let pool = pool(1, 60_000);
+
+                let conn = pool
+                    .get::<CounterService>(&[b.addr], Duration::from_secs(1), std::iter::empty())
+                    .await
+                    .unwrap();
+                let changes: Vec<_> = (1..=100).map(|amount| Change { amount }).collect();
+                let res = conn
+                    .send_batch_with_timeout(&changes, Duration::from_secs(10))
+                    .await
+                    .unwrap();
+
+                // the server handles the requests in order
+                let expected: Vec<i32> = (1..=100)
+                    .scan(0, |sum, i| {
+                        *sum += i;
+                        Some(*sum)
+                    })
+                    .collect();
+                assert_eq!(res, expected);
+
+                let conn = pool
+                    .get::<CounterService>(&[b.addr], Duration::from_secs(1), std::iter::empty())
+                    .await
+                    .unwrap();
+                assert!(conn.is_reused());
+                let res = conn
+                    .send_batch_with_timeout::<Change>(&[], Duration::from_secs(1))
+                    .await
+                    .unwrap();
+                assert!(res.is_empty());
+
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn streaming_service() -> Result<(), TestCaseError> {
+        fixture(
+            CounterService {
+                counter: AtomicI32::new(0),
+            },
+            |b| async move {
+                let items: Vec<i32> = super::Connection::create(b.addr)
+                    .await
+                    .unwrap()
+                    .send_streaming(&CountUp { times: 3 }, Duration::from_secs(1))
+                    .await
+                    .unwrap()
+                    .try_collect()
+                    .await
+                    .unwrap();
+                assert_eq!(items, vec![1, 2, 3]);
+
+                let items: Vec<i32> = super::Connection::create(b.addr)
+                    .await
+                    .unwrap()
+                    .send_streaming(&CountUp { times: 0 }, Duration::from_secs(1))
+                    .await
+                    .unwrap()
+                    .try_collect()
+                    .await
+                    .unwrap();
+                assert!(items.is_empty());
+
+                // dropping the stream early leaves the server able to take new requests
+                let mut items = Box::pin(
+                    super::Connection::create(b.addr)
+                        .await
+                        .unwrap()
+                        .send_streaming(&CountUp { times: 2 }, Duration::from_secs(1))
+                        .await
+                        .unwrap(),
+                );
+                assert_eq!(items.next().await.unwrap().unwrap(), 4);
+                drop(items);
+
+                let val = b.send(&Change { amount: 0 }).await.unwrap();
+                assert!(val >= 4);
+
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn deadline_propagation() -> Result<(), TestCaseError> {
+        fixture(
+            CounterService {
+                counter: AtomicI32::new(0),
+            },
+            |b| async move {
+                assert_eq!(b.send(&RemainingMs).await.unwrap(), None);
+
+                let remaining = Context::with_timeout(Duration::from_secs(10))
+                    .scope(b.send(&RemainingMs))
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert!(remaining > 5_000 && remaining <= 10_000);
+
+                // requests are not sent once the deadline has passed
+                let res = Context::with_timeout(Duration::ZERO)
+                    .scope(b.send(&Change { amount: 1 }))
+                    .await;
+                assert!(matches!(
+                    res.unwrap_err().downcast_ref::<super::Error>(),
+                    Some(super::Error::DeadlineExceeded)
+                ));
+                assert_eq!(b.send(&Change { amount: 0 }).await.unwrap(), 0);
+
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn overloaded_server_sheds_requests() -> Result<(), TestCaseError> {
+        fixture_with_limit(
+            CounterService {
+                counter: AtomicI32::new(0),
+            },
+            Some(ConcurrencyLimitConfig {
+                max_inflight: 1,
+                max_queued: 0,
+                queue_timeout_ms: 0,
+            }),
+            |b| async move {
+                let addr = b.addr;
+                let slow = tokio::spawn(async move {
+                    super::Connection::create(addr)
+                        .await
+                        .unwrap()
+                        .send(&Sleep { ms: 500 })
+                        .await
+                });
+                tokio::time::sleep(Duration::from_millis(100)).await;
+
+                let res = b.send(&Change { amount: 1 }).await;
+                assert!(matches!(
+                    res.unwrap_err().downcast_ref::<super::Error>(),
+                    Some(super::Error::Overloaded)
+                ));
+
+                slow.await.unwrap().unwrap();
+
+                // the slot is free again once the slow request has been answered
+                assert_eq!(b.send(&Change { amount: 1 }).await.unwrap(), 1);
+
+                Ok(())
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown() {
+        let server = Server::bind(
+            CounterService {
+                counter: AtomicI32::new(0),
+            },
+            ("127.0.0.1", 0),
+        )
+        .await
+        .unwrap();
+        let addr = server.inner.listener.local_addr().unwrap();
+
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let svr_task = tokio::spawn(server.serve_until(
+            async move {
+                stopped.await.ok();
+            },
+            Duration::from_secs(10),
+        ));
+
+        // leave an idle connection in the pool
+        let pool = pool(1, 60_000);
+        pool.get::<CounterService>(&[addr], Duration::from_secs(1), std::iter::empty())
+            .await
+            .unwrap()
+            .send_with_timeout(&Reset, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(pool.num_idle(addr), 1);
+
+        let slow = tokio::spawn(async move {
+            super::Connection::<CounterService>::create(addr)
+                .await
+                .unwrap()
+                .send(&Sleep { ms: 500 })
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        stop.send(()).unwrap();
+
+        // the request that was being handled is answered before the server stops
+        slow.await.unwrap().unwrap();
+        tokio::time::timeout(Duration::from_secs(5), svr_task)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(super::Connection::<CounterService>::create(addr)
+            .await
+            .is_err());
+    }
+
     proptest! {
         #[test]
         fn ref_serialization(a: Change) {
@@ -0,0 +1,568 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! TLS and authentication for the sonic connections.
+//!
+//! The TLS settings are configured for each service, so a process can talk TLS to some
+//! services and plain tcp to others. Servers of the service that are bound after
+//! [`configure`] only accept TLS connections if a certificate is set, and connections to
+//! the service are made with TLS if a CA is set. The key of a service is its type, e.g. the
+//! [`Service`](super::service::Service) or the mapreduce [`Worker`](crate::mapreduce::Worker).
+//!
+//! Only members of the cluster can send requests if either the servers require client
+//! certificates from a CA, or the members share a secret that is sent with every request.
+
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader},
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+};
+
+use anyhow::anyhow;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_rustls::{rustls, TlsAcceptor, TlsConnector};
+
+use crate::config::TlsConfig;
+
+use super::Result;
+
+/// A connection that is either plain tcp or TLS over tcp.
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(Box<tokio_rustls::TlsStream<TcpStream>>),
+}
+
+impl Stream {
+    /// The underlying tcp connection.
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(stream) => stream,
+            Stream::Tls(stream) => stream.get_ref().0,
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+fn open(path: &str) -> Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| anyhow!("failed to open {path}: {e}").into())
+}
+
+fn load_certs(path: &str) -> Result<Vec<Vec<u8>>> {
+    let certs = rustls_pemfile::certs(&mut open(path)?)?;
+
+    if certs.is_empty() {
+        return Err(anyhow!("no certificates in {path}").into());
+    }
+
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> Result<rustls::PrivateKey> {
+    for item in rustls_pemfile::read_all(&mut open(path)?)? {
+        match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(rustls::PrivateKey(key)),
+            _ => {}
+        }
+    }
+
+    Err(anyhow!("no private key in {path}").into())
+}
+
+fn load_roots(path: &str) -> Result<rustls::RootCertStore> {
+    let mut roots = rustls::RootCertStore::empty();
+    let (num_valid, _) = roots.add_parsable_certificates(&load_certs(path)?);
+
+    if num_valid == 0 {
+        return Err(anyhow!("no valid CA certificates in {path}").into());
+    }
+
+    Ok(roots)
+}
+
+/// Accepts the TLS connections of a server with the certificate and key.
+/// Clients must present a certificate signed by the CA in `client_ca_path` if it is set.
+pub fn acceptor(
+    cert_path: &str,
+    key_path: &str,
+    client_ca_path: Option<&str>,
+) -> Result<TlsAcceptor> {
+    let certs = load_certs(cert_path)?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = match client_ca_path {
+        Some(path) => builder.with_client_cert_verifier(
+            rustls::server::AllowAnyAuthenticatedClient::new(load_roots(path)?).boxed(),
+        ),
+        None => builder.with_no_client_auth(),
+    };
+
+    let config = builder.with_single_cert(certs, load_key(key_path)?)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// A secret that is shared by the members of the cluster. Only its digest is kept
+/// and sent with the requests.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SharedSecret([u8; 32]);
+
+impl std::fmt::Debug for SharedSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SharedSecret(..)")
+    }
+}
+
+impl SharedSecret {
+    pub fn new(secret: &[u8]) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, secret);
+
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(digest.as_ref());
+
+        Self(bytes)
+    }
+
+    /// Read the secret from the file. Surrounding whitespace is ignored.
+    pub fn open(path: &str) -> Result<Self> {
+        let secret =
+            std::fs::read_to_string(path).map_err(|e| anyhow!("failed to open {path}: {e}"))?;
+        let secret = secret.trim();
+
+        if secret.is_empty() {
+            return Err(anyhow!("no secret in {path}").into());
+        }
+
+        Ok(Self::new(secret.as_bytes()))
+    }
+
+    pub(super) fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Compare with the digest a client sent in constant time.
+    pub(super) fn verify(&self, digest: &[u8]) -> bool {
+        ring::constant_time::verify_slices_are_equal(&self.0, digest).is_ok()
+    }
+}
+
+/// Makes TLS connections and verifies the certificates of the servers against the CA.
+#[derive(Clone)]
+pub struct TlsClient {
+    connector: TlsConnector,
+    server_name: Option<rustls::ServerName>,
+}
+
+impl TlsClient {
+    /// `identity` is the certificate and key the client presents to servers that require
+    /// client certificates.
+    pub fn new(
+        ca_path: &str,
+        server_name: Option<&str>,
+        identity: Option<(&str, &str)>,
+    ) -> Result<Self> {
+        let builder = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(load_roots(ca_path)?);
+
+        let config = match identity {
+            Some((cert_path, key_path)) => {
+                let certs = load_certs(cert_path)?
+                    .into_iter()
+                    .map(rustls::Certificate)
+                    .collect();
+
+                builder.with_client_auth_cert(certs, load_key(key_path)?)?
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let server_name = server_name
+            .map(|name| {
+                rustls::ServerName::try_from(name)
+                    .map_err(|e| anyhow!("invalid server name {name}: {e}"))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(config)),
+            server_name,
+        })
+    }
+
+    async fn connect(&self, stream: TcpStream) -> Result<Stream> {
+        let server_name = match &self.server_name {
+            Some(name) => name.clone(),
+            None => rustls::ServerName::IpAddress(stream.peer_addr()?.ip()),
+        };
+
+        let stream = self.connector.connect(server_name, stream).await?;
+
+        Ok(Stream::Tls(Box::new(stream.into())))
+    }
+}
+
+/// Do the TLS handshake of a client if it has TLS.
+pub async fn connect(client: Option<&TlsClient>, stream: TcpStream) -> Result<Stream> {
+    match client {
+        Some(client) => client.connect(stream).await,
+        None => Ok(Stream::Plain(stream)),
+    }
+}
+
+/// Do the TLS handshake of a server if it has TLS.
+pub async fn accept(acceptor: Option<&TlsAcceptor>, stream: TcpStream) -> Result<Stream> {
+    match acceptor {
+        Some(acceptor) => {
+            let stream = acceptor.accept(stream).await?;
+            Ok(Stream::Tls(Box::new(stream.into())))
+        }
+        None => Ok(Stream::Plain(stream)),
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Tls {
+    pub acceptor: Option<TlsAcceptor>,
+    pub client: Option<TlsClient>,
+    pub secret: Option<SharedSecret>,
+}
+
+impl Tls {
+    pub fn new(config: &TlsConfig) -> Result<Self> {
+        let identity = match (&config.cert_path, &config.key_path) {
+            (Some(cert_path), Some(key_path)) => Some((cert_path.as_str(), key_path.as_str())),
+            (None, None) => None,
+            _ => return Err(anyhow!("both cert_path and key_path must be set for tls").into()),
+        };
+
+        let acceptor = match identity {
+            Some((cert_path, key_path)) => Some(acceptor(
+                cert_path,
+                key_path,
+                config.client_ca_path.as_deref(),
+            )?),
+            None if config.client_ca_path.is_some() => {
+                return Err(anyhow!("client_ca_path requires cert_path and key_path").into())
+            }
+            None => None,
+        };
+
+        let client = config
+            .ca_path
+            .as_ref()
+            .map(|ca_path| TlsClient::new(ca_path, config.server_name.as_deref(), identity))
+            .transpose()?;
+
+        let secret = config
+            .shared_secret_path
+            .as_deref()
+            .map(SharedSecret::open)
+            .transpose()?;
+
+        Ok(Self {
+            acceptor,
+            client,
+            secret,
+        })
+    }
+}
+
+static TLS: once_cell::sync::Lazy<RwLock<HashMap<TypeId, Tls>>> =
+    once_cell::sync::Lazy::new(Default::default);
+
+/// Use TLS for the servers of the service `S` that are bound and the connections to
+/// the service that are made from now on. Other services are not affected.
+pub fn configure<S: 'static>(config: &TlsConfig) -> Result<()> {
+    let tls = Tls::new(config)?;
+    TLS.write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(TypeId::of::<S>(), tls);
+
+    Ok(())
+}
+
+/// The TLS settings of the service `S`. Services that are not configured use plain tcp.
+pub fn service<S: 'static>() -> Tls {
+    TLS.read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&TypeId::of::<S>())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// A certificate that is only valid for `name` and signs itself, so it is also the CA.
+#[cfg(test)]
+pub(crate) fn self_signed_config(name: &str) -> TlsConfig {
+    let cert = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+
+    let folder = crate::gen_temp_path();
+    std::fs::create_dir_all(&folder).unwrap();
+
+    let cert_path = folder.join("cert.pem");
+    let key_path = folder.join("key.pem");
+    std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+    std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+
+    TlsConfig {
+        cert_path: Some(cert_path.to_str().unwrap().to_string()),
+        key_path: Some(key_path.to_str().unwrap().to_string()),
+        ca_path: Some(cert_path.to_str().unwrap().to_string()),
+        server_name: Some(name.to_string()),
+        client_ca_path: None,
+        shared_secret_path: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use super::super::{Connection, Server};
+    use super::*;
+
+    async fn echo_server(
+        acceptor: Option<TlsAcceptor>,
+        secret: Option<SharedSecret>,
+    ) -> std::net::SocketAddr {
+        let server = Server::<String, String>::bind_with_tls(("127.0.0.1", 0), acceptor)
+            .await
+            .unwrap()
+            .with_secret(secret);
+        let addr = server.listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                if let Ok(req) = server.accept().await {
+                    let res = format!("{} back", req.body());
+                    req.respond(res).await.ok();
+                }
+            }
+        });
+
+        addr
+    }
+
+    async fn send(
+        addr: std::net::SocketAddr,
+        tls: &Tls,
+        secret: Option<SharedSecret>,
+    ) -> Result<String> {
+        Connection::<String, String>::create_with_tls(
+            addr,
+            Duration::from_secs(5),
+            tls.client.as_ref(),
+        )
+        .await?
+        .with_secret(secret)
+        .send(&"hello".to_string())
+        .await
+    }
+
+    #[tokio::test]
+    async fn tls_roundtrip() {
+        let tls = Tls::new(&self_signed_config("localhost")).unwrap();
+        let addr = echo_server(tls.acceptor.clone(), None).await;
+
+        let conn = Connection::<String, String>::create_with_tls(
+            addr,
+            Duration::from_secs(5),
+            tls.client.as_ref(),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(conn.stream, Stream::Tls(_)));
+
+        let res = conn.send(&"hello".to_string()).await.unwrap();
+        assert_eq!(res, "hello back");
+    }
+
+    #[tokio::test]
+    async fn unknown_ca_is_rejected() {
+        let server_tls = Tls::new(&self_signed_config("localhost")).unwrap();
+        let client_tls = Tls::new(&self_signed_config("localhost")).unwrap();
+        let addr = echo_server(server_tls.acceptor, None).await;
+
+        assert!(Connection::<String, String>::create_with_tls(
+            addr,
+            Duration::from_secs(5),
+            client_tls.client.as_ref(),
+        )
+        .await
+        .is_err());
+
+        // the certificate is not valid for the name
+        let other_name = Tls::new(&TlsConfig {
+            server_name: Some("example.com".to_string()),
+            ..self_signed_config("localhost")
+        })
+        .unwrap();
+        let addr = echo_server(other_name.acceptor, None).await;
+
+        assert!(Connection::<String, String>::create_with_tls(
+            addr,
+            Duration::from_secs(5),
+            other_name.client.as_ref(),
+        )
+        .await
+        .is_err());
+    }
+
+    #[test]
+    fn key_without_cert() {
+        let config = TlsConfig {
+            cert_path: None,
+            ..self_signed_config("localhost")
+        };
+
+        assert!(Tls::new(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn client_certificates_are_required() {
+        let server_config = self_signed_config("localhost");
+        let server_config = TlsConfig {
+            client_ca_path: server_config.ca_path.clone(),
+            ..server_config
+        };
+        let tls = Tls::new(&server_config).unwrap();
+        let addr = echo_server(tls.acceptor.clone(), None).await;
+
+        assert_eq!(send(addr, &tls, None).await.unwrap(), "hello back");
+
+        // a client that trusts the server, but has no certificate of its own
+        let anonymous = Tls {
+            client: Some(
+                TlsClient::new(
+                    server_config.ca_path.as_deref().unwrap(),
+                    server_config.server_name.as_deref(),
+                    None,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        assert!(send(addr, &anonymous, None).await.is_err());
+
+        // a client with a certificate from another CA
+        let other = Tls::new(&TlsConfig {
+            ca_path: server_config.ca_path.clone(),
+            ..self_signed_config("localhost")
+        })
+        .unwrap();
+        assert!(send(addr, &other, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn shared_secret_is_required() {
+        let plain = Tls::default();
+        let secret = SharedSecret::new(b"cluster secret");
+        let addr = echo_server(None, Some(secret)).await;
+
+        assert_eq!(
+            send(addr, &plain, Some(secret)).await.unwrap(),
+            "hello back"
+        );
+        assert!(send(addr, &plain, None).await.is_err());
+        assert!(send(addr, &plain, Some(SharedSecret::new(b"other")))
+            .await
+            .is_err());
+
+        // servers without a secret accept requests with one
+        let addr = echo_server(None, None).await;
+        assert_eq!(
+            send(addr, &plain, Some(secret)).await.unwrap(),
+            "hello back"
+        );
+    }
+
+    #[test]
+    fn services_are_configured_separately() {
+        struct Secure;
+        struct Plain;
+
+        configure::<Secure>(&self_signed_config("localhost")).unwrap();
+
+        assert!(service::<Secure>().acceptor.is_some());
+        assert!(service::<Secure>().client.is_some());
+        assert!(service::<Plain>().acceptor.is_none());
+        assert!(service::<Plain>().client.is_none());
+    }
+
+    #[test]
+    fn shared_secret_from_file() {
+        let path = crate::gen_temp_path();
+        fs::write(&path, "cluster secret\n").unwrap();
+
+        let secret = SharedSecret::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(secret, SharedSecret::new(b"cluster secret"));
+
+        fs::write(&path, "  \n").unwrap();
+        assert!(SharedSecret::open(path.to_str().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Commands used by operators to administer a running deployment.
+
+use std::net::SocketAddr;
+
+use anyhow::anyhow;
+
+use crate::{
+    audit_log::{self, AdminRequest, AuditAction},
+    crawler::trap_detector::{self, RuleStatus},
+    distributed::sonic,
+    query_telemetry::QueryTelemetry,
+    Result,
+};
+
+use super::{
+    live_index::{self, DeleteUrl},
+    search_server::{BlockHost, OverrideOptic, ReloadSignalStore, SearchService, UnblockHost},
+};
+
+/// Print the entries of the audit log. If a secret is given, the signatures
+/// of the entries are verified as well.
+pub fn audit_log(path: &str, secret_path: Option<&str>) -> Result<()> {
+    let entries = audit_log::read(path)?;
+
+    for entry in &entries {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            entry.seq,
+            entry.timestamp.to_rfc3339(),
+            entry.actor,
+            serde_json::to_string(&entry.action)?,
+            serde_json::to_string(&entry.outcome)?
+        );
+    }
+
+    if let Some(secret_path) = secret_path {
+        let secret = std::fs::read_to_string(secret_path)?;
+        audit_log::verify(&entries, secret.trim().as_bytes())?;
+        println!("verified {} entries", entries.len());
+    }
+
+    Ok(())
+}
+
+/// The request is signed with the secret of `actor` in the file at `secret_path`.
+pub async fn reload_signal_store(host: SocketAddr, actor: &str, secret_path: &str) -> Result<()> {
+    let request = AdminRequest::with_secret_file(actor, ReloadSignalStore::action(), secret_path)?;
+
+    let conn = sonic::service::Connection::<SearchService>::create(host).await?;
+    let reloaded = conn
+        .send(&ReloadSignalStore { request })
+        .await?
+        .map_err(|e| anyhow!("failed to reload signal store: {e}"))?;
+
+    if reloaded {
+        println!("signal store reloaded");
+    } else {
+        println!("signal store is already up to date");
+    }
+
+    Ok(())
+}
+
+/// Remove the host from the results of the search server. The request is signed with
+/// the secret of `actor` in the file at `secret_path`.
+pub async fn block_host(
+    host: SocketAddr,
+    blocked: &str,
+    actor: &str,
+    secret_path: &str,
+) -> Result<()> {
+    let action = AuditAction::HostBlock {
+        host: blocked.to_string(),
+    };
+    let request = AdminRequest::with_secret_file(actor, action, secret_path)?;
+
+    let conn = sonic::service::Connection::<SearchService>::create(host).await?;
+    let newly_blocked = conn
+        .send(&BlockHost { request })
+        .await?
+        .map_err(|e| anyhow!("failed to block {blocked}: {e}"))?;
+
+    if newly_blocked {
+        println!("{blocked} is blocked");
+    } else {
+        println!("{blocked} was already blocked");
+    }
+
+    Ok(())
+}
+
+/// Let a blocked host appear in the results of the search server again. The request is
+/// signed with the secret of `actor` in the file at `secret_path`.
+pub async fn unblock_host(
+    host: SocketAddr,
+    blocked: &str,
+    actor: &str,
+    secret_path: &str,
+) -> Result<()> {
+    let action = AuditAction::HostUnblock {
+        host: blocked.to_string(),
+    };
+    let request = AdminRequest::with_secret_file(actor, action, secret_path)?;
+
+    let conn = sonic::service::Connection::<SearchService>::create(host).await?;
+    let was_blocked = conn
+        .send(&UnblockHost { request })
+        .await?
+        .map_err(|e| anyhow!("failed to unblock {blocked}: {e}"))?;
+
+    if was_blocked {
+        println!("{blocked} is unblocked");
+    } else {
+        println!("{blocked} was not blocked");
+    }
+
+    Ok(())
+}
+
+/// Use the optic in the file at `optic_path` for the searches without an optic of their
+/// own, or stop overriding the optic if no file is given. The request is signed with the
+/// secret of `actor` in the file at `secret_path`.
+pub async fn override_optic(
+    host: SocketAddr,
+    optic_path: Option<&str>,
+    actor: &str,
+    secret_path: &str,
+) -> Result<()> {
+    let optic = optic_path.map(std::fs::read_to_string).transpose()?;
+    let action = AuditAction::OpticOverride { optic };
+    let request = AdminRequest::with_secret_file(actor, action, secret_path)?;
+
+    let conn = sonic::service::Connection::<SearchService>::create(host).await?;
+    conn.send(&OverrideOptic { request })
+        .await?
+        .map_err(|e| anyhow!("failed to override optic: {e}"))?;
+
+    match optic_path {
+        Some(optic_path) => println!("searches use the optic in {optic_path}"),
+        None => println!("optic override is removed"),
+    }
+
+    Ok(())
+}
+
+/// Delete the page from a live index. The request is signed with the secret of
+/// `actor` in the file at `secret_path`.
+pub async fn delete_url(host: SocketAddr, url: &str, actor: &str, secret_path: &str) -> Result<()> {
+    let action = AuditAction::DeletionRequest {
+        url: url.to_string(),
+    };
+    let request = AdminRequest::with_secret_file(actor, action, secret_path)?;
+
+    let conn = sonic::service::Connection::<live_index::SearchService>::create(host).await?;
+    conn.send(&DeleteUrl { request })
+        .await?
+        .map_err(|e| anyhow!("failed to delete {url}: {e}"))?;
+
+    println!("{url} is deleted once the index is committed");
+
+    Ok(())
+}
+
+/// Review the suppression rules generated by the crawl trap detection. The rules for
+/// the given templates are approved or rejected, and the pending rules are printed.
+pub fn crawl_traps(path: &str, approve: &[String], reject: &[String]) -> Result<()> {
+    let mut rules = trap_detector::merge_rules(trap_detector::read_rules(path)?);
+
+    for (templates, status) in [
+        (approve, RuleStatus::Approved),
+        (reject, RuleStatus::Rejected),
+    ] {
+        for template in templates {
+            let rule = rules
+                .iter_mut()
+                .find(|rule| rule.template.as_str() == template)
+                .ok_or_else(|| anyhow!("no rule for template {template}"))?;
+
+            rule.status = status;
+        }
+    }
+
+    if !approve.is_empty() || !reject.is_empty() {
+        trap_detector::write_rules(path, &rules)?;
+    }
+
+    for rule in rules
+        .iter()
+        .filter(|rule| rule.status == RuleStatus::Pending)
+    {
+        println!(
+            "{}\t{}\t{}\t{}",
+            rule.template,
+            serde_json::to_string(&rule.kind)?.trim_matches('"'),
+            rule.num_urls,
+            rule.example
+        );
+    }
+
+    Ok(())
+}
+
+/// Print a summary of the query telemetry from the last `num_days` days.
+pub fn query_telemetry(path: &str, num_days: u64) -> Result<()> {
+    let summary = QueryTelemetry::open_read_only(path)?.summary(num_days)?;
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+
+    Ok(())
+}
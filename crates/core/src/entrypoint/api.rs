@@ -25,11 +25,20 @@ use crate::{
     metrics::Label,
 };
 
+/// The OpenAPI specification of the api, so clients can be generated without a running api.
+pub fn openapi_spec() -> String {
+    crate::api::openapi_json()
+}
+
 pub async fn run(config: config::ApiConfig) -> Result<()> {
     let search_counter_success = crate::metrics::Counter::default();
     let search_counter_fail = crate::metrics::Counter::default();
     let explore_counter = crate::metrics::Counter::default();
     let daily_active_users = user_count::UserCount::new()?;
+    let abuse_throttled = crate::metrics::Counter::default();
+    let abuse_challenged = crate::metrics::Counter::default();
+    let abuse_blocked = crate::metrics::Counter::default();
+    let search_cost = crate::searcher::cost::CostCounters::default();
 
     let mut registry = crate::metrics::PrometheusRegistry::default();
 
@@ -71,11 +80,63 @@ pub async fn run(config: config::ApiConfig) -> Result<()> {
         .unwrap();
     group.register(daily_active_users.metric(), vec![]);
 
+    let group = registry
+        .new_group(
+            "stract_abuse_actions".to_string(),
+            Some("Number of search requests flagged as abusive.".to_string()),
+        )
+        .unwrap();
+
+    for (counter, action) in [
+        (&abuse_throttled, "throttle"),
+        (&abuse_challenged, "captcha"),
+        (&abuse_blocked, "block"),
+    ] {
+        group.register(
+            counter.clone(),
+            vec![Label {
+                key: "action".to_string(),
+                val: action.to_string(),
+            }],
+        );
+    }
+
+    for (counter, name, help) in [
+        (
+            &search_cost.cpu_time_us,
+            "stract_search_cpu_time_microseconds",
+            "Total CPU time the search servers used for searches.",
+        ),
+        (
+            &search_cost.docs_scored,
+            "stract_search_docs_scored",
+            "Total number of documents scored for searches.",
+        ),
+        (
+            &search_cost.bytes_read,
+            "stract_search_bytes_read",
+            "Total number of bytes of postings read for searches.",
+        ),
+    ] {
+        let group = registry
+            .new_group(name.to_string(), Some(help.to_string()))
+            .unwrap();
+        group.register(counter.clone(), vec![]);
+    }
+
+    crate::distributed::sonic::metrics::client()
+        .register(&mut registry)
+        .unwrap();
+
     let counters = Counters {
         search_counter_success,
         search_counter_fail,
         explore_counter,
         daily_active_users,
+        abuse_throttled,
+        abuse_challenged,
+        abuse_blocked,
+        search_cost,
     };
 
     let app = router(&config, counters).await?;
@@ -0,0 +1,543 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Re-run selected extraction stages on the raw pages from our crawl and patch
+//! the results into the existing index and signal store, so improvements to an
+//! extractor don't require a full reindex.
+//!
+//! Tantivy documents cannot be updated in place, so a page in the index is patched
+//! by replacing its document, and only if one of the backfilled fields has changed.
+//! The new document takes the backfilled fields from the raw page and keeps the
+//! indexed values of the other stored and fast fields. Fields that are only indexed
+//! cannot be read back from the index, so they are extracted from the raw page again.
+//! The signal store is rewritten with the patched entries and the next version.
+
+use anyhow::bail;
+use tracing::{debug, info};
+use url::Url;
+
+use crate::{
+    config::{BackfillConfig, BackfillIndexConfig, BackfillSignalStoreConfig, BackfillStage},
+    index::Index,
+    inverted_index::RetrievedWebpage,
+    schema::{FastField, Field, TextField, FLOAT_SCALING},
+    signal_store::{SignalStore, SignalStoreWriter, StoredSignal},
+    warc::{PayloadType, WarcRecord},
+    webgraph::Node,
+    webpage::{
+        topic_classifier::{self, ALL_TOPICS},
+        Html,
+    },
+    Result,
+};
+
+use super::{download_all_warc_files, indexer::IndexingWorker};
+
+fn encode_likelihood(likelihood: f64) -> u64 {
+    (likelihood * FLOAT_SCALING as f64).round() as u64
+}
+
+/// The fields of the index that the stage patches.
+fn index_fields(stage: &BackfillStage) -> &'static [Field] {
+    match stage {
+        BackfillStage::Readability => &[
+            Field::Fast(FastField::ReadingEase),
+            Field::Fast(FastField::NumCleanBodyWords),
+        ],
+        BackfillStage::GeneratedContent => &[Field::Fast(FastField::GeneratedLikelihood)],
+        BackfillStage::StructuredData => &[
+            Field::Text(TextField::SchemaOrgJson),
+            Field::Text(TextField::FlattenedSchemaOrgJson),
+        ],
+        BackfillStage::Security | BackfillStage::Topics => &[],
+    }
+}
+
+/// Whether any of the fields extracted by the index stages differ from the indexed page.
+fn index_fields_changed(stages: &[BackfillStage], html: &Html, indexed: &RetrievedWebpage) -> bool {
+    stages.iter().any(|stage| match stage {
+        BackfillStage::Readability => {
+            html.reading_ease().map(f64::round) != indexed.reading_ease
+                || html.num_words() as u64 != indexed.num_words
+        }
+        BackfillStage::GeneratedContent => {
+            html.generated_likelihood().map(encode_likelihood)
+                != indexed.generated_likelihood.map(encode_likelihood)
+        }
+        BackfillStage::StructuredData => html.schema_org() != indexed.schema_org,
+        BackfillStage::Security | BackfillStage::Topics => false,
+    })
+}
+
+struct IndexPatcher {
+    index: Index,
+    worker: IndexingWorker,
+    stages: Vec<BackfillStage>,
+    fields: Vec<Field>,
+    dry_run: bool,
+    num_patched: usize,
+}
+
+impl IndexPatcher {
+    fn open(
+        config: &BackfillIndexConfig,
+        stages: Vec<BackfillStage>,
+        dry_run: bool,
+    ) -> Result<Self> {
+        let mut index = Index::open(&config.index_path)?;
+
+        if !dry_run {
+            index.prepare_writer()?;
+        }
+
+        let mut worker = IndexingWorker::new(
+            config.host_centrality_store_path.clone(),
+            config.page_centrality_store_path.clone(),
+            config.page_webgraph_path.clone(),
+            config.topics_path.clone(),
+            config.safety_classifier_path.clone(),
+        );
+
+        if let Some(path) = config.signal_store_path.as_ref() {
+            worker.set_signal_store(SignalStore::open(path)?);
+        }
+
+        if let Some(path) = config.topic_classifier_path.as_ref() {
+            worker.set_topic_classifier(topic_classifier::Model::open(path)?);
+        }
+
+        let fields = stages
+            .iter()
+            .flat_map(|stage| index_fields(stage).iter().copied())
+            .collect();
+
+        Ok(Self {
+            index,
+            worker,
+            stages,
+            fields,
+            dry_run,
+            num_patched: 0,
+        })
+    }
+
+    fn patch(&mut self, record: &WarcRecord, html: &Html) -> Result<()> {
+        let url = &record.request.url;
+
+        let Some(indexed) = self.index.inverted_index.get_webpage(url) else {
+            return Ok(());
+        };
+
+        if !index_fields_changed(&self.stages, html, &indexed) {
+            return Ok(());
+        }
+
+        self.num_patched += 1;
+
+        if self.dry_run {
+            return Ok(());
+        }
+
+        match self
+            .worker
+            .prepare_webpage(&record.response.body, url, record.metadata.fetch_time_ms)
+        {
+            Ok(webpage) => {
+                self.index
+                    .inverted_index
+                    .patch_webpage(webpage, &self.fields)?;
+            }
+            Err(err) => debug!("skipping {}: {}", url, err),
+        }
+
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if !self.dry_run {
+            self.index.commit()?;
+        }
+
+        Ok(())
+    }
+}
+
+struct SignalStorePatcher {
+    store: SignalStore,
+    writer: SignalStoreWriter,
+    model: Option<topic_classifier::Model>,
+    stages: Vec<BackfillStage>,
+    num_patched: usize,
+}
+
+impl SignalStorePatcher {
+    fn open(config: &BackfillSignalStoreConfig, stages: Vec<BackfillStage>) -> Result<Self> {
+        let store = SignalStore::open(&config.path)?;
+
+        let mut writer = SignalStoreWriter::new(
+            config.output_path.as_ref().unwrap_or(&config.path),
+            store.version() + 1,
+        );
+
+        for (node, vector) in store.iter() {
+            writer.insert_vector(node, &vector);
+        }
+
+        let model = match config.topic_classifier_path.as_ref() {
+            Some(path) => Some(topic_classifier::Model::open(path)?),
+            None => None,
+        };
+
+        if stages.contains(&BackfillStage::Topics) && model.is_none() {
+            bail!("the topics stage requires a topic classifier");
+        }
+
+        Ok(Self {
+            store,
+            writer,
+            model,
+            stages,
+            num_patched: 0,
+        })
+    }
+
+    fn patch(&mut self, record: &WarcRecord, html: Option<&Html>) {
+        let Ok(url) = Url::parse(&record.request.url) else {
+            return;
+        };
+
+        let node = Node::from(&url).id();
+        let before = self.store.get(&node).unwrap_or_default();
+
+        let flag = |value: bool| if value { 1.0 } else { 0.0 };
+        let mut patch = Vec::new();
+
+        for stage in &self.stages {
+            match stage {
+                BackfillStage::Security => {
+                    if let Some(security) = record.metadata.security.as_ref() {
+                        patch.push((StoredSignal::Https, flag(security.https)));
+                        patch.push((StoredSignal::Hsts, flag(security.hsts)));
+                        patch.push((StoredSignal::MixedContent, flag(security.mixed_content)));
+                    }
+                }
+                BackfillStage::Topics => {
+                    if let (Some(model), Some(text)) =
+                        (self.model.as_ref(), html.and_then(|html| html.clean_text()))
+                    {
+                        let topics = model.predict_text(text);
+
+                        for topic in ALL_TOPICS {
+                            patch.push((StoredSignal::Topic(topic), topics.get(topic) as f64));
+                        }
+                    }
+                }
+                BackfillStage::Readability
+                | BackfillStage::GeneratedContent
+                | BackfillStage::StructuredData => {}
+            }
+        }
+
+        if patch
+            .iter()
+            .all(|(signal, value)| before.get(*signal) == Some(*value))
+        {
+            return;
+        }
+
+        self.num_patched += 1;
+
+        for (signal, value) in patch {
+            self.writer.insert(node, signal, value);
+        }
+    }
+
+    fn finalize(self) -> Result<SignalStore> {
+        drop(self.store);
+        self.writer.finalize()
+    }
+}
+
+pub fn run(config: BackfillConfig) -> Result<()> {
+    if config.stages.is_empty() {
+        bail!("no stages to backfill");
+    }
+
+    let index_stages: Vec<_> = config
+        .stages
+        .iter()
+        .copied()
+        .filter(BackfillStage::patches_index)
+        .collect();
+
+    let store_stages: Vec<_> = config
+        .stages
+        .iter()
+        .copied()
+        .filter(BackfillStage::patches_signal_store)
+        .collect();
+
+    let mut index = match (index_stages.is_empty(), config.index.as_ref()) {
+        (true, _) => None,
+        (false, Some(index)) => Some(IndexPatcher::open(index, index_stages, config.dry_run)?),
+        (false, None) => bail!("the index stages require an index"),
+    };
+
+    let mut store = match (store_stages.is_empty(), config.signal_store.as_ref()) {
+        (true, _) => None,
+        (false, Some(store)) => Some(SignalStorePatcher::open(store, store_stages)?),
+        (false, None) => bail!("the signal store stages require a signal store"),
+    };
+
+    // the security stage only needs the crawl metadata
+    let parse_html = index.is_some() || config.stages.contains(&BackfillStage::Topics);
+
+    let warc_paths: Vec<_> = config
+        .warc_source
+        .paths()?
+        .into_iter()
+        .skip(config.skip_warc_files.unwrap_or(0))
+        .take(config.limit_warc_files.unwrap_or(usize::MAX))
+        .collect();
+
+    info!(
+        "backfilling {:?} from {} warc files",
+        config.stages,
+        warc_paths.len()
+    );
+
+    let mut num_pages = 0;
+
+    for file in download_all_warc_files(&warc_paths, &config.warc_source) {
+        for record in file
+            .records()
+            .flatten()
+            .filter(|record| matches!(record.response.payload_type, Some(PayloadType::Html) | None))
+        {
+            num_pages += 1;
+
+            let html = if parse_html {
+                match Html::parse(&record.response.body, &record.request.url) {
+                    Ok(html) => Some(html),
+                    Err(err) => {
+                        debug!("error parsing {}: {:?}", record.request.url, err);
+                        continue;
+                    }
+                }
+            } else {
+                None
+            };
+
+            if let (Some(index), Some(html)) = (index.as_mut(), html.as_ref()) {
+                index.patch(&record, html)?;
+            }
+
+            if let Some(store) = store.as_mut() {
+                store.patch(&record, html.as_ref());
+            }
+        }
+
+        if let Some(index) = index.as_mut() {
+            index.commit()?;
+        }
+    }
+
+    info!("{} pages backfilled", num_pages);
+
+    if let Some(index) = index {
+        info!("{} pages patched in the index", index.num_patched);
+    }
+
+    if let Some(store) = store {
+        info!("{} nodes patched in the signal store", store.num_patched);
+
+        if !config.dry_run {
+            let store = store.finalize()?;
+            info!("signal store with version {} written", store.version());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::{BackfillSignalStoreConfig, WarcSource},
+        security::SecurityProperties,
+        warc::{Metadata, Request, Response},
+    };
+
+    use super::*;
+
+    const PAGE: &str = r#"
+        <html>
+            <head>
+                <title>Recipe</title>
+                <script type="application/ld+json">
+                    {
+                        "@context": "https://schema.org",
+                        "@type": "Recipe",
+                        "name": "Pancakes"
+                    }
+                </script>
+            </head>
+            <body>
+                <p>Mix the flour and the milk. Fry the pancakes in butter until golden.</p>
+            </body>
+        </html>
+    "#;
+
+    fn record(url: &str, security: Option<SecurityProperties>) -> WarcRecord {
+        WarcRecord {
+            request: Request {
+                url: url.to_string(),
+            },
+            response: Response {
+                body: PAGE.to_string(),
+                payload_type: Some(PayloadType::Html),
+            },
+            metadata: Metadata {
+                fetch_time_ms: 100,
+                security,
+            },
+        }
+    }
+
+    #[test]
+    fn index_fields() {
+        let html = Html::parse(PAGE, "https://example.com/").unwrap();
+
+        let indexed = RetrievedWebpage {
+            reading_ease: html.reading_ease().map(f64::round),
+            num_words: html.num_words() as u64,
+            generated_likelihood: html.generated_likelihood(),
+            ..Default::default()
+        };
+
+        // the extracted values are unchanged, except for the structured data
+        // that was not extracted when the page was indexed.
+        assert!(!index_fields_changed(
+            &[BackfillStage::Readability, BackfillStage::GeneratedContent],
+            &html,
+            &indexed
+        ));
+        assert!(index_fields_changed(
+            &[BackfillStage::StructuredData],
+            &html,
+            &indexed
+        ));
+        assert!(!index_fields_changed(
+            &[BackfillStage::Security, BackfillStage::Topics],
+            &html,
+            &indexed
+        ));
+    }
+
+    #[test]
+    fn patch_signal_store() {
+        let path = crate::gen_temp_path();
+        let patched = Url::parse("https://example.com/").unwrap();
+        let unchanged = Url::parse("https://example.com/unchanged").unwrap();
+
+        let mut writer = SignalStoreWriter::new(&path, 1);
+        for url in [&patched, &unchanged] {
+            let node = Node::from(url).id();
+            writer.insert(node, StoredSignal::HostCentrality, 0.5);
+            writer.insert(node, StoredSignal::Https, 0.0);
+            writer.insert(node, StoredSignal::Hsts, 0.0);
+            writer.insert(node, StoredSignal::MixedContent, 0.0);
+        }
+        writer.finalize().unwrap();
+
+        let config = BackfillSignalStoreConfig {
+            path: path.to_str().unwrap().to_string(),
+            output_path: None,
+            topic_classifier_path: None,
+        };
+
+        assert!(SignalStorePatcher::open(&config, vec![BackfillStage::Topics]).is_err());
+
+        let mut patcher = SignalStorePatcher::open(&config, vec![BackfillStage::Security]).unwrap();
+
+        patcher.patch(
+            &record(
+                patched.as_str(),
+                Some(SecurityProperties {
+                    https: true,
+                    hsts: true,
+                    mixed_content: false,
+                }),
+            ),
+            None,
+        );
+        patcher.patch(
+            &record(
+                unchanged.as_str(),
+                Some(SecurityProperties {
+                    https: false,
+                    hsts: false,
+                    mixed_content: false,
+                }),
+            ),
+            None,
+        );
+        patcher.patch(&record("https://example.com/unknown", None), None);
+
+        assert_eq!(patcher.num_patched, 1);
+
+        let store = patcher.finalize().unwrap();
+        assert_eq!(store.version(), 2);
+
+        let node = Node::from(&patched).id();
+        assert_eq!(store.get_signal(&node, StoredSignal::Https), Some(1.0));
+        assert_eq!(store.get_signal(&node, StoredSignal::Hsts), Some(1.0));
+        assert_eq!(
+            store.get_signal(&node, StoredSignal::HostCentrality),
+            Some(0.5)
+        );
+
+        let node = Node::from(&unchanged).id();
+        assert_eq!(store.get_signal(&node, StoredSignal::Https), Some(0.0));
+        assert_eq!(
+            store.get_signal(&node, StoredSignal::HostCentrality),
+            Some(0.5)
+        );
+    }
+
+    #[test]
+    fn requires_targets() {
+        let config = BackfillConfig {
+            warc_source: WarcSource::Local(crate::config::LocalConfig {
+                folder: ".".to_string(),
+                names: vec![],
+            }),
+            limit_warc_files: None,
+            skip_warc_files: None,
+            stages: vec![BackfillStage::Readability],
+            dry_run: true,
+            index: None,
+            signal_store: None,
+        };
+
+        assert!(run(config.clone()).is_err());
+        assert!(run(BackfillConfig {
+            stages: vec![],
+            ..config
+        })
+        .is_err());
+    }
+}
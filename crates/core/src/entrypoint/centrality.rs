@@ -23,7 +23,10 @@ use crate::{
     kv::{rocksdb_store::RocksDbStore, Kv},
     ranking::inbound_similarity::InboundSimilarity,
     webgraph::{
-        centrality::{approx_harmonic::ApproxHarmonic, harmonic::HarmonicCentrality},
+        centrality::{
+            approx_harmonic::ApproxHarmonic, derived_harmonic::DerivedCentrality,
+            harmonic::HarmonicCentrality,
+        },
         Node, WebgraphBuilder,
     },
 };
@@ -159,4 +162,28 @@ impl Centrality {
 
         Ok(())
     }
+
+    /// Derive the centrality of the pages from the harmonic centrality of the hosts,
+    /// which is stored in `host_output` by [`Centrality::build_harmonic`].
+    pub fn build_derived_harmonic<P: AsRef<Path>>(
+        webgraph_path: P,
+        host_output: P,
+        base_output: P,
+    ) -> Result<()> {
+        tracing::info!(
+            "Building derived harmonic centrality for {}",
+            webgraph_path.as_ref().to_str().unwrap()
+        );
+
+        let graph = WebgraphBuilder::new(webgraph_path).single_threaded().open();
+        let host_harmonic = RocksDbStore::open(host_output.as_ref().join("harmonic"));
+
+        DerivedCentrality::build(
+            &host_harmonic,
+            &graph,
+            base_output.as_ref().join("derived_harmonic"),
+        )?;
+
+        Ok(())
+    }
 }
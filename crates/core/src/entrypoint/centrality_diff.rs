@@ -0,0 +1,478 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Compares two runs of the `centrality` command, so changes to the webgraph or the
+//! centrality algorithms that move the ranks of many nodes are noticed before the
+//! new centralities are used for ranking.
+//!
+//! The ranks of all the nodes are compared, but the names of the nodes are only known
+//! for the top nodes in the csv of each run, so the movers and the shifts of the top
+//! level domains are found among those.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    path::Path,
+};
+
+use anyhow::bail;
+use serde::Serialize;
+use url::Url;
+
+use crate::{
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    webgraph::{Node, NodeID},
+    Result,
+};
+
+/// The number of top nodes that are compared for [`CentralityDiff::top_overlap`].
+const TOP_OVERLAP: usize = 1_000;
+
+/// Top level domains with fewer named nodes are left out of the shifts.
+const MIN_TLD_NODES: usize = 10;
+
+pub struct DiffOptions {
+    pub old_path: String,
+    pub new_path: String,
+    /// Compare the page centralities instead of the host centralities.
+    pub page: bool,
+    pub num_movers: usize,
+    /// Write the report as json to this path.
+    pub report_path: Option<String>,
+    /// Fail if the rank correlation between the runs is lower than this.
+    pub min_correlation: Option<f64>,
+}
+
+/// The correlation of a stream of pairs, updated one pair at a time so
+/// the ranks of all the nodes do not have to fit in memory.
+#[derive(Debug, Default)]
+struct Correlation {
+    n: u64,
+    mean_x: f64,
+    mean_y: f64,
+    m2_x: f64,
+    m2_y: f64,
+    co_moment: f64,
+}
+
+impl Correlation {
+    fn add(&mut self, x: f64, y: f64) {
+        self.n += 1;
+        let n = self.n as f64;
+
+        let dx = x - self.mean_x;
+        self.mean_x += dx / n;
+        let dy = y - self.mean_y;
+        self.mean_y += dy / n;
+
+        self.m2_x += dx * (x - self.mean_x);
+        self.m2_y += dy * (y - self.mean_y);
+        self.co_moment += dx * (y - self.mean_y);
+    }
+
+    fn get(&self) -> Option<f64> {
+        if self.n < 2 || self.m2_x <= 0.0 || self.m2_y <= 0.0 {
+            return None;
+        }
+
+        Some(self.co_moment / (self.m2_x * self.m2_y).sqrt())
+    }
+}
+
+/// How many times better the new rank is than the old rank on a log2 scale, so
+/// moving from rank 10 to 5 counts as much as moving from rank 1000 to 500.
+fn rank_change(old_rank: u64, new_rank: u64) -> f64 {
+    ((old_rank + 1) as f64 / (new_rank + 1) as f64).log2()
+}
+
+fn tld(name: &str) -> Option<String> {
+    let host = if name.contains("://") {
+        Url::parse(name).ok()?.host_str()?.to_string()
+    } else {
+        name.to_string()
+    };
+
+    let (_, tld) = host.rsplit_once('.')?;
+
+    if tld.is_empty() {
+        None
+    } else {
+        Some(tld.to_lowercase())
+    }
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+
+    if values.len() % 2 == 0 {
+        Some((values[mid - 1] + values[mid]) / 2.0)
+    } else {
+        Some(values[mid])
+    }
+}
+
+/// The fraction of the top `k` nodes of the old run that are also among the top `k` of the new run.
+fn top_overlap(old: &[String], new: &[String], k: usize) -> Option<f64> {
+    let k = k.min(old.len()).min(new.len());
+
+    if k == 0 {
+        return None;
+    }
+
+    let new: HashSet<_> = new.iter().take(k).collect();
+    let shared = old.iter().take(k).filter(|name| new.contains(name)).count();
+
+    Some(shared as f64 / k as f64)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Mover {
+    pub name: String,
+    pub old_rank: u64,
+    pub new_rank: u64,
+    /// See [`rank_change`]. Positive if the node moved up.
+    pub change: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TldShift {
+    pub tld: String,
+    pub num_nodes: usize,
+    pub median_change: f64,
+}
+
+/// The top level domains where the median node moved the most.
+fn tld_shifts(movers: &[Mover]) -> Vec<TldShift> {
+    let mut changes: HashMap<String, Vec<f64>> = HashMap::new();
+
+    for mover in movers {
+        if let Some(tld) = tld(&mover.name) {
+            changes.entry(tld).or_default().push(mover.change);
+        }
+    }
+
+    let mut shifts: Vec<_> = changes
+        .into_iter()
+        .filter(|(_, changes)| changes.len() >= MIN_TLD_NODES)
+        .filter_map(|(tld, changes)| {
+            let num_nodes = changes.len();
+
+            Some(TldShift {
+                tld,
+                num_nodes,
+                median_change: median(changes)?,
+            })
+        })
+        .collect();
+
+    shifts.sort_by(|a, b| {
+        b.median_change
+            .abs()
+            .total_cmp(&a.median_change.abs())
+            .then(a.tld.cmp(&b.tld))
+    });
+
+    shifts
+}
+
+/// The nodes that moved the most up and down respectively.
+fn risers_and_fallers(mut movers: Vec<Mover>, num: usize) -> (Vec<Mover>, Vec<Mover>) {
+    movers.sort_by(|a, b| b.change.total_cmp(&a.change).then(a.name.cmp(&b.name)));
+
+    let risers = movers
+        .iter()
+        .take(num)
+        .filter(|m| m.change > 0.0)
+        .cloned()
+        .collect();
+    let fallers = movers
+        .iter()
+        .rev()
+        .take(num)
+        .filter(|m| m.change < 0.0)
+        .cloned()
+        .collect();
+
+    (risers, fallers)
+}
+
+#[derive(Debug, Serialize)]
+pub struct CentralityDiff {
+    pub num_old: u64,
+    pub num_new: u64,
+    pub num_common: u64,
+    /// The correlation of the ranks of the nodes in both runs.
+    pub rank_correlation: Option<f64>,
+    /// See [`top_overlap`].
+    pub top_overlap: Option<f64>,
+    pub risers: Vec<Mover>,
+    pub fallers: Vec<Mover>,
+    pub tld_shifts: Vec<TldShift>,
+}
+
+struct Run {
+    rank: RocksDbStore<NodeID, f64>,
+    top: Vec<String>,
+}
+
+impl Run {
+    fn open<P: AsRef<Path>>(path: P, name: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let rank = RocksDbStore::open_read_only(path.join(format!("{name}_rank")));
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(path.join(format!("{name}.csv")))?;
+
+        let mut top = Vec::new();
+        for record in reader.records() {
+            if let Some(name) = record?.get(0) {
+                top.push(name.to_string());
+            }
+        }
+
+        Ok(Self { rank, top })
+    }
+
+    fn rank(&self, node: &NodeID) -> Option<u64> {
+        self.rank.get(node).map(|rank| rank as u64)
+    }
+}
+
+pub fn diff(options: &DiffOptions) -> Result<CentralityDiff> {
+    let name = if options.page {
+        "approx_harmonic"
+    } else {
+        "harmonic"
+    };
+
+    let old = Run::open(&options.old_path, name)?;
+    let new = Run::open(&options.new_path, name)?;
+
+    tracing::info!("comparing the ranks of the nodes");
+    let mut correlation = Correlation::default();
+    let mut num_old = 0;
+
+    for (node, old_rank) in old.rank.iter() {
+        num_old += 1;
+
+        if let Some(new_rank) = new.rank(&node) {
+            correlation.add(old_rank, new_rank as f64);
+        }
+    }
+
+    let num_new = new.rank.iter().count() as u64;
+
+    tracing::info!("finding the movers among the top nodes");
+    let names: HashSet<_> = old.top.iter().chain(new.top.iter()).collect();
+    let movers: Vec<_> = names
+        .into_iter()
+        .filter_map(|name| {
+            let node = Node { name: name.clone() }.id();
+            let old_rank = old.rank(&node)?;
+            let new_rank = new.rank(&node)?;
+
+            Some(Mover {
+                name: name.clone(),
+                old_rank,
+                new_rank,
+                change: rank_change(old_rank, new_rank),
+            })
+        })
+        .collect();
+
+    let tld_shifts = tld_shifts(&movers);
+    let (risers, fallers) = risers_and_fallers(movers, options.num_movers);
+
+    Ok(CentralityDiff {
+        num_old,
+        num_new,
+        num_common: correlation.n,
+        rank_correlation: correlation.get(),
+        top_overlap: top_overlap(&old.top, &new.top, TOP_OVERLAP),
+        risers,
+        fallers,
+        tld_shifts,
+    })
+}
+
+fn fmt_opt(value: Option<f64>) -> String {
+    value
+        .map(|value| format!("{value:.4}"))
+        .unwrap_or_else(|| "n/a".to_string())
+}
+
+impl Display for CentralityDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "nodes: {} old, {} new, {} in both",
+            self.num_old, self.num_new, self.num_common
+        )?;
+        writeln!(f, "rank correlation: {}", fmt_opt(self.rank_correlation))?;
+        writeln!(
+            f,
+            "overlap of the top {TOP_OVERLAP}: {}",
+            fmt_opt(self.top_overlap)
+        )?;
+
+        for (title, movers) in [("risers", &self.risers), ("fallers", &self.fallers)] {
+            writeln!(f, "\n{title}:")?;
+
+            for mover in movers {
+                writeln!(
+                    f,
+                    "  {:>+7.2} {} ({} -> {})",
+                    mover.change, mover.name, mover.old_rank, mover.new_rank
+                )?;
+            }
+        }
+
+        writeln!(f, "\ntld shifts:")?;
+        for shift in &self.tld_shifts {
+            writeln!(
+                f,
+                "  {:>+7.2} .{} ({} nodes)",
+                shift.median_change, shift.tld, shift.num_nodes
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn run(options: DiffOptions) -> Result<()> {
+    let diff = diff(&options)?;
+
+    println!("{diff}");
+
+    if let Some(path) = &options.report_path {
+        serde_json::to_writer_pretty(std::fs::File::create(path)?, &diff)?;
+    }
+
+    if let (Some(min), Some(correlation)) = (options.min_correlation, diff.rank_correlation) {
+        if correlation < min {
+            bail!("rank correlation {correlation:.4} is below the minimum of {min}");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mover(name: &str, change: f64) -> Mover {
+        Mover {
+            name: name.to_string(),
+            old_rank: 0,
+            new_rank: 0,
+            change,
+        }
+    }
+
+    #[test]
+    fn correlation() {
+        let mut corr = Correlation::default();
+        assert_eq!(corr.get(), None);
+
+        for i in 0..10 {
+            corr.add(i as f64, (i * 2) as f64);
+        }
+        assert!((corr.get().unwrap() - 1.0).abs() < 1e-9);
+
+        let mut corr = Correlation::default();
+        for i in 0..10 {
+            corr.add(i as f64, (10 - i) as f64);
+        }
+        assert!((corr.get().unwrap() + 1.0).abs() < 1e-9);
+
+        // no variance
+        let mut corr = Correlation::default();
+        corr.add(1.0, 1.0);
+        corr.add(1.0, 2.0);
+        assert_eq!(corr.get(), None);
+    }
+
+    #[test]
+    fn rank_changes() {
+        assert_eq!(rank_change(9, 4), 1.0);
+        assert_eq!(rank_change(999, 499), 1.0);
+        assert_eq!(rank_change(4, 9), -1.0);
+        assert_eq!(rank_change(3, 3), 0.0);
+    }
+
+    #[test]
+    fn tlds() {
+        assert_eq!(tld("example.com"), Some("com".to_string()));
+        assert_eq!(tld("www.example.CO.UK"), Some("uk".to_string()));
+        assert_eq!(tld("https://example.dk/page"), Some("dk".to_string()));
+        assert_eq!(tld("localhost"), None);
+    }
+
+    #[test]
+    fn medians() {
+        assert_eq!(median(vec![]), None);
+        assert_eq!(median(vec![3.0, 1.0, 2.0]), Some(2.0));
+        assert_eq!(median(vec![4.0, 1.0, 2.0, 3.0]), Some(2.5));
+    }
+
+    #[test]
+    fn overlap() {
+        let old = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let new = vec!["b".to_string(), "a".to_string(), "d".to_string()];
+
+        assert_eq!(top_overlap(&old, &new, 2), Some(1.0));
+        assert_eq!(top_overlap(&old, &new, 3), Some(2.0 / 3.0));
+        assert_eq!(top_overlap(&old, &[], 3), None);
+    }
+
+    #[test]
+    fn shifts_and_movers() {
+        let mut movers: Vec<_> = (0..MIN_TLD_NODES)
+            .map(|i| mover(&format!("{i}.dk"), -2.0))
+            .chain((0..MIN_TLD_NODES).map(|i| mover(&format!("{i}.com"), 1.0)))
+            .collect();
+        movers.push(mover("a.org", 5.0));
+
+        let shifts = tld_shifts(&movers);
+        assert_eq!(
+            shifts,
+            vec![
+                TldShift {
+                    tld: "dk".to_string(),
+                    num_nodes: MIN_TLD_NODES,
+                    median_change: -2.0
+                },
+                TldShift {
+                    tld: "com".to_string(),
+                    num_nodes: MIN_TLD_NODES,
+                    median_change: 1.0
+                },
+            ]
+        );
+
+        let (risers, fallers) = risers_and_fallers(movers, 2);
+        assert_eq!(risers, vec![mover("a.org", 5.0), mover("0.com", 1.0)]);
+        assert_eq!(fallers, vec![mover("9.dk", -2.0), mover("8.dk", -2.0)]);
+    }
+}
@@ -0,0 +1,338 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Serves the centrality stores built by the `centrality` command, so the frontends and
+//! admin tools can look up the centrality of hosts and pages without access to the stores.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use url::Url;
+
+use crate::{
+    config,
+    distributed::{
+        cluster::Cluster,
+        member::{Member, Service},
+        sonic,
+    },
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    memory_budget, sonic_service,
+    webgraph::{Node, NodeID},
+    Result,
+};
+
+/// The maximum number of hosts in a response to [`TopHosts`].
+pub const MAX_TOP_HOSTS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CentralityLevel {
+    Host,
+    Page,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CentralityScore {
+    pub score: f64,
+    /// The position of the node when all the nodes are ordered by their centrality, starting from 0.
+    pub rank: u64,
+    /// The percentage of the nodes with a lower centrality than the node.
+    pub percentile: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostScore {
+    pub host: String,
+    pub score: f64,
+}
+
+fn percentile(rank: u64, num_nodes: u64) -> f64 {
+    if num_nodes == 0 {
+        return 0.0;
+    }
+
+    100.0 * (num_nodes.saturating_sub(rank + 1)) as f64 / num_nodes as f64
+}
+
+fn node(level: CentralityLevel, name: &str) -> Option<Node> {
+    let url = if name.contains("://") {
+        Url::parse(name)
+    } else {
+        Url::parse(&format!("http://{name}"))
+    }
+    .ok()?;
+
+    let node = match level {
+        CentralityLevel::Host => Node::from(&url).into_host(),
+        CentralityLevel::Page => Node::from(&url),
+    };
+
+    if node.name.is_empty() {
+        None
+    } else {
+        Some(node)
+    }
+}
+
+/// The `k` hosts with the highest centrality, optionally only the ones under `tld`.
+/// The hosts must be ordered by descending centrality.
+fn top_k(hosts: &[HostScore], k: usize, tld: Option<&str>) -> Vec<HostScore> {
+    let suffix = tld.map(|tld| format!(".{}", tld.trim_start_matches('.').to_lowercase()));
+
+    hosts
+        .iter()
+        .filter(|host| {
+            suffix
+                .as_ref()
+                .map_or(true, |suffix| host.host.ends_with(suffix.as_str()))
+        })
+        .take(k.min(MAX_TOP_HOSTS))
+        .cloned()
+        .collect()
+}
+
+/// A centrality store together with the ranks of its nodes.
+struct RankedStore {
+    centrality: RocksDbStore<NodeID, f64>,
+    rank: RocksDbStore<NodeID, f64>,
+    num_nodes: u64,
+}
+
+impl RankedStore {
+    fn open<P: AsRef<Path>>(path: P, name: &str) -> Self {
+        let path = path.as_ref();
+        let centrality = RocksDbStore::open_read_only(path.join(name));
+        let rank: RocksDbStore<NodeID, f64> =
+            RocksDbStore::open_read_only(path.join(format!("{name}_rank")));
+
+        info!("counting the nodes in {}", path.join(name).display());
+        let num_nodes = rank.iter().count() as u64;
+
+        Self {
+            centrality,
+            rank,
+            num_nodes,
+        }
+    }
+
+    fn get(&self, node: &NodeID) -> Option<CentralityScore> {
+        let score = self.centrality.get(node)?;
+        let rank = self.rank.get(node)? as u64;
+
+        Some(CentralityScore {
+            score,
+            rank,
+            percentile: percentile(rank, self.num_nodes),
+        })
+    }
+}
+
+/// Read the top hosts from the csv that is written next to the host centrality store.
+fn read_top_hosts<P: AsRef<Path>>(path: P) -> Result<Vec<HostScore>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_path(path)?;
+
+    let mut hosts = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+
+        if let (Some(host), Some(Ok(score))) = (record.get(0), record.get(1).map(str::parse)) {
+            hosts.push(HostScore {
+                host: host.to_string(),
+                score,
+            });
+        }
+    }
+
+    hosts.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    Ok(hosts)
+}
+
+sonic_service!(CentralityService, [GetCentralities, TopHosts]);
+
+pub struct CentralityService {
+    host: RankedStore,
+    page: Option<RankedStore>,
+    top_hosts: Vec<HostScore>,
+    // dropping the handle leaves the cluster
+    #[allow(unused)]
+    cluster_handle: Cluster,
+}
+
+impl CentralityService {
+    async fn new(config: config::CentralityServerConfig) -> Result<Self> {
+        memory_budget::global().set_limit(config.memory_budget_bytes);
+
+        let host = RankedStore::open(&config.host_centrality_path, "harmonic");
+        let page = config
+            .page_centrality_path
+            .as_ref()
+            .map(|path| RankedStore::open(path, "approx_harmonic"));
+        let top_hosts =
+            read_top_hosts(Path::new(&config.host_centrality_path).join("harmonic.csv"))?;
+
+        let cluster_handle = Cluster::join(
+            Member {
+                id: config.cluster_id,
+                service: Service::Centrality { host: config.host },
+            },
+            config.gossip_addr,
+            config.gossip_seed_nodes.unwrap_or_default(),
+        )
+        .await?;
+
+        Ok(Self {
+            host,
+            page,
+            top_hosts,
+            cluster_handle,
+        })
+    }
+
+    fn store(&self, level: CentralityLevel) -> Option<&RankedStore> {
+        match level {
+            CentralityLevel::Host => Some(&self.host),
+            CentralityLevel::Page => self.page.as_ref(),
+        }
+    }
+}
+
+/// Get the centrality of each of the hosts or pages. The response has an entry for
+/// each name in the same order, which is `None` if the centrality is not known.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetCentralities {
+    pub level: CentralityLevel,
+    pub names: Vec<String>,
+}
+
+impl sonic::service::Message<CentralityService> for GetCentralities {
+    type Response = Vec<Option<CentralityScore>>;
+    async fn handle(
+        self,
+        server: &CentralityService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        let Some(store) = server.store(self.level) else {
+            return Ok(vec![None; self.names.len()]);
+        };
+
+        Ok(self
+            .names
+            .iter()
+            .map(|name| store.get(&node(self.level, name)?.id()))
+            .collect())
+    }
+}
+
+/// Get the hosts with the highest centrality, optionally only the ones under a top level domain.
+/// Only the hosts in the csv of the top hosts can be listed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopHosts {
+    pub k: usize,
+    pub tld: Option<String>,
+}
+
+impl sonic::service::Message<CentralityService> for TopHosts {
+    type Response = Vec<HostScore>;
+    async fn handle(
+        self,
+        server: &CentralityService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        Ok(top_k(&server.top_hosts, self.k, self.tld.as_deref()))
+    }
+}
+
+pub async fn run(config: config::CentralityServerConfig) -> Result<()> {
+    let addr = config.host;
+    let prometheus_host = config.prometheus_host;
+    let server = CentralityService::new(config)
+        .await?
+        .bind(addr)
+        .await
+        .unwrap();
+
+    if let Some(prometheus_host) = prometheus_host {
+        super::serve_metrics(&server, prometheus_host)?;
+    }
+
+    info!("centrality server is ready to accept requests on {}", addr);
+
+    loop {
+        if let Err(e) = server.accept().await {
+            tracing::error!("{:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(name: &str, score: f64) -> HostScore {
+        HostScore {
+            host: name.to_string(),
+            score,
+        }
+    }
+
+    #[test]
+    fn percentiles() {
+        assert_eq!(percentile(0, 4), 75.0);
+        assert_eq!(percentile(3, 4), 0.0);
+        assert_eq!(percentile(0, 1), 0.0);
+        assert_eq!(percentile(0, 0), 0.0);
+    }
+
+    #[test]
+    fn node_names() {
+        assert_eq!(
+            node(CentralityLevel::Host, "https://www.example.com/page").map(|n| n.name),
+            Some("example.com".to_string())
+        );
+        assert_eq!(
+            node(CentralityLevel::Host, "example.com").map(|n| n.id()),
+            node(CentralityLevel::Host, "http://example.com/").map(|n| n.id())
+        );
+        assert!(node(CentralityLevel::Page, "https://example.com/page").is_some());
+        assert!(node(CentralityLevel::Host, "http://").is_none());
+    }
+
+    #[test]
+    fn top_hosts() {
+        let hosts = vec![
+            host("a.com", 4.0),
+            host("b.dk", 3.0),
+            host("c.co.uk", 2.0),
+            host("d.dk", 1.0),
+        ];
+
+        assert_eq!(
+            top_k(&hosts, 2, None),
+            vec![host("a.com", 4.0), host("b.dk", 3.0)]
+        );
+        assert_eq!(
+            top_k(&hosts, 10, Some("dk")),
+            vec![host("b.dk", 3.0), host("d.dk", 1.0)]
+        );
+        assert_eq!(top_k(&hosts, 10, Some(".UK")), vec![host("c.co.uk", 2.0)]);
+        assert!(top_k(&hosts, 10, Some("org")).is_empty());
+        assert!(top_k(&hosts, 0, None).is_empty());
+    }
+}
@@ -0,0 +1,472 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Startup self-tests for the services. A check validates the config, opens all
+//! the stores and models it references and performs a canary request, without
+//! joining the cluster or accepting traffic. The result of every step is
+//! collected in a [`Report`] that is printed when the check is done.
+
+use std::{
+    fmt::Display,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    config::{self, ThreadPoolConfig},
+    crawler,
+    distributed::net,
+    index::Index,
+    ranking::{
+        inbound_similarity::InboundSimilarity,
+        models::{lambdamart::LambdaMART, linear::LinearRegression},
+    },
+    searcher::{LocalSearcher, SearchQuery},
+    signal_store::SignalStore,
+    webgraph::{Compression, NodeID, WebgraphBuilder},
+    webpage::topic_classifier,
+    Result,
+};
+
+const CANARY_QUERY: &str = "test";
+const CANARY_URL: &str = "https://example.com/";
+
+enum Outcome {
+    Ok(Option<String>),
+    Failed(String),
+    Skipped(String),
+}
+
+struct Entry {
+    name: String,
+    outcome: Outcome,
+    elapsed: Duration,
+}
+
+#[derive(Default)]
+pub struct Report {
+    entries: Vec<Entry>,
+}
+
+impl Report {
+    fn run<T>(
+        &mut self,
+        name: &str,
+        f: impl FnOnce() -> Result<T>,
+        detail: impl FnOnce(&T) -> Option<String>,
+    ) -> Option<T> {
+        let start = Instant::now();
+        let res = f();
+        let elapsed = start.elapsed();
+
+        let (outcome, res) = match res {
+            Ok(res) => (Outcome::Ok(detail(&res)), Some(res)),
+            Err(e) => (Outcome::Failed(format!("{e:#}")), None),
+        };
+
+        self.entries.push(Entry {
+            name: name.to_string(),
+            outcome,
+            elapsed,
+        });
+
+        res
+    }
+
+    /// Run a single step of the check.
+    pub fn check<T>(&mut self, name: &str, f: impl FnOnce() -> Result<T>) -> Option<T> {
+        self.run(name, f, |_| None)
+    }
+
+    /// Run a single step of the check and include its result in the report.
+    pub fn check_detail(&mut self, name: &str, f: impl FnOnce() -> Result<String>) {
+        self.run(name, f, |detail| Some(detail.clone()));
+    }
+
+    pub fn skip(&mut self, name: &str, reason: &str) {
+        self.entries.push(Entry {
+            name: name.to_string(),
+            outcome: Outcome::Skipped(reason.to_string()),
+            elapsed: Duration::ZERO,
+        });
+    }
+
+    pub fn num_failed(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| matches!(entry.outcome, Outcome::Failed(_)))
+            .count()
+    }
+
+    /// Print the report and return an error if any of the steps failed.
+    pub fn finish(self) -> Result<()> {
+        println!("{self}");
+
+        let num_failed = self.num_failed();
+        if num_failed > 0 {
+            bail!("{} of {} checks failed", num_failed, self.entries.len());
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for entry in &self.entries {
+            let (status, detail) = match &entry.outcome {
+                Outcome::Ok(detail) => ("ok", detail.clone()),
+                Outcome::Failed(err) => ("FAIL", Some(err.clone())),
+                Outcome::Skipped(reason) => ("skip", Some(reason.clone())),
+            };
+
+            write!(
+                f,
+                "[{status:>4}] {} ({} ms)",
+                entry.name,
+                entry.elapsed.as_millis()
+            )?;
+
+            if let Some(detail) = detail {
+                write!(f, ": {detail}")?;
+            }
+
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn load_config<T: DeserializeOwned>(report: &mut Report, path: &str) -> Option<T> {
+    report.check("config", || {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("failed to read config '{path}': {e}"))?;
+        toml::from_str(&raw).map_err(|e| anyhow!("failed to parse config '{path}': {e}"))
+    })
+}
+
+fn existing_path(path: &str) -> Result<&Path> {
+    let path = Path::new(path);
+
+    if !path.exists() {
+        bail!("{} does not exist", path.display());
+    }
+
+    Ok(path)
+}
+
+fn check_thread_pool(report: &mut Report, name: &str, config: &ThreadPoolConfig) {
+    report.check(name, || {
+        if config.num_threads == Some(0) {
+            bail!("num_threads must be positive");
+        }
+
+        if let Some(cores) = &config.pin_cores {
+            let num_cpus = num_cpus::get();
+            if let Some(core) = cores.iter().find(|core| **core >= num_cpus) {
+                bail!("cannot pin to core {core}, the machine has {num_cpus} cores");
+            }
+        }
+
+        Ok(())
+    });
+}
+
+pub fn search_server(config_path: &str) -> Result<()> {
+    let mut report = Report::default();
+
+    let Some(config) = load_config::<config::SearchServerConfig>(&mut report, config_path) else {
+        return report.finish();
+    };
+
+    check_thread_pool(&mut report, "runtime threads", &config.threads.runtime);
+    check_thread_pool(&mut report, "rayon threads", &config.threads.rayon);
+    if let Some(io_pool) = &config.io_pool {
+        check_thread_pool(&mut report, "io pool threads", io_pool);
+    }
+
+    let index = report.check("index", || {
+        let index = Index::open_read_only(existing_path(&config.index_path)?)?;
+        let num_docs = index.inverted_index.tv_searcher().num_docs();
+
+        if num_docs == 0 {
+            bail!("index at {} is empty", config.index_path);
+        }
+
+        Ok(index)
+    });
+
+    let inbound_similarity = match &config.host_centrality_store_path {
+        Some(path) => report.check("inbound similarity", || {
+            InboundSimilarity::open(existing_path(path)?.join("inbound_similarity"))
+        }),
+        None => {
+            report.skip("inbound similarity", "host_centrality_store_path not set");
+            None
+        }
+    };
+
+    let linear_model = match &config.linear_model_path {
+        Some(path) => report.check("linear model", || {
+            LinearRegression::open(existing_path(path)?)
+        }),
+        None => {
+            report.skip("linear model", "linear_model_path not set");
+            None
+        }
+    };
+
+    let lambda_model = match &config.lambda_model_path {
+        Some(path) => report.check("lambdamart model", || {
+            LambdaMART::open(existing_path(path)?)
+        }),
+        None => {
+            report.skip("lambdamart model", "lambda_model_path not set");
+            None
+        }
+    };
+
+    let topic_classifier = match &config.topic_classifier_path {
+        Some(path) => report.check("topic classifier", || {
+            topic_classifier::Model::open(existing_path(path)?)
+        }),
+        None => {
+            report.skip("topic classifier", "topic_classifier_path not set");
+            None
+        }
+    };
+
+    match &config.signal_store_path {
+        Some(path) => report.check_detail("signal store", || {
+            let store = SignalStore::open(existing_path(path)?)?;
+            Ok(format!(
+                "version {} with {} nodes",
+                store.version(),
+                store.len()
+            ))
+        }),
+        None => report.skip("signal store", "signal_store_path not set"),
+    }
+
+    match index {
+        Some(index) => {
+            let mut searcher = LocalSearcher::new(index);
+
+            if let Some(inbound_similarity) = inbound_similarity {
+                searcher.set_inbound_similarity(inbound_similarity);
+            }
+
+            if let Some(linear_model) = linear_model {
+                searcher.set_linear_model(linear_model);
+            }
+
+            if let Some(lambda_model) = lambda_model {
+                searcher.set_lambda_model(lambda_model);
+            }
+
+            if let Some(topic_classifier) = topic_classifier {
+                searcher.set_topic_classifier(topic_classifier);
+            }
+
+            searcher.set_collector_config(config.collector.clone());
+            searcher.set_snippet_config(config.snippet.clone());
+
+            report.check_detail("canary query", || {
+                let query = SearchQuery {
+                    query: CANARY_QUERY.to_string(),
+                    ..Default::default()
+                };
+
+                let res = searcher.search_initial(&query, true)?;
+
+                Ok(format!(
+                    "'{}' returned {} results",
+                    CANARY_QUERY,
+                    res.websites.len()
+                ))
+            });
+        }
+        None => report.skip("canary query", "index could not be opened"),
+    }
+
+    report.finish()
+}
+
+pub fn webgraph_server(config_path: &str) -> Result<()> {
+    let mut report = Report::default();
+
+    let Some(config) = load_config::<config::WebgraphServerConfig>(&mut report, config_path) else {
+        return report.finish();
+    };
+
+    report.check("max similar hosts", || {
+        if config.max_similar_hosts == 0 {
+            bail!("max_similar_hosts must be positive");
+        }
+
+        Ok(())
+    });
+
+    let graph = report.check("webgraph", || {
+        WebgraphBuilder::new(existing_path(&config.graph_path)?)
+            .compression(Compression::Lz4)
+            .try_open()
+    });
+
+    match &config.inbound_similarity_path {
+        Some(path) => {
+            report.check("inbound similarity", || {
+                InboundSimilarity::open(existing_path(path)?)
+            });
+        }
+        None => report.skip("inbound similarity", "inbound_similarity_path not set"),
+    }
+
+    match graph {
+        Some(graph) => report.check_detail("canary lookup", || {
+            let node: NodeID = graph
+                .nodes()
+                .next()
+                .ok_or_else(|| anyhow!("the webgraph has no nodes"))?;
+
+            let ingoing = graph.raw_ingoing_edges(&node).len();
+            let outgoing = graph.raw_outgoing_edges(&node).len();
+
+            Ok(format!(
+                "node {} has {} ingoing and {} outgoing edges",
+                node.as_u64(),
+                ingoing,
+                outgoing
+            ))
+        }),
+        None => report.skip("canary lookup", "webgraph could not be opened"),
+    }
+
+    report.finish()
+}
+
+pub fn crawler(config_path: &str) -> Result<()> {
+    let mut report = Report::default();
+
+    let Some(config) = load_config::<config::CrawlerConfig>(&mut report, config_path) else {
+        return report.finish();
+    };
+
+    report.check("settings", || {
+        if config.num_worker_threads == 0 {
+            bail!("num_worker_threads must be positive");
+        }
+
+        if config.timeout_seconds == 0 {
+            bail!("timeout_seconds must be positive");
+        }
+
+        if config.min_crawl_delay_ms > config.max_crawl_delay_ms {
+            bail!(
+                "min_crawl_delay_ms ({}) is larger than max_crawl_delay_ms ({})",
+                config.min_crawl_delay_ms,
+                config.max_crawl_delay_ms
+            );
+        }
+
+        if config.politeness_factor > config.max_politeness_factor {
+            bail!(
+                "politeness_factor ({}) is larger than max_politeness_factor ({})",
+                config.politeness_factor,
+                config.max_politeness_factor
+            );
+        }
+
+        if config.router_hosts.is_empty() {
+            bail!("no router_hosts configured");
+        }
+
+        Ok(())
+    });
+
+    let client = report.check("http client", || crawler::reqwest_client(&config));
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    for host in &config.router_hosts {
+        report.check(&format!("router {host}"), || {
+            rt.block_on(async {
+                tokio::time::timeout(
+                    Duration::from_secs(config.timeout_seconds),
+                    net::connect(host.as_str()),
+                )
+                .await
+                .map_err(|_| anyhow!("timed out connecting to {host}"))??;
+
+                Ok::<_, anyhow::Error>(())
+            })
+        });
+    }
+
+    match client {
+        Some(client) => report.check_detail("canary fetch", || {
+            rt.block_on(async {
+                let res = client.get(CANARY_URL).send().await?;
+                let status = res.status();
+
+                if !status.is_success() {
+                    bail!("{CANARY_URL} returned {status}");
+                }
+
+                Ok::<_, anyhow::Error>(format!("{CANARY_URL} returned {status}"))
+            })
+        }),
+        None => report.skip("canary fetch", "http client could not be created"),
+    }
+
+    report.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report() {
+        let mut report = Report::default();
+
+        assert_eq!(report.check("ok", || Ok(1)), Some(1));
+        assert_eq!(report.check::<()>("fail", || bail!("boom")), None);
+        report.skip("skipped", "not configured");
+        report.check_detail("detail", || Ok("all good".to_string()));
+
+        assert_eq!(report.num_failed(), 1);
+
+        let printed = report.to_string();
+        assert!(printed.contains("[FAIL] fail"));
+        assert!(printed.contains(": boom"));
+        assert!(printed.contains("[skip] skipped"));
+        assert!(printed.contains(": all good"));
+
+        assert!(report.finish().is_err());
+    }
+
+    #[test]
+    fn missing_config() {
+        assert!(search_server("this/path/does/not/exist.toml").is_err());
+    }
+}
@@ -0,0 +1,237 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Bootstrap an index and webgraphs from Common Crawl instead of our own crawl.
+//!
+//! Every segment is processed in parallel into its own index or webgraphs in the
+//! staging folder, which are then merged into the existing artifacts.
+
+use std::{fs, path::Path};
+
+use rayon::prelude::*;
+use tracing::{info, warn};
+
+use crate::{
+    common_crawl::{open_segment, wat_links, SegmentKind, WetPage},
+    config::{CommonCrawlConfig, WarcSource},
+    index::Index,
+    warc::{PayloadType, RecordIterator},
+    webgraph::{edge_weight::EdgeWeights, Webgraph, WebgraphBuilder},
+    Result,
+};
+
+use super::{
+    indexer::{IndexingWorker, JobSettings},
+    webgraph::{open_host_graph_writer, open_page_graph_writer, WebgraphWorker},
+};
+
+fn segment_name(path: &str) -> &str {
+    path.split('/').last().unwrap_or(path)
+}
+
+fn index_segment(
+    worker: &IndexingWorker,
+    source: &WarcSource,
+    path: &str,
+    kind: SegmentKind,
+    staging_path: &Path,
+) -> Result<String> {
+    let name = segment_name(path);
+    info!("indexing {}", name);
+
+    let mut index = Index::open(staging_path.join("index").join(name))?;
+    index.prepare_writer()?;
+
+    let records = RecordIterator::new(open_segment(source, path)?);
+
+    let pages: Box<dyn Iterator<Item = (String, String, u64)>> = match kind {
+        SegmentKind::Wet => Box::new(
+            records
+                .raw()
+                .flatten()
+                .filter_map(|record| WetPage::from_record(&record))
+                .map(|page| (page.to_html(), page.url, page.fetch_time_ms)),
+        ),
+        _ => Box::new(
+            records
+                .flatten()
+                .filter(|record| {
+                    matches!(record.response.payload_type, None | Some(PayloadType::Html))
+                })
+                .map(|record| {
+                    (
+                        record.response.body,
+                        record.request.url,
+                        record.metadata.fetch_time_ms,
+                    )
+                }),
+        ),
+    };
+
+    for (body, url, fetch_time_ms) in pages {
+        if let Ok(webpage) = worker.prepare_webpage(&body, &url, fetch_time_ms) {
+            index.insert(webpage)?;
+        }
+    }
+
+    index.commit()?;
+    index.inverted_index.merge_into_max_segments(1)?;
+
+    info!("{} done", name);
+
+    Ok(index.path)
+}
+
+fn link_segment(
+    source: &WarcSource,
+    path: &str,
+    staging_path: &Path,
+    edge_weights: &EdgeWeights,
+) -> Result<(Webgraph, Webgraph)> {
+    let name = segment_name(path);
+    info!("extracting links from {}", name);
+
+    let mut worker = WebgraphWorker {
+        host_graph: open_host_graph_writer(staging_path.join("host_graph").join(name)),
+        page_graph: open_page_graph_writer(staging_path.join("page_graph").join(name)),
+        edge_weights: edge_weights.clone(),
+    };
+
+    for record in RecordIterator::new(open_segment(source, path)?)
+        .raw()
+        .flatten()
+    {
+        worker.insert_links(wat_links(&record).unwrap_or_default());
+    }
+
+    worker.host_graph.commit();
+    worker.page_graph.commit();
+
+    info!("{} done", name);
+
+    Ok((worker.host_graph.finalize(), worker.page_graph.finalize()))
+}
+
+fn ingest_pages(config: &CommonCrawlConfig, paths: Vec<(String, SegmentKind)>) -> Result<()> {
+    let mut worker = IndexingWorker::new(
+        config.host_centrality_store_path.clone(),
+        config.page_centrality_store_path.clone(),
+        None,
+        None,
+        config.safety_classifier_path.clone(),
+    );
+
+    worker.set_job_settings(JobSettings {
+        host_centrality_threshold: config.host_centrality_threshold,
+        minimum_clean_words: config.minimum_clean_words,
+    });
+
+    let staging_path = Path::new(&config.staging_path);
+
+    let indexes: Vec<String> = paths
+        .into_par_iter()
+        .filter_map(|(path, kind)| {
+            match index_segment(&worker, &config.warc_source, &path, kind, staging_path) {
+                Ok(index) => Some(index),
+                Err(err) => {
+                    warn!("failed to index {}: {:?}", path, err);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    let mut index = Index::open(&config.index_path)?;
+
+    for other_path in indexes {
+        let other = Index::open(&other_path)?;
+        index = index.merge(other);
+        fs::remove_dir_all(other_path)?;
+    }
+
+    index.inverted_index.merge_into_max_segments(1)?;
+
+    Ok(())
+}
+
+fn ingest_links(config: &CommonCrawlConfig, paths: Vec<String>) -> Result<()> {
+    let staging_path = Path::new(&config.staging_path);
+    let edge_weights = EdgeWeights::from(config.edge_weights.clone());
+
+    let graphs: Vec<_> = paths
+        .into_par_iter()
+        .filter_map(|path| {
+            match link_segment(&config.warc_source, &path, staging_path, &edge_weights) {
+                Ok(graphs) => Some(graphs),
+                Err(err) => {
+                    warn!("failed to extract links from {}: {:?}", path, err);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    let mut host_graph = WebgraphBuilder::new(&config.host_graph_path).open();
+    let mut page_graph = WebgraphBuilder::new(&config.page_graph_path).open();
+
+    for (other_host, other_page) in graphs {
+        let other_host_path = other_host.path.clone();
+        let other_page_path = other_page.path.clone();
+
+        host_graph.merge(other_host);
+        page_graph.merge(other_page);
+
+        fs::remove_dir_all(other_host_path)?;
+        fs::remove_dir_all(other_page_path)?;
+    }
+
+    Ok(())
+}
+
+pub fn ingest(config: &CommonCrawlConfig) -> Result<()> {
+    let mut pages = Vec::new();
+    let mut links = Vec::new();
+
+    for path in config
+        .warc_source
+        .paths()?
+        .into_iter()
+        .skip(config.skip_warc_files.unwrap_or(0))
+        .take(config.limit_warc_files.unwrap_or(usize::MAX))
+    {
+        match SegmentKind::from_path(&path) {
+            Some(SegmentKind::Wat) => links.push(path),
+            Some(kind) => pages.push((path, kind)),
+            None => warn!("skipping {}: not a WARC, WAT or WET segment", path),
+        }
+    }
+
+    info!(
+        "ingesting {} page segments and {} link segments",
+        pages.len(),
+        links.len()
+    );
+
+    if !pages.is_empty() {
+        ingest_pages(config, pages)?;
+    }
+
+    if !links.is_empty() {
+        ingest_links(config, links)?;
+    }
+
+    Ok(())
+}
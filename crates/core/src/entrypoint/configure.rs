@@ -22,6 +22,7 @@ use tracing::{debug, info};
 use crate::config::{LocalConfig, WebSpellConfig};
 use crate::entrypoint::indexer::JobSettings;
 use crate::entrypoint::{dmoz_parser, indexer};
+use crate::webgraph::edge_weight::EdgeWeights;
 use crate::Result;
 use std::fs::{self};
 use std::path::Path;
@@ -122,6 +123,7 @@ fn create_webgraph() -> Result<()> {
     let mut worker = webgraph::WebgraphWorker {
         host_graph: webgraph::open_host_graph_writer(&out_path_host),
         page_graph: webgraph::open_page_graph_writer(&out_path_page),
+        edge_weights: EdgeWeights::default(),
     };
 
     worker.process_job(&job);
@@ -20,8 +20,14 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     config,
-    crawler::{self, planner::make_crawl_plan, CrawlCoordinator, Crawler},
+    crawler::{
+        self,
+        index_now::{self, IndexNow},
+        planner::make_crawl_plan,
+        seeder, CrawlCoordinator, Crawler,
+    },
     distributed::sonic::{self, service::Message},
+    host_stats::HostStatsStore,
     kv::rocksdb_store::RocksDbStore,
     sonic_service,
     webgraph::WebgraphBuilder,
@@ -29,6 +35,10 @@ use crate::{
 };
 
 pub async fn worker(config: config::CrawlerConfig) -> Result<()> {
+    if let Some(tls) = &config.tls {
+        sonic::tls::configure::<router::RouterService>(tls)?;
+    }
+
     let crawler = Crawler::new(config).await?;
 
     crawler.run().await;
@@ -37,14 +47,33 @@ pub async fn worker(config: config::CrawlerConfig) -> Result<()> {
 }
 
 pub async fn coordinator(config: config::CrawlCoordinatorConfig) -> Result<()> {
+    if let Some(tls) = &config.tls {
+        sonic::tls::configure::<coordinator::CoordinatorService>(tls)?;
+    }
+
     let coordinator = Arc::new(CrawlCoordinator::new(config.job_queue)?);
 
+    if let Some(index_now_config) = config.index_now {
+        let host = index_now_config.host;
+        let index_now = Arc::new(IndexNow::new(index_now_config, coordinator.clone())?);
+
+        tokio::task::spawn(async move {
+            if let Err(e) = index_now::serve(host, index_now).await {
+                tracing::error!("IndexNow server failed: {:?}", e);
+            }
+        });
+    }
+
     let addr: SocketAddr = config.host;
     let server = coordinator::CoordinatorService { coordinator }
         .bind(addr)
         .await
         .unwrap();
 
+    if let Some(prometheus_host) = config.prometheus_host {
+        super::serve_metrics(&server, prometheus_host)?;
+    }
+
     tracing::info!("Crawl coordinator listening on {}", addr);
 
     loop {
@@ -53,12 +82,21 @@ pub async fn coordinator(config: config::CrawlCoordinatorConfig) -> Result<()> {
 }
 
 pub async fn router(config: config::CrawlRouterConfig) -> Result<()> {
+    if let Some(tls) = &config.tls {
+        sonic::tls::configure::<router::RouterService>(tls)?;
+        sonic::tls::configure::<coordinator::CoordinatorService>(tls)?;
+    }
+
     let router = crawler::Router::new(config.coordinator_addrs.clone()).await?;
 
     let addr: SocketAddr = config.host;
 
     let server = router::RouterService { router }.bind(addr).await.unwrap();
 
+    if let Some(prometheus_host) = config.prometheus_host {
+        super::serve_metrics(&server, prometheus_host)?;
+    }
+
     tracing::info!("Crawl router listening on {}", addr);
 
     loop {
@@ -87,6 +125,26 @@ pub fn planner(config: config::CrawlPlannerConfig) -> Result<()> {
     Ok(())
 }
 
+pub fn seeder(config: config::CrawlSeederConfig) -> Result<()> {
+    let host_centrality = RocksDbStore::open(&config.host_harmonic_path);
+    let host_graph = WebgraphBuilder::new(&config.host_graph_path).open();
+    let page_graph = WebgraphBuilder::new(&config.page_graph_path).open();
+    let host_stats = HostStatsStore::open(&config.host_stats_path)?;
+
+    let seeds = seeder::select_seeds(
+        &host_centrality,
+        &host_graph,
+        &page_graph,
+        &host_stats,
+        &config,
+    )?;
+    seeder::write_seeds(&config.output_path, &seeds)?;
+
+    tracing::info!("wrote {} seeds to {}", seeds.len(), config.output_path);
+
+    Ok(())
+}
+
 pub mod router {
     use crate::crawler::Job;
 
@@ -103,7 +161,11 @@ pub mod router {
     impl Message<RouterService> for NewJob {
         type Response = Option<Job>;
 
-        async fn handle(self, server: &RouterService) -> sonic::Result<Self::Response> {
+        async fn handle(
+            self,
+            server: &RouterService,
+            _ctx: sonic::service::Context,
+        ) -> sonic::Result<Self::Response> {
             Ok(server.router.sample_job().await?)
         }
     }
@@ -126,7 +188,11 @@ pub mod coordinator {
     impl Message<CoordinatorService> for GetJob {
         type Response = Option<Job>;
 
-        async fn handle(self, server: &CoordinatorService) -> sonic::Result<Self::Response> {
+        async fn handle(
+            self,
+            server: &CoordinatorService,
+            _ctx: sonic::service::Context,
+        ) -> sonic::Result<Self::Response> {
             let job = server.coordinator.sample_job()?;
             Ok(job)
         }
@@ -65,7 +65,12 @@ pub struct Search {
 
 impl sonic::service::Message<SearchService> for Search {
     type Response = Option<crate::entity_index::EntityMatch>;
-    async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
+    async fn handle(
+        self,
+        server: &SearchService,
+        ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        ctx.check()?;
         Ok(server.index.search(&self.query))
     }
 }
@@ -78,7 +83,11 @@ pub struct GetEntityImage {
 }
 impl sonic::service::Message<SearchService> for GetEntityImage {
     type Response = Option<Image>;
-    async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
+    async fn handle(
+        self,
+        server: &SearchService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
         Ok(server.index.retrieve_image(&self.image_id).map(|img| {
             let max_width = self.max_width.unwrap_or(u64::MAX) as u32;
             let max_height = self.max_height.unwrap_or(u64::MAX) as u32;
@@ -90,8 +99,18 @@ impl sonic::service::Message<SearchService> for GetEntityImage {
 
 pub async fn run(config: config::EntitySearchServerConfig) -> Result<()> {
     let addr = config.host;
+    let prometheus_host = config.prometheus_host;
+
+    if let Some(tls) = &config.tls {
+        sonic::tls::configure::<SearchService>(tls)?;
+    }
+
     let server = SearchService::new(config).await?.bind(addr).await.unwrap();
 
+    if let Some(prometheus_host) = prometheus_host {
+        super::serve_metrics(&server, prometheus_host)?;
+    }
+
     tracing::info!(
         "entity search server is ready to accept requests on {}",
         addr
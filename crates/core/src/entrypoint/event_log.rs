@@ -0,0 +1,554 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The downstream consumers of the event log. The extractor parses the fetched
+//! pages and appends their links, the indexer inserts the fetched pages into the
+//! index, the webgraph consumer inserts the extracted links into the webgraphs and
+//! the signal store consumer updates the security signals of the fetched pages.
+
+use std::{collections::HashMap, path::Path};
+
+use tracing::{debug, info};
+use url::Url;
+
+use crate::{
+    config::{
+        EventLogConsumerConfig, EventLogIndexerConfig, EventLogSignalStoreConfig,
+        EventLogWebgraphConfig,
+    },
+    event_log::{Consumer, Event, EventLog, ExtractedLink, LogEntry},
+    index::Index,
+    security::SecurityProperties,
+    shard_router::ShardRouter,
+    signal_store::SignalStoreWriter,
+    warc::PayloadType,
+    webgraph::{edge_weight::EdgeWeights, Compression, Node, NodeID, WebgraphBuilder},
+    webpage::{Html, Link},
+    Result,
+};
+
+use super::{
+    indexer::IndexingWorker,
+    webgraph::{open_host_graph_writer, open_page_graph_writer, WebgraphWorker},
+};
+
+pub const EXTRACTOR: &str = "extractor";
+pub const INDEXER: &str = "indexer";
+pub const WEBGRAPH: &str = "webgraph";
+pub const SIGNAL_STORE: &str = "signal_store";
+
+fn is_success(status_code: u16) -> bool {
+    (200..300).contains(&status_code)
+}
+
+/// Parses the fetched html pages and appends their title and links to the log.
+pub struct Extractor;
+
+impl Consumer for Extractor {
+    fn name(&self) -> &str {
+        EXTRACTOR
+    }
+
+    fn handle(&mut self, entry: &LogEntry, log: &EventLog) -> Result<()> {
+        let Event::Fetched {
+            url,
+            status_code,
+            payload_type: PayloadType::Html,
+            body,
+            ..
+        } = &entry.event
+        else {
+            return Ok(());
+        };
+
+        if !is_success(*status_code) {
+            return Ok(());
+        }
+
+        let html = match Html::parse_without_text(body, url) {
+            Ok(html) => html,
+            Err(err) => {
+                debug!("error parsing {}: {:?}", url, err);
+                return Ok(());
+            }
+        };
+
+        log.append(Event::Parsed {
+            url: url.clone(),
+            title: html.title(),
+        })?;
+
+        let links = html
+            .anchor_links()
+            .into_iter()
+            .filter(|link| matches!(link.destination.scheme(), "http" | "https"))
+            .map(|link| ExtractedLink {
+                destination: link.destination.to_string(),
+                text: link.text,
+                rel: link.rel,
+            })
+            .collect();
+
+        log.append(Event::Extracted {
+            url: url.clone(),
+            links,
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Inserts the fetched pages into the index. Pages are deleted before they are
+/// inserted, so replaying the events does not create duplicates.
+///
+/// If routing is configured, only the urls owned by the shard are inserted and deleted.
+pub struct Indexer {
+    index: Index,
+    worker: IndexingWorker,
+}
+
+impl Indexer {
+    pub fn open(config: &EventLogIndexerConfig) -> Result<Self> {
+        let mut index = Index::open(&config.index_path)?;
+        index.prepare_writer()?;
+
+        let mut worker = IndexingWorker::new(
+            config.host_centrality_store_path.clone(),
+            config.page_centrality_store_path.clone(),
+            config.page_webgraph_path.clone(),
+            config.topics_path.clone(),
+            config.safety_classifier_path.clone(),
+        );
+
+        if let Some((router, shard)) = ShardRouter::for_shard(config.routing, config.shard_id)? {
+            worker.set_shard(router, shard);
+        }
+
+        Ok(Self { index, worker })
+    }
+}
+
+impl Consumer for Indexer {
+    fn name(&self) -> &str {
+        INDEXER
+    }
+
+    fn handle(&mut self, entry: &LogEntry, log: &EventLog) -> Result<()> {
+        match &entry.event {
+            Event::Fetched {
+                url,
+                status_code,
+                payload_type: PayloadType::Html,
+                body,
+                fetch_time_ms,
+                ..
+            } if is_success(*status_code) && self.worker.owns(url) => {
+                self.index.inverted_index.delete_url(url)?;
+
+                match self.worker.prepare_webpage(body, url, *fetch_time_ms) {
+                    Ok(webpage) => {
+                        self.index.insert(webpage)?;
+                        log.append(Event::Indexed { url: url.clone() })?;
+                    }
+                    Err(err) => debug!("skipping {}: {}", url, err),
+                }
+            }
+            Event::Deleted { url } if self.worker.owns(url) => {
+                self.index.inverted_index.delete_url(url)?
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.index.commit()
+    }
+}
+
+/// Inserts the extracted links into the webgraphs. The links of each batch are
+/// written to a new segment that is merged into the graphs when the batch is committed.
+///
+/// Deleted pages lose their outgoing links in the page graph. The host graph keeps the
+/// links, as the other pages of the host may still link to the same hosts.
+pub struct Webgraph {
+    config: EventLogWebgraphConfig,
+    worker: Option<WebgraphWorker>,
+}
+
+impl Webgraph {
+    pub fn new(config: EventLogWebgraphConfig) -> Self {
+        Self {
+            config,
+            worker: None,
+        }
+    }
+
+    fn batch_path(graph_path: &str) -> String {
+        format!("{graph_path}.batch")
+    }
+
+    fn worker(&mut self) -> &mut WebgraphWorker {
+        let config = &self.config;
+
+        self.worker.get_or_insert_with(|| WebgraphWorker {
+            host_graph: open_host_graph_writer(Self::batch_path(&config.host_graph_path)),
+            page_graph: open_page_graph_writer(Self::batch_path(&config.page_graph_path)),
+            edge_weights: EdgeWeights::default(),
+        })
+    }
+
+    fn open(graph_path: &str) -> crate::webgraph::Webgraph {
+        WebgraphBuilder::new(graph_path)
+            .compression(Compression::Lz4)
+            .single_threaded()
+            .open()
+    }
+
+    fn merge(graph_path: &str, batch: crate::webgraph::Webgraph) -> Result<()> {
+        let batch_path = batch.path.clone();
+
+        let mut graph = Self::open(graph_path);
+        graph.merge(batch);
+
+        std::fs::remove_dir_all(batch_path)?;
+
+        Ok(())
+    }
+}
+
+impl Consumer for Webgraph {
+    fn name(&self) -> &str {
+        WEBGRAPH
+    }
+
+    fn handle(&mut self, entry: &LogEntry, _: &EventLog) -> Result<()> {
+        let (url, links) = match &entry.event {
+            Event::Extracted { url, links } => (url, links),
+            Event::Deleted { url } => {
+                let Ok(url) = Url::parse(url) else {
+                    return Ok(());
+                };
+
+                // the links of the page in the current batch are merged before they are removed.
+                self.commit()?;
+                Self::open(&self.config.page_graph_path).delete_page(&Node::from(&url).id());
+
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+
+        let Ok(source) = Url::parse(url) else {
+            return Ok(());
+        };
+
+        let links = links.iter().filter_map(|link| {
+            Some(Link {
+                source: source.clone(),
+                destination: Url::parse(&link.destination).ok()?,
+                text: link.text.clone(),
+                rel: link.rel.clone(),
+            })
+        });
+
+        self.worker().insert_links(links);
+
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if let Some(worker) = self.worker.take() {
+            Self::merge(&self.config.host_graph_path, worker.host_graph.finalize())?;
+            Self::merge(&self.config.page_graph_path, worker.page_graph.finalize())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Updates the security signals of the fetched pages in the signal store and removes
+/// the signals of the deleted pages. The store is rewritten with a new version when
+/// the batch is committed, so searchers pick up the changes when they refresh the store.
+pub struct SignalStore {
+    config: EventLogSignalStoreConfig,
+    /// The security properties of the pages in the batch, or `None` if the page was deleted.
+    updates: HashMap<NodeID, Option<SecurityProperties>>,
+}
+
+impl SignalStore {
+    pub fn new(config: EventLogSignalStoreConfig) -> Self {
+        Self {
+            config,
+            updates: HashMap::new(),
+        }
+    }
+}
+
+impl Consumer for SignalStore {
+    fn name(&self) -> &str {
+        SIGNAL_STORE
+    }
+
+    fn handle(&mut self, entry: &LogEntry, _: &EventLog) -> Result<()> {
+        let (url, security) = match &entry.event {
+            Event::Fetched {
+                url,
+                status_code,
+                security: Some(security),
+                ..
+            } if is_success(*status_code) => (url, Some(*security)),
+            Event::Deleted { url } => (url, None),
+            _ => return Ok(()),
+        };
+
+        if let Ok(url) = Url::parse(url) {
+            self.updates.insert(Node::from(&url).id(), security);
+        }
+
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if self.updates.is_empty() {
+            return Ok(());
+        }
+
+        let path = Path::new(&self.config.store_path);
+
+        let store = if path.exists() {
+            Some(crate::signal_store::SignalStore::open(path)?)
+        } else {
+            None
+        };
+
+        let version = store.as_ref().map_or(0, |store| store.version()) + 1;
+        let mut writer = SignalStoreWriter::new(path, version);
+
+        if let Some(store) = store {
+            for (node, vector) in store.iter() {
+                if !matches!(self.updates.get(&node), Some(None)) {
+                    writer.insert_vector(node, &vector);
+                }
+            }
+        }
+
+        for (node, security) in self.updates.drain() {
+            if let Some(security) = security {
+                writer.insert_security(node, &security);
+            }
+        }
+
+        writer.finalize()?;
+
+        Ok(())
+    }
+}
+
+/// Run all the configured consumers until they have processed the events in the log.
+pub fn run(config: EventLogConsumerConfig) -> Result<()> {
+    let log = EventLog::open(&config.event_log)?;
+
+    if config.extractor {
+        let num_events = log.consume(&mut Extractor, config.batch_size)?;
+        info!("{} processed {} events", EXTRACTOR, num_events);
+        log.sync()?;
+    }
+
+    if let Some(indexer) = &config.indexer {
+        let mut indexer = Indexer::open(indexer)?;
+        let num_events = log.consume(&mut indexer, config.batch_size)?;
+        info!("{} processed {} events", INDEXER, num_events);
+        log.sync()?;
+    }
+
+    if let Some(webgraph) = &config.webgraph {
+        let mut webgraph = Webgraph::new(webgraph.clone());
+        let num_events = log.consume(&mut webgraph, config.batch_size)?;
+        info!("{} processed {} events", WEBGRAPH, num_events);
+    }
+
+    if let Some(signal_store) = &config.signal_store {
+        let mut signal_store = SignalStore::new(signal_store.clone());
+        let num_events = log.consume(&mut signal_store, config.batch_size)?;
+        info!("{} processed {} events", SIGNAL_STORE, num_events);
+    }
+
+    Ok(())
+}
+
+/// Replay the events from `offset` the next time the consumer runs.
+pub fn replay(config: EventLogConsumerConfig, consumer: &str, offset: u64) -> Result<()> {
+    let log = EventLog::open(&config.event_log)?;
+    log.replay(consumer, offset)?;
+
+    info!("{} will replay the events from offset {}", consumer, offset);
+
+    Ok(())
+}
+
+/// Print the offset of each consumer and how far it is behind the end of the log.
+pub fn offsets(config: EventLogConsumerConfig) -> Result<()> {
+    let log = EventLog::open(&config.event_log)?;
+    let end = log.next_offset();
+
+    println!("end of log: {end}");
+
+    for (consumer, offset) in log.offsets() {
+        println!(
+            "{consumer}: {offset} ({} behind)",
+            end.saturating_sub(offset)
+        );
+    }
+
+    Ok(())
+}
+
+/// Append a deletion of the url, so it is removed from the downstream stores.
+pub fn delete(config: EventLogConsumerConfig, url: &str) -> Result<()> {
+    let log = EventLog::open(&config.event_log)?;
+    let offset = log.append(Event::Deleted {
+        url: url.to_string(),
+    })?;
+    log.sync()?;
+
+    info!("appended deletion of {} at offset {}", url, offset);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config::EventLogConfig, signal_store::StoredSignal};
+
+    use super::*;
+
+    fn fetched(url: &str, body: &str) -> Event {
+        Event::Fetched {
+            url: url.to_string(),
+            status_code: 200,
+            payload_type: PayloadType::Html,
+            body: body.to_string(),
+            fetch_time_ms: 100,
+            security: None,
+        }
+    }
+
+    #[test]
+    fn extractor() {
+        let log = EventLog::open(&EventLogConfig {
+            path: crate::gen_temp_path().to_str().unwrap().to_string(),
+            segment_size: 100,
+        })
+        .unwrap();
+
+        log.append(fetched(
+            "https://example.com/",
+            r#"
+            <html>
+                <head>
+                    <title>Example</title>
+                </head>
+                <body>
+                    <a href="https://other.com/page">other page</a>
+                    <a href="mailto:mail@example.com">mail</a>
+                </body>
+            </html>
+            "#,
+        ))
+        .unwrap();
+
+        assert_eq!(log.consume(&mut Extractor, 10).unwrap(), 1);
+
+        let events: Vec<_> = log
+            .read_from(1)
+            .unwrap()
+            .map(|entry| entry.unwrap().event)
+            .collect();
+
+        assert_eq!(
+            events,
+            vec![
+                Event::Parsed {
+                    url: "https://example.com/".to_string(),
+                    title: Some("Example".to_string()),
+                },
+                Event::Extracted {
+                    url: "https://example.com/".to_string(),
+                    links: vec![ExtractedLink {
+                        destination: "https://other.com/page".to_string(),
+                        text: "other page".to_string(),
+                        rel: None,
+                    }],
+                },
+            ]
+        );
+
+        // the appended events are not fetched pages, so replaying only extracts the page again.
+        log.replay(EXTRACTOR, 0).unwrap();
+        assert_eq!(log.consume(&mut Extractor, 10).unwrap(), 3);
+        assert_eq!(log.next_offset(), 5);
+    }
+
+    #[test]
+    fn signal_store() {
+        let log = EventLog::open(&EventLogConfig {
+            path: crate::gen_temp_path().to_str().unwrap().to_string(),
+            segment_size: 100,
+        })
+        .unwrap();
+        let mut consumer = SignalStore::new(EventLogSignalStoreConfig {
+            store_path: crate::gen_temp_path().to_str().unwrap().to_string(),
+        });
+
+        let security = SecurityProperties {
+            https: true,
+            hsts: false,
+            mixed_content: false,
+        };
+
+        for url in ["https://a.com/", "https://b.com/"] {
+            log.append(Event::Fetched {
+                security: Some(security),
+                ..fetched(url, "")
+            })
+            .unwrap();
+        }
+
+        assert_eq!(log.consume(&mut consumer, 10).unwrap(), 2);
+
+        let https = |store: &crate::signal_store::SignalStore, url: &str| {
+            store.get_signal(&Node::from(url).id(), StoredSignal::Https)
+        };
+
+        let store = crate::signal_store::SignalStore::open(&consumer.config.store_path).unwrap();
+        assert_eq!(store.version(), 1);
+        assert_eq!(https(&store, "https://a.com/"), Some(1.0));
+        assert_eq!(https(&store, "https://b.com/"), Some(1.0));
+
+        log.append(Event::Deleted {
+            url: "https://a.com/".to_string(),
+        })
+        .unwrap();
+        assert_eq!(log.consume(&mut consumer, 10).unwrap(), 1);
+
+        let store = crate::signal_store::SignalStore::open(&consumer.config.store_path).unwrap();
+        assert_eq!(store.version(), 2);
+        assert_eq!(https(&store, "https://a.com/"), None);
+        assert_eq!(https(&store, "https://b.com/"), Some(1.0));
+    }
+}
@@ -41,7 +41,7 @@ pub struct Job {
     pub base_path: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IndexPointer(PathBuf);
 
 impl From<String> for IndexPointer {
@@ -0,0 +1,481 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A suite of golden queries with structural expectations about their results,
+//! e.g. the host of the top result or that no spam hosts are among the top results.
+//! The suite is run against a local index and the results are written as a junit
+//! report, so ranking regressions can fail the release pipeline.
+
+use std::{
+    fmt::Write as _,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+
+use crate::{
+    config::{GoldenQueriesConfig, WidgetsConfig},
+    index::Index,
+    search_prettifier::DisplayedWebpage,
+    searcher::{LocalSearcher, SearchQuery},
+    signal_store::LiveSignalStore,
+    widgets::{Widget, Widgets},
+    Result,
+};
+
+const SUITE_NAME: &str = "golden queries";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WidgetKind {
+    Calculator,
+    Thesaurus,
+}
+
+impl WidgetKind {
+    fn matches(&self, widget: &Widget) -> bool {
+        matches!(
+            (self, widget),
+            (WidgetKind::Calculator, Widget::Calculator(_))
+                | (WidgetKind::Thesaurus, Widget::Thesaurus(_))
+        )
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GoldenQuery {
+    pub query: String,
+
+    /// Name of the test case in the report. Defaults to the query.
+    pub name: Option<String>,
+
+    /// The top result must be from this host or one of its subdomains.
+    pub top_host: Option<String>,
+
+    /// The query must trigger this widget.
+    pub widget: Option<WidgetKind>,
+
+    /// Skip the spam check for this query.
+    #[serde(default)]
+    pub allow_spam: bool,
+}
+
+impl GoldenQuery {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.query)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Suite {
+    /// Hosts that must not appear among the top results of any query.
+    #[serde(default)]
+    pub spam_hosts: Vec<String>,
+
+    #[serde(default)]
+    pub queries: Vec<GoldenQuery>,
+}
+
+impl Suite {
+    pub fn open(path: &str) -> Result<Self> {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("failed to read suite '{path}': {e}"))?;
+
+        toml::from_str(&raw).map_err(|e| anyhow!("failed to parse suite '{path}': {e}"))
+    }
+}
+
+fn host_matches(host: &str, expected: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let expected = expected.trim_end_matches('.').to_ascii_lowercase();
+
+    host == expected || host.ends_with(&format!(".{expected}"))
+}
+
+fn result_host(webpage: &DisplayedWebpage) -> String {
+    url::Url::parse(&webpage.url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_else(|| webpage.site.clone())
+}
+
+/// Check the results of the query against its expectations and
+/// return a description of each expectation that was not met.
+fn evaluate(
+    query: &GoldenQuery,
+    webpages: &[DisplayedWebpage],
+    widget: Option<&Widget>,
+    spam_hosts: &[String],
+    spam_depth: usize,
+) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    if let Some(expected) = &query.top_host {
+        match webpages.first() {
+            Some(top) => {
+                let host = result_host(top);
+
+                if !host_matches(&host, expected) {
+                    failures.push(format!(
+                        "expected the top result from {expected} but got {}",
+                        top.url
+                    ));
+                }
+            }
+            None => failures.push(format!(
+                "expected the top result from {expected} but there were no results"
+            )),
+        }
+    }
+
+    if let Some(expected) = query.widget {
+        match widget {
+            Some(widget) if expected.matches(widget) => {}
+            Some(_) => failures.push(format!("expected a {expected:?} widget but got another")),
+            None => failures.push(format!("expected a {expected:?} widget but got none")),
+        }
+    }
+
+    if !query.allow_spam {
+        for (rank, webpage) in webpages.iter().take(spam_depth).enumerate() {
+            let host = result_host(webpage);
+
+            if let Some(spam) = spam_hosts.iter().find(|spam| host_matches(&host, spam)) {
+                failures.push(format!(
+                    "spam host {spam} at position {}: {}",
+                    rank + 1,
+                    webpage.url
+                ));
+            }
+        }
+    }
+
+    failures
+}
+
+enum Outcome {
+    Passed,
+    Failed(Vec<String>),
+    Error(String),
+}
+
+struct TestCase {
+    name: String,
+    outcome: Outcome,
+    elapsed: Duration,
+}
+
+#[derive(Default)]
+pub struct Report {
+    cases: Vec<TestCase>,
+}
+
+impl Report {
+    fn push(&mut self, name: &str, outcome: Outcome, elapsed: Duration) {
+        self.cases.push(TestCase {
+            name: name.to_string(),
+            outcome,
+            elapsed,
+        });
+    }
+
+    pub fn num_failed(&self) -> usize {
+        self.cases
+            .iter()
+            .filter(|case| matches!(case.outcome, Outcome::Failed(_)))
+            .count()
+    }
+
+    pub fn num_errors(&self) -> usize {
+        self.cases
+            .iter()
+            .filter(|case| matches!(case.outcome, Outcome::Error(_)))
+            .count()
+    }
+
+    fn total_time(&self) -> Duration {
+        self.cases.iter().map(|case| case.elapsed).sum()
+    }
+
+    /// The report in the junit xml format understood by most CI systems.
+    pub fn junit(&self) -> String {
+        use quick_xml::escape::escape;
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+
+        writeln!(
+            xml,
+            "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">",
+            SUITE_NAME,
+            self.cases.len(),
+            self.num_failed(),
+            self.num_errors(),
+            self.total_time().as_secs_f64()
+        )
+        .unwrap();
+
+        for case in &self.cases {
+            write!(
+                xml,
+                "  <testcase classname=\"golden_queries\" name=\"{}\" time=\"{:.3}\"",
+                escape(case.name.as_str()),
+                case.elapsed.as_secs_f64()
+            )
+            .unwrap();
+
+            match &case.outcome {
+                Outcome::Passed => xml.push_str("/>\n"),
+                Outcome::Failed(failures) => {
+                    writeln!(
+                        xml,
+                        ">\n    <failure message=\"{}\">{}</failure>\n  </testcase>",
+                        escape(failures[0].as_str()),
+                        escape(failures.join("\n").as_str())
+                    )
+                    .unwrap();
+                }
+                Outcome::Error(err) => {
+                    writeln!(
+                        xml,
+                        ">\n    <error message=\"{}\"/>\n  </testcase>",
+                        escape(err.as_str())
+                    )
+                    .unwrap();
+                }
+            }
+        }
+
+        xml.push_str("</testsuite>\n");
+
+        xml
+    }
+
+    /// Print a summary of the run and return an error if any of the queries failed.
+    pub fn finish(self, report_path: Option<&str>) -> Result<()> {
+        for case in &self.cases {
+            match &case.outcome {
+                Outcome::Passed => println!("[  ok] {}", case.name),
+                Outcome::Failed(failures) => {
+                    println!("[FAIL] {}", case.name);
+                    for failure in failures {
+                        println!("       {failure}");
+                    }
+                }
+                Outcome::Error(err) => println!("[ ERR] {}: {err}", case.name),
+            }
+        }
+
+        if let Some(path) = report_path {
+            std::fs::write(path, self.junit())?;
+        }
+
+        let num_failed = self.num_failed() + self.num_errors();
+        if num_failed > 0 {
+            bail!(
+                "{} of {} golden queries failed",
+                num_failed,
+                self.cases.len()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+pub fn run(config: GoldenQueriesConfig) -> Result<()> {
+    let suite = Suite::open(&config.suite_path)?;
+
+    let index = Index::open(&config.index_path)?;
+    let mut searcher = LocalSearcher::new(index);
+
+    if let Some(path) = &config.signal_store_path {
+        searcher.set_signal_store(Arc::new(LiveSignalStore::open(path)?));
+    }
+
+    searcher.set_collector_config(config.collector.clone());
+    searcher.set_snippet_config(config.snippet.clone());
+
+    let widgets = Widgets::new(config.widgets.clone().unwrap_or(WidgetsConfig {
+        thesaurus_paths: Vec::new(),
+        calculator_fetch_currencies_exchange: false,
+    }))?;
+
+    let mut report = Report::default();
+
+    for query in &suite.queries {
+        let start = Instant::now();
+
+        let search_query = SearchQuery {
+            query: query.query.clone(),
+            num_results: config.spam_depth.max(1),
+            ..Default::default()
+        };
+
+        let outcome = match searcher.search(&search_query) {
+            Ok(res) => {
+                let widget = widgets.widget(&query.query, Default::default());
+
+                let failures = evaluate(
+                    query,
+                    &res.webpages,
+                    widget.as_ref(),
+                    &suite.spam_hosts,
+                    config.spam_depth,
+                );
+
+                if failures.is_empty() {
+                    Outcome::Passed
+                } else {
+                    Outcome::Failed(failures)
+                }
+            }
+            Err(err) => Outcome::Error(format!("{err:#}")),
+        };
+
+        report.push(query.name(), outcome, start.elapsed());
+    }
+
+    report.finish(config.report_path.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::webpage::{Html, Webpage};
+
+    use super::*;
+
+    fn webpages(urls: &[&str]) -> Vec<DisplayedWebpage> {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for (i, url) in urls.iter().enumerate() {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+                        <html>
+                            <head>
+                                <title>Golden query {i}</title>
+                            </head>
+                            <body>
+                                golden query example text
+                            </body>
+                        </html>
+                    "#
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    host_centrality: (urls.len() - i) as f64,
+                    fetch_time_ms: 500,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().unwrap();
+
+        let searcher = LocalSearcher::new(index);
+        let res = searcher
+            .search(&SearchQuery {
+                query: "golden".to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        res.webpages
+    }
+
+    #[test]
+    fn expectations() {
+        let results = webpages(&["https://www.example.com/", "https://spam.com/"]);
+        let top = result_host(&results[0]);
+
+        let query = GoldenQuery {
+            query: "golden".to_string(),
+            name: None,
+            top_host: Some(top.trim_start_matches("www.").to_string()),
+            widget: None,
+            allow_spam: true,
+        };
+
+        assert!(evaluate(&query, &results, None, &["spam.com".to_string()], 10).is_empty());
+
+        let query = GoldenQuery {
+            top_host: Some("other.com".to_string()),
+            widget: Some(WidgetKind::Calculator),
+            allow_spam: false,
+            ..query
+        };
+
+        let failures = evaluate(&query, &results, None, &["spam.com".to_string()], 10);
+        assert_eq!(failures.len(), 3);
+
+        let failures = evaluate(&query, &[], None, &["spam.com".to_string()], 10);
+        assert_eq!(failures.len(), 2);
+    }
+
+    #[test]
+    fn junit() {
+        let mut report = Report::default();
+        report.push("passes", Outcome::Passed, Duration::from_millis(5));
+        report.push(
+            "fails <html>",
+            Outcome::Failed(vec!["spam host spam.com at position 1".to_string()]),
+            Duration::from_millis(10),
+        );
+        report.push(
+            "errors",
+            Outcome::Error("index closed".to_string()),
+            Duration::ZERO,
+        );
+
+        let xml = report.junit();
+
+        assert!(xml.contains(r#"tests="3" failures="1" errors="1""#));
+        assert!(xml.contains(r#"name="passes" time="0.005"/>"#));
+        assert!(xml.contains("fails &lt;html&gt;"));
+        assert!(xml.contains(r#"<failure message="spam host spam.com at position 1">"#));
+        assert!(xml.contains(r#"<error message="index closed"/>"#));
+
+        assert!(report.finish(None).is_err());
+    }
+
+    #[test]
+    fn suite() {
+        let suite: Suite = toml::from_str(
+            r#"
+            spam_hosts = ["spam.com"]
+
+            [[queries]]
+            query = "rust"
+            top_host = "rust-lang.org"
+
+            [[queries]]
+            query = "2 + 2"
+            widget = "calculator"
+            allow_spam = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(suite.queries.len(), 2);
+        assert_eq!(suite.queries[1].widget, Some(WidgetKind::Calculator));
+        assert_eq!(suite.queries[0].name(), "rust");
+    }
+}
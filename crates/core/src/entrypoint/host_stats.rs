@@ -0,0 +1,208 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use tantivy::columnar::ColumnValues;
+use tracing::info;
+use url::Url;
+
+use crate::{
+    config,
+    distributed::{
+        cluster::Cluster,
+        member::{Member, Service},
+        sonic,
+    },
+    host_stats::{HostStats, HostStatsBuilder, HostStatsStore},
+    index::Index,
+    schema::{FastField, FLOAT_SCALING},
+    signal_store::{SignalStore, StoredSignal},
+    sonic_service,
+    webgraph::{Node, NodeID},
+    Result,
+};
+
+pub fn build(config: config::HostStatsConfig) -> Result<()> {
+    let mut builder = HostStatsBuilder::new();
+
+    info!("aggregating pages in {}", config.index_path);
+    let centralities = insert_pages(&mut builder, &config.index_path)?;
+
+    for path in &config.crawl_error_paths {
+        info!("adding crawl errors from {}", path);
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_path(path)?;
+
+        for record in reader.records() {
+            let record = record?;
+
+            let Some(Ok(url)) = record.get(0).map(Url::parse) else {
+                continue;
+            };
+
+            builder.add_crawl_error(Node::from(&url).into_host().id());
+        }
+    }
+
+    let signal_store = match config.signal_store_path.as_ref() {
+        Some(path) => Some(SignalStore::open(path)?),
+        None => None,
+    };
+
+    let hosts: Vec<_> = builder.hosts().copied().collect();
+
+    for host in hosts {
+        let centrality = signal_store
+            .as_ref()
+            .and_then(|store| store.get_signal(&host, StoredSignal::HostCentrality))
+            .or_else(|| centralities.get(&host).copied());
+
+        if let Some(centrality) = centrality {
+            builder.set_centrality(host, centrality);
+        }
+
+        if let Some(topics) = signal_store.as_ref().and_then(|store| store.topics(&host)) {
+            builder.set_topics(host, topics);
+        }
+    }
+
+    let num_hosts = builder.len();
+    builder.finalize(&config.output_path)?;
+
+    info!(
+        "host stats for {} hosts written to {}",
+        num_hosts, config.output_path
+    );
+
+    Ok(())
+}
+
+/// Add all the live documents in the index to the stats of their host.
+/// Returns the host centralities that were stored in the index.
+fn insert_pages(builder: &mut HostStatsBuilder, path: &str) -> Result<HashMap<NodeID, f64>> {
+    let index = Index::open(path)?;
+    let searcher = index.inverted_index.tv_searcher();
+    let mut centralities = HashMap::new();
+
+    for segment_reader in searcher.segment_readers() {
+        let fastfields = segment_reader.fast_fields();
+
+        let host_ids = fastfields.u64(FastField::HostNodeID.name())?;
+        let scores = fastfields.u64(FastField::PreComputedScore.name())?;
+        let host_centralities = fastfields.u64(FastField::HostCentrality.name())?;
+
+        for doc in segment_reader.doc_ids_alive() {
+            let host_id = host_ids.values.get_val(doc);
+
+            if host_id == u64::MAX {
+                continue;
+            }
+
+            let host = NodeID::from(host_id);
+            let quality = scores.values.get_val(doc) as f64 / FLOAT_SCALING as f64;
+            let centrality = host_centralities.values.get_val(doc) as f64 / FLOAT_SCALING as f64;
+
+            builder.add_page(host, quality);
+            centralities.insert(host, centrality);
+        }
+    }
+
+    Ok(centralities)
+}
+
+sonic_service!(HostStatsService, [GetHostStats]);
+
+pub struct HostStatsService {
+    store: HostStatsStore,
+    // dropping the handle leaves the cluster
+    #[allow(unused)]
+    cluster_handle: Cluster,
+}
+
+impl HostStatsService {
+    async fn new(config: config::HostStatsServerConfig) -> Result<Self> {
+        let store = HostStatsStore::open(&config.store_path)?;
+
+        let cluster_handle = Cluster::join(
+            Member {
+                id: config.cluster_id,
+                service: Service::HostStats { host: config.host },
+            },
+            config.gossip_addr,
+            config.gossip_seed_nodes.unwrap_or_default(),
+        )
+        .await?;
+
+        Ok(Self {
+            store,
+            cluster_handle,
+        })
+    }
+}
+
+/// Get the stats for each of the hosts. The response
+/// has an entry for each host in the same order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetHostStats {
+    pub hosts: Vec<String>,
+}
+
+impl sonic::service::Message<HostStatsService> for GetHostStats {
+    type Response = Vec<Option<HostStats>>;
+    async fn handle(
+        self,
+        server: &HostStatsService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        Ok(self
+            .hosts
+            .iter()
+            .map(|host| server.store.get_by_name(host))
+            .collect())
+    }
+}
+
+pub async fn run(config: config::HostStatsServerConfig) -> Result<()> {
+    let addr = config.host;
+    let prometheus_host = config.prometheus_host;
+
+    if let Some(tls) = &config.tls {
+        sonic::tls::configure::<HostStatsService>(tls)?;
+    }
+
+    let server = HostStatsService::new(config)
+        .await?
+        .bind(addr)
+        .await
+        .unwrap();
+
+    if let Some(prometheus_host) = prometheus_host {
+        super::serve_metrics(&server, prometheus_host)?;
+    }
+
+    info!("host stats server is ready to accept requests on {}", addr);
+
+    loop {
+        if let Err(e) = server.accept().await {
+            tracing::error!("{:?}", e);
+        }
+    }
+}
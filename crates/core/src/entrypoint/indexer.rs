@@ -16,6 +16,7 @@
 use anyhow::anyhow;
 use chrono::Utc;
 use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::thread;
 
@@ -24,16 +25,23 @@ use serde::{Deserialize, Serialize};
 use tokio::pin;
 use tracing::{debug, info, trace, warn};
 
+use crate::access_log::AccessLog;
 use crate::config::{self, WarcSource};
 use crate::entrypoint::download_all_warc_files;
+use crate::geo_store::GeoStore;
 use crate::index::Index;
 use crate::kv::rocksdb_store::RocksDbStore;
 use crate::kv::Kv;
 use crate::mapreduce::{Map, Reduce, Worker};
+use crate::product_store::ProductStore;
 use crate::ranking::SignalAggregator;
+use crate::searcher::ShardId;
+use crate::shard_router::ShardRouter;
+use crate::signal_store::{SignalStore, StoredSignal};
 use crate::warc::PayloadType;
 use crate::webgraph::{Node, NodeID, Webgraph, WebgraphBuilder};
-use crate::webpage::{safety_classifier, Html, Webpage};
+use crate::webpage::region::ALL_REGIONS;
+use crate::webpage::{document_expansion, safety_classifier, topic_classifier, Html, Webpage};
 use crate::{human_website_annotations, Result};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -58,7 +66,11 @@ pub struct IndexingWorker {
     page_webgraph: Option<Webgraph>,
     topics: Option<human_website_annotations::Mapper>,
     safety_classifier: Option<safety_classifier::Model>,
+    topic_classifier: Option<topic_classifier::Model>,
+    expansion_model: Option<(document_expansion::Model, usize)>,
+    signal_store: Option<SignalStore>,
     job_settings: Option<JobSettings>,
+    shard: Option<(ShardRouter, ShardId)>,
 }
 
 impl IndexingWorker {
@@ -87,7 +99,11 @@ impl IndexingWorker {
             topics: topics_path.map(|path| human_website_annotations::Mapper::open(path).unwrap()),
             safety_classifier: safety_classifier_path
                 .map(|path| safety_classifier::Model::open(path).unwrap()),
+            topic_classifier: None,
+            expansion_model: None,
+            signal_store: None,
             job_settings: None,
+            shard: None,
         }
     }
 
@@ -95,7 +111,46 @@ impl IndexingWorker {
         self.job_settings = Some(job_settings);
     }
 
+    /// Use the signal store for centrality values. Nodes that are not
+    /// in the signal store falls back to the centrality stores.
+    pub fn set_signal_store(&mut self, signal_store: SignalStore) {
+        self.signal_store = Some(signal_store);
+    }
+
+    /// Classify the topics of pages that have no topics in the signal store.
+    pub fn set_topic_classifier(&mut self, model: topic_classifier::Model) {
+        self.topic_classifier = Some(model);
+    }
+
+    /// Only index the pages owned by the shard.
+    pub fn set_shard(&mut self, router: ShardRouter, shard: ShardId) {
+        self.shard = Some((router, shard));
+    }
+
+    /// Whether the page should be indexed by this worker, which is always the case if
+    /// the worker is not restricted to a shard.
+    pub fn owns(&self, url: &str) -> bool {
+        self.shard
+            .map(|(router, shard)| router.owns(shard, url))
+            .unwrap_or(true)
+    }
+
+    /// Index up to `max_terms` terms predicted by the document expansion model for each page.
+    pub fn set_expansion_model(&mut self, model: document_expansion::Model, max_terms: usize) {
+        self.expansion_model = Some((model, max_terms));
+    }
+
+    fn stored_signal(&self, node: &NodeID, signal: StoredSignal) -> Option<f64> {
+        self.signal_store
+            .as_ref()
+            .and_then(|store| store.get_signal(node, signal))
+    }
+
     pub fn prepare_webpage(&self, body: &str, url: &str, fetch_time_ms: u64) -> Result<Webpage> {
+        if !self.owns(url) {
+            return Err(anyhow!("owned by another shard"));
+        }
+
         let mut html = match Html::parse_without_text(body, url) {
             Ok(html) => html,
             Err(err) => {
@@ -117,13 +172,13 @@ impl IndexingWorker {
         let host_node_id = node.clone().into_host().id();
 
         let mut host_centrality = self
-            .host_centrality_store
-            .get(&host_node_id)
+            .stored_signal(&host_node_id, StoredSignal::HostCentrality)
+            .or_else(|| self.host_centrality_store.get(&host_node_id))
             .unwrap_or_default();
 
         let mut host_centrality_rank = self
-            .host_centrality_rank_store
-            .get(&host_node_id)
+            .stored_signal(&host_node_id, StoredSignal::HostCentralityRank)
+            .or_else(|| self.host_centrality_rank_store.get(&host_node_id))
             .unwrap_or(u64::MAX as f64);
 
         if let Some(host_centrality_threshold) =
@@ -188,21 +243,25 @@ impl IndexingWorker {
             })
             .unwrap_or_default();
 
-        let mut page_centrality = 0.0;
-
-        if let Some(store) = self.page_centrality_store.as_ref() {
-            let node_id = node.id();
+        let node_id = node.id();
 
-            page_centrality = store.get(&node_id).unwrap_or_default();
-        }
-
-        let mut page_centrality_rank = u64::MAX as f64;
-
-        if let Some(store) = self.page_centrality_rank_store.as_ref() {
-            let node_id = node.id();
+        let mut page_centrality = self
+            .stored_signal(&node_id, StoredSignal::PageCentrality)
+            .or_else(|| {
+                self.page_centrality_store
+                    .as_ref()
+                    .and_then(|store| store.get(&node_id))
+            })
+            .unwrap_or_default();
 
-            page_centrality_rank = store.get(&node_id).unwrap_or(u64::MAX as f64);
-        }
+        let mut page_centrality_rank = self
+            .stored_signal(&node_id, StoredSignal::PageCentralityRank)
+            .or_else(|| {
+                self.page_centrality_rank_store
+                    .as_ref()
+                    .and_then(|store| store.get(&node_id))
+            })
+            .unwrap_or(u64::MAX as f64);
 
         if !page_centrality.is_finite() {
             page_centrality = 0.0;
@@ -240,6 +299,8 @@ impl IndexingWorker {
             node_id: Some(host_node_id),
             dmoz_description,
             safety_classification: None,
+            topics: None,
+            expansion_terms: Vec::new(),
             inserted_at: Utc::now(),
         };
 
@@ -247,6 +308,25 @@ impl IndexingWorker {
             webpage.safety_classification = Some(model.predict(&webpage).label);
         }
 
+        webpage.topics = self
+            .signal_store
+            .as_ref()
+            .and_then(|store| store.topics(&node_id))
+            .or_else(|| {
+                self.topic_classifier
+                    .as_ref()
+                    .map(|model| model.predict(&webpage))
+            })
+            .or_else(|| {
+                self.signal_store
+                    .as_ref()
+                    .and_then(|store| store.topics(&host_node_id))
+            });
+
+        if let Some((model, max_terms)) = self.expansion_model.as_ref() {
+            webpage.expansion_terms = model.expand(&webpage, *max_terms);
+        }
+
         let mut signal_aggregator = SignalAggregator::new(None);
         signal_aggregator.set_current_timestamp(Utc::now().timestamp().max(0) as usize);
 
@@ -329,7 +409,7 @@ pub fn process_job(job: &Job, worker: &IndexingWorker) -> Index {
     index
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IndexPointer(String);
 
 impl From<String> for IndexPointer {
@@ -367,7 +447,7 @@ impl Indexer {
 
         let job_config: WarcSource = config.warc_source.clone();
 
-        let worker = IndexingWorker::new(
+        let mut worker = IndexingWorker::new(
             config.host_centrality_store_path.clone(),
             config.page_centrality_store_path.clone(),
             config.page_webgraph_path.clone(),
@@ -375,6 +455,25 @@ impl Indexer {
             config.safety_classifier_path.clone(),
         );
 
+        if let Some(path) = config.signal_store_path.as_ref() {
+            worker.set_signal_store(SignalStore::open(path)?);
+        }
+
+        if let Some(path) = config.topic_classifier_path.as_ref() {
+            worker.set_topic_classifier(topic_classifier::Model::open(path)?);
+        }
+
+        if let Some(expansion) = config.document_expansion.as_ref() {
+            worker.set_expansion_model(
+                document_expansion::Model::open(&expansion.model_path)?,
+                expansion.max_terms,
+            );
+        }
+
+        if let Some((router, shard)) = ShardRouter::for_shard(config.routing, config.shard_id)? {
+            worker.set_shard(router, shard);
+        }
+
         let indexes = warc_paths
             .into_iter()
             .skip(config.skip_warc_files.unwrap_or(0))
@@ -457,4 +556,301 @@ impl Indexer {
 
         Ok(())
     }
+
+    /// Split an index into a head tier with the pages that has the highest pre-computed
+    /// score and an archive tier with the remaining pages. The tiers can then be served by
+    /// search servers with the corresponding `tier` so the archive is only searched when
+    /// the head does not have enough results.
+    ///
+    /// If demotion is configured, pages from hosts with a low centrality that have not been
+    /// retrieved recently are moved to the archive tier, or pruned entirely.
+    pub fn split_tiers(config: &config::IndexTiersConfig) -> Result<()> {
+        if !(0.0..=1.0).contains(&config.head_fraction) {
+            return Err(anyhow!("head_fraction must be in range [0.0, 1.0]"));
+        }
+
+        let index = Index::open(&config.index_path)?;
+        let docs = index.inverted_index.document_summaries()?;
+
+        let mut scores: Vec<_> = docs.iter().map(|doc| doc.pre_computed_score).collect();
+        scores.sort_by(|a, b| b.total_cmp(a));
+
+        let num_head = (scores.len() as f64 * config.head_fraction).round() as usize;
+        let threshold = match scores.get(num_head.saturating_sub(1)) {
+            Some(score) if num_head > 0 => *score,
+            _ => f64::INFINITY,
+        };
+
+        info!(
+            "putting {} of {} pages with a score of at least {} in the head tier",
+            num_head,
+            scores.len(),
+            threshold
+        );
+
+        // urls to delete from the head and archive tiers in addition to the score ranges.
+        // the archive tier is only split by score if no pages are demoted.
+        let mut head_deletions = Vec::new();
+        let mut archive_deletions = None;
+
+        if let Some(demotion) = &config.demotion {
+            let access_log = AccessLog::open_read_only(&demotion.access_log_path);
+            let cutoff = (Utc::now().timestamp().max(0) as u64)
+                .saturating_sub(demotion.max_idle_days * 24 * 60 * 60);
+
+            let mut deletions = Vec::new();
+            let mut num_demoted = 0;
+
+            for doc in &docs {
+                let is_head = doc.pre_computed_score >= threshold;
+                let is_demoted = doc.host_centrality < demotion.max_host_centrality
+                    && access_log
+                        .last_access(&doc.page)
+                        .map(|last_access| last_access < cutoff)
+                        .unwrap_or(true);
+
+                if is_demoted {
+                    num_demoted += 1;
+                }
+
+                if is_head && is_demoted {
+                    head_deletions.push(index.inverted_index.url(doc.address)?);
+                }
+
+                if (is_head && !is_demoted) || (is_demoted && demotion.prune) {
+                    deletions.push(index.inverted_index.url(doc.address)?);
+                }
+            }
+
+            info!(
+                "demoting {} pages ({} from the head tier)",
+                num_demoted,
+                head_deletions.len()
+            );
+
+            archive_deletions = Some(deletions);
+        }
+
+        drop(index);
+
+        copy_dir(&config.index_path, &config.head_output_path)?;
+        let mut head = Index::open(&config.head_output_path)?;
+        head.inverted_index.prepare_writer()?;
+        head.inverted_index
+            .delete_by_pre_computed_score(..threshold)?;
+        for url in &head_deletions {
+            head.inverted_index.delete_url(url)?;
+        }
+        head.commit()?;
+        head.inverted_index.merge_into_max_segments(1)?;
+
+        copy_dir(&config.index_path, &config.archive_output_path)?;
+        let mut archive = Index::open(&config.archive_output_path)?;
+        archive.inverted_index.prepare_writer()?;
+        match &archive_deletions {
+            Some(urls) => {
+                for url in urls {
+                    archive.inverted_index.delete_url(url)?;
+                }
+            }
+            None => archive
+                .inverted_index
+                .delete_by_pre_computed_score(threshold..)?,
+        }
+        archive.commit()?;
+        archive.inverted_index.merge_into_max_segments(1)?;
+
+        Ok(())
+    }
+
+    /// Partition an index into groups of languages. Each group can then be served by search
+    /// servers with the corresponding `languages`, so queries are sent to the shards for
+    /// their language before the rest.
+    ///
+    /// The language of a page is only known in the index if it has a region, so
+    /// pages in all other languages are put in the group for other languages.
+    pub fn split_languages(config: &config::IndexLanguagesConfig) -> Result<()> {
+        let mut grouped_regions = HashSet::new();
+        let mut groups = Vec::new();
+
+        for group in &config.groups {
+            if group.name == config.other_group {
+                return Err(anyhow!(
+                    "the group name '{}' is used for the other languages",
+                    group.name
+                ));
+            }
+
+            let regions: Vec<_> = ALL_REGIONS
+                .iter()
+                .filter(|region| {
+                    region
+                        .lang()
+                        .map(|lang| group.languages.contains(&lang))
+                        .unwrap_or(false)
+                })
+                .copied()
+                .collect();
+
+            if regions.is_empty() {
+                warn!(
+                    "none of the languages in '{}' are detected by the indexer. the group will be empty",
+                    group.name
+                );
+            }
+
+            for region in &regions {
+                if !grouped_regions.insert(*region) {
+                    return Err(anyhow!(
+                        "{} is in more than one language group",
+                        region.name()
+                    ));
+                }
+            }
+
+            groups.push((group.name.clone(), regions));
+        }
+
+        groups.push((
+            config.other_group.clone(),
+            ALL_REGIONS
+                .iter()
+                .filter(|region| !grouped_regions.contains(region))
+                .copied()
+                .collect(),
+        ));
+
+        for (name, regions) in groups {
+            info!("writing language group '{}'", name);
+
+            let output_path = Path::new(&config.output_path).join(&name);
+            copy_dir(&config.index_path, &output_path)?;
+
+            let mut index = Index::open(&output_path)?;
+            index.inverted_index.prepare_writer()?;
+            for region in ALL_REGIONS
+                .iter()
+                .filter(|region| !regions.contains(region))
+            {
+                index.inverted_index.delete_by_region(*region)?;
+            }
+            index.commit()?;
+            index.inverted_index.merge_into_max_segments(1)?;
+        }
+
+        Ok(())
+    }
+
+    /// Partition an index into shards by the hash of the urls, so each shard owns the
+    /// same urls as the live ingest path and the coordinator route to.
+    pub fn split_shards(config: &config::IndexShardsConfig) -> Result<()> {
+        let router = ShardRouter::try_from(config.routing)?;
+        let index = Index::open(&config.index_path)?;
+
+        let mut urls: HashMap<ShardId, Vec<String>> = HashMap::new();
+        for doc in index.inverted_index.document_summaries()? {
+            let url = index.inverted_index.url(doc.address)?;
+
+            let shard = router.shard_of_str(&url);
+            urls.entry(shard).or_default().push(url);
+        }
+
+        drop(index);
+
+        for shard in router.shards() {
+            info!(
+                "writing shard {:?} with {} pages",
+                shard,
+                urls.get(&shard).map(Vec::len).unwrap_or_default()
+            );
+
+            let output_path = Path::new(&config.output_path).join(shard.as_u64().to_string());
+            copy_dir(&config.index_path, &output_path)?;
+
+            let mut index = Index::open(&output_path)?;
+            index.inverted_index.prepare_writer()?;
+            for url in urls
+                .iter()
+                .filter(|(other, _)| **other != shard)
+                .flat_map(|(_, urls)| urls)
+            {
+                index.inverted_index.delete_url(url)?;
+            }
+            index.commit()?;
+            index.inverted_index.merge_into_max_segments(1)?;
+        }
+
+        Ok(())
+    }
+
+    /// Record the prices of the products in the index in the product store, so price
+    /// changes are tracked between index builds.
+    pub fn record_products(index_path: &str, store_path: &str) -> Result<()> {
+        let index = Index::open(index_path)?;
+        let store = ProductStore::open(store_path);
+        let timestamp = Utc::now().timestamp() as u64;
+
+        let mut num_products = 0;
+        let mut num_changed = 0;
+
+        for (url, product) in index.inverted_index.products()? {
+            num_products += 1;
+
+            if store.record(&url, &product, timestamp) {
+                num_changed += 1;
+            }
+        }
+
+        store.flush();
+
+        info!(
+            "recorded {} products where {} had a new price or availability",
+            num_products, num_changed
+        );
+
+        Ok(())
+    }
+
+    /// Record the places in the index in the geo store, so they can be
+    /// returned for local intent queries.
+    pub fn record_places(index_path: &str, store_path: &str) -> Result<()> {
+        let index = Index::open(index_path)?;
+        let store = GeoStore::open(store_path);
+
+        let mut num_places = 0;
+
+        for (url, places) in index.inverted_index.places()? {
+            for place in places {
+                num_places += 1;
+                store.insert(&url, place);
+            }
+        }
+
+        store.flush();
+
+        info!("recorded {} places", num_places);
+
+        Ok(())
+    }
+}
+
+fn copy_dir<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
+    if to.as_ref().exists() {
+        return Err(anyhow!("{} already exists", to.as_ref().display()));
+    }
+
+    std::fs::create_dir_all(to.as_ref())?;
+
+    for entry in std::fs::read_dir(from.as_ref())? {
+        let entry = entry?;
+        let dest = to.as_ref().join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            copy_dir(entry.path(), dest)?;
+        } else {
+            std::fs::copy(entry.path(), dest)?;
+        }
+    }
+
+    Ok(())
 }
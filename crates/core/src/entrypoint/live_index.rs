@@ -16,7 +16,10 @@
 
 use std::{path::Path, sync::Arc};
 
+use serde::{Deserialize, Serialize};
+
 use crate::{
+    audit_log::{self, AdminRequest, AuditAction, AuditLog},
     config::{LiveIndexConfig, LiveIndexSchedulerConfig},
     distributed::{
         cluster::Cluster,
@@ -37,10 +40,12 @@ use tracing::info;
 
 use super::search_server::{RetrieveWebsites, Search};
 
-sonic_service!(SearchService, [RetrieveWebsites, Search]);
+sonic_service!(SearchService, [RetrieveWebsites, Search, DeleteUrl]);
 
 pub struct SearchService {
     local_searcher: LocalSearcher<Arc<Index>>,
+    index: Arc<Index>,
+    audit_log: Option<Arc<AuditLog>>,
     // dropping the handle leaves the cluster
     #[allow(unused)]
     cluster_handle: Cluster,
@@ -53,12 +58,27 @@ impl SearchService {
         )?;
 
         let manager = IndexManager::new(config.clone())?;
-        let mut local_searcher = LocalSearcher::new(manager.index());
+        let index = manager.index();
+        let mut local_searcher = LocalSearcher::new(Arc::clone(&index));
 
         local_searcher.set_inbound_similarity(inbound_similarity);
 
+        if let (Some(websub), Some(callback)) = (&config.websub, manager.websub()) {
+            let host = websub.host;
+            tokio::task::spawn(async move {
+                if let Err(e) = feed::websub::serve(host, callback).await {
+                    tracing::error!("websub callback server failed: {:?}", e);
+                }
+            });
+        }
+
         tokio::task::spawn(manager.run());
 
+        let audit_log = match &config.audit_log {
+            Some(audit_log) => Some(Arc::new(AuditLog::open_with_config(audit_log)?)),
+            None => None,
+        };
+
         let cluster_handle = Cluster::join(
             Member {
                 id: config.cluster_id,
@@ -74,6 +94,8 @@ impl SearchService {
 
         Ok(Self {
             local_searcher,
+            index,
+            audit_log,
             cluster_handle,
         })
     }
@@ -81,10 +103,14 @@ impl SearchService {
 
 impl sonic::service::Message<SearchService> for RetrieveWebsites {
     type Response = Option<Vec<inverted_index::RetrievedWebpage>>;
-    async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
+    async fn handle(
+        self,
+        server: &SearchService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
         match server
             .local_searcher
-            .retrieve_websites(&self.websites, &self.query)
+            .retrieve_websites(&self.websites, &self.query, &self.snippet)
         {
             Ok(response) => Ok(Some(response)),
             Err(_) => Ok(None),
@@ -94,7 +120,11 @@ impl sonic::service::Message<SearchService> for RetrieveWebsites {
 
 impl sonic::service::Message<SearchService> for Search {
     type Response = Option<InitialWebsiteResult>;
-    async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
+    async fn handle(
+        self,
+        server: &SearchService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
         match server.local_searcher.search_initial(&self.query, true) {
             Ok(result) => Ok(Some(result)),
             Err(_) => Ok(None),
@@ -102,10 +132,52 @@ impl sonic::service::Message<SearchService> for Search {
     }
 }
 
+/// Administrative rpc that deletes the page with the url from the index. The
+/// deletion is visible once the index is committed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteUrl {
+    pub request: AdminRequest,
+}
+
+impl sonic::service::Message<SearchService> for DeleteUrl {
+    type Response = std::result::Result<(), String>;
+    async fn handle(
+        self,
+        server: &SearchService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        let AuditAction::DeletionRequest { url } = self.request.action.clone() else {
+            return Ok(Err("request is not a deletion request".to_string()));
+        };
+
+        Ok(
+            audit_log::perform(server.audit_log.as_ref(), self.request, || {
+                server.index.read().inverted_index.delete_url(&url)
+            })
+            .await,
+        )
+    }
+}
+
 pub async fn serve(config: LiveIndexConfig) -> Result<()> {
     let addr = config.host;
+    let prometheus_host = config.prometheus_host;
+    let split_id = config.split_id.id();
 
-    let server = SearchService::new(config).await?.bind(&addr).await.unwrap();
+    if let Some(tls) = &config.tls {
+        sonic::tls::configure::<SearchService>(tls)?;
+    }
+
+    let server = SearchService::new(config)
+        .await?
+        .bind(&addr)
+        .await
+        .unwrap()
+        .with_shard(split_id);
+
+    if let Some(prometheus_host) = prometheus_host {
+        super::serve_metrics(&server, prometheus_host)?;
+    }
 
     info!("live index is ready to accept requests on {}", addr);
 
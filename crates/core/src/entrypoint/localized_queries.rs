@@ -0,0 +1,225 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Build the popular queries of each language for autosuggest from the search indexes.
+//!
+//! The search index is split into shards by the language of the pages, so the words in
+//! the titles of the shards for a language, ranked by the number of documents they are in,
+//! are the queries for the language. The queries are written to a csv for each language,
+//! named after its language tag, e.g. `de.csv`, which is loaded by
+//! [`crate::autosuggest::Autosuggest::with_localized_dir`].
+
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    config::LocalizedQueriesConfig, entrypoint::term_export, inverted_index::InvertedIndex,
+    locale::Locale, schema::TextField, Result,
+};
+
+/// Numbers and ids are not useful as suggestions, so only words are queries.
+fn is_query(term: &str) -> bool {
+    term.chars().count() >= 2 && term.chars().all(char::is_alphabetic)
+}
+
+/// The number of documents in the shard with each word in their title.
+fn title_words(index_path: &str) -> Result<HashMap<String, u64>> {
+    let index = InvertedIndex::open(index_path)?;
+    let searcher = index.tv_searcher();
+    let mut words = HashMap::new();
+
+    term_export::for_each_term(&searcher, TextField::Title, |term, doc_freq| {
+        if let Ok(term) = std::str::from_utf8(term) {
+            if is_query(term) {
+                words.insert(term.to_string(), doc_freq);
+            }
+        }
+
+        Ok(())
+    })?;
+
+    Ok(words)
+}
+
+/// The queries that are in at least `min_doc_freq` documents, most frequent first.
+fn top_queries(
+    doc_freqs: HashMap<String, u64>,
+    min_doc_freq: u64,
+    max_queries: usize,
+) -> Vec<String> {
+    let mut queries: Vec<_> = doc_freqs
+        .into_iter()
+        .filter(|(_, doc_freq)| *doc_freq >= min_doc_freq)
+        .collect();
+
+    queries.sort_by(|(a, a_freq), (b, b_freq)| b_freq.cmp(a_freq).then_with(|| a.cmp(b)));
+
+    queries
+        .into_iter()
+        .take(max_queries)
+        .map(|(query, _)| query)
+        .collect()
+}
+
+fn write_queries(path: &Path, queries: &[String]) -> Result<()> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record(["query"])?;
+
+    for query in queries {
+        wtr.write_record([query])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+pub fn run(config: &LocalizedQueriesConfig) -> Result<()> {
+    let mut doc_freqs: HashMap<Locale, HashMap<String, u64>> = HashMap::new();
+
+    for shard in &config.shards {
+        let locales: Vec<_> = shard
+            .languages
+            .iter()
+            .filter_map(|lang| {
+                let locale = Locale::from_lang(*lang);
+
+                if locale.is_none() {
+                    tracing::warn!("{} is not a supported language", lang);
+                }
+
+                locale
+            })
+            .collect();
+
+        if locales.is_empty() {
+            continue;
+        }
+
+        let words = title_words(&shard.index_path)?;
+
+        for locale in locales {
+            let locale_doc_freqs = doc_freqs.entry(locale).or_default();
+
+            for (word, doc_freq) in &words {
+                *locale_doc_freqs.entry(word.clone()).or_default() += doc_freq;
+            }
+        }
+
+        tracing::info!("counted the title words of {}", shard.index_path);
+    }
+
+    fs::create_dir_all(&config.output_path)?;
+
+    for (locale, doc_freqs) in doc_freqs {
+        let queries = top_queries(doc_freqs, config.min_doc_freq, config.max_queries);
+        let path = Path::new(&config.output_path).join(format!("{}.csv", locale.tag()));
+
+        write_queries(&path, &queries)?;
+
+        tracing::info!("wrote {} queries for {}", queries.len(), locale.tag());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        autosuggest::Autosuggest, config::LocalizedQueriesShardConfig, index::Index,
+        webpage::Webpage,
+    };
+
+    use super::*;
+
+    fn page(title: &str, url: &str) -> Webpage {
+        Webpage::new(
+            &format!(
+                r#"
+            <html>
+                <head>
+                    <title>{title}</title>
+                </head>
+                <body>
+                    {}
+                </body>
+            </html>
+            "#,
+                crate::rand_words(100)
+            ),
+            url,
+        )
+        .unwrap()
+    }
+
+    fn index(titles: &[&str]) -> Index {
+        let mut index = Index::temporary().unwrap();
+
+        for (i, title) in titles.iter().enumerate() {
+            index
+                .insert(page(title, &format!("https://{i}.com/")))
+                .unwrap();
+        }
+        index.commit().unwrap();
+
+        index
+    }
+
+    #[test]
+    fn queries_for_each_language() {
+        let english = index(&["Bread recipe", "Bread 2024", "Brexit news"]);
+        let german = index(&["Brot backen", "Brötchen backen"]);
+
+        let output = crate::gen_temp_path();
+        let config = LocalizedQueriesConfig {
+            shards: vec![
+                LocalizedQueriesShardConfig {
+                    index_path: english.inverted_index.path.clone(),
+                    languages: vec![whatlang::Lang::Eng],
+                },
+                LocalizedQueriesShardConfig {
+                    index_path: german.inverted_index.path.clone(),
+                    languages: vec![whatlang::Lang::Deu, whatlang::Lang::Jpn],
+                },
+            ],
+            output_path: output.to_str().unwrap().to_string(),
+            max_queries: 3,
+            min_doc_freq: 1,
+        };
+        run(&config).unwrap();
+
+        assert!(output.join("en.csv").exists());
+        assert!(!output.join("ja.csv").exists());
+
+        let autosuggest = Autosuggest::load_csv(output.join("en.csv"))
+            .unwrap()
+            .with_localized_dir(&output)
+            .unwrap();
+
+        // numbers are not queries and only the most frequent words are kept
+        assert_eq!(
+            autosuggest.suggestions("b", Locale::English).unwrap(),
+            vec!["bread".to_string(), "brexit".to_string()]
+        );
+        assert_eq!(
+            autosuggest.suggestions("b", Locale::German).unwrap(),
+            vec![
+                "backen".to_string(),
+                "brot".to_string(),
+                "brötchen".to_string()
+            ]
+        );
+    }
+}
@@ -15,21 +15,35 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 //! The entrypoint module contains all entrypoints that runs the executables.
+pub mod admin;
 pub mod api;
 pub mod autosuggest_scrape;
+pub mod backfill;
 mod centrality;
+pub mod centrality_diff;
+pub mod centrality_server;
+pub mod check;
+pub mod common_crawl;
 #[cfg(feature = "dev")]
 pub mod configure;
 pub mod crawler;
 pub mod dmoz_parser;
 mod entity;
 pub mod entity_search_server;
+pub mod event_log;
 pub mod feed_indexer;
+pub mod golden_queries;
+pub mod host_stats;
 pub mod indexer;
+pub mod localized_queries;
+pub mod prune;
 pub mod safety_classifier;
 pub mod search_server;
+pub mod signal_store;
+pub mod term_export;
+pub mod topic_classifier;
 pub mod web_spell;
-mod webgraph;
+pub(crate) mod webgraph;
 pub mod webgraph_server;
 
 pub use centrality::Centrality;
@@ -39,7 +53,15 @@ use tracing::{debug, log::error};
 pub use webgraph::Webgraph;
 pub mod live_index;
 
-use crate::{config, warc::WarcFile};
+use std::net::SocketAddr;
+
+use crate::{
+    config,
+    distributed::sonic::{self, service::Service},
+    metrics::PrometheusRegistry,
+    warc::WarcFile,
+    Result,
+};
 
 fn download_all_warc_files<'a>(
     warc_paths: &'a [String],
@@ -64,3 +86,26 @@ fn download_all_warc_files<'a>(
         Some(res.unwrap())
     })
 }
+
+/// Expose the request metrics of the sonic server for prometheus on `/metrics` of `addr`.
+fn serve_metrics<S: Service>(server: &sonic::service::Server<S>, addr: SocketAddr) -> Result<()> {
+    let mut registry = PrometheusRegistry::default();
+    server.register_metrics(&mut registry)?;
+    sonic::metrics::client().register(&mut registry)?;
+    let app = crate::api::metrics_router(registry);
+
+    tokio::spawn(async move {
+        let res = match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => axum::serve(listener, app.into_make_service()).await,
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = res {
+            error!("prometheus exporter failed: {:?}", err);
+        }
+    });
+
+    tracing::info!("prometheus exporter listening on {}", addr);
+
+    Ok(())
+}
@@ -0,0 +1,278 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Pruning of the low quality documents of a search index.
+//!
+//! The documents below the quality thresholds are tombstoned instead of deleted, so they
+//! are excluded from the search results but can be restored until the index is merged.
+//! The statistics of a prune estimate how much is lost: the fraction of the documents and
+//! of the summed pre-computed scores that are pruned, and the fraction of the top results
+//! of a sample of queries that are kept.
+
+use std::{collections::HashSet, fs};
+
+use anyhow::anyhow;
+use serde::Serialize;
+
+use crate::{
+    config::IndexPruneConfig,
+    index::Index,
+    inverted_index::{DocAddress, DocumentSummary},
+    searcher::{LocalSearcher, SearchQuery},
+    Result,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PruneStats {
+    pub num_docs: usize,
+    pub num_pruned: usize,
+    /// Fraction of the summed pre-computed scores of the index that is in the pruned documents.
+    pub pruned_score_fraction: f64,
+    /// Number of sample queries with results.
+    pub num_queries: usize,
+    /// Fraction of the top results of the sample queries that are not pruned.
+    /// Not set if there are no sample queries.
+    pub expected_recall: Option<f64>,
+}
+
+fn is_pruned(doc: &DocumentSummary, config: &IndexPruneConfig) -> bool {
+    config
+        .min_pre_computed_score
+        .is_some_and(|min| doc.pre_computed_score < min)
+        || config
+            .min_host_centrality
+            .is_some_and(|min| doc.host_centrality < min)
+}
+
+/// Fraction of the top results of the queries that are not pruned, and the number of queries with results.
+fn expected_recall(
+    index: Index,
+    queries: &[String],
+    pruned: &HashSet<DocAddress>,
+    top_k: usize,
+) -> Result<(Option<f64>, usize)> {
+    let searcher = LocalSearcher::new(index);
+
+    let mut num_queries = 0;
+    let mut num_results = 0;
+    let mut num_kept = 0;
+
+    for query in queries {
+        let res = searcher.search_initial(
+            &SearchQuery {
+                query: query.clone(),
+                num_results: top_k,
+                ..Default::default()
+            },
+            true,
+        )?;
+
+        if res.websites.is_empty() {
+            continue;
+        }
+
+        num_queries += 1;
+
+        for website in res.websites.iter().take(top_k) {
+            num_results += 1;
+
+            if !pruned.contains(&website.pointer.address) {
+                num_kept += 1;
+            }
+        }
+    }
+
+    let recall = if num_results > 0 {
+        Some(num_kept as f64 / num_results as f64)
+    } else {
+        None
+    };
+
+    Ok((recall, num_queries))
+}
+
+pub fn run(config: &IndexPruneConfig) -> Result<PruneStats> {
+    if config.min_pre_computed_score.is_none() && config.min_host_centrality.is_none() {
+        return Err(anyhow!("no pruning threshold is set"));
+    }
+
+    let index = Index::open(&config.index_path)?;
+    let docs = index.inverted_index.document_summaries()?;
+
+    let total_score: f64 = docs.iter().map(|doc| doc.pre_computed_score).sum();
+    let mut pruned_score = 0.0;
+    let mut pruned = HashSet::new();
+
+    for doc in docs.iter().filter(|doc| is_pruned(doc, config)) {
+        pruned_score += doc.pre_computed_score;
+        pruned.insert(doc.address);
+    }
+
+    let queries: Vec<String> = match &config.queries_path {
+        Some(path) => fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let (expected_recall, num_queries) = expected_recall(index, &queries, &pruned, config.top_k)?;
+
+    let stats = PruneStats {
+        num_docs: docs.len(),
+        num_pruned: pruned.len(),
+        pruned_score_fraction: if total_score > 0.0 {
+            pruned_score / total_score
+        } else {
+            0.0
+        },
+        num_queries,
+        expected_recall,
+    };
+
+    tracing::info!("{}", serde_json::to_string_pretty(&stats)?);
+
+    if !config.dry_run {
+        let mut index = Index::open(&config.index_path)?;
+        index.inverted_index.tombstone(pruned)?;
+
+        tracing::info!(
+            "tombstoned {} of {} documents",
+            stats.num_pruned,
+            stats.num_docs
+        );
+    }
+
+    Ok(stats)
+}
+
+/// Return the pruned documents to the search results.
+pub fn restore(index_path: &str) -> Result<()> {
+    let mut index = Index::open(index_path)?;
+    let num_restored = index.inverted_index.restore_tombstoned()?;
+
+    tracing::info!("restored {} documents", num_restored);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::webpage::{Html, Webpage};
+
+    use super::*;
+
+    fn page(url: &str, score: f64) -> Webpage {
+        let mut webpage = Webpage {
+            html: Html::parse(
+                &format!(
+                    r#"
+            <html>
+                <head>
+                    <title>Example website</title>
+                </head>
+                <body>
+                    example {}
+                </body>
+            </html>
+            "#,
+                    crate::rand_words(100)
+                ),
+                url,
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+        webpage.pre_computed_score = score;
+
+        webpage
+    }
+
+    fn config(path: &str, dry_run: bool) -> IndexPruneConfig {
+        IndexPruneConfig {
+            index_path: path.to_string(),
+            min_pre_computed_score: Some(0.5),
+            min_host_centrality: None,
+            queries_path: None,
+            top_k: 10,
+            dry_run,
+        }
+    }
+
+    fn search(path: &str) -> Vec<String> {
+        let searcher = LocalSearcher::new(Index::open(path).unwrap());
+        let mut urls: Vec<_> = searcher
+            .search(&SearchQuery {
+                query: "example".to_string(),
+                ..Default::default()
+            })
+            .unwrap()
+            .webpages
+            .into_iter()
+            .map(|webpage| webpage.url)
+            .collect();
+        urls.sort();
+
+        urls
+    }
+
+    #[test]
+    fn prune_and_restore() {
+        let path = crate::gen_temp_path();
+        let path = path.to_str().unwrap();
+
+        {
+            let mut index = Index::open(path).unwrap();
+            index.prepare_writer().unwrap();
+            index.insert(page("https://a.com/", 1.0)).unwrap();
+            index.insert(page("https://b.com/", 0.8)).unwrap();
+            index.insert(page("https://c.com/", 0.1)).unwrap();
+            index.commit().unwrap();
+        }
+
+        let queries_path = crate::gen_temp_path();
+        fs::write(&queries_path, "example\n\n").unwrap();
+
+        let mut dry_run = config(path, true);
+        dry_run.queries_path = Some(queries_path.to_str().unwrap().to_string());
+
+        let stats = run(&dry_run).unwrap();
+        assert_eq!(stats.num_docs, 3);
+        assert_eq!(stats.num_pruned, 1);
+        assert!((stats.pruned_score_fraction - 0.1 / 1.9).abs() < 0.01);
+        assert_eq!(stats.num_queries, 1);
+        assert!((stats.expected_recall.unwrap() - 2.0 / 3.0).abs() < 0.01);
+        assert_eq!(search(path).len(), 3);
+
+        run(&config(path, false)).unwrap();
+        assert_eq!(
+            search(path),
+            vec!["https://a.com/".to_string(), "https://b.com/".to_string()]
+        );
+
+        restore(path).unwrap();
+        assert_eq!(search(path).len(), 3);
+
+        // the tombstoned documents are deleted when the index is merged
+        run(&config(path, false)).unwrap();
+        let mut index = Index::open(path).unwrap();
+        index.inverted_index.merge_into_max_segments(1).unwrap();
+        assert_eq!(index.inverted_index.document_summaries().unwrap().len(), 2);
+        assert!(index.inverted_index.tombstones().is_empty());
+    }
+}
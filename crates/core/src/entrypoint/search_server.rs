@@ -14,27 +14,39 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{collections::HashMap, path::Path};
+use std::{collections::HashMap, path::Path, sync::Arc, time::Duration};
 
 use serde::{Deserialize, Serialize};
 use tracing::info;
 use url::Url;
 
 use crate::{
+    access_log::AccessLog,
+    audit_log::{self, AdminRequest, AuditAction, AuditLog},
+    collection_stats::CollectionStats,
     config,
     distributed::{
         cluster::Cluster,
         member::{Member, Service},
+        probe::{Health, ProbeResponder},
         sonic,
     },
     index::Index,
-    inverted_index::{self, RetrievedWebpage},
+    inverted_index::{self, RetrievedWebpage, UrlIndexStatus},
+    io_pool::IoPool,
+    memory_budget,
+    object_store::Snapshots,
     ranking::{
         inbound_similarity::InboundSimilarity,
         models::{lambdamart::LambdaMART, linear::LinearRegression},
     },
+    search_overrides::SearchOverrides,
     searcher::{InitialWebsiteResult, LocalSearcher, SearchQuery},
-    sonic_service, Result,
+    signal_store::LiveSignalStore,
+    snippet::SnippetOptions,
+    sonic_service,
+    webpage::topic_classifier,
+    Result,
 };
 
 sonic_service!(
@@ -44,21 +56,63 @@ sonic_service!(
         Search,
         GetWebpage,
         GetHomepageDescriptions,
-    ]
+        GetUrlStatuses,
+        ReloadSignalStore,
+        BlockHost,
+        UnblockHost,
+        OverrideOptic,
+        GetCollectionStats,
+        SetCollectionStats,
+    ],
+    compression = Some(crate::distributed::sonic::compression::Compression::zstd()),
+    codec = crate::distributed::sonic::codec::Format::preferred()
 );
 
 pub struct SearchService {
-    local_searcher: LocalSearcher<Index>,
+    local_searcher: Arc<LocalSearcher<Index>>,
+    io_pool: Option<IoPool>,
+    signal_store: Option<Arc<LiveSignalStore>>,
+    overrides: Arc<SearchOverrides>,
+    audit_log: Option<Arc<AuditLog>>,
+    access_log: Option<Arc<AccessLog>>,
     // dropping the handle leaves the cluster
     #[allow(unused)]
     cluster_handle: Cluster,
+    probe: ProbeResponder,
 }
 
 impl SearchService {
     async fn new(config: config::SearchServerConfig) -> Result<Self> {
+        memory_budget::global().set_limit(config.memory_budget_bytes);
+
+        // the searcher joins the cluster before loading the index, so the clients can
+        // tell from the probes that it is loading instead of it being gone
+        let probe = ProbeResponder::bind(config.host, Health::Loading).await?;
+        let cluster_handle = Cluster::join(
+            Member {
+                id: config.cluster_id,
+                service: Service::Searcher {
+                    host: config.host,
+                    shard: config.shard_id,
+                    tier: config.tier,
+                    languages: config.languages.clone(),
+                },
+            },
+            config.gossip_addr,
+            config.gossip_seed_nodes.unwrap_or_default(),
+        )
+        .await?;
+
         let centrality_store = config
             .host_centrality_store_path
             .map(|p| InboundSimilarity::open(Path::new(&p).join("inbound_similarity")).unwrap());
+
+        if let Some(snapshot) = &config.snapshot {
+            Snapshots::open(snapshot)?
+                .restore_if_missing(Path::new(&config.index_path))
+                .await?;
+        }
+
         let search_index = Index::open(config.index_path)?;
 
         let mut local_searcher = LocalSearcher::new(search_index);
@@ -75,43 +129,118 @@ impl SearchService {
             local_searcher.set_lambda_model(LambdaMART::open(model_path)?);
         }
 
+        if let Some(model_path) = config.topic_classifier_path {
+            local_searcher.set_topic_classifier(topic_classifier::Model::open(model_path)?);
+        }
+
+        let signal_store = match config.signal_store_path.as_ref() {
+            Some(path) => Some(Arc::new(LiveSignalStore::open(path)?)),
+            None => None,
+        };
+
+        if let Some(signal_store) = signal_store.clone() {
+            local_searcher.set_signal_store(Arc::clone(&signal_store));
+
+            let refresh_interval = Duration::from_secs(config.signal_store_refresh_interval_sec);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(refresh_interval);
+
+                loop {
+                    interval.tick().await;
+
+                    if let Err(e) = signal_store.refresh() {
+                        tracing::error!("failed to refresh signal store: {:?}", e);
+                    }
+                }
+            });
+        }
+
         local_searcher.set_collector_config(config.collector);
         local_searcher.set_snippet_config(config.snippet);
 
-        let cluster_handle = Cluster::join(
-            Member {
-                id: config.cluster_id,
-                service: Service::Searcher {
-                    host: config.host,
-                    shard: config.shard_id,
-                },
-            },
-            config.gossip_addr,
-            config.gossip_seed_nodes.unwrap_or_default(),
-        )
-        .await?;
+        let io_pool = match &config.io_pool {
+            Some(io_pool) => Some(IoPool::new(io_pool)?),
+            None => None,
+        };
+
+        let overrides = match &config.overrides_path {
+            Some(path) => SearchOverrides::open(path)?,
+            None => SearchOverrides::in_memory(),
+        };
+
+        let audit_log = match &config.audit_log {
+            Some(audit_log) => Some(Arc::new(AuditLog::open_with_config(audit_log)?)),
+            None => None,
+        };
+
+        let access_log = config
+            .access_log
+            .as_ref()
+            .map(|access_log| Arc::new(AccessLog::open(&access_log.path, access_log.sample_rate)));
 
         Ok(SearchService {
-            local_searcher,
+            local_searcher: Arc::new(local_searcher),
+            io_pool,
+            signal_store,
+            overrides: Arc::new(overrides),
+            audit_log,
+            access_log,
             cluster_handle,
+            probe,
         })
     }
+
+    /// Run a blocking read against the local index. The read is executed
+    /// on the io pool if one is configured and otherwise on the current thread.
+    async fn read<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&LocalSearcher<Index>) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        match &self.io_pool {
+            Some(io_pool) => {
+                let local_searcher = Arc::clone(&self.local_searcher);
+                io_pool.spawn(move || f(&local_searcher)).await
+            }
+            None => Ok(f(&self.local_searcher)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RetrieveWebsites {
     pub websites: Vec<inverted_index::WebsitePointer>,
     pub query: String,
+    pub snippet: SnippetOptions,
 }
 impl sonic::service::Message<SearchService> for RetrieveWebsites {
     type Response = Option<Vec<inverted_index::RetrievedWebpage>>;
-    async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
+    async fn handle(
+        self,
+        server: &SearchService,
+        ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        ctx.check()?;
+        let access_log = server.access_log.clone();
+
         match server
-            .local_searcher
-            .retrieve_websites(&self.websites, &self.query)
+            .read(move |searcher| {
+                // the request might have waited for a thread until after its deadline
+                ctx.check()?;
+                let res = searcher.retrieve_websites(&self.websites, &self.query, &self.snippet);
+
+                if let (Ok(pages), Some(access_log)) = (&res, access_log) {
+                    for page in pages {
+                        access_log.record(&page.url);
+                    }
+                }
+
+                res
+            })
+            .await
         {
-            Ok(response) => Ok(Some(response)),
-            Err(_) => Ok(None),
+            Ok(Ok(response)) => Ok(Some(response)),
+            _ => Ok(None),
         }
     }
 }
@@ -122,10 +251,26 @@ pub struct Search {
 }
 impl sonic::service::Message<SearchService> for Search {
     type Response = Option<InitialWebsiteResult>;
-    async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
-        match server.local_searcher.search_initial(&self.query, true) {
-            Ok(result) => Ok(Some(result)),
-            Err(_) => Ok(None),
+    async fn handle(
+        self,
+        server: &SearchService,
+        ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        ctx.check()?;
+
+        let mut query = self.query;
+        server.overrides.apply(&mut query);
+
+        match server
+            .read(move |searcher| {
+                // the request might have waited for a thread until after its deadline
+                ctx.check()?;
+                searcher.search_initial_with_deadline(&query, true, ctx.deadline())
+            })
+            .await
+        {
+            Ok(Ok(result)) => Ok(Some(result)),
+            _ => Ok(None),
         }
     }
 }
@@ -136,8 +281,16 @@ pub struct GetWebpage {
 }
 impl sonic::service::Message<SearchService> for GetWebpage {
     type Response = Option<RetrievedWebpage>;
-    async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
-        Ok(server.local_searcher.get_webpage(&self.url))
+    async fn handle(
+        self,
+        server: &SearchService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        Ok(server
+            .read(move |searcher| searcher.get_webpage(&self.url))
+            .await
+            .ok()
+            .flatten())
     }
 }
 
@@ -147,30 +300,264 @@ pub struct GetHomepageDescriptions {
 }
 impl sonic::service::Message<SearchService> for GetHomepageDescriptions {
     type Response = HashMap<Url, String>;
-    async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
-        let mut result = HashMap::with_capacity(self.urls.len());
+    async fn handle(
+        self,
+        server: &SearchService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        let result = server
+            .read(move |searcher| {
+                let mut result = HashMap::with_capacity(self.urls.len());
 
-        for url in &self.urls {
-            if let Some(homepage) = server.local_searcher.get_homepage(url) {
-                if let Some(desc) = homepage.description() {
-                    result.insert(url.clone(), desc.clone());
+                for url in &self.urls {
+                    if let Some(homepage) = searcher.get_homepage(url) {
+                        if let Some(desc) = homepage.description() {
+                            result.insert(url.clone(), desc.clone());
+                        }
+                    }
                 }
+
+                result
+            })
+            .await
+            .unwrap_or_default();
+
+        Ok(result)
+    }
+}
+
+/// Look up the index status of a batch of urls. The statuses are returned in
+/// the same order as the urls.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetUrlStatuses {
+    pub urls: Vec<String>,
+}
+impl sonic::service::Message<SearchService> for GetUrlStatuses {
+    type Response = Option<Vec<UrlIndexStatus>>;
+    async fn handle(
+        self,
+        server: &SearchService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        match server
+            .read(move |searcher| {
+                self.urls
+                    .iter()
+                    .map(|url| searcher.url_status(url))
+                    .collect::<crate::Result<Vec<_>>>()
+            })
+            .await
+        {
+            Ok(Ok(statuses)) => Ok(Some(statuses)),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Administrative rpc that refreshes the signal store immediately instead of
+/// waiting for the next scheduled refresh. Responds with whether a newer
+/// version of the store was loaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReloadSignalStore {
+    pub request: AdminRequest,
+}
+
+impl ReloadSignalStore {
+    pub fn action() -> AuditAction {
+        AuditAction::IndexReload {
+            target: "signal_store".to_string(),
+        }
+    }
+}
+
+impl sonic::service::Message<SearchService> for ReloadSignalStore {
+    type Response = std::result::Result<bool, String>;
+    async fn handle(
+        self,
+        server: &SearchService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        let Some(signal_store) = server.signal_store.as_ref() else {
+            return Ok(Err("search server has no signal store".to_string()));
+        };
+
+        if self.request.action != Self::action() {
+            return Ok(Err("request is not a signal store reload".to_string()));
+        }
+
+        Ok(
+            audit_log::perform(server.audit_log.as_ref(), self.request, || {
+                signal_store.refresh()
+            })
+            .await,
+        )
+    }
+}
+
+/// Administrative rpc that removes a host from the results of all searches.
+/// Responds with whether the host was not already blocked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockHost {
+    pub request: AdminRequest,
+}
+
+impl sonic::service::Message<SearchService> for BlockHost {
+    type Response = std::result::Result<bool, String>;
+    async fn handle(
+        self,
+        server: &SearchService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        let AuditAction::HostBlock { host } = self.request.action.clone() else {
+            return Ok(Err("request is not a host block".to_string()));
+        };
+
+        Ok(
+            audit_log::perform(server.audit_log.as_ref(), self.request, || {
+                server.overrides.block_host(&host)
+            })
+            .await,
+        )
+    }
+}
+
+/// Administrative rpc that lets a blocked host appear in the results again.
+/// Responds with whether the host was blocked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnblockHost {
+    pub request: AdminRequest,
+}
+
+impl sonic::service::Message<SearchService> for UnblockHost {
+    type Response = std::result::Result<bool, String>;
+    async fn handle(
+        self,
+        server: &SearchService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        let AuditAction::HostUnblock { host } = self.request.action.clone() else {
+            return Ok(Err("request is not a host unblock".to_string()));
+        };
+
+        Ok(
+            audit_log::perform(server.audit_log.as_ref(), self.request, || {
+                server.overrides.unblock_host(&host)
+            })
+            .await,
+        )
+    }
+}
+
+/// Administrative rpc that sets the optic used for the searches without an
+/// optic of their own, or removes it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OverrideOptic {
+    pub request: AdminRequest,
+}
+
+impl sonic::service::Message<SearchService> for OverrideOptic {
+    type Response = std::result::Result<(), String>;
+    async fn handle(
+        self,
+        server: &SearchService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        let AuditAction::OpticOverride { optic } = self.request.action.clone() else {
+            return Ok(Err("request is not an optic override".to_string()));
+        };
+
+        Ok(
+            audit_log::perform(server.audit_log.as_ref(), self.request, || {
+                server.overrides.set_optic(optic.as_deref())
+            })
+            .await,
+        )
+    }
+}
+
+/// The collection statistics of the shard, to be merged with the other shards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetCollectionStats {
+    pub min_doc_freq: u64,
+    pub max_terms_per_field: usize,
+}
+impl sonic::service::Message<SearchService> for GetCollectionStats {
+    type Response = Option<CollectionStats>;
+    async fn handle(
+        self,
+        server: &SearchService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        let result = server
+            .read(move |searcher| {
+                searcher.collection_stats(self.min_doc_freq, self.max_terms_per_field)
+            })
+            .await;
+
+        match result {
+            Ok(Ok(stats)) => Ok(Some(stats)),
+            Ok(Err(e)) | Err(e) => {
+                tracing::error!("failed to compute collection statistics: {:?}", e);
+                Ok(None)
             }
         }
+    }
+}
 
-        Ok(result)
+/// Use the merged collection statistics of all the shards for BM25.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetCollectionStats {
+    pub stats: CollectionStats,
+}
+impl sonic::service::Message<SearchService> for SetCollectionStats {
+    type Response = ();
+    async fn handle(
+        self,
+        server: &SearchService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        server
+            .local_searcher
+            .set_collection_stats(Arc::new(self.stats));
+
+        Ok(())
     }
 }
 
 pub async fn run(config: config::SearchServerConfig) -> Result<()> {
     let addr = config.host;
-    let server = SearchService::new(config).await?.bind(addr).await.unwrap();
 
-    info!("search server is ready to accept requests on {}", addr);
+    if let Some(tls) = &config.tls {
+        sonic::tls::configure::<SearchService>(tls)?;
+    }
 
-    loop {
-        if let Err(e) = server.accept().await {
-            tracing::error!("{:?}", e);
-        }
+    let prometheus_host = config.prometheus_host;
+    let shard_id = config.shard_id;
+    let concurrency_limit = config.concurrency_limit;
+    let drain_timeout = Duration::from_millis(config.drain_timeout_ms);
+
+    let server = SearchService::new(config)
+        .await?
+        .bind(addr)
+        .await
+        .unwrap()
+        .with_shard(shard_id.as_u64())
+        .with_concurrency_limit(concurrency_limit);
+
+    if let Some(prometheus_host) = prometheus_host {
+        super::serve_metrics(&server, prometheus_host)?;
     }
+
+    server.service().probe.set_health(Health::Ready);
+    info!("search server is ready to accept requests on {}", addr);
+
+    let service = server.service();
+    let shutdown = async move {
+        sonic::shutdown_signal().await;
+        service.cluster_handle.leave().await;
+    };
+
+    server.serve_until(shutdown, drain_timeout).await;
+
+    Ok(())
 }
@@ -0,0 +1,225 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{collections::HashMap, path::Path};
+
+use tracing::info;
+use url::Url;
+
+use crate::{
+    config,
+    security::MalwareList,
+    signal_store::{SignalStoreWriter, StoredSignal},
+    webgraph::{centrality::topic_centrality::TopicCentrality, Node, NodeID, WebgraphBuilder},
+    webpage::topic_classifier::{self, TopicVector},
+    Result,
+};
+
+use super::download_all_warc_files;
+
+pub fn build(config: config::SignalStoreConfig) -> Result<()> {
+    let version = config
+        .version
+        .unwrap_or_else(|| chrono::Utc::now().timestamp().max(0) as u64);
+
+    let mut writer = SignalStoreWriter::new(&config.output_path, version);
+
+    if let Some(path) = config.host_centrality_store_path.as_ref() {
+        info!("adding host centrality from {}", path);
+        writer.insert_from_kv(
+            Path::new(path).join("harmonic"),
+            StoredSignal::HostCentrality,
+        );
+        writer.insert_from_kv(
+            Path::new(path).join("harmonic_rank"),
+            StoredSignal::HostCentralityRank,
+        );
+    }
+
+    if let Some(path) = config.page_centrality_store_path.as_ref() {
+        info!("adding page centrality from {}", path);
+        writer.insert_from_kv(
+            Path::new(path).join("approx_harmonic"),
+            StoredSignal::PageCentrality,
+        );
+        writer.insert_from_kv(
+            Path::new(path).join("approx_harmonic_rank"),
+            StoredSignal::PageCentralityRank,
+        );
+    }
+
+    for csv_signal in &config.csv_signals {
+        info!("adding {:?} from {}", csv_signal.signal, csv_signal.path);
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(&csv_signal.path)?;
+
+        for record in reader.records() {
+            let record = record?;
+
+            let (Some(url), Some(value)) = (record.get(0), record.get(1)) else {
+                continue;
+            };
+
+            let (Ok(url), Ok(value)) = (Url::parse(url), value.trim().parse::<f64>()) else {
+                continue;
+            };
+
+            writer.insert(Node::from(&url).id(), csv_signal.signal, value);
+        }
+    }
+
+    if let Some(security) = config.security.as_ref() {
+        insert_security(&mut writer, security)?;
+    }
+
+    if let Some(topics) = config.topics.as_ref() {
+        info!("classifying topics of pages in {}", topics.pages_path);
+        let host_topics = insert_topics(&mut writer, topics)?;
+
+        if let Some(path) = topics.host_graph_path.as_ref() {
+            info!("calculating topic-sensitive centrality for {}", path);
+            let graph = WebgraphBuilder::new(path).single_threaded().open();
+            let centrality = TopicCentrality::calculate(&graph, &host_topics);
+
+            for (node, topic, score) in centrality.iter() {
+                writer.insert(node, StoredSignal::TopicCentrality(topic), score);
+            }
+        }
+    }
+
+    let store = writer.finalize()?;
+
+    info!(
+        "signal store with version {} and {} nodes written to {}",
+        store.version(),
+        store.len(),
+        config.output_path
+    );
+
+    Ok(())
+}
+
+/// Classify the pages and add both the topics of each page and the
+/// average topics of the pages for each host. Returns the topics of the hosts.
+fn insert_topics(
+    writer: &mut SignalStoreWriter,
+    config: &config::TopicSignalsConfig,
+) -> Result<HashMap<NodeID, TopicVector>> {
+    let model = topic_classifier::Model::open(&config.model_path)?;
+    let mut hosts: HashMap<NodeID, Vec<TopicVector>> = HashMap::new();
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_path(&config.pages_path)?;
+
+    for record in reader.records() {
+        let record = record?;
+
+        let (Some(url), Some(text)) = (record.get(0), record.get(1)) else {
+            continue;
+        };
+
+        let Ok(url) = Url::parse(url) else {
+            continue;
+        };
+
+        let node = Node::from(&url);
+        let topics = model.predict_text(text);
+
+        writer.insert_topics(node.id(), &topics);
+        hosts.entry(node.into_host().id()).or_default().push(topics);
+    }
+
+    let host_topics: HashMap<_, _> = hosts
+        .into_iter()
+        .map(|(host, pages)| (host, TopicVector::mean(&pages)))
+        .collect();
+
+    for (host, topics) in &host_topics {
+        writer.insert_topics(*host, topics);
+    }
+
+    Ok(host_topics)
+}
+
+/// Add the security properties recorded by the crawler for each page
+/// and flag the pages and hosts on the malware list.
+fn insert_security(
+    writer: &mut SignalStoreWriter,
+    config: &config::SecuritySignalsConfig,
+) -> Result<()> {
+    let malware = match config.malware_list_path.as_ref() {
+        Some(path) => {
+            info!("adding malware from {}", path);
+            MalwareList::open(path)?
+        }
+        None => MalwareList::default(),
+    };
+
+    for host in malware.hosts() {
+        if let Ok(url) = Url::parse(&format!("https://{host}/")) {
+            writer.insert(
+                Node::from(&url).into_host().id(),
+                StoredSignal::Malware,
+                1.0,
+            );
+        }
+    }
+
+    for url in malware.urls() {
+        if let Ok(url) = Url::parse(url) {
+            writer.insert(Node::from(&url).id(), StoredSignal::Malware, 1.0);
+        }
+    }
+
+    // hosts on the list also match their subdomains, which can only
+    // be found for the pages we have crawled.
+    if let Some(source) = config.warc_source.as_ref() {
+        let warc_paths: Vec<_> = source
+            .paths()?
+            .into_iter()
+            .skip(config.skip_warc_files.unwrap_or(0))
+            .take(config.limit_warc_files.unwrap_or(usize::MAX))
+            .collect();
+
+        info!(
+            "adding security properties from {} warc files",
+            warc_paths.len()
+        );
+
+        for file in download_all_warc_files(&warc_paths, source) {
+            for record in file.records().flatten() {
+                let Ok(url) = Url::parse(&record.request.url) else {
+                    continue;
+                };
+
+                let node = Node::from(&url).id();
+
+                if let Some(security) = record.metadata.security.as_ref() {
+                    writer.insert_security(node, security);
+                }
+
+                if malware.contains(&url) {
+                    writer.insert(node, StoredSignal::Malware, 1.0);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
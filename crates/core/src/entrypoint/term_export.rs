@@ -0,0 +1,422 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Export of the term dictionaries of search indexes, for the spell corrector,
+//! synonym mining and analysis outside of stract.
+//!
+//! Each shard is written to a sub-folder with the id of the shard, with a folder for
+//! each text field and a `stats.json` with the collection statistics of the shard.
+//! A field folder has three column files: `terms` with the bytes of the terms
+//! after each other, `offsets` with the end offset of each term in `terms`, and
+//! `doc_freqs` with the number of documents that contain the term. The numbers are
+//! little endian u64 and the terms are sorted.
+//!
+//! The term dictionary of each segment is streamed and merged with the other segments
+//! of the shard, so the dictionaries are never loaded into memory. The shards and
+//! fields are exported in parallel.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use anyhow::anyhow;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use tantivy::termdict::TermStreamer;
+
+use crate::{
+    config::{TermExportConfig, TermExportShardConfig},
+    inverted_index::InvertedIndex,
+    schema::{Field, TextField},
+    Result,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FieldStats {
+    /// Number of exported terms.
+    pub num_terms: u64,
+    /// Sum of the document frequencies of the exported terms.
+    pub sum_doc_freq: u64,
+    /// Number of tokens in the field across all documents.
+    pub total_num_tokens: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ShardStats {
+    pub num_docs: u64,
+    pub fields: BTreeMap<String, FieldStats>,
+}
+
+struct SegmentTerms<'a> {
+    stream: TermStreamer<'a>,
+    is_finished: bool,
+}
+
+impl<'a> SegmentTerms<'a> {
+    fn advance(&mut self) {
+        self.is_finished = !self.stream.advance();
+    }
+}
+
+struct ColumnWriter {
+    terms: BufWriter<File>,
+    offsets: BufWriter<File>,
+    doc_freqs: BufWriter<File>,
+    offset: u64,
+}
+
+impl ColumnWriter {
+    fn create(folder: &Path) -> Result<Self> {
+        fs::create_dir_all(folder)?;
+
+        Ok(Self {
+            terms: BufWriter::new(File::create(folder.join("terms"))?),
+            offsets: BufWriter::new(File::create(folder.join("offsets"))?),
+            doc_freqs: BufWriter::new(File::create(folder.join("doc_freqs"))?),
+            offset: 0,
+        })
+    }
+
+    fn push(&mut self, term: &[u8], doc_freq: u64) -> Result<()> {
+        self.terms.write_all(term)?;
+        self.offset += term.len() as u64;
+        self.offsets.write_all(&self.offset.to_le_bytes())?;
+        self.doc_freqs.write_all(&doc_freq.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.terms.flush()?;
+        self.offsets.flush()?;
+        self.doc_freqs.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Streams the exported terms of a field with their document frequencies.
+pub struct TermReader {
+    terms: BufReader<File>,
+    offsets: BufReader<File>,
+    doc_freqs: BufReader<File>,
+    offset: u64,
+}
+
+impl TermReader {
+    pub fn open<P: AsRef<Path>>(folder: P) -> Result<Self> {
+        let folder = folder.as_ref();
+
+        Ok(Self {
+            terms: BufReader::new(File::open(folder.join("terms"))?),
+            offsets: BufReader::new(File::open(folder.join("offsets"))?),
+            doc_freqs: BufReader::new(File::open(folder.join("doc_freqs"))?),
+            offset: 0,
+        })
+    }
+
+    fn next_term(&mut self) -> Result<Option<(Vec<u8>, u64)>> {
+        let mut buf = [0; 8];
+
+        match self.offsets.read_exact(&mut buf) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+
+        let end = u64::from_le_bytes(buf);
+        let mut term = vec![
+            0;
+            end.checked_sub(self.offset)
+                .ok_or_else(|| anyhow!("corrupt offsets"))? as usize
+        ];
+        self.terms.read_exact(&mut term)?;
+        self.offset = end;
+
+        self.doc_freqs.read_exact(&mut buf)?;
+
+        Ok(Some((term, u64::from_le_bytes(buf))))
+    }
+}
+
+impl Iterator for TermReader {
+    type Item = Result<(Vec<u8>, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_term().transpose()
+    }
+}
+
+/// The text fields with the names, or all searchable text fields if there are no names.
+pub fn fields(names: &[String]) -> Result<Vec<TextField>> {
+    if names.is_empty() {
+        return Ok(Field::all()
+            .filter(|field| field.is_searchable())
+            .filter_map(Field::as_text)
+            .collect());
+    }
+
+    names
+        .iter()
+        .map(|name| {
+            Field::all()
+                .filter_map(Field::as_text)
+                .find(|field| field.name() == name)
+                .ok_or_else(|| anyhow!("unknown text field: {name}"))
+        })
+        .collect()
+}
+
+/// Merge the term dictionaries of the segments and call `f` with each term of the
+/// field and its document frequency. The terms are visited in sorted order.
+pub fn for_each_term(
+    searcher: &tantivy::Searcher,
+    field: TextField,
+    mut f: impl FnMut(&[u8], u64) -> Result<()>,
+) -> Result<()> {
+    let tv_field = searcher.schema().get_field(field.name()).unwrap();
+
+    let inverted_indexes = searcher
+        .segment_readers()
+        .iter()
+        .map(|segment_reader| segment_reader.inverted_index(tv_field))
+        .collect::<tantivy::Result<Vec<_>>>()?;
+
+    let mut segments = Vec::with_capacity(inverted_indexes.len());
+    for inverted_index in &inverted_indexes {
+        let mut segment = SegmentTerms {
+            stream: inverted_index.terms().stream()?,
+            is_finished: false,
+        };
+        segment.advance();
+        segments.push(segment);
+    }
+
+    let mut term = Vec::new();
+
+    loop {
+        let Some(min) = segments
+            .iter()
+            .filter(|segment| !segment.is_finished)
+            .map(|segment| segment.stream.key())
+            .min()
+        else {
+            break;
+        };
+
+        term.clear();
+        term.extend_from_slice(min);
+
+        let mut doc_freq = 0;
+
+        for segment in segments
+            .iter_mut()
+            .filter(|segment| !segment.is_finished && segment.stream.key() == term.as_slice())
+        {
+            doc_freq += segment.stream.value().doc_freq as u64;
+            segment.advance();
+        }
+
+        f(&term, doc_freq)?;
+    }
+
+    Ok(())
+}
+
+/// Number of tokens in the field across all documents.
+pub fn total_num_tokens(searcher: &tantivy::Searcher, field: TextField) -> Result<u64> {
+    let tv_field = searcher.schema().get_field(field.name()).unwrap();
+    let mut total = 0;
+
+    for segment_reader in searcher.segment_readers() {
+        total += segment_reader.inverted_index(tv_field)?.total_num_tokens();
+    }
+
+    Ok(total)
+}
+
+/// Merge the term dictionaries of the segments and write the terms that are
+/// in at least `min_doc_freq` documents to the folder.
+pub fn export_field(
+    searcher: &tantivy::Searcher,
+    field: TextField,
+    folder: &Path,
+    min_doc_freq: u64,
+) -> Result<FieldStats> {
+    let mut stats = FieldStats {
+        total_num_tokens: total_num_tokens(searcher, field)?,
+        ..Default::default()
+    };
+
+    let mut writer = ColumnWriter::create(folder)?;
+
+    for_each_term(searcher, field, |term, doc_freq| {
+        if doc_freq >= min_doc_freq {
+            writer.push(term, doc_freq)?;
+            stats.num_terms += 1;
+            stats.sum_doc_freq += doc_freq;
+        }
+
+        Ok(())
+    })?;
+
+    writer.finish()?;
+
+    Ok(stats)
+}
+
+fn export_shard(
+    shard: &TermExportShardConfig,
+    fields: &[TextField],
+    config: &TermExportConfig,
+) -> Result<ShardStats> {
+    let index = InvertedIndex::open(&shard.index_path)?;
+    let searcher = index.tv_searcher();
+    let folder = Path::new(&config.output_path).join(shard.id.as_u64().to_string());
+
+    let fields = fields
+        .par_iter()
+        .map(|field| {
+            let stats = export_field(
+                &searcher,
+                *field,
+                &folder.join(field.name()),
+                config.min_doc_freq,
+            )?;
+
+            Ok((field.name().to_string(), stats))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+
+    let stats = ShardStats {
+        num_docs: searcher.num_docs(),
+        fields,
+    };
+
+    fs::write(
+        folder.join("stats.json"),
+        serde_json::to_string_pretty(&stats)?,
+    )?;
+
+    tracing::info!(
+        "exported {} fields of shard {}",
+        stats.fields.len(),
+        shard.id.as_u64()
+    );
+
+    Ok(stats)
+}
+
+pub fn run(config: &TermExportConfig) -> Result<()> {
+    let fields = fields(&config.fields)?;
+
+    config
+        .shards
+        .par_iter()
+        .map(|shard| export_shard(shard, &fields, config))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{index::Index, searcher::ShardId, webpage::Webpage};
+
+    use super::*;
+
+    fn page(title: &str, url: &str) -> Webpage {
+        Webpage::new(
+            &format!(
+                r#"
+            <html>
+                <head>
+                    <title>{title}</title>
+                </head>
+                <body>
+                    {}
+                </body>
+            </html>
+            "#,
+                crate::rand_words(100)
+            ),
+            url,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn merges_segments() {
+        let mut index = Index::temporary().unwrap();
+
+        index
+            .insert(page("Test website", "https://a.com/"))
+            .unwrap();
+        index.commit().unwrap();
+
+        index
+            .insert(page("Test test website", "https://b.com/"))
+            .unwrap();
+        index
+            .insert(page("Another page", "https://c.com/"))
+            .unwrap();
+        index.commit().unwrap();
+
+        let output = crate::gen_temp_path();
+        let config = TermExportConfig {
+            shards: vec![TermExportShardConfig {
+                id: ShardId::new(0),
+                index_path: index.inverted_index.path.clone(),
+            }],
+            output_path: output.to_str().unwrap().to_string(),
+            fields: vec!["title".to_string()],
+            min_doc_freq: 1,
+        };
+        run(&config).unwrap();
+
+        let terms: BTreeMap<_, _> = TermReader::open(output.join("0").join("title"))
+            .unwrap()
+            .map(|res| {
+                let (term, doc_freq) = res.unwrap();
+                (String::from_utf8(term).unwrap(), doc_freq)
+            })
+            .collect();
+
+        assert_eq!(terms.get("test"), Some(&2));
+        assert_eq!(terms.get("website"), Some(&2));
+        assert_eq!(terms.get("another"), Some(&1));
+
+        let stats: ShardStats =
+            serde_json::from_str(&fs::read_to_string(output.join("0").join("stats.json")).unwrap())
+                .unwrap();
+
+        assert_eq!(stats.num_docs, 3);
+        assert_eq!(stats.fields["title"].num_terms, terms.len() as u64);
+        assert_eq!(
+            stats.fields["title"].sum_doc_freq,
+            terms.values().sum::<u64>()
+        );
+    }
+
+    #[test]
+    fn unknown_field() {
+        assert!(fields(&["not_a_field".to_string()]).is_err());
+        assert!(!fields(&[]).unwrap().is_empty());
+    }
+}
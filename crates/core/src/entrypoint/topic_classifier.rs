@@ -0,0 +1,75 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use rand::seq::SliceRandom;
+use tracing::info;
+
+use crate::{human_website_annotations, webpage::topic_classifier, Result};
+use std::path::Path;
+
+const TEST_SIZE: f64 = 0.2;
+
+/// Train the classifier on a csv dataset with `label,text` rows and/or
+/// the descriptions of the hosts in the DMOZ annotations.
+pub fn train<P: AsRef<Path>>(dataset: Option<P>, annotations: Option<P>, output: P) -> Result<()> {
+    let mut datapoints = Vec::new();
+
+    if let Some(dataset) = dataset {
+        if !dataset.as_ref().exists() {
+            return Err(anyhow::anyhow!(
+                "dataset path {:?} does not exist",
+                dataset.as_ref()
+            ));
+        }
+
+        datapoints.extend(topic_classifier::load_dataset(dataset)?);
+    }
+
+    if let Some(annotations) = annotations {
+        let mapper = human_website_annotations::Mapper::open(annotations)?;
+        datapoints.extend(topic_classifier::dataset_from_annotations(&mapper));
+    }
+
+    if datapoints.is_empty() {
+        return Err(anyhow::anyhow!("dataset is empty"));
+    }
+
+    datapoints.shuffle(&mut rand::thread_rng());
+
+    let test_size = (datapoints.len() as f64 * TEST_SIZE) as usize;
+    let test_set = datapoints.split_off(datapoints.len() - test_size);
+
+    let mut model = topic_classifier::Model::new();
+    model.fit(&datapoints);
+    let evaluation = model.evaluate(&test_set);
+
+    info!("accuracy: {}", evaluation.accuracy);
+
+    model.save(output)?;
+
+    Ok(())
+}
+
+pub fn predict<P: AsRef<Path>>(model: P, text: &str) -> Result<()> {
+    let model = topic_classifier::Model::open(model)?;
+    let pred = model.predict_text(text);
+
+    for (topic, weight) in pred.top(topic_classifier::NUM_TOPICS) {
+        info!("{}: {:.3}", topic, weight);
+    }
+
+    Ok(())
+}
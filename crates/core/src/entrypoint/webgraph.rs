@@ -15,11 +15,16 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 use crate::{
     config::WarcSource,
-    config::{self, WebgraphConstructConfig},
+    config::{self, WebgraphCompactionConfig, WebgraphConstructConfig},
     entrypoint::download_all_warc_files,
     mapreduce::Worker,
-    webgraph::{self, Node, WebgraphWriter},
-    webpage::{url_ext::UrlExt, Html},
+    webgraph::{
+        self,
+        compaction::{self, NodeIdMapping},
+        edge_weight::{EdgeProperties, EdgeWeights},
+        Node, WebgraphBuilder, WebgraphWriter,
+    },
+    webpage::{url_ext::UrlExt, Html, Link},
     Result,
 };
 use itertools::Itertools;
@@ -85,9 +90,53 @@ pub fn open_page_graph_writer<P: AsRef<Path>>(path: P) -> webgraph::WebgraphWrit
 pub struct WebgraphWorker {
     pub host_graph: webgraph::WebgraphWriter,
     pub page_graph: webgraph::WebgraphWriter,
+    pub edge_weights: EdgeWeights,
 }
 
 impl WebgraphWorker {
+    /// Insert the link with its weight into the page graph, and into the host graph if it is between different domains.
+    pub fn insert_link(&mut self, mut link: Link, weight: f32) {
+        let source = link.source.clone();
+        let destination = link.destination.clone();
+        link.text = link.text.chars().take(128).collect();
+
+        trace!("inserting link {:?}", link);
+        let mut source = Node::from(source);
+
+        let mut destination = Node::from(destination);
+
+        self.page_graph.insert_with_weight(
+            source.clone(),
+            destination.clone(),
+            link.text.clone(),
+            weight,
+        );
+
+        source = source.into_host();
+        destination = destination.into_host();
+
+        let dest_domain = link.destination.root_domain();
+        let source_domain = link.source.root_domain();
+        if dest_domain.is_some() && source_domain.is_some() && dest_domain != source_domain {
+            self.host_graph
+                .insert_with_weight(source, destination, link.text, weight);
+        }
+    }
+
+    /// Insert the links of a page in the order they appear on the page.
+    /// Links with a weight of 0 are left out of the graphs.
+    pub fn insert_links(&mut self, links: impl IntoIterator<Item = Link>) {
+        for (position, link) in links.into_iter().enumerate() {
+            let weight = self
+                .edge_weights
+                .weight(&EdgeProperties::from_link(&link, position));
+
+            if weight > 0.0 {
+                self.insert_link(link, weight as f32);
+            }
+        }
+    }
+
     pub fn process_job(&mut self, job: &Job) {
         let name = job.warc_paths.first().unwrap().split('/').last().unwrap();
 
@@ -109,35 +158,12 @@ impl WebgraphWorker {
                         }
                     };
 
-                for mut link in webpage
-                    .anchor_links()
-                    .into_iter()
-                    .filter(|link| matches!(link.destination.scheme(), "http" | "https"))
-                {
-                    let source = link.source.clone();
-                    let destination = link.destination.clone();
-                    link.text = link.text.chars().take(128).collect();
-
-                    trace!("inserting link {:?}", link);
-                    let mut source = Node::from(source);
-
-                    let mut destination = Node::from(destination);
-
-                    self.page_graph
-                        .insert(source.clone(), destination.clone(), link.text.clone());
-
-                    source = source.into_host();
-                    destination = destination.into_host();
-
-                    let dest_domain = link.destination.root_domain();
-                    let source_domain = link.source.root_domain();
-                    if dest_domain.is_some()
-                        && source_domain.is_some()
-                        && dest_domain != source_domain
-                    {
-                        self.host_graph.insert(source, destination, link.text);
-                    }
-                }
+                self.insert_links(
+                    webpage
+                        .anchor_links()
+                        .into_iter()
+                        .filter(|link| matches!(link.destination.scheme(), "http" | "https")),
+                );
             }
 
             self.host_graph.commit();
@@ -195,6 +221,7 @@ impl Webgraph {
             let mut worker = WebgraphWorker {
                 host_graph: open_host_graph_writer(host_path),
                 page_graph: open_page_graph_writer(page_path),
+                edge_weights: EdgeWeights::from(config.edge_weights.clone()),
             };
 
             let jobs = jobs.clone();
@@ -231,4 +258,28 @@ impl Webgraph {
 
         Ok(())
     }
+
+    /// Renumber the nodes of a graph into a dense range and rewrite the
+    /// signal store that was computed from the graph to use the new ids.
+    pub fn compact(config: &WebgraphCompactionConfig) -> Result<()> {
+        let mapping = if Path::new(&config.mapping_path).exists() {
+            info!("using existing node id mapping {}", config.mapping_path);
+            NodeIdMapping::open(&config.mapping_path)?
+        } else {
+            let graph = WebgraphBuilder::new(&config.graph_path).open();
+            let mapping = NodeIdMapping::build(&graph);
+            mapping.save(&config.mapping_path)?;
+            mapping
+        };
+
+        info!("mapping contains {} nodes", mapping.len());
+
+        if let Some(store) = &config.signal_store {
+            let num_rows =
+                compaction::remap_signal_store(&mapping, &store.input_path, &store.output_path)?;
+            info!("remapped {} rows in {}", num_rows, store.input_path);
+        }
+
+        Ok(())
+    }
 }
@@ -16,7 +16,10 @@
 
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
+use futures::stream::BoxStream;
+use futures::StreamExt;
 use itertools::Itertools;
 use serde::Deserialize;
 use serde::Serialize;
@@ -31,14 +34,18 @@ use crate::distributed::member::Member;
 use crate::distributed::member::Service;
 use crate::distributed::sonic;
 use crate::distributed::sonic::service::Message;
+use crate::distributed::sonic::service::StreamingMessage;
+use crate::object_store::Snapshots;
 use crate::ranking::inbound_similarity::InboundSimilarity;
 use crate::searcher::DistributedSearcher;
 use crate::searcher::SearchClient;
 use crate::similar_hosts::SimilarHostsFinder;
 use crate::sonic_service;
+use crate::webgraph::sampling;
 use crate::webgraph::Compression;
 use crate::webgraph::FullEdge;
 use crate::webgraph::Node;
+use crate::webgraph::NodeID;
 use crate::webgraph::Webgraph;
 use crate::webgraph::WebgraphBuilder;
 use crate::Result;
@@ -51,7 +58,17 @@ pub struct ScoredHost {
     pub description: Option<String>,
 }
 
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ScoredNode {
+    pub node: Node,
+    pub score: f64,
+}
+
 const MAX_HOSTS: usize = 20;
+const MAX_SAMPLES: usize = 10_000;
+const MAX_WALK_STEPS: usize = 1_000_000;
+/// The links of a node are streamed to the client in chunks of this many edges.
+const EDGE_CHUNK_SIZE: usize = 1_024;
 
 pub struct WebGraphService {
     granularity: WebgraphGranularity,
@@ -62,9 +79,48 @@ pub struct WebGraphService {
 
 sonic_service!(
     WebGraphService,
-    [SimilarHosts, Knows, IngoingLinks, OutgoingLinks]
+    [SimilarHosts, Knows, SampleNodes, SampleEdges, RandomWalk],
+    streaming = [IngoingLinks, OutgoingLinks],
+    compression = Some(crate::distributed::sonic::compression::Compression::lz4()),
+    // the links became streaming messages, which changed the order of the messages
+    schema_version = 1,
+    min_schema_version = 1
 );
 
+impl WebGraphService {
+    fn node(&self, node: Node) -> Node {
+        match self.granularity {
+            WebgraphGranularity::Host => node.into_host(),
+            WebgraphGranularity::Page => node,
+        }
+    }
+
+    /// Stream the edges in chunks of [`EDGE_CHUNK_SIZE`] edges.
+    fn edge_chunks(edges: Vec<FullEdge>) -> BoxStream<'static, sonic::Result<Vec<FullEdge>>> {
+        let mut edges = edges.into_iter();
+
+        futures::stream::iter(std::iter::from_fn(move || {
+            let chunk: Vec<_> = edges.by_ref().take(EDGE_CHUNK_SIZE).collect();
+            (!chunk.is_empty()).then_some(Ok(chunk))
+        }))
+        .boxed()
+    }
+
+    /// Run a job like sampling on the blocking pool since it performs many
+    /// lookups in the graph.
+    async fn sample<F, R>(&self, f: F) -> sonic::Result<R>
+    where
+        F: FnOnce(&Webgraph) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let graph = Arc::clone(&self.graph);
+
+        tokio::task::spawn_blocking(move || f(&graph))
+            .await
+            .map_err(|e| sonic::Error::Other(e.into()))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimilarHosts {
     pub hosts: Vec<String>,
@@ -79,7 +135,11 @@ pub struct Knows {
 impl Message<WebGraphService> for SimilarHosts {
     type Response = Vec<ScoredHost>;
 
-    async fn handle(self, server: &WebGraphService) -> sonic::Result<Self::Response> {
+    async fn handle(
+        self,
+        server: &WebGraphService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
         if server.similar_hosts_finder.is_none() {
             return Ok(vec![]);
         }
@@ -120,7 +180,11 @@ impl Message<WebGraphService> for SimilarHosts {
 impl Message<WebGraphService> for Knows {
     type Response = Option<Node>;
 
-    async fn handle(mut self, server: &WebGraphService) -> sonic::Result<Self::Response> {
+    async fn handle(
+        mut self,
+        server: &WebGraphService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
         if let Some(suf) = self.host.strip_prefix("http://") {
             self.host = suf.to_string();
         }
@@ -129,8 +193,10 @@ impl Message<WebGraphService> for Knows {
             self.host = suf.to_string();
         }
 
-        let url = Url::parse(&("http://".to_string() + self.host.as_str()))
-            .map_err(|_| sonic::Error::BadRequest)?;
+        // an invalid host is unknown instead of an error, so it does not fail a batch of lookups
+        let Ok(url) = Url::parse(&("http://".to_string() + self.host.as_str())) else {
+            return Ok(None);
+        };
 
         let node = Node::from(url).into_host();
 
@@ -152,17 +218,20 @@ pub struct IngoingLinks {
     pub node: Node,
 }
 
-impl Message<WebGraphService> for IngoingLinks {
-    type Response = Vec<FullEdge>;
+impl StreamingMessage<WebGraphService> for IngoingLinks {
+    type Item = Vec<FullEdge>;
 
-    async fn handle(self, server: &WebGraphService) -> sonic::Result<Self::Response> {
-        match server.granularity {
-            WebgraphGranularity::Host => {
-                let node = self.node.into_host();
-                Ok(server.graph.ingoing_edges(node))
-            }
-            WebgraphGranularity::Page => Ok(server.graph.ingoing_edges(self.node)),
-        }
+    async fn handle(
+        self,
+        server: &WebGraphService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<BoxStream<'_, sonic::Result<Self::Item>>> {
+        let node = server.node(self.node);
+        let edges = server
+            .sample(move |graph| graph.ingoing_edges(node))
+            .await?;
+
+        Ok(WebGraphService::edge_chunks(edges))
     }
 }
 
@@ -171,17 +240,137 @@ pub struct OutgoingLinks {
     pub node: Node,
 }
 
-impl Message<WebGraphService> for OutgoingLinks {
+impl StreamingMessage<WebGraphService> for OutgoingLinks {
+    type Item = Vec<FullEdge>;
+
+    async fn handle(
+        self,
+        server: &WebGraphService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<BoxStream<'_, sonic::Result<Self::Item>>> {
+        let node = server.node(self.node);
+        let edges = server
+            .sample(move |graph| graph.outgoing_edges(node))
+            .await?;
+
+        Ok(WebGraphService::edge_chunks(edges))
+    }
+}
+
+/// Sample nodes uniformly at random with replacement.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SampleNodes {
+    pub num: usize,
+}
+
+impl Message<WebGraphService> for SampleNodes {
+    type Response = Vec<Node>;
+
+    async fn handle(
+        self,
+        server: &WebGraphService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        let num = self.num.min(MAX_SAMPLES);
+
+        server
+            .sample(move |graph| {
+                sampling::random_nodes(graph, num, &mut rand::thread_rng())
+                    .into_iter()
+                    .filter_map(|id| graph.id2node(&id))
+                    .collect()
+            })
+            .await
+    }
+}
+
+/// Sample edges uniformly at random with replacement. The sampled edges
+/// do not have labels.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SampleEdges {
+    pub num: usize,
+}
+
+impl Message<WebGraphService> for SampleEdges {
     type Response = Vec<FullEdge>;
 
-    async fn handle(self, server: &WebGraphService) -> sonic::Result<Self::Response> {
-        match server.granularity {
-            WebgraphGranularity::Host => {
-                let node = self.node.into_host();
-                Ok(server.graph.outgoing_edges(node))
-            }
-            WebgraphGranularity::Page => Ok(server.graph.outgoing_edges(self.node)),
+    async fn handle(
+        self,
+        server: &WebGraphService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        let num = self.num.min(MAX_SAMPLES);
+
+        server
+            .sample(move |graph| {
+                sampling::random_edges(graph, num, &mut rand::thread_rng())
+                    .into_iter()
+                    .filter_map(|(from, to)| {
+                        Some(FullEdge {
+                            from: graph.id2node(&from)?,
+                            to: graph.id2node(&to)?,
+                            label: String::new(),
+                        })
+                    })
+                    .collect()
+            })
+            .await
+    }
+}
+
+/// Random walk with restart to the source nodes. Returns the `top_n` most
+/// visited nodes scored by the fraction of steps spent in them.
+/// Requests with a `restart_prob` outside [0, 1] are rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RandomWalk {
+    pub sources: Vec<Node>,
+    pub restart_prob: f64,
+    pub num_steps: usize,
+    pub top_n: usize,
+}
+
+impl Message<WebGraphService> for RandomWalk {
+    type Response = Vec<ScoredNode>;
+
+    async fn handle(
+        self,
+        server: &WebGraphService,
+        _ctx: sonic::service::Context,
+    ) -> sonic::Result<Self::Response> {
+        // `gen_bool` panics on probabilities outside [0, 1], including NaN
+        if !(0.0..=1.0).contains(&self.restart_prob) {
+            return Err(sonic::Error::BadRequest);
         }
+
+        let sources: Vec<NodeID> = self
+            .sources
+            .into_iter()
+            .map(|node| server.node(node).id())
+            .collect();
+        let num_steps = self.num_steps.min(MAX_WALK_STEPS);
+        let restart_prob = self.restart_prob;
+        let top_n = self.top_n;
+
+        server
+            .sample(move |graph| {
+                sampling::random_walk_with_restart(
+                    graph,
+                    &sources,
+                    restart_prob,
+                    num_steps,
+                    &mut rand::thread_rng(),
+                )
+                .into_iter()
+                .filter_map(|(id, score)| {
+                    Some(ScoredNode {
+                        node: graph.id2node(&id)?,
+                        score,
+                    })
+                })
+                .take(top_n)
+                .collect()
+            })
+            .await
     }
 }
 
@@ -203,7 +392,13 @@ pub async fn run(config: config::WebgraphServerConfig) -> Result<()> {
         )
         .await?,
     );
-    let searcher = DistributedSearcher::new(cluster);
+    let searcher = DistributedSearcher::new(Arc::clone(&cluster));
+
+    if let Some(snapshot) = &config.snapshot {
+        Snapshots::open(snapshot)?
+            .restore_if_missing(std::path::Path::new(&config.graph_path))
+            .await?;
+    }
 
     let graph = Arc::new(
         WebgraphBuilder::new(config.graph_path)
@@ -220,6 +415,10 @@ pub async fn run(config: config::WebgraphServerConfig) -> Result<()> {
         )
     });
 
+    if let Some(tls) = &config.tls {
+        sonic::tls::configure::<WebGraphService>(tls)?;
+    }
+
     let server = WebGraphService {
         graph,
         searcher,
@@ -230,11 +429,20 @@ pub async fn run(config: config::WebgraphServerConfig) -> Result<()> {
     .await
     .unwrap();
 
+    if let Some(prometheus_host) = config.prometheus_host {
+        super::serve_metrics(&server, prometheus_host)?;
+    }
+
     info!("webgraph server is ready to accept requests on {}", addr);
 
-    loop {
-        if let Err(e) = server.accept().await {
-            tracing::error!("{:?}", e);
-        }
-    }
+    let shutdown = async move {
+        sonic::shutdown_signal().await;
+        cluster.leave().await;
+    };
+
+    server
+        .serve_until(shutdown, Duration::from_millis(config.drain_timeout_ms))
+        .await;
+
+    Ok(())
 }
@@ -0,0 +1,558 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Append-only log of the events between the crawler and the indexer.
+//!
+//! The crawler appends an event for every page it fetches. Downstream components
+//! like the extractor, the indexer and the webgraph builder consume the log and
+//! may append events of their own. Every consumer has its own offset into the log,
+//! so a consumer can be reset to an earlier offset and replay the events after a
+//! bug has been fixed in its stage, without refetching the pages.
+//!
+//! The log is split into segments of json lines named by the offset of their first
+//! event. The consumer offsets are stored next to the segments in `offsets.json`.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::EventLogConfig, security::SecurityProperties, warc::PayloadType, Result};
+
+const SEGMENT_EXTENSION: &str = "jsonl";
+const OFFSETS_FILE: &str = "offsets.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// The crawler fetched the page.
+    Fetched {
+        url: String,
+        status_code: u16,
+        payload_type: PayloadType,
+        body: String,
+        fetch_time_ms: u64,
+        security: Option<SecurityProperties>,
+    },
+    /// The page was parsed by the extractor.
+    Parsed { url: String, title: Option<String> },
+    /// The links extracted from the page.
+    Extracted {
+        url: String,
+        links: Vec<ExtractedLink>,
+    },
+    /// The page was inserted into the index.
+    Indexed { url: String },
+    /// The page should be removed from the downstream stores. The indexer deletes the
+    /// page, the webgraph consumer removes its outgoing links from the page graph and
+    /// the signal store consumer removes its signals.
+    Deleted { url: String },
+}
+
+impl Event {
+    pub fn url(&self) -> &str {
+        match self {
+            Event::Fetched { url, .. }
+            | Event::Parsed { url, .. }
+            | Event::Extracted { url, .. }
+            | Event::Indexed { url }
+            | Event::Deleted { url } => url,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtractedLink {
+    pub destination: String,
+    pub text: String,
+    #[serde(default)]
+    pub rel: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub offset: u64,
+    pub timestamp: DateTime<Utc>,
+    pub event: Event,
+}
+
+/// A downstream component that processes the events of the log.
+pub trait Consumer {
+    /// The name the offset of the consumer is stored under.
+    fn name(&self) -> &str;
+
+    /// Process a single event. New events can be appended to `log`.
+    fn handle(&mut self, entry: &LogEntry, log: &EventLog) -> Result<()>;
+
+    /// Persist everything handled so far. The offset of the consumer is only
+    /// advanced after this returns, so events are processed at least once.
+    fn commit(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn segment_path(dir: &Path, base_offset: u64) -> PathBuf {
+    dir.join(format!("{base_offset:020}.{SEGMENT_EXTENSION}"))
+}
+
+/// The base offsets of the segments in the log, sorted ascending.
+fn segments(dir: &Path) -> Result<Vec<u64>> {
+    let mut segments = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+            continue;
+        }
+
+        if let Some(base_offset) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+        {
+            segments.push(base_offset);
+        }
+    }
+
+    segments.sort_unstable();
+
+    Ok(segments)
+}
+
+fn read_segment(path: &Path) -> Result<impl Iterator<Item = Result<LogEntry>>> {
+    let file = File::open(path)?;
+
+    Ok(BufReader::new(file)
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?)))
+}
+
+/// Cut off a partial line at the end of the segment, which is left behind if the process
+/// crashed while an event was appended. The event was never acknowledged, so it is dropped.
+fn truncate_torn_line(path: &Path) -> Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+
+    let mut end = len;
+    let mut buf = vec![0; 64 * 1024];
+
+    while end > 0 {
+        let start = end.saturating_sub(buf.len() as u64);
+        let chunk = &mut buf[..(end - start) as usize];
+
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+
+        if let Some(pos) = chunk.iter().rposition(|byte| *byte == b'\n') {
+            end = start + pos as u64 + 1;
+            break;
+        }
+
+        end = start;
+    }
+
+    if end < len {
+        tracing::warn!(
+            "dropping {} bytes of a partial event at the end of {}",
+            len - end,
+            path.display()
+        );
+        file.set_len(end)?;
+        file.sync_data()?;
+    }
+
+    Ok(())
+}
+
+struct Tail {
+    file: File,
+    base_offset: u64,
+    next_offset: u64,
+}
+
+pub struct EventLog {
+    path: PathBuf,
+    segment_size: u64,
+    tail: Mutex<Tail>,
+    offsets: Mutex<BTreeMap<String, u64>>,
+}
+
+impl EventLog {
+    /// Open the log in the folder, creating it if it does not exist.
+    pub fn open(config: &EventLogConfig) -> Result<Self> {
+        if config.segment_size == 0 {
+            bail!("segment_size must be positive");
+        }
+
+        let path = PathBuf::from(&config.path);
+        fs::create_dir_all(&path)?;
+
+        let (base_offset, next_offset) = match segments(&path)?.last() {
+            Some(&base_offset) => {
+                let segment = segment_path(&path, base_offset);
+                truncate_torn_line(&segment)?;
+
+                let mut next_offset = base_offset;
+
+                for entry in read_segment(&segment)? {
+                    next_offset = entry?.offset + 1;
+                }
+
+                (base_offset, next_offset)
+            }
+            None => (0, 0),
+        };
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(&path, base_offset))?;
+
+        let offsets_path = path.join(OFFSETS_FILE);
+        let offsets = if offsets_path.exists() {
+            serde_json::from_str(&fs::read_to_string(offsets_path)?)?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self {
+            path,
+            segment_size: config.segment_size,
+            tail: Mutex::new(Tail {
+                file,
+                base_offset,
+                next_offset,
+            }),
+            offsets: Mutex::new(offsets),
+        })
+    }
+
+    /// Append the event and return its offset.
+    pub fn append(&self, event: Event) -> Result<u64> {
+        let mut tail = self.tail.lock().unwrap_or_else(|e| e.into_inner());
+
+        if tail.next_offset - tail.base_offset >= self.segment_size {
+            tail.file.sync_data()?;
+            tail.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(segment_path(&self.path, tail.next_offset))?;
+            tail.base_offset = tail.next_offset;
+        }
+
+        let entry = LogEntry {
+            offset: tail.next_offset,
+            timestamp: Utc::now(),
+            event,
+        };
+
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+
+        tail.file.write_all(line.as_bytes())?;
+        tail.next_offset += 1;
+
+        Ok(entry.offset)
+    }
+
+    /// Flush the appended events to disk.
+    pub fn sync(&self) -> Result<()> {
+        let tail = self.tail.lock().unwrap_or_else(|e| e.into_inner());
+        tail.file.sync_data()?;
+
+        Ok(())
+    }
+
+    /// The offset the next appended event will get.
+    pub fn next_offset(&self) -> u64 {
+        self.tail
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .next_offset
+    }
+
+    /// The events from `offset` up to the events appended when this was called.
+    pub fn read_from(&self, offset: u64) -> Result<impl Iterator<Item = Result<LogEntry>>> {
+        let end = self.next_offset();
+        let segments = segments(&self.path)?;
+
+        // the segment containing `offset` is the last one starting at or before it.
+        let first = segments
+            .iter()
+            .rposition(|base_offset| *base_offset <= offset)
+            .unwrap_or(0);
+
+        let dir = self.path.clone();
+
+        Ok(segments
+            .into_iter()
+            .skip(first)
+            .flat_map(move |base_offset| {
+                let entries: Box<dyn Iterator<Item = Result<LogEntry>>> =
+                    match read_segment(&segment_path(&dir, base_offset)) {
+                        Ok(entries) => Box::new(entries),
+                        Err(err) => Box::new(std::iter::once(Err(err))),
+                    };
+
+                entries
+            })
+            .filter(move |entry| match entry {
+                Ok(entry) => entry.offset >= offset,
+                Err(_) => true,
+            })
+            .take(end.saturating_sub(offset) as usize))
+    }
+
+    /// The offset of the next event the consumer will process.
+    pub fn offset(&self, consumer: &str) -> u64 {
+        self.offsets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(consumer)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// The offsets of all consumers that have processed events.
+    pub fn offsets(&self) -> BTreeMap<String, u64> {
+        self.offsets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn store_offset(&self, consumer: &str, offset: u64) -> Result<()> {
+        let mut offsets = self.offsets.lock().unwrap_or_else(|e| e.into_inner());
+        offsets.insert(consumer.to_string(), offset);
+
+        // write to a temporary file first so a crash never leaves a partial file.
+        let tmp = self.path.join(format!("{OFFSETS_FILE}.tmp"));
+        fs::write(&tmp, serde_json::to_string_pretty(&*offsets)?)?;
+        fs::rename(tmp, self.path.join(OFFSETS_FILE))?;
+
+        Ok(())
+    }
+
+    /// Move the offset of the consumer, so the events from `offset` are
+    /// processed again the next time the consumer runs.
+    pub fn replay(&self, consumer: &str, offset: u64) -> Result<()> {
+        let next_offset = self.next_offset();
+
+        if offset > next_offset {
+            return Err(anyhow!(
+                "offset {offset} is after the end of the log ({next_offset})"
+            ));
+        }
+
+        self.store_offset(consumer, offset)
+    }
+
+    /// Process the events the consumer has not yet processed. The consumer is
+    /// committed and its offset is stored after every `batch_size` events.
+    /// Returns the number of processed events.
+    pub fn consume<C: Consumer>(&self, consumer: &mut C, batch_size: usize) -> Result<u64> {
+        let mut offset = self.offset(consumer.name());
+        let mut num_processed = 0;
+        let mut num_uncommitted = 0;
+
+        for entry in self.read_from(offset)? {
+            let entry = entry?;
+
+            consumer.handle(&entry, self)?;
+
+            offset = entry.offset + 1;
+            num_processed += 1;
+            num_uncommitted += 1;
+
+            if num_uncommitted >= batch_size.max(1) {
+                consumer.commit()?;
+                self.store_offset(consumer.name(), offset)?;
+                num_uncommitted = 0;
+            }
+        }
+
+        if num_uncommitted > 0 {
+            consumer.commit()?;
+            self.store_offset(consumer.name(), offset)?;
+        }
+
+        Ok(num_processed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(segment_size: u64) -> EventLogConfig {
+        EventLogConfig {
+            path: crate::gen_temp_path().to_str().unwrap().to_string(),
+            segment_size,
+        }
+    }
+
+    fn deleted(url: &str) -> Event {
+        Event::Deleted {
+            url: url.to_string(),
+        }
+    }
+
+    #[derive(Default)]
+    struct Collector {
+        pending: Vec<String>,
+        committed: Vec<String>,
+    }
+
+    impl Consumer for Collector {
+        fn name(&self) -> &str {
+            "collector"
+        }
+
+        fn handle(&mut self, entry: &LogEntry, _: &EventLog) -> Result<()> {
+            self.pending.push(entry.event.url().to_string());
+            Ok(())
+        }
+
+        fn commit(&mut self) -> Result<()> {
+            self.committed.append(&mut self.pending);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn append_and_read() {
+        let config = config(2);
+
+        {
+            let log = EventLog::open(&config).unwrap();
+
+            for i in 0..5 {
+                assert_eq!(
+                    log.append(deleted(&format!("https://{i}.com/"))).unwrap(),
+                    i
+                );
+            }
+        }
+
+        assert_eq!(segments(Path::new(&config.path)).unwrap(), vec![0, 2, 4]);
+
+        // reopening continues after the last event
+        let log = EventLog::open(&config).unwrap();
+        assert_eq!(log.next_offset(), 5);
+        log.append(deleted("https://5.com/")).unwrap();
+
+        let offsets: Vec<_> = log
+            .read_from(3)
+            .unwrap()
+            .map(|entry| entry.unwrap().offset)
+            .collect();
+        assert_eq!(offsets, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn torn_line_is_dropped() {
+        let config = config(10);
+
+        {
+            let log = EventLog::open(&config).unwrap();
+            log.append(deleted("https://0.com/")).unwrap();
+            log.append(deleted("https://1.com/")).unwrap();
+        }
+
+        // a crash in the middle of an append leaves a partial line behind
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(segment_path(Path::new(&config.path), 0))
+            .unwrap();
+        file.write_all(br#"{"offset":2,"timestamp":"#).unwrap();
+        drop(file);
+
+        let log = EventLog::open(&config).unwrap();
+        assert_eq!(log.next_offset(), 2);
+        log.append(deleted("https://2.com/")).unwrap();
+
+        let urls: Vec<_> = log
+            .read_from(0)
+            .unwrap()
+            .map(|entry| entry.unwrap().event.url().to_string())
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://0.com/".to_string(),
+                "https://1.com/".to_string(),
+                "https://2.com/".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn consume_and_replay() {
+        let config = config(3);
+        let log = EventLog::open(&config).unwrap();
+
+        for i in 0..4 {
+            log.append(deleted(&format!("https://{i}.com/"))).unwrap();
+        }
+
+        let mut consumer = Collector::default();
+        assert_eq!(log.consume(&mut consumer, 3).unwrap(), 4);
+        assert_eq!(consumer.committed.len(), 4);
+        assert_eq!(log.offset("collector"), 4);
+
+        // nothing new to process
+        assert_eq!(log.consume(&mut consumer, 3).unwrap(), 0);
+
+        log.replay("collector", 2).unwrap();
+        assert!(log.replay("collector", 10).is_err());
+
+        // the offsets survive a restart
+        let log = EventLog::open(&config).unwrap();
+        assert_eq!(log.offset("collector"), 2);
+
+        let mut consumer = Collector::default();
+        assert_eq!(log.consume(&mut consumer, 3).unwrap(), 2);
+        assert_eq!(
+            consumer.committed,
+            vec!["https://2.com/".to_string(), "https://3.com/".to_string()]
+        );
+    }
+
+    #[test]
+    fn serialization() {
+        let event = Event::Fetched {
+            url: "https://example.com/".to_string(),
+            status_code: 200,
+            payload_type: PayloadType::Html,
+            body: "<html>\n</html>".to_string(),
+            fetch_time_ms: 100,
+            security: Some(SecurityProperties::default()),
+        };
+
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains(r#""type":"fetched""#));
+        assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+    }
+}
@@ -19,6 +19,7 @@ use url::Url;
 pub mod index;
 mod parser;
 pub mod scheduler;
+pub mod websub;
 
 pub use parser::parse;
 
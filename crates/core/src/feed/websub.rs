@@ -0,0 +1,832 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! WebSub (formerly PubSubHubbub) subscriber.
+//!
+//! Feeds that advertise a hub (`<link rel="hub">` in the feed or a `Link` header) are
+//! subscribed to when the live index checks them. The hub verifies the subscription
+//! with a `GET` to our callback and afterwards pushes the updated feed with a `POST`
+//! every time the publisher has new or updated pages. The links in the pushed feed
+//! are added directly to the ingest queue of the live index instead of waiting for the
+//! next time the feed is polled.
+//!
+//! Each subscription has its own callback url with a random id and its own secret, so pushes
+//! can be attributed to the subscription and their signatures verified. Pushes are rate
+//! limited per publisher. A renewal is kept next to the active subscription until the hub
+//! has verified it, so pushes signed with the current secret are accepted in the meantime.
+//!
+//! The hubs are advertised by the feeds, so they are only contacted if they resolve to
+//! public addresses.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use axum::{
+    body::Bytes,
+    extract,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use quick_xml::events::Event;
+use ring::{hmac, rand::SecureRandom};
+use tokio::net::TcpListener;
+use url::Url;
+
+use crate::{
+    config::WebSubConfig,
+    crawler::public_addr::{is_fetchable, PublicResolver},
+    ingest_queue::IngestQueue,
+    kv::{rocksdb_store::RocksDbStore, Kv},
+};
+
+use super::{parse, scheduler::Domain, FeedKind};
+
+const SIGNATURE_HEADER: &str = "x-hub-signature";
+
+/// Pushes are rejected while this many batches of urls are waiting to be crawled, so the hubs retry later.
+const MAX_QUEUED_BATCHES: usize = 1024;
+
+/// Don't ask the hub again for a subscription that it hasn't verified within this time.
+const PENDING_TIMEOUT_SECS: u64 = 60 * 60;
+
+/// Subscriptions are renewed when less than this fraction of the lease is left.
+const RENEW_FRACTION: u64 = 10;
+
+/// Leases granted by the hubs are capped to this length.
+const MAX_LEASE_SECS: u64 = 30 * 24 * 60 * 60;
+
+const HUB_TIMEOUT_SECS: u64 = 30;
+
+const RATE_LIMIT_WINDOW_SECS: u64 = 60 * 60;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// A new random id for a subscription. It is part of the callback url, so it must not be
+/// guessable from the topic, or anyone could send pushes and verifications for the topic.
+fn new_subscription_id() -> Result<String> {
+    let mut id = [0u8; 16];
+    ring::rand::SystemRandom::new()
+        .fill(&mut id)
+        .map_err(|_| anyhow!("failed to generate subscription id"))?;
+
+    Ok(hex(&id))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Advertisement {
+    pub hub: Url,
+    /// The canonical url of the feed that should be used as the topic.
+    pub topic: Option<Url>,
+}
+
+/// Parse a `Link` header like `<https://hub.example.com/>; rel="hub", <https://example.com/feed>; rel="self"`.
+fn parse_link_header(header: &str) -> Vec<(Url, String)> {
+    header
+        .split(',')
+        .filter_map(|link| {
+            let (url, params) = link.split_once(';')?;
+            let url = Url::parse(url.trim().trim_start_matches('<').trim_end_matches('>')).ok()?;
+
+            let rel = params.split(';').find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                (key.trim() == "rel").then(|| value.trim().trim_matches('"').to_string())
+            })?;
+
+            Some((url, rel))
+        })
+        .collect()
+}
+
+/// The `link` elements of the feed itself, i.e. not of its entries or items.
+fn feed_links(feed: &str) -> Vec<(Url, String)> {
+    let mut reader = quick_xml::Reader::from_str(feed);
+    let mut buf = Vec::new();
+    let mut links = Vec::new();
+    let mut depth_in_entry = 0;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) if matches!(e.local_name().as_ref(), b"entry" | b"item") => {
+                depth_in_entry += 1;
+            }
+            Ok(Event::End(ref e)) if matches!(e.local_name().as_ref(), b"entry" | b"item") => {
+                depth_in_entry -= 1;
+            }
+            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e))
+                if depth_in_entry == 0 && e.local_name().as_ref() == b"link" =>
+            {
+                let mut href = None;
+                let mut rel = None;
+
+                for attr in e.attributes().flatten() {
+                    let Ok(value) = attr.unescape_value() else {
+                        continue;
+                    };
+
+                    match attr.key.as_ref() {
+                        b"href" => href = Url::parse(&value).ok(),
+                        b"rel" => rel = Some(value.to_string()),
+                        _ => {}
+                    }
+                }
+
+                if let (Some(href), Some(rel)) = (href, rel) {
+                    links.push((href, rel));
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+
+        buf.clear();
+    }
+
+    links
+}
+
+/// The hub advertised by the feed, either in the feed itself or in the `Link` header of the response.
+pub fn discover(feed: &str, link_header: Option<&str>) -> Option<Advertisement> {
+    let links: Vec<_> = link_header
+        .map(parse_link_header)
+        .unwrap_or_default()
+        .into_iter()
+        .chain(feed_links(feed))
+        .collect();
+
+    let hub = links
+        .iter()
+        .find(|(_, rel)| rel == "hub")
+        .map(|(url, _)| url.clone())?;
+    let topic = links
+        .iter()
+        .find(|(_, rel)| rel == "self")
+        .map(|(url, _)| url.clone());
+
+    Some(Advertisement { hub, topic })
+}
+
+fn sniff_kind(feed: &str) -> FeedKind {
+    if feed.contains("<feed") {
+        FeedKind::Atom
+    } else {
+        FeedKind::Rss
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Subscription {
+    pub topic: Url,
+    pub hub: Url,
+    pub secret: String,
+    /// Unix timestamp in seconds of when the subscription was requested from the hub.
+    pub requested_at: u64,
+    /// Unix timestamp in seconds of when the lease ends. `None` until the hub has verified the subscription.
+    pub expires_at: Option<u64>,
+    /// A renewal of the verified subscription that the hub has not verified yet.
+    #[serde(default)]
+    pub renewal: Option<Renewal>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Renewal {
+    pub hub: Url,
+    pub secret: String,
+    /// Unix timestamp in seconds of when the renewal was requested from the hub.
+    pub requested_at: u64,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct VerificationParams {
+    #[serde(rename = "hub.mode")]
+    pub mode: String,
+    #[serde(rename = "hub.topic")]
+    pub topic: String,
+    #[serde(rename = "hub.challenge")]
+    pub challenge: Option<String>,
+    #[serde(rename = "hub.lease_seconds")]
+    pub lease_seconds: Option<u64>,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum PushError {
+    #[error("no subscription with the id")]
+    UnknownSubscription,
+    #[error("the signature does not match the secret of the subscription")]
+    InvalidSignature,
+    #[error("the publisher has pushed too many updates")]
+    RateLimited,
+}
+
+pub struct Subscriber {
+    config: WebSubConfig,
+    /// The subscriptions by their id.
+    subscriptions: RocksDbStore<String, Subscription>,
+    /// The id of the subscription to each topic.
+    ids: Mutex<HashMap<Url, String>>,
+    client: reqwest::Client,
+    pushes: Mutex<HashMap<Domain, VecDeque<u64>>>,
+}
+
+impl Subscriber {
+    pub fn open(config: WebSubConfig, user_agent: &str) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(user_agent)
+            .timeout(Duration::from_secs(HUB_TIMEOUT_SECS))
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicResolver))
+            .build()?;
+
+        let subscriptions: RocksDbStore<String, Subscription> =
+            RocksDbStore::open(&config.subscriptions_path);
+        let ids = subscriptions
+            .iter()
+            .map(|(id, subscription)| (subscription.topic, id))
+            .collect();
+
+        Ok(Self {
+            subscriptions,
+            ids: Mutex::new(ids),
+            config,
+            client,
+            pushes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn subscription_id(&self, topic: &Url) -> Option<String> {
+        self.ids
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(topic)
+            .cloned()
+    }
+
+    fn insert(&self, id: String, subscription: Subscription) {
+        self.ids
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(subscription.topic.clone(), id.clone());
+
+        self.subscriptions.insert(id, subscription);
+        self.subscriptions.flush();
+    }
+
+    fn callback(&self, id: &str) -> String {
+        format!(
+            "{}/websub/{}",
+            self.config.callback_base_url.trim_end_matches('/'),
+            id
+        )
+    }
+
+    fn should_subscribe(&self, topic: &Url, now: u64) -> bool {
+        match self
+            .subscription_id(topic)
+            .and_then(|id| self.subscriptions.get(&id))
+        {
+            None => true,
+            Some(Subscription {
+                renewal: Some(Renewal { requested_at, .. }),
+                ..
+            }) => now.saturating_sub(requested_at) > PENDING_TIMEOUT_SECS,
+            Some(Subscription {
+                expires_at: Some(expires_at),
+                ..
+            }) => expires_at.saturating_sub(now) < self.config.lease_seconds / RENEW_FRACTION,
+            Some(Subscription {
+                expires_at: None,
+                requested_at,
+                ..
+            }) => now.saturating_sub(requested_at) > PENDING_TIMEOUT_SECS,
+        }
+    }
+
+    /// Subscribe to the feed if it advertises a hub and we are not already subscribed.
+    pub async fn subscribe_if_advertised(
+        &self,
+        feed_url: &Url,
+        feed: &str,
+        link_header: Option<&str>,
+    ) -> Result<()> {
+        let Some(advertisement) = discover(feed, link_header) else {
+            return Ok(());
+        };
+
+        let topic = advertisement.topic.unwrap_or_else(|| feed_url.clone());
+
+        if Domain::from(&topic) != Domain::from(feed_url) || !self.should_subscribe(&topic, now()) {
+            return Ok(());
+        }
+
+        self.subscribe(advertisement.hub, topic).await
+    }
+
+    /// Store the request for a subscription to the topic until the hub verifies it and return
+    /// the id and secret to send to the hub. Renewals keep the callback url and the current
+    /// secret of a verified subscription until the renewal has been verified.
+    fn request(&self, hub: &Url, topic: &Url, now: u64) -> Result<(String, String)> {
+        let mut secret = [0u8; 32];
+        ring::rand::SystemRandom::new()
+            .fill(&mut secret)
+            .map_err(|_| anyhow!("failed to generate secret"))?;
+        let secret = hex(&secret);
+
+        let existing = self
+            .subscription_id(topic)
+            .and_then(|id| Some((self.subscriptions.get(&id)?, id)));
+
+        match existing {
+            Some((mut subscription, id)) if subscription.expires_at.is_some() => {
+                subscription.renewal = Some(Renewal {
+                    hub: hub.clone(),
+                    secret: secret.clone(),
+                    requested_at: now,
+                });
+                self.insert(id.clone(), subscription);
+
+                Ok((id, secret))
+            }
+            existing => {
+                let id = match existing {
+                    Some((_, id)) => id,
+                    None => new_subscription_id()?,
+                };
+
+                self.insert(
+                    id.clone(),
+                    Subscription {
+                        topic: topic.clone(),
+                        hub: hub.clone(),
+                        secret: secret.clone(),
+                        requested_at: now,
+                        expires_at: None,
+                        renewal: None,
+                    },
+                );
+
+                Ok((id, secret))
+            }
+        }
+    }
+
+    async fn subscribe(&self, hub: Url, topic: Url) -> Result<()> {
+        if !is_fetchable(&hub) {
+            return Err(anyhow!("hub {} is not a public url", hub));
+        }
+
+        let (id, secret) = self.request(&hub, &topic, now())?;
+        let lease_seconds = self.config.lease_seconds.to_string();
+
+        let res = self
+            .client
+            .post(hub.as_str())
+            .form(&[
+                ("hub.mode", "subscribe"),
+                ("hub.topic", topic.as_str()),
+                ("hub.callback", self.callback(&id).as_str()),
+                ("hub.secret", secret.as_str()),
+                ("hub.lease_seconds", lease_seconds.as_str()),
+            ])
+            .send()
+            .await?;
+
+        if !res.status().is_success() {
+            return Err(anyhow!(
+                "hub {} rejected the subscription to {} with status {}",
+                hub,
+                topic,
+                res.status()
+            ));
+        }
+
+        tracing::debug!("requested subscription to {} from {}", topic, hub);
+
+        Ok(())
+    }
+
+    /// Verify the intent of a subscription request from the hub. Returns the body to respond with
+    /// if the request matches a subscription we requested.
+    pub fn verify_intent(&self, id: &str, params: &VerificationParams, now: u64) -> Option<String> {
+        let mut subscription = self.subscriptions.get(&id.to_string())?;
+
+        if subscription.topic.as_str() != params.topic {
+            return None;
+        }
+
+        match params.mode.as_str() {
+            "subscribe" => {
+                let challenge = params.challenge.clone()?;
+
+                let lease_seconds = params
+                    .lease_seconds
+                    .unwrap_or(self.config.lease_seconds)
+                    .min(MAX_LEASE_SECS);
+                subscription.expires_at = Some(now.saturating_add(lease_seconds));
+
+                if let Some(renewal) = subscription.renewal.take() {
+                    subscription.hub = renewal.hub;
+                    subscription.secret = renewal.secret;
+                    subscription.requested_at = renewal.requested_at;
+                }
+
+                self.insert(id.to_string(), subscription);
+
+                Some(challenge)
+            }
+            "denied" => {
+                // the hub will not deliver, so we try again after the pending timeout.
+                tracing::debug!("hub denied subscription to {}", subscription.topic);
+                Some(String::new())
+            }
+            // we never unsubscribe, so these requests were not sent on our behalf.
+            _ => None,
+        }
+    }
+
+    fn rate_limit(&self, domain: Domain, now: u64) -> Result<(), PushError> {
+        let mut pushes = self.pushes.lock().unwrap_or_else(|e| e.into_inner());
+        let timestamps = pushes.entry(domain).or_default();
+
+        while timestamps
+            .front()
+            .is_some_and(|timestamp| now.saturating_sub(*timestamp) >= RATE_LIMIT_WINDOW_SECS)
+        {
+            timestamps.pop_front();
+        }
+
+        if timestamps.len() >= self.config.max_pushes_per_hour {
+            return Err(PushError::RateLimited);
+        }
+
+        timestamps.push_back(now);
+
+        Ok(())
+    }
+
+    /// Verify and parse content pushed by the hub. Returns the links in the feed that are on
+    /// the same domain as the topic.
+    pub fn receive(
+        &self,
+        id: &str,
+        signature: Option<&str>,
+        body: &[u8],
+        now: u64,
+    ) -> Result<Vec<Url>, PushError> {
+        let subscription = self
+            .subscriptions
+            .get(&id.to_string())
+            .filter(|subscription| subscription.expires_at.is_some())
+            .ok_or(PushError::UnknownSubscription)?;
+
+        verify_signature(&subscription.secret, signature, body)?;
+
+        let domain = Domain::from(&subscription.topic);
+        self.rate_limit(domain.clone(), now)?;
+
+        let feed = String::from_utf8_lossy(body);
+        let links = parse(&feed, sniff_kind(&feed))
+            .map(|feed| feed.links)
+            .unwrap_or_default();
+
+        Ok(links
+            .into_iter()
+            .filter(|url| Domain::from(url) == domain)
+            .collect())
+    }
+}
+
+/// Verify the `X-Hub-Signature` header, e.g. `sha256=<hex digest>`.
+fn verify_signature(secret: &str, signature: Option<&str>, body: &[u8]) -> Result<(), PushError> {
+    let (method, digest) = signature
+        .and_then(|signature| signature.split_once('='))
+        .ok_or(PushError::InvalidSignature)?;
+
+    let algorithm = match method {
+        "sha1" => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+        "sha256" => hmac::HMAC_SHA256,
+        "sha384" => hmac::HMAC_SHA384,
+        "sha512" => hmac::HMAC_SHA512,
+        _ => return Err(PushError::InvalidSignature),
+    };
+
+    let digest = decode_hex(digest.trim()).ok_or(PushError::InvalidSignature)?;
+    let key = hmac::Key::new(algorithm, secret.as_bytes());
+
+    hmac::verify(&key, body, &digest).map_err(|_| PushError::InvalidSignature)
+}
+
+/// The state of the callback server.
+#[derive(Clone)]
+pub struct Callback {
+    pub subscriber: Arc<Subscriber>,
+    /// The pushed urls are added to the queue of the live index crawler.
+    pub queue: Arc<IngestQueue>,
+}
+
+async fn verify(
+    extract::State(callback): extract::State<Callback>,
+    extract::Path(id): extract::Path<String>,
+    extract::Query(params): extract::Query<VerificationParams>,
+) -> impl IntoResponse {
+    match callback.subscriber.verify_intent(&id, &params, now()) {
+        Some(body) => (StatusCode::OK, body),
+        None => (StatusCode::NOT_FOUND, String::new()),
+    }
+}
+
+async fn push(
+    extract::State(callback): extract::State<Callback>,
+    extract::Path(id): extract::Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let signature = headers
+        .get(SIGNATURE_HEADER)
+        .and_then(|signature| signature.to_str().ok());
+
+    match callback.subscriber.receive(&id, signature, &body, now()) {
+        Ok(urls) => {
+            if urls.is_empty() {
+                return StatusCode::OK;
+            }
+
+            if callback.queue.len() >= MAX_QUEUED_BATCHES {
+                // the hub retries later
+                return StatusCode::SERVICE_UNAVAILABLE;
+            }
+
+            // the hub must only get a success response once the urls are on disk
+            match callback.queue.push(urls) {
+                Ok(_) => StatusCode::OK,
+                Err(e) => {
+                    tracing::error!("failed to queue pushed urls: {:?}", e);
+                    StatusCode::SERVICE_UNAVAILABLE
+                }
+            }
+        }
+        Err(PushError::UnknownSubscription) => StatusCode::NOT_FOUND,
+        // the spec requires a success response for invalid signatures,
+        // but the content must be ignored.
+        Err(PushError::InvalidSignature) => StatusCode::OK,
+        Err(PushError::RateLimited) => StatusCode::TOO_MANY_REQUESTS,
+    }
+}
+
+pub async fn serve(host: SocketAddr, callback: Callback) -> Result<()> {
+    let app = Router::new()
+        .route("/websub/:id", get(verify).post(push))
+        .with_state(callback);
+
+    tracing::info!("websub callbacks listening on {}", host);
+    axum::serve(TcpListener::bind(&host).await?, app.into_make_service()).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscriber(max_pushes_per_hour: usize) -> Subscriber {
+        Subscriber::open(
+            WebSubConfig {
+                host: "0.0.0.0:0".parse().unwrap(),
+                callback_base_url: "https://live.example.com/".to_string(),
+                subscriptions_path: crate::gen_temp_path().to_str().unwrap().to_string(),
+                lease_seconds: 1000,
+                max_pushes_per_hour,
+            },
+            "test",
+        )
+        .unwrap()
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        format!("sha256={}", hex(hmac::sign(&key, body).as_ref()))
+    }
+
+    const ATOM: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+        <feed xmlns="http://www.w3.org/2005/Atom">
+            <link rel="hub" href="https://hub.example.org/" />
+            <link rel="self" href="https://blog.example.com/feed.xml" />
+            <entry>
+                <link rel="alternate" href="https://blog.example.com/new-post" />
+            </entry>
+            <entry>
+                <link rel="alternate" href="https://spam.example.net/post" />
+            </entry>
+        </feed>"#;
+
+    #[test]
+    fn discover_hub() {
+        assert_eq!(
+            discover(ATOM, None),
+            Some(Advertisement {
+                hub: Url::parse("https://hub.example.org/").unwrap(),
+                topic: Some(Url::parse("https://blog.example.com/feed.xml").unwrap()),
+            })
+        );
+
+        let rss = r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+            <channel>
+                <atom:link rel="hub" href="https://hub.example.org/" />
+                <item><link>https://blog.example.com/post</link></item>
+            </channel>
+        </rss>"#;
+        assert_eq!(
+            discover(rss, None).map(|ad| ad.hub),
+            Some(Url::parse("https://hub.example.org/").unwrap())
+        );
+
+        assert_eq!(
+            discover(
+                "<rss></rss>",
+                Some(
+                    r#"<https://hub.example.org/>; rel="hub", <https://blog.example.com/rss>; rel="self""#
+                )
+            ),
+            Some(Advertisement {
+                hub: Url::parse("https://hub.example.org/").unwrap(),
+                topic: Some(Url::parse("https://blog.example.com/rss").unwrap()),
+            })
+        );
+
+        assert_eq!(discover("<rss></rss>", None), None);
+    }
+
+    #[test]
+    fn verification_and_push() {
+        let subscriber = subscriber(2);
+        let topic = Url::parse("https://blog.example.com/feed.xml").unwrap();
+        let id = new_subscription_id().unwrap();
+        assert_ne!(id, new_subscription_id().unwrap());
+
+        subscriber.insert(
+            id.clone(),
+            Subscription {
+                topic: topic.clone(),
+                hub: Url::parse("https://hub.example.org/").unwrap(),
+                secret: "secret".to_string(),
+                requested_at: 0,
+                expires_at: None,
+                renewal: None,
+            },
+        );
+
+        let body = ATOM.as_bytes();
+        let signature = sign("secret", body);
+
+        // pushes are not accepted before the hub has verified the subscription
+        assert_eq!(
+            subscriber.receive(&id, Some(&signature), body, 10),
+            Err(PushError::UnknownSubscription)
+        );
+
+        let params = VerificationParams {
+            mode: "subscribe".to_string(),
+            topic: topic.to_string(),
+            challenge: Some("challenge".to_string()),
+            lease_seconds: Some(500),
+        };
+
+        assert_eq!(subscriber.verify_intent("unknown", &params, 10), None);
+        assert_eq!(
+            subscriber.verify_intent(
+                &id,
+                &VerificationParams {
+                    topic: "https://other.example.com/feed".to_string(),
+                    ..params.clone()
+                },
+                10
+            ),
+            None
+        );
+        assert_eq!(
+            subscriber.verify_intent(&id, &params, 10),
+            Some("challenge".to_string())
+        );
+        assert!(!subscriber.should_subscribe(&topic, 20));
+        assert!(subscriber.should_subscribe(&topic, 500));
+
+        assert_eq!(
+            subscriber.receive(&id, Some(&signature), body, 20),
+            Ok(vec![
+                Url::parse("https://blog.example.com/new-post").unwrap()
+            ])
+        );
+        assert_eq!(
+            subscriber.receive(&id, Some(&sign("wrong", body)), body, 20),
+            Err(PushError::InvalidSignature)
+        );
+        assert_eq!(
+            subscriber.receive(&id, None, body, 20),
+            Err(PushError::InvalidSignature)
+        );
+
+        assert!(subscriber.receive(&id, Some(&signature), body, 30).is_ok());
+        assert_eq!(
+            subscriber.receive(&id, Some(&signature), body, 40),
+            Err(PushError::RateLimited)
+        );
+        assert!(subscriber
+            .receive(&id, Some(&signature), body, 20 + RATE_LIMIT_WINDOW_SECS)
+            .is_ok());
+    }
+
+    #[test]
+    fn renewal() {
+        let subscriber = subscriber(100);
+        let hub = Url::parse("https://hub.example.org/").unwrap();
+        let topic = Url::parse("https://blog.example.com/feed.xml").unwrap();
+
+        let (id, secret) = subscriber.request(&hub, &topic, 0).unwrap();
+        let params = VerificationParams {
+            mode: "subscribe".to_string(),
+            topic: topic.to_string(),
+            challenge: Some("challenge".to_string()),
+            lease_seconds: Some(u64::MAX),
+        };
+
+        // the lease from the hub is capped
+        assert!(subscriber.verify_intent(&id, &params, 10).is_some());
+        assert_eq!(
+            subscriber.subscriptions.get(&id).unwrap().expires_at,
+            Some(10 + MAX_LEASE_SECS)
+        );
+
+        let body = ATOM.as_bytes();
+        assert!(subscriber.should_subscribe(&topic, MAX_LEASE_SECS));
+        let (renewed_id, renewed_secret) =
+            subscriber.request(&hub, &topic, MAX_LEASE_SECS).unwrap();
+        assert_eq!(renewed_id, id);
+
+        // the renewal is not requested again while the hub is verifying it
+        assert!(!subscriber.should_subscribe(&topic, MAX_LEASE_SECS + 5));
+        assert!(subscriber.should_subscribe(&topic, MAX_LEASE_SECS + PENDING_TIMEOUT_SECS + 1));
+
+        // pushes signed with the current secret are accepted until the hub verifies the renewal
+        assert!(subscriber
+            .receive(&id, Some(&sign(&secret, body)), body, 30)
+            .is_ok());
+        assert_eq!(
+            subscriber.receive(&id, Some(&sign(&renewed_secret, body)), body, 30),
+            Err(PushError::InvalidSignature)
+        );
+
+        assert!(subscriber.verify_intent(&id, &params, 40).is_some());
+        assert!(subscriber
+            .receive(&id, Some(&sign(&renewed_secret, body)), body, 50)
+            .is_ok());
+        assert_eq!(
+            subscriber.receive(&id, Some(&sign(&secret, body)), body, 50),
+            Err(PushError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn hex_roundtrip() {
+        assert_eq!(decode_hex(&hex(&[0, 15, 255])), Some(vec![0, 15, 255]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+}
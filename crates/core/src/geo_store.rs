@@ -0,0 +1,344 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Geo-indexed store of the places found in the index.
+//!
+//! Places are bucketed into cells of [`CELL_SIZE`] degrees, so the places near a point
+//! can be found by only looking at the cells that overlap the search radius.
+//! Places that are areas (cities, neighborhoods etc.) are also recorded in a gazetteer,
+//! which is used to resolve the location in local intent queries like `pizza near nørrebro`.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    webpage::{GeoCoordinates, Place},
+};
+
+/// Size of the cells in degrees. A cell is roughly 11km along the latitude.
+const CELL_SIZE: f64 = 0.1;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Categories of places that describe an area rather than a single venue.
+/// Only these are used to resolve the location of a local intent query.
+const AREA_CATEGORIES: [&str; 7] = [
+    "Place",
+    "City",
+    "AdministrativeArea",
+    "Country",
+    "State",
+    "Landform",
+    "TouristDestination",
+];
+
+/// The words that separate what the user is looking for from where, e.g. `pizza near nørrebro`.
+const LOCAL_INTENT_SEPARATORS: [&str; 3] = [" near ", " around ", " in "];
+
+type Cell = (i32, i32);
+
+fn cell(coordinates: &GeoCoordinates) -> Cell {
+    (
+        (coordinates.latitude / CELL_SIZE).floor() as i32,
+        (coordinates.longitude / CELL_SIZE).floor() as i32,
+    )
+}
+
+/// Great-circle distance in kilometers.
+pub fn haversine_km(a: &GeoCoordinates, b: &GeoCoordinates) -> f64 {
+    let lat_a = a.latitude.to_radians();
+    let lat_b = b.latitude.to_radians();
+    let d_lat = lat_b - lat_a;
+    let d_lon = (b.longitude - a.longitude).to_radians();
+
+    let h = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lon / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_KM * h.sqrt().asin()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalIntent {
+    /// What the user is looking for, e.g. `pizza`.
+    pub what: String,
+    /// The name of the location, e.g. `nørrebro`.
+    pub location: String,
+}
+
+impl LocalIntent {
+    pub fn parse(query: &str) -> Option<Self> {
+        let query = query.trim().to_lowercase();
+
+        LOCAL_INTENT_SEPARATORS.iter().find_map(|separator| {
+            let (what, location) = query.split_once(separator)?;
+            let (what, location) = (what.trim(), location.trim());
+
+            if what.is_empty() || location.is_empty() {
+                None
+            } else {
+                Some(Self {
+                    what: what.to_string(),
+                    location: location.to_string(),
+                })
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredPlace {
+    pub url: String,
+    pub place: Place,
+}
+
+impl StoredPlace {
+    /// Whether all the words of the query are in the name or categories of the place.
+    fn matches(&self, what: &str) -> bool {
+        let haystack = std::iter::once(&self.place.name)
+            .chain(self.place.categories.iter())
+            .map(|s| s.to_lowercase())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        what.split_whitespace().all(|word| haystack.contains(word))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DisplayedPlace {
+    pub url: String,
+    pub name: String,
+    pub address: Option<String>,
+    pub categories: Vec<String>,
+    pub coordinates: GeoCoordinates,
+    pub distance_km: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PlacesResult {
+    /// The location from the query as it was found in the gazetteer.
+    pub location: String,
+    /// The center of the map.
+    pub center: GeoCoordinates,
+    pub places: Vec<DisplayedPlace>,
+}
+
+pub struct GeoStore {
+    cells: RocksDbStore<Cell, Vec<StoredPlace>>,
+    gazetteer: RocksDbStore<String, GeoCoordinates>,
+}
+
+impl GeoStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            cells: RocksDbStore::open(path.as_ref().join("cells")),
+            gazetteer: RocksDbStore::open(path.as_ref().join("gazetteer")),
+        }
+    }
+
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            cells: RocksDbStore::open_read_only(path.as_ref().join("cells")),
+            gazetteer: RocksDbStore::open_read_only(path.as_ref().join("gazetteer")),
+        }
+    }
+
+    /// Insert the place found on the url. A place with the same name from the
+    /// same url is replaced, so the store can be rebuilt from newer indexes.
+    pub fn insert(&self, url: &str, place: Place) {
+        let key = cell(&place.coordinates);
+        let mut places = self.cells.get(&key).unwrap_or_default();
+
+        if place
+            .categories
+            .iter()
+            .any(|category| AREA_CATEGORIES.contains(&category.as_str()))
+        {
+            self.gazetteer
+                .insert(place.name.to_lowercase(), place.coordinates);
+        }
+
+        places.retain(|stored| stored.url != url || stored.place.name != place.name);
+        places.push(StoredPlace {
+            url: url.to_string(),
+            place,
+        });
+
+        self.cells.insert(key, places);
+    }
+
+    /// The coordinates of the named area.
+    pub fn locate(&self, name: &str) -> Option<GeoCoordinates> {
+        self.gazetteer.get(&name.trim().to_lowercase())
+    }
+
+    /// The places within the radius of the center sorted by their distance to the center.
+    pub fn nearby(
+        &self,
+        center: &GeoCoordinates,
+        radius_km: f64,
+        limit: usize,
+    ) -> Vec<(StoredPlace, f64)> {
+        let (lat_cell, lon_cell) = cell(center);
+
+        // a degree of latitude is ~111km, while a degree of longitude shrinks towards the poles.
+        let km_per_cell = 111.0 * CELL_SIZE;
+        let lat_cells = (radius_km / km_per_cell).ceil() as i32;
+        let lon_cells = (radius_km / (km_per_cell * center.latitude.to_radians().cos().max(0.01)))
+            .ceil()
+            .min(1800.0) as i32;
+
+        let mut places = Vec::new();
+
+        for lat in (lat_cell - lat_cells)..=(lat_cell + lat_cells) {
+            for lon in (lon_cell - lon_cells)..=(lon_cell + lon_cells) {
+                for stored in self.cells.get(&(lat, lon)).unwrap_or_default() {
+                    let distance = haversine_km(center, &stored.place.coordinates);
+
+                    if distance <= radius_km {
+                        places.push((stored, distance));
+                    }
+                }
+            }
+        }
+
+        places.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        places.truncate(limit);
+
+        places
+    }
+
+    /// The places matching a local intent query like `pizza near nørrebro`.
+    pub fn search(&self, query: &str, radius_km: f64, limit: usize) -> Option<PlacesResult> {
+        let intent = LocalIntent::parse(query)?;
+        let center = self.locate(&intent.location)?;
+
+        let places: Vec<_> = self
+            .nearby(&center, radius_km, usize::MAX)
+            .into_iter()
+            .filter(|(stored, _)| stored.matches(&intent.what))
+            .take(limit)
+            .map(|(stored, distance_km)| DisplayedPlace {
+                url: stored.url,
+                name: stored.place.name,
+                address: stored.place.address,
+                categories: stored.place.categories,
+                coordinates: stored.place.coordinates,
+                distance_km,
+            })
+            .collect();
+
+        if places.is_empty() {
+            None
+        } else {
+            Some(PlacesResult {
+                location: intent.location,
+                center,
+                places,
+            })
+        }
+    }
+
+    pub fn flush(&self) {
+        self.cells.flush();
+        self.gazetteer.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn place(name: &str, categories: &[&str], latitude: f64, longitude: f64) -> Place {
+        Place {
+            name: name.to_string(),
+            categories: categories.iter().map(|c| c.to_string()).collect(),
+            address: None,
+            coordinates: GeoCoordinates::new(latitude, longitude).unwrap(),
+        }
+    }
+
+    #[test]
+    fn local_intent() {
+        assert_eq!(
+            LocalIntent::parse("Pizza near Nørrebro"),
+            Some(LocalIntent {
+                what: "pizza".to_string(),
+                location: "nørrebro".to_string(),
+            })
+        );
+        assert_eq!(
+            LocalIntent::parse("coffee in copenhagen"),
+            Some(LocalIntent {
+                what: "coffee".to_string(),
+                location: "copenhagen".to_string(),
+            })
+        );
+        assert_eq!(LocalIntent::parse("near me"), None);
+        assert_eq!(LocalIntent::parse("best pizza"), None);
+    }
+
+    #[test]
+    fn distance() {
+        let copenhagen = GeoCoordinates::new(55.6761, 12.5683).unwrap();
+        let aarhus = GeoCoordinates::new(56.1629, 10.2039).unwrap();
+
+        let distance = haversine_km(&copenhagen, &aarhus);
+        assert!((distance - 157.0).abs() < 2.0, "{distance}");
+        assert_eq!(haversine_km(&copenhagen, &copenhagen), 0.0);
+    }
+
+    #[test]
+    fn search() {
+        let store = GeoStore::open(crate::gen_temp_path());
+
+        store.insert(
+            "https://example.com/norrebro",
+            place("Nørrebro", &["Place"], 55.6941, 12.5494),
+        );
+        store.insert(
+            "https://luca.example.com/",
+            place("Pizzeria Luca", &["Restaurant", "Pizza"], 55.6867, 12.5578),
+        );
+        store.insert(
+            "https://luca.example.com/",
+            place("Pizzeria Luca", &["Restaurant", "Pizza"], 55.6867, 12.5578),
+        );
+        store.insert(
+            "https://far.example.com/",
+            place("Aarhus Pizza", &["Restaurant"], 56.1629, 10.2039),
+        );
+        store.insert(
+            "https://cafe.example.com/",
+            place("Café Nørrebro", &["CafeOrCoffeeShop"], 55.6950, 12.5500),
+        );
+        store.flush();
+
+        let res = store.search("pizza near nørrebro", 5.0, 10).unwrap();
+
+        assert_eq!(res.location, "nørrebro");
+        assert_eq!(res.places.len(), 1);
+        assert_eq!(res.places[0].name, "Pizzeria Luca");
+        assert!(res.places[0].distance_km < 2.0);
+
+        assert_eq!(store.search("pizza near aarhus", 5.0, 10), None);
+        assert_eq!(store.search("sushi near nørrebro", 5.0, 10), None);
+    }
+}
@@ -0,0 +1,277 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Detection of internationalized domains that spoof popular hosts.
+//!
+//! Characters from other scripts that look like latin letters, such as the
+//! cyrillic `а` in `аpple.com`, can make a phishing domain indistinguishable from
+//! the real one. The punycode host is decoded and each character is replaced by
+//! the latin letter it is confusable with. If this skeleton is a popular host,
+//! the domain is a homograph of it. Internationalized domains with characters
+//! that don't look like latin letters, like `例え.jp`, are never homographs.
+
+use std::{collections::HashSet, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{config::HomographConfig, Result};
+
+/// Hosts that are commonly targeted by phishing. Used if no list is configured.
+const POPULAR_HOSTS: &[&str] = &[
+    "google.com",
+    "youtube.com",
+    "facebook.com",
+    "instagram.com",
+    "whatsapp.com",
+    "twitter.com",
+    "x.com",
+    "linkedin.com",
+    "wikipedia.org",
+    "amazon.com",
+    "ebay.com",
+    "apple.com",
+    "icloud.com",
+    "microsoft.com",
+    "live.com",
+    "outlook.com",
+    "office.com",
+    "github.com",
+    "paypal.com",
+    "stripe.com",
+    "netflix.com",
+    "spotify.com",
+    "dropbox.com",
+    "yahoo.com",
+    "reddit.com",
+    "binance.com",
+    "coinbase.com",
+    "blockchain.com",
+    "steampowered.com",
+    "steamcommunity.com",
+    "chase.com",
+    "wellsfargo.com",
+    "bankofamerica.com",
+];
+
+/// The latin letter that the character is easily confused with.
+fn confusable(c: char) -> Option<char> {
+    let latin = match c {
+        // cyrillic
+        'а' => 'a',
+        'с' => 'c',
+        'ԁ' => 'd',
+        'е' => 'e',
+        'һ' => 'h',
+        'і' => 'i',
+        'ј' => 'j',
+        'к' => 'k',
+        'ӏ' => 'l',
+        'о' => 'o',
+        'р' => 'p',
+        'ԛ' => 'q',
+        'ѕ' => 's',
+        'у' => 'y',
+        'х' => 'x',
+        'ԝ' => 'w',
+        // greek
+        'α' => 'a',
+        'ε' => 'e',
+        'ι' => 'i',
+        'κ' => 'k',
+        'ν' => 'v',
+        'ο' => 'o',
+        'ρ' => 'p',
+        'τ' => 't',
+        'υ' => 'u',
+        'χ' => 'x',
+        'ϲ' => 'c',
+        // armenian
+        'ո' => 'n',
+        'ս' => 'u',
+        'օ' => 'o',
+        // latin lookalikes
+        'ɑ' => 'a',
+        'ɡ' => 'g',
+        'ı' => 'i',
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => 'a',
+        'ç' => 'c',
+        'è' | 'é' | 'ê' | 'ë' => 'e',
+        'ì' | 'í' | 'î' | 'ï' => 'i',
+        'ñ' => 'n',
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' => 'o',
+        'ù' | 'ú' | 'û' | 'ü' => 'u',
+        'ý' | 'ÿ' => 'y',
+        _ => return None,
+    };
+
+    Some(latin)
+}
+
+/// The host with each confusable character replaced by the latin letter it looks like.
+pub fn skeleton(host: &str) -> String {
+    host.chars().map(|c| confusable(c).unwrap_or(c)).collect()
+}
+
+/// What to do with results whose domain is a homograph of a popular host.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HomographAction {
+    /// Keep the results but annotate them with the host they resemble.
+    #[default]
+    Flag,
+    /// Annotate the results and move them below the other results.
+    Demote,
+}
+
+#[derive(Debug)]
+pub struct HomographDetector {
+    hosts: HashSet<String>,
+    action: HomographAction,
+}
+
+impl HomographDetector {
+    pub fn open(config: &HomographConfig) -> Result<Self> {
+        let mut detector = match config.popular_hosts_path.as_ref() {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        detector.action = config.action;
+
+        Ok(detector)
+    }
+
+    /// The list is a text file with a host on each line. Empty lines and
+    /// lines starting with `#` are ignored.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(
+            fs::read_to_string(path)?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#')),
+        ))
+    }
+
+    pub fn new<'a>(hosts: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            hosts: hosts
+                .into_iter()
+                .map(|host| host.trim_start_matches("www.").to_ascii_lowercase())
+                .collect(),
+            action: HomographAction::default(),
+        }
+    }
+
+    pub fn action(&self) -> HomographAction {
+        self.action
+    }
+
+    /// The popular host that the host of the url is a homograph of.
+    pub fn resembles(&self, url: &Url) -> Option<&str> {
+        let host = url.host_str()?;
+
+        let (host, res) = idna::domain_to_unicode(host);
+        if res.is_err() || host.is_ascii() {
+            return None;
+        }
+
+        let skeleton = skeleton(host.trim_start_matches("www."));
+        if !skeleton.is_ascii() {
+            return None;
+        }
+
+        let mut candidate = skeleton.as_str();
+
+        loop {
+            if let Some(popular) = self.hosts.get(candidate) {
+                return Some(popular.as_str());
+            }
+
+            match candidate.split_once('.') {
+                Some((_, parent)) if parent.contains('.') => candidate = parent,
+                _ => return None,
+            }
+        }
+    }
+}
+
+impl Default for HomographDetector {
+    fn default() -> Self {
+        Self::new(POPULAR_HOSTS.iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resembles(url: &str) -> Option<String> {
+        HomographDetector::default()
+            .resembles(&Url::parse(url).unwrap())
+            .map(str::to_string)
+    }
+
+    #[test]
+    fn mixed_script() {
+        // cyrillic `а` followed by latin letters
+        assert_eq!(
+            resembles("https://www.аpple.com/login"),
+            Some("apple.com".to_string())
+        );
+        assert_eq!(
+            resembles("https://accounts.pаypаl.com/"),
+            Some("paypal.com".to_string())
+        );
+    }
+
+    #[test]
+    fn whole_script() {
+        // all letters are cyrillic
+        assert_eq!(
+            resembles("https://аррӏе.com/"),
+            Some("apple.com".to_string())
+        );
+        assert_eq!(
+            resembles("https://xn--80ak6aa92e.com/"),
+            Some("apple.com".to_string())
+        );
+    }
+
+    #[test]
+    fn legitimate_hosts() {
+        assert_eq!(resembles("https://apple.com/"), None);
+        assert_eq!(resembles("https://www.google.com/"), None);
+        assert_eq!(resembles("https://münchen.de/"), None);
+        assert_eq!(resembles("https://例え.jp/"), None);
+        // the skeleton contains a character that does not look latin
+        assert_eq!(resembles("https://аpple.рф/"), None);
+    }
+
+    #[test]
+    fn custom_list() {
+        let detector = HomographDetector::new(["www.stract.com"]);
+
+        assert_eq!(
+            detector.resembles(&Url::parse("https://strаct.com/").unwrap()),
+            Some("stract.com")
+        );
+        assert_eq!(
+            detector.resembles(&Url::parse("https://аpple.com/").unwrap()),
+            None
+        );
+    }
+}
@@ -0,0 +1,246 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Indexing directives of each host, such as how many of its pages ask not to be
+//! archived or shown with a snippet, and the crawl-delay and sitemap from its robots.txt.
+//!
+//! The crawl workers observe the directives while crawling and log them together with the
+//! host outcomes. The planner folds the logs into a [`HostPolicyStore`], which the api
+//! consults so the preferences of a publisher are respected for all results from the host,
+//! and not only for the pages where the directive was seen.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{HostPolicyConfig, HostPolicyReaderConfig},
+    crawler::reputation::HostOutcome,
+    kv::{rocksdb_store::RocksDbStore, Kv},
+};
+
+/// The directives observed for a host during a single job.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostDirectives {
+    /// Number of pages where the robots meta tags were parsed.
+    pub pages: u64,
+    pub noarchive: u64,
+    pub nosnippet: u64,
+    /// Crawl-delay from robots.txt in milliseconds.
+    pub crawl_delay_ms: Option<u64>,
+    /// Not set if robots.txt was not checked for a sitemap during the job.
+    pub has_sitemap: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostPolicy {
+    pub pages: u64,
+    pub noarchive: u64,
+    pub nosnippet: u64,
+    pub crawl_delay_ms: Option<u64>,
+    pub has_sitemap: bool,
+    /// Timestamp of the last outcome that was recorded.
+    pub last_update: u64,
+}
+
+impl HostPolicy {
+    /// Update the policy with the directives observed in a job. The page counts are
+    /// replaced by the latest job that parsed any pages, so a publisher that changes
+    /// its preferences is respected after the next crawl.
+    pub fn record(&mut self, outcome: &HostOutcome) {
+        if outcome.timestamp <= self.last_update {
+            return;
+        }
+
+        self.last_update = outcome.timestamp;
+
+        let directives = &outcome.directives;
+
+        if directives.pages > 0 {
+            self.pages = directives.pages;
+            self.noarchive = directives.noarchive;
+            self.nosnippet = directives.nosnippet;
+        }
+
+        if let Some(has_sitemap) = directives.has_sitemap {
+            self.has_sitemap = has_sitemap;
+            self.crawl_delay_ms = directives.crawl_delay_ms;
+        }
+    }
+
+    pub fn noarchive_fraction(&self) -> f64 {
+        fraction(self.noarchive, self.pages)
+    }
+
+    pub fn nosnippet_fraction(&self) -> f64 {
+        fraction(self.nosnippet, self.pages)
+    }
+}
+
+fn fraction(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+pub struct HostPolicyStore {
+    store: RocksDbStore<String, HostPolicy>,
+    min_directive_fraction: f64,
+}
+
+impl HostPolicyStore {
+    pub fn open(config: &HostPolicyConfig) -> Self {
+        Self {
+            store: RocksDbStore::open(&config.store_path),
+            min_directive_fraction: config.min_directive_fraction,
+        }
+    }
+
+    /// Open the store for reading while the planner writes to it.
+    /// New policies are seen after [`HostPolicyStore::refresh`].
+    pub fn open_reader(config: &HostPolicyReaderConfig) -> Self {
+        Self {
+            store: RocksDbStore::open_secondary(&config.store_path, &config.secondary_path),
+            min_directive_fraction: config.min_directive_fraction,
+        }
+    }
+
+    pub fn refresh(&self) {
+        self.store.catch_up();
+    }
+
+    pub fn get(&self, host: &str) -> Option<HostPolicy> {
+        self.store.get(&host.to_string())
+    }
+
+    /// Whether links to cached or archived copies of pages from the host may be shown.
+    pub fn show_archive(&self, host: &str) -> bool {
+        self.get(host)
+            .map(|policy| policy.noarchive_fraction() < self.min_directive_fraction)
+            .unwrap_or(true)
+    }
+
+    /// Whether snippets of pages from the host may be shown.
+    pub fn show_snippet(&self, host: &str) -> bool {
+        self.get(host)
+            .map(|policy| policy.nosnippet_fraction() < self.min_directive_fraction)
+            .unwrap_or(true)
+    }
+
+    pub fn record(&self, outcomes: impl IntoIterator<Item = HostOutcome>) {
+        for outcome in outcomes {
+            let mut policy = self.get(&outcome.host).unwrap_or_default();
+            policy.record(&outcome);
+            self.store.insert(outcome.host, policy);
+        }
+    }
+
+    pub fn flush(&self) {
+        self.store.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> HostPolicyConfig {
+        HostPolicyConfig {
+            store_path: crate::gen_temp_path().to_str().unwrap().to_string(),
+            min_directive_fraction: 0.5,
+        }
+    }
+
+    fn outcome(timestamp: u64, directives: HostDirectives) -> HostOutcome {
+        HostOutcome {
+            host: "example.com".to_string(),
+            timestamp,
+            directives,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn latest_job_wins() {
+        let mut policy = HostPolicy::default();
+
+        policy.record(&outcome(
+            1,
+            HostDirectives {
+                pages: 10,
+                noarchive: 10,
+                crawl_delay_ms: Some(2_000),
+                has_sitemap: Some(true),
+                ..Default::default()
+            },
+        ));
+
+        assert_eq!(policy.noarchive_fraction(), 1.0);
+        assert_eq!(policy.nosnippet_fraction(), 0.0);
+
+        // robots.txt was not checked and no pages were parsed
+        policy.record(&outcome(2, HostDirectives::default()));
+        assert_eq!(policy.noarchive_fraction(), 1.0);
+        assert_eq!(policy.crawl_delay_ms, Some(2_000));
+        assert!(policy.has_sitemap);
+
+        policy.record(&outcome(
+            3,
+            HostDirectives {
+                pages: 4,
+                noarchive: 1,
+                has_sitemap: Some(false),
+                ..Default::default()
+            },
+        ));
+        assert_eq!(policy.noarchive_fraction(), 0.25);
+        assert_eq!(policy.crawl_delay_ms, None);
+        assert!(!policy.has_sitemap);
+
+        // old outcomes are ignored
+        policy.record(&outcome(
+            2,
+            HostDirectives {
+                pages: 1,
+                noarchive: 1,
+                ..Default::default()
+            },
+        ));
+        assert_eq!(policy.noarchive_fraction(), 0.25);
+    }
+
+    #[test]
+    fn store() {
+        let store = HostPolicyStore::open(&config());
+
+        store.record([outcome(
+            1,
+            HostDirectives {
+                pages: 4,
+                noarchive: 1,
+                nosnippet: 3,
+                ..Default::default()
+            },
+        )]);
+        store.flush();
+
+        assert!(store.show_archive("example.com"));
+        assert!(!store.show_snippet("example.com"));
+
+        assert!(store.show_archive("unknown.com"));
+        assert!(store.show_snippet("unknown.com"));
+    }
+}
@@ -0,0 +1,244 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Pre-aggregated statistics for each host, such as the number of indexed pages,
+//! their average quality, the centrality of the host and how often it failed to be crawled.
+//!
+//! The stats are assembled offline from the index, the signal store and the crawl
+//! error logs, and are served by the host stats server. The api uses them for site info
+//! panels and the crawl planner uses them to allocate less budget to hosts that
+//! mostly fail.
+
+use std::{collections::HashMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    webgraph::{Node, NodeID},
+    webpage::topic_classifier::TopicVector,
+    Result,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostStats {
+    pub num_pages: u64,
+    /// Average pre-computed score of the indexed pages.
+    pub avg_quality: f64,
+    pub centrality: f64,
+    pub crawl_errors: u64,
+    pub topics: Option<TopicVector>,
+}
+
+impl HostStats {
+    /// Fraction of the crawled pages of the host that did not fail.
+    /// Hosts that have never been crawled are assumed to be healthy.
+    pub fn crawl_success_rate(&self) -> f64 {
+        let total = self.num_pages + self.crawl_errors;
+
+        if total == 0 {
+            1.0
+        } else {
+            self.num_pages as f64 / total as f64
+        }
+    }
+}
+
+#[derive(Default)]
+struct Accumulator {
+    num_pages: u64,
+    quality_sum: f64,
+    centrality: f64,
+    crawl_errors: u64,
+    topics: Option<TopicVector>,
+}
+
+impl From<Accumulator> for HostStats {
+    fn from(acc: Accumulator) -> Self {
+        let avg_quality = if acc.num_pages > 0 {
+            acc.quality_sum / acc.num_pages as f64
+        } else {
+            0.0
+        };
+
+        Self {
+            num_pages: acc.num_pages,
+            avg_quality,
+            centrality: acc.centrality,
+            crawl_errors: acc.crawl_errors,
+            topics: acc.topics,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HostStatsBuilder {
+    hosts: HashMap<NodeID, Accumulator>,
+}
+
+impl HostStatsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_page(&mut self, host: NodeID, quality: f64) {
+        let acc = self.hosts.entry(host).or_default();
+        acc.num_pages += 1;
+
+        if quality.is_finite() {
+            acc.quality_sum += quality;
+        }
+    }
+
+    pub fn add_crawl_error(&mut self, host: NodeID) {
+        self.hosts.entry(host).or_default().crawl_errors += 1;
+    }
+
+    pub fn set_centrality(&mut self, host: NodeID, centrality: f64) {
+        if centrality.is_finite() {
+            self.hosts.entry(host).or_default().centrality = centrality;
+        }
+    }
+
+    pub fn set_topics(&mut self, host: NodeID, topics: TopicVector) {
+        if !topics.is_empty() {
+            self.hosts.entry(host).or_default().topics = Some(topics);
+        }
+    }
+
+    pub fn hosts(&self) -> impl Iterator<Item = &NodeID> {
+        self.hosts.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    pub fn finalize<P: AsRef<Path>>(self, path: P) -> Result<HostStatsStore> {
+        if path.as_ref().exists() {
+            return Err(anyhow::anyhow!(
+                "host stats already exists at {}",
+                path.as_ref().display()
+            ));
+        }
+
+        let store: RocksDbStore<NodeID, HostStats> = RocksDbStore::open(path.as_ref());
+
+        for (host, acc) in self.hosts {
+            store.insert(host, acc.into());
+        }
+
+        store.flush();
+
+        Ok(HostStatsStore { store })
+    }
+}
+
+pub struct HostStatsStore {
+    store: RocksDbStore<NodeID, HostStats>,
+}
+
+impl HostStatsStore {
+    /// Open the store built by [`HostStatsBuilder::finalize`]. Fails if there is no store at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self {
+            store: RocksDbStore::try_open_read_only(path)?,
+        })
+    }
+
+    pub fn get(&self, host: &NodeID) -> Option<HostStats> {
+        self.store.get(host)
+    }
+
+    /// Lookup the stats of a host by its name, e.g. `www.example.com`.
+    pub fn get_by_name(&self, host: &str) -> Option<HostStats> {
+        let node = Node {
+            name: host.to_string(),
+        }
+        .into_host();
+
+        if node.name.is_empty() {
+            return None;
+        }
+
+        self.get(&node.id())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeID, HostStats)> + '_ {
+        self.store.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::webpage::topic_classifier::Topic;
+
+    use super::*;
+
+    #[test]
+    fn aggregate() {
+        let host = Node::from("www.example.com").into_host().id();
+        let other = Node::from("other.com").into_host().id();
+
+        let mut builder = HostStatsBuilder::new();
+        builder.add_page(host, 1.0);
+        builder.add_page(host, 3.0);
+        builder.add_crawl_error(host);
+        builder.set_centrality(host, 0.5);
+
+        let mut topics = TopicVector::default();
+        topics.set(Topic::News, 1.0);
+        builder.set_topics(host, topics.clone());
+
+        builder.add_crawl_error(other);
+        builder.set_topics(other, TopicVector::default());
+
+        let path = crate::gen_temp_path().join("host_stats");
+        builder.finalize(&path).unwrap();
+
+        let store = HostStatsStore::open(&path).unwrap();
+
+        let stats = store.get_by_name("www.example.com").unwrap();
+        assert_eq!(stats.num_pages, 2);
+        assert_eq!(stats.avg_quality, 2.0);
+        assert_eq!(stats.centrality, 0.5);
+        assert_eq!(stats.crawl_errors, 1);
+        assert_eq!(stats.topics, Some(topics));
+        assert!((stats.crawl_success_rate() - 2.0 / 3.0).abs() < 1e-9);
+
+        let stats = store.get(&other).unwrap();
+        assert_eq!(stats.num_pages, 0);
+        assert_eq!(stats.avg_quality, 0.0);
+        assert_eq!(stats.topics, None);
+        assert_eq!(stats.crawl_success_rate(), 0.0);
+
+        assert!(store.get_by_name("unknown.com").is_none());
+        assert_eq!(HostStats::default().crawl_success_rate(), 1.0);
+        assert_eq!(store.iter().count(), 2);
+    }
+
+    #[test]
+    fn missing_store() {
+        let path = crate::gen_temp_path().join("host_stats");
+
+        assert!(HostStatsStore::open(&path).is_err());
+        assert!(!path.exists());
+    }
+}
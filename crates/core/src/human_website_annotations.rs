@@ -55,6 +55,14 @@ impl<const N: usize> Topic<N> {
                 .collect::<Vec<_>>(),
         }
     }
+
+    /// The top level category, e.g. `Arts` for `Top/Arts/Music`.
+    pub fn top_level(&self) -> Option<&str> {
+        self.detailed_topics
+            .iter()
+            .map(String::as_str)
+            .find(|topic| *topic != "Top")
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -99,6 +107,10 @@ impl Mapper {
         self.0.get(host)
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Info)> {
+        self.0.iter()
+    }
+
     pub fn all_topics(&self) -> HashSet<Topic> {
         self.0.values().map(|info| info.topic.clone()).collect()
     }
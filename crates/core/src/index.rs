@@ -26,6 +26,7 @@ use crate::collector::MainCollector;
 use crate::inverted_index::{self, InvertedIndex};
 use crate::query::Query;
 use crate::search_ctx::Ctx;
+use crate::snippet::SnippetOptions;
 use crate::webgraph::NodeID;
 use crate::webpage::region::{Region, RegionCount};
 use crate::webpage::Webpage;
@@ -58,6 +59,25 @@ impl Index {
         })
     }
 
+    /// Open an existing index without creating or writing any of its files.
+    /// The index is opened without a writer, so it can only be searched.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let inverted_index_path = path.as_ref().join(INVERTED_INDEX_SUBFOLDER_NAME);
+        let region_count_path = path.as_ref().join(REGION_COUNT_FILE_NAME);
+
+        for required in [&inverted_index_path, &region_count_path] {
+            if !required.exists() {
+                anyhow::bail!("{} does not exist", required.display());
+            }
+        }
+
+        Ok(Self {
+            inverted_index: InvertedIndex::open(inverted_index_path)?,
+            region_count: Mutex::new(RegionCount::open(region_count_path)),
+            path: path.as_ref().to_str().unwrap().to_string(),
+        })
+    }
+
     pub fn set_auto_merge_policy(&mut self) {
         self.inverted_index.set_auto_merge_policy();
     }
@@ -123,8 +143,10 @@ impl Index {
         &self,
         websites: &[inverted_index::WebsitePointer],
         query: &Query,
+        snippet: &SnippetOptions,
     ) -> Result<Vec<inverted_index::RetrievedWebpage>> {
-        self.inverted_index.retrieve_websites(websites, query)
+        self.inverted_index
+            .retrieve_websites(websites, query, snippet)
     }
 
     pub fn merge(self, other: Self) -> Self {
@@ -249,4 +271,29 @@ mod tests {
                 .unwrap())
             .all(|&v| v.value > 0.0));
     }
+
+    #[test]
+    fn open_read_only() {
+        let path = crate::gen_temp_path();
+
+        assert!(Index::open_read_only(&path).is_err());
+        assert!(!path.exists());
+
+        let mut index = Index::open(&path).unwrap();
+        index.prepare_writer().unwrap();
+        index
+            .insert(
+                Webpage::new(
+                    &format!("<title>Test website</title>{CONTENT}"),
+                    "https://www.first.com",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        index.commit().unwrap();
+        drop(index);
+
+        let index = Index::open_read_only(&path).unwrap();
+        assert_eq!(index.inverted_index.tv_searcher().num_docs(), 1);
+    }
 }
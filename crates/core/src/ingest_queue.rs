@@ -0,0 +1,273 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Durable queue of the urls that are waiting to be crawled by the live index.
+//!
+//! The urls found in the feeds and pushed by the WebSub hubs are written to disk
+//! before they are crawled. The live index takes batches from the queue and only acks
+//! them once the crawled pages have been committed to the index. Batches that were
+//! taken but not acked when the live index stopped are delivered again after a restart,
+//! so every url is indexed at least once.
+
+use std::{collections::BTreeSet, path::Path, sync::Mutex};
+
+use url::Url;
+
+use crate::Result;
+
+/// Urls that were added to the queue together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch {
+    pub id: u64,
+    pub urls: Vec<Url>,
+}
+
+struct State {
+    next_id: u64,
+    len: usize,
+    /// Batches that have been taken but not acked or released.
+    in_flight: BTreeSet<u64>,
+}
+
+pub struct IngestQueue {
+    db: rocksdb::DB,
+    state: Mutex<State>,
+}
+
+impl IngestQueue {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut options = rocksdb::Options::default();
+        options.create_if_missing(true);
+
+        let db = rocksdb::DB::open(&options, path)?;
+
+        let mut next_id = 0;
+        let mut len = 0;
+
+        for res in db.iterator(rocksdb::IteratorMode::Start) {
+            let (key, _) = res?;
+            next_id = batch_id(&key)? + 1;
+            len += 1;
+        }
+
+        if len > 0 {
+            tracing::info!("replaying {} batches from the ingest queue", len);
+        }
+
+        Ok(Self {
+            db,
+            state: Mutex::new(State {
+                next_id,
+                len,
+                in_flight: BTreeSet::new(),
+            }),
+        })
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write_options() -> rocksdb::WriteOptions {
+        let mut opts = rocksdb::WriteOptions::default();
+        opts.set_sync(true);
+        opts
+    }
+
+    /// Number of batches that have not been acked.
+    pub fn len(&self) -> usize {
+        self.state().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add the urls to the queue. The urls are on disk when this returns.
+    pub fn push(&self, urls: Vec<Url>) -> Result<u64> {
+        let urls: Vec<String> = urls.into_iter().map(|url| url.to_string()).collect();
+        let mut state = self.state();
+
+        let id = state.next_id;
+        self.db.put_opt(
+            id.to_be_bytes(),
+            bincode::serialize(&urls)?,
+            &Self::write_options(),
+        )?;
+
+        state.next_id += 1;
+        state.len += 1;
+
+        Ok(id)
+    }
+
+    /// Take up to `max_batches` of the oldest batches that are not already taken.
+    /// The batches are delivered again if they are released, or if the process
+    /// stops before they are acked.
+    pub fn take(&self, max_batches: usize) -> Result<Vec<Batch>> {
+        let mut state = self.state();
+        let mut batches = Vec::new();
+        let mut corrupt = Vec::new();
+
+        for res in self.db.iterator(rocksdb::IteratorMode::Start) {
+            if batches.len() >= max_batches {
+                break;
+            }
+
+            let (key, value) = res?;
+            let id = batch_id(&key)?;
+
+            if state.in_flight.contains(&id) {
+                continue;
+            }
+
+            match bincode::deserialize::<Vec<String>>(&value) {
+                Ok(urls) => {
+                    state.in_flight.insert(id);
+                    batches.push(Batch {
+                        id,
+                        urls: urls
+                            .into_iter()
+                            .filter_map(|url| Url::parse(&url).ok())
+                            .collect(),
+                    });
+                }
+                Err(e) => {
+                    tracing::error!("dropping corrupt batch {} from the ingest queue: {}", id, e);
+                    corrupt.push(id);
+                }
+            }
+        }
+
+        drop(state);
+        self.ack(corrupt)?;
+
+        Ok(batches)
+    }
+
+    /// Remove the batches from the queue once they have been indexed.
+    pub fn ack(&self, ids: impl IntoIterator<Item = u64>) -> Result<()> {
+        let mut state = self.state();
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut removed = Vec::new();
+
+        for id in ids {
+            let key = id.to_be_bytes();
+
+            if self.db.get_pinned(key)?.is_some() {
+                batch.delete(key);
+                removed.push(id);
+            }
+        }
+
+        self.db.write_opt(batch, &Self::write_options())?;
+
+        for id in removed {
+            state.in_flight.remove(&id);
+            state.len -= 1;
+        }
+
+        Ok(())
+    }
+
+    /// Return the batches to the queue so they are taken again, e.g. after a failure.
+    pub fn release(&self, ids: impl IntoIterator<Item = u64>) {
+        let mut state = self.state();
+
+        for id in ids {
+            state.in_flight.remove(&id);
+        }
+    }
+}
+
+fn batch_id(key: &[u8]) -> Result<u64> {
+    Ok(u64::from_be_bytes(key.try_into()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(urls: &[&str]) -> Vec<Url> {
+        urls.iter().map(|url| Url::parse(url).unwrap()).collect()
+    }
+
+    #[test]
+    fn fifo() {
+        let queue = IngestQueue::open(crate::gen_temp_path()).unwrap();
+
+        queue.push(urls(&["https://a.com/1"])).unwrap();
+        queue
+            .push(urls(&["https://b.com/1", "https://b.com/2"]))
+            .unwrap();
+        queue.push(urls(&["https://c.com/1"])).unwrap();
+        assert_eq!(queue.len(), 3);
+
+        let first = queue.take(2).unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[0].urls, urls(&["https://a.com/1"]));
+        assert_eq!(first[1].urls.len(), 2);
+
+        // taken batches are not delivered twice
+        let second = queue.take(2).unwrap();
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].urls, urls(&["https://c.com/1"]));
+        assert!(queue.take(2).unwrap().is_empty());
+
+        queue.ack(first.iter().map(|batch| batch.id)).unwrap();
+        assert_eq!(queue.len(), 1);
+
+        queue.release([second[0].id]);
+        assert_eq!(queue.take(10).unwrap(), second);
+    }
+
+    #[test]
+    fn replay_after_restart() {
+        let path = crate::gen_temp_path();
+
+        {
+            let queue = IngestQueue::open(&path).unwrap();
+            queue.push(urls(&["https://a.com/"])).unwrap();
+            queue.push(urls(&["https://b.com/"])).unwrap();
+
+            let taken = queue.take(2).unwrap();
+            queue.ack([taken[0].id]).unwrap();
+        }
+
+        let queue = IngestQueue::open(&path).unwrap();
+        assert_eq!(queue.len(), 1);
+
+        // the batch that was taken but not acked is delivered again
+        let taken = queue.take(10).unwrap();
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].urls, urls(&["https://b.com/"]));
+
+        // new batches get ids after the replayed ones
+        let id = queue.push(urls(&["https://c.com/"])).unwrap();
+        assert!(id > taken[0].id);
+    }
+
+    #[test]
+    fn ack_is_idempotent() {
+        let queue = IngestQueue::open(crate::gen_temp_path()).unwrap();
+        let id = queue.push(urls(&["https://a.com/"])).unwrap();
+
+        queue.ack([id]).unwrap();
+        queue.ack([id]).unwrap();
+
+        assert!(queue.is_empty());
+    }
+}
@@ -29,6 +29,7 @@ use chrono::NaiveDateTime;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use tantivy::collector::Count;
+use tantivy::columnar::ColumnValues;
 use tantivy::directory::MmapDirectory;
 use tantivy::merge_policy::NoMergePolicy;
 use tantivy::schema::{Schema, Value};
@@ -36,36 +37,51 @@ use tantivy::tokenizer::TokenizerManager;
 use tantivy::{IndexReader, IndexWriter, SegmentMeta, TantivyDocument};
 use url::Url;
 
+use crate::collection_stats::CollectionStats;
 use crate::collector::{Hashes, MainCollector};
 use crate::config::SnippetConfig;
 use crate::fastfield_reader::FastFieldReader;
+use crate::memory_budget;
+use crate::query::filter_cache::FilterCache;
 use crate::query::shortcircuit::ShortCircuitQuery;
 use crate::query::Query;
 use crate::ranking::initial::Score;
 use crate::ranking::pipeline::RankingWebsite;
 use crate::ranking::SignalAggregator;
-use crate::schema::{FastField, Field, TextField};
+use crate::schema::{FastField, Field, TextField, FLOAT_SCALING};
 use crate::search_ctx::Ctx;
+use crate::security::SecurityAnnotations;
 use crate::snippet::TextSnippet;
-use crate::snippet::{self, TextSnippetFragment};
+use crate::snippet::{self, SnippetOptions, TextSnippetFragment};
 use crate::tokenizer::{
     BigramTokenizer, Identity, JsonField, SiteOperatorUrlTokenizer, TrigramTokenizer,
 };
+use crate::tombstone::{TombstoneQuery, Tombstones};
 use crate::webgraph::NodeID;
 use crate::webpage::region::Region;
+use crate::webpage::topic_classifier::Topic;
 use crate::webpage::url_ext::UrlExt;
-use crate::webpage::{schema_org, Webpage};
+use crate::webpage::{
+    schema_org, Place, PreviewImage, Product, ScholarlyMetadata, VideoMetadata, Webpage,
+};
 use crate::Result;
 use crate::{schema::create_schema, tokenizer::Tokenizer};
 use std::collections::HashSet;
 use std::fs;
+use std::ops::{Bound, RangeBounds};
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InitialSearchResult {
     pub num_websites: Option<usize>,
     pub top_websites: Vec<WebsitePointer>,
+    /// The collector stopped at its deadline before it had seen all the documents.
+    pub truncated: bool,
+    /// Number of documents the collector scored.
+    pub docs_scored: u64,
+    /// Number of bytes of postings that were read for the terms of the query.
+    pub bytes_read: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -75,7 +91,29 @@ pub struct WebsitePointer {
     pub address: DocAddress,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+/// Whether a url is in the index.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum UrlIndexStatus {
+    /// The page is indexed under `url`, which is the requested url after it
+    /// has been normalized like the urls of the indexed pages, or the
+    /// rel=canonical url of the page if the requested url was canonicalized.
+    Indexed {
+        url: String,
+    },
+    /// The page has been deleted from the index, but is not purged yet.
+    Deleted,
+    NotIndexed,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DocumentSummary {
+    pub address: DocAddress,
+    pub page: NodeID,
+    pub pre_computed_score: f64,
+    pub host_centrality: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct DocAddress {
     pub segment: u32,
     pub doc_id: u32,
@@ -164,6 +202,10 @@ pub struct InvertedIndex {
     schema: Arc<Schema>,
     snippet_config: SnippetConfig,
     fastfield_reader: FastFieldReader,
+    filter_cache: Arc<FilterCache>,
+    tombstones: Arc<Tombstones>,
+    auto_merge: bool,
+    collection_stats: RwLock<Option<Arc<CollectionStats>>>,
 }
 
 impl InvertedIndex {
@@ -226,6 +268,19 @@ impl InvertedIndex {
 
         let fastfield_reader = FastFieldReader::new(&reader.searcher());
 
+        let mut tombstones = Tombstones::open(&path)?;
+        tombstones.retain_segments(
+            &reader
+                .searcher()
+                .segment_readers()
+                .iter()
+                .map(|segment| segment.segment_id())
+                .collect::<Vec<_>>(),
+        );
+
+        let filter_cache = Arc::new(FilterCache::default());
+        memory_budget::global().register(filter_cache.clone());
+
         Ok(InvertedIndex {
             writer: None,
             reader,
@@ -234,6 +289,10 @@ impl InvertedIndex {
             tantivy_index,
             snippet_config: SnippetConfig::default(),
             fastfield_reader,
+            filter_cache,
+            tombstones: Arc::new(tombstones),
+            auto_merge: false,
+            collection_stats: RwLock::new(None),
         })
     }
 
@@ -250,10 +309,8 @@ impl InvertedIndex {
             .tantivy_index
             .writer_with_num_threads(1, 1_000_000_000)?;
 
-        let merge_policy = NoMergePolicy;
-        writer.set_merge_policy(Box::new(merge_policy));
-
         self.writer = Some(writer);
+        self.apply_merge_policy();
 
         Ok(())
     }
@@ -263,11 +320,24 @@ impl InvertedIndex {
     }
 
     pub fn set_auto_merge_policy(&mut self) {
-        let merge_policy = tantivy::merge_policy::LogMergePolicy::default();
-        self.writer
-            .as_mut()
-            .expect("writer has not been prepared")
-            .set_merge_policy(Box::new(merge_policy));
+        assert!(self.writer.is_some(), "writer has not been prepared");
+        self.auto_merge = true;
+        self.apply_merge_policy();
+    }
+
+    /// Segments are only merged in the background when there are no tombstones, as the
+    /// tombstones of the merged segments would be lost. They are merged again once the
+    /// tombstones are purged or restored.
+    fn apply_merge_policy(&self) {
+        let Some(writer) = self.writer.as_ref() else {
+            return;
+        };
+
+        if self.auto_merge && self.tombstones.is_empty() {
+            writer.set_merge_policy(Box::new(tantivy::merge_policy::LogMergePolicy::default()));
+        } else {
+            writer.set_merge_policy(Box::new(NoMergePolicy));
+        }
     }
 
     pub fn tokenizers(&self) -> &TokenizerManager {
@@ -325,12 +395,205 @@ impl InvertedIndex {
         self.delete(Box::new(query))
     }
 
+    /// Delete all documents with a pre-computed score in the range.
+    pub fn delete_by_pre_computed_score(&self, range: impl RangeBounds<f64>) -> Result<()> {
+        let scale = |bound: Bound<&f64>| match bound {
+            Bound::Included(score) => Bound::Included((score * FLOAT_SCALING as f64) as u64),
+            Bound::Excluded(score) => Bound::Excluded((score * FLOAT_SCALING as f64) as u64),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+
+        let query = tantivy::query::RangeQuery::new_u64_bounds(
+            Field::Fast(FastField::PreComputedScore).name().to_string(),
+            scale(range.start_bound()),
+            scale(range.end_bound()),
+        );
+
+        self.delete(Box::new(query))
+    }
+
+    /// The pre-computed scores of all the documents in the index that are not deleted.
+    pub fn pre_computed_scores(&self) -> Result<Vec<f64>> {
+        Ok(self
+            .document_summaries()?
+            .into_iter()
+            .map(|doc| doc.pre_computed_score)
+            .collect())
+    }
+
+    /// Summaries of all the documents in the index that are not deleted.
+    pub fn document_summaries(&self) -> Result<Vec<DocumentSummary>> {
+        let searcher = self.reader.searcher();
+        let mut docs = Vec::new();
+
+        for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+            let fastfields = segment_reader.fast_fields();
+
+            let scores = fastfields.u64(Field::Fast(FastField::PreComputedScore).name())?;
+            let host_centralities =
+                fastfields.u64(Field::Fast(FastField::HostCentrality).name())?;
+            let pages = fastfields.u64(Field::Fast(FastField::PageNodeID).name())?;
+
+            for doc_id in segment_reader.doc_ids_alive() {
+                docs.push(DocumentSummary {
+                    address: DocAddress {
+                        segment: segment_ord as u32,
+                        doc_id,
+                    },
+                    page: pages.values.get_val(doc_id).into(),
+                    pre_computed_score: scores.values.get_val(doc_id) as f64 / FLOAT_SCALING as f64,
+                    host_centrality: host_centralities.values.get_val(doc_id) as f64
+                        / FLOAT_SCALING as f64,
+                });
+            }
+        }
+
+        Ok(docs)
+    }
+
+    /// All the documents that are not deleted where the flag in the fast field is set.
+    fn retrieve_flagged(&self, flag: FastField) -> Result<Vec<RetrievedWebpage>> {
+        let searcher = self.reader.searcher();
+        let mut webpages = Vec::new();
+
+        for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+            let flags = segment_reader.fast_fields().u64(Field::Fast(flag).name())?;
+
+            for doc_id in segment_reader.doc_ids_alive() {
+                if flags.values.get_val(doc_id) == 0 {
+                    continue;
+                }
+
+                let address = DocAddress {
+                    segment: segment_ord as u32,
+                    doc_id,
+                };
+                webpages.push(self.retrieve_doc(address, &searcher)?);
+            }
+        }
+
+        Ok(webpages)
+    }
+
+    /// The url and product of all the product pages in the index that are not deleted.
+    pub fn products(&self) -> Result<Vec<(String, Product)>> {
+        Ok(self
+            .retrieve_flagged(FastField::IsProduct)?
+            .into_iter()
+            .filter_map(|webpage| webpage.product.map(|product| (webpage.url, product)))
+            .collect())
+    }
+
+    /// The url and places of all the pages with places in the index that are not deleted.
+    pub fn places(&self) -> Result<Vec<(String, Vec<Place>)>> {
+        Ok(self
+            .retrieve_flagged(FastField::HasPlaces)?
+            .into_iter()
+            .filter(|webpage| !webpage.places.is_empty())
+            .map(|webpage| (webpage.url, webpage.places))
+            .collect())
+    }
+
+    /// The documents that are excluded from the search results but can still be restored.
+    pub fn tombstones(&self) -> Arc<Tombstones> {
+        Arc::clone(&self.tombstones)
+    }
+
+    fn set_tombstones(&mut self, tombstones: Tombstones) -> Result<()> {
+        tombstones.save(&self.path)?;
+        self.tombstones = Arc::new(tombstones);
+        self.apply_merge_policy();
+
+        Ok(())
+    }
+
+    /// Exclude the documents from the search results. They can be restored
+    /// until the segments are merged, where the documents are deleted.
+    pub fn tombstone(&mut self, addresses: impl IntoIterator<Item = DocAddress>) -> Result<()> {
+        let searcher = self.reader.searcher();
+        let mut tombstones = (*self.tombstones).clone();
+
+        for address in addresses {
+            let segment = searcher.segment_reader(address.segment).segment_id();
+            tombstones.insert(segment, address.doc_id);
+        }
+
+        self.set_tombstones(tombstones)
+    }
+
+    /// Return all the tombstoned documents to the search results. Returns the number of restored documents.
+    pub fn restore_tombstoned(&mut self) -> Result<usize> {
+        let num_restored = self.tombstones.len();
+        self.set_tombstones(Tombstones::default())?;
+
+        Ok(num_restored)
+    }
+
+    fn is_tombstoned(&self, address: tantivy::DocAddress, searcher: &tantivy::Searcher) -> bool {
+        self.tombstones.contains(
+            searcher.segment_reader(address.segment_ord).segment_id(),
+            address.doc_id,
+        )
+    }
+
+    /// Delete the tombstoned documents for real.
+    fn purge_tombstoned(&mut self) -> Result<()> {
+        if self.tombstones.is_empty() {
+            return Ok(());
+        }
+
+        let num_tombstoned = self.tombstones.len();
+        self.delete(Box::new(TombstoneQuery::new((*self.tombstones).clone())))?;
+        self.commit()?;
+        self.set_tombstones(Tombstones::default())?;
+
+        tracing::info!("deleted {} tombstoned documents", num_tombstoned);
+
+        Ok(())
+    }
+
+    pub fn url(&self, address: DocAddress) -> Result<String> {
+        Ok(self.retrieve_doc(address, &self.reader.searcher())?.url)
+    }
+
+    /// Delete the document with the url. The url must match exactly.
+    pub fn delete_url(&self, url: &str) -> Result<()> {
+        let field = self
+            .schema
+            .get_field(Field::Text(TextField::UrlNoTokenizer).name())
+            .unwrap();
+
+        self.writer
+            .as_ref()
+            .expect("writer has not been prepared")
+            .delete_term(tantivy::Term::from_field_text(field, url));
+
+        Ok(())
+    }
+
+    /// Delete all documents in the region.
+    pub fn delete_by_region(&self, region: Region) -> Result<()> {
+        let field = self
+            .schema
+            .get_field(Field::Fast(FastField::Region).name())
+            .unwrap();
+
+        self.writer
+            .as_ref()
+            .expect("writer has not been prepared")
+            .delete_term(tantivy::Term::from_field_u64(field, region.id()));
+
+        Ok(())
+    }
+
     pub fn search_initial(
         &self,
         query: &Query,
         ctx: &Ctx,
         collector: MainCollector,
     ) -> Result<InitialSearchResult> {
+        let bytes_read = Self::postings_bytes(query, ctx)?;
+
         if !query.count_results() {
             let mut query: Box<dyn tantivy::query::Query> = Box::new(query.clone());
 
@@ -339,31 +602,96 @@ impl InvertedIndex {
                 query = Box::new(ShortCircuitQuery::new(query, docs_per_segment as u64));
             }
 
-            let pointers = ctx.tv_searcher.search(&query, &collector)?;
+            let pointers = ctx.tv_searcher.search_with_statistics_provider(
+                query.as_ref(),
+                &collector,
+                &ctx.statistics_provider(),
+            )?;
 
             return Ok(InitialSearchResult {
                 num_websites: None,
                 top_websites: pointers,
+                truncated: collector.top_docs().is_truncated(),
+                docs_scored: collector.top_docs().num_docs_scored(),
+                bytes_read,
             });
         }
 
         let collector = (Count, collector);
-        let (count, pointers) = ctx.tv_searcher.search(query, &collector)?;
+        let (count, pointers) = ctx.tv_searcher.search_with_statistics_provider(
+            query,
+            &collector,
+            &ctx.statistics_provider(),
+        )?;
 
         Ok(InitialSearchResult {
             num_websites: Some(count),
             top_websites: pointers,
+            truncated: collector.1.top_docs().is_truncated(),
+            docs_scored: collector.1.top_docs().num_docs_scored(),
+            bytes_read,
         })
     }
 
+    /// Size of the postings, and the positions if the query needs them, of the terms
+    /// of the query in all the segments.
+    fn postings_bytes(query: &Query, ctx: &Ctx) -> Result<u64> {
+        let mut terms = Vec::new();
+        tantivy::query::Query::query_terms(query, &mut |term, need_positions| {
+            terms.push((term.clone(), need_positions));
+        });
+
+        let mut bytes = 0;
+
+        for segment in ctx.tv_searcher.segment_readers() {
+            for (term, need_positions) in &terms {
+                let inverted_index = segment.inverted_index(term.field())?;
+
+                if let Some(info) = inverted_index.get_term_info(term)? {
+                    bytes += info.postings_range.len() as u64;
+
+                    if *need_positions {
+                        bytes += info.positions_range.len() as u64;
+                    }
+                }
+            }
+        }
+
+        Ok(bytes)
+    }
+
     pub fn local_search_ctx(&self) -> Ctx {
         let tv_searcher = self.tv_searcher();
         Ctx {
             fastfield_reader: self.fastfield_reader.clone(),
+            filter_cache: Arc::clone(&self.filter_cache),
+            tombstones: Arc::clone(&self.tombstones),
+            collection_stats: self
+                .collection_stats
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
             tv_searcher,
         }
     }
 
+    /// Score with the statistics of all the shards instead of the statistics of this index.
+    pub fn set_collection_stats(&self, stats: Arc<CollectionStats>) {
+        *self
+            .collection_stats
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = Some(stats);
+    }
+
+    /// The statistics of this index, to be merged with the statistics of the other shards.
+    pub fn local_collection_stats(
+        &self,
+        min_doc_freq: u64,
+        max_terms_per_field: usize,
+    ) -> Result<CollectionStats> {
+        CollectionStats::from_searcher(&self.tv_searcher(), min_doc_freq, max_terms_per_field)
+    }
+
     pub fn tv_searcher(&self) -> tantivy::Searcher {
         self.reader.searcher()
     }
@@ -395,7 +723,7 @@ impl InvertedIndex {
 
             if update_segment {
                 let segment_reader = ctx.tv_searcher.segment_reader(pointer.address.segment);
-                aggregator.register_segment(&ctx.tv_searcher, segment_reader, fastfield_reader)?;
+                aggregator.register_segment(ctx, segment_reader, fastfield_reader)?;
             }
 
             prev_segment = Some(pointer.address.segment);
@@ -433,7 +761,9 @@ impl InvertedIndex {
         &self,
         websites: &[WebsitePointer],
         query: &Query,
+        snippet_options: &SnippetOptions,
     ) -> Result<Vec<RetrievedWebpage>> {
+        let snippet_config = self.snippet_config.clone().with_options(snippet_options);
         let tv_searcher = self.reader.searcher();
         let mut webpages: Vec<RetrievedWebpage> = websites
             .iter()
@@ -449,13 +779,13 @@ impl InvertedIndex {
                 let snippet = if let Some(description) = page.description.as_deref() {
                     let snip = description
                         .split_whitespace()
-                        .take(self.snippet_config.empty_query_snippet_words)
+                        .take(snippet_config.empty_query_snippet_words)
                         .join(" ");
 
-                    if snip.split_whitespace().count() < self.snippet_config.min_description_words {
+                    if snip.split_whitespace().count() < snippet_config.min_description_words {
                         page.body
                             .split_whitespace()
-                            .take(self.snippet_config.empty_query_snippet_words)
+                            .take(snippet_config.empty_query_snippet_words)
                             .join(" ")
                     } else {
                         snip
@@ -463,7 +793,7 @@ impl InvertedIndex {
                 } else {
                     page.body
                         .split_whitespace()
-                        .take(self.snippet_config.empty_query_snippet_words)
+                        .take(snippet_config.empty_query_snippet_words)
                         .join(" ")
                 };
 
@@ -472,9 +802,9 @@ impl InvertedIndex {
                 };
             } else {
                 let min_body_len = if url.is_homepage() {
-                    self.snippet_config.min_body_length_homepage
+                    snippet_config.min_body_length_homepage
                 } else {
-                    self.snippet_config.min_body_length
+                    snippet_config.min_body_length
                 };
 
                 if page.body.split_whitespace().count() < min_body_len
@@ -484,21 +814,17 @@ impl InvertedIndex {
                         .unwrap_or_default()
                         .split_whitespace()
                         .count()
-                        >= self.snippet_config.min_description_words
+                        >= snippet_config.min_description_words
                 {
                     page.snippet = snippet::generate(
                         query,
                         page.description.as_deref().unwrap_or_default(),
                         &page.region,
-                        self.snippet_config.clone(),
+                        snippet_config.clone(),
                     );
                 } else {
-                    page.snippet = snippet::generate(
-                        query,
-                        &page.body,
-                        &page.region,
-                        self.snippet_config.clone(),
-                    );
+                    page.snippet =
+                        snippet::generate(query, &page.body, &page.region, snippet_config.clone());
                 }
             }
         }
@@ -508,6 +834,8 @@ impl InvertedIndex {
 
     pub fn merge_into_max_segments(&mut self, max_num_segments: u64) -> Result<()> {
         self.prepare_writer()?;
+        self.purge_tombstoned()?;
+
         let base_path = Path::new(&self.path);
         let segments: Vec<_> = self
             .tantivy_index
@@ -592,6 +920,12 @@ impl InvertedIndex {
             meta.segments
                 .sort_by_key(|a| std::cmp::Reverse(a.max_doc()));
 
+            let mut tombstones = (*self.tombstones).clone();
+            tombstones.extend(&other.tombstones);
+            tombstones
+                .save(self_path)
+                .expect("failed to save tombstones");
+
             fs::remove_dir_all(other_path).ok();
 
             let self_path = Path::new(&path);
@@ -626,9 +960,7 @@ impl InvertedIndex {
         self.tantivy_index.searchable_segments().unwrap().len()
     }
 
-    pub(crate) fn get_webpage(&self, url: &str) -> Option<RetrievedWebpage> {
-        let url = Url::parse(url).ok()?;
-        let tv_searcher = self.reader.searcher();
+    fn find_url(&self, url: &Url, tv_searcher: &tantivy::Searcher) -> Option<tantivy::DocAddress> {
         let field = tv_searcher
             .schema()
             .get_field(Field::Text(TextField::UrlNoTokenizer).name())
@@ -643,7 +975,122 @@ impl InvertedIndex {
             .unwrap();
 
         res.pop()
-            .map(|(_, doc)| self.retrieve_doc(doc.into(), &tv_searcher).unwrap())
+            .map(|(_, doc)| doc)
+            .filter(|doc| !self.is_tombstoned(*doc, tv_searcher))
+    }
+
+    pub(crate) fn get_webpage(&self, url: &str) -> Option<RetrievedWebpage> {
+        let url = Url::parse(url).ok()?;
+        let tv_searcher = self.reader.searcher();
+
+        self.find_url(&url, &tv_searcher)
+            .map(|doc| self.retrieve_doc(doc.into(), &tv_searcher).unwrap())
+    }
+
+    /// Replace the indexed page with `webpage`, but only take the values of `fields` from it.
+    /// The other fields keep their indexed values if they can be read back from the index,
+    /// which is the case for stored and fast fields. Fields that are only indexed are taken
+    /// from `webpage` as well. Returns false if the page is not in the index.
+    pub(crate) fn patch_webpage(&self, webpage: Webpage, fields: &[Field]) -> Result<bool> {
+        let url = webpage.html.url().clone();
+        let tv_searcher = self.reader.searcher();
+
+        let Some(address) = self.find_url(&url, &tv_searcher) else {
+            return Ok(false);
+        };
+
+        let indexed: TantivyDocument = tv_searcher.doc(address)?;
+        let fast_fields = self
+            .fastfield_reader
+            .get_segment(&tv_searcher.segment_reader(address.segment_ord).segment_id());
+        let fast_values = fast_fields.get_field_reader(&address.doc_id);
+
+        let new = webpage.into_tantivy(&self.schema)?;
+        let mut doc = TantivyDocument::default();
+
+        for (tv_field, entry) in self.schema.fields() {
+            match Field::get(tv_field.field_id() as usize).copied() {
+                Some(field) if !fields.contains(&field) && entry.is_stored() => {
+                    for value in indexed.get_all(tv_field) {
+                        doc.add_field_value(tv_field, value.clone());
+                    }
+                }
+                Some(field @ Field::Fast(fast)) if !fields.contains(&field) => {
+                    doc.add_u64(tv_field, fast_values.get(&fast));
+                }
+                _ => {
+                    for value in new.get_all(tv_field) {
+                        doc.add_field_value(tv_field, value.clone());
+                    }
+                }
+            }
+        }
+
+        self.delete_url(url.as_str())?;
+        self.writer
+            .as_ref()
+            .expect("writer has not been prepared")
+            .add_document(doc)?;
+
+        Ok(true)
+    }
+
+    /// Look up the url both as it is and after it has been normalized the
+    /// way the urls of the pages are normalized before they are indexed.
+    pub(crate) fn url_status(&self, url: &str) -> Result<UrlIndexStatus> {
+        let Ok(url) = Url::parse(url) else {
+            return Ok(UrlIndexStatus::NotIndexed);
+        };
+
+        let mut normalized = url.clone();
+        crate::webpage::normalize_url(&mut normalized);
+
+        let tv_searcher = self.reader.searcher();
+        let schema = tv_searcher.schema();
+        let url_field = schema.get_field(Field::Text(TextField::UrlNoTokenizer).name())?;
+        let fetched_url_field =
+            schema.get_field(Field::Text(TextField::FetchedUrlNoTokenizer).name())?;
+
+        let mut status = UrlIndexStatus::NotIndexed;
+
+        // pages that were canonicalized are indexed under their canonical url,
+        // so the fetched url is looked up after the urls the pages are indexed under.
+        let lookups = [url, normalized.clone()]
+            .into_iter()
+            .dedup()
+            .map(|url| (url_field, url))
+            .chain(std::iter::once((fetched_url_field, normalized)));
+
+        for (field, url) in lookups {
+            let term = tantivy::Term::from_field_text(field, url.as_str());
+            let query =
+                tantivy::query::TermQuery::new(term, tantivy::schema::IndexRecordOption::Basic);
+
+            // the same url can be in several segments until the tombstoned
+            // documents have been purged.
+            let docs = tv_searcher.search(&query, &tantivy::collector::TopDocs::with_limit(16))?;
+
+            if docs.is_empty() {
+                continue;
+            }
+
+            if let Some((_, doc)) = docs
+                .iter()
+                .find(|(_, doc)| !self.is_tombstoned(*doc, &tv_searcher))
+            {
+                let url = if field == url_field {
+                    url.to_string()
+                } else {
+                    self.retrieve_doc((*doc).into(), &tv_searcher)?.url
+                };
+
+                return Ok(UrlIndexStatus::Indexed { url });
+            }
+
+            status = UrlIndexStatus::Deleted;
+        }
+
+        Ok(status)
     }
 
     pub(crate) fn get_homepage(&self, url: &Url) -> Option<RetrievedWebpage> {
@@ -664,6 +1111,7 @@ impl InvertedIndex {
             .unwrap();
 
         res.pop()
+            .filter(|(_, doc)| !self.is_tombstoned(*doc, &tv_searcher))
             .map(|(_, doc)| self.retrieve_doc(doc.into(), &tv_searcher).unwrap())
     }
 }
@@ -689,6 +1137,18 @@ pub struct RetrievedWebpage {
     pub likely_has_ads: bool,
     pub likely_has_paywall: bool,
     pub recipe_first_ingredient_tag_id: Option<String>,
+    pub topics: Vec<Topic>,
+    pub preview_image: Option<PreviewImage>,
+    pub video: Option<VideoMetadata>,
+    pub product: Option<Product>,
+    pub scholarly: Option<ScholarlyMetadata>,
+    pub places: Vec<Place>,
+    pub authors: Vec<String>,
+    pub reading_ease: Option<f64>,
+    pub num_words: u64,
+    pub generated_likelihood: Option<f64>,
+    /// Set by the searcher from the signal store.
+    pub security: Option<SecurityAnnotations>,
 }
 impl RetrievedWebpage {
     pub fn description(&self) -> Option<&String> {
@@ -788,6 +1248,25 @@ impl From<TantivyDocument> for RetrievedWebpage {
                     webpage.likely_has_paywall =
                         value.value().as_value().as_u64().unwrap_or_default() != 0;
                 }
+                Some(Field::Fast(FastField::ReadingEase)) => {
+                    webpage.reading_ease = value
+                        .value()
+                        .as_value()
+                        .as_u64()
+                        .filter(|ease| *ease != u64::MAX)
+                        .map(|ease| ease as f64);
+                }
+                Some(Field::Fast(FastField::NumCleanBodyWords)) => {
+                    webpage.num_words = value.value().as_value().as_u64().unwrap_or_default();
+                }
+                Some(Field::Fast(FastField::GeneratedLikelihood)) => {
+                    webpage.generated_likelihood = value
+                        .value()
+                        .as_value()
+                        .as_u64()
+                        .filter(|likelihood| *likelihood != u64::MAX)
+                        .map(|likelihood| likelihood as f64 / FLOAT_SCALING as f64);
+                }
                 Some(Field::Text(TextField::RecipeFirstIngredientTagId)) => {
                     let tag_id = value
                         .value()
@@ -800,6 +1279,71 @@ impl From<TantivyDocument> for RetrievedWebpage {
                         webpage.recipe_first_ingredient_tag_id = Some(tag_id);
                     }
                 }
+                Some(Field::Text(TextField::PreviewImage)) => {
+                    let json = value
+                        .value()
+                        .as_value()
+                        .as_str()
+                        .expect("Preview image field should be stored as text");
+
+                    webpage.preview_image = serde_json::from_str(json).ok();
+                }
+                Some(Field::Text(TextField::VideoMetadata)) => {
+                    let json = value
+                        .value()
+                        .as_value()
+                        .as_str()
+                        .expect("Video metadata field should be stored as text");
+
+                    webpage.video = serde_json::from_str(json).ok();
+                }
+                Some(Field::Text(TextField::Product)) => {
+                    let json = value
+                        .value()
+                        .as_value()
+                        .as_str()
+                        .expect("Product field should be stored as text");
+
+                    webpage.product = serde_json::from_str(json).ok();
+                }
+                Some(Field::Text(TextField::Scholarly)) => {
+                    let json = value
+                        .value()
+                        .as_value()
+                        .as_str()
+                        .expect("Scholarly field should be stored as text");
+
+                    webpage.scholarly = serde_json::from_str(json).ok();
+                }
+                Some(Field::Text(TextField::Places)) => {
+                    let json = value
+                        .value()
+                        .as_value()
+                        .as_str()
+                        .expect("Places field should be stored as text");
+
+                    webpage.places = serde_json::from_str(json).unwrap_or_default();
+                }
+                Some(Field::Text(TextField::Topics)) => {
+                    let topic = value
+                        .value()
+                        .as_value()
+                        .as_str()
+                        .expect("Topics field should be stored as text");
+
+                    if let Ok(topic) = Topic::try_from(topic) {
+                        webpage.topics.push(topic);
+                    }
+                }
+                Some(Field::Text(TextField::Author)) => {
+                    let author = value
+                        .value()
+                        .as_value()
+                        .as_str()
+                        .expect("Author field should be stored as text");
+
+                    webpage.authors.push(author.to_string());
+                }
                 _ => {}
             }
         }
@@ -832,7 +1376,7 @@ mod tests {
 
         let pointers: Vec<_> = initial_result.top_websites;
 
-        let websites = index.retrieve_websites(&pointers, query)?;
+        let websites = index.retrieve_websites(&pointers, query, &SnippetOptions::default())?;
 
         Ok(SearchResult {
             num_docs: initial_result.num_websites,
@@ -1120,6 +1664,63 @@ mod tests {
         assert_eq!(result.documents[0].url, "https://www.a.com/");
     }
 
+    #[test]
+    fn searchable_expansion_terms() {
+        let mut index = InvertedIndex::temporary().expect("Unable to open index");
+
+        for (url, expansion_terms) in [
+            ("https://www.a.com", vec![("notebook".to_string(), 1.0)]),
+            ("https://www.b.com", vec![]),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+                <html>
+                    <head>
+                        <title>Cheap laptops</title>
+                    </head>
+                    <body>
+                        {CONTENT}
+                    </body>
+                </html>
+                "#
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    expansion_terms,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+
+        let ctx = index.local_search_ctx();
+        let query = Query::parse(
+            &ctx,
+            &SearchQuery {
+                query: "notebook".to_string(),
+                ..Default::default()
+            },
+            &index,
+        )
+        .expect("Failed to parse query");
+        let ranker = Ranker::new(
+            SignalAggregator::new(Some(&query)),
+            ctx.fastfield_reader.clone(),
+            Default::default(),
+        );
+
+        let result =
+            search(&index, &query, &ctx, ranker.collector(ctx.clone())).expect("Search failed");
+
+        assert_eq!(result.documents.len(), 1);
+        assert_eq!(result.documents[0].url, "https://www.a.com/");
+    }
+
     #[test]
     fn limited_top_docs() {
         let mut index = InvertedIndex::temporary().expect("Unable to open index");
@@ -1516,6 +2117,178 @@ mod tests {
         assert_eq!(webpage.url, "https://www.example.com/".to_string());
     }
 
+    #[test]
+    fn patch_webpage() {
+        let mut index = InvertedIndex::temporary().expect("Unable to open index");
+
+        index
+            .insert(
+                Webpage::new(
+                    &format!(
+                        r#"
+                    <html>
+                        <head>
+                            <title>Old title</title>
+                        </head>
+                        <body>
+                            {CONTENT} test
+                        </body>
+                    </html>
+                "#
+                    ),
+                    "https://www.example.com/",
+                )
+                .unwrap(),
+            )
+            .expect("failed to insert webpage");
+
+        index.commit().expect("failed to commit index");
+        let old = index.get_webpage("https://www.example.com/").unwrap();
+
+        let patched = Webpage::new(
+            r#"
+                    <html>
+                        <head>
+                            <title>New title</title>
+                            <script type="application/ld+json">{"@context":"http://schema.org","@type":"Person","name":"Someone"}</script>
+                        </head>
+                        <body>
+                            A much shorter page
+                        </body>
+                    </html>
+                "#,
+            "https://www.example.com/",
+        )
+        .unwrap();
+
+        assert!(index
+            .patch_webpage(
+                patched,
+                &[
+                    Field::Text(TextField::SchemaOrgJson),
+                    Field::Text(TextField::FlattenedSchemaOrgJson)
+                ],
+            )
+            .unwrap());
+        index.commit().expect("failed to commit index");
+
+        let webpage = index.get_webpage("https://www.example.com/").unwrap();
+        assert_eq!(webpage.title, "Old title".to_string());
+        assert_eq!(webpage.num_words, old.num_words);
+        assert_eq!(webpage.schema_org.len(), 1);
+        assert!(old.schema_org.is_empty());
+        assert_eq!(index.reader.searcher().num_docs(), 1);
+
+        let missing = Webpage::new(
+            "<html><head><title>Missing</title></head></html>",
+            "https://www.missing.com/",
+        )
+        .unwrap();
+        assert!(!index.patch_webpage(missing, &[]).unwrap());
+    }
+
+    #[test]
+    fn url_status() {
+        let mut index = InvertedIndex::temporary().expect("Unable to open index");
+
+        index
+            .insert(
+                Webpage::new(
+                    &format!(
+                        r#"
+                    <html>
+                        <head>
+                            <title>Test website</title>
+                        </head>
+                        <body>
+                            {CONTENT} test
+                        </body>
+                    </html>
+                "#
+                    ),
+                    "https://www.example.com/page?utm_source=feed#top",
+                )
+                .unwrap(),
+            )
+            .expect("failed to insert webpage");
+
+        index.commit().expect("failed to commit index");
+
+        assert_eq!(
+            index.url_status("https://www.example.com/page").unwrap(),
+            UrlIndexStatus::Indexed {
+                url: "https://www.example.com/page".to_string()
+            }
+        );
+        assert_eq!(
+            index
+                .url_status("https://www.example.com/page?utm_medium=email")
+                .unwrap(),
+            UrlIndexStatus::Indexed {
+                url: "https://www.example.com/page".to_string()
+            }
+        );
+        assert_eq!(
+            index.url_status("https://www.example.com/other").unwrap(),
+            UrlIndexStatus::NotIndexed
+        );
+        assert_eq!(
+            index.url_status("not a url").unwrap(),
+            UrlIndexStatus::NotIndexed
+        );
+
+        index
+            .tombstone([DocAddress {
+                segment: 0,
+                doc_id: 0,
+            }])
+            .unwrap();
+
+        assert_eq!(
+            index.url_status("https://www.example.com/page").unwrap(),
+            UrlIndexStatus::Deleted
+        );
+
+        index
+            .insert(
+                Webpage::new(
+                    &format!(
+                        r#"
+                    <html>
+                        <head>
+                            <title>Canonicalized website</title>
+                            <link rel="canonical" href="https://example.com/canonical" />
+                        </head>
+                        <body>
+                            {CONTENT} test
+                        </body>
+                    </html>
+                "#
+                    ),
+                    "https://www.example.com/article?id=1",
+                )
+                .unwrap(),
+            )
+            .expect("failed to insert webpage");
+
+        index.commit().expect("failed to commit index");
+
+        assert_eq!(
+            index
+                .url_status("https://www.example.com/article?id=1&utm_source=feed")
+                .unwrap(),
+            UrlIndexStatus::Indexed {
+                url: "https://example.com/canonical".to_string()
+            }
+        );
+        assert_eq!(
+            index.url_status("https://example.com/canonical").unwrap(),
+            UrlIndexStatus::Indexed {
+                url: "https://example.com/canonical".to_string()
+            }
+        );
+    }
+
     #[test]
     fn get_homepage() {
         let mut index = InvertedIndex::temporary().expect("Unable to open index");
@@ -1650,4 +2423,147 @@ mod tests {
 
         assert_eq!(result.documents.len(), 1);
     }
+
+    #[test]
+    fn pre_computed_score_deletion() {
+        let mut index = InvertedIndex::temporary().expect("Unable to open index");
+
+        for (i, score) in [0.1, 0.5, 0.9].into_iter().enumerate() {
+            let webpage = Webpage {
+                html: Html::parse(
+                    &format!(
+                        r#"
+                    <html>
+                        <head>
+                            <title>Test website</title>
+                        </head>
+                        <body>
+                            {CONTENT} test
+                        </body>
+                    </html>
+                "#,
+                        CONTENT = crate::rand_words(100)
+                    ),
+                    &format!("https://www.{i}.com"),
+                )
+                .unwrap(),
+                pre_computed_score: score,
+                ..Default::default()
+            };
+
+            index.insert(webpage).unwrap();
+        }
+
+        index.commit().expect("failed to commit index");
+
+        let mut scores = index.pre_computed_scores().unwrap();
+        scores.sort_by(|a, b| a.total_cmp(b));
+        assert_eq!(scores, vec![0.1, 0.5, 0.9]);
+
+        index.delete_by_pre_computed_score(..0.5).unwrap();
+        index.commit().expect("failed to commit index");
+
+        let mut scores = index.pre_computed_scores().unwrap();
+        scores.sort_by(|a, b| a.total_cmp(b));
+        assert_eq!(scores, vec![0.5, 0.9]);
+    }
+
+    #[test]
+    fn region_deletion() {
+        let mut index = InvertedIndex::temporary().expect("Unable to open index");
+
+        for (i, text) in [
+            "this is an english page about the weather and the news of today",
+            "dette er en dansk side om vejret og dagens nyheder i landet",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let webpage = Webpage {
+                html: Html::parse(
+                    &format!(
+                        r#"
+                    <html>
+                        <head>
+                            <title>Test website</title>
+                        </head>
+                        <body>
+                            {text} {text} {text}
+                        </body>
+                    </html>
+                "#
+                    ),
+                    &format!("https://www.{i}.com"),
+                )
+                .unwrap(),
+                ..Default::default()
+            };
+
+            index.insert(webpage).unwrap();
+        }
+
+        index.commit().expect("failed to commit index");
+        assert_eq!(index.document_summaries().unwrap().len(), 2);
+
+        index.delete_by_region(Region::Denmark).unwrap();
+        index.commit().expect("failed to commit index");
+
+        let docs = index.document_summaries().unwrap();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(index.url(docs[0].address).unwrap(), "https://www.0.com/");
+    }
+
+    #[test]
+    fn products() {
+        let mut index = InvertedIndex::temporary().expect("Unable to open index");
+
+        for (url, schema) in [
+            (
+                "https://shop.example.com/espresso",
+                r#"<script type="application/ld+json">
+                {
+                    "@context": "https://schema.org",
+                    "@type": "Product",
+                    "name": "Espresso machine",
+                    "offers": { "@type": "Offer", "price": "99.50", "priceCurrency": "EUR" }
+                }
+                </script>"#,
+            ),
+            ("https://blog.example.com/espresso", ""),
+        ] {
+            let webpage = Webpage {
+                html: Html::parse(
+                    &format!(
+                        r#"
+                    <html>
+                        <head>
+                            <title>Espresso</title>
+                            {schema}
+                        </head>
+                        <body>
+                            the best espresso machine for your kitchen
+                        </body>
+                    </html>
+                "#
+                    ),
+                    url,
+                )
+                .unwrap(),
+                ..Default::default()
+            };
+
+            index.insert(webpage).unwrap();
+        }
+
+        index.commit().expect("failed to commit index");
+
+        let products = index.products().unwrap();
+        assert_eq!(products.len(), 1);
+
+        let (url, product) = &products[0];
+        assert_eq!(url, "https://shop.example.com/espresso");
+        assert_eq!(product.name, "Espresso machine");
+        assert_eq!(product.price, 99.5);
+        assert_eq!(product.currency.as_deref(), Some("EUR"));
+    }
 }
@@ -0,0 +1,103 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A dedicated pool of threads for blocking index reads.
+//!
+//! The index is memory mapped, so reading stored fields or positions from a
+//! cold segment blocks the reading thread on page faults. When this happens on
+//! a tokio worker thread it stalls every other request scheduled on that worker.
+//! Jobs spawned on the pool run on their own threads and the result is awaited
+//! asynchronously, so the tokio workers are free to serve other requests while
+//! the pool waits for the disk. The size of the pool bounds the number of
+//! concurrent reads.
+
+use std::panic::{self, AssertUnwindSafe};
+
+use rayon::ThreadPool;
+
+use crate::{config::ThreadPoolConfig, threads, Error, Result};
+
+pub struct IoPool {
+    pool: ThreadPool,
+}
+
+impl IoPool {
+    pub fn new(config: &ThreadPoolConfig) -> Result<Self> {
+        Ok(Self {
+            pool: threads::rayon_pool(config, "io-pool-")?,
+        })
+    }
+
+    /// Run `f` on the pool and wait for the result without blocking the
+    /// current tokio worker. A panic in `f` is returned as an error.
+    pub async fn spawn<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+
+        self.pool.spawn(move || {
+            let res = panic::catch_unwind(AssertUnwindSafe(f));
+            // the receiver might have been dropped if the request was cancelled
+            let _ = tx.send(res);
+        });
+
+        match rx.await {
+            Ok(Ok(res)) => Ok(res),
+            Ok(Err(_)) => Err(Error::InternalError("io pool job panicked".to_string()).into()),
+            Err(_) => Err(Error::InternalError("io pool job was dropped".to_string()).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(num_threads: usize) -> IoPool {
+        IoPool::new(&ThreadPoolConfig {
+            num_threads: Some(num_threads),
+            pin_cores: None,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn spawn() {
+        let pool = pool(2);
+
+        let res = pool.spawn(|| 1 + 1).await.unwrap();
+        assert_eq!(res, 2);
+
+        let name = pool
+            .spawn(|| std::thread::current().name().map(|s| s.to_string()))
+            .await
+            .unwrap();
+        assert!(name.unwrap().starts_with("io-pool-"));
+    }
+
+    #[tokio::test]
+    async fn panic_is_error() {
+        let pool = pool(1);
+
+        let res = pool.spawn(|| -> usize { panic!("boom") }).await;
+        assert!(res.is_err());
+
+        // the pool is still usable after a panic
+        assert_eq!(pool.spawn(|| 3).await.unwrap(), 3);
+    }
+}
@@ -14,7 +14,11 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{fs, marker::PhantomData};
+use std::{
+    fs,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
 
 use rocksdb::{
     BlockBasedOptions, DBIteratorWithThreadMode, DBWithThreadMode, IteratorMode, Options,
@@ -22,7 +26,51 @@ use rocksdb::{
 };
 use serde::{de::DeserializeOwned, Serialize};
 
-use crate::kv::Kv;
+use crate::{
+    kv::Kv,
+    memory_budget::{self, MemoryConsumer},
+};
+
+const BLOCK_CACHE_CAPACITY: usize = 256 * 1024 * 1024; // 256 mb
+
+/// The block cache of a store. To evict, the capacity of the cache is
+/// temporarily lowered so rocksdb drops its least recently used blocks and
+/// then restored, so the cache can grow again once the pressure is gone.
+struct BlockCache {
+    cache: Mutex<rocksdb::Cache>,
+}
+
+impl BlockCache {
+    fn new() -> Arc<Self> {
+        let cache = Arc::new(Self {
+            cache: Mutex::new(rocksdb::Cache::new_lru_cache(BLOCK_CACHE_CAPACITY)),
+        });
+        memory_budget::global().register(cache.clone());
+
+        cache
+    }
+}
+
+impl MemoryConsumer for BlockCache {
+    fn name(&self) -> &str {
+        "rocksdb_block_cache"
+    }
+
+    fn size_bytes(&self) -> usize {
+        self.cache.lock().unwrap().get_usage()
+    }
+
+    fn evict(&self, bytes: usize) -> usize {
+        let mut cache = self.cache.lock().unwrap();
+        let usage = cache.get_usage();
+
+        cache.set_capacity(usage.saturating_sub(bytes));
+        let freed = usage.saturating_sub(cache.get_usage());
+        cache.set_capacity(BLOCK_CACHE_CAPACITY);
+
+        freed
+    }
+}
 
 pub struct RocksDbStore<K, V>
 where
@@ -30,7 +78,7 @@ where
     V: Serialize + DeserializeOwned,
 {
     db: DB,
-    _cache: rocksdb::Cache,
+    _cache: Arc<BlockCache>,
     _phantom: PhantomData<(K, V)>,
 }
 
@@ -75,8 +123,8 @@ where
             fs::create_dir_all(path.as_ref()).expect("faild to create dir");
         }
 
-        let cache = rocksdb::Cache::new_lru_cache(256 * 1024 * 1024); // 256 mb
-        let options = Self::options(&cache);
+        let cache = BlockCache::new();
+        let options = Self::options(&cache.cache.lock().unwrap());
 
         // create db to ensure it exists
         DB::open(&options, &path).expect("unable to open rocks db");
@@ -90,6 +138,68 @@ where
         }
     }
 
+    /// Open an existing store read-only. Unlike [`RocksDbStore::open_read_only`], the store
+    /// is not created if it is missing, and failing to open it is an error instead of a panic.
+    pub fn try_open_read_only<P>(path: P) -> crate::Result<Self>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        if !path.as_ref().join("CURRENT").exists() {
+            anyhow::bail!("there is no store at {}", path.as_ref().display());
+        }
+
+        let cache = BlockCache::new();
+        let options = Self::options(&cache.cache.lock().unwrap());
+
+        let db = DB::open_for_read_only(&options, path, false)?;
+
+        Ok(Self {
+            db,
+            _cache: cache,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Open the store as a secondary instance, which reads the store while another process
+    /// writes to it. The writes are only seen after [`RocksDbStore::catch_up`]. The secondary
+    /// instance keeps its own logs in `secondary_path`.
+    pub fn open_secondary<P, S>(path: P, secondary_path: S) -> Self
+    where
+        P: AsRef<std::path::Path>,
+        S: AsRef<std::path::Path>,
+    {
+        if !path.as_ref().exists() {
+            fs::create_dir_all(path.as_ref()).expect("faild to create dir");
+        }
+
+        let cache = BlockCache::new();
+        let mut options = Self::options(&cache.cache.lock().unwrap());
+        // secondary instances must keep all the files of the primary open
+        options.set_max_open_files(-1);
+
+        // create db to ensure it exists. The primary might already have it open.
+        if !path.as_ref().join("CURRENT").exists() {
+            drop(DB::open(&options, &path).expect("unable to open rocks db"));
+        }
+
+        let db = DB::open_as_secondary(&options, path.as_ref(), secondary_path.as_ref())
+            .expect("unable to open rocks db");
+
+        Self {
+            db,
+            _cache: cache,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Read the writes of the primary instance since the store was opened or last caught up.
+    /// Only does something for stores opened with [`RocksDbStore::open_secondary`].
+    pub fn catch_up(&self) {
+        if let Err(err) = self.db.try_catch_up_with_primary() {
+            tracing::warn!("failed to catch up with the primary store: {}", err);
+        }
+    }
+
     pub fn open<P>(path: P) -> Self
     where
         P: AsRef<std::path::Path>,
@@ -98,8 +208,8 @@ where
             fs::create_dir_all(path.as_ref()).expect("faild to create dir");
         }
 
-        let cache = rocksdb::Cache::new_lru_cache(256 * 1024 * 1024); // 256 mb
-        let options = Self::options(&cache);
+        let cache = BlockCache::new();
+        let options = Self::options(&cache.cache.lock().unwrap());
 
         let db = DB::open(&options, path).expect("unable to open rocks db");
 
@@ -31,46 +31,73 @@ pub mod inverted_index;
 
 pub mod mapreduce;
 
+pub mod access_log;
 mod api;
+pub mod audit_log;
 pub mod autosuggest;
 pub mod bangs;
+mod blocklist;
 mod bloom;
+pub mod collection_stats;
 mod collector;
+pub mod common_crawl;
 pub mod config;
 pub mod crawler;
 mod distributed;
 pub mod entity_index;
 mod enum_map;
+pub mod event_log;
 mod executor;
 mod external_sort;
 mod fastfield_reader;
 pub mod feed;
+pub mod geo_store;
+mod homograph;
+mod host_policy;
+pub mod host_stats;
 mod human_website_annotations;
 pub mod hyperloglog;
 pub mod image_store;
 mod improvement;
 pub mod index;
+mod ingest_queue;
 mod intmap;
+mod io_pool;
 mod kahan_sum;
 mod kv;
 mod leaky_queue;
 mod live_index;
+mod liveness;
 mod llm_utils;
+mod locale;
+mod memory_budget;
 mod metrics;
 mod models;
 pub mod naive_bayes;
+pub mod object_store;
 pub mod prehashed;
+pub mod product_store;
 mod query;
+pub mod query_telemetry;
 pub mod ranking;
 mod schema;
+pub mod screenshot_store;
 mod search_ctx;
+mod search_overrides;
 mod search_prettifier;
 pub mod searcher;
+pub mod security;
+pub mod shard_router;
+pub mod signal_store;
 mod simhash;
 pub mod similar_hosts;
-mod snippet;
+pub mod snippet;
 pub mod summarizer;
+#[cfg(any(test, feature = "test-harness"))]
+pub mod test_harness;
+pub mod threads;
 mod tokenizer;
+pub mod tombstone;
 #[allow(unused)]
 mod ttl_cache;
 pub mod warc;
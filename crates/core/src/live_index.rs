@@ -16,7 +16,7 @@
 
 use anyhow::Result;
 use std::{
-    path::Path,
+    path::{Path, PathBuf},
     sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
     time::{Duration, SystemTime},
 };
@@ -27,13 +27,19 @@ use url::Url;
 use crate::{
     config::{CrawlerConfig, LiveIndexConfig},
     crawler::{
-        reqwest_client, CrawlDatum, DatumStream, JobExecutor, RetrieableUrl, WeightedUrl, WorkerJob,
+        reputation::{self, HostOutcome, HostReputationStore, ReputationStatus},
+        reqwest_client, CrawlDatum, DatumStream, JobExecutor, RetrieableUrl, WeightedUrl,
+        WorkerJob,
     },
     entrypoint::indexer::IndexingWorker,
     feed::{
         self,
         scheduler::{Domain, DomainFeeds, Split},
+        websub::{self, Subscriber},
     },
+    ingest_queue::IngestQueue,
+    shard_router::ShardRouter,
+    webpage::url_ext::UrlExt,
 };
 
 const TTL: Duration = Duration::from_secs(60 * 60 * 24 * 60); // 60 days
@@ -41,6 +47,8 @@ const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60); // 1 hour
 const FEED_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 10); // 10 minutes
 const AUTO_COMMIT_INTERVAL: Duration = Duration::from_secs(60 * 5); // 5 minutes
 const EVENT_LOOP_INTERVAL: Duration = Duration::from_secs(5);
+/// Number of batches from the ingest queue that are crawled concurrently.
+const BATCHES_PER_LOOP: usize = 64;
 
 #[derive(Debug, Clone)]
 struct Feeds {
@@ -110,17 +118,15 @@ struct Indexer {
     worker: IndexingWorker,
 }
 
-enum CrawlResults {
-    None,
-    HasInserts,
-}
-
 struct Crawler {
     feeds: Vec<Feeds>,
     indexer: Arc<Indexer>,
     downloaded_db: DownloadedDb,
+    queue: Arc<IngestQueue>,
     config: Arc<CrawlerConfig>,
     client: reqwest::Client,
+    reputation: Option<HostReputationStore>,
+    websub: Option<Arc<Subscriber>>,
 }
 
 impl Crawler {
@@ -128,7 +134,9 @@ impl Crawler {
         split: Split,
         indexer: Arc<Indexer>,
         downloaded_db: DownloadedDb,
+        queue: Arc<IngestQueue>,
         config: Arc<CrawlerConfig>,
+        reputation: Option<HostReputationStore>,
     ) -> Result<Self> {
         let client = reqwest_client(&config)?;
 
@@ -136,14 +144,53 @@ impl Crawler {
             feeds: split.into(),
             indexer,
             downloaded_db,
+            queue,
             config,
             client,
+            reputation,
+            websub: None,
         })
     }
 
-    async fn process_urls(&self, urls: Vec<Url>) -> Result<bool> {
+    fn is_demoted(&self, url: &Url) -> bool {
+        self.reputation
+            .as_ref()
+            .map(|reputation| {
+                reputation.status(
+                    url.normalized_host().unwrap_or_default(),
+                    reputation::now_ms(),
+                ) == ReputationStatus::Demoted
+            })
+            .unwrap_or(false)
+    }
+
+    fn record_feed_error(&self, url: &Url) {
+        if let Some(reputation) = &self.reputation {
+            reputation.record([HostOutcome {
+                host: url.normalized_host().unwrap_or_default().to_string(),
+                timestamp: reputation::now_ms(),
+                successes: 0,
+                errors: 1,
+                ..Default::default()
+            }]);
+        }
+    }
+
+    async fn process_urls(&self, urls: Vec<Url>) -> Result<()> {
+        // urls owned by other shards are indexed by the live indexes of those shards.
+        let urls: Vec<_> = urls
+            .into_iter()
+            .filter(|url| self.indexer.worker.owns(url.as_str()))
+            .collect();
+
+        // demoted hosts are skipped and hosts that are due for a probe only get the probe budget.
+        let urls = match &self.reputation {
+            Some(reputation) => reputation.within_budget(urls, reputation::now_ms()),
+            None => urls,
+        };
+
         if urls.is_empty() {
-            return Ok(false);
+            return Ok(());
         }
 
         let domain = urls.first().unwrap().into();
@@ -163,26 +210,50 @@ impl Crawler {
             self.config.clone(),
             self.indexer.clone(),
         );
-        executor.run().await;
+        let outcomes = executor.run().await;
+
+        if let Some(reputation) = &self.reputation {
+            reputation.record(outcomes);
+        }
 
         for url in &urls {
             self.downloaded_db.insert(url)?;
         }
 
-        Ok(true)
+        Ok(())
     }
 
-    async fn process_feed(&self, domain_feeds: &DomainFeeds) -> Result<bool> {
+    /// Add the new links in the feeds to the ingest queue.
+    async fn process_feed(&self, domain_feeds: &DomainFeeds) -> Result<()> {
         let mut urls = Vec::new();
 
         for feed in domain_feeds.feeds.iter() {
-            let content = self
-                .client
-                .get(feed.url.as_str())
-                .send()
-                .await?
-                .text()
-                .await?;
+            if self.is_demoted(&feed.url) {
+                continue;
+            }
+
+            let res = self.client.get(feed.url.as_str()).send().await;
+
+            if reputation::is_host_error(res.as_ref().ok().map(|res| res.status().as_u16())) {
+                self.record_feed_error(&feed.url);
+            }
+
+            let res = res?;
+            let link_header = res
+                .headers()
+                .get(reqwest::header::LINK)
+                .and_then(|header| header.to_str().ok())
+                .map(|header| header.to_string());
+            let content = res.text().await?;
+
+            if let Some(subscriber) = &self.websub {
+                if let Err(e) = subscriber
+                    .subscribe_if_advertised(&feed.url, &content, link_header.as_deref())
+                    .await
+                {
+                    tracing::debug!("failed to subscribe to {}: {:?}", feed.url, e);
+                }
+            }
 
             let feed = feed::parse(&content, feed.kind)?;
 
@@ -195,10 +266,14 @@ impl Crawler {
             }
         }
 
-        self.process_urls(urls).await
+        if !urls.is_empty() {
+            self.queue.push(urls)?;
+        }
+
+        Ok(())
     }
 
-    async fn check_feeds(&mut self) -> CrawlResults {
+    async fn check_feeds(&mut self) {
         let mut feeds = self.feeds.clone();
 
         let mut futures = Vec::new();
@@ -209,15 +284,13 @@ impl Crawler {
             }
         }
 
-        let res = futures::future::join_all(futures).await;
+        for res in futures::future::join_all(futures).await {
+            if let Err(e) = res {
+                tracing::debug!("failed to check feed: {:?}", e);
+            }
+        }
 
         self.feeds = feeds;
-
-        if res.iter().filter_map(|r| r.as_ref().ok()).any(|r| *r) {
-            CrawlResults::HasInserts
-        } else {
-            CrawlResults::None
-        }
     }
 }
 
@@ -236,12 +309,11 @@ impl Index {
         Ok(Self { search_index })
     }
 
-    fn commit(&self) {
+    fn commit(&self) -> Result<()> {
         self.search_index
             .write()
             .unwrap_or_else(|e| e.into_inner())
             .commit()
-            .ok();
     }
 
     fn prune(&self) {
@@ -314,6 +386,13 @@ impl From<&LiveIndexConfig> for CrawlerConfig {
                 endpoint: String::new(),
             },
             router_hosts: Vec::new(),
+            trap_detection: Default::default(),
+            max_host_error_streak: live.max_host_error_streak,
+            host_outcomes_path: None,
+            url_outcomes_path: None,
+            event_log: None,
+            screenshots: None,
+            tls: None,
         }
     }
 }
@@ -321,46 +400,117 @@ impl From<&LiveIndexConfig> for CrawlerConfig {
 pub struct IndexManager {
     index: Arc<Index>,
     crawler: Crawler,
+    queue: Arc<IngestQueue>,
+    websub: Option<websub::Callback>,
 }
 
 impl IndexManager {
     pub fn new(config: LiveIndexConfig) -> Result<Self> {
         let index = Index::new(&config.index_path)?;
+
+        let mut worker = IndexingWorker::new(
+            config.host_centrality_store_path.clone(),
+            config.page_centrality_store_path.clone(),
+            config.page_webgraph_path.clone(),
+            None,
+            config.safety_classifier_path.clone(),
+        );
+
+        if let Some((router, shard)) = ShardRouter::for_shard(config.routing, config.shard_id)? {
+            worker.set_shard(router, shard);
+        }
+
         let indexer = Arc::new(Indexer {
             search_index: index.clone_inner_index(),
-            worker: IndexingWorker::new(
-                config.host_centrality_store_path.clone(),
-                config.page_centrality_store_path.clone(),
-                config.page_webgraph_path.clone(),
-                None,
-                config.safety_classifier_path.clone(),
-            ),
+            worker,
         });
 
         let crawler_config = CrawlerConfig::from(&config);
+        let queue_path = match &config.ingest_queue_path {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(&config.downloaded_db_path).with_file_name("ingest_queue"),
+        };
+        let queue = Arc::new(IngestQueue::open(queue_path)?);
 
-        let crawler = Crawler::new(
+        let mut crawler = Crawler::new(
             Split::open(&config.split_path)?,
             indexer,
             DownloadedDb::open(&config.downloaded_db_path)?,
+            queue.clone(),
             Arc::new(crawler_config),
+            config
+                .host_reputation
+                .clone()
+                .map(HostReputationStore::open),
         )?;
 
+        let websub = config
+            .websub
+            .clone()
+            .map(|websub_config| {
+                let subscriber = Arc::new(Subscriber::open(
+                    websub_config,
+                    &crawler.config.user_agent.full,
+                )?);
+                crawler.websub = Some(subscriber.clone());
+
+                Ok::<_, anyhow::Error>(websub::Callback {
+                    subscriber,
+                    queue: queue.clone(),
+                })
+            })
+            .transpose()?;
+
         Ok(Self {
             index: Arc::new(index),
             crawler,
+            queue,
+            websub,
         })
     }
 
+    /// Crawl the batches in the ingest queue. Returns the ids of the batches that were crawled.
+    async fn process_queue(&self) -> Vec<u64> {
+        let batches = match self.queue.take(BATCHES_PER_LOOP) {
+            Ok(batches) => batches,
+            Err(e) => {
+                tracing::error!("failed to read the ingest queue: {:?}", e);
+                return Vec::new();
+            }
+        };
+
+        // pushed urls are crawled even if they have been downloaded before,
+        // as the publisher has told us that they have been updated.
+        let futures = batches.into_iter().map(|batch| async move {
+            let res = self.crawler.process_urls(batch.urls).await;
+            (batch.id, res)
+        });
+
+        let mut processed = Vec::new();
+
+        for (id, res) in futures::future::join_all(futures).await {
+            match res {
+                Ok(()) => processed.push(id),
+                Err(e) => {
+                    tracing::error!("failed to process queued urls: {:?}", e);
+                    self.queue.release([id]);
+                }
+            }
+        }
+
+        processed
+    }
+
     pub async fn run(mut self) {
-        let mut has_inserts = false;
+        // batches that have been crawled, but whose pages are not yet committed
+        let mut uncommitted = Vec::new();
         let mut last_commit = Utc::now();
         let mut last_prune = Utc::now();
 
         loop {
-            if let CrawlResults::HasInserts = self.crawler.check_feeds().await {
-                has_inserts = true;
-            }
+            self.crawler.check_feeds().await;
+
+            uncommitted.extend(self.process_queue().await);
 
             if last_prune + PRUNE_INTERVAL < Utc::now() {
                 self.index.prune();
@@ -368,11 +518,19 @@ impl IndexManager {
                 last_prune = Utc::now();
             }
 
-            if last_commit + AUTO_COMMIT_INTERVAL < Utc::now() && has_inserts {
-                self.index.commit();
+            if last_commit + AUTO_COMMIT_INTERVAL < Utc::now() && !uncommitted.is_empty() {
+                // the batches are only acked once their pages are committed, so they are
+                // crawled again after a restart if the index was not committed.
+                match self.index.commit() {
+                    Ok(()) => {
+                        if let Err(e) = self.queue.ack(uncommitted.drain(..)) {
+                            tracing::error!("failed to ack the ingest queue: {:?}", e);
+                        }
+                    }
+                    Err(e) => tracing::error!("failed to commit the live index: {:?}", e),
+                }
 
                 last_commit = Utc::now();
-                has_inserts = false;
             }
 
             tokio::time::sleep(EVENT_LOOP_INTERVAL).await;
@@ -382,4 +540,9 @@ impl IndexManager {
     pub fn index(&self) -> Arc<Index> {
         self.index.clone()
     }
+
+    /// The state of the WebSub callback server if it is enabled.
+    pub fn websub(&self) -> Option<websub::Callback> {
+        self.websub.clone()
+    }
 }
@@ -0,0 +1,343 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Liveness of urls based on their latest fetch.
+//!
+//! The crawl workers log the outcome of every url they fetch. The planner folds the
+//! logs into a [`LivenessStore`] that the api uses to mark results whose latest fetch
+//! failed, and link to an archived copy of the page instead. Only failures that say the
+//! page is gone are logged, so a timeout or a reset connection does not mark a page as dead.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::LivenessConfig,
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    Result,
+};
+
+/// Error messages of the resolver when the name of the host does not exist (NXDOMAIN).
+/// Temporary resolution failures are not included.
+const HOST_NOT_FOUND_MESSAGES: [&str; 3] = [
+    "Name or service not known",
+    "nodename nor servname provided, or not known",
+    "No such host is known",
+];
+
+/// Whether a failed fetch says that the page is unavailable. Error responses do, except
+/// for rate limiting. Requests that failed without a response only do if the host does not exist.
+pub fn is_conclusive(status_code: Option<u16>, host_not_found: bool) -> bool {
+    match status_code {
+        Some(429) => false,
+        Some(_) => true,
+        None => host_not_found,
+    }
+}
+
+/// Whether the request failed because the name of the host could not be resolved.
+pub fn is_host_not_found(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        let message = cause.to_string();
+        HOST_NOT_FOUND_MESSAGES
+            .iter()
+            .any(|not_found| message.contains(not_found))
+    })
+}
+
+/// The outcome of a single fetch of a url.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UrlOutcome {
+    pub url: String,
+    /// Unix timestamp in milliseconds of the fetch.
+    pub timestamp: u64,
+    /// Status code of the response. Not set if the request failed.
+    pub status_code: Option<u16>,
+    pub ok: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Liveness {
+    /// Unix timestamp in milliseconds of the latest fetch.
+    pub last_fetch: u64,
+    pub status_code: Option<u16>,
+    pub ok: bool,
+    /// Unix timestamp in milliseconds of the latest successful fetch.
+    pub last_success: Option<u64>,
+}
+
+impl Liveness {
+    /// Update the liveness with the outcome of a fetch. Outcomes that are older than
+    /// the latest recorded fetch are ignored, so the same log can be recorded more than once.
+    pub fn record(&mut self, outcome: &UrlOutcome) {
+        if outcome.timestamp < self.last_fetch {
+            return;
+        }
+
+        self.last_fetch = outcome.timestamp;
+        self.status_code = outcome.status_code;
+        self.ok = outcome.ok;
+
+        if outcome.ok {
+            self.last_success = Some(outcome.timestamp);
+        }
+    }
+
+    /// Url of the archived copy of the page closest to its latest successful fetch.
+    pub fn archive_url(&self, prefix: &str, url: &str) -> String {
+        match self
+            .last_success
+            .and_then(|ms| Utc.timestamp_millis_opt(ms as i64).single())
+        {
+            Some(time) => format!("{prefix}{}/{url}", time.format("%Y%m%d%H%M%S")),
+            None => format!("{prefix}{url}"),
+        }
+    }
+}
+
+pub fn read_outcomes<P: AsRef<Path>>(path: P) -> Result<Vec<UrlOutcome>> {
+    let file = File::open(path)?;
+    let mut outcomes = Vec::new();
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        outcomes.push(serde_json::from_str(&line)?);
+    }
+
+    Ok(outcomes)
+}
+
+/// Log of url outcomes shared by the worker threads.
+pub struct UrlOutcomeLog {
+    file: Mutex<File>,
+}
+
+impl UrlOutcomeLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn append(&self, outcomes: &[UrlOutcome]) -> Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+
+        for outcome in outcomes {
+            writeln!(file, "{}", serde_json::to_string(outcome)?)?;
+        }
+
+        file.flush()?;
+
+        Ok(())
+    }
+}
+
+pub struct LivenessStore {
+    store: RocksDbStore<String, Liveness>,
+    archive_url_prefix: String,
+}
+
+impl LivenessStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            store: RocksDbStore::open(path),
+            archive_url_prefix: crate::config::defaults::Liveness::archive_url_prefix(),
+        }
+    }
+
+    /// Open the store for reading while the planner writes to it.
+    /// New outcomes are seen after [`LivenessStore::refresh`].
+    pub fn open_reader(config: &LivenessConfig) -> Self {
+        Self {
+            store: RocksDbStore::open_secondary(&config.store_path, &config.secondary_path),
+            archive_url_prefix: config.archive_url_prefix.clone(),
+        }
+    }
+
+    pub fn refresh(&self) {
+        self.store.catch_up();
+    }
+
+    pub fn get(&self, url: &str) -> Option<Liveness> {
+        self.store.get(&url.to_string())
+    }
+
+    /// Url of an archived copy of the page if its latest fetch failed.
+    pub fn fallback(&self, url: &str) -> Option<String> {
+        self.get(url)
+            .filter(|liveness| !liveness.ok)
+            .map(|liveness| liveness.archive_url(&self.archive_url_prefix, url))
+    }
+
+    pub fn record(&self, outcomes: impl IntoIterator<Item = UrlOutcome>) {
+        for outcome in outcomes {
+            let mut liveness = self.get(&outcome.url).unwrap_or_default();
+            liveness.record(&outcome);
+            self.store.insert(outcome.url, liveness);
+        }
+    }
+
+    pub fn flush(&self) {
+        self.store.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(timestamp: u64, status_code: Option<u16>) -> UrlOutcome {
+        UrlOutcome {
+            url: "https://example.com/".to_string(),
+            timestamp,
+            status_code,
+            ok: status_code == Some(200),
+        }
+    }
+
+    #[test]
+    fn latest_fetch_wins() {
+        let mut liveness = Liveness::default();
+
+        liveness.record(&outcome(1_000, Some(200)));
+        liveness.record(&outcome(3_000, Some(404)));
+        liveness.record(&outcome(2_000, Some(200)));
+
+        assert!(!liveness.ok);
+        assert_eq!(liveness.status_code, Some(404));
+        assert_eq!(liveness.last_success, Some(1_000));
+
+        liveness.record(&outcome(4_000, None));
+        assert_eq!(liveness.status_code, None);
+        assert_eq!(liveness.last_fetch, 4_000);
+    }
+
+    #[test]
+    fn archive_url() {
+        let prefix = "https://web.archive.org/web/";
+        let url = "https://example.com/page";
+
+        let never_fetched = Liveness::default();
+        assert_eq!(
+            never_fetched.archive_url(prefix, url),
+            "https://web.archive.org/web/https://example.com/page"
+        );
+
+        let mut liveness = Liveness::default();
+        liveness.record(&UrlOutcome {
+            url: url.to_string(),
+            timestamp: 1_700_000_000_000,
+            status_code: Some(200),
+            ok: true,
+        });
+        assert_eq!(
+            liveness.archive_url(prefix, url),
+            "https://web.archive.org/web/20231114221320/https://example.com/page"
+        );
+    }
+
+    #[test]
+    fn store_fallback() {
+        let path = crate::gen_temp_path();
+        let store = LivenessStore::open(&path);
+
+        store.record([
+            outcome(1_000, Some(200)),
+            UrlOutcome {
+                url: "https://alive.com/".to_string(),
+                timestamp: 1_000,
+                status_code: Some(200),
+                ok: true,
+            },
+            outcome(2_000, Some(410)),
+        ]);
+        store.flush();
+
+        assert!(store.fallback("https://example.com/").is_some());
+        assert_eq!(store.fallback("https://alive.com/"), None);
+        assert_eq!(store.fallback("https://unknown.com/"), None);
+    }
+
+    #[test]
+    fn only_definitive_failures_are_conclusive() {
+        assert!(is_conclusive(Some(404), false));
+        assert!(is_conclusive(Some(503), false));
+        assert!(!is_conclusive(Some(429), false));
+        assert!(!is_conclusive(None, false));
+        assert!(is_conclusive(None, true));
+
+        let not_found = anyhow::Error::new(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "failed to lookup address information: Name or service not known",
+        ))
+        .context("error sending request");
+        let temporary = anyhow::Error::new(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "failed to lookup address information: Temporary failure in name resolution",
+        ));
+        let timeout = anyhow::anyhow!("operation timed out");
+
+        assert!(is_host_not_found(&not_found));
+        assert!(!is_host_not_found(&temporary));
+        assert!(!is_host_not_found(&timeout));
+    }
+
+    #[test]
+    fn reader_sees_new_outcomes_after_refresh() {
+        let config = LivenessConfig {
+            store_path: crate::gen_temp_path().to_str().unwrap().to_string(),
+            secondary_path: crate::gen_temp_path().to_str().unwrap().to_string(),
+            archive_url_prefix: crate::config::defaults::Liveness::archive_url_prefix(),
+            refresh_interval_sec: crate::config::defaults::Liveness::refresh_interval_sec(),
+        };
+        let writer = LivenessStore::open(&config.store_path);
+        let reader = LivenessStore::open_reader(&config);
+
+        writer.record([outcome(1_000, Some(404))]);
+        writer.flush();
+        reader.refresh();
+
+        assert!(reader.fallback("https://example.com/").is_some());
+    }
+
+    #[test]
+    fn outcome_log() {
+        let path = crate::gen_temp_path();
+        let log = UrlOutcomeLog::open(&path).unwrap();
+
+        log.append(&[outcome(1_000, Some(200))]).unwrap();
+        log.append(&[outcome(2_000, None)]).unwrap();
+
+        assert_eq!(
+            read_outcomes(&path).unwrap(),
+            vec![outcome(1_000, Some(200)), outcome(2_000, None)]
+        );
+    }
+}
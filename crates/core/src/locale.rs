@@ -0,0 +1,515 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Localized query syntax. Search operators and the words used in widget queries
+//! are translated to their english form before the query is parsed, so the parser
+//! and the widgets only have to understand a single language.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use whatlang::Lang;
+
+static NUMBER_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\d[\d.,]*\d").unwrap());
+static DECIMAL_POINT_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\d)\.(\d)").unwrap());
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Locale {
+    #[default]
+    English,
+    German,
+    French,
+    Spanish,
+    Italian,
+    Dutch,
+}
+
+impl Locale {
+    /// The locale from a language tag like `de`, `de-AT` or `de_AT`.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let language = tag
+            .trim()
+            .split(['-', '_'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        match language.as_str() {
+            "en" => Some(Locale::English),
+            "de" => Some(Locale::German),
+            "fr" => Some(Locale::French),
+            "es" => Some(Locale::Spanish),
+            "it" => Some(Locale::Italian),
+            "nl" => Some(Locale::Dutch),
+            _ => None,
+        }
+    }
+
+    /// The preferred supported locale from an `Accept-Language` header,
+    /// e.g. `fr-CH, fr;q=0.9, en;q=0.8`.
+    pub fn from_accept_language(header: &str) -> Option<Self> {
+        Self::preferred_tag(header).and_then(Self::from_tag)
+    }
+
+    /// The language tag of the preferred supported locale from an `Accept-Language` header.
+    pub fn preferred_tag(header: &str) -> Option<&str> {
+        let mut tags: Vec<(f32, &str)> = header
+            .split(',')
+            .filter_map(|part| {
+                let mut parts = part.split(';');
+                let tag = parts.next()?.trim();
+                Locale::from_tag(tag)?;
+
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+
+                Some((quality, tag))
+            })
+            .collect();
+
+        // stable sort keeps the header order for languages with the same quality.
+        tags.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        tags.into_iter()
+            .find(|(quality, _)| *quality > 0.0)
+            .map(|(_, tag)| tag)
+    }
+
+    /// The region of a language tag like `de-AT` or `de_AT`, e.g. `AT`.
+    pub fn region_from_tag(tag: &str) -> Option<String> {
+        let region = tag.trim().split(['-', '_']).nth(1)?;
+
+        if region.len() == 2 && region.chars().all(|c| c.is_ascii_alphabetic()) {
+            Some(region.to_ascii_uppercase())
+        } else {
+            None
+        }
+    }
+
+    /// The language tag of the locale, e.g. `de`.
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Locale::English => "en",
+            Locale::German => "de",
+            Locale::French => "fr",
+            Locale::Spanish => "es",
+            Locale::Italian => "it",
+            Locale::Dutch => "nl",
+        }
+    }
+
+    /// The language used to select language specific models like the spell checker.
+    pub fn lang(&self) -> Lang {
+        match self {
+            Locale::English => Lang::Eng,
+            Locale::German => Lang::Deu,
+            Locale::French => Lang::Fra,
+            Locale::Spanish => Lang::Spa,
+            Locale::Italian => Lang::Ita,
+            Locale::Dutch => Lang::Nld,
+        }
+    }
+
+    /// The locale of a language detected in the pages, if the language is supported.
+    pub fn from_lang(lang: Lang) -> Option<Self> {
+        match lang {
+            Lang::Eng => Some(Locale::English),
+            Lang::Deu => Some(Locale::German),
+            Lang::Fra => Some(Locale::French),
+            Lang::Spa => Some(Locale::Spanish),
+            Lang::Ita => Some(Locale::Italian),
+            Lang::Nld => Some(Locale::Dutch),
+            _ => None,
+        }
+    }
+
+    /// Localized operator prefixes and the operator they are an alias for.
+    fn operators(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Locale::English => &[],
+            Locale::German => &[
+                ("seite:", "site:"),
+                ("intitel:", "intitle:"),
+                ("intext:", "inbody:"),
+                ("thema:", "topic:"),
+            ],
+            Locale::French => &[
+                ("intitre:", "intitle:"),
+                ("intexte:", "inbody:"),
+                ("sujet:", "topic:"),
+            ],
+            Locale::Spanish => &[
+                ("sitio:", "site:"),
+                ("intitulo:", "intitle:"),
+                ("intexto:", "inbody:"),
+                ("tema:", "topic:"),
+            ],
+            Locale::Italian => &[
+                ("sito:", "site:"),
+                ("intitolo:", "intitle:"),
+                ("intesto:", "inbody:"),
+                ("argomento:", "topic:"),
+            ],
+            Locale::Dutch => &[
+                ("intitel:", "intitle:"),
+                ("intekst:", "inbody:"),
+                ("onderwerp:", "topic:"),
+            ],
+        }
+    }
+
+    /// Localized words in widget queries and their english form.
+    /// Words that are translated to an empty string are removed.
+    /// Operators like `to` and `*` are only translated in an operator position.
+    fn widget_words(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Locale::English => &[],
+            Locale::German => &[
+                ("zoll", "inches"),
+                ("fuß", "feet"),
+                ("fuss", "feet"),
+                ("meile", "mile"),
+                ("meilen", "miles"),
+                ("pfund", "pounds"),
+                ("unzen", "ounces"),
+                ("sekunden", "seconds"),
+                ("minuten", "minutes"),
+                ("stunde", "hour"),
+                ("stunden", "hours"),
+                ("tag", "day"),
+                ("tage", "days"),
+                ("wochen", "weeks"),
+                ("jahre", "years"),
+                ("grad", "degrees"),
+                ("prozent", "percent"),
+                ("nach", "to"),
+                ("mal", "*"),
+                ("geteilt", ""),
+                ("durch", "/"),
+            ],
+            Locale::French => &[
+                ("pouce", "inch"),
+                ("pouces", "inches"),
+                ("pieds", "feet"),
+                ("milles", "miles"),
+                ("livres", "pounds"),
+                ("onces", "ounces"),
+                ("secondes", "seconds"),
+                ("heure", "hour"),
+                ("heures", "hours"),
+                ("jour", "day"),
+                ("jours", "days"),
+                ("semaines", "weeks"),
+                ("ans", "years"),
+                ("degrés", "degrees"),
+                ("pourcent", "percent"),
+                ("en", "to"),
+                ("fois", "*"),
+                ("divisé", ""),
+                ("par", "/"),
+            ],
+            Locale::Spanish => &[
+                ("pulgadas", "inches"),
+                ("pies", "feet"),
+                ("millas", "miles"),
+                ("libras", "pounds"),
+                ("onzas", "ounces"),
+                ("segundos", "seconds"),
+                ("minutos", "minutes"),
+                ("horas", "hours"),
+                ("días", "days"),
+                ("dias", "days"),
+                ("semanas", "weeks"),
+                ("años", "years"),
+                ("grados", "degrees"),
+                ("porciento", "percent"),
+                ("en", "to"),
+                ("a", "to"),
+                ("por", "*"),
+                ("dividido", ""),
+                ("entre", "/"),
+            ],
+            Locale::Italian => &[
+                ("pollici", "inches"),
+                ("piedi", "feet"),
+                ("miglia", "miles"),
+                ("libbre", "pounds"),
+                ("secondi", "seconds"),
+                ("minuti", "minutes"),
+                ("ore", "hours"),
+                ("giorni", "days"),
+                ("settimane", "weeks"),
+                ("anni", "years"),
+                ("gradi", "degrees"),
+                ("percento", "percent"),
+                ("in", "to"),
+                ("per", "*"),
+                ("diviso", "/"),
+            ],
+            Locale::Dutch => &[
+                ("voet", "feet"),
+                ("mijl", "miles"),
+                ("pond", "pounds"),
+                ("seconden", "seconds"),
+                ("minuten", "minutes"),
+                ("uur", "hours"),
+                ("dagen", "days"),
+                ("weken", "weeks"),
+                ("jaar", "years"),
+                ("graden", "degrees"),
+                ("procent", "percent"),
+                ("naar", "to"),
+                ("keer", "*"),
+                ("gedeeld", ""),
+                ("door", "/"),
+            ],
+        }
+    }
+
+    /// Whether numbers are written with a decimal comma, e.g. `3,5` instead of `3.5`.
+    pub fn has_decimal_comma(&self) -> bool {
+        !matches!(self, Locale::English)
+    }
+
+    /// Rewrite localized operators like `seite:example.com` to the english
+    /// operators understood by the query parser. Phrases are left as is.
+    pub fn canonical_operators(&self, query: &str) -> String {
+        let operators = self.operators();
+
+        if operators.is_empty() {
+            return query.to_string();
+        }
+
+        let mut in_phrase = false;
+
+        query
+            .split(' ')
+            .map(|term| {
+                let was_in_phrase = in_phrase;
+                in_phrase ^= term.matches(['"', '“', '”']).count() % 2 == 1;
+
+                if was_in_phrase {
+                    return term.to_string();
+                }
+
+                let negations = term.len() - term.trim_start_matches('-').len();
+                let (prefix, rest) = term.split_at(negations);
+
+                operators
+                    .iter()
+                    .find_map(|(localized, canonical)| {
+                        rest.get(..localized.len())
+                            .filter(|operator| operator.eq_ignore_ascii_case(localized))
+                            .map(|_| format!("{prefix}{canonical}{}", &rest[localized.len()..]))
+                    })
+                    .unwrap_or_else(|| term.to_string())
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Write the number with a decimal point and without the digit group separators,
+    /// e.g. `1.234,5` and `1.000` become `1234.5` and `1000` in german. Numbers that are
+    /// not written the way the locale writes them are left as is.
+    fn canonical_number(&self, number: &str) -> String {
+        if !self.has_decimal_comma() {
+            return number.to_string();
+        }
+
+        let (integer, fraction) = match number.split_once(',') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (number, None),
+        };
+
+        if fraction.map_or(false, |fraction| fraction.contains(['.', ','])) {
+            return number.to_string();
+        }
+
+        let mut groups = integer.split('.');
+        let first = groups.next().unwrap_or_default();
+
+        if integer.contains('.')
+            && (first.is_empty() || first.len() > 3 || groups.any(|group| group.len() != 3))
+        {
+            return number.to_string();
+        }
+
+        let integer = integer.replace('.', "");
+
+        match fraction {
+            Some(fraction) => format!("{integer}.{fraction}"),
+            None => integer,
+        }
+    }
+
+    /// Translate a widget query to english, with numbers written with decimal points.
+    pub fn canonical_widget_query(&self, query: &str) -> String {
+        let words = self.widget_words();
+
+        let query = NUMBER_REGEX.replace_all(query, |captures: &regex::Captures| {
+            self.canonical_number(&captures[0])
+        });
+
+        if words.is_empty() {
+            return query.to_string();
+        }
+
+        let query: Vec<_> = query.split_whitespace().collect();
+
+        // an operator is between a quantity and what it is converted or applied to,
+        // so words like the spanish `a` are kept in queries like `vuelos a madrid`.
+        let is_operator_position = |i: usize| {
+            i > 0
+                && i + 1 < query.len()
+                && query[..i]
+                    .iter()
+                    .any(|word| word.chars().any(|c| c.is_ascii_digit()))
+        };
+
+        query
+            .iter()
+            .enumerate()
+            .filter_map(|(i, word)| {
+                match words
+                    .iter()
+                    .find(|(localized, _)| localized == word)
+                    .map(|(_, canonical)| *canonical)
+                {
+                    Some("" | "to" | "*" | "/") if !is_operator_position(i) => Some(*word),
+                    Some("") => None,
+                    Some(canonical) => Some(canonical),
+                    None => Some(*word),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Write the decimal numbers in the text with the decimal separator of the locale.
+    pub fn localize_numbers(&self, text: &str) -> String {
+        if !self.has_decimal_comma() {
+            return text.to_string();
+        }
+
+        DECIMAL_POINT_REGEX.replace_all(text, "$1,$2").to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags() {
+        assert_eq!(Locale::from_tag("de"), Some(Locale::German));
+        assert_eq!(Locale::from_tag("de-AT"), Some(Locale::German));
+        assert_eq!(Locale::from_tag("FR_ch"), Some(Locale::French));
+        assert_eq!(Locale::from_tag("ja-JP"), None);
+
+        assert_eq!(
+            Locale::from_accept_language("ja, nl;q=0.5, fr-CH;q=0.9, en;q=0.8"),
+            Some(Locale::French)
+        );
+        assert_eq!(
+            Locale::from_accept_language("es;q=0, it"),
+            Some(Locale::Italian)
+        );
+        assert_eq!(Locale::from_accept_language("*"), None);
+        assert_eq!(Locale::from_tag("nl-BE").map(|l| l.lang()), Some(Lang::Nld));
+        assert_eq!(Locale::from_lang(Lang::Nld), Some(Locale::Dutch));
+        assert_eq!(Locale::from_lang(Lang::Jpn), None);
+
+        assert_eq!(
+            Locale::preferred_tag("ja, nl;q=0.5, fr-CH;q=0.9, en;q=0.8"),
+            Some("fr-CH")
+        );
+        assert_eq!(Locale::region_from_tag("fr-CH"), Some("CH".to_string()));
+        assert_eq!(Locale::region_from_tag("de_at"), Some("AT".to_string()));
+        assert_eq!(Locale::region_from_tag("de"), None);
+        assert_eq!(Locale::region_from_tag("zh-Hant"), None);
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(
+            Locale::German.canonical_operators("rezepte seite:chefkoch.de -Thema:sport"),
+            "rezepte site:chefkoch.de -topic:sport"
+        );
+        assert_eq!(
+            Locale::German.canonical_operators("\"seite: im buch\" seite:buch.de"),
+            "\"seite: im buch\" site:buch.de"
+        );
+        assert_eq!(
+            Locale::English.canonical_operators("seite:example.com"),
+            "seite:example.com"
+        );
+    }
+
+    #[test]
+    fn widget_queries() {
+        assert_eq!(
+            Locale::German.canonical_widget_query("3,5 mal 2"),
+            "3.5 * 2"
+        );
+        assert_eq!(
+            Locale::German.canonical_widget_query("1.234,5 geteilt durch 2"),
+            "1234.5 / 2"
+        );
+        assert_eq!(
+            Locale::French.canonical_widget_query("12 pouces en cm"),
+            "12 inches to cm"
+        );
+        assert_eq!(
+            Locale::English.canonical_widget_query("1,000 miles to km"),
+            "1,000 miles to km"
+        );
+        assert_eq!(
+            Locale::Spanish.canonical_widget_query("10 millas a km"),
+            "10 miles to km"
+        );
+        assert_eq!(
+            Locale::Spanish.canonical_widget_query("vuelos a madrid"),
+            "vuelos a madrid"
+        );
+        assert_eq!(Locale::Spanish.canonical_widget_query("de 2 a"), "de 2 a");
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(Locale::German.canonical_number("1.000"), "1000");
+        assert_eq!(
+            Locale::German.canonical_number("1.234.567,89"),
+            "1234567.89"
+        );
+        assert_eq!(Locale::German.canonical_number("3,5"), "3.5");
+        assert_eq!(Locale::German.canonical_number("3.5"), "3.5");
+        assert_eq!(Locale::German.canonical_number("1,2,3"), "1,2,3");
+        assert_eq!(Locale::German.canonical_number("12.34,5"), "12.34,5");
+        assert_eq!(Locale::English.canonical_number("1,000"), "1,000");
+
+        assert_eq!(
+            Locale::German.canonical_widget_query("1.000 meilen nach km"),
+            "1000 miles to km"
+        );
+    }
+
+    #[test]
+    fn localized_numbers() {
+        assert_eq!(Locale::German.localize_numbers("30.48 cm"), "30,48 cm");
+        assert_eq!(Locale::English.localize_numbers("30.48 cm"), "30.48 cm");
+    }
+}
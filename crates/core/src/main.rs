@@ -20,12 +20,15 @@ use std::fs;
 use std::path::Path;
 use stract::config;
 use stract::entrypoint::autosuggest_scrape::{self, Gl};
+use stract::object_store::Snapshots;
+use stract::threads;
 
 #[cfg(feature = "dev")]
 use stract::entrypoint::configure;
 
 use stract::entrypoint::{
-    self, api, entity_search_server, safety_classifier, search_server, webgraph_server,
+    self, api, entity_search_server, safety_classifier, search_server, topic_classifier,
+    webgraph_server,
 };
 use stract::webgraph::WebgraphBuilder;
 use tracing_subscriber::prelude::*;
@@ -75,6 +78,10 @@ enum Commands {
     /// Deploy the search server.
     SearchServer {
         config_path: String,
+
+        /// Validate the config, open all stores and run a canary query, then exit.
+        #[clap(long)]
+        check: bool,
     },
 
     /// Deploy the entity search server.
@@ -88,6 +95,12 @@ enum Commands {
         config_path: String,
     },
 
+    /// Write the OpenAPI specification of the json http api.
+    ApiSpec {
+        /// Print the specification if not set.
+        output_path: Option<String>,
+    },
+
     /// Scrape the Google autosuggest API for search queries.
     AutosuggestScrape {
         num_queries: usize,
@@ -108,6 +121,12 @@ enum Commands {
         options: SafetyClassifierOptions,
     },
 
+    /// Train or run inference on the classifier that predicts the topics of a webpage.
+    TopicClassifier {
+        #[clap(subcommand)]
+        options: TopicClassifierOptions,
+    },
+
     /// Setup dev environment.
     #[cfg(feature = "dev")]
     Configure {
@@ -125,6 +144,209 @@ enum Commands {
     WebSpell {
         config_path: String,
     },
+
+    /// Assemble the signal store with query independent ranking signals.
+    SignalStore {
+        #[clap(subcommand)]
+        options: SignalStoreOptions,
+    },
+
+    /// Build or serve the pre-aggregated statistics of each host.
+    HostStats {
+        #[clap(subcommand)]
+        options: HostStatsOptions,
+    },
+
+    /// Administrative commands for a running deployment.
+    Admin {
+        #[clap(subcommand)]
+        options: AdminOptions,
+    },
+
+    /// Upload or restore snapshots of indexes and webgraphs in object storage.
+    Snapshot {
+        #[clap(subcommand)]
+        options: SnapshotOptions,
+    },
+    /// Run the golden query suite against a local index and write a junit report.
+    /// Fails if any of the queries do not meet their expectations.
+    GoldenQueries {
+        config_path: String,
+    },
+
+    /// Consume or replay the event log between the crawler and the indexer.
+    EventLog {
+        #[clap(subcommand)]
+        options: EventLogOptions,
+    },
+
+    /// Re-run extraction stages on the raw pages from the crawl and patch
+    /// the results into the existing index and signal store.
+    Backfill {
+        config_path: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum EventLogOptions {
+    /// Run the configured consumers until they have processed all events in the log.
+    Consume { config_path: String },
+
+    /// Reset the offset of a consumer, so it processes the events from the offset again.
+    Replay {
+        config_path: String,
+        consumer: String,
+
+        #[clap(long)]
+        from_offset: u64,
+    },
+
+    /// Print the offset of each consumer.
+    Offsets { config_path: String },
+
+    /// Append a deletion of the url so it is removed from the downstream stores.
+    Delete { config_path: String, url: String },
+}
+
+#[derive(Subcommand)]
+enum SnapshotOptions {
+    /// Upload the folder as a new generation of the snapshot.
+    Upload { config_path: String, path: String },
+
+    /// Restore a generation of the snapshot into the folder. If the folder was restored
+    /// from an earlier generation, only the changed files are downloaded.
+    Restore {
+        config_path: String,
+        path: String,
+
+        /// Restore the latest generation if not set.
+        #[clap(long)]
+        generation: Option<String>,
+    },
+
+    /// List the generations of the snapshot from oldest to newest.
+    List { config_path: String },
+}
+
+#[derive(Subcommand)]
+enum AdminOptions {
+    /// Print the audit log of administrative actions.
+    AuditLog {
+        path: String,
+
+        /// Verify the signatures of the entries with the secret in this file.
+        #[clap(long)]
+        secret_path: Option<String>,
+    },
+
+    /// Reload the signal store of a search server.
+    ReloadSignalStore {
+        host: std::net::SocketAddr,
+
+        /// The operator performing the action. Recorded in the audit log of the server.
+        #[clap(long)]
+        actor: String,
+
+        /// File with the secret of the operator, used to sign the request.
+        #[clap(long)]
+        secret_path: String,
+    },
+
+    /// Remove a host from the results of a search server.
+    BlockHost {
+        host: std::net::SocketAddr,
+        blocked_host: String,
+
+        /// The operator performing the action. Recorded in the audit log of the server.
+        #[clap(long)]
+        actor: String,
+
+        /// File with the secret of the operator, used to sign the request.
+        #[clap(long)]
+        secret_path: String,
+    },
+
+    /// Let a blocked host appear in the results of a search server again.
+    UnblockHost {
+        host: std::net::SocketAddr,
+        blocked_host: String,
+
+        /// The operator performing the action. Recorded in the audit log of the server.
+        #[clap(long)]
+        actor: String,
+
+        /// File with the secret of the operator, used to sign the request.
+        #[clap(long)]
+        secret_path: String,
+    },
+
+    /// Set the optic used by a search server for the searches without an optic of their own.
+    OverrideOptic {
+        host: std::net::SocketAddr,
+
+        /// File with the optic. The override is removed if not set.
+        #[clap(long)]
+        optic_path: Option<String>,
+
+        /// The operator performing the action. Recorded in the audit log of the server.
+        #[clap(long)]
+        actor: String,
+
+        /// File with the secret of the operator, used to sign the request.
+        #[clap(long)]
+        secret_path: String,
+    },
+
+    /// Delete a page from a live index.
+    DeleteUrl {
+        host: std::net::SocketAddr,
+        url: String,
+
+        /// The operator performing the action. Recorded in the audit log of the server.
+        #[clap(long)]
+        actor: String,
+
+        /// File with the secret of the operator, used to sign the request.
+        #[clap(long)]
+        secret_path: String,
+    },
+
+    /// Review the suppression rules generated for crawl traps. Prints the pending rules.
+    CrawlTraps {
+        path: String,
+
+        /// Approve the rule for this template.
+        #[clap(long)]
+        approve: Vec<String>,
+
+        /// Reject the rule for this template.
+        #[clap(long)]
+        reject: Vec<String>,
+    },
+
+    /// Print a summary of the sampled query telemetry recorded by the api.
+    QueryTelemetry {
+        path: String,
+
+        /// Summarize the telemetry from this many days, including today.
+        #[clap(long, default_value_t = 30)]
+        days: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum SignalStoreOptions {
+    /// Build a new signal store from the centrality stores and signal csv files.
+    Build { config_path: String },
+}
+
+#[derive(Subcommand)]
+enum HostStatsOptions {
+    /// Aggregate the host stats from an index, the signal store and crawl error logs.
+    Build { config_path: String },
+
+    /// Serve the host stats to the api.
+    Serve { config_path: String },
 }
 
 #[derive(Subcommand)]
@@ -140,7 +362,13 @@ enum LiveIndex {
 enum Crawler {
     /// Deploy the crawl worker. The worker is responsible for downloading webpages, saving them to S3,
     /// and sending newly discovered urls back to the crawl coordinator.
-    Worker { config_path: String },
+    Worker {
+        config_path: String,
+
+        /// Validate the config, connect to the routers and fetch a canary url, then exit.
+        #[clap(long)]
+        check: bool,
+    },
 
     /// Deploy the crawl coordinator. The crawl coordinator is responsible for
     /// distributing crawl jobs to the crawles and deciding which urls to crawl next.
@@ -152,6 +380,9 @@ enum Crawler {
 
     /// Create a crawl plan.
     Plan { config_path: String },
+
+    /// Select hosts from the webgraph that should be seeded in the next crawl plan.
+    Seed { config_path: String },
 }
 
 /// Commands to train or run inference on the classifier that predicts if a webpage is NSFW or SFW.
@@ -167,6 +398,25 @@ enum SafetyClassifierOptions {
     Predict { model_path: String, text: String },
 }
 
+#[derive(Subcommand)]
+enum TopicClassifierOptions {
+    /// Train the classifier
+    Train {
+        output_path: String,
+
+        /// csv file with `label,text` rows
+        #[clap(long)]
+        dataset_path: Option<String>,
+
+        /// Use the host descriptions from the parsed DMOZ annotations as training data
+        #[clap(long)]
+        annotations_path: Option<String>,
+    },
+
+    /// Run a single prediction to test the model
+    Predict { model_path: String, text: String },
+}
+
 #[derive(Subcommand)]
 enum CentralityMode {
     /// Calculate metrics for the host webgraph.
@@ -178,6 +428,35 @@ enum CentralityMode {
     Page {
         webgraph_path: String,
         output_path: String,
+
+        /// Also derive the centrality of the pages from the harmonic centrality
+        /// of the hosts in this folder (the output of the host metrics).
+        #[clap(long)]
+        host_centrality_path: Option<String>,
+    },
+    /// Serve the calculated metrics to the frontends and admin tools.
+    Serve { config_path: String },
+    /// Compare the ranks of two runs of the metrics and report the nodes and top level
+    /// domains that moved the most.
+    Diff {
+        old_path: String,
+        new_path: String,
+
+        /// Compare the metrics of the page webgraph instead of the host webgraph.
+        #[clap(long)]
+        page: bool,
+
+        /// The number of nodes that moved the most up and down to report.
+        #[clap(long, default_value_t = 25)]
+        num_movers: usize,
+
+        /// Write the report as json to this path.
+        #[clap(long)]
+        report_path: Option<String>,
+
+        /// Fail if the rank correlation between the runs is lower than this.
+        #[clap(long)]
+        min_correlation: Option<f64>,
     },
 }
 
@@ -192,9 +471,19 @@ enum WebgraphOptions {
         paths: Vec<String>,
     },
 
+    /// Renumber the nodes of a webgraph into a dense range and rewrite
+    /// the signal store computed from the graph to use the new ids.
+    Compact { config_path: String },
+
     /// Deploy the webgraph server. The webgraph server is responsible for serving the webgraph to the search servers.
     /// This is e.g. used to find similar sites etc.
-    Server { config_path: String },
+    Server {
+        config_path: String,
+
+        /// Validate the config, open the webgraph and run a canary lookup, then exit.
+        #[clap(long)]
+        check: bool,
+    },
 }
 
 #[derive(Subcommand)]
@@ -216,6 +505,45 @@ enum IndexingOptions {
 
     /// Create the feed index. Used to find feeds to put into the live index.
     Feed { config_path: String },
+
+    /// Split a search index into a small head tier with the highest quality pages
+    /// and an archive tier with the rest.
+    Tiers { config_path: String },
+
+    /// Partition a search index into groups of languages that can be
+    /// served by separate shards.
+    Languages { config_path: String },
+
+    /// Partition a search index into shards by the hash of the urls.
+    Shards { config_path: String },
+
+    /// Export the term dictionary of each shard with document frequencies and collection statistics.
+    ExportTerms { config_path: String },
+
+    /// Build the popular queries of each language for autosuggest from the words in the titles of the shards.
+    LocalizedQueries { config_path: String },
+
+    /// Tombstone the documents below a quality threshold, so they are excluded
+    /// from the search results until the index is merged.
+    Prune { config_path: String },
+
+    /// Return the tombstoned documents of a search index to the search results.
+    RestorePruned { index_path: String },
+
+    /// Record the prices of the products in a search index in the product store.
+    Products {
+        index_path: String,
+        store_path: String,
+    },
+
+    /// Record the places with geo coordinates in a search index in the geo store.
+    Places {
+        index_path: String,
+        store_path: String,
+    },
+
+    /// Ingest Common Crawl segments into an existing index and webgraphs.
+    CommonCrawl { config_path: String },
 }
 
 fn load_toml_config<T: DeserializeOwned, P: AsRef<Path>>(path: P) -> T {
@@ -242,7 +570,8 @@ fn main() -> Result<()> {
     match args.command {
         Commands::Indexer { options } => match options {
             IndexingOptions::Search { config_path } => {
-                let config = load_toml_config(config_path);
+                let config: config::IndexingLocalConfig = load_toml_config(config_path);
+                threads::init_global_rayon(&config.threads.rayon)?;
                 entrypoint::Indexer::run(&config)?;
             }
             IndexingOptions::Entity {
@@ -260,6 +589,49 @@ fn main() -> Result<()> {
                     .collect::<Vec<_>>();
                 entrypoint::indexer::Indexer::merge(pointers)?;
             }
+            IndexingOptions::Tiers { config_path } => {
+                let config: config::IndexTiersConfig = load_toml_config(config_path);
+                entrypoint::indexer::Indexer::split_tiers(&config)?;
+            }
+            IndexingOptions::Languages { config_path } => {
+                let config: config::IndexLanguagesConfig = load_toml_config(config_path);
+                entrypoint::indexer::Indexer::split_languages(&config)?;
+            }
+            IndexingOptions::Shards { config_path } => {
+                let config: config::IndexShardsConfig = load_toml_config(config_path);
+                entrypoint::indexer::Indexer::split_shards(&config)?;
+            }
+            IndexingOptions::ExportTerms { config_path } => {
+                let config: config::TermExportConfig = load_toml_config(config_path);
+                entrypoint::term_export::run(&config)?;
+            }
+            IndexingOptions::LocalizedQueries { config_path } => {
+                let config: config::LocalizedQueriesConfig = load_toml_config(config_path);
+                entrypoint::localized_queries::run(&config)?;
+            }
+            IndexingOptions::Prune { config_path } => {
+                let config: config::IndexPruneConfig = load_toml_config(config_path);
+                entrypoint::prune::run(&config)?;
+            }
+            IndexingOptions::RestorePruned { index_path } => {
+                entrypoint::prune::restore(&index_path)?;
+            }
+            IndexingOptions::Products {
+                index_path,
+                store_path,
+            } => {
+                entrypoint::indexer::Indexer::record_products(&index_path, &store_path)?;
+            }
+            IndexingOptions::Places {
+                index_path,
+                store_path,
+            } => {
+                entrypoint::indexer::Indexer::record_places(&index_path, &store_path)?;
+            }
+            IndexingOptions::CommonCrawl { config_path } => {
+                let config: config::CommonCrawlConfig = load_toml_config(config_path);
+                entrypoint::common_crawl::ingest(&config)?;
+            }
         },
         Commands::Centrality { mode } => {
             match mode {
@@ -273,7 +645,41 @@ fn main() -> Result<()> {
                 CentralityMode::Page {
                     webgraph_path,
                     output_path,
-                } => entrypoint::Centrality::build_approx_harmonic(webgraph_path, output_path)?,
+                    host_centrality_path,
+                } => {
+                    entrypoint::Centrality::build_approx_harmonic(&webgraph_path, &output_path)?;
+
+                    if let Some(host_centrality_path) = host_centrality_path {
+                        entrypoint::Centrality::build_derived_harmonic(
+                            &webgraph_path,
+                            &host_centrality_path,
+                            &output_path,
+                        )?;
+                    }
+                }
+                CentralityMode::Serve { config_path } => {
+                    let config: config::CentralityServerConfig = load_toml_config(config_path);
+
+                    tokio::runtime::Builder::new_multi_thread()
+                        .enable_all()
+                        .build()?
+                        .block_on(entrypoint::centrality_server::run(config))?
+                }
+                CentralityMode::Diff {
+                    old_path,
+                    new_path,
+                    page,
+                    num_movers,
+                    report_path,
+                    min_correlation,
+                } => entrypoint::centrality_diff::run(entrypoint::centrality_diff::DiffOptions {
+                    old_path,
+                    new_path,
+                    page,
+                    num_movers,
+                    report_path,
+                    min_correlation,
+                })?,
             }
             tracing::info!("Done");
         }
@@ -293,7 +699,18 @@ fn main() -> Result<()> {
                     std::fs::remove_dir_all(other_path).unwrap();
                 }
             }
-            WebgraphOptions::Server { config_path } => {
+            WebgraphOptions::Compact { config_path } => {
+                let config: config::WebgraphCompactionConfig = load_toml_config(config_path);
+                entrypoint::Webgraph::compact(&config)?;
+            }
+            WebgraphOptions::Server {
+                config_path,
+                check: true,
+            } => entrypoint::check::webgraph_server(&config_path)?,
+            WebgraphOptions::Server {
+                config_path,
+                check: false,
+            } => {
                 let config: config::WebgraphServerConfig = load_toml_config(config_path);
 
                 tokio::runtime::Builder::new_multi_thread()
@@ -304,19 +721,30 @@ fn main() -> Result<()> {
         },
         Commands::Api { config_path } => {
             let config: config::ApiConfig = load_toml_config(config_path);
+            threads::init_global_rayon(&config.threads.rayon)?;
 
-            tokio::runtime::Builder::new_multi_thread()
-                .enable_all()
-                .build()?
-                .block_on(api::run(config))?
+            threads::tokio_runtime(&config.threads.runtime)?.block_on(api::run(config))?
         }
-        Commands::SearchServer { config_path } => {
+        Commands::ApiSpec { output_path } => {
+            let spec = api::openapi_spec();
+
+            match output_path {
+                Some(path) => fs::write(path, spec)?,
+                None => println!("{spec}"),
+            }
+        }
+        Commands::SearchServer {
+            config_path,
+            check: true,
+        } => entrypoint::check::search_server(&config_path)?,
+        Commands::SearchServer {
+            config_path,
+            check: false,
+        } => {
             let config: config::SearchServerConfig = load_toml_config(config_path);
+            threads::init_global_rayon(&config.threads.rayon)?;
 
-            tokio::runtime::Builder::new_multi_thread()
-                .enable_all()
-                .build()?
-                .block_on(search_server::run(config))?
+            threads::tokio_runtime(&config.threads.runtime)?.block_on(search_server::run(config))?
         }
         Commands::EntitySearchServer { config_path } => {
             let config: config::EntitySearchServerConfig = load_toml_config(config_path);
@@ -343,7 +771,14 @@ fn main() -> Result<()> {
             output_path,
         } => entrypoint::dmoz_parser::run(dmoz_file, output_path).unwrap(),
         Commands::Crawler { options } => match options {
-            Crawler::Worker { config_path } => {
+            Crawler::Worker {
+                config_path,
+                check: true,
+            } => entrypoint::check::crawler(&config_path)?,
+            Crawler::Worker {
+                config_path,
+                check: false,
+            } => {
                 let config: config::CrawlerConfig = load_toml_config(config_path);
 
                 tokio::runtime::Builder::new_multi_thread()
@@ -372,6 +807,11 @@ fn main() -> Result<()> {
 
                 entrypoint::crawler::planner(config)?;
             }
+            Crawler::Seed { config_path } => {
+                let config: config::CrawlSeederConfig = load_toml_config(config_path);
+
+                entrypoint::crawler::seeder(config)?;
+            }
         },
         Commands::SafetyClassifier { options } => match options {
             SafetyClassifierOptions::Train {
@@ -382,6 +822,16 @@ fn main() -> Result<()> {
                 safety_classifier::predict(model_path, &text)?
             }
         },
+        Commands::TopicClassifier { options } => match options {
+            TopicClassifierOptions::Train {
+                output_path,
+                dataset_path,
+                annotations_path,
+            } => topic_classifier::train(dataset_path, annotations_path, output_path)?,
+            TopicClassifierOptions::Predict { model_path, text } => {
+                topic_classifier::predict(model_path, &text)?
+            }
+        },
         Commands::LiveIndex { options } => match options {
             LiveIndex::Schedule { config_path } => {
                 let config = load_toml_config(config_path);
@@ -400,6 +850,170 @@ fn main() -> Result<()> {
             let config: config::WebSpellConfig = load_toml_config(config_path);
             entrypoint::web_spell::run(config)?;
         }
+        Commands::SignalStore { options } => match options {
+            SignalStoreOptions::Build { config_path } => {
+                let config: config::SignalStoreConfig = load_toml_config(config_path);
+                entrypoint::signal_store::build(config)?;
+            }
+        },
+        Commands::HostStats { options } => match options {
+            HostStatsOptions::Build { config_path } => {
+                let config: config::HostStatsConfig = load_toml_config(config_path);
+                entrypoint::host_stats::build(config)?;
+            }
+            HostStatsOptions::Serve { config_path } => {
+                let config: config::HostStatsServerConfig = load_toml_config(config_path);
+
+                tokio::runtime::Builder::new_multi_thread()
+                    .enable_all()
+                    .build()?
+                    .block_on(entrypoint::host_stats::run(config))?
+            }
+        },
+        Commands::Admin { options } => match options {
+            AdminOptions::AuditLog { path, secret_path } => {
+                entrypoint::admin::audit_log(&path, secret_path.as_deref())?
+            }
+            AdminOptions::ReloadSignalStore {
+                host,
+                actor,
+                secret_path,
+            } => tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(entrypoint::admin::reload_signal_store(
+                    host,
+                    &actor,
+                    &secret_path,
+                ))?,
+            AdminOptions::BlockHost {
+                host,
+                blocked_host,
+                actor,
+                secret_path,
+            } => tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(entrypoint::admin::block_host(
+                    host,
+                    &blocked_host,
+                    &actor,
+                    &secret_path,
+                ))?,
+            AdminOptions::UnblockHost {
+                host,
+                blocked_host,
+                actor,
+                secret_path,
+            } => tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(entrypoint::admin::unblock_host(
+                    host,
+                    &blocked_host,
+                    &actor,
+                    &secret_path,
+                ))?,
+            AdminOptions::OverrideOptic {
+                host,
+                optic_path,
+                actor,
+                secret_path,
+            } => tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(entrypoint::admin::override_optic(
+                    host,
+                    optic_path.as_deref(),
+                    &actor,
+                    &secret_path,
+                ))?,
+            AdminOptions::DeleteUrl {
+                host,
+                url,
+                actor,
+                secret_path,
+            } => tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(entrypoint::admin::delete_url(
+                    host,
+                    &url,
+                    &actor,
+                    &secret_path,
+                ))?,
+            AdminOptions::CrawlTraps {
+                path,
+                approve,
+                reject,
+            } => entrypoint::admin::crawl_traps(&path, &approve, &reject)?,
+            AdminOptions::QueryTelemetry { path, days } => {
+                entrypoint::admin::query_telemetry(&path, days)?
+            }
+        },
+        Commands::Snapshot { options } => {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()?;
+
+            match options {
+                SnapshotOptions::Upload { config_path, path } => {
+                    let config: config::SnapshotConfig = load_toml_config(config_path);
+                    let snapshots = Snapshots::open(&config)?;
+                    let generation = runtime.block_on(snapshots.upload(Path::new(&path)))?;
+
+                    println!("{generation}");
+                }
+                SnapshotOptions::Restore {
+                    config_path,
+                    path,
+                    generation,
+                } => {
+                    let config: config::SnapshotConfig = load_toml_config(config_path);
+                    let snapshots = Snapshots::open(&config)?;
+
+                    runtime.block_on(snapshots.restore(generation.as_deref(), Path::new(&path)))?;
+                }
+                SnapshotOptions::List { config_path } => {
+                    let config: config::SnapshotConfig = load_toml_config(config_path);
+                    let snapshots = Snapshots::open(&config)?;
+
+                    for generation in runtime.block_on(snapshots.generations())? {
+                        println!("{generation}");
+                    }
+                }
+            }
+        }
+        Commands::GoldenQueries { config_path } => {
+            let config: config::GoldenQueriesConfig = load_toml_config(config_path);
+            entrypoint::golden_queries::run(config)?;
+        }
+        Commands::EventLog { options } => match options {
+            EventLogOptions::Consume { config_path } => {
+                let config: config::EventLogConsumerConfig = load_toml_config(config_path);
+                entrypoint::event_log::run(config)?;
+            }
+            EventLogOptions::Replay {
+                config_path,
+                consumer,
+                from_offset,
+            } => {
+                let config: config::EventLogConsumerConfig = load_toml_config(config_path);
+                entrypoint::event_log::replay(config, &consumer, from_offset)?;
+            }
+            EventLogOptions::Offsets { config_path } => {
+                let config: config::EventLogConsumerConfig = load_toml_config(config_path);
+                entrypoint::event_log::offsets(config)?;
+            }
+            EventLogOptions::Delete { config_path, url } => {
+                let config: config::EventLogConsumerConfig = load_toml_config(config_path);
+                entrypoint::event_log::delete(config, &url)?;
+            }
+        },
+        Commands::Backfill { config_path } => {
+            let config: config::BackfillConfig = load_toml_config(config_path);
+            entrypoint::backfill::run(config)?;
+        }
     }
 
     Ok(())
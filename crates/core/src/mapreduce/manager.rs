@@ -1,20 +1,44 @@
-use super::{Error, MapReduceConnection, Result, Worker};
-use super::{Map, Reduce};
+use super::{Error, JobKey, MapReduceConnection, Response, Result, StatefulWorker, Worker};
+use super::{Map, Reduce, SpillingReducer};
+use crate::distributed::probe::{self, Health};
 use crate::distributed::retry_strategy::ExponentialBackoff;
+use crate::distributed::sonic;
+use crate::kv::rocksdb_store::RocksDbStore;
 use crate::mapreduce::Task;
 use futures::StreamExt;
 use itertools::Itertools;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
 use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
 use std::ops::Deref;
+use std::path::Path;
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tracing::{debug, warn};
 
+/// How long a worker can go without answering the heartbeats of the manager
+/// before its job is given to another worker.
+const DEFAULT_LEASE: Duration = Duration::from_secs(60);
+/// How often the manager sends a heartbeat to the worker of a running job.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// The number of times a job is tried before the run fails.
+const MAX_ATTEMPTS: usize = 5;
+/// How long a stateful worker has to turn its state into an aggregate and send each chunk of it.
+const TEARDOWN_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+/// A job is a straggler when it runs this many times longer than the median job.
+const DEFAULT_STRAGGLER_FACTOR: f64 = 3.0;
+/// The number of finished jobs needed before the median job duration is trusted.
+const MIN_FINISHED_JOBS: usize = 3;
+/// How often the running jobs are checked for stragglers before enough jobs have finished.
+const STRAGGLER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How long each attempt to connect to a worker, including the TLS handshake, may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[derive(Debug)]
 struct RemoteWorker {
     addr: SocketAddr,
@@ -33,10 +57,18 @@ impl RemoteWorker {
         I: Map<W, O> + Send,
         O: Serialize + DeserializeOwned + Send,
     {
+        let tls = sonic::tls::service::<W>();
+
         for dur in RemoteWorker::retry_strategy() {
-            if let Ok(conn) = MapReduceConnection::create(self.addr).await {
+            if let Ok(conn) = MapReduceConnection::create_with_tls(
+                self.addr,
+                CONNECT_TIMEOUT,
+                tls.client.as_ref(),
+            )
+            .await
+            {
                 debug!("connected");
-                return Ok(conn);
+                return Ok(conn.with_secret(tls.secret));
             }
 
             std::thread::sleep(dur);
@@ -45,16 +77,40 @@ impl RemoteWorker {
         Err(Error::NoResponse)
     }
 
-    async fn perform<W, I, O>(&self, job: I) -> Result<O>
+    /// Run the job on the worker. The lease is renewed each time the worker answers
+    /// a heartbeat, so jobs can run for as long as the worker is alive.
+    async fn perform<W, I, O>(&self, key: JobKey, job: I, lease: Duration) -> Result<O>
     where
         W: Worker,
         I: Map<W, O> + Send,
         O: Serialize + DeserializeOwned + Send,
     {
         let conn = self.connect::<W, I, O>().await?;
-        match conn.send(&Task::Job(job)).await {
-            Ok(Some(res)) => Ok(res),
-            _ => Err(Error::NoResponse),
+        let task = Task::Job { key, job };
+        let res = conn.send_without_timeout(&task);
+        tokio::pin!(res);
+
+        let mut renewed = Instant::now();
+
+        loop {
+            tokio::select! {
+                res = &mut res => {
+                    return match res {
+                        Ok(Response::Job(res)) => Ok(res),
+                        _ => Err(Error::NoResponse),
+                    };
+                }
+                _ = tokio::time::sleep(HEARTBEAT_INTERVAL.min(lease)) => {}
+            }
+
+            if probe::probe(self.addr, probe::PROBE_TIMEOUT)
+                .await
+                .is_some()
+            {
+                renewed = Instant::now();
+            } else if renewed.elapsed() >= lease {
+                return Err(Error::LeaseExpired);
+            }
         }
     }
 
@@ -68,31 +124,82 @@ impl RemoteWorker {
         let conn = self.connect().await?;
         let res = conn.send(&Task::<I>::AllFinished).await?;
 
-        debug_assert!(res.is_none());
+        debug_assert!(matches!(res, Response::Finished));
 
         Ok(())
     }
+
+    /// Get the aggregate of the state of the worker. `None` if the worker is stateless.
+    async fn teardown<W, I, O, A>(&self, timeout: Duration) -> Result<Option<A>>
+    where
+        W: Worker,
+        I: Map<W, O> + Send,
+        O: Serialize + DeserializeOwned + Send,
+        A: DeserializeOwned,
+    {
+        debug!("tearing down worker {:}", self.addr);
+        let conn = self.connect::<W, I, O>().await?;
+
+        // the aggregate is streamed in chunks, and `timeout` applies to each chunk
+        let mut chunks = std::pin::pin!(conn.send_streaming(&Task::<I>::Teardown, timeout).await?);
+        let mut aggregate = Vec::new();
+
+        while let Some(chunk) = chunks.next().await {
+            match chunk? {
+                Response::Aggregate(chunk) => aggregate.extend_from_slice(&chunk),
+                Response::Finished => return Ok(None),
+                Response::Job(_) => return Err(Error::NoResponse),
+            }
+        }
+
+        Ok(Some(
+            bincode::deserialize(&aggregate).map_err(sonic::Error::from)?,
+        ))
+    }
+}
+
+/// What happens to the worker when its guard is dropped.
+enum Outcome {
+    /// The attempt was dropped before the worker answered, so it is put
+    /// back in the pool, but the other workers get jobs before it.
+    Cancelled,
+    Success,
+    /// The worker might still be alive and finish the job, so it is
+    /// put back in the pool like a cancelled worker.
+    LeaseExpired,
+    /// The worker is removed from the pool.
+    Failed,
 }
 
-struct WorkerGuard<'a> {
-    from_pool: &'a WorkerPool,
+struct WorkerGuard {
+    from_pool: Arc<WorkerPool>,
     worker: Arc<RemoteWorker>,
+    outcome: Outcome,
 }
 
-impl<'a> WorkerGuard<'a> {
-    fn new(pool: &'a WorkerPool, worker: Arc<RemoteWorker>) -> Self {
+impl WorkerGuard {
+    fn new(pool: Arc<WorkerPool>, worker: Arc<RemoteWorker>) -> Self {
         Self {
             worker,
             from_pool: pool,
+            outcome: Outcome::Cancelled,
         }
     }
 
-    async fn success(self) {
-        self.from_pool.insert(Arc::clone(&self.worker)).await;
+    fn success(mut self) {
+        self.outcome = Outcome::Success;
+    }
+
+    fn lease_expired(mut self) {
+        self.outcome = Outcome::LeaseExpired;
+    }
+
+    fn failed(mut self) {
+        self.outcome = Outcome::Failed;
     }
 }
 
-impl<'a> Deref for WorkerGuard<'a> {
+impl Deref for WorkerGuard {
     type Target = Arc<RemoteWorker>;
 
     fn deref(&self) -> &Self::Target {
@@ -100,8 +207,16 @@ impl<'a> Deref for WorkerGuard<'a> {
     }
 }
 
-impl<'a> Drop for WorkerGuard<'a> {
+impl Drop for WorkerGuard {
     fn drop(&mut self) {
+        match self.outcome {
+            Outcome::Success => self.from_pool.insert(Arc::clone(&self.worker)),
+            Outcome::Cancelled | Outcome::LeaseExpired => {
+                self.from_pool.insert_last(Arc::clone(&self.worker));
+            }
+            Outcome::Failed => {}
+        }
+
         self.from_pool.put_back();
     }
 }
@@ -138,19 +253,32 @@ impl WorkerPool {
         self.running_workers.fetch_sub(1, Ordering::SeqCst);
     }
 
-    async fn insert(&self, worker: Arc<RemoteWorker>) {
-        self.ready_workers.lock().await.push(worker);
+    fn insert(&self, worker: Arc<RemoteWorker>) {
+        self.ready_workers.lock().unwrap().push(worker);
     }
 
-    async fn get_worker(&self) -> Result<Option<WorkerGuard<'_>>> {
-        let mut ready_workers = self.ready_workers.lock().await;
+    /// Insert the worker so it is the last of the ready workers to get a job.
+    fn insert_last(&self, worker: Arc<RemoteWorker>) {
+        self.ready_workers.lock().unwrap().insert(0, worker);
+    }
+
+    /// Stop giving jobs to the worker.
+    fn remove(&self, addr: SocketAddr) {
+        self.ready_workers
+            .lock()
+            .unwrap()
+            .retain(|worker| worker.addr != addr);
+    }
+
+    fn get_worker(self: &Arc<Self>) -> Result<Option<WorkerGuard>> {
+        let mut ready_workers = self.ready_workers.lock().unwrap();
         if ready_workers.len() as u32 + self.running_workers.load(Ordering::SeqCst) == 0 {
             return Err(Error::NoAvailableWorker);
         }
 
         if let Some(worker) = ready_workers.pop() {
             self.running_workers.fetch_add(1, Ordering::SeqCst);
-            Ok(Some(WorkerGuard::new(self, worker)))
+            Ok(Some(WorkerGuard::new(Arc::clone(self), worker)))
         } else {
             Ok(None)
         }
@@ -183,7 +311,13 @@ impl WorkerPool {
 }
 
 pub struct Manager {
-    pool: WorkerPool,
+    pool: Arc<WorkerPool>,
+    lease: Duration,
+    straggler_factor: Option<f64>,
+    /// The durations of the finished jobs, measured from when they were given to a worker.
+    durations: Mutex<Vec<Duration>>,
+    /// The jobs each worker has finished since its last teardown.
+    finished_on: Arc<Mutex<HashMap<SocketAddr, Vec<JobKey>>>>,
 }
 
 impl Manager {
@@ -192,47 +326,202 @@ impl Manager {
         A: ToSocketAddrs + std::fmt::Debug,
     {
         Self {
-            pool: WorkerPool::new(workers),
+            pool: Arc::new(WorkerPool::new(workers)),
+            lease: DEFAULT_LEASE,
+            straggler_factor: Some(DEFAULT_STRAGGLER_FACTOR),
+            durations: Mutex::new(Vec::new()),
+            finished_on: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
-    async fn try_map<W, I, O>(&self, job: &I) -> Result<O>
+    /// Give a job to another worker if its worker does not answer the heartbeats
+    /// of the manager for `lease`.
+    pub fn with_lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    /// When a job runs `factor` times longer than the median job, a duplicate of the job
+    /// is given to an idle worker and the result of the first worker to finish is used.
+    /// Jobs are never duplicated if `factor` is `None`.
+    pub fn with_straggler_factor(mut self, factor: Option<f64>) -> Self {
+        self.straggler_factor = factor;
+        self
+    }
+
+    /// How long a job can run before it is a straggler. `None` until enough jobs have finished.
+    fn straggler_threshold(&self) -> Option<Duration> {
+        let factor = self.straggler_factor?;
+        let mut durations = self.durations.lock().unwrap().clone();
+
+        if durations.len() < MIN_FINISHED_JOBS {
+            return None;
+        }
+
+        durations.sort();
+        Some(durations[durations.len() / 2].mul_f64(factor))
+    }
+
+    /// Run the job on the worker. The attempt only owns its arguments, so it
+    /// can keep running in the background after the caller stops waiting for it.
+    fn perform_on<W, I, O>(
+        &self,
+        worker: WorkerGuard,
+        key: JobKey,
+        job: I,
+    ) -> impl Future<Output = Result<O>> + Send + 'static
     where
         W: Worker,
-        I: Map<W, O> + Send + Clone,
-        O: Serialize + DeserializeOwned + Send,
+        I: Map<W, O> + Send + Sync + 'static,
+        O: Serialize + DeserializeOwned + Send + 'static,
     {
+        let lease = self.lease;
+        let finished_on = Arc::clone(&self.finished_on);
+
+        async move {
+            match worker.perform(key, job, lease).await {
+                Ok(res) => {
+                    finished_on
+                        .lock()
+                        .unwrap()
+                        .entry(worker.addr)
+                        .or_default()
+                        .push(key);
+                    worker.success();
+                    Ok(res)
+                }
+                Err(Error::LeaseExpired) => {
+                    warn!("lease of job {} expired on worker {}", key, worker.addr);
+                    worker.lease_expired();
+                    Err(Error::LeaseExpired)
+                }
+                Err(err) => {
+                    worker.failed();
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    /// Wait for a worker that is ready to run a job.
+    async fn ready_worker(&self) -> Result<WorkerGuard> {
         loop {
-            match self.pool.get_worker().await? {
+            match self.pool.get_worker()? {
                 Some(worker) => {
-                    let res = worker.perform(job.clone()).await?;
-                    worker.success().await;
+                    // a worker that is still loading is alive, so it is put back in the
+                    // pool instead of failing the job after the connection retries
+                    if probe::probe(worker.addr, probe::PROBE_TIMEOUT).await
+                        == Some(Health::Loading)
+                    {
+                        debug!("worker {} is loading", worker.addr);
+                        worker.success();
+                        tokio::time::sleep(Duration::from_millis(1000)).await;
+                        continue;
+                    }
 
-                    return Ok(res);
+                    return Ok(worker);
                 }
                 None => std::thread::sleep(std::time::Duration::from_millis(1000)),
             }
         }
     }
 
+    /// Run the job and give a duplicate of it to an idle worker if it becomes a straggler.
+    async fn try_map_speculative<W, I, O>(&self, key: JobKey, job: &I) -> Result<O>
+    where
+        W: Worker,
+        I: Map<W, O> + Send + Sync + Clone + 'static,
+        O: Serialize + DeserializeOwned + Send + 'static,
+    {
+        let worker = self.ready_worker().await?;
+
+        // the time spent waiting for a worker is not part of the duration of the job
+        let start = Instant::now();
+        let mut attempt = Box::pin(self.perform_on(worker, key, job.clone()));
+
+        let res = loop {
+            let wait = match self.straggler_threshold() {
+                Some(threshold) => threshold.saturating_sub(start.elapsed()),
+                None if self.straggler_factor.is_some() => STRAGGLER_CHECK_INTERVAL,
+                None => break attempt.await,
+            };
+
+            tokio::select! {
+                res = &mut attempt => break res,
+                _ = tokio::time::sleep(wait) => {}
+            }
+
+            let is_straggler = self
+                .straggler_threshold()
+                .map_or(false, |threshold| start.elapsed() >= threshold);
+
+            if !is_straggler {
+                continue;
+            }
+
+            // only idle workers get duplicates, so the other jobs are not delayed
+            let Ok(Some(worker)) = self.pool.get_worker() else {
+                tokio::select! {
+                    res = &mut attempt => break res,
+                    _ = tokio::time::sleep(STRAGGLER_CHECK_INTERVAL) => continue,
+                }
+            };
+
+            debug!(
+                "job {} is a straggler, duplicating it on {}",
+                key, worker.addr
+            );
+            let mut duplicate = Box::pin(self.perform_on(worker, key, job.clone()));
+
+            // the worker of the attempt that loses is still running the job, so the attempt
+            // runs to the end in the background to keep the worker out of the pool until then
+            break tokio::select! {
+                res = &mut attempt => match res {
+                    Ok(res) => {
+                        tokio::spawn(duplicate);
+                        Ok(res)
+                    }
+                    Err(_) => duplicate.await,
+                },
+                res = &mut duplicate => match res {
+                    Ok(res) => {
+                        tokio::spawn(attempt);
+                        Ok(res)
+                    }
+                    Err(_) => attempt.await,
+                },
+            };
+        };
+
+        if res.is_ok() {
+            self.durations.lock().unwrap().push(start.elapsed());
+        }
+
+        res
+    }
+
     /// Execute job on one of the remote machines. If the remote machine fails for some reason,
-    /// the job should be allocated to another machine.
-    pub async fn map<W, I, O>(&self, job: I) -> O
+    /// or its lease expires, the job should be allocated to another machine, at most
+    /// [`MAX_ATTEMPTS`] times. Jobs that run much longer than the others are duplicated
+    /// on an idle machine. `key` identifies the job across the machines it is allocated to.
+    pub async fn map<W, I, O>(&self, key: JobKey, job: I) -> Result<O>
     where
         W: Worker,
-        I: Map<W, O> + Send + Clone,
-        O: Serialize + DeserializeOwned + Send,
+        I: Map<W, O> + Send + Sync + Clone + 'static,
+        O: Serialize + DeserializeOwned + Send + 'static,
     {
-        loop {
-            match self.try_map(&job).await {
-                Ok(res) => return res,
-                Err(Error::NoAvailableWorker) => panic!("{}", Error::NoAvailableWorker),
+        for attempt in 1..=MAX_ATTEMPTS {
+            match self.try_map_speculative(key, &job).await {
+                Ok(res) => return Ok(res),
+                Err(Error::NoAvailableWorker) => return Err(Error::NoAvailableWorker),
                 Err(err) => {
-                    warn!("Worker failed - rescheduling job");
+                    warn!("Worker failed on attempt {} of job {}", attempt, key);
                     debug!("{:?}", err);
                 }
             }
         }
+
+        Err(Error::TooManyAttempts(key))
     }
 
     fn reduce<O1, O2>(acc: Option<O2>, elem: O1) -> O2
@@ -247,40 +536,637 @@ impl Manager {
     }
 
     #[allow(clippy::trait_duplication_in_bounds)]
-    async fn get_results<W, I, O1, O2>(&self, jobs: impl Iterator<Item = I> + Send) -> Option<O2>
+    async fn get_results<W, I, O1, O2>(
+        &self,
+        jobs: impl Iterator<Item = (JobKey, I)> + Send,
+    ) -> Result<Option<O2>>
     where
         W: Worker,
-        I: Map<W, O1> + Send + Clone,
-        O1: Serialize + DeserializeOwned + Send,
+        I: Map<W, O1> + Send + Sync + Clone + 'static,
+        O1: Serialize + DeserializeOwned + Send + 'static,
         O2: From<O1> + Reduce<O1> + Send + Reduce<O2>,
     {
         let mut acc = None;
 
         for chunk in jobs.chunks(self.pool.size()).into_iter() {
-            let results = futures::stream::iter(chunk.map(|job| self.map::<W, I, O1>(job)))
-                .buffer_unordered(self.pool.size())
-                .collect::<Vec<_>>()
-                .await;
+            let results =
+                futures::stream::iter(chunk.map(|(key, job)| self.map::<W, I, O1>(key, job)))
+                    .buffer_unordered(self.pool.size())
+                    .collect::<Vec<_>>()
+                    .await;
 
             for elem in results {
-                acc = Some(Self::reduce(acc, elem));
+                acc = Some(Self::reduce(acc, elem?));
             }
         }
 
-        acc
+        Ok(acc)
     }
 
     #[allow(clippy::trait_duplication_in_bounds)]
-    pub async fn run<W, I, O1, O2>(self, jobs: impl Iterator<Item = I> + Send) -> Option<O2>
+    pub async fn run<W, I, O1, O2>(self, jobs: impl Iterator<Item = I> + Send) -> Result<Option<O2>>
     where
         W: Worker,
-        I: Map<W, O1> + Send + Clone,
-        O1: Serialize + DeserializeOwned + Send,
+        I: Map<W, O1> + Send + Sync + Clone + 'static,
+        O1: Serialize + DeserializeOwned + Send + 'static,
         O2: From<O1> + Reduce<O1> + Send + Reduce<O2>,
     {
-        let result = self.get_results(jobs).await;
+        let result = self
+            .get_results(jobs.enumerate().map(|(key, job)| (key as JobKey, job)))
+            .await;
         self.pool.stop_workers::<W, I, O1>().await;
 
         result
     }
+
+    /// Run jobs that output keyed records and reduce the records of each key into the store
+    /// at `path`. Unlike [`Manager::run`], the reduced output does not have to fit in memory,
+    /// since the reductions are spilled to the store when more than `max_buffered_keys`
+    /// keys are reduced in memory.
+    pub async fn run_spilling<W, I, K, V, P>(
+        self,
+        jobs: impl Iterator<Item = I> + Send,
+        path: P,
+        max_buffered_keys: usize,
+    ) -> Result<RocksDbStore<K, V>>
+    where
+        W: Worker,
+        I: Map<W, Vec<(K, V)>> + Send + Sync + Clone + 'static,
+        K: Serialize + DeserializeOwned + Hash + Eq + Send + Sync + 'static,
+        V: Serialize + DeserializeOwned + Reduce<V> + Send + Sync + 'static,
+        P: AsRef<Path>,
+    {
+        let mut reducer = match SpillingReducer::open(path, max_buffered_keys) {
+            Ok(reducer) => reducer,
+            Err(err) => {
+                self.pool.stop_workers::<W, I, Vec<(K, V)>>().await;
+                return Err(err);
+            }
+        };
+
+        for chunk in jobs.enumerate().chunks(self.pool.size()).into_iter() {
+            let results = futures::stream::iter(
+                chunk.map(|(key, job)| self.map::<W, I, Vec<(K, V)>>(key as JobKey, job)),
+            )
+            .buffer_unordered(self.pool.size())
+            .collect::<Vec<_>>()
+            .await;
+
+            for records in results {
+                match records {
+                    Ok(records) => reducer.extend(records),
+                    Err(err) => {
+                        self.pool.stop_workers::<W, I, Vec<(K, V)>>().await;
+                        return Err(err);
+                    }
+                }
+            }
+        }
+
+        self.pool.stop_workers::<W, I, Vec<(K, V)>>().await;
+        debug!("spilled the reductions {} times", reducer.num_spills());
+
+        Ok(reducer.finish())
+    }
+
+    /// Run the jobs on stateful workers and reduce the aggregates of their state. The jobs
+    /// of a worker that fails to send its aggregate are run again on the other workers.
+    pub async fn run_stateful<W, I>(
+        mut self,
+        jobs: impl Iterator<Item = I> + Send,
+    ) -> Result<Option<W::Aggregate>>
+    where
+        W: StatefulWorker,
+        I: Map<W, ()> + Send + Sync + Clone + 'static,
+        W::Aggregate: Reduce<W::Aggregate>,
+    {
+        // a duplicate of a job would be applied to the state of both workers
+        self.straggler_factor = None;
+
+        let jobs: Vec<_> = jobs.collect();
+        let mut pending: Vec<JobKey> = (0..jobs.len() as JobKey).collect();
+        let mut acc: Option<W::Aggregate> = None;
+
+        while !pending.is_empty() {
+            let batch: Vec<_> = pending
+                .drain(..)
+                .map(|key| (key, jobs[key as usize].clone()))
+                .collect();
+            if let Err(err) = self.get_results::<W, I, (), ()>(batch.into_iter()).await {
+                self.pool.stop_workers::<W, I, ()>().await;
+                return Err(err);
+            }
+
+            let finished_on: Vec<_> = self.finished_on.lock().unwrap().drain().collect();
+
+            for (addr, keys) in finished_on {
+                let worker = RemoteWorker { addr };
+
+                match worker
+                    .teardown::<W, I, (), W::Aggregate>(TEARDOWN_TIMEOUT)
+                    .await
+                {
+                    Ok(Some(aggregate)) => {
+                        acc = Some(match acc {
+                            Some(acc) => acc.reduce(aggregate),
+                            None => aggregate,
+                        });
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        warn!(
+                            "worker {} failed to send its aggregate - rescheduling {} jobs",
+                            addr,
+                            keys.len()
+                        );
+                        debug!("{:?}", err);
+                        self.pool.remove(addr);
+                        pending.extend(keys);
+                    }
+                }
+            }
+        }
+
+        self.pool.stop_workers::<W, I, ()>().await;
+
+        Ok(acc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use tokio::net::TcpListener;
+
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::kv::Kv;
+    use crate::mapreduce::{Partitions, StatelessWorker};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct Double(u64);
+
+    impl Map<StatelessWorker, u64> for Double {
+        fn map(&self, _: &StatelessWorker) -> u64 {
+            self.0 * 2
+        }
+    }
+
+    async fn start_worker() -> SocketAddr {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(StatelessWorker::default().run::<Double, u64>(addr))
+        });
+
+        addr
+    }
+
+    /// Contributes 1 to the remainder of each number when divided by 3.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct Contributions(Vec<u64>);
+
+    impl Map<StatelessWorker, Vec<(u64, u64)>> for Contributions {
+        fn map(&self, _: &StatelessWorker) -> Vec<(u64, u64)> {
+            self.0.iter().map(|n| (n % 3, 1)).collect()
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    struct Count(u64);
+
+    impl Reduce<Count> for Count {
+        fn reduce(self, element: Count) -> Self {
+            Count(self.0 + element.0)
+        }
+    }
+
+    /// Counts the numbers with each remainder when divided by 5.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct Remainders(Vec<u64>);
+
+    impl Map<StatelessWorker, Vec<(u64, Count)>> for Remainders {
+        fn map(&self, _: &StatelessWorker) -> Vec<(u64, Count)> {
+            self.0.iter().map(|n| (n % 5, Count(1))).collect()
+        }
+    }
+
+    /// The pages of a host in each job, keyed by the id of the job.
+    type JobCounts = BTreeMap<u64, u64>;
+
+    /// Counts the pages of each host in its state. The count of a job replaces the
+    /// earlier count of the same job, so a job that is applied twice is only counted once.
+    #[derive(Default)]
+    struct HostCounter {
+        partitions: Partitions<String, JobCounts>,
+    }
+
+    impl Worker for HostCounter {}
+
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct HostCounts(BTreeMap<String, JobCounts>);
+
+    impl HostCounts {
+        fn totals(&self) -> BTreeMap<String, u64> {
+            self.0
+                .iter()
+                .map(|(host, jobs)| (host.clone(), jobs.values().sum()))
+                .collect()
+        }
+    }
+
+    impl Reduce<HostCounts> for HostCounts {
+        fn reduce(mut self, element: HostCounts) -> Self {
+            for (host, jobs) in element.0 {
+                self.0.entry(host).or_default().extend(jobs);
+            }
+
+            self
+        }
+    }
+
+    impl StatefulWorker for HostCounter {
+        type Key = String;
+        type State = JobCounts;
+        type Aggregate = HostCounts;
+
+        fn partitions(&self) -> &Partitions<String, JobCounts> {
+            &self.partitions
+        }
+
+        fn teardown(&self, partitions: HashMap<String, JobCounts>) -> HostCounts {
+            HostCounts(partitions.into_iter().collect())
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct CountHosts {
+        id: u64,
+        hosts: Vec<String>,
+    }
+
+    impl CountHosts {
+        fn new(id: u64, hosts: &[&str]) -> Self {
+            Self {
+                id,
+                hosts: hosts.iter().map(|host| host.to_string()).collect(),
+            }
+        }
+    }
+
+    impl Map<HostCounter, ()> for CountHosts {
+        fn map(&self, worker: &HostCounter) {
+            let mut counts: HashMap<&String, u64> = HashMap::new();
+            for host in &self.hosts {
+                *counts.entry(host).or_default() += 1;
+            }
+
+            for (host, count) in counts {
+                worker.partitions().update(host.clone(), |jobs| {
+                    jobs.insert(self.id, count);
+                });
+            }
+        }
+    }
+
+    fn totals(counts: &[(&str, u64)]) -> BTreeMap<String, u64> {
+        counts
+            .iter()
+            .map(|(host, count)| (host.to_string(), *count))
+            .collect()
+    }
+
+    #[test]
+    fn host_counts_are_idempotent() {
+        let worker = HostCounter::default();
+        let job = CountHosts::new(0, &["a.com", "a.com", "b.com"]);
+
+        job.map(&worker);
+        job.map(&worker);
+        CountHosts::new(1, &["a.com"]).map(&worker);
+
+        let total = |host: &str| {
+            worker
+                .partitions()
+                .update(host.to_string(), |jobs| jobs.values().sum::<u64>())
+        };
+        assert_eq!(total("a.com"), 3);
+        assert_eq!(total("b.com"), 1);
+
+        // a job that was applied by two workers is only counted once
+        let counts = |jobs: &[(u64, u64)]| {
+            HostCounts(
+                [("a.com".to_string(), jobs.iter().copied().collect())]
+                    .into_iter()
+                    .collect(),
+            )
+        };
+        assert_eq!(
+            counts(&[(0, 2)]).reduce(counts(&[(0, 2), (1, 1)])).totals(),
+            totals(&[("a.com", 3)])
+        );
+    }
+
+    /// A worker that accepts the jobs but never responds.
+    async fn start_stuck_worker() -> SocketAddr {
+        let stuck = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = stuck.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut conns = Vec::new();
+            while let Ok((conn, _)) = stuck.accept().await {
+                conns.push(conn);
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn reassign_job_when_lease_expires() {
+        let addr = start_worker().await;
+        let stuck_addr = start_stuck_worker().await;
+
+        // the stuck worker is the first to get a job
+        let manager = Manager::new(&[addr, stuck_addr])
+            .with_lease(Duration::from_millis(500))
+            .with_straggler_factor(None);
+
+        assert_eq!(
+            manager
+                .map::<StatelessWorker, Double, u64>(0, Double(21))
+                .await
+                .unwrap(),
+            42
+        );
+    }
+
+    #[tokio::test]
+    async fn combine_on_worker() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new().unwrap().block_on(
+                StatelessWorker::default()
+                    .run_with_combiner::<Contributions, Vec<(u64, u64)>>(addr),
+            )
+        });
+
+        let manager = Manager::new(&[addr]);
+
+        assert_eq!(
+            manager
+                .map::<StatelessWorker, Contributions, Vec<(u64, u64)>>(
+                    0,
+                    Contributions((0..10).collect())
+                )
+                .await
+                .unwrap(),
+            vec![(0, 4), (1, 3), (2, 3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn spilling_reduce() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(StatelessWorker::default().run::<Remainders, Vec<(u64, Count)>>(addr))
+        });
+
+        let manager = Manager::new(&[addr]);
+        let jobs = (0..4).map(|i| Remainders((i * 10..(i + 1) * 10).collect()));
+
+        let store = manager
+            .run_spilling::<StatelessWorker, _, _, _, _>(jobs, crate::gen_temp_path(), 2)
+            .await
+            .unwrap();
+
+        let mut counts: Vec<_> = store.iter().collect();
+        counts.sort_by_key(|(remainder, _)| *remainder);
+
+        assert_eq!(counts, (0..5).map(|r| (r, Count(8))).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn stateful_workers() {
+        let mut addrs = Vec::new();
+
+        for _ in 0..2 {
+            let addr = TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap()
+                .local_addr()
+                .unwrap();
+
+            std::thread::spawn(move || {
+                tokio::runtime::Runtime::new()
+                    .unwrap()
+                    .block_on(HostCounter::default().run_stateful::<CountHosts>(addr))
+            });
+
+            addrs.push(addr);
+        }
+
+        let jobs = [
+            vec!["a.com", "b.com"],
+            vec!["a.com"],
+            vec!["c.com", "a.com"],
+            vec!["b.com"],
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(id, hosts)| CountHosts::new(id as u64, &hosts));
+
+        let counts = Manager::new(&addrs)
+            .run_stateful::<HostCounter, CountHosts>(jobs)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            counts.totals(),
+            totals(&[("a.com", 3), ("b.com", 2), ("c.com", 1)])
+        );
+    }
+
+    /// A [`HostCounter`] whose setup blocks until it is told to finish.
+    struct SlowSetup {
+        counter: HostCounter,
+        finish: Mutex<std::sync::mpsc::Receiver<()>>,
+    }
+
+    impl Worker for SlowSetup {}
+
+    impl StatefulWorker for SlowSetup {
+        type Key = String;
+        type State = JobCounts;
+        type Aggregate = HostCounts;
+
+        fn partitions(&self) -> &Partitions<String, JobCounts> {
+            self.counter.partitions()
+        }
+
+        fn setup(&self) {
+            self.finish.lock().unwrap().recv().ok();
+        }
+
+        fn teardown(&self, partitions: HashMap<String, JobCounts>) -> HostCounts {
+            self.counter.teardown(partitions)
+        }
+    }
+
+    impl Map<SlowSetup, ()> for CountHosts {
+        fn map(&self, worker: &SlowSetup) {
+            Map::<HostCounter, ()>::map(self, &worker.counter)
+        }
+    }
+
+    #[tokio::test]
+    async fn workers_are_loading_until_setup_finishes() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (finish, finished) = std::sync::mpsc::channel();
+
+        std::thread::spawn(move || {
+            let worker = SlowSetup {
+                counter: HostCounter::default(),
+                finish: Mutex::new(finished),
+            };
+
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(worker.run_stateful::<CountHosts>(addr))
+        });
+
+        let wait_for = |health: Health| async move {
+            tokio::time::timeout(Duration::from_secs(10), async {
+                while probe::probe(addr, probe::PROBE_TIMEOUT).await != Some(health) {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .unwrap();
+        };
+
+        wait_for(Health::Loading).await;
+        finish.send(()).unwrap();
+        wait_for(Health::Ready).await;
+
+        let counts = Manager::new(&[addr])
+            .run_stateful::<SlowSetup, CountHosts>(std::iter::once(CountHosts::new(0, &["a.com"])))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(counts.totals(), totals(&[("a.com", 1)]));
+    }
+
+    /// Doubles the numbers like the [`StatelessWorker`], but over TLS.
+    #[derive(Default)]
+    struct TlsWorker;
+
+    impl Worker for TlsWorker {}
+
+    impl Map<TlsWorker, u64> for Double {
+        fn map(&self, _: &TlsWorker) -> u64 {
+            self.0 * 2
+        }
+    }
+
+    #[tokio::test]
+    async fn workers_with_tls() {
+        sonic::tls::configure::<TlsWorker>(&sonic::tls::self_signed_config("localhost")).unwrap();
+
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(TlsWorker.run::<Double, u64>(addr))
+        });
+
+        let manager = Manager::new(&[addr]);
+
+        assert_eq!(
+            manager
+                .map::<TlsWorker, Double, u64>(0, Double(21))
+                .await
+                .unwrap(),
+            42
+        );
+
+        // the worker only accepts TLS connections
+        assert!(MapReduceConnection::<Double, u64>::create_with_timeout(
+            addr,
+            Duration::from_secs(1)
+        )
+        .await
+        .unwrap()
+        .send(&Task::Job {
+            key: 1,
+            job: Double(1)
+        })
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn duplicate_stragglers() {
+        let addr = start_worker().await;
+        let stuck_addr = start_stuck_worker().await;
+
+        // the stuck worker is the first to get a job, and its lease does not expire in the test
+        let manager = Manager::new(&[addr, stuck_addr]);
+        manager
+            .durations
+            .lock()
+            .unwrap()
+            .extend([Duration::from_millis(10); MIN_FINISHED_JOBS]);
+
+        let res = tokio::time::timeout(
+            Duration::from_secs(10),
+            manager.map::<StatelessWorker, Double, u64>(0, Double(21)),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(res, 42);
+
+        // the stuck worker is still running the job, so only the other worker is back in the pool
+        let ready: Vec<_> = manager
+            .pool
+            .ready_workers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|worker| worker.addr)
+            .collect();
+        assert_eq!(ready, vec![addr]);
+        assert_eq!(manager.pool.running_workers.load(Ordering::SeqCst), 1);
+    }
 }
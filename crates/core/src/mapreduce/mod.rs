@@ -17,13 +17,19 @@
 //! MapReduce is a distributed computing framework for processing large data sets across
 //! clusters of computers using a master node to coordinate the work.
 
+use std::{collections::HashMap, hash::Hash, ops::AddAssign};
+
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 mod manager;
+mod spill;
 mod worker;
 
 pub use manager::Manager;
+pub use spill::SpillingReducer;
 use thiserror::Error;
+pub use worker::Partitions;
+pub use worker::StatefulWorker;
 pub use worker::StatelessWorker;
 pub use worker::Worker;
 
@@ -41,8 +47,24 @@ pub enum Error {
 
     #[error("did not get a reponse")]
     NoResponse,
+
+    #[error("the worker did not answer the heartbeats before its lease expired")]
+    LeaseExpired,
+
+    #[error("job {0} failed on every attempt")]
+    TooManyAttempts(JobKey),
+
+    #[error("the spill store at {0:?} is not empty")]
+    SpillStoreNotEmpty(std::path::PathBuf),
+
+    #[error("io error")]
+    Io(#[from] std::io::Error),
 }
 
+/// Identifies a job across its attempts, so a worker that gets a job it has
+/// already finished can answer with the result instead of running it again.
+pub type JobKey = u64;
+
 pub trait Map<W, T>
 where
     Self: Serialize + DeserializeOwned + Send,
@@ -52,16 +74,82 @@ where
     fn map(&self, worker: &W) -> T;
 }
 
+/// Combines the records in the result of a job on the worker before the result is sent to
+/// the manager, like a reduce that runs on the worker. Only used by workers that are run
+/// with [`Worker::run_with_combiner`].
+pub trait Combine {
+    #[must_use]
+    fn combine(self) -> Self;
+}
+
+/// Sum the values of the records with the same key, e.g. the centrality contributions of each node.
+/// The records are kept in the order their keys first appear.
+impl<K, V> Combine for Vec<(K, V)>
+where
+    K: Hash + Eq + Clone,
+    V: AddAssign,
+{
+    fn combine(self) -> Self {
+        let mut positions = HashMap::with_capacity(self.len());
+        let mut combined: Vec<(K, V)> = Vec::new();
+
+        for (key, value) in self {
+            match positions.get(&key) {
+                Some(&pos) => combined[pos].1 += value,
+                None => {
+                    positions.insert(key.clone(), combined.len());
+                    combined.push((key, value));
+                }
+            }
+        }
+
+        combined
+    }
+}
+
 pub trait Reduce<T> {
     #[must_use]
     fn reduce(self, element: T) -> Self;
 }
 
+/// The jobs of stateful workers return nothing, since their results are in the state.
+impl Reduce<()> for () {
+    fn reduce(self, _: ()) -> Self {}
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 enum Task<T> {
-    Job(T),
+    Job {
+        key: JobKey,
+        job: T,
+    },
+    /// Send the aggregate of the state of the worker, see [`StatefulWorker`].
+    Teardown,
     AllFinished,
 }
 
-type MapReduceServer<I, O> = sonic::Server<Task<I>, Option<O>>;
-type MapReduceConnection<I, O> = sonic::Connection<Task<I>, Option<O>>;
+#[derive(Serialize, Deserialize, Debug)]
+enum Response<T> {
+    Job(T),
+    /// A chunk of the aggregate of a stateful worker serialized with bincode, since
+    /// its type is not known to the jobs. The worker responds to a teardown with
+    /// a stream of the chunks.
+    Aggregate(Vec<u8>),
+    Finished,
+}
+
+type MapReduceServer<I, O> = sonic::Server<Task<I>, Response<O>>;
+type MapReduceConnection<I, O> = sonic::Connection<Task<I>, Response<O>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combine_records_by_key() {
+        let records = vec![("b", 1), ("a", 2), ("b", 3), ("c", 4), ("a", 5)];
+
+        assert_eq!(records.combine(), vec![("b", 4), ("a", 7), ("c", 4)]);
+        assert_eq!(Vec::<(u64, f64)>::new().combine(), vec![]);
+    }
+}
@@ -0,0 +1,175 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Reduction of keyed records that might not fit in memory. The records are reduced in
+//! memory until there are too many keys, and then the reductions are merged into the
+//! reductions on disk, so the memory use is bounded by the number of buffered keys.
+
+use std::{collections::HashMap, hash::Hash, path::Path};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Error, Reduce, Result};
+use crate::kv::{rocksdb_store::RocksDbStore, Kv};
+
+pub struct SpillingReducer<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    buffer: HashMap<K, V>,
+    max_buffered_keys: usize,
+    store: RocksDbStore<K, V>,
+    num_spills: usize,
+}
+
+impl<K, V> SpillingReducer<K, V>
+where
+    K: Serialize + DeserializeOwned + Hash + Eq + Send + Sync + 'static,
+    V: Serialize + DeserializeOwned + Reduce<V> + Send + Sync + 'static,
+{
+    /// The reductions are spilled to the store at `path` when more than
+    /// `max_buffered_keys` keys are reduced in memory. The store must be new, since
+    /// the records of an earlier run would otherwise be reduced into the results.
+    pub fn open<P: AsRef<Path>>(path: P, max_buffered_keys: usize) -> Result<Self> {
+        let path = path.as_ref();
+
+        if path.exists() && std::fs::read_dir(path)?.next().is_some() {
+            return Err(Error::SpillStoreNotEmpty(path.to_path_buf()));
+        }
+
+        Ok(Self {
+            buffer: HashMap::new(),
+            max_buffered_keys: max_buffered_keys.max(1),
+            store: RocksDbStore::open(path),
+            num_spills: 0,
+        })
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        let value = match self.buffer.remove(&key) {
+            Some(acc) => acc.reduce(value),
+            None => value,
+        };
+        self.buffer.insert(key, value);
+
+        if self.buffer.len() > self.max_buffered_keys {
+            self.spill();
+        }
+    }
+
+    pub fn extend(&mut self, records: impl IntoIterator<Item = (K, V)>) {
+        for (key, value) in records {
+            self.insert(key, value);
+        }
+    }
+
+    /// Merge the reductions in memory into the reductions on disk.
+    fn spill(&mut self) {
+        for (key, value) in self.buffer.drain() {
+            let value = match self.store.get(&key) {
+                Some(acc) => acc.reduce(value),
+                None => value,
+            };
+
+            self.store.insert(key, value);
+        }
+
+        self.store.flush();
+        self.num_spills += 1;
+    }
+
+    /// The number of times the reductions in memory have been merged into the store.
+    pub fn num_spills(&self) -> usize {
+        self.num_spills
+    }
+
+    /// The store with the reduction of the records of each key.
+    pub fn finish(mut self) -> RocksDbStore<K, V> {
+        if !self.buffer.is_empty() {
+            self.spill();
+        }
+
+        self.store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    struct Sum(u64);
+
+    impl Reduce<Sum> for Sum {
+        fn reduce(self, element: Sum) -> Self {
+            Sum(self.0 + element.0)
+        }
+    }
+
+    fn records() -> Vec<(u64, Sum)> {
+        (0..1_000).map(|i| (i % 37, Sum(i))).collect()
+    }
+
+    fn expected() -> BTreeMap<u64, Sum> {
+        let mut expected = BTreeMap::new();
+
+        for (key, value) in records() {
+            let sum = expected.entry(key).or_insert(Sum(0));
+            *sum = sum.reduce(value);
+        }
+
+        expected
+    }
+
+    #[test]
+    fn spilled_reductions_are_merged() {
+        let mut reducer = SpillingReducer::open(crate::gen_temp_path(), 10).unwrap();
+        reducer.extend(records());
+        assert!(reducer.num_spills() > 1);
+
+        let store = reducer.finish();
+        assert_eq!(store.iter().collect::<BTreeMap<_, _>>(), expected());
+    }
+
+    #[test]
+    fn reductions_in_memory() {
+        let mut reducer = SpillingReducer::open(crate::gen_temp_path(), 100).unwrap();
+        reducer.extend(records());
+        assert_eq!(reducer.num_spills(), 0);
+
+        let store = reducer.finish();
+        assert_eq!(store.iter().collect::<BTreeMap<_, _>>(), expected());
+    }
+
+    #[test]
+    fn existing_store_is_rejected() {
+        let path = crate::gen_temp_path();
+
+        let mut reducer = SpillingReducer::open(&path, 10).unwrap();
+        reducer.extend(records());
+        drop(reducer.finish());
+
+        assert!(matches!(
+            SpillingReducer::<u64, Sum>::open(&path, 10),
+            Err(Error::SpillStoreNotEmpty(_))
+        ));
+    }
+}
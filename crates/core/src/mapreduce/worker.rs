@@ -14,47 +14,223 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{future::Future, net::SocketAddr};
+use std::{collections::HashMap, future::Future, hash::Hash, net::SocketAddr, sync::Mutex};
 
+use crate::distributed::probe::{Health, ProbeResponder};
+use crate::distributed::sonic;
 use crate::mapreduce::MapReduceServer;
 
-use super::{Map, Result, Task};
+use super::{Combine, JobKey, Map, Response, Result, Task};
 use serde::{de::DeserializeOwned, Serialize};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+
+/// The aggregate of a stateful worker can be much larger than the results of its jobs,
+/// so it is sent to the manager in chunks of at most this many bytes.
+const AGGREGATE_CHUNK_SIZE: usize = 64 * 1024 * 1024;
 
 #[derive(Default)]
 pub struct StatelessWorker {}
 
-pub trait Worker {
+/// Handle the tasks from the manager until it says that all jobs are finished. The worker
+/// answers the probes of the manager that it is loading until `setup` has finished. `combine`
+/// is applied to the result of each job before it is sent, and `teardown` returns the
+/// serialized aggregate of the state of the worker, if it has any.
+async fn serve<W, I, O>(
+    worker: &W,
+    addr: SocketAddr,
+    setup: impl FnOnce(),
+    combine: impl Fn(O) -> O,
+    teardown: impl Fn() -> Result<Option<Vec<u8>>>,
+) -> Result<()>
+where
+    I: Map<W, O> + Send + Sync,
+    O: Serialize + DeserializeOwned + Send + Sync + Clone,
+    W: Worker,
+{
+    let tls = sonic::tls::service::<W>();
+    let server = MapReduceServer::<I, O>::bind_with_tls(addr, tls.acceptor)
+        .await?
+        .with_secret(tls.secret);
+    // answers the probes of the manager while the worker is running
+    let probe = ProbeResponder::bind(addr, Health::Loading)
+        .await
+        .map_err(sonic::Error::from)?;
+
+    // the probes are answered by a background task while the setup blocks
+    setup();
+    probe.set_health(Health::Ready);
+    info!("worker listening on: {:}", addr);
+
+    // jobs are handled one at a time, so the job that is being handled
+    // always finishes before the worker stops
+    let shutdown = sonic::shutdown_signal();
+    tokio::pin!(shutdown);
+
+    // the manager might give the job to the worker again if the lease
+    // expired before the worker finished it
+    let mut finished: Option<(JobKey, O)> = None;
+
+    loop {
+        let req = tokio::select! {
+            req = server.accept() => req?,
+            _ = &mut shutdown => {
+                info!("worker is shutting down");
+                break;
+            }
+        };
+        debug!("received request");
+        match req.body() {
+            Task::Job { key, job } => {
+                debug!("request is job {}", key);
+
+                let res = match finished.take() {
+                    Some((finished_key, res)) if finished_key == *key => {
+                        debug!("job {} is already finished", key);
+                        res
+                    }
+                    _ => combine(job.map(worker)),
+                };
+
+                finished = Some((*key, res.clone()));
+
+                // the manager stops waiting for the response when the lease expires
+                if let Err(e) = req.respond(Response::Job(res)).await {
+                    warn!("failed to respond to job {}: {:?}", key, e);
+                }
+            }
+            Task::Teardown => {
+                // jobs after the teardown start from an empty state,
+                // so a job given again must be applied again
+                finished = None;
+
+                let mut req = req;
+
+                match teardown()? {
+                    Some(aggregate) => {
+                        let chunks = aggregate
+                            .chunks(AGGREGATE_CHUNK_SIZE)
+                            .map(|chunk| Ok(Response::Aggregate(chunk.to_vec())));
+
+                        req.write_stream(futures::stream::iter(chunks)).await?
+                    }
+                    None => {
+                        req.write_stream(futures::stream::iter([Ok(Response::Finished)]))
+                            .await?
+                    }
+                };
+            }
+            Task::AllFinished => {
+                req.respond(Response::Finished).await?;
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The workers and the manager use the TLS settings of the worker type,
+/// see [`sonic::tls::configure`].
+pub trait Worker: 'static {
     fn run<I, O>(&self, addr: SocketAddr) -> impl Future<Output = Result<()>>
     where
         Self: Sized,
         I: Map<Self, O> + Send + Sync,
-        O: Serialize + DeserializeOwned + Send + Sync,
+        O: Serialize + DeserializeOwned + Send + Sync + Clone,
     {
-        async move {
-            let server = MapReduceServer::<I, O>::bind(addr).await?;
-            info!("worker listening on: {:}", addr);
-
-            loop {
-                let req = server.accept().await?;
-                debug!("received request");
-                match req.body() {
-                    Task::Job(job) => {
-                        debug!("request is a job");
-                        let res = job.map(self);
-                        req.respond(Some(res)).await?;
-                    }
-                    Task::AllFinished => {
-                        req.respond(None).await?;
-                        break;
-                    }
-                }
-            }
+        serve::<Self, I, O>(self, addr, || {}, |res| res, || Ok(None))
+    }
 
-            Ok(())
-        }
+    /// Like [`Worker::run`], but the results of the jobs are combined before they are sent.
+    fn run_with_combiner<I, O>(&self, addr: SocketAddr) -> impl Future<Output = Result<()>>
+    where
+        Self: Sized,
+        I: Map<Self, O> + Send + Sync,
+        O: Serialize + DeserializeOwned + Send + Sync + Clone + Combine,
+    {
+        serve::<Self, I, O>(self, addr, || {}, O::combine, || Ok(None))
     }
 }
 
 impl Worker for StatelessWorker {}
+
+/// The state of a [`StatefulWorker`], partitioned by key, e.g. by host.
+pub struct Partitions<K, S> {
+    partitions: Mutex<HashMap<K, S>>,
+}
+
+impl<K, S> Default for Partitions<K, S> {
+    fn default() -> Self {
+        Self {
+            partitions: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K, S> Partitions<K, S>
+where
+    K: Hash + Eq,
+    S: Default,
+{
+    /// Update the state of the partition with the key. New partitions start from the default state.
+    pub fn update<T>(&self, key: K, f: impl FnOnce(&mut S) -> T) -> T {
+        f(self.partitions.lock().unwrap().entry(key).or_default())
+    }
+
+    pub fn len(&self) -> usize {
+        self.partitions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn take(&self) -> HashMap<K, S> {
+        std::mem::take(&mut *self.partitions.lock().unwrap())
+    }
+}
+
+/// A worker that keeps state between its jobs, so jobs like the construction of the webgraph
+/// can accumulate the state of each host locally. The jobs update the state through
+/// [`StatefulWorker::partitions`], and when the manager has given out all the jobs, the
+/// state is turned into an aggregate that is the only thing sent back to the manager.
+///
+/// A job whose lease expires because the worker stopped answering the heartbeats of the
+/// manager for a while can still be applied by two workers, so the updates of the state
+/// should be idempotent.
+pub trait StatefulWorker: Worker + Sized + Sync {
+    type Key: Hash + Eq;
+    type State: Default;
+    type Aggregate: Serialize + DeserializeOwned + Send;
+
+    fn partitions(&self) -> &Partitions<Self::Key, Self::State>;
+
+    /// Called before the worker accepts any jobs. The worker tells the manager
+    /// that it is loading until the setup has finished.
+    fn setup(&self) {}
+
+    /// Turn the partitions of the state into the aggregate that is sent to the manager.
+    /// The state of the worker is empty afterwards.
+    fn teardown(&self, partitions: HashMap<Self::Key, Self::State>) -> Self::Aggregate;
+
+    fn run_stateful<I>(&self, addr: SocketAddr) -> impl Future<Output = Result<()>>
+    where
+        I: Map<Self, ()> + Send + Sync,
+    {
+        async move {
+            serve::<Self, I, ()>(
+                self,
+                addr,
+                || self.setup(),
+                |res| res,
+                || {
+                    let aggregate = self.teardown(self.partitions().take());
+                    let aggregate = bincode::serialize(&aggregate).map_err(sonic::Error::from)?;
+
+                    Ok(Some(aggregate))
+                },
+            )
+            .await
+        }
+    }
+}
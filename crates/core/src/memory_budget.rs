@@ -0,0 +1,209 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Process wide memory budget for caches.
+//!
+//! Caches register themselves with the budget as a [`MemoryConsumer`]. When the
+//! combined size of all registered consumers exceeds the limit, the budget asks
+//! the largest consumers to evict entries until the total is below the limit
+//! again. This makes a single limit control the memory used by all caches,
+//! instead of having to tune each cache individually.
+//!
+//! The registered consumers are the query filter caches, the instant search
+//! cache of the api and the block caches of the rocksdb stores, which includes
+//! the centrality stores. Snippets and the model runners are computed per
+//! request without a cache, so there is nothing for them to register.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex, Weak,
+};
+
+/// A cache whose memory is accounted for by the [`MemoryBudget`].
+pub trait MemoryConsumer: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Current number of bytes used by the consumer.
+    fn size_bytes(&self) -> usize;
+
+    /// Evict entries until at least `bytes` has been freed or the consumer is
+    /// empty. Returns the number of bytes actually freed.
+    fn evict(&self, bytes: usize) -> usize;
+}
+
+static GLOBAL: once_cell::sync::Lazy<MemoryBudget> = once_cell::sync::Lazy::new(MemoryBudget::new);
+
+/// The budget shared by all caches in the process.
+pub fn global() -> &'static MemoryBudget {
+    &GLOBAL
+}
+
+const UNLIMITED: usize = usize::MAX;
+
+pub struct MemoryBudget {
+    limit_bytes: AtomicUsize,
+    consumers: Mutex<Vec<Weak<dyn MemoryConsumer>>>,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryBudget {
+    pub fn new() -> Self {
+        Self {
+            limit_bytes: AtomicUsize::new(UNLIMITED),
+            consumers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn set_limit(&self, limit_bytes: Option<usize>) {
+        self.limit_bytes
+            .store(limit_bytes.unwrap_or(UNLIMITED), Ordering::Relaxed);
+        self.enforce();
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        match self.limit_bytes.load(Ordering::Relaxed) {
+            UNLIMITED => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// Register a consumer. The budget only keeps a weak reference, so the
+    /// consumer is automatically unregistered when it is dropped.
+    pub fn register(&self, consumer: Arc<dyn MemoryConsumer>) {
+        self.consumers
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&consumer));
+    }
+
+    fn live_consumers(&self) -> Vec<Arc<dyn MemoryConsumer>> {
+        let mut consumers = self.consumers.lock().unwrap();
+        consumers.retain(|c| c.strong_count() > 0);
+        consumers.iter().filter_map(|c| c.upgrade()).collect()
+    }
+
+    /// Total number of bytes used by the registered consumers.
+    pub fn usage(&self) -> usize {
+        self.live_consumers().iter().map(|c| c.size_bytes()).sum()
+    }
+
+    /// Evict from the largest consumers until the usage is within the limit.
+    /// Must not be called while holding a lock that the consumers'
+    /// [`MemoryConsumer::evict`] needs.
+    pub fn enforce(&self) {
+        let Some(limit) = self.limit() else {
+            return;
+        };
+
+        let mut consumers: Vec<_> = self
+            .live_consumers()
+            .into_iter()
+            .map(|c| (c.size_bytes(), c))
+            .collect();
+
+        let usage: usize = consumers.iter().map(|(size, _)| *size).sum();
+
+        if usage <= limit {
+            return;
+        }
+
+        let mut to_free = usage - limit;
+        consumers.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+        for (size, consumer) in consumers {
+            if to_free == 0 {
+                break;
+            }
+
+            let freed = consumer.evict(to_free.min(size));
+            tracing::debug!("evicted {} bytes from {}", freed, consumer.name());
+            to_free = to_free.saturating_sub(freed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestConsumer {
+        size: AtomicUsize,
+    }
+
+    impl TestConsumer {
+        fn new(size: usize) -> Arc<Self> {
+            Arc::new(Self {
+                size: AtomicUsize::new(size),
+            })
+        }
+    }
+
+    impl MemoryConsumer for TestConsumer {
+        fn name(&self) -> &str {
+            "test"
+        }
+
+        fn size_bytes(&self) -> usize {
+            self.size.load(Ordering::Relaxed)
+        }
+
+        fn evict(&self, bytes: usize) -> usize {
+            let size = self.size_bytes();
+            let freed = bytes.min(size);
+            self.size.store(size - freed, Ordering::Relaxed);
+            freed
+        }
+    }
+
+    #[test]
+    fn evicts_largest_first() {
+        let budget = MemoryBudget::new();
+
+        let small = TestConsumer::new(10);
+        let large = TestConsumer::new(100);
+
+        budget.register(small.clone());
+        budget.register(large.clone());
+
+        assert_eq!(budget.usage(), 110);
+
+        budget.enforce();
+        assert_eq!(budget.usage(), 110);
+
+        budget.set_limit(Some(50));
+        assert_eq!(budget.usage(), 50);
+        assert_eq!(small.size_bytes(), 10);
+        assert_eq!(large.size_bytes(), 40);
+    }
+
+    #[test]
+    fn dropped_consumers_are_unregistered() {
+        let budget = MemoryBudget::new();
+
+        let consumer = TestConsumer::new(10);
+        budget.register(consumer.clone());
+        assert_eq!(budget.usage(), 10);
+
+        drop(consumer);
+        assert_eq!(budget.usage(), 0);
+        assert!(budget.consumers.lock().unwrap().is_empty());
+    }
+}
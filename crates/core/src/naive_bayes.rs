@@ -180,6 +180,36 @@ impl<L: Label> NaiveBayes<L> {
         Prediction { label, confidence }
     }
 
+    /// Predicts the confidence of every class for a given sample.
+    /// The confidences sum to 1 and the predictions are sorted by confidence.
+    pub fn predict_all(&self, sample: &[TfIdf]) -> Vec<Prediction<L>> {
+        // `predict` takes the class with the lowest score, so the scores
+        // are negated before taking the softmax.
+        let scores: Vec<_> = self
+            .calculate_class_log_probs(sample)
+            .map(|(class_id, log_prob)| (class_id, -log_prob))
+            .collect();
+
+        let max = scores
+            .iter()
+            .map(|(_, score)| *score)
+            .fold(f32::NEG_INFINITY, f32::max);
+
+        let exps: Vec<_> = scores
+            .into_iter()
+            .map(|(class_id, score)| (class_id, (score - max).exp()))
+            .collect();
+        let sum: f32 = exps.iter().map(|(_, e)| *e).sum();
+
+        exps.into_iter()
+            .map(|(class_id, e)| Prediction {
+                label: self.classes[class_id].clone(),
+                confidence: e / sum,
+            })
+            .sorted_by(|a, b| b.confidence.total_cmp(&a.confidence))
+            .collect()
+    }
+
     /// Helper function to extract unique classes from the data points
     fn extract_unique_classes(&self, datapoints: &[Datapoint<L>]) -> Vec<L> {
         datapoints
@@ -299,6 +329,11 @@ impl<L: Label> Pipeline<L> {
         let features = self.vectorizer.transform(doc);
         self.classifier.predict(&features)
     }
+
+    pub fn predict_all(&self, doc: &str) -> Vec<Prediction<L>> {
+        let features = self.vectorizer.transform(doc);
+        self.classifier.predict_all(&features)
+    }
 }
 
 #[cfg(test)]
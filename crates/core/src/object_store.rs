@@ -0,0 +1,1007 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Storage of large artifacts like indexes, webgraphs and warc files in
+//! S3-compatible object storage, or a local folder.
+//!
+//! Folders are stored as snapshots. The content of every file is stored once under its sha256
+//! (e.g. `index/blobs/<sha256>`), and each generation of the artifact is a manifest with the
+//! path, size and sha256 of its files (e.g. `index/00000001702288800000/MANIFEST.json`).
+//! The manifest is uploaded last, so a generation is only visible once all its files has been
+//! uploaded, and the files are verified against it when the snapshot is restored.
+//! This lets nodes be provisioned without any local state by restoring the latest generation.
+//!
+//! Since index segments are immutable, consecutive generations share most of their files.
+//! Only new content is uploaded, and replicas that restored an earlier generation only
+//! download the files that changed since.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::config::{LocalObjectStoreConfig, ObjectStoreConfig, S3Config, SnapshotConfig};
+
+/// Objects larger than this are uploaded and downloaded in parts of this size.
+/// S3 requires all parts but the last to be at least 5MB.
+const PART_SIZE: usize = 64 * 1024 * 1024;
+
+const CONTENT_TYPE: &str = "application/octet-stream";
+
+const MANIFEST_NAME: &str = "MANIFEST.json";
+
+/// Folder in the store with the content of all files, named by their sha256.
+const BLOBS_NAME: &str = "blobs";
+
+/// File in restored folders with the generation it was restored from.
+const LOCAL_STATE_NAME: &str = ".snapshot.json";
+
+fn join_key(prefix: &str, key: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{prefix}/{key}")
+    }
+}
+
+pub struct S3Store {
+    bucket: s3::Bucket,
+    folder: String,
+}
+
+impl S3Store {
+    pub fn open(config: &S3Config) -> Result<Self> {
+        let bucket = s3::Bucket::new(
+            &config.bucket,
+            s3::Region::Custom {
+                region: "".to_string(),
+                endpoint: config.endpoint.clone(),
+            },
+            s3::creds::Credentials {
+                access_key: Some(config.access_key.clone()),
+                secret_key: Some(config.secret_key.clone()),
+                security_token: None,
+                session_token: None,
+                expiration: None,
+            },
+        )?
+        .with_path_style()
+        .with_request_timeout(Duration::from_secs(30 * 60));
+
+        Ok(Self {
+            bucket,
+            folder: config.folder.clone(),
+        })
+    }
+
+    async fn size(&self, key: &str) -> Result<u64> {
+        let (head, _) = self.bucket.head_object(key).await?;
+
+        head.content_length
+            .map(|len| len as u64)
+            .ok_or_else(|| anyhow!("{} has no content length", key))
+    }
+
+    /// Upload the parts with a multipart upload. The upload is aborted if any part fails.
+    async fn put_parts(&self, key: &str, parts: &mut (dyn Read + Send)) -> Result<()> {
+        let upload = self
+            .bucket
+            .initiate_multipart_upload(key, CONTENT_TYPE)
+            .await?;
+
+        let mut uploaded = Vec::new();
+        let mut part_number = 1;
+
+        let res: Result<()> = async {
+            loop {
+                let mut chunk = Vec::with_capacity(PART_SIZE);
+                (&mut *parts)
+                    .take(PART_SIZE as u64)
+                    .read_to_end(&mut chunk)?;
+
+                if chunk.is_empty() {
+                    break;
+                }
+
+                uploaded.push(
+                    self.bucket
+                        .put_multipart_chunk(
+                            chunk,
+                            key,
+                            part_number,
+                            &upload.upload_id,
+                            CONTENT_TYPE,
+                        )
+                        .await?,
+                );
+                part_number += 1;
+            }
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(err) = res {
+            self.bucket.abort_upload(key, &upload.upload_id).await.ok();
+            return Err(err);
+        }
+
+        self.bucket
+            .complete_multipart_upload(key, &upload.upload_id, uploaded)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn put(&self, key: &str, data: &mut (dyn Read + Send), size: u64) -> Result<()> {
+        let key = join_key(&self.folder, key);
+
+        if size as usize <= PART_SIZE {
+            let mut bytes = Vec::with_capacity(size as usize);
+            data.read_to_end(&mut bytes)?;
+
+            let res = self
+                .bucket
+                .put_object_with_content_type(&key, &bytes, CONTENT_TYPE)
+                .await?;
+
+            if res.status_code() != 200 {
+                bail!("failed to upload {}: status {}", key, res.status_code());
+            }
+        } else {
+            self.put_parts(&key, data).await?;
+        }
+
+        let stored = self.size(&key).await?;
+        if stored != size {
+            bail!("{} has {} bytes but {} was uploaded", key, stored, size);
+        }
+
+        Ok(())
+    }
+
+    async fn get_file(&self, key: &str, path: &Path) -> Result<()> {
+        let key = join_key(&self.folder, key);
+        let size = self.size(&key).await?;
+
+        let mut file = tokio::fs::File::create(path).await?;
+        let mut start = 0;
+
+        while start < size {
+            let end = (start + PART_SIZE as u64).min(size) - 1;
+            let res = self.bucket.get_object_range(&key, start, Some(end)).await?;
+
+            if !matches!(res.status_code(), 200 | 206) {
+                bail!("failed to download {}: status {}", key, res.status_code());
+            }
+
+            file.write_all(res.bytes()).await?;
+            start = end + 1;
+        }
+
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let key = join_key(&self.folder, key);
+        let res = self.bucket.get_object(&key).await?;
+
+        if res.status_code() != 200 {
+            bail!("failed to download {}: status {}", key, res.status_code());
+        }
+
+        Ok(res.bytes().to_vec())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let folder = join_key(&self.folder, "");
+        let pages = self
+            .bucket
+            .list(join_key(&self.folder, prefix), None)
+            .await?;
+
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.contents.into_iter())
+            .filter_map(|object| {
+                object
+                    .key
+                    .strip_prefix(&folder)
+                    .map(|key| key.trim_start_matches('/').to_string())
+            })
+            .collect())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.bucket
+            .delete_object(join_key(&self.folder, key))
+            .await?;
+
+        Ok(())
+    }
+}
+
+pub struct LocalStore {
+    folder: PathBuf,
+}
+
+impl LocalStore {
+    pub fn open(config: &LocalObjectStoreConfig) -> Self {
+        Self {
+            folder: PathBuf::from(&config.folder),
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.folder.join(key)
+    }
+
+    async fn put(&self, key: &str, data: &mut (dyn Read + Send)) -> Result<()> {
+        let path = self.path(key);
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // written to a temporary file first, so readers never see a partial object.
+        let tmp = path.with_extension("partial");
+        let mut file = std::fs::File::create(&tmp)?;
+        std::io::copy(data, &mut file)?;
+        file.sync_all()?;
+        tokio::fs::rename(tmp, path).await?;
+
+        Ok(())
+    }
+
+    async fn get_file(&self, key: &str, path: &Path) -> Result<()> {
+        tokio::fs::copy(self.path(key), path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        Ok(tokio::fs::read(self.path(key)).await?)
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        if !self.folder.exists() {
+            return Ok(Vec::new());
+        }
+
+        Ok(files(&self.folder)?
+            .into_iter()
+            .filter(|key| key.starts_with(prefix))
+            .collect())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        tokio::fs::remove_file(self.path(key)).await?;
+        Ok(())
+    }
+}
+
+pub enum ObjectStore {
+    S3(S3Store),
+    Local(LocalStore),
+}
+
+impl ObjectStore {
+    pub fn open(config: &ObjectStoreConfig) -> Result<Self> {
+        Ok(match config {
+            ObjectStoreConfig::S3(config) => ObjectStore::S3(S3Store::open(config)?),
+            ObjectStoreConfig::Local(config) => ObjectStore::Local(LocalStore::open(config)),
+        })
+    }
+
+    /// Upload the bytes to the key. Large objects are uploaded in parts.
+    pub async fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        match self {
+            ObjectStore::S3(store) => store.put(key, &mut &data[..], data.len() as u64).await,
+            ObjectStore::Local(store) => store.put(key, &mut &data[..]).await,
+        }
+    }
+
+    /// Upload the file to the key without reading it into memory.
+    pub async fn put_file(&self, key: &str, path: &Path) -> Result<()> {
+        let size = std::fs::metadata(path)?.len();
+        let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+
+        match self {
+            ObjectStore::S3(store) => store.put(key, &mut file, size).await,
+            ObjectStore::Local(store) => store.put(key, &mut file).await,
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        match self {
+            ObjectStore::S3(store) => store.get(key).await,
+            ObjectStore::Local(store) => store.get(key).await,
+        }
+    }
+
+    /// Download the object to the file without reading it into memory.
+    pub async fn get_file(&self, key: &str, path: &Path) -> Result<()> {
+        match self {
+            ObjectStore::S3(store) => store.get_file(key, path).await,
+            ObjectStore::Local(store) => store.get_file(key, path).await,
+        }
+    }
+
+    /// The keys of all objects that starts with the prefix.
+    pub async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        match self {
+            ObjectStore::S3(store) => store.list(prefix).await,
+            ObjectStore::Local(store) => store.list(prefix).await,
+        }
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        match self {
+            ObjectStore::S3(store) => store.delete(key).await,
+            ObjectStore::Local(store) => store.delete(key).await,
+        }
+    }
+}
+
+/// The paths of all files in the folder relative to the folder, using `/` as separator.
+fn files(folder: &Path) -> Result<Vec<String>> {
+    fn walk(folder: &Path, dir: &Path, files: &mut Vec<String>) -> Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+
+            if path.is_dir() {
+                walk(folder, &path, files)?;
+            } else {
+                let relative = path
+                    .strip_prefix(folder)?
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().to_string())
+                    .collect::<Vec<_>>()
+                    .join("/");
+
+                files.push(relative);
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut res = Vec::new();
+    walk(folder, folder, &mut res)?;
+    res.sort();
+
+    Ok(res)
+}
+
+fn sha256(path: &Path) -> Result<String> {
+    let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+    let mut buf = vec![0; 1024 * 1024];
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+
+        context.update(&buf[..n]);
+    }
+
+    Ok(context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path of the file relative to the snapshot.
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    fn create(folder: &Path) -> Result<Self> {
+        let files = files(folder)?
+            .into_iter()
+            .filter(|path| path != LOCAL_STATE_NAME)
+            .map(|path| {
+                let file = folder.join(&path);
+
+                Ok(ManifestEntry {
+                    size: std::fs::metadata(&file)?.len(),
+                    sha256: sha256(&file)?,
+                    path,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { files })
+    }
+
+    /// Split the files by whether their content is already in the previous manifest.
+    /// Segment files are immutable, so a new generation usually shares most of its
+    /// files with the previous one.
+    pub fn delta(&self, previous: &Manifest) -> Delta {
+        let previous: HashMap<_, _> = previous
+            .files
+            .iter()
+            .map(|entry| ((&entry.sha256, entry.size), &entry.path))
+            .collect();
+
+        let mut delta = Delta::default();
+
+        for entry in &self.files {
+            match previous.get(&(&entry.sha256, entry.size)) {
+                Some(path) => delta.unchanged.push((entry.clone(), path.to_string())),
+                None => delta.changed.push(entry.clone()),
+            }
+        }
+
+        delta
+    }
+}
+
+/// The files of a generation compared to a previous generation.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Delta {
+    /// Files with the same content as a file in the previous generation, and the path of that file.
+    pub unchanged: Vec<(ManifestEntry, String)>,
+    /// Files that are new or changed since the previous generation.
+    pub changed: Vec<ManifestEntry>,
+}
+
+impl Delta {
+    pub fn changed_bytes(&self) -> u64 {
+        self.changed.iter().map(|entry| entry.size).sum()
+    }
+}
+
+/// The generation a folder was restored from. Stored in the folder, so the next
+/// restore into the folder only has to download the files that changed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LocalState {
+    generation: String,
+    manifest: Manifest,
+}
+
+impl LocalState {
+    fn path(folder: &Path) -> PathBuf {
+        folder.join(LOCAL_STATE_NAME)
+    }
+
+    fn open(folder: &Path) -> Result<Option<Self>> {
+        let path = Self::path(folder);
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        Ok(Some(serde_json::from_slice(&std::fs::read(path)?)?))
+    }
+
+    fn save(&self, folder: &Path) -> Result<()> {
+        std::fs::write(Self::path(folder), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+}
+
+/// Only plain relative paths are restored, so a manifest can't write outside the target folder.
+fn is_safe_path(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Local files that has been modified since the folder was restored can't be reused.
+fn is_unmodified(path: &Path, size: u64, restored_at: SystemTime) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| Ok(metadata.len() == size && metadata.modified()? <= restored_at))
+        .unwrap_or(false)
+}
+
+fn manifest_key(name: &str, generation: &str) -> String {
+    format!("{name}/{generation}/{MANIFEST_NAME}")
+}
+
+fn blob_key(name: &str, sha256: &str) -> String {
+    format!("{name}/{BLOBS_NAME}/{sha256}")
+}
+
+/// Generations are named by their creation time, so they sort chronologically.
+fn new_generation() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+
+    format!("{now:020}")
+}
+
+pub struct Snapshots {
+    store: ObjectStore,
+    name: String,
+    keep_generations: usize,
+}
+
+impl Snapshots {
+    pub fn open(config: &SnapshotConfig) -> Result<Self> {
+        Ok(Self {
+            store: ObjectStore::open(&config.store)?,
+            name: config.name.clone(),
+            keep_generations: config.keep_generations,
+        })
+    }
+
+    /// The generations with a manifest from oldest to newest.
+    pub async fn generations(&self) -> Result<Vec<String>> {
+        let mut generations: Vec<_> = self
+            .store
+            .list(&format!("{}/", self.name))
+            .await?
+            .into_iter()
+            .filter_map(|key| {
+                let (generation, file) = key
+                    .strip_prefix(&format!("{}/", self.name))?
+                    .split_once('/')?;
+
+                (file == MANIFEST_NAME).then(|| generation.to_string())
+            })
+            .collect();
+
+        generations.sort();
+
+        Ok(generations)
+    }
+
+    async fn manifest(&self, generation: &str) -> Result<Manifest> {
+        Ok(serde_json::from_slice(
+            &self
+                .store
+                .get(&manifest_key(&self.name, generation))
+                .await?,
+        )?)
+    }
+
+    /// The content hashes of all uploaded files.
+    async fn blobs(&self) -> Result<HashSet<String>> {
+        let prefix = format!("{}/{}/", self.name, BLOBS_NAME);
+
+        Ok(self
+            .store
+            .list(&prefix)
+            .await?
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&prefix).map(|sha256| sha256.to_string()))
+            .collect())
+    }
+
+    /// Upload the folder as a new generation and remove the generations that are
+    /// no longer kept. Only files with content that isn't already in the store are
+    /// uploaded. Returns the new generation.
+    pub async fn upload(&self, folder: &Path) -> Result<String> {
+        let generation = new_generation();
+        let manifest = Manifest::create(folder)?;
+        let mut blobs = self.blobs().await?;
+        let mut uploaded_bytes = 0;
+
+        for entry in &manifest.files {
+            if blobs.contains(&entry.sha256) {
+                continue;
+            }
+
+            tracing::debug!("uploading {}", entry.path);
+            self.store
+                .put_file(
+                    &blob_key(&self.name, &entry.sha256),
+                    &folder.join(&entry.path),
+                )
+                .await?;
+
+            uploaded_bytes += entry.size;
+            blobs.insert(entry.sha256.clone());
+        }
+
+        self.store
+            .put(
+                &manifest_key(&self.name, &generation),
+                &serde_json::to_vec(&manifest)?,
+            )
+            .await?;
+
+        tracing::info!(
+            "uploaded generation {} of {} ({} of {} bytes were new)",
+            generation,
+            self.name,
+            uploaded_bytes,
+            manifest.files.iter().map(|entry| entry.size).sum::<u64>()
+        );
+
+        self.prune().await?;
+
+        Ok(generation)
+    }
+
+    /// Remove the generations that are no longer kept, and the files that are
+    /// only part of the removed generations.
+    async fn prune(&self) -> Result<()> {
+        let generations = self.generations().await?;
+        let num_remove = generations
+            .len()
+            .saturating_sub(self.keep_generations.max(1));
+
+        for generation in &generations[..num_remove] {
+            self.store
+                .delete(&manifest_key(&self.name, generation))
+                .await?;
+        }
+
+        let mut referenced = HashSet::new();
+        for generation in &generations[num_remove..] {
+            referenced.extend(
+                self.manifest(generation)
+                    .await?
+                    .files
+                    .into_iter()
+                    .map(|entry| entry.sha256),
+            );
+        }
+
+        for sha256 in self.blobs().await? {
+            if !referenced.contains(&sha256) {
+                self.store.delete(&blob_key(&self.name, &sha256)).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Restore a generation, or the latest if not specified, into the folder. If the folder was
+    /// restored from an earlier generation, only the files that changed since are downloaded.
+    /// The new folder is assembled and verified next to the folder before it is moved into place.
+    pub async fn restore(&self, generation: Option<&str>, folder: &Path) -> Result<String> {
+        let local = if folder.exists() {
+            Some(LocalState::open(folder)?.ok_or_else(|| {
+                anyhow!(
+                    "{} already exists and was not restored from a snapshot",
+                    folder.display()
+                )
+            })?)
+        } else {
+            None
+        };
+
+        let generation = match generation {
+            Some(generation) => generation.to_string(),
+            None => self
+                .generations()
+                .await?
+                .pop()
+                .ok_or_else(|| anyhow!("{} has no snapshots", self.name))?,
+        };
+
+        if local.as_ref().map(|local| &local.generation) == Some(&generation) {
+            return Ok(generation);
+        }
+
+        let manifest = self.manifest(&generation).await?;
+
+        if let Some(entry) = manifest.files.iter().find(|e| !is_safe_path(&e.path)) {
+            bail!("invalid path in manifest: {}", entry.path);
+        }
+
+        let previous = local
+            .as_ref()
+            .map(|local| local.manifest.clone())
+            .unwrap_or_default();
+        let restored_at = std::fs::metadata(LocalState::path(folder))
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(UNIX_EPOCH);
+
+        let tmp = folder.with_extension("restoring");
+        if tmp.exists() {
+            std::fs::remove_dir_all(&tmp)?;
+        }
+        std::fs::create_dir_all(&tmp)?;
+
+        let delta = manifest.delta(&previous);
+        let mut changed = delta.changed;
+
+        for (entry, previous_path) in delta.unchanged {
+            let source = folder.join(&previous_path);
+
+            if !is_unmodified(&source, entry.size, restored_at) {
+                changed.push(entry);
+                continue;
+            }
+
+            let path = tmp.join(&entry.path);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            // hard links avoids copying the unchanged segments.
+            if std::fs::hard_link(&source, &path).is_err() {
+                std::fs::copy(&source, &path)?;
+            }
+        }
+
+        let mut downloaded_bytes = 0;
+
+        for entry in &changed {
+            let path = tmp.join(&entry.path);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            self.store
+                .get_file(&blob_key(&self.name, &entry.sha256), &path)
+                .await?;
+
+            if std::fs::metadata(&path)?.len() != entry.size || sha256(&path)? != entry.sha256 {
+                std::fs::remove_dir_all(&tmp)?;
+                bail!("{} is corrupted in generation {}", entry.path, generation);
+            }
+
+            downloaded_bytes += entry.size;
+        }
+
+        LocalState {
+            generation: generation.clone(),
+            manifest,
+        }
+        .save(&tmp)?;
+
+        if folder.exists() {
+            let old = folder.with_extension("previous");
+            if old.exists() {
+                std::fs::remove_dir_all(&old)?;
+            }
+
+            std::fs::rename(folder, &old)?;
+            std::fs::rename(&tmp, folder)?;
+            std::fs::remove_dir_all(&old)?;
+        } else {
+            std::fs::rename(&tmp, folder)?;
+        }
+
+        tracing::info!(
+            "restored generation {} of {} into {} ({} files downloaded, {} bytes)",
+            generation,
+            self.name,
+            folder.display(),
+            changed.len(),
+            downloaded_bytes
+        );
+
+        Ok(generation)
+    }
+
+    /// Restore the latest generation if the folder doesn't exist. Used when nodes start,
+    /// so they can be provisioned without any local state.
+    pub async fn restore_if_missing(&self, folder: &Path) -> Result<()> {
+        if !folder.exists() {
+            self.restore(None, folder).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshots(keep_generations: usize) -> Snapshots {
+        Snapshots::open(&SnapshotConfig {
+            name: "index".to_string(),
+            store: ObjectStoreConfig::Local(LocalObjectStoreConfig {
+                folder: crate::gen_temp_path().to_str().unwrap().to_string(),
+            }),
+            keep_generations,
+        })
+        .unwrap()
+    }
+
+    fn local_store(snapshots: &Snapshots) -> &LocalStore {
+        match &snapshots.store {
+            ObjectStore::Local(store) => store,
+            ObjectStore::S3(_) => unreachable!(),
+        }
+    }
+
+    fn folder() -> PathBuf {
+        let folder = crate::gen_temp_path();
+        std::fs::create_dir_all(folder.join("segments")).unwrap();
+        std::fs::write(folder.join("meta.json"), b"{}").unwrap();
+        std::fs::write(folder.join("segments").join("a.idx"), b"segment data").unwrap();
+
+        folder
+    }
+
+    fn entry(path: &str, content: &str) -> ManifestEntry {
+        ManifestEntry {
+            path: path.to_string(),
+            size: content.len() as u64,
+            sha256: content.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn upload_and_restore() {
+        let snapshots = snapshots(2);
+        let folder = folder();
+
+        let generation = snapshots.upload(&folder).await.unwrap();
+        assert_eq!(snapshots.generations().await.unwrap(), vec![generation]);
+
+        let restored = crate::gen_temp_path();
+        snapshots.restore_if_missing(&restored).await.unwrap();
+
+        assert_eq!(
+            files(&restored).unwrap(),
+            vec![LOCAL_STATE_NAME, "meta.json", "segments/a.idx"]
+        );
+        assert_eq!(
+            std::fs::read(restored.join("segments").join("a.idx")).unwrap(),
+            b"segment data"
+        );
+
+        assert!(snapshots.restore(None, &folder).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn corrupted_file() {
+        let snapshots = snapshots(2);
+        let folder = folder();
+
+        snapshots.upload(&folder).await.unwrap();
+        let manifest = Manifest::create(&folder).unwrap();
+        let segment = manifest
+            .files
+            .iter()
+            .find(|entry| entry.path == "segments/a.idx")
+            .unwrap();
+
+        std::fs::write(
+            local_store(&snapshots).path(&blob_key("index", &segment.sha256)),
+            b"segment dat4",
+        )
+        .unwrap();
+
+        let restored = crate::gen_temp_path();
+        assert!(snapshots.restore(None, &restored).await.is_err());
+        assert!(!restored.exists());
+    }
+
+    #[tokio::test]
+    async fn prune_old_generations() {
+        let snapshots = snapshots(2);
+        let folder = folder();
+
+        let mut generations = Vec::new();
+        for i in 0..3 {
+            std::fs::write(folder.join("meta.json"), format!("{{\"opstamp\": {i}}}")).unwrap();
+            generations.push(snapshots.upload(&folder).await.unwrap());
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+
+        assert_eq!(snapshots.generations().await.unwrap(), generations[1..]);
+
+        // the segment is shared by all generations and the meta of the first is removed.
+        assert_eq!(snapshots.blobs().await.unwrap().len(), 3);
+    }
+
+    #[test]
+    fn delta() {
+        let previous = Manifest {
+            files: vec![entry("meta.json", "a"), entry("segments/1.idx", "bbbb")],
+        };
+        let next = Manifest {
+            files: vec![
+                entry("meta.json", "c"),
+                entry("segments/1.idx", "bbbb"),
+                entry("segments/2.idx", "dddddd"),
+            ],
+        };
+
+        let delta = next.delta(&previous);
+
+        assert_eq!(
+            delta.unchanged,
+            vec![(
+                entry("segments/1.idx", "bbbb"),
+                "segments/1.idx".to_string()
+            )]
+        );
+        assert_eq!(
+            delta.changed,
+            vec![entry("meta.json", "c"), entry("segments/2.idx", "dddddd")]
+        );
+        assert_eq!(delta.changed_bytes(), 7);
+    }
+
+    #[tokio::test]
+    async fn restore_only_downloads_changes() {
+        let snapshots = snapshots(2);
+        let folder = folder();
+
+        let first = snapshots.upload(&folder).await.unwrap();
+        let restored = crate::gen_temp_path();
+        snapshots.restore(None, &restored).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        std::fs::write(folder.join("segments").join("b.idx"), b"new segment").unwrap();
+        let second = snapshots.upload(&folder).await.unwrap();
+
+        // the unchanged segment is reused from the restored folder, so a
+        // broken copy in the store is never downloaded.
+        let segment = Manifest::create(&folder)
+            .unwrap()
+            .files
+            .into_iter()
+            .find(|entry| entry.path == "segments/a.idx")
+            .unwrap();
+        std::fs::write(
+            local_store(&snapshots).path(&blob_key("index", &segment.sha256)),
+            b"broken",
+        )
+        .unwrap();
+
+        assert_eq!(snapshots.restore(None, &restored).await.unwrap(), second);
+        assert_eq!(
+            std::fs::read(restored.join("segments").join("b.idx")).unwrap(),
+            b"new segment"
+        );
+        assert_eq!(
+            std::fs::read(restored.join("segments").join("a.idx")).unwrap(),
+            b"segment data"
+        );
+
+        // going back downloads the changed files again.
+        assert_eq!(
+            snapshots.restore(Some(&first), &restored).await.unwrap(),
+            first
+        );
+        assert!(!restored.join("segments").join("b.idx").exists());
+    }
+
+    #[test]
+    fn unsafe_paths() {
+        assert!(is_safe_path("segments/a.idx"));
+        assert!(!is_safe_path("../etc/passwd"));
+        assert!(!is_safe_path("/etc/passwd"));
+        assert!(!is_safe_path(""));
+    }
+}
@@ -0,0 +1,218 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Price history of the products found in the index.
+//!
+//! Every time an index is built, the products in it are recorded in the [`ProductStore`].
+//! A new observation is only added to the history of a url when the price or availability
+//! has changed since the last observation, so the history stays small for stable products.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    webpage::{Availability, Product},
+};
+
+/// The oldest observations are dropped when the history grows beyond this.
+const MAX_OBSERVATIONS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceObservation {
+    /// Unix timestamp in seconds.
+    pub timestamp: u64,
+    pub price: f64,
+    pub currency: Option<String>,
+    pub availability: Option<Availability>,
+}
+
+impl PriceObservation {
+    fn is_same(&self, product: &Product) -> bool {
+        self.price == product.price
+            && self.currency == product.currency
+            && self.availability == product.availability
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PriceHistory {
+    pub name: String,
+    pub observations: Vec<PriceObservation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceDrop {
+    pub previous_price: f64,
+    pub price: f64,
+    pub currency: Option<String>,
+    /// Unix timestamp in seconds of when the lower price was first seen.
+    pub since: u64,
+}
+
+impl PriceHistory {
+    /// Add the product to the history if it has changed since the last observation.
+    /// Returns whether an observation was added.
+    pub fn record(&mut self, product: &Product, timestamp: u64) -> bool {
+        self.name.clone_from(&product.name);
+
+        if let Some(last) = self.observations.last() {
+            if last.is_same(product) || last.timestamp > timestamp {
+                return false;
+            }
+        }
+
+        self.observations.push(PriceObservation {
+            timestamp,
+            price: product.price,
+            currency: product.currency.clone(),
+            availability: product.availability,
+        });
+
+        if self.observations.len() > MAX_OBSERVATIONS {
+            let excess = self.observations.len() - MAX_OBSERVATIONS;
+            self.observations.drain(..excess);
+        }
+
+        true
+    }
+
+    pub fn latest(&self) -> Option<&PriceObservation> {
+        self.observations.last()
+    }
+
+    /// The latest observation if it lowered the price compared to the one before it.
+    pub fn price_drop(&self) -> Option<PriceDrop> {
+        let [.., previous, latest] = self.observations.as_slice() else {
+            return None;
+        };
+
+        if previous.currency != latest.currency || latest.price >= previous.price {
+            return None;
+        }
+
+        Some(PriceDrop {
+            previous_price: previous.price,
+            price: latest.price,
+            currency: latest.currency.clone(),
+            since: latest.timestamp,
+        })
+    }
+}
+
+pub struct ProductStore {
+    store: RocksDbStore<String, PriceHistory>,
+}
+
+impl ProductStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            store: RocksDbStore::open(path),
+        }
+    }
+
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            store: RocksDbStore::open_read_only(path),
+        }
+    }
+
+    pub fn get(&self, url: &str) -> Option<PriceHistory> {
+        self.store.get(&url.to_string())
+    }
+
+    /// Record the product found on the url. Returns whether the price history changed.
+    pub fn record(&self, url: &str, product: &Product, timestamp: u64) -> bool {
+        let mut history = self.get(url).unwrap_or_default();
+
+        if history.record(product, timestamp) {
+            self.store.insert(url.to_string(), history);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn price_drop(&self, url: &str) -> Option<PriceDrop> {
+        self.get(url).and_then(|history| history.price_drop())
+    }
+
+    pub fn flush(&self) {
+        self.store.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn product(price: f64) -> Product {
+        Product {
+            name: "Espresso machine".to_string(),
+            price,
+            currency: Some("EUR".to_string()),
+            availability: Some(Availability::InStock),
+        }
+    }
+
+    #[test]
+    fn history_only_records_changes() {
+        let mut history = PriceHistory::default();
+
+        assert!(history.record(&product(100.0), 1));
+        assert!(!history.record(&product(100.0), 2));
+        assert!(history.record(&product(120.0), 3));
+        assert!(!history.record(&product(90.0), 2));
+
+        assert_eq!(history.observations.len(), 2);
+        assert_eq!(history.price_drop(), None);
+
+        assert!(history.record(&product(80.0), 4));
+        assert_eq!(
+            history.price_drop(),
+            Some(PriceDrop {
+                previous_price: 120.0,
+                price: 80.0,
+                currency: Some("EUR".to_string()),
+                since: 4,
+            })
+        );
+
+        for timestamp in 5..100 {
+            history.record(&product(timestamp as f64), timestamp);
+        }
+
+        assert_eq!(history.observations.len(), MAX_OBSERVATIONS);
+        assert_eq!(history.latest().unwrap().timestamp, 99);
+    }
+
+    #[test]
+    fn store() {
+        let store = ProductStore::open(crate::gen_temp_path());
+        let url = "https://shop.example.com/espresso";
+
+        assert!(store.record(url, &product(100.0), 1));
+        assert!(store.record(url, &product(75.0), 2));
+        assert!(!store.record(url, &product(75.0), 3));
+
+        assert_eq!(store.get(url).unwrap().observations.len(), 2);
+        assert_eq!(store.price_drop(url).unwrap().previous_price, 100.0);
+        assert_eq!(store.price_drop("https://example.com/"), None);
+    }
+}
@@ -0,0 +1,449 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Shard-local cache of filter bitsets. Filters like `site:` or the safe-search
+//! mask match the same documents for every query they appear in, so we only
+//! compute the matching documents of a segment once and afterwards iterate
+//! the cached bitset instead of the postings.
+//!
+//! Segments are immutable, so the segment id identifies the generation of the
+//! postings and the cache never has to be invalidated. Entries for segments
+//! that have been merged away are evicted when the cache runs out of space.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use tantivy::{
+    query::{EnableScoring, Explanation, Query, Scorer, Weight},
+    DocId, DocSet, Score, SegmentId, SegmentReader, TERMINATED,
+};
+
+use crate::memory_budget::{self, MemoryConsumer};
+
+/// Default amount of memory the cache can use per shard.
+pub const DEFAULT_FILTER_CACHE_BYTES: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    filter: String,
+    segment: SegmentId,
+}
+
+/// The documents in a segment that match a filter.
+#[derive(Debug)]
+pub struct FilterBitSet {
+    words: Vec<u64>,
+    max_doc: DocId,
+    len: u32,
+}
+
+impl FilterBitSet {
+    fn with_max_doc(max_doc: DocId) -> Self {
+        Self {
+            words: vec![0; (max_doc as usize).div_ceil(64)],
+            max_doc,
+            len: 0,
+        }
+    }
+
+    fn insert(&mut self, doc: DocId) {
+        let word = &mut self.words[doc as usize / 64];
+        let mask = 1 << (doc % 64);
+
+        if *word & mask == 0 {
+            *word |= mask;
+            self.len += 1;
+        }
+    }
+
+    pub fn contains(&self, doc: DocId) -> bool {
+        doc < self.max_doc && self.words[doc as usize / 64] & (1 << (doc % 64)) != 0
+    }
+
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn size_bytes(&self) -> usize {
+        self.words.len() * std::mem::size_of::<u64>()
+    }
+
+    /// The first document that is at least `target`, or `TERMINATED`.
+    fn next_from(&self, target: DocId) -> DocId {
+        if target >= self.max_doc {
+            return TERMINATED;
+        }
+
+        let mut idx = target as usize / 64;
+        let mut word = self.words[idx] & (u64::MAX << (target % 64));
+
+        loop {
+            if word != 0 {
+                return (idx * 64) as DocId + word.trailing_zeros();
+            }
+
+            idx += 1;
+            if idx >= self.words.len() {
+                return TERMINATED;
+            }
+
+            word = self.words[idx];
+        }
+    }
+}
+
+struct Inner {
+    data: HashMap<CacheKey, Arc<FilterBitSet>>,
+    insertion_order: VecDeque<CacheKey>,
+    size_bytes: usize,
+}
+
+pub struct FilterCache {
+    max_bytes: usize,
+    inner: Mutex<Inner>,
+}
+
+impl Default for FilterCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_FILTER_CACHE_BYTES)
+    }
+}
+
+impl FilterCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            inner: Mutex::new(Inner {
+                data: HashMap::new(),
+                insertion_order: VecDeque::new(),
+                size_bytes: 0,
+            }),
+        }
+    }
+
+    fn get(&self, key: &CacheKey) -> Option<Arc<FilterBitSet>> {
+        self.inner.lock().unwrap().data.get(key).cloned()
+    }
+
+    fn insert(&self, key: CacheKey, bitset: Arc<FilterBitSet>) {
+        let size = bitset.size_bytes();
+
+        if size > self.max_bytes {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.data.contains_key(&key) {
+            return;
+        }
+
+        while inner.size_bytes + size > self.max_bytes {
+            let Some(front) = inner.insertion_order.pop_front() else {
+                break;
+            };
+
+            if let Some(evicted) = inner.data.remove(&front) {
+                inner.size_bytes -= evicted.size_bytes();
+            }
+        }
+
+        inner.size_bytes += size;
+        inner.insertion_order.push_back(key.clone());
+        inner.data.insert(key, bitset);
+        drop(inner);
+
+        memory_budget::global().enforce();
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.inner.lock().unwrap().size_bytes
+    }
+}
+
+impl MemoryConsumer for FilterCache {
+    fn name(&self) -> &str {
+        "filter_cache"
+    }
+
+    fn size_bytes(&self) -> usize {
+        FilterCache::size_bytes(self)
+    }
+
+    fn evict(&self, bytes: usize) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let mut freed = 0;
+
+        while freed < bytes {
+            let Some(front) = inner.insertion_order.pop_front() else {
+                break;
+            };
+
+            if let Some(evicted) = inner.data.remove(&front) {
+                inner.size_bytes -= evicted.size_bytes();
+                freed += evicted.size_bytes();
+            }
+        }
+
+        freed
+    }
+}
+
+/// Wraps a filter query so its matching documents are cached per segment.
+/// The `filter` string must uniquely identify the documents matched by the
+/// query, e.g. `site:example.com`. Matching documents are scored with the boost.
+pub struct CachedFilterQuery {
+    filter: String,
+    query: Box<dyn Query>,
+    cache: Arc<FilterCache>,
+}
+
+impl CachedFilterQuery {
+    pub fn new(filter: String, query: Box<dyn Query>, cache: Arc<FilterCache>) -> Self {
+        Self {
+            filter,
+            query,
+            cache,
+        }
+    }
+}
+
+impl Clone for CachedFilterQuery {
+    fn clone(&self) -> Self {
+        Self {
+            filter: self.filter.clone(),
+            query: self.query.box_clone(),
+            cache: Arc::clone(&self.cache),
+        }
+    }
+}
+
+impl std::fmt::Debug for CachedFilterQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CachedFilterQuery")
+            .field("filter", &self.filter)
+            .field("query", &self.query)
+            .finish()
+    }
+}
+
+impl Query for CachedFilterQuery {
+    fn weight(&self, enable_scoring: EnableScoring) -> tantivy::Result<Box<dyn Weight>> {
+        let schema = match enable_scoring {
+            EnableScoring::Enabled {
+                searcher,
+                statistics_provider: _,
+            } => searcher.schema(),
+            EnableScoring::Disabled {
+                schema,
+                searcher_opt: _,
+            } => schema,
+        };
+
+        Ok(Box::new(CachedFilterWeight {
+            filter: self.filter.clone(),
+            weight: self.query.weight(EnableScoring::Disabled {
+                schema,
+                searcher_opt: None,
+            })?,
+            cache: Arc::clone(&self.cache),
+        }))
+    }
+
+    fn query_terms<'a>(&'a self, visitor: &mut dyn FnMut(&'a tantivy::Term, bool)) {
+        self.query.query_terms(visitor)
+    }
+}
+
+struct CachedFilterWeight {
+    filter: String,
+    weight: Box<dyn Weight>,
+    cache: Arc<FilterCache>,
+}
+
+impl CachedFilterWeight {
+    fn bitset(&self, reader: &SegmentReader) -> tantivy::Result<Arc<FilterBitSet>> {
+        let key = CacheKey {
+            filter: self.filter.clone(),
+            segment: reader.segment_id(),
+        };
+
+        if let Some(bitset) = self.cache.get(&key) {
+            return Ok(bitset);
+        }
+
+        let mut bitset = FilterBitSet::with_max_doc(reader.max_doc());
+        let mut scorer = self.weight.scorer(reader, 1.0)?;
+
+        while scorer.doc() != TERMINATED {
+            bitset.insert(scorer.doc());
+            scorer.advance();
+        }
+
+        let bitset = Arc::new(bitset);
+        self.cache.insert(key, Arc::clone(&bitset));
+
+        Ok(bitset)
+    }
+}
+
+impl Weight for CachedFilterWeight {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> tantivy::Result<Box<dyn Scorer>> {
+        Ok(Box::new(BitSetScorer::new(self.bitset(reader)?, boost)))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> tantivy::Result<Explanation> {
+        if !self.bitset(reader)?.contains(doc) {
+            return Err(tantivy::TantivyError::InvalidArgument(format!(
+                "Document #({doc}) does not match filter {}",
+                self.filter
+            )));
+        }
+
+        Ok(Explanation::new(
+            format!("Cached filter {}", self.filter),
+            1.0,
+        ))
+    }
+}
+
+struct BitSetScorer {
+    bitset: Arc<FilterBitSet>,
+    doc: DocId,
+    score: Score,
+}
+
+impl BitSetScorer {
+    fn new(bitset: Arc<FilterBitSet>, score: Score) -> Self {
+        let doc = bitset.next_from(0);
+        Self { bitset, doc, score }
+    }
+}
+
+impl Scorer for BitSetScorer {
+    fn score(&mut self) -> Score {
+        self.score
+    }
+}
+
+impl DocSet for BitSetScorer {
+    fn advance(&mut self) -> DocId {
+        if self.doc != TERMINATED {
+            self.doc = self.bitset.next_from(self.doc + 1);
+        }
+
+        self.doc
+    }
+
+    fn seek(&mut self, target: DocId) -> DocId {
+        if self.doc != TERMINATED && self.doc < target {
+            self.doc = self.bitset.next_from(target);
+        }
+
+        self.doc
+    }
+
+    fn doc(&self) -> DocId {
+        self.doc
+    }
+
+    fn size_hint(&self) -> u32 {
+        self.bitset.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitset_iteration() {
+        let mut bitset = FilterBitSet::with_max_doc(200);
+
+        for doc in [0, 3, 63, 64, 130, 199] {
+            bitset.insert(doc);
+        }
+
+        assert_eq!(bitset.len(), 6);
+        assert!(bitset.contains(63));
+        assert!(!bitset.contains(62));
+        assert!(!bitset.contains(1000));
+
+        let mut scorer = BitSetScorer::new(Arc::new(bitset), 1.0);
+        let mut docs = Vec::new();
+
+        while scorer.doc() != TERMINATED {
+            docs.push(scorer.doc());
+            scorer.advance();
+        }
+
+        assert_eq!(docs, vec![0, 3, 63, 64, 130, 199]);
+    }
+
+    #[test]
+    fn seek() {
+        let mut bitset = FilterBitSet::with_max_doc(200);
+        bitset.insert(10);
+        bitset.insert(150);
+
+        let mut scorer = BitSetScorer::new(Arc::new(bitset), 1.0);
+        assert_eq!(scorer.seek(11), 150);
+        assert_eq!(scorer.seek(100), 150);
+        assert_eq!(scorer.advance(), TERMINATED);
+    }
+
+    #[test]
+    fn eviction() {
+        let bitset = || Arc::new(FilterBitSet::with_max_doc(64 * 10));
+        let size = bitset().size_bytes();
+        let cache = FilterCache::new(size * 2);
+
+        let key = |filter: &str| CacheKey {
+            filter: filter.to_string(),
+            segment: SegmentId::generate_random(),
+        };
+
+        let a = key("a");
+        let b = key("b");
+        let c = key("c");
+
+        cache.insert(a.clone(), bitset());
+        cache.insert(b.clone(), bitset());
+        assert_eq!(cache.len(), 2);
+
+        cache.insert(c.clone(), bitset());
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.size_bytes(), size * 2);
+
+        assert!(cache.get(&a).is_none());
+        assert!(cache.get(&b).is_some());
+        assert!(cache.get(&c).is_some());
+    }
+}
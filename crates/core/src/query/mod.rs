@@ -17,28 +17,32 @@
 use crate::{
     inverted_index::InvertedIndex,
     query::parser::TermCompound,
-    ranking::SignalCoefficient,
-    schema::{Field, TextField},
+    ranking::{bm25f::Bm25FParams, SignalCoefficient},
+    schema::{FastField, Field, TextField},
     search_ctx::Ctx,
-    searcher::SearchQuery,
-    webpage::{region::Region, safety_classifier},
+    searcher::{SearchQuery, Vertical},
+    webpage::{region::Region, safety_classifier, topic_classifier::Topic},
     Result,
 };
 use optics::{HostRankings, Optic};
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 use tantivy::query::{BooleanQuery, Occur, QueryClone, TermQuery};
 
 mod const_query;
+pub mod filter_cache;
 pub mod intersection;
 pub mod optic;
 pub mod parser;
 mod pattern_query;
 pub mod shortcircuit;
+pub mod structured;
 pub mod union;
 
 use parser::Term;
 
-use self::{optic::AsMultipleTantivyQuery, parser::CompoundAwareTerm};
+use self::{
+    filter_cache::CachedFilterQuery, optic::AsMultipleTantivyQuery, parser::CompoundAwareTerm,
+};
 
 const MAX_SIMILAR_TERMS: usize = 10;
 
@@ -54,11 +58,17 @@ pub struct Query {
     optics: Vec<Optic>,
     top_n: usize,
     count_results: bool,
+    signal_coefficients: Option<SignalCoefficient>,
+    bm25f: Option<Bm25FParams>,
+    max_docs_considered: Option<usize>,
 }
 
 impl Query {
     pub fn parse(ctx: &Ctx, query: &SearchQuery, index: &InvertedIndex) -> Result<Query> {
-        let parsed_terms = parser::parse(&query.query);
+        let parsed_terms = match &query.terms {
+            Some(terms) => terms.iter().cloned().map(Box::new).collect(),
+            None => parser::parse(&query.query),
+        };
         let mut term_count = HashMap::new();
         let mut terms = Vec::new();
 
@@ -108,7 +118,29 @@ impl Query {
 
         let mut queries: Vec<(Occur, Box<dyn tantivy::query::Query + 'static>)> = compound_terms
             .iter()
-            .map(|term| term.as_tantivy_query(&fields))
+            .map(|term| {
+                let (occur, tv_query) = term.as_tantivy_query(&fields);
+
+                match &term.term {
+                    Term::Site(site) => (
+                        occur,
+                        Box::new(CachedFilterQuery::new(
+                            format!("site:{site}"),
+                            tv_query,
+                            Arc::clone(&ctx.filter_cache),
+                        )) as Box<dyn tantivy::query::Query>,
+                    ),
+                    Term::Topic(topic) => (
+                        occur,
+                        Box::new(CachedFilterQuery::new(
+                            format!("topic:{topic}"),
+                            tv_query,
+                            Arc::clone(&ctx.filter_cache),
+                        )) as Box<dyn tantivy::query::Query>,
+                    ),
+                    _ => (occur, tv_query),
+                }
+            })
             .collect();
 
         if query.safe_search {
@@ -117,12 +149,37 @@ impl Query {
 
             queries.push((
                 Occur::MustNot,
-                Box::new(TermQuery::new(
-                    tantivy::Term::from_field_text(
-                        field,
-                        safety_classifier::Label::NSFW.to_string().as_str(),
-                    ),
-                    tantivy::schema::IndexRecordOption::Basic,
+                Box::new(CachedFilterQuery::new(
+                    "safe_search".to_string(),
+                    Box::new(TermQuery::new(
+                        tantivy::Term::from_field_text(
+                            field,
+                            safety_classifier::Label::NSFW.to_string().as_str(),
+                        ),
+                        tantivy::schema::IndexRecordOption::Basic,
+                    )),
+                    Arc::clone(&ctx.filter_cache),
+                )),
+            ));
+        }
+
+        if let Some(vertical) = query.vertical {
+            let field = match vertical {
+                Vertical::Videos => Field::Fast(FastField::IsVideo),
+                Vertical::Products => Field::Fast(FastField::IsProduct),
+                Vertical::Scholar => Field::Fast(FastField::IsScholarly),
+            };
+            let field = schema.get_field(field.name()).unwrap();
+
+            queries.push((
+                Occur::Must,
+                Box::new(CachedFilterQuery::new(
+                    format!("vertical:{}", vertical.name()),
+                    Box::new(TermQuery::new(
+                        tantivy::Term::from_field_u64(field, 1),
+                        tantivy::schema::IndexRecordOption::Basic,
+                    )),
+                    Arc::clone(&ctx.filter_cache),
                 )),
             ));
         }
@@ -169,6 +226,9 @@ impl Query {
             region: query.selected_region,
             top_n: query.num_results,
             count_results: query.count_results,
+            signal_coefficients: query.signal_coefficients.clone(),
+            bm25f: query.bm25f.clone(),
+            max_docs_considered: query.max_docs_considered,
         })
     }
 
@@ -200,28 +260,51 @@ impl Query {
         self.offset
     }
 
+    /// Number of documents to score before the search stops early, if the query overrides it.
+    pub fn max_docs_considered(&self) -> Option<usize> {
+        self.max_docs_considered
+    }
+
     pub fn region(&self) -> Option<&Region> {
         self.region.as_ref()
     }
 
+    /// The topic selected with the `topic:` operator, if any.
+    pub fn topic(&self) -> Option<Topic> {
+        self.terms.iter().find_map(|term| match term.as_ref() {
+            Term::Topic(topic) => Some(*topic),
+            _ => None,
+        })
+    }
+
+    pub fn bm25f(&self) -> Option<&Bm25FParams> {
+        self.bm25f.as_ref()
+    }
+
     pub fn host_rankings(&self) -> &HostRankings {
         &self.host_rankings
     }
 
+    /// The coefficients from the optics, falling back to the coefficients
+    /// of the ranking pipeline for signals the optics do not mention.
     pub fn signal_coefficients(&self) -> Option<SignalCoefficient> {
         if self.optics.is_empty() {
-            return None;
+            return self.signal_coefficients.clone();
         }
 
-        Some(
-            self.optics
-                .iter()
-                .fold(SignalCoefficient::default(), |mut acc, optic| {
-                    let coeffs = SignalCoefficient::from_optic(optic);
-                    acc.merge_into(coeffs);
-                    acc
-                }),
-        )
+        let coeffs = self
+            .optics
+            .iter()
+            .fold(SignalCoefficient::default(), |mut acc, optic| {
+                let coeffs = SignalCoefficient::from_optic(optic);
+                acc.merge_into(coeffs);
+                acc
+            });
+
+        Some(match &self.signal_coefficients {
+            Some(pipeline) => coeffs.with_defaults(pipeline),
+            None => coeffs,
+        })
     }
 }
 
@@ -243,8 +326,11 @@ mod tests {
     use crate::{
         index::Index,
         rand_words,
-        searcher::{LocalSearcher, SearchQuery},
-        webpage::Webpage,
+        searcher::{AuthorFacet, LocalSearcher, SearchQuery, TopicFacet},
+        webpage::{
+            topic_classifier::{Topic, TopicVector},
+            Webpage,
+        },
     };
 
     use super::*;
@@ -925,6 +1011,199 @@ mod tests {
         assert_eq!(result.webpages[0].url, "https://www.sfw.com/");
     }
 
+    #[test]
+    fn video_vertical() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for (url, embed) in [
+            (
+                "https://www.video.com",
+                r#"<iframe src="https://player.vimeo.com/video/123"></iframe>"#,
+            ),
+            ("https://www.text.com", ""),
+        ] {
+            index
+                .insert(
+                    Webpage::new(
+                        &format!(
+                            r#"
+                            <html>
+                                <head>
+                                    <title>Test website</title>
+                                </head>
+                                <body>
+                                    {embed}
+                                    This is a test website {}
+                                </body>
+                            </html>
+                        "#,
+                            rand_words(1000)
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                )
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+        let searcher = LocalSearcher::from(index);
+
+        let query = SearchQuery {
+            query: "test".to_string(),
+            ..Default::default()
+        };
+
+        let result = searcher.search(&query).expect("Search failed");
+        assert_eq!(result.webpages.len(), 2);
+
+        let query = SearchQuery {
+            query: "test".to_string(),
+            vertical: Some(Vertical::Videos),
+            ..Default::default()
+        };
+
+        let result = searcher.search(&query).expect("Search failed");
+        assert_eq!(result.webpages.len(), 1);
+        assert_eq!(result.webpages[0].url, "https://www.video.com/");
+        assert!(result.webpages[0].video.is_some());
+    }
+
+    #[test]
+    fn topic_filter() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for (url, topic) in [
+            ("https://www.science.com", Topic::Science),
+            ("https://www.sports.com", Topic::Sports),
+        ] {
+            let mut webpage = Webpage::new(
+                &format!(
+                    r#"
+                    <html>
+                        <head>
+                            <title>Test website</title>
+                        </head>
+                        <body>
+                            This is a test website {}
+                        </body>
+                    </html>
+                "#,
+                    rand_words(1000)
+                ),
+                url,
+            )
+            .unwrap();
+
+            let mut topics = TopicVector::default();
+            topics.set(topic, 1.0);
+            webpage.topics = Some(topics);
+
+            index.insert(webpage).expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+        let searcher = LocalSearcher::from(index);
+
+        let query = SearchQuery {
+            query: "test".to_string(),
+            ..Default::default()
+        };
+
+        let result = searcher.search(&query).expect("Search failed");
+        assert_eq!(result.webpages.len(), 2);
+        assert_eq!(result.topic_facets.len(), 2);
+
+        let query = SearchQuery {
+            query: "test topic:science".to_string(),
+            ..Default::default()
+        };
+
+        let result = searcher.search(&query).expect("Search failed");
+        assert_eq!(result.webpages.len(), 1);
+        assert_eq!(result.webpages[0].url, "https://www.science.com/");
+        assert_eq!(result.webpages[0].topics, vec![Topic::Science]);
+        assert_eq!(
+            result.topic_facets,
+            vec![TopicFacet {
+                topic: Topic::Science,
+                count: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn author_filter() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for (url, author) in [
+            ("https://www.a.com", "Jane Doe"),
+            ("https://www.b.com", "John Doe"),
+            ("https://www.c.com", "Jane Smith"),
+        ] {
+            index
+                .insert(
+                    Webpage::new(
+                        &format!(
+                            r#"
+                            <html>
+                                <head>
+                                    <title>Test website</title>
+                                    <meta name="author" content="{}">
+                                </head>
+                                <body>
+                                    This is a test website {}
+                                </body>
+                            </html>
+                        "#,
+                            author,
+                            rand_words(1000)
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                )
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+        let searcher = LocalSearcher::from(index);
+
+        let result = searcher
+            .search(&SearchQuery {
+                query: "test".to_string(),
+                ..Default::default()
+            })
+            .expect("Search failed");
+        assert_eq!(result.webpages.len(), 3);
+        assert_eq!(result.author_facets.len(), 3);
+
+        let result = searcher
+            .search(&SearchQuery {
+                query: "test author:doe".to_string(),
+                ..Default::default()
+            })
+            .expect("Search failed");
+        assert_eq!(result.webpages.len(), 2);
+
+        let result = searcher
+            .search(&SearchQuery {
+                query: "test author:\"Jane Doe\"".to_string(),
+                ..Default::default()
+            })
+            .expect("Search failed");
+        assert_eq!(result.webpages.len(), 1);
+        assert_eq!(result.webpages[0].url, "https://www.a.com/");
+        assert_eq!(result.webpages[0].authors, vec!["Jane Doe".to_string()]);
+        assert_eq!(
+            result.author_facets,
+            vec![AuthorFacet {
+                author: "Jane Doe".to_string(),
+                count: 1
+            }]
+        );
+    }
+
     #[test]
     fn suffix_domain_prefix_path_site_operator() {
         let mut index = Index::temporary().expect("Unable to open index");
@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use tantivy::{
     query::{BooleanQuery, Occur, PhraseQuery, TermQuery},
@@ -24,6 +25,7 @@ use crate::{
     bangs::BANG_PREFIXES,
     floor_char_boundary,
     schema::{Field, TextField},
+    webpage::topic_classifier::Topic,
 };
 
 #[derive(Debug, Clone)]
@@ -51,7 +53,7 @@ impl CompoundAwareTerm {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct SimpleTerm(String);
 impl SimpleTerm {
     pub fn as_str(&self) -> &str {
@@ -71,7 +73,7 @@ impl From<SimpleTerm> for String {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Term {
     Simple(SimpleTerm),
     Phrase(String),
@@ -80,6 +82,8 @@ pub enum Term {
     Title(String),
     Body(String),
     Url(String),
+    Topic(Topic),
+    Author(String),
     PossibleBang(String),
 }
 
@@ -93,6 +97,9 @@ impl Display for Term {
             Term::Title(title) => write!(f, "intitle:{}", title),
             Term::Body(body) => write!(f, "inbody:{}", body),
             Term::Url(url) => write!(f, "inurl:{}", url),
+            Term::Topic(topic) => write!(f, "topic:{}", topic),
+            Term::Author(author) if author.contains(' ') => write!(f, "author:\"{}\"", author),
+            Term::Author(author) => write!(f, "author:{}", author),
             Term::PossibleBang(bang) => write!(f, "{}{}", BANG_PREFIXES[0], bang),
         }
     }
@@ -231,6 +238,46 @@ impl Term {
 
                 (Occur::Must, Term::tantivy_text_query(field, url))
             }
+            Term::Topic(topic) => {
+                let field = fields
+                    .iter()
+                    .find(|field| {
+                        matches!(
+                            Field::get(field.field_id() as usize),
+                            Some(Field::Text(TextField::Topics))
+                        )
+                    })
+                    .unwrap();
+
+                (
+                    Occur::Must,
+                    Box::new(TermQuery::new(
+                        tantivy::Term::from_field_text(*field, topic.as_str()),
+                        tantivy::schema::IndexRecordOption::Basic,
+                    )),
+                )
+            }
+            Term::Author(author) => {
+                let field = fields
+                    .iter()
+                    .find(|field| {
+                        matches!(
+                            Field::get(field.field_id() as usize),
+                            Some(Field::Text(TextField::Author))
+                        )
+                    })
+                    .unwrap();
+
+                let terms = Term::process_tantivy_term(author, *field);
+
+                // the words of the name must be next to each other, so that
+                // "jane doe" doesn't match a page by jane smith and john doe
+                if terms.len() > 1 {
+                    (Occur::Must, Box::new(PhraseQuery::new(terms)))
+                } else {
+                    (Occur::Must, Term::tantivy_text_query(field, author))
+                }
+            }
             Term::PossibleBang(text) => {
                 let mut term = String::new();
 
@@ -379,6 +426,17 @@ fn parse_term(term: &str) -> Box<Term> {
         } else {
             Box::new(Term::Simple(term.to_string().into()))
         }
+    } else if let Some(author) = term.strip_prefix("author:") {
+        if !author.is_empty() {
+            Box::new(Term::Author(author.to_string()))
+        } else {
+            Box::new(Term::Simple(term.to_string().into()))
+        }
+    } else if let Some(topic) = term.strip_prefix("topic:") {
+        match Topic::try_from(topic) {
+            Ok(topic) => Box::new(Term::Topic(topic)),
+            Err(_) => Box::new(Term::Simple(term.to_string().into())),
+        }
     } else {
         for bang_prefix in BANG_PREFIXES {
             if let Some(bang) = term.strip_prefix(bang_prefix) {
@@ -405,6 +463,20 @@ pub fn parse(query: &str) -> Vec<Box<Term>> {
 
         cur_term_begin = floor_char_boundary(&query, cur_term_begin);
 
+        // names with spaces can be quoted, e.g. `author:"jane doe"`
+        if let Some(name) = query[cur_term_begin..].strip_prefix("author:\"") {
+            if let Some(end) = name.find('"') {
+                let author = name[..end].trim();
+
+                if !author.is_empty() {
+                    res.push(Box::new(Term::Author(author.to_string())));
+                }
+
+                cur_term_begin += "author:\"".len() + end + 1;
+                continue;
+            }
+        }
+
         if query[cur_term_begin..].starts_with('"') {
             if let Some(offset) = query[cur_term_begin + 1..].find('"') {
                 let offset = offset + cur_term_begin + 1;
@@ -515,6 +587,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn topic() {
+        assert_eq!(
+            parse("this topic:science"),
+            vec![
+                Box::new(Term::Simple("this".to_string().into())),
+                Box::new(Term::Topic(Topic::Science))
+            ]
+        );
+
+        assert_eq!(
+            parse("topic:unknown"),
+            vec![Box::new(Term::Simple("topic:unknown".to_string().into()))]
+        );
+    }
+
+    #[test]
+    fn author() {
+        assert_eq!(
+            parse("this author:doe"),
+            vec![
+                Box::new(Term::Simple("this".to_string().into())),
+                Box::new(Term::Author("doe".to_string()))
+            ]
+        );
+
+        assert_eq!(
+            parse("rust author:\"Jane Doe\" book"),
+            vec![
+                Box::new(Term::Simple("rust".to_string().into())),
+                Box::new(Term::Author("jane doe".to_string())),
+                Box::new(Term::Simple("book".to_string().into()))
+            ]
+        );
+
+        assert_eq!(
+            parse("author:"),
+            vec![Box::new(Term::Simple("author:".to_string().into()))]
+        );
+
+        assert_eq!(
+            Term::Author("jane doe".to_string()).to_string(),
+            "author:\"jane doe\""
+        );
+    }
+
     #[test]
     fn empty() {
         assert_eq!(parse(""), vec![]);
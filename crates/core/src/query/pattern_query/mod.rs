@@ -130,13 +130,13 @@ impl tantivy::query::Query for PatternQuery {
     ) -> tantivy::Result<Box<dyn tantivy::query::Weight>> {
         let bm25_weight = match scoring {
             tantivy::query::EnableScoring::Enabled {
-                searcher,
-                statistics_provider: _,
+                searcher: _,
+                statistics_provider,
             } => {
                 if self.raw_terms.is_empty() {
                     None
                 } else {
-                    Some(Bm25Weight::for_terms(searcher, &self.raw_terms)?)
+                    Some(Bm25Weight::for_terms(statistics_provider, &self.raw_terms)?)
                 }
             }
             tantivy::query::EnableScoring::Disabled { .. } => None,
@@ -0,0 +1,292 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Query trees built by programmatic clients. The terms of a structured query are used
+//! as they are instead of being parsed from text, so words like `site:` or `-` don't
+//! have to be escaped.
+
+use optics::{Action, MatchLocation, Matching, PatternPart, Rule};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::webpage::topic_classifier::Topic;
+
+use super::parser::{self, Term};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StructuredQuery {
+    pub terms: Vec<StructuredTerm>,
+    /// Boost or downrank the results from these sites.
+    #[serde(default)]
+    pub boosts: Vec<SiteBoost>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum StructuredTerm {
+    /// Words that should appear anywhere on the page.
+    Term(String),
+    /// Words that should appear next to each other.
+    Phrase(String),
+    Site(String),
+    /// Words that should appear in the title.
+    Title(String),
+    /// Words that should appear in the body.
+    Body(String),
+    /// Words that should appear in the url.
+    Url(String),
+    Topic(Topic),
+    /// The name of an author of the page.
+    Author(String),
+    /// Exclude the pages that match the term.
+    Not(Box<StructuredTerm>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SiteBoost {
+    pub site: String,
+    /// Positive values boost the site and negative values downrank it.
+    pub boost: i64,
+}
+
+impl StructuredTerm {
+    fn into_terms(self) -> Vec<Term> {
+        fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+            text.split_whitespace().map(|word| word.to_lowercase())
+        }
+
+        match self {
+            StructuredTerm::Term(text) => {
+                words(&text).map(|word| Term::Simple(word.into())).collect()
+            }
+            StructuredTerm::Phrase(text) => {
+                let phrase = words(&text).collect::<Vec<_>>().join(" ");
+
+                if phrase.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![Term::Phrase(phrase)]
+                }
+            }
+            StructuredTerm::Site(site) => words(&site).map(Term::Site).collect(),
+            StructuredTerm::Title(text) => words(&text).map(Term::Title).collect(),
+            StructuredTerm::Body(text) => words(&text).map(Term::Body).collect(),
+            StructuredTerm::Url(text) => words(&text).map(Term::Url).collect(),
+            StructuredTerm::Topic(topic) => vec![Term::Topic(topic)],
+            StructuredTerm::Author(author) => {
+                let author = words(&author).collect::<Vec<_>>().join(" ");
+
+                if author.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![Term::Author(author)]
+                }
+            }
+            StructuredTerm::Not(term) => term
+                .into_terms()
+                .into_iter()
+                .map(|term| match term {
+                    Term::Not(term) => *term,
+                    term => Term::Not(Box::new(term)),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl StructuredQuery {
+    /// The terms of the query as if they had been parsed from text.
+    pub fn terms(&self) -> Vec<Term> {
+        self.terms
+            .iter()
+            .cloned()
+            .flat_map(StructuredTerm::into_terms)
+            .collect()
+    }
+
+    /// Optic rules for the boosts of the query.
+    pub fn rules(&self) -> Vec<Rule> {
+        self.boosts
+            .iter()
+            .filter(|boost| boost.boost != 0 && !boost.site.trim().is_empty())
+            .map(|boost| {
+                let site = boost.site.trim().to_lowercase();
+                let site = site.strip_prefix("www.").unwrap_or(&site).to_string();
+
+                Rule {
+                    matches: vec![vec![Matching {
+                        pattern: vec![
+                            PatternPart::Anchor,
+                            PatternPart::Raw(site),
+                            PatternPart::Anchor,
+                        ],
+                        location: MatchLocation::Site,
+                    }]],
+                    action: if boost.boost > 0 {
+                        Action::Boost(boost.boost.unsigned_abs())
+                    } else {
+                        Action::Downrank(boost.boost.unsigned_abs())
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+/// The terms as text that is parsed back into the same terms. Some parts of the search
+/// only take the query as text, e.g. the snippets, so the words that would otherwise be
+/// read as operators, like `site:x` or `-foo`, are quoted.
+pub fn to_text(terms: &[Term]) -> String {
+    fn escape(term: &Term) -> Option<String> {
+        match term {
+            Term::Simple(word) => {
+                let word = word.as_str().replace('"', "");
+
+                if word.is_empty() {
+                    return None;
+                }
+
+                let is_simple = matches!(
+                    parser::parse(&word).as_slice(),
+                    [term] if matches!(**term, Term::Simple(_))
+                );
+
+                if is_simple {
+                    Some(word)
+                } else {
+                    Some(format!("\"{word}\""))
+                }
+            }
+            Term::Phrase(phrase) => {
+                let phrase = phrase.replace('"', "");
+
+                if phrase.trim().is_empty() {
+                    None
+                } else {
+                    Some(format!("\"{phrase}\""))
+                }
+            }
+            Term::Not(term) => escape(term).map(|term| format!("-{term}")),
+            term => Some(term.to_string()),
+        }
+    }
+
+    terms
+        .iter()
+        .filter_map(escape)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators_are_not_parsed() {
+        let query: StructuredQuery = serde_json::from_str(
+            r#"{
+                "terms": [
+                    {"type": "term", "value": "Site:Example.com -rust"},
+                    {"type": "phrase", "value": "Hello  World"},
+                    {"type": "not", "value": {"type": "site", "value": "spam.com"}},
+                    {"type": "title", "value": "intitle:news"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            query.terms(),
+            vec![
+                Term::Simple("site:example.com".to_string().into()),
+                Term::Simple("-rust".to_string().into()),
+                Term::Phrase("hello world".to_string()),
+                Term::Not(Box::new(Term::Site("spam.com".to_string()))),
+                Term::Title("intitle:news".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        let terms = vec![
+            Term::Simple("site:example.com".to_string().into()),
+            Term::Simple("-rust".to_string().into()),
+            Term::Simple("!w".to_string().into()),
+            Term::Simple("book".to_string().into()),
+            Term::Phrase("hello world".to_string()),
+            Term::Not(Box::new(Term::Site("spam.com".to_string()))),
+            Term::Title("intitle:news".to_string()),
+        ];
+
+        let text = to_text(&terms);
+        assert_eq!(
+            text,
+            r#""site:example.com" "-rust" "!w" book "hello world" -site:spam.com intitle:intitle:news"#
+        );
+
+        let parsed: Vec<_> = parser::parse(&text).into_iter().map(|term| *term).collect();
+        assert_eq!(
+            parsed,
+            vec![
+                Term::Phrase("site:example.com".to_string()),
+                Term::Phrase("-rust".to_string()),
+                Term::Phrase("!w".to_string()),
+                Term::Simple("book".to_string().into()),
+                Term::Phrase("hello world".to_string()),
+                Term::Not(Box::new(Term::Site("spam.com".to_string()))),
+                Term::Title("intitle:news".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn boosts() {
+        let query = StructuredQuery {
+            terms: Vec::new(),
+            boosts: vec![
+                SiteBoost {
+                    site: "www.docs.rs".to_string(),
+                    boost: 3,
+                },
+                SiteBoost {
+                    site: "spam.com".to_string(),
+                    boost: -2,
+                },
+                SiteBoost {
+                    site: "ignored.com".to_string(),
+                    boost: 0,
+                },
+            ],
+        };
+
+        let rules = query.rules();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].action, Action::Boost(3));
+        assert_eq!(
+            rules[0].matches[0][0].pattern,
+            vec![
+                PatternPart::Anchor,
+                PatternPart::Raw("docs.rs".to_string()),
+                PatternPart::Anchor
+            ]
+        );
+        assert_eq!(rules[1].action, Action::Downrank(2));
+    }
+}
@@ -0,0 +1,698 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Sampled telemetry of the features of the queries, like how long they are,
+//! which operators they use and whether they return any results.
+//!
+//! The query itself is never stored, only the features extracted from it, and
+//! the only time information is the day of the query. Nothing leaves the instance.
+//! Each day is a folder with a file for each column, and days older than the
+//! retention are deleted when the store is flushed.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{defaults, QueryTelemetryConfig},
+    query::parser::{self, Term},
+    widgets::Widget,
+    Result,
+};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operator {
+    Site,
+    Title,
+    Body,
+    Url,
+    Topic,
+    Phrase,
+    Not,
+    Bang,
+    Optic,
+    Author,
+}
+
+pub const ALL_OPERATORS: [Operator; 10] = [
+    Operator::Site,
+    Operator::Title,
+    Operator::Body,
+    Operator::Url,
+    Operator::Topic,
+    Operator::Phrase,
+    Operator::Not,
+    Operator::Bang,
+    Operator::Optic,
+    Operator::Author,
+];
+
+impl Operator {
+    fn bit(&self) -> u16 {
+        1 << (*self as u16)
+    }
+
+    fn of_term(term: &Term) -> Option<Self> {
+        match term {
+            Term::Simple(_) => None,
+            Term::Phrase(_) => Some(Operator::Phrase),
+            Term::Not(_) => Some(Operator::Not),
+            Term::Site(_) => Some(Operator::Site),
+            Term::Title(_) => Some(Operator::Title),
+            Term::Body(_) => Some(Operator::Body),
+            Term::Url(_) => Some(Operator::Url),
+            Term::Topic(_) => Some(Operator::Topic),
+            Term::Author(_) => Some(Operator::Author),
+            Term::PossibleBang(_) => Some(Operator::Bang),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WidgetTrigger {
+    Calculator,
+    Thesaurus,
+}
+
+impl From<&Widget> for WidgetTrigger {
+    fn from(widget: &Widget) -> Self {
+        match widget {
+            Widget::Calculator(_) => WidgetTrigger::Calculator,
+            Widget::Thesaurus(_) => WidgetTrigger::Thesaurus,
+        }
+    }
+}
+
+/// Which endpoint the query was sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    Search,
+    Widget,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryFeatures {
+    pub endpoint: Endpoint,
+    pub num_terms: u8,
+    pub num_chars: u16,
+    operators: u16,
+    pub widget: Option<WidgetTrigger>,
+    pub zero_results: bool,
+}
+
+impl QueryFeatures {
+    fn new(endpoint: Endpoint, query: &str, terms: Option<&[Term]>) -> Self {
+        let parsed;
+        let terms: Vec<&Term> = match terms {
+            Some(terms) => terms.iter().collect(),
+            None => {
+                parsed = parser::parse(query);
+                parsed.iter().map(|term| term.as_ref()).collect()
+            }
+        };
+
+        Self {
+            endpoint,
+            num_terms: terms.len().min(u8::MAX as usize) as u8,
+            num_chars: query.chars().count().min(u16::MAX as usize) as u16,
+            operators: terms
+                .iter()
+                .filter_map(|term| Operator::of_term(term))
+                .fold(0, |operators, operator| operators | operator.bit()),
+            widget: None,
+            zero_results: false,
+        }
+    }
+
+    /// The `terms` of structured queries are used instead of parsing the query.
+    pub fn search(
+        query: &str,
+        terms: Option<&[Term]>,
+        has_optic: bool,
+        zero_results: bool,
+    ) -> Self {
+        let mut features = Self::new(Endpoint::Search, query, terms);
+
+        if has_optic {
+            features.operators |= Operator::Optic.bit();
+        }
+
+        features.zero_results = zero_results;
+        features
+    }
+
+    pub fn widget(query: &str, widget: Option<&Widget>) -> Self {
+        let mut features = Self::new(Endpoint::Widget, query, None);
+        features.widget = widget.map(WidgetTrigger::from);
+        features
+    }
+
+    pub fn has_operator(&self, operator: Operator) -> bool {
+        self.operators & operator.bit() != 0
+    }
+}
+
+/// The columns of the rows in a day. Each column has a fixed width, so row `i`
+/// of a column is at byte `i * width` of its file.
+#[derive(Debug, Default)]
+struct Columns {
+    endpoint: Vec<u8>,
+    num_terms: Vec<u8>,
+    num_chars: Vec<u8>,
+    operators: Vec<u8>,
+    widget: Vec<u8>,
+    zero_results: Vec<u8>,
+}
+
+impl Columns {
+    fn columns(&self) -> [(&'static str, &Vec<u8>); 6] {
+        [
+            ("endpoint", &self.endpoint),
+            ("num_terms", &self.num_terms),
+            ("num_chars", &self.num_chars),
+            ("operators", &self.operators),
+            ("widget", &self.widget),
+            ("zero_results", &self.zero_results),
+        ]
+    }
+
+    /// The width of a row of the column in bytes.
+    fn width(name: &str) -> usize {
+        match name {
+            "num_chars" | "operators" => 2,
+            _ => 1,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.endpoint.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn push(&mut self, features: &QueryFeatures) {
+        self.endpoint.push(match features.endpoint {
+            Endpoint::Search => 0,
+            Endpoint::Widget => 1,
+        });
+        self.num_terms.push(features.num_terms);
+        self.num_chars
+            .extend_from_slice(&features.num_chars.to_le_bytes());
+        self.operators
+            .extend_from_slice(&features.operators.to_le_bytes());
+        self.widget.push(match features.widget {
+            None => 0,
+            Some(WidgetTrigger::Calculator) => 1,
+            Some(WidgetTrigger::Thesaurus) => 2,
+        });
+        self.zero_results.push(features.zero_results as u8);
+    }
+
+    fn get(&self, row: usize) -> QueryFeatures {
+        let u16_at = |column: &[u8]| u16::from_le_bytes([column[2 * row], column[2 * row + 1]]);
+
+        QueryFeatures {
+            endpoint: if self.endpoint[row] == 0 {
+                Endpoint::Search
+            } else {
+                Endpoint::Widget
+            },
+            num_terms: self.num_terms[row],
+            num_chars: u16_at(&self.num_chars),
+            operators: u16_at(&self.operators),
+            widget: match self.widget[row] {
+                1 => Some(WidgetTrigger::Calculator),
+                2 => Some(WidgetTrigger::Thesaurus),
+                _ => None,
+            },
+            zero_results: self.zero_results[row] != 0,
+        }
+    }
+
+    fn append_to(&self, folder: &Path) -> Result<()> {
+        fs::create_dir_all(folder)?;
+
+        for (name, column) in self.columns() {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(folder.join(name))?
+                .write_all(column)?;
+        }
+
+        Ok(())
+    }
+
+    fn read(folder: &Path) -> Result<Self> {
+        let read = |name: &str| fs::read(folder.join(name));
+
+        let columns = Self {
+            endpoint: read("endpoint")?,
+            num_terms: read("num_terms")?,
+            num_chars: read("num_chars")?,
+            operators: read("operators")?,
+            widget: read("widget")?,
+            zero_results: read("zero_results")?,
+        };
+
+        // a partially written row from a crash is ignored
+        let len = columns
+            .columns()
+            .iter()
+            .map(|(name, column)| column.len() / Self::width(name))
+            .min()
+            .unwrap_or_default();
+
+        Ok(Self {
+            endpoint: columns.endpoint[..len].to_vec(),
+            num_terms: columns.num_terms[..len].to_vec(),
+            num_chars: columns.num_chars[..2 * len].to_vec(),
+            operators: columns.operators[..2 * len].to_vec(),
+            widget: columns.widget[..len].to_vec(),
+            zero_results: columns.zero_results[..len].to_vec(),
+        })
+    }
+
+    /// Truncate the files in the folder to the rows that were completely written, so the
+    /// rows that are appended after a crash line up. Returns the number of rows.
+    fn truncate_partial_row(folder: &Path) -> Result<usize> {
+        if !folder.exists() {
+            return Ok(0);
+        }
+
+        let len = Self::read(folder)?.len();
+
+        for (name, _) in Self::default().columns() {
+            OpenOptions::new()
+                .write(true)
+                .open(folder.join(name))?
+                .set_len((len * Self::width(name)) as u64)?;
+        }
+
+        Ok(len)
+    }
+}
+
+fn today() -> i64 {
+    chrono::Utc::now().timestamp() / SECONDS_PER_DAY
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct TelemetrySummary {
+    pub num_searches: u64,
+    pub num_zero_results: u64,
+    pub mean_num_terms: f64,
+    pub mean_num_chars: f64,
+    /// Number of searches that use each operator.
+    pub operators: BTreeMap<Operator, u64>,
+    pub num_widget_queries: u64,
+    /// Number of widget queries that triggered each widget.
+    pub widgets: BTreeMap<WidgetTrigger, u64>,
+}
+
+impl TelemetrySummary {
+    fn add(&mut self, features: &QueryFeatures) {
+        match features.endpoint {
+            Endpoint::Search => {
+                self.num_searches += 1;
+                self.num_zero_results += features.zero_results as u64;
+                self.mean_num_terms += features.num_terms as f64;
+                self.mean_num_chars += features.num_chars as f64;
+
+                for operator in ALL_OPERATORS {
+                    if features.has_operator(operator) {
+                        *self.operators.entry(operator).or_default() += 1;
+                    }
+                }
+            }
+            Endpoint::Widget => {
+                self.num_widget_queries += 1;
+
+                if let Some(widget) = features.widget {
+                    *self.widgets.entry(widget).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) -> Self {
+        if self.num_searches > 0 {
+            self.mean_num_terms /= self.num_searches as f64;
+            self.mean_num_chars /= self.num_searches as f64;
+        }
+
+        self
+    }
+}
+
+struct Buffer {
+    day: i64,
+    rows: Columns,
+    /// Rows of the day that have already been written.
+    num_written: usize,
+}
+
+pub struct QueryTelemetry {
+    path: PathBuf,
+    config: QueryTelemetryConfig,
+    buffer: Mutex<Buffer>,
+}
+
+impl QueryTelemetry {
+    pub fn open(config: QueryTelemetryConfig) -> Result<Self> {
+        let path = PathBuf::from(&config.path);
+        fs::create_dir_all(&path)?;
+
+        let day = today();
+        let num_written = Columns::truncate_partial_row(&path.join(day.to_string()))?;
+
+        Ok(Self::new(path, config, day, num_written))
+    }
+
+    /// Open the store to read the summaries. Nothing is recorded, and the
+    /// files are not changed, so it can be opened while the api writes to it.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let config = QueryTelemetryConfig {
+            path: path.as_ref().to_str().unwrap_or_default().to_string(),
+            sample_rate: 0.0,
+            retention_days: defaults::QueryTelemetry::retention_days(),
+            max_rows_per_day: 0,
+        };
+
+        Ok(Self::new(PathBuf::from(&config.path), config, today(), 0))
+    }
+
+    fn new(path: PathBuf, config: QueryTelemetryConfig, day: i64, num_written: usize) -> Self {
+        Self {
+            path,
+            config,
+            buffer: Mutex::new(Buffer {
+                day,
+                rows: Columns::default(),
+                num_written,
+            }),
+        }
+    }
+
+    fn read_day(path: &Path, day: i64) -> Result<Columns> {
+        let folder = path.join(day.to_string());
+
+        if folder.exists() {
+            Columns::read(&folder)
+        } else {
+            Ok(Columns::default())
+        }
+    }
+
+    fn days(&self) -> Result<Vec<i64>> {
+        let mut days: Vec<i64> = fs::read_dir(&self.path)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+            .collect();
+        days.sort();
+
+        Ok(days)
+    }
+
+    /// Record the features of the query, if it is sampled.
+    pub fn record(&self, features: QueryFeatures) {
+        if self.config.sample_rate <= 0.0 || rand::random::<f64>() >= self.config.sample_rate {
+            return;
+        }
+
+        self.record_at(today(), features);
+    }
+
+    fn record_at(&self, day: i64, features: QueryFeatures) {
+        let previous_day = {
+            let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+
+            let previous_day = (buffer.day != day).then(|| {
+                let rows = std::mem::take(&mut buffer.rows);
+                let previous_day = std::mem::replace(&mut buffer.day, day);
+                buffer.num_written = 0;
+
+                (previous_day, rows)
+            });
+
+            if buffer.num_written + buffer.rows.len() < self.config.max_rows_per_day {
+                buffer.rows.push(&features);
+            }
+
+            previous_day
+        };
+
+        // the rows of the previous day are written after the lock is released
+        // so the queries of the new day are not blocked on the disk
+        if let Some((previous_day, rows)) = previous_day {
+            if let Err(err) = self.write(previous_day, &rows) {
+                tracing::error!("failed to write query telemetry: {:?}", err);
+            }
+        }
+    }
+
+    fn write(&self, day: i64, rows: &Columns) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        rows.append_to(&self.path.join(day.to_string()))
+    }
+
+    /// Write the buffered rows and delete the days that are older than the retention.
+    pub fn flush(&self) -> Result<()> {
+        {
+            let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+            let rows = std::mem::take(&mut buffer.rows);
+
+            self.write(buffer.day, &rows)?;
+            buffer.num_written += rows.len();
+        }
+
+        let oldest = today() - self.config.retention_days as i64;
+
+        for day in self.days()? {
+            if day < oldest {
+                fs::remove_dir_all(self.path.join(day.to_string()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Summary of the written rows from the last `num_days` days, including today.
+    pub fn summary(&self, num_days: u64) -> Result<TelemetrySummary> {
+        let first = today() - num_days as i64 + 1;
+        let mut summary = TelemetrySummary::default();
+
+        for day in self.days()?.into_iter().filter(|day| *day >= first) {
+            let rows = Self::read_day(&self.path, day)?;
+
+            for row in 0..rows.len() {
+                summary.add(&rows.get(row));
+            }
+        }
+
+        Ok(summary.finish())
+    }
+}
+
+pub async fn flush_loop(telemetry: Arc<QueryTelemetry>) {
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let telemetry = telemetry.clone();
+        match tokio::task::spawn_blocking(move || telemetry.flush()).await {
+            Ok(Err(err)) => tracing::error!("failed to flush query telemetry: {:?}", err),
+            Err(err) => tracing::error!("failed to flush query telemetry: {:?}", err),
+            Ok(Ok(())) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn telemetry(max_rows_per_day: usize) -> QueryTelemetry {
+        QueryTelemetry::open(QueryTelemetryConfig {
+            path: crate::gen_temp_path().to_str().unwrap().to_string(),
+            sample_rate: 1.0,
+            retention_days: defaults::QueryTelemetry::retention_days(),
+            max_rows_per_day,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn features() {
+        let features = QueryFeatures::search(
+            "\"rust book\" site:example.com -beginner",
+            None,
+            true,
+            false,
+        );
+
+        assert_eq!(features.num_terms, 3);
+        assert!(features.has_operator(Operator::Phrase));
+        assert!(features.has_operator(Operator::Site));
+        assert!(features.has_operator(Operator::Not));
+        assert!(features.has_operator(Operator::Optic));
+        assert!(!features.has_operator(Operator::Title));
+
+        let features = QueryFeatures::search("!w stract", None, false, true);
+        assert!(features.has_operator(Operator::Bang));
+        assert!(features.zero_results);
+
+        let terms = vec![
+            Term::Simple("!w".to_string().into()),
+            Term::Simple("site:example.com".to_string().into()),
+        ];
+        let features =
+            QueryFeatures::search("\"!w\" \"site:example.com\"", Some(&terms), false, false);
+        assert_eq!(features.num_terms, 2);
+        assert!(!features.has_operator(Operator::Bang));
+        assert!(!features.has_operator(Operator::Site));
+        assert!(!features.has_operator(Operator::Phrase));
+    }
+
+    #[test]
+    fn columns_roundtrip() {
+        let path = crate::gen_temp_path();
+        let rows = [
+            QueryFeatures::search("best rust books", None, false, false),
+            QueryFeatures::widget("2 + 2", None),
+            QueryFeatures::search(&"a".repeat(1_000), None, false, true),
+        ];
+
+        let mut columns = Columns::default();
+        for row in &rows {
+            columns.push(row);
+        }
+
+        columns.append_to(&path).unwrap();
+        columns.append_to(&path).unwrap();
+
+        let read = Columns::read(&path).unwrap();
+        assert_eq!(read.len(), 6);
+
+        for (i, row) in rows.iter().chain(rows.iter()).enumerate() {
+            assert_eq!(&read.get(i), row);
+        }
+    }
+
+    #[test]
+    fn summary() {
+        let telemetry = telemetry(100);
+
+        telemetry.record(QueryFeatures::search(
+            "site:example.com rust",
+            None,
+            false,
+            false,
+        ));
+        telemetry.record(QueryFeatures::search("rust programming", None, false, true));
+        telemetry.record(QueryFeatures::widget("2 + 2", None));
+        telemetry.flush().unwrap();
+
+        let summary = telemetry.summary(1).unwrap();
+
+        assert_eq!(summary.num_searches, 2);
+        assert_eq!(summary.num_zero_results, 1);
+        assert_eq!(summary.mean_num_terms, 2.0);
+        assert_eq!(summary.operators.get(&Operator::Site), Some(&1));
+        assert_eq!(summary.operators.get(&Operator::Phrase), None);
+        assert_eq!(summary.num_widget_queries, 1);
+        assert!(summary.widgets.is_empty());
+
+        let read_only = QueryTelemetry::open_read_only(&telemetry.path).unwrap();
+        read_only.record(QueryFeatures::search("not recorded", None, false, false));
+        assert_eq!(read_only.summary(1).unwrap(), summary);
+    }
+
+    #[test]
+    fn partial_row_is_truncated_on_open() {
+        let path = crate::gen_temp_path();
+        let folder = path.join(today().to_string());
+
+        let mut columns = Columns::default();
+        columns.push(&QueryFeatures::search("first query", None, false, false));
+        columns.append_to(&folder).unwrap();
+
+        // a crash after only some of the columns of the next row were written
+        for (name, bytes) in [("endpoint", &[0][..]), ("num_chars", &[7, 0][..])] {
+            OpenOptions::new()
+                .append(true)
+                .open(folder.join(name))
+                .unwrap()
+                .write_all(bytes)
+                .unwrap();
+        }
+
+        let telemetry = QueryTelemetry::open(QueryTelemetryConfig {
+            path: path.to_str().unwrap().to_string(),
+            sample_rate: 1.0,
+            retention_days: defaults::QueryTelemetry::retention_days(),
+            max_rows_per_day: 100,
+        })
+        .unwrap();
+
+        telemetry.record(QueryFeatures::widget("2 + 2", None));
+        telemetry.flush().unwrap();
+
+        let rows = QueryTelemetry::read_day(&path, today()).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows.get(1), QueryFeatures::widget("2 + 2", None));
+    }
+
+    #[test]
+    fn retention() {
+        let telemetry = telemetry(2);
+        let today = today();
+        let old = today - defaults::QueryTelemetry::retention_days() as i64 - 1;
+
+        telemetry.record_at(old, QueryFeatures::search("old query", None, false, false));
+        for _ in 0..3 {
+            telemetry.record_at(
+                today,
+                QueryFeatures::search("new query", None, false, false),
+            );
+        }
+        assert_eq!(telemetry.days().unwrap(), vec![old]);
+
+        telemetry.flush().unwrap();
+
+        // the old day is deleted and only the first rows of a day are kept
+        assert_eq!(telemetry.days().unwrap(), vec![today]);
+        assert_eq!(telemetry.summary(1).unwrap().num_searches, 2);
+    }
+}
@@ -3,8 +3,8 @@
 use serde::{Deserialize, Serialize};
 
 use tantivy::fieldnorm::FieldNormReader;
-use tantivy::query::Explanation;
-use tantivy::{Score, Searcher, Term};
+use tantivy::query::{Bm25StatisticsProvider, Explanation};
+use tantivy::{Score, Term};
 
 const K1: Score = 1.2;
 const B: Score = 0.75;
@@ -52,7 +52,10 @@ impl Bm25Weight {
         }
     }
 
-    pub fn for_terms(searcher: &Searcher, terms: &[Term]) -> tantivy::Result<Bm25Weight> {
+    pub fn for_terms(
+        statistics: &dyn Bm25StatisticsProvider,
+        terms: &[Term],
+    ) -> tantivy::Result<Bm25Weight> {
         assert!(!terms.is_empty(), "Bm25 requires at least one term");
         let field = terms[0].field();
         for term in &terms[1..] {
@@ -63,17 +66,12 @@ impl Bm25Weight {
             );
         }
 
-        let mut total_num_tokens = 0u64;
-        let mut total_num_docs = 0u64;
-        for segment_reader in searcher.segment_readers() {
-            let inverted_index = segment_reader.inverted_index(field)?;
-            total_num_tokens += inverted_index.total_num_tokens();
-            total_num_docs += u64::from(segment_reader.max_doc());
-        }
+        let total_num_tokens = statistics.total_num_tokens(field)?;
+        let total_num_docs = statistics.total_num_docs()?;
         let average_fieldnorm = total_num_tokens as Score / total_num_docs as Score;
 
         if terms.len() == 1 {
-            let term_doc_freq = searcher.doc_freq(&terms[0])?;
+            let term_doc_freq = statistics.doc_freq(&terms[0])?;
             Ok(Bm25Weight::for_one_term(
                 term_doc_freq,
                 total_num_docs,
@@ -82,7 +80,7 @@ impl Bm25Weight {
         } else {
             let mut idf_sum: Score = 0.0;
             for term in terms {
-                let term_doc_freq = searcher.doc_freq(term)?;
+                let term_doc_freq = statistics.doc_freq(term)?;
                 idf_sum += idf(term_doc_freq, total_num_docs);
             }
             let idf_explain = Explanation::new("idf", idf_sum);
@@ -0,0 +1,330 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! BM25F scores the query terms across several fields at once. The length normalized
+//! term frequencies of the fields are weighted and summed before the saturation is
+//! applied, so a term that occurs in both the title and the body is not counted as two
+//! independent matches like it is when each field has its own BM25 signal.
+
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use tantivy::fieldnorm::FieldNormReader;
+use tantivy::postings::SegmentPostings;
+use tantivy::query::Bm25StatisticsProvider;
+use tantivy::tokenizer::Tokenizer;
+use tantivy::{DocId, DocSet, Postings};
+
+use crate::config::{self, Bm25FField};
+use crate::schema::TextField;
+use crate::search_ctx::Ctx;
+use crate::Result;
+
+use super::bm25::idf;
+
+fn text_field(field: Bm25FField) -> TextField {
+    match field {
+        Bm25FField::Title => TextField::Title,
+        Bm25FField::Body => TextField::CleanBody,
+        Bm25FField::Anchor => TextField::BacklinkText,
+        Bm25FField::Url => TextField::Url,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FieldParams {
+    pub field: Bm25FField,
+    pub weight: f64,
+    pub b: f64,
+}
+
+/// The validated BM25F config of a ranking pipeline. It is sent to the search
+/// servers with the query, as they score the candidates.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bm25FParams {
+    pub k1: f64,
+    pub fields: Vec<FieldParams>,
+}
+
+impl Bm25FParams {
+    pub fn parse(config: &config::Bm25FConfig) -> Result<Self> {
+        if !config.k1.is_finite() || config.k1 <= 0.0 {
+            bail!("k1 of bm25f must be a positive number");
+        }
+
+        if config.fields.is_empty() {
+            bail!("bm25f must score at least one field");
+        }
+
+        let mut fields = Vec::with_capacity(config.fields.len());
+        for (field, field_config) in &config.fields {
+            if !field_config.weight.is_finite() || field_config.weight < 0.0 {
+                bail!("weight of the {field:?} field of bm25f must be a non-negative number");
+            }
+
+            if !(0.0..=1.0).contains(&field_config.b) {
+                bail!("b of the {field:?} field of bm25f must be between 0 and 1");
+            }
+
+            fields.push(FieldParams {
+                field: *field,
+                weight: field_config.weight,
+                b: field_config.b,
+            });
+        }
+
+        Ok(Self {
+            k1: config.k1,
+            fields,
+        })
+    }
+
+    /// Whether the field is scored by BM25F instead of its own BM25 signal.
+    pub fn replaces(&self, field: TextField) -> bool {
+        self.fields.iter().any(|f| text_field(f.field) == field)
+    }
+}
+
+/// The statistics of a term in a field of a document.
+#[derive(Debug, Clone, Copy)]
+struct FieldMatch {
+    weight: f64,
+    b: f64,
+    term_freq: u32,
+    len: u32,
+    avg_len: f64,
+}
+
+fn term_score(k1: f64, idf: f64, matches: impl Iterator<Item = FieldMatch>) -> f64 {
+    let tf: f64 = matches
+        .map(|m| {
+            let norm = 1.0 - m.b + m.b * m.len as f64 / m.avg_len.max(1.0);
+            m.weight * m.term_freq as f64 / norm
+        })
+        .sum();
+
+    if tf == 0.0 {
+        return 0.0;
+    }
+
+    idf * tf * (k1 + 1.0) / (k1 + tf)
+}
+
+struct FieldData {
+    params: FieldParams,
+    avg_len: f64,
+    fieldnorm_reader: FieldNormReader,
+}
+
+struct TermData {
+    idf: f64,
+    /// The postings of the term in each of the fields, by their index in `Scorer::fields`.
+    postings: Vec<(usize, SegmentPostings)>,
+}
+
+/// Scores the documents of a segment in ascending order of their ids.
+pub struct Scorer {
+    k1: f64,
+    fields: Vec<FieldData>,
+    terms: Vec<TermData>,
+}
+
+impl Scorer {
+    pub fn new(
+        params: &Bm25FParams,
+        terms: &[String],
+        ctx: &Ctx,
+        segment_reader: &tantivy::SegmentReader,
+    ) -> Result<Self> {
+        let schema = ctx.tv_searcher.schema();
+        let statistics = ctx.statistics_provider();
+        let total_num_docs = statistics.total_num_docs()?;
+        let text =
+            itertools::intersperse(terms.iter().map(|s| s.as_str()), " ").collect::<String>();
+
+        let mut fields = Vec::with_capacity(params.fields.len());
+        let mut by_text: BTreeMap<String, (u64, Vec<(usize, SegmentPostings)>)> = BTreeMap::new();
+
+        for field_params in &params.fields {
+            let text_field = text_field(field_params.field);
+            let tv_field = schema.get_field(text_field.name())?;
+
+            let avg_len =
+                statistics.total_num_tokens(tv_field)? as f64 / total_num_docs.max(1) as f64;
+            let idx = fields.len();
+            fields.push(FieldData {
+                params: *field_params,
+                avg_len,
+                fieldnorm_reader: segment_reader.get_fieldnorms_reader(tv_field)?,
+            });
+
+            let inverted_index = segment_reader.inverted_index(tv_field)?;
+            let mut tokenizer = text_field.indexing_tokenizer();
+            let mut stream = tokenizer.token_stream(&text);
+
+            while let Some(token) = stream.next() {
+                let term = tantivy::Term::from_field_text(tv_field, &token.text);
+                let (doc_freq, postings) = by_text.entry(token.text.clone()).or_default();
+
+                if postings.iter().any(|(i, _)| *i == idx) {
+                    continue;
+                }
+
+                // a document has the term if any of its fields has it, so the
+                // field with the most documents is the closest estimate
+                *doc_freq = (*doc_freq).max(statistics.doc_freq(&term)?);
+
+                if let Some(p) = inverted_index.read_postings(&term, text_field.index_option())? {
+                    postings.push((idx, p));
+                }
+            }
+        }
+
+        let terms = by_text
+            .into_values()
+            .filter(|(_, postings)| !postings.is_empty())
+            .map(|(doc_freq, postings)| TermData {
+                idf: idf(doc_freq.min(total_num_docs), total_num_docs) as f64,
+                postings,
+            })
+            .collect();
+
+        Ok(Self {
+            k1: params.k1,
+            fields,
+            terms,
+        })
+    }
+
+    pub fn score(&mut self, doc: DocId) -> f64 {
+        let fields = &self.fields;
+        let mut score = 0.0;
+
+        for term in &mut self.terms {
+            let matches = term.postings.iter_mut().filter_map(|(idx, posting)| {
+                if posting.doc() == doc || (posting.doc() < doc && posting.seek(doc) == doc) {
+                    let field = &fields[*idx];
+
+                    Some(FieldMatch {
+                        weight: field.params.weight,
+                        b: field.params.b,
+                        term_freq: posting.term_freq(),
+                        len: field.fieldnorm_reader.fieldnorm(doc),
+                        avg_len: field.avg_len,
+                    })
+                } else {
+                    None
+                }
+            });
+
+            score += term_score(self.k1, term.idf, matches);
+        }
+
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field_match(weight: f64, b: f64, term_freq: u32, len: u32, avg_len: f64) -> FieldMatch {
+        FieldMatch {
+            weight,
+            b,
+            term_freq,
+            len,
+            avg_len,
+        }
+    }
+
+    #[test]
+    fn single_field_is_bm25() {
+        let (k1, b, idf) = (1.2, 0.75, 2.0);
+        let (tf, len, avg_len) = (3.0, 20.0, 10.0);
+
+        let bm25 = idf * tf * (k1 + 1.0) / (tf + k1 * (1.0 - b + b * len / avg_len));
+        let bm25f = term_score(
+            k1,
+            idf,
+            std::iter::once(field_match(1.0, b, 3, 20, avg_len)),
+        );
+
+        assert!((bm25 - bm25f).abs() < 1e-9);
+        assert_eq!(term_score(k1, idf, std::iter::empty()), 0.0);
+    }
+
+    #[test]
+    fn fields_saturate_together() {
+        let title = field_match(2.0, 0.0, 1, 5, 5.0);
+        let body = field_match(1.0, 0.0, 1, 100, 100.0);
+
+        let combined = term_score(1.2, 1.0, [title, body].into_iter());
+        let separate = term_score(1.2, 1.0, std::iter::once(title))
+            + term_score(1.2, 1.0, std::iter::once(body));
+
+        assert!(combined < separate);
+        assert!(combined > term_score(1.2, 1.0, std::iter::once(title)));
+
+        // a match in the field with the higher weight counts more
+        assert!(
+            term_score(1.2, 1.0, std::iter::once(title))
+                > term_score(1.2, 1.0, std::iter::once(body))
+        );
+    }
+
+    #[test]
+    fn length_normalization() {
+        let short = field_match(1.0, 1.0, 1, 5, 10.0);
+        let long = field_match(1.0, 1.0, 1, 20, 10.0);
+        assert!(
+            term_score(1.2, 1.0, std::iter::once(short))
+                > term_score(1.2, 1.0, std::iter::once(long))
+        );
+
+        // without normalization the length does not matter
+        let short = field_match(1.0, 0.0, 1, 5, 10.0);
+        let long = field_match(1.0, 0.0, 1, 20, 10.0);
+        assert_eq!(
+            term_score(1.2, 1.0, std::iter::once(short)),
+            term_score(1.2, 1.0, std::iter::once(long))
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let bm25f_config = |k1: f64, weight: f64, b: f64| config::Bm25FConfig {
+            k1,
+            fields: [(Bm25FField::Title, config::Bm25FFieldConfig { weight, b })]
+                .into_iter()
+                .collect(),
+        };
+
+        let params = Bm25FParams::parse(&bm25f_config(1.2, 2.0, 0.5)).unwrap();
+        assert!(params.replaces(TextField::Title));
+        assert!(!params.replaces(TextField::CleanBody));
+
+        assert!(Bm25FParams::parse(&bm25f_config(0.0, 2.0, 0.5)).is_err());
+        assert!(Bm25FParams::parse(&bm25f_config(1.2, -1.0, 0.5)).is_err());
+        assert!(Bm25FParams::parse(&bm25f_config(1.2, 1.0, 1.5)).is_err());
+        assert!(Bm25FParams::parse(&config::Bm25FConfig {
+            k1: 1.2,
+            fields: BTreeMap::new(),
+        })
+        .is_err());
+    }
+}
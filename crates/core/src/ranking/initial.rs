@@ -15,6 +15,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::fastfield_reader::FastFieldReader;
+use crate::search_ctx::Ctx;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use tantivy::collector::{ScoreSegmentTweaker, ScoreTweaker};
@@ -23,7 +24,7 @@ use tantivy::{DocId, SegmentReader};
 use super::SignalAggregator;
 
 pub struct InitialScoreTweaker {
-    tv_searcher: tantivy::Searcher,
+    ctx: Ctx,
     fastfield_reader: FastFieldReader,
     aggregator: SignalAggregator,
 }
@@ -35,13 +36,9 @@ unsafe impl Sync for InitialScoreTweaker {}
 unsafe impl Send for InitialScoreTweaker {}
 
 impl InitialScoreTweaker {
-    pub fn new(
-        tv_searcher: tantivy::Searcher,
-        aggregator: SignalAggregator,
-        fastfield_reader: FastFieldReader,
-    ) -> Self {
+    pub fn new(ctx: Ctx, aggregator: SignalAggregator, fastfield_reader: FastFieldReader) -> Self {
         Self {
-            tv_searcher,
+            ctx,
             aggregator,
             fastfield_reader,
         }
@@ -58,7 +55,7 @@ impl ScoreTweaker<Score> for InitialScoreTweaker {
         aggregator.set_current_timestamp(current_timestamp);
 
         aggregator
-            .register_segment(&self.tv_searcher, segment_reader, &self.fastfield_reader)
+            .register_segment(&self.ctx, segment_reader, &self.fastfield_reader)
             .unwrap();
 
         Ok(InitialSegmentScoreTweaker { aggregator })
@@ -16,6 +16,7 @@
 
 pub mod bitvec_similarity;
 pub mod bm25;
+pub mod bm25f;
 pub mod inbound_similarity;
 pub mod initial;
 pub mod models;
@@ -23,6 +24,9 @@ pub mod optics;
 pub mod pipeline;
 pub mod query_centrality;
 pub mod signal;
+pub mod syndication;
+
+use std::{sync::Arc, time::Instant};
 
 use initial::InitialScoreTweaker;
 
@@ -45,6 +49,7 @@ pub struct Ranker {
     de_rank_similar: bool,
     num_results: Option<usize>,
     collector_config: CollectorConfig,
+    deadline: Option<Instant>,
 }
 
 impl Ranker {
@@ -61,6 +66,7 @@ impl Ranker {
             fastfield_reader,
             num_results: None,
             collector_config,
+            deadline: None,
         }
     }
 
@@ -82,6 +88,12 @@ impl Ranker {
         self
     }
 
+    /// The collectors stop collecting documents at the deadline.
+    pub fn with_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
     pub fn de_rank_similar(&mut self, de_rank_similar: bool) {
         self.de_rank_similar = de_rank_similar;
     }
@@ -93,8 +105,9 @@ impl Ranker {
     pub fn collector(&self, ctx: Ctx) -> MainCollector {
         let aggregator = self.aggregator();
 
+        let tombstones = Arc::clone(&ctx.tombstones);
         let score_tweaker =
-            InitialScoreTweaker::new(ctx.tv_searcher, aggregator, self.fastfield_reader.clone());
+            InitialScoreTweaker::new(ctx, aggregator, self.fastfield_reader.clone());
 
         let mut collector = TopDocs::with_limit(
             self.num_results.unwrap_or(NUM_RESULTS_PER_PAGE),
@@ -113,8 +126,16 @@ impl Ranker {
             collector = collector.and_max_docs(max_docs.clone());
         }
 
+        if let Some(deadline) = self.deadline {
+            collector = collector.and_deadline(deadline);
+        }
+
         collector = collector.and_collector_config(self.collector_config.clone());
 
+        if !tombstones.is_empty() {
+            collector = collector.and_tombstones(tombstones);
+        }
+
         collector.main_collector(score_tweaker)
     }
 
@@ -14,21 +14,24 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::sync::Arc;
+use std::{collections::HashMap, str::FromStr, sync::Arc};
 
+use anyhow::{anyhow, bail};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     collector::{self, BucketCollector},
-    config::CollectorConfig,
+    config::{self, CollectorConfig, RankingModel, RankingStageKind},
     enum_map::EnumMap,
     inverted_index::{RetrievedWebpage, WebsitePointer},
-    searcher::SearchQuery,
+    searcher::{distributed, SearchQuery},
     Result,
 };
 
 use super::{
+    bm25f::Bm25FParams,
     models::lambdamart::{self, LambdaMART},
+    syndication::{self, SourceInfo},
     Signal, SignalAggregator, SignalCoefficient, SignalScore,
 };
 
@@ -85,6 +88,10 @@ impl RetrievedWebpageRanking {
         }
     }
 
+    pub fn retrieved_webpage(&self) -> &RetrievedWebpage {
+        &self.retrieved_webpage
+    }
+
     pub fn into_retrieved_webpage(self) -> RetrievedWebpage {
         self.retrieved_webpage
     }
@@ -107,7 +114,10 @@ pub struct RankingWebsite {
     pub title: Option<String>,
     pub snippet: Option<String>,
     pub optic_boost: Option<f64>,
+    /// Weight of the index the page was found in, when the results of several indexes are fused.
+    pub source_weight: Option<f64>,
     pub score: f64,
+    pub source: SourceInfo,
 }
 
 impl RankingWebsite {
@@ -117,7 +127,9 @@ impl RankingWebsite {
             title: None,
             score: pointer.score.total,
             optic_boost: None,
+            source_weight: None,
             snippet: None,
+            source: aggregator.source_info(pointer.address.doc_id),
             pointer: pointer.clone(),
         };
 
@@ -143,15 +155,21 @@ struct ReRanker<M: CrossEncoder> {
     crossencoder: Arc<M>,
     lambda_mart: Option<Arc<LambdaMART>>,
     query: Option<SearchQuery>,
+    stage_coefficients: SignalCoefficient,
     signal_coefficients: Option<SignalCoefficient>,
 }
 
 impl<M: CrossEncoder> ReRanker<M> {
-    fn new(crossencoder: Arc<M>, lambda: Option<Arc<LambdaMART>>) -> Self {
+    fn new(
+        crossencoder: Arc<M>,
+        lambda: Option<Arc<LambdaMART>>,
+        stage_coefficients: SignalCoefficient,
+    ) -> Self {
         Self {
             crossencoder,
             lambda_mart: lambda,
             query: None,
+            stage_coefficients,
             signal_coefficients: None,
         }
     }
@@ -211,6 +229,11 @@ impl<T: AsRankingWebsite, M: CrossEncoder> Scorer<T> for ReRanker<M> {
 
         for website in websites.iter_mut() {
             let website = website.as_mut_ranking();
+
+            if let Some(coefficients) = &self.signal_coefficients {
+                coefficients.apply(&mut website.signals);
+            }
+
             website.score = calculate_score(
                 &self.lambda_mart,
                 &self.signal_coefficients,
@@ -222,7 +245,7 @@ impl<T: AsRankingWebsite, M: CrossEncoder> Scorer<T> for ReRanker<M> {
     fn set_query_info(&mut self, query: &SearchQuery) {
         self.query = Some(query.clone());
 
-        self.signal_coefficients = query.optic.as_ref().map(SignalCoefficient::from_optic);
+        self.signal_coefficients = query_coefficients(query, &self.stage_coefficients);
     }
 }
 
@@ -232,6 +255,18 @@ impl<T: AsRankingWebsite> Scorer<T> for IdentityScorer {
     fn score(&self, _websites: &mut [T]) {}
 }
 
+/// Coefficients from the optic of the query, falling back to the coefficients of the stage.
+fn query_coefficients(
+    query: &SearchQuery,
+    stage_coefficients: &SignalCoefficient,
+) -> Option<SignalCoefficient> {
+    match query.optic.as_ref().map(SignalCoefficient::from_optic) {
+        Some(coefficients) => Some(coefficients.with_defaults(stage_coefficients)),
+        None if stage_coefficients.is_empty() => None,
+        None => Some(stage_coefficients.clone()),
+    }
+}
+
 fn calculate_score(
     model: &Option<Arc<LambdaMART>>,
     signal_coefficients: &Option<SignalCoefficient>,
@@ -266,6 +301,7 @@ fn calculate_score(
 #[derive(Default)]
 struct Initial {
     model: Option<Arc<LambdaMART>>,
+    stage_coefficients: SignalCoefficient,
     signal_coefficients: Option<SignalCoefficient>,
 }
 
@@ -273,13 +309,18 @@ impl<T: AsRankingWebsite> Scorer<T> for Initial {
     fn score(&self, websites: &mut [T]) {
         for website in websites {
             let website = website.as_mut_ranking();
+
+            if let Some(coefficients) = &self.signal_coefficients {
+                coefficients.apply(&mut website.signals);
+            }
+
             website.score =
                 calculate_score(&self.model, &self.signal_coefficients, &website.signals);
         }
     }
 
     fn set_query_info(&mut self, query: &SearchQuery) {
-        self.signal_coefficients = query.optic.as_ref().map(SignalCoefficient::from_optic);
+        self.signal_coefficients = query_coefficients(query, &self.stage_coefficients);
     }
 }
 
@@ -287,6 +328,7 @@ struct RankingStage<T> {
     scorer: Box<dyn Scorer<T>>,
     stage_top_n: usize,
     derank_similar: bool,
+    min_score: Option<f64>,
 }
 
 impl<T: AsRankingWebsite> RankingStage<T> {
@@ -311,12 +353,26 @@ impl<T: AsRankingWebsite> RankingStage<T> {
                     website.as_mut_ranking().score *= boost;
                 }
             }
+
+            if let Some(weight) = website.as_ranking().source_weight {
+                website.as_mut_ranking().score *= weight;
+            }
+        }
+
+        if self.derank_similar {
+            syndication::promote_original_sources(&mut websites);
         }
 
         let mut collector =
             BucketCollector::new(self.stage_top_n.max(top_n) + offset, collector_config);
 
         for website in websites {
+            if let Some(min_score) = self.min_score {
+                if website.as_ranking().score < min_score {
+                    continue;
+                }
+            }
+
             collector.insert(website);
         }
 
@@ -343,20 +399,27 @@ impl<T: AsRankingWebsite> RankingPipeline<T> {
     fn create_reranking_stage<M: CrossEncoder + 'static>(
         crossencoder: Option<Arc<M>>,
         lambda: Option<Arc<LambdaMART>>,
+        stage_config: &StageConfig,
         collector_config: CollectorConfig,
         top_n_considered: usize,
     ) -> Result<Self> {
+        let crossencoder = crossencoder.filter(|_| stage_config.cross_encoder);
+        let lambda = lambda.filter(|_| stage_config.lambda_mart);
+
         let scorer = match crossencoder {
-            Some(cross_encoder) => {
-                Box::new(ReRanker::new(cross_encoder, lambda)) as Box<dyn Scorer<T>>
-            }
+            Some(cross_encoder) => Box::new(ReRanker::new(
+                cross_encoder,
+                lambda,
+                stage_config.coefficients.clone(),
+            )) as Box<dyn Scorer<T>>,
             None => Box::new(IdentityScorer) as Box<dyn Scorer<T>>,
         };
 
         let stage = RankingStage {
             scorer,
-            stage_top_n: top_n_considered,
+            stage_top_n: stage_config.top_n.unwrap_or(top_n_considered),
             derank_similar: true,
+            min_score: stage_config.min_score,
         };
 
         Ok(Self {
@@ -374,8 +437,32 @@ impl<T: AsRankingWebsite> RankingPipeline<T> {
         collector_config: CollectorConfig,
         top_n_considered: usize,
     ) -> Result<Self> {
-        let mut pipeline =
-            Self::create_reranking_stage(crossencoder, lambda, collector_config, top_n_considered)?;
+        Self::reranker_with_config(
+            query,
+            &StageConfig::default(),
+            crossencoder,
+            lambda,
+            collector_config,
+            top_n_considered,
+        )
+    }
+
+    /// Reranking stage that only uses the models enabled in `stage_config`.
+    pub fn reranker_with_config<M: CrossEncoder + 'static>(
+        query: &mut SearchQuery,
+        stage_config: &StageConfig,
+        crossencoder: Option<Arc<M>>,
+        lambda: Option<Arc<LambdaMART>>,
+        collector_config: CollectorConfig,
+        top_n_considered: usize,
+    ) -> Result<Self> {
+        let mut pipeline = Self::create_reranking_stage(
+            crossencoder,
+            lambda,
+            stage_config,
+            collector_config,
+            top_n_considered,
+        )?;
         pipeline.set_query_info(query);
 
         Ok(pipeline)
@@ -383,16 +470,19 @@ impl<T: AsRankingWebsite> RankingPipeline<T> {
 
     fn create_recall_stage(
         model: Option<Arc<LambdaMART>>,
+        stage_config: &StageConfig,
         collector_config: CollectorConfig,
         stage_top_n: usize,
     ) -> Self {
         let last_stage = RankingStage {
             scorer: Box::new(Initial {
-                model,
+                model: model.filter(|_| stage_config.lambda_mart),
+                stage_coefficients: stage_config.coefficients.clone(),
                 signal_coefficients: None,
             }),
-            stage_top_n,
+            stage_top_n: stage_config.top_n.unwrap_or(stage_top_n),
             derank_similar: true,
+            min_score: stage_config.min_score,
         };
 
         Self {
@@ -409,7 +499,25 @@ impl<T: AsRankingWebsite> RankingPipeline<T> {
         collector_config: CollectorConfig,
         top_n_considered: usize,
     ) -> Self {
-        let mut pipeline = Self::create_recall_stage(model, collector_config, top_n_considered);
+        Self::recall_stage_with_config(
+            query,
+            &StageConfig::default(),
+            model,
+            collector_config,
+            top_n_considered,
+        )
+    }
+
+    /// Recall stage with the top n, coefficients, threshold and model from `stage_config`.
+    pub fn recall_stage_with_config(
+        query: &mut SearchQuery,
+        stage_config: &StageConfig,
+        model: Option<Arc<LambdaMART>>,
+        collector_config: CollectorConfig,
+        top_n_considered: usize,
+    ) -> Self {
+        let mut pipeline =
+            Self::create_recall_stage(model, stage_config, collector_config, top_n_considered);
         pipeline.set_query_info(query);
 
         pipeline
@@ -450,6 +558,197 @@ impl<T: AsRankingWebsite> RankingPipeline<T> {
     }
 }
 
+/// A validated stage of a ranking pipeline config.
+#[derive(Debug, Clone)]
+pub struct StageConfig {
+    top_n: Option<usize>,
+    coefficients: SignalCoefficient,
+    min_score: Option<f64>,
+    lambda_mart: bool,
+    cross_encoder: bool,
+}
+
+impl Default for StageConfig {
+    /// The built-in stage uses the models whenever they are available.
+    fn default() -> Self {
+        Self {
+            top_n: None,
+            coefficients: SignalCoefficient::default(),
+            min_score: None,
+            lambda_mart: true,
+            cross_encoder: true,
+        }
+    }
+}
+
+impl StageConfig {
+    /// A rerank stage that keeps the order of the recall stage.
+    fn identity() -> Self {
+        Self {
+            lambda_mart: false,
+            cross_encoder: false,
+            ..Default::default()
+        }
+    }
+
+    fn parse(config: &config::RankingStageConfig) -> Result<Self> {
+        if config.top_n == Some(0) {
+            bail!("top_n of a ranking stage must be positive");
+        }
+
+        let mut coefficients = Vec::with_capacity(config.weights.len());
+        for (name, weight) in &config.weights {
+            let signal = Signal::from_str(name).map_err(|_| anyhow!("unknown signal: {name}"))?;
+
+            if !weight.is_finite() {
+                bail!("weight of {name} must be a finite number");
+            }
+
+            coefficients.push((signal, *weight));
+        }
+
+        if let Some(min_score) = config.min_score {
+            if !min_score.is_finite() {
+                bail!("min_score of a ranking stage must be a finite number");
+            }
+        }
+
+        let lambda_mart = config.models.contains(&RankingModel::LambdaMart);
+        let cross_encoder = config.models.contains(&RankingModel::CrossEncoder);
+
+        match config.kind {
+            RankingStageKind::Recall if cross_encoder => {
+                bail!("the cross encoder can only be used in a rerank stage")
+            }
+            RankingStageKind::Rerank if !cross_encoder => {
+                bail!("a rerank stage must use the cross encoder")
+            }
+            _ => {}
+        }
+
+        Ok(Self {
+            top_n: config.top_n,
+            coefficients: SignalCoefficient::new(coefficients.into_iter()),
+            min_score: config.min_score,
+            lambda_mart,
+            cross_encoder,
+        })
+    }
+
+    pub fn coefficients(&self) -> &SignalCoefficient {
+        &self.coefficients
+    }
+}
+
+/// A validated ranking pipeline config.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineConfig {
+    pub recall: StageConfig,
+    pub rerank: StageConfig,
+    pub bm25f: Option<Bm25FParams>,
+}
+
+impl PipelineConfig {
+    pub fn parse(config: &config::RankingPipelineConfig) -> Result<Self> {
+        let mut stages = config.stages.iter();
+
+        let recall = match stages.next() {
+            Some(stage) if stage.kind == RankingStageKind::Recall => StageConfig::parse(stage)?,
+            _ => bail!("the first stage of a ranking pipeline must be a recall stage"),
+        };
+
+        let rerank = match stages.next() {
+            Some(stage) if stage.kind == RankingStageKind::Rerank => StageConfig::parse(stage)?,
+            Some(_) => bail!("a recall stage can only be followed by a rerank stage"),
+            None => StageConfig::identity(),
+        };
+
+        if stages.next().is_some() {
+            bail!("a ranking pipeline can have at most one recall and one rerank stage");
+        }
+
+        let bm25f = config.bm25f.as_ref().map(Bm25FParams::parse).transpose()?;
+
+        Ok(Self {
+            recall,
+            rerank,
+            bm25f,
+        })
+    }
+
+    fn uses(&self, model: RankingModel) -> bool {
+        [&self.recall, &self.rerank]
+            .iter()
+            .any(|stage| match model {
+                RankingModel::LambdaMart => stage.lambda_mart,
+                RankingModel::CrossEncoder => stage.cross_encoder,
+            })
+    }
+}
+
+/// The default ranking pipeline and the variants that can be selected per query.
+#[derive(Debug, Clone, Default)]
+pub struct RankingPipelines {
+    default: PipelineConfig,
+    variants: HashMap<String, PipelineConfig>,
+}
+
+impl RankingPipelines {
+    /// Validate the config. Pipelines that use a model which is not
+    /// loaded are rejected, so a typo cannot silently disable a model.
+    pub fn new(
+        config: &config::RankingConfig,
+        has_lambda_mart: bool,
+        has_cross_encoder: bool,
+    ) -> Result<Self> {
+        let check = |name: &str, pipeline: &PipelineConfig| -> Result<()> {
+            if pipeline.uses(RankingModel::LambdaMart) && !has_lambda_mart {
+                bail!("ranking pipeline {name} uses lambdamart but no lambdamart model is loaded");
+            }
+
+            if pipeline.uses(RankingModel::CrossEncoder) && !has_cross_encoder {
+                bail!("ranking pipeline {name} uses the cross encoder but it is not loaded");
+            }
+
+            Ok(())
+        };
+
+        let default = match &config.default {
+            Some(pipeline) => {
+                let pipeline = PipelineConfig::parse(pipeline)
+                    .map_err(|e| anyhow!("invalid default ranking pipeline: {e}"))?;
+                check("default", &pipeline)?;
+                pipeline
+            }
+            None => PipelineConfig::default(),
+        };
+
+        let mut variants = HashMap::with_capacity(config.variants.len());
+        for (name, pipeline) in &config.variants {
+            let pipeline = PipelineConfig::parse(pipeline)
+                .map_err(|e| anyhow!("invalid ranking pipeline {name}: {e}"))?;
+            check(name, &pipeline)?;
+
+            tracing::info!("ranking pipeline variant {name}: {pipeline:?}");
+            variants.insert(name.clone(), pipeline);
+        }
+
+        Ok(Self { default, variants })
+    }
+
+    /// The pipeline of the variant, or the default pipeline if no variant is selected.
+    /// Unknown variants are rejected so a typo does not silently rank with the default.
+    pub fn get(&self, variant: Option<&str>) -> Result<&PipelineConfig> {
+        match variant {
+            Some(name) => self
+                .variants
+                .get(name)
+                .ok_or_else(|| distributed::Error::UnknownRankingVariant(name.to_string()).into()),
+            None => Ok(&self.default),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use itertools::Itertools;
@@ -482,6 +781,7 @@ mod tests {
                             url: Prehashed(0),
                             url_without_tld: Prehashed(0),
                             simhash: 0,
+                            doi: 0,
                         },
                         address: DocAddress {
                             segment: 0,
@@ -490,9 +790,11 @@ mod tests {
                     },
                     signals,
                     optic_boost: None,
+                    source_weight: None,
                     title: None,
                     snippet: None,
                     score: 1.0 / i as f64,
+                    source: Default::default(),
                 }
             })
             .collect()
@@ -610,4 +912,115 @@ mod tests {
             prev = res;
         }
     }
+
+    fn stage(kind: RankingStageKind) -> config::RankingStageConfig {
+        config::RankingStageConfig {
+            kind,
+            top_n: None,
+            weights: Default::default(),
+            min_score: None,
+            models: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn config_validation() {
+        let recall = stage(RankingStageKind::Recall);
+        let mut rerank = stage(RankingStageKind::Rerank);
+        rerank.models = vec![RankingModel::CrossEncoder];
+
+        let pipeline = |stages: Vec<config::RankingStageConfig>| {
+            PipelineConfig::parse(&config::RankingPipelineConfig {
+                stages,
+                bm25f: None,
+            })
+        };
+
+        assert!(pipeline(vec![recall.clone()]).is_ok());
+        assert!(pipeline(vec![recall.clone(), rerank.clone()]).is_ok());
+
+        assert!(pipeline(vec![]).is_err());
+        assert!(pipeline(vec![rerank.clone(), recall.clone()]).is_err());
+        assert!(pipeline(vec![recall.clone(), recall.clone()]).is_err());
+        assert!(pipeline(vec![recall.clone(), rerank.clone(), rerank.clone()]).is_err());
+
+        let mut unknown_signal = recall.clone();
+        unknown_signal
+            .weights
+            .insert("not_a_signal".to_string(), 1.0);
+        assert!(pipeline(vec![unknown_signal]).is_err());
+
+        let bm25f = |b: f64| {
+            PipelineConfig::parse(&config::RankingPipelineConfig {
+                stages: vec![recall.clone()],
+                bm25f: Some(config::Bm25FConfig {
+                    k1: 1.2,
+                    fields: [(
+                        config::Bm25FField::Title,
+                        config::Bm25FFieldConfig { weight: 2.0, b },
+                    )]
+                    .into_iter()
+                    .collect(),
+                }),
+            })
+        };
+        assert!(bm25f(0.5).unwrap().bm25f.is_some());
+        assert!(bm25f(2.0).is_err());
+
+        let mut cross_encoder_recall = recall.clone();
+        cross_encoder_recall.models = vec![RankingModel::CrossEncoder];
+        assert!(pipeline(vec![cross_encoder_recall]).is_err());
+
+        let mut lambda_recall = recall.clone();
+        lambda_recall.models = vec![RankingModel::LambdaMart];
+
+        let mut config = config::RankingConfig::default();
+        config.variants.insert(
+            "lambda".to_string(),
+            config::RankingPipelineConfig {
+                stages: vec![lambda_recall],
+                bm25f: None,
+            },
+        );
+
+        assert!(RankingPipelines::new(&config, false, false).is_err());
+
+        let pipelines = RankingPipelines::new(&config, true, false).unwrap();
+        assert!(pipelines.get(None).is_ok());
+        assert!(pipelines.get(Some("lambda")).is_ok());
+        assert!(matches!(
+            pipelines.get(Some("lamda")).unwrap_err().downcast_ref(),
+            Some(distributed::Error::UnknownRankingVariant(_))
+        ));
+    }
+
+    #[test]
+    fn stage_weights_and_threshold() {
+        let mut recall = stage(RankingStageKind::Recall);
+        recall.weights.insert("host_centrality".to_string(), 2.0);
+        recall.min_score = Some(0.1);
+        recall.top_n = Some(100);
+
+        let stage_config = StageConfig::parse(&recall).unwrap();
+
+        let pipeline: RankingPipeline<RankingWebsite> = RankingPipeline::recall_stage_with_config(
+            &mut SearchQuery {
+                num_results: 100,
+                ..Default::default()
+            },
+            &stage_config,
+            None,
+            CollectorConfig::default(),
+            100,
+        );
+
+        let res = pipeline.apply(sample_websites(100));
+
+        // the scores are 2 / i, so only the first 21 websites are above the threshold
+        assert_eq!(res.len(), 21);
+        assert!(res.iter().all(|w| w.score >= 0.1));
+        assert!(res
+            .iter()
+            .all(|w| w.signals.get(Signal::HostCentrality).unwrap().coefficient == 2.0));
+    }
 }
@@ -16,13 +16,19 @@
 
 use crate::query::optic::AsSearchableRule;
 use crate::query::Query;
+use crate::ranking::syndication::SourceInfo;
 use crate::Result;
 use crate::{
+    combine_u64s,
     enum_map::EnumMap,
     fastfield_reader,
+    prehashed::Prehashed,
     schema::{FastField, TextField},
+    search_ctx::Ctx,
+    security::SecurityAnnotations,
+    signal_store::{SignalStore, StoredSignal},
     webgraph::NodeID,
-    webpage::Webpage,
+    webpage::{topic_classifier::Topic, Webpage},
 };
 use optics::ast::RankingTarget;
 use optics::Optic;
@@ -46,6 +52,7 @@ use crate::{
 };
 
 use super::bm25::Bm25Weight;
+use super::bm25f::{self, Bm25FParams};
 use super::models::linear::LinearRegression;
 use super::{inbound_similarity, query_centrality};
 
@@ -97,6 +104,10 @@ pub enum Signal {
     Bm25TitleIfHomepage,
     #[serde(rename = "bm25_backlink_text")]
     Bm25BacklinkText,
+    #[serde(rename = "bm25_expansion_terms")]
+    Bm25ExpansionTerms,
+    #[serde(rename = "bm25f")]
+    Bm25F,
     #[serde(rename = "cross_encoder_snippet")]
     CrossEncoderSnippet,
     #[serde(rename = "cross_encoder_title")]
@@ -131,6 +142,16 @@ pub enum Signal {
     UrlSlashes,
     #[serde(rename = "link_density")]
     LinkDensity,
+    #[serde(rename = "topic_centrality")]
+    TopicCentrality,
+    #[serde(rename = "reading_ease")]
+    ReadingEase,
+    #[serde(rename = "content_length")]
+    ContentLength,
+    #[serde(rename = "security_risk")]
+    SecurityRisk,
+    #[serde(rename = "generated_content")]
+    GeneratedContent,
 }
 
 impl From<Signal> for usize {
@@ -139,7 +160,7 @@ impl From<Signal> for usize {
     }
 }
 
-pub const ALL_SIGNALS: [Signal; 37] = [
+pub const ALL_SIGNALS: [Signal; 44] = [
     Signal::Bm25Title,
     Signal::Bm25TitleBigrams,
     Signal::Bm25TitleTrigrams,
@@ -160,6 +181,8 @@ pub const ALL_SIGNALS: [Signal; 37] = [
     Signal::Bm25DomainIfHomepageNoTokenizer,
     Signal::Bm25TitleIfHomepage,
     Signal::Bm25BacklinkText,
+    Signal::Bm25ExpansionTerms,
+    Signal::Bm25F,
     Signal::CrossEncoderSnippet,
     Signal::CrossEncoderTitle,
     Signal::HostCentrality,
@@ -177,6 +200,11 @@ pub const ALL_SIGNALS: [Signal; 37] = [
     Signal::UrlDigits,
     Signal::UrlSlashes,
     Signal::LinkDensity,
+    Signal::TopicCentrality,
+    Signal::ReadingEase,
+    Signal::ContentLength,
+    Signal::SecurityRisk,
+    Signal::GeneratedContent,
 ];
 
 fn score_timestamp(timestamp: usize, signal_aggregator: &SignalAggregator) -> f64 {
@@ -223,6 +251,19 @@ fn score_link_density(link_density: f64) -> f64 {
     }
 }
 
+/// Pages with at least this many words are considered fully long-form.
+const LONG_FORM_NUM_WORDS: f64 = 5000.0;
+
+#[inline]
+fn score_reading_ease(reading_ease: f64) -> f64 {
+    reading_ease / 100.0
+}
+
+#[inline]
+fn score_content_length(num_words: f64) -> f64 {
+    (num_words.ln_1p() / LONG_FORM_NUM_WORDS.ln_1p()).min(1.0)
+}
+
 fn score_region(webpage_region: Region, aggregator: &SignalAggregator) -> f64 {
     match aggregator.region_count.as_ref() {
         Some(region_count) => {
@@ -291,6 +332,8 @@ impl Signal {
             Signal::Bm25DomainIfHomepageNoTokenizer => 0.0036,
             Signal::Bm25TitleIfHomepage => 0.00022,
             Signal::Bm25BacklinkText => 0.003,
+            Signal::Bm25ExpansionTerms => 0.001,
+            Signal::Bm25F => 0.01,
             Signal::CrossEncoderSnippet => 0.17,
             Signal::CrossEncoderTitle => 0.17,
             Signal::HostCentrality => 0.5,
@@ -308,6 +351,11 @@ impl Signal {
             Signal::UrlSlashes => 0.01,
             Signal::UrlDigits => 0.01,
             Signal::LinkDensity => 0.00,
+            Signal::TopicCentrality => 0.25,
+            Signal::ReadingEase => 0.0,
+            Signal::ContentLength => 0.0,
+            Signal::SecurityRisk => 0.0,
+            Signal::GeneratedContent => 0.0,
         }
     }
 
@@ -331,15 +379,32 @@ impl Signal {
             Some(node_id.into())
         };
 
+        let stored = self.as_stored_signal().and_then(|stored_signal| {
+            let node_id = match self {
+                Signal::PageCentrality | Signal::PageCentralityRank => {
+                    Some(fastfield_reader.get(&FastField::PageNodeID).into())
+                }
+                _ => host_id,
+            }?;
+
+            signal_aggregator.stored_signal(&node_id, stored_signal)
+        });
+
         let value: Option<f64> = match self {
-            Signal::HostCentrality | Signal::PageCentrality => {
-                let val = fastfield_reader.get(&self.as_fastfield().unwrap());
-                Some(val as f64 / FLOAT_SCALING as f64)
-            }
-            Signal::HostCentralityRank | Signal::PageCentralityRank => {
-                let val = fastfield_reader.get(&self.as_fastfield().unwrap());
-                Some(score_rank(val as f64))
-            }
+            Signal::HostCentrality | Signal::PageCentrality => match stored {
+                Some(val) => Some(val),
+                None => {
+                    let val = fastfield_reader.get(&self.as_fastfield().unwrap());
+                    Some(val as f64 / FLOAT_SCALING as f64)
+                }
+            },
+            Signal::HostCentralityRank | Signal::PageCentralityRank => match stored {
+                Some(val) => Some(score_rank(val)),
+                None => {
+                    let val = fastfield_reader.get(&self.as_fastfield().unwrap());
+                    Some(score_rank(val as f64))
+                }
+            },
             Signal::IsHomepage => {
                 let val = fastfield_reader.get(&self.as_fastfield().unwrap());
                 Some(val as f64)
@@ -348,6 +413,28 @@ impl Signal {
                 let val = fastfield_reader.get(&self.as_fastfield().unwrap());
                 Some(score_link_density(val as f64 / FLOAT_SCALING as f64))
             }
+            Signal::ReadingEase => {
+                let val = fastfield_reader.get(&self.as_fastfield().unwrap());
+
+                if val == u64::MAX {
+                    None
+                } else {
+                    Some(score_reading_ease(val as f64))
+                }
+            }
+            Signal::ContentLength => {
+                let val = fastfield_reader.get(&self.as_fastfield().unwrap());
+                Some(score_content_length(val as f64))
+            }
+            Signal::GeneratedContent => {
+                let val = fastfield_reader.get(&self.as_fastfield().unwrap());
+
+                if val == u64::MAX {
+                    None
+                } else {
+                    Some(val as f64 / FLOAT_SCALING as f64)
+                }
+            }
             Signal::FetchTimeMs => {
                 let fetch_time_ms = fastfield_reader.get(&self.as_fastfield().unwrap()) as usize;
 
@@ -385,6 +472,16 @@ impl Signal {
             Signal::InboundSimilarity => {
                 host_id.map(|host_id| signal_aggregator.inbound_similarity(host_id))
             }
+            Signal::TopicCentrality => {
+                host_id.and_then(|host_id| signal_aggregator.topic_centrality(host_id))
+            }
+            Signal::SecurityRisk => host_id.and_then(|host_id| {
+                let page_id = fastfield_reader.get(&FastField::PageNodeID).into();
+
+                signal_aggregator
+                    .security(&page_id, &host_id)
+                    .map(|security| security.risk())
+            }),
             Signal::Bm25Title
             | Signal::Bm25TitleBigrams
             | Signal::Bm25TitleTrigrams
@@ -404,10 +501,12 @@ impl Signal {
             | Signal::Bm25DomainNameIfHomepageNoTokenizer
             | Signal::Bm25DomainIfHomepageNoTokenizer
             | Signal::Bm25TitleIfHomepage
-            | Signal::Bm25BacklinkText => seg_reader
+            | Signal::Bm25BacklinkText
+            | Signal::Bm25ExpansionTerms => seg_reader
                 .text_fields
                 .get_mut(self.as_textfield().unwrap())
                 .map(|field| bm25(field, doc)),
+            Signal::Bm25F => seg_reader.bm25f.as_mut().map(|scorer| scorer.score(doc)),
 
             Signal::CrossEncoderSnippet => None, // this is calculated in a later step
             Signal::CrossEncoderTitle => None,   // this is calculated in a later step
@@ -492,6 +591,9 @@ impl Signal {
                 let link_density = webpage.html.link_density();
                 Some(score_link_density(link_density))
             }
+            Signal::ReadingEase => webpage.html.reading_ease().map(score_reading_ease),
+            Signal::ContentLength => Some(score_content_length(webpage.html.num_words() as f64)),
+            Signal::GeneratedContent => webpage.html.generated_likelihood(),
             Signal::Bm25Title
             | Signal::Bm25TitleBigrams
             | Signal::Bm25TitleTrigrams
@@ -512,11 +614,15 @@ impl Signal {
             | Signal::Bm25DomainIfHomepageNoTokenizer
             | Signal::Bm25TitleIfHomepage
             | Signal::Bm25BacklinkText
+            | Signal::Bm25ExpansionTerms
+            | Signal::Bm25F
             | Signal::CrossEncoderSnippet
             | Signal::CrossEncoderTitle
             | Signal::InboundSimilarity
             | Signal::LambdaMART
-            | Signal::QueryCentrality => {
+            | Signal::QueryCentrality
+            | Signal::TopicCentrality
+            | Signal::SecurityRisk => {
                 tracing::error!("signal {self:?} cannot be precomputed");
                 None
             }
@@ -545,6 +651,21 @@ impl Signal {
             Signal::UrlSlashes => Some(FastField::NumPathAndQuerySlashes),
             Signal::UrlDigits => Some(FastField::NumPathAndQueryDigits),
             Signal::LinkDensity => Some(FastField::LinkDensity),
+            Signal::ReadingEase => Some(FastField::ReadingEase),
+            Signal::ContentLength => Some(FastField::NumCleanBodyWords),
+            Signal::GeneratedContent => Some(FastField::GeneratedLikelihood),
+            _ => None,
+        }
+    }
+
+    /// The signals that are refreshed from the signal store when it
+    /// is available instead of being read from the index.
+    fn as_stored_signal(&self) -> Option<StoredSignal> {
+        match self {
+            Signal::HostCentrality => Some(StoredSignal::HostCentrality),
+            Signal::HostCentralityRank => Some(StoredSignal::HostCentralityRank),
+            Signal::PageCentrality => Some(StoredSignal::PageCentrality),
+            Signal::PageCentralityRank => Some(StoredSignal::PageCentralityRank),
             _ => None,
         }
     }
@@ -572,6 +693,7 @@ impl Signal {
             }
             Signal::Bm25TitleIfHomepage => Some(TextField::TitleIfHomepage),
             Signal::Bm25BacklinkText => Some(TextField::BacklinkText),
+            Signal::Bm25ExpansionTerms => Some(TextField::ExpansionTerms),
             Signal::Bm25DomainIfHomepageNoTokenizer => Some(TextField::DomainIfHomepageNoTokenizer),
             _ => None,
         }
@@ -588,7 +710,7 @@ impl FromStr for Signal {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SignalCoefficient {
     map: EnumMap<Signal, f64>,
 }
@@ -618,6 +740,33 @@ impl SignalCoefficient {
         }))
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.map.len() == 0
+    }
+
+    /// Add the coefficients from `defaults` for the signals that
+    /// do not already have a coefficient.
+    pub fn with_defaults(mut self, defaults: &SignalCoefficient) -> Self {
+        for signal in ALL_SIGNALS {
+            if let Some(coeff) = defaults.get(&signal) {
+                if !self.map.contains_key(signal) {
+                    self.map.insert(signal, coeff);
+                }
+            }
+        }
+
+        self
+    }
+
+    /// Overwrite the coefficients of the computed signals.
+    pub fn apply(&self, signals: &mut EnumMap<Signal, SignalScore>) {
+        for signal in ALL_SIGNALS {
+            if let (Some(coeff), Some(score)) = (self.get(&signal), signals.get_mut(signal)) {
+                score.coefficient = coeff;
+            }
+        }
+    }
+
     pub fn merge_into(&mut self, coeffs: SignalCoefficient) {
         for signal in ALL_SIGNALS {
             if let Some(coeff) = coeffs.get(&signal) {
@@ -650,6 +799,7 @@ struct OpticBoosts {
 
 struct SegmentReader {
     text_fields: EnumMap<TextField, TextFieldData>,
+    bm25f: Option<bm25f::Scorer>,
     optic_boosts: OpticBoosts,
     fastfield_reader: Arc<fastfield_reader::SegmentReader>,
 }
@@ -659,6 +809,8 @@ struct QueryData {
     simple_terms: Vec<String>,
     optic_rules: Vec<optics::Rule>,
     selected_region: Option<Region>,
+    topic: Option<Topic>,
+    bm25f: Option<Bm25FParams>,
 }
 
 pub struct SignalAggregator {
@@ -672,6 +824,7 @@ pub struct SignalAggregator {
     region_count: Option<Arc<RegionCount>>,
     current_timestamp: Option<usize>,
     linear_regression: Option<Arc<LinearRegression>>,
+    signal_store: Option<Arc<SignalStore>>,
     order: SignalOrder,
 }
 
@@ -698,6 +851,7 @@ impl Clone for SignalAggregator {
             region_count: self.region_count.clone(),
             current_timestamp: self.current_timestamp,
             linear_regression: self.linear_regression.clone(),
+            signal_store: self.signal_store.clone(),
             order: self.order.clone(),
         }
     }
@@ -743,6 +897,8 @@ impl SignalAggregator {
                 .cloned()
                 .collect(),
             selected_region: q.region().cloned(),
+            topic: q.topic(),
+            bm25f: q.bm25f().cloned(),
         });
 
         let mut s = Self {
@@ -755,6 +911,7 @@ impl SignalAggregator {
             region_count: None,
             current_timestamp: None,
             linear_regression: None,
+            signal_store: None,
             query_data: query,
             order: SignalOrder::empty(),
         };
@@ -767,11 +924,12 @@ impl SignalAggregator {
 
     fn prepare_textfields(
         &self,
-        tv_searcher: &tantivy::Searcher,
+        ctx: &Ctx,
         segment_reader: &tantivy::SegmentReader,
     ) -> Result<EnumMap<TextField, TextFieldData>> {
         let mut text_fields = EnumMap::new();
-        let schema = tv_searcher.schema();
+        let schema = ctx.tv_searcher.schema();
+        let statistics = ctx.statistics_provider();
 
         if let Some(query) = &self.query_data {
             if !query.simple_terms.is_empty() {
@@ -797,7 +955,7 @@ impl SignalAggregator {
                             continue;
                         }
 
-                        let weight = Bm25Weight::for_terms(tv_searcher, &terms)?;
+                        let weight = Bm25Weight::for_terms(&statistics, &terms)?;
 
                         let fieldnorm_reader = segment_reader.get_fieldnorms_reader(tv_field)?;
                         let inverted_index = segment_reader.inverted_index(tv_field)?;
@@ -860,16 +1018,26 @@ impl SignalAggregator {
 
     pub fn register_segment(
         &mut self,
-        tv_searcher: &tantivy::Searcher,
+        ctx: &Ctx,
         segment_reader: &tantivy::SegmentReader,
         fastfield_reader: &fastfield_reader::FastFieldReader,
     ) -> Result<()> {
         let fastfield_segment_reader = fastfield_reader.get_segment(&segment_reader.segment_id());
-        let text_fields = self.prepare_textfields(tv_searcher, segment_reader)?;
-        let optic_rule_boosts = self.prepare_optic(tv_searcher, segment_reader, fastfield_reader);
+        let text_fields = self.prepare_textfields(ctx, segment_reader)?;
+        let bm25f = match &self.query_data {
+            Some(query) if !query.simple_terms.is_empty() => query
+                .bm25f
+                .as_ref()
+                .map(|params| bm25f::Scorer::new(params, &query.simple_terms, ctx, segment_reader))
+                .transpose()?,
+            _ => None,
+        };
+        let optic_rule_boosts =
+            self.prepare_optic(&ctx.tv_searcher, segment_reader, fastfield_reader);
 
         self.segment_reader = Some(RefCell::new(SegmentReader {
             text_fields,
+            bm25f,
             fastfield_reader: fastfield_segment_reader,
             optic_boosts: OpticBoosts {
                 rules: optic_rule_boosts,
@@ -902,6 +1070,51 @@ impl SignalAggregator {
         self.linear_regression = Some(linear_model);
     }
 
+    pub fn set_signal_store(&mut self, signal_store: Arc<SignalStore>) {
+        self.signal_store = Some(signal_store);
+    }
+
+    fn stored_signal(&self, node: &NodeID, signal: StoredSignal) -> Option<f64> {
+        self.signal_store
+            .as_ref()
+            .and_then(|store| store.get_signal(node, signal))
+    }
+
+    pub fn security(&self, page: &NodeID, host: &NodeID) -> Option<SecurityAnnotations> {
+        self.signal_store
+            .as_ref()
+            .and_then(|store| store.security(page, host))
+    }
+
+    /// The BM25 signals of the fields that are scored together by BM25F are not used.
+    fn replaced_by_bm25f(&self, signal: &Signal) -> bool {
+        match (
+            signal.as_textfield(),
+            self.query_data.as_ref().and_then(|q| q.bm25f.as_ref()),
+        ) {
+            (Some(field), Some(params)) => params.replaces(field),
+            _ => false,
+        }
+    }
+
+    pub fn query_topic(&self) -> Option<Topic> {
+        self.query_data.as_ref().and_then(|q| q.topic)
+    }
+
+    /// Set the topic of the query, e.g. when it has been predicted by
+    /// the topic classifier instead of selected with the `topic:` operator.
+    pub fn set_query_topic(&mut self, topic: Topic) {
+        if let Some(query) = self.query_data.as_mut() {
+            query.topic = Some(topic);
+        }
+    }
+
+    /// Centrality of the host within the subgraph of the query topic.
+    pub fn topic_centrality(&self, host_id: NodeID) -> Option<f64> {
+        let topic = self.query_topic()?;
+        self.stored_signal(&host_id, StoredSignal::TopicCentrality(topic))
+    }
+
     pub fn query_centrality(&self, host_id: NodeID) -> Option<f64> {
         self.query_centrality
             .as_ref()
@@ -954,6 +1167,27 @@ impl SignalAggregator {
         })
     }
 
+    pub fn source_info(&self, doc: DocId) -> SourceInfo {
+        self.segment_reader
+            .as_ref()
+            .map(|segment_reader| {
+                let segment_reader = segment_reader.borrow();
+                let fastfield_reader = segment_reader.fastfield_reader.get_field_reader(&doc);
+
+                let published = fastfield_reader.get(&FastField::PublishedTime);
+                let canonical_site = combine_u64s([
+                    fastfield_reader.get(&FastField::CanonicalSiteHash1),
+                    fastfield_reader.get(&FastField::CanonicalSiteHash2),
+                ]);
+
+                SourceInfo {
+                    published: (published != 0).then_some(published),
+                    canonical_site: (canonical_site != 0).then_some(Prehashed(canonical_site)),
+                }
+            })
+            .unwrap_or_default()
+    }
+
     pub fn precompute_score(&self, webpage: &Webpage) -> f64 {
         ALL_SIGNALS
             .into_iter()
@@ -963,6 +1197,10 @@ impl SignalAggregator {
     }
 
     pub fn coefficient(&self, signal: &Signal) -> f64 {
+        if self.replaced_by_bm25f(signal) {
+            return 0.0;
+        }
+
         self.query_signal_coefficients
             .as_ref()
             .and_then(|coefficients| coefficients.get(signal))
@@ -0,0 +1,264 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Detection of syndicated articles. When the same article is published on
+//! several hosts, the host that originally published it should rank above the
+//! hosts that copied it.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{prehashed::Prehashed, simhash};
+
+use super::pipeline::AsRankingWebsite;
+
+/// Provenance information about a document used to identify the original source of an article.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceInfo {
+    /// Unix timestamp of when the article was first published.
+    pub published: Option<u64>,
+    /// Site hash of the canonical url if it points to another site.
+    pub canonical_site: Option<Prehashed>,
+}
+
+/// Find groups of near-duplicate articles across different sites and make sure
+/// the original source scores at least as high as any of its syndicated copies.
+/// The original is primarily identified by the canonical urls of the copies,
+/// and otherwise by being published strictly before all the other copies.
+pub fn promote_original_sources<T: AsRankingWebsite>(websites: &mut [T]) {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+
+    for (i, website) in websites.iter().enumerate() {
+        let simhash = website.as_ranking().pointer.hashes.simhash;
+
+        if simhash == 0 {
+            continue;
+        }
+
+        match groups.iter_mut().find(|group| {
+            simhash::is_near_duplicate(
+                websites[group[0]].as_ranking().pointer.hashes.simhash,
+                simhash,
+            )
+        }) {
+            Some(group) => group.push(i),
+            None => groups.push(vec![i]),
+        }
+    }
+
+    for group in groups {
+        if let Some(original) = original_source(websites, &group) {
+            promote(websites, &group, original);
+        }
+    }
+}
+
+fn original_source<T: AsRankingWebsite>(websites: &[T], group: &[usize]) -> Option<usize> {
+    let site = |i: usize| websites[i].as_ranking().pointer.hashes.site;
+    let sites: HashSet<Prehashed> = group.iter().map(|i| site(*i)).collect();
+
+    if sites.len() < 2 {
+        return None;
+    }
+
+    let mut votes: HashMap<Prehashed, usize> = HashMap::new();
+    for i in group {
+        let website = websites[*i].as_ranking();
+
+        if let Some(canonical) = website.source.canonical_site {
+            if canonical != website.pointer.hashes.site && sites.contains(&canonical) {
+                *votes.entry(canonical).or_default() += 1;
+            }
+        }
+    }
+
+    if let Some((original_site, _)) = votes
+        .into_iter()
+        .max_by_key(|(site, count)| (*count, site.0))
+    {
+        return group.iter().copied().find(|i| site(*i) == original_site);
+    }
+
+    let published: Vec<(usize, u64)> = group
+        .iter()
+        .filter_map(|i| websites[*i].as_ranking().source.published.map(|t| (*i, t)))
+        .collect();
+
+    if published.len() < 2 {
+        return None;
+    }
+
+    let earliest = published.iter().map(|(_, t)| *t).min()?;
+    let mut candidates = published.iter().filter(|(_, t)| *t == earliest);
+    let (original, _) = candidates.next()?;
+
+    if candidates.next().is_some() {
+        return None;
+    }
+
+    Some(*original)
+}
+
+/// Swap the score of the original with the best scoring copy if the copy is currently ahead.
+fn promote<T: AsRankingWebsite>(websites: &mut [T], group: &[usize], original: usize) {
+    let score = |i: usize| websites[i].as_ranking().score;
+
+    let Some(best) = group
+        .iter()
+        .copied()
+        .max_by(|a, b| score(*a).total_cmp(&score(*b)))
+    else {
+        return;
+    };
+
+    if best != original && score(best) > score(original) {
+        let best_score = score(best);
+        let original_score = score(original);
+
+        websites[original].as_mut_ranking().score = best_score;
+        websites[best].as_mut_ranking().score = original_score;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tantivy::DocAddress;
+
+    use crate::{
+        collector::Hashes,
+        enum_map::EnumMap,
+        inverted_index::WebsitePointer,
+        prehashed::hash,
+        ranking::{initial::Score, pipeline::RankingWebsite},
+    };
+
+    use super::*;
+
+    fn website(site: &str, simhash: u64, score: f64, source: SourceInfo) -> RankingWebsite {
+        RankingWebsite {
+            pointer: WebsitePointer {
+                score: Score { total: 0.0 },
+                hashes: Hashes {
+                    site: hash(site),
+                    title: Prehashed(0),
+                    url: Prehashed(0),
+                    url_without_tld: Prehashed(0),
+                    simhash,
+                    doi: 0,
+                },
+                address: DocAddress {
+                    segment: 0,
+                    doc_id: 0,
+                },
+            },
+            signals: EnumMap::new(),
+            title: None,
+            snippet: None,
+            optic_boost: None,
+            source_weight: None,
+            score,
+            source,
+        }
+    }
+
+    #[test]
+    fn canonical_points_to_original() {
+        let mut websites = vec![
+            website(
+                "aggregator.com",
+                1,
+                2.0,
+                SourceInfo {
+                    published: None,
+                    canonical_site: Some(hash("news.com")),
+                },
+            ),
+            website("news.com", 1, 1.0, SourceInfo::default()),
+        ];
+
+        promote_original_sources(&mut websites);
+
+        assert_eq!(websites[0].score, 1.0);
+        assert_eq!(websites[1].score, 2.0);
+
+        promote_original_sources(&mut websites);
+
+        assert_eq!(websites[0].score, 1.0);
+        assert_eq!(websites[1].score, 2.0);
+    }
+
+    #[test]
+    fn earliest_published_is_original() {
+        let mut websites = vec![
+            website(
+                "a.com",
+                0b1111,
+                3.0,
+                SourceInfo {
+                    published: Some(200),
+                    canonical_site: None,
+                },
+            ),
+            website(
+                "b.com",
+                0b1110,
+                2.0,
+                SourceInfo {
+                    published: Some(100),
+                    canonical_site: None,
+                },
+            ),
+            website(
+                "c.com",
+                u64::MAX,
+                5.0,
+                SourceInfo {
+                    published: Some(50),
+                    canonical_site: None,
+                },
+            ),
+        ];
+
+        promote_original_sources(&mut websites);
+
+        assert_eq!(websites[0].score, 2.0);
+        assert_eq!(websites[1].score, 3.0);
+        assert_eq!(websites[2].score, 5.0);
+    }
+
+    #[test]
+    fn ambiguous_sources_are_untouched() {
+        let published = SourceInfo {
+            published: Some(100),
+            canonical_site: None,
+        };
+
+        let mut websites = vec![
+            website("a.com", 1, 2.0, published),
+            website("b.com", 1, 1.0, published),
+            website("c.com", u64::MAX, 3.0, SourceInfo::default()),
+            website("c.com", u64::MAX, 1.0, published),
+        ];
+
+        promote_original_sources(&mut websites);
+
+        assert_eq!(websites[0].score, 2.0);
+        assert_eq!(websites[1].score, 1.0);
+        assert_eq!(websites[2].score, 3.0);
+        assert_eq!(websites[3].score, 1.0);
+    }
+}
@@ -59,8 +59,27 @@ pub enum TextField {
     MicroformatTags,
     /// can either be NSFW or SFW (see safety classifier)
     SafetyClassification,
+    /// the most likely topics of the page (see topic classifier)
+    Topics,
     InsertionTimestamp,
     RecipeFirstIngredientTagId,
+    /// json serialized preview image for visual result layouts
+    PreviewImage,
+    /// json serialized metadata for video watch pages
+    VideoMetadata,
+    /// json serialized product sold on the page
+    Product,
+    /// json serialized metadata for scholarly articles
+    Scholarly,
+    /// json serialized places with geo coordinates found on the page
+    Places,
+    /// terms predicted by the document expansion model that are not on the page.
+    /// Each term is repeated according to its weight.
+    ExpansionTerms,
+    /// the authors of the page (meta tags, schema.org and bylines), one value per author
+    Author,
+    /// the url the page was fetched from. Only set if the page is indexed under its rel=canonical url
+    FetchedUrlNoTokenizer,
 }
 
 impl From<TextField> for usize {
@@ -101,8 +120,17 @@ impl TextField {
             TextField::TitleTrigrams => 3,
             TextField::MicroformatTags => 1,
             TextField::SafetyClassification => 1,
+            TextField::Topics => 1,
             TextField::InsertionTimestamp => 1,
             TextField::RecipeFirstIngredientTagId => 1,
+            TextField::PreviewImage => 1,
+            TextField::VideoMetadata => 1,
+            TextField::Product => 1,
+            TextField::Scholarly => 1,
+            TextField::Places => 1,
+            TextField::ExpansionTerms => 1,
+            TextField::Author => 1,
+            TextField::FetchedUrlNoTokenizer => 1,
         }
     }
 
@@ -139,8 +167,17 @@ impl TextField {
             TextField::TitleTrigrams => TextField::Title,
             TextField::MicroformatTags => TextField::MicroformatTags,
             TextField::SafetyClassification => TextField::SafetyClassification,
+            TextField::Topics => TextField::Topics,
             TextField::InsertionTimestamp => TextField::InsertionTimestamp,
             TextField::RecipeFirstIngredientTagId => TextField::RecipeFirstIngredientTagId,
+            TextField::PreviewImage => TextField::PreviewImage,
+            TextField::VideoMetadata => TextField::VideoMetadata,
+            TextField::Product => TextField::Product,
+            TextField::Scholarly => TextField::Scholarly,
+            TextField::Places => TextField::Places,
+            TextField::ExpansionTerms => TextField::ExpansionTerms,
+            TextField::Author => TextField::Author,
+            TextField::FetchedUrlNoTokenizer => TextField::FetchedUrlNoTokenizer,
         }
     }
 
@@ -185,8 +222,17 @@ impl TextField {
             TextField::TitleTrigrams => Tokenizer::Trigram(TrigramTokenizer::default()),
             TextField::MicroformatTags => Tokenizer::default(),
             TextField::SafetyClassification => Tokenizer::Identity(Identity {}),
+            TextField::Topics => Tokenizer::Identity(Identity {}),
             TextField::InsertionTimestamp => Tokenizer::Identity(Identity {}),
             TextField::RecipeFirstIngredientTagId => Tokenizer::Identity(Identity {}),
+            TextField::PreviewImage => Tokenizer::Identity(Identity {}),
+            TextField::VideoMetadata => Tokenizer::Identity(Identity {}),
+            TextField::Product => Tokenizer::Identity(Identity {}),
+            TextField::Scholarly => Tokenizer::Identity(Identity {}),
+            TextField::Places => Tokenizer::Identity(Identity {}),
+            TextField::ExpansionTerms => Tokenizer::default(),
+            TextField::Author => Tokenizer::default(),
+            TextField::FetchedUrlNoTokenizer => Tokenizer::Identity(Identity {}),
         }
     }
 
@@ -229,8 +275,17 @@ impl TextField {
             TextField::TitleTrigrams => false,
             TextField::MicroformatTags => true,
             TextField::SafetyClassification => false,
+            TextField::Topics => false,
             TextField::InsertionTimestamp => false,
             TextField::RecipeFirstIngredientTagId => false,
+            TextField::PreviewImage => false,
+            TextField::VideoMetadata => false,
+            TextField::Product => false,
+            TextField::Scholarly => false,
+            TextField::Places => false,
+            TextField::ExpansionTerms => false,
+            TextField::Author => true,
+            TextField::FetchedUrlNoTokenizer => false,
         }
     }
 
@@ -265,8 +320,17 @@ impl TextField {
             TextField::TitleTrigrams => "title_trigrams",
             TextField::MicroformatTags => "microformat_tags",
             TextField::SafetyClassification => "safety_classification",
+            TextField::Topics => "topics",
             TextField::InsertionTimestamp => "insertion_timestamp",
             TextField::RecipeFirstIngredientTagId => "recipe_first_ingredient_tag_id",
+            TextField::PreviewImage => "preview_image",
+            TextField::VideoMetadata => "video_metadata",
+            TextField::Product => "product",
+            TextField::Scholarly => "scholarly",
+            TextField::Places => "places",
+            TextField::ExpansionTerms => "expansion_terms",
+            TextField::Author => "author",
+            TextField::FetchedUrlNoTokenizer => "fetched_url_no_tokenizer",
         }
     }
 }
@@ -310,6 +374,26 @@ pub enum FastField {
     LikelyHasAds,
     LikelyHasPaywall,
     LinkDensity,
+    /// id of the page in the page graph, used to look up the page signals in the signal store.
+    /// Indexes built before this field was added must be reindexed to be searched.
+    PageNodeID,
+    IsVideo,
+    IsProduct,
+    IsScholarly,
+    /// hash of the normalized DOI, or 0 if the page has no DOI
+    DoiHash,
+    HasPlaces,
+    /// flesch reading ease of the clean body from 0 to 100, or `u64::MAX` if the text is too short
+    ReadingEase,
+    NumCleanBodyWords,
+    /// likelihood that the clean body is machine generated scaled by `FLOAT_SCALING`,
+    /// or `u64::MAX` if the text is too short
+    GeneratedLikelihood,
+    /// `article:published_time` of the page as a unix timestamp, or 0 if it has none
+    PublishedTime,
+    /// hash of the host of the canonical url if it is on another host than the page, or 0
+    CanonicalSiteHash1,
+    CanonicalSiteHash2,
 }
 
 impl FastField {
@@ -352,6 +436,18 @@ impl FastField {
             FastField::LikelyHasAds => "likely_has_ads",
             FastField::LikelyHasPaywall => "likely_has_paywall",
             FastField::LinkDensity => "link_density",
+            FastField::PageNodeID => "page_node_id",
+            FastField::IsVideo => "is_video",
+            FastField::IsProduct => "is_product",
+            FastField::IsScholarly => "is_scholarly",
+            FastField::DoiHash => "doi_hash",
+            FastField::HasPlaces => "has_places",
+            FastField::ReadingEase => "reading_ease",
+            FastField::NumCleanBodyWords => "num_clean_body_words",
+            FastField::GeneratedLikelihood => "generated_likelihood",
+            FastField::PublishedTime => "published_time",
+            FastField::CanonicalSiteHash1 => "canonical_site_hash1",
+            FastField::CanonicalSiteHash2 => "canonical_site_hash2",
         }
     }
 }
@@ -368,7 +464,7 @@ pub enum Field {
     Text(TextField),
 }
 
-static ALL_FIELDS: [Field; 66] = [
+static ALL_FIELDS: [Field; 87] = [
     Field::Text(TextField::Title),
     Field::Text(TextField::CleanBody),
     Field::Text(TextField::StemmedTitle),
@@ -398,7 +494,16 @@ static ALL_FIELDS: [Field; 66] = [
     Field::Text(TextField::TitleTrigrams),
     Field::Text(TextField::MicroformatTags),
     Field::Text(TextField::SafetyClassification),
+    Field::Text(TextField::Topics),
     Field::Text(TextField::InsertionTimestamp),
+    Field::Text(TextField::PreviewImage),
+    Field::Text(TextField::VideoMetadata),
+    Field::Text(TextField::Product),
+    Field::Text(TextField::Scholarly),
+    Field::Text(TextField::Places),
+    Field::Text(TextField::ExpansionTerms),
+    Field::Text(TextField::Author),
+    Field::Text(TextField::FetchedUrlNoTokenizer),
     // FAST FIELDS
     Field::Fast(FastField::IsHomepage),
     Field::Fast(FastField::HostCentrality),
@@ -436,6 +541,18 @@ static ALL_FIELDS: [Field; 66] = [
     Field::Fast(FastField::NumPathAndQueryDigits),
     Field::Fast(FastField::LikelyHasAds),
     Field::Fast(FastField::LikelyHasPaywall),
+    Field::Fast(FastField::PageNodeID),
+    Field::Fast(FastField::IsVideo),
+    Field::Fast(FastField::IsProduct),
+    Field::Fast(FastField::IsScholarly),
+    Field::Fast(FastField::DoiHash),
+    Field::Fast(FastField::HasPlaces),
+    Field::Fast(FastField::ReadingEase),
+    Field::Fast(FastField::NumCleanBodyWords),
+    Field::Fast(FastField::GeneratedLikelihood),
+    Field::Fast(FastField::PublishedTime),
+    Field::Fast(FastField::CanonicalSiteHash1),
+    Field::Fast(FastField::CanonicalSiteHash2),
 ];
 
 impl Field {
@@ -549,9 +666,36 @@ impl Field {
             Field::Text(TextField::SafetyClassification) => {
                 IndexingOption::Text(self.default_text_options())
             }
+            Field::Text(TextField::Topics) => {
+                IndexingOption::Text(self.default_text_options().set_stored())
+            }
             Field::Text(TextField::RecipeFirstIngredientTagId) => {
                 IndexingOption::Text(self.default_text_options().set_stored())
             }
+            Field::Text(TextField::PreviewImage) => {
+                IndexingOption::Text(self.default_text_options().set_stored())
+            }
+            Field::Text(TextField::VideoMetadata) => {
+                IndexingOption::Text(self.default_text_options().set_stored())
+            }
+            Field::Text(TextField::Product) => {
+                IndexingOption::Text(self.default_text_options().set_stored())
+            }
+            Field::Text(TextField::Scholarly) => {
+                IndexingOption::Text(self.default_text_options().set_stored())
+            }
+            Field::Text(TextField::Places) => {
+                IndexingOption::Text(self.default_text_options().set_stored())
+            }
+            Field::Text(TextField::ExpansionTerms) => {
+                IndexingOption::Text(self.default_text_options())
+            }
+            Field::Text(TextField::Author) => {
+                IndexingOption::Text(self.default_text_options().set_stored())
+            }
+            Field::Text(TextField::FetchedUrlNoTokenizer) => {
+                IndexingOption::Text(self.default_text_options())
+            }
             Field::Text(TextField::InsertionTimestamp) => {
                 IndexingOption::DateTime(tantivy::schema::DateOptions::default().set_indexed())
             }
@@ -693,6 +837,42 @@ impl Field {
             Field::Fast(FastField::LinkDensity) => {
                 IndexingOption::Integer(NumericOptions::default().set_fast().set_stored())
             }
+            Field::Fast(FastField::PageNodeID) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_stored())
+            }
+            Field::Fast(FastField::IsVideo) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
+            }
+            Field::Fast(FastField::IsProduct) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
+            }
+            Field::Fast(FastField::IsScholarly) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
+            }
+            Field::Fast(FastField::DoiHash) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_stored())
+            }
+            Field::Fast(FastField::HasPlaces) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast())
+            }
+            Field::Fast(FastField::ReadingEase) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_stored())
+            }
+            Field::Fast(FastField::NumCleanBodyWords) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_stored())
+            }
+            Field::Fast(FastField::GeneratedLikelihood) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_stored())
+            }
+            Field::Fast(FastField::PublishedTime) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast())
+            }
+            Field::Fast(FastField::CanonicalSiteHash1) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast())
+            }
+            Field::Fast(FastField::CanonicalSiteHash2) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast())
+            }
         }
     }
 
@@ -715,6 +895,7 @@ impl Field {
                 | Field::Text(TextField::SchemaOrgJson)
                 | Field::Text(TextField::MicroformatTags)
                 | Field::Text(TextField::SafetyClassification)
+                | Field::Text(TextField::Topics)
                 | Field::Text(TextField::FlattenedSchemaOrgJson)
                 | Field::Text(TextField::UrlForSiteOperator)
                 | Field::Text(TextField::Description)
@@ -728,6 +909,13 @@ impl Field {
                 | Field::Text(TextField::Domain) // will match url
                 | Field::Text(TextField::InsertionTimestamp)
                 | Field::Text(TextField::RecipeFirstIngredientTagId)
+                | Field::Text(TextField::PreviewImage)
+                | Field::Text(TextField::VideoMetadata)
+                | Field::Text(TextField::Product)
+                | Field::Text(TextField::Scholarly)
+                | Field::Text(TextField::Places)
+                | Field::Text(TextField::Author)
+                | Field::Text(TextField::FetchedUrlNoTokenizer)
         ) && !self.is_fast()
     }
 
@@ -814,6 +1002,18 @@ impl FastField {
             FastField::LikelyHasAds => DataType::U64,
             FastField::LikelyHasPaywall => DataType::U64,
             FastField::LinkDensity => DataType::U64,
+            FastField::PageNodeID => DataType::U64,
+            FastField::IsVideo => DataType::U64,
+            FastField::IsProduct => DataType::U64,
+            FastField::IsScholarly => DataType::U64,
+            FastField::DoiHash => DataType::U64,
+            FastField::HasPlaces => DataType::U64,
+            FastField::ReadingEase => DataType::U64,
+            FastField::NumCleanBodyWords => DataType::U64,
+            FastField::GeneratedLikelihood => DataType::U64,
+            FastField::PublishedTime => DataType::U64,
+            FastField::CanonicalSiteHash1 => DataType::U64,
+            FastField::CanonicalSiteHash2 => DataType::U64,
         }
     }
 }
@@ -0,0 +1,282 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Screenshot thumbnails of pages for visual preview cards.
+//!
+//! The crawler captures the thumbnails with a render service when it is enabled, and the
+//! api serves them from the store. The store keeps the thumbnail of the latest generation
+//! of each page, within a quota for each host and a quota for the whole store.
+
+use std::{collections::HashMap, io::Cursor, path::Path, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    config::ScreenshotConfig,
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    webpage::url_ext::UrlExt,
+    Result,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Screenshot {
+    /// Unix timestamp in milliseconds of the fetch of the page the screenshot was taken of.
+    pub generation: u64,
+    pub jpeg: Vec<u8>,
+}
+
+/// Scale the image down to fit within `max_width` x `max_height` and encode it as jpeg.
+pub fn thumbnail(image: &[u8], max_width: u32, max_height: u32, quality: u8) -> Result<Vec<u8>> {
+    let image = image::load_from_memory(image)?.thumbnail(max_width, max_height);
+
+    let mut jpeg = Vec::new();
+    image.to_rgb8().write_to(
+        &mut Cursor::new(&mut jpeg),
+        image::ImageOutputFormat::Jpeg(quality),
+    )?;
+
+    Ok(jpeg)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quotas {
+    pub max_thumbnail_bytes: u64,
+    pub max_host_bytes: u64,
+    pub max_total_bytes: u64,
+}
+
+impl From<&ScreenshotConfig> for Quotas {
+    fn from(config: &ScreenshotConfig) -> Self {
+        Self {
+            max_thumbnail_bytes: config.max_thumbnail_bytes,
+            max_host_bytes: config.max_host_bytes,
+            max_total_bytes: config.max_total_bytes,
+        }
+    }
+}
+
+/// Why a screenshot was not stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum Rejection {
+    #[error("the thumbnail is larger than the maximum size of a thumbnail")]
+    TooLarge,
+    #[error("the screenshots of the host use their whole quota")]
+    HostQuota,
+    #[error("the screenshots use the whole quota of the store")]
+    TotalQuota,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    total: u64,
+    hosts: HashMap<String, u64>,
+}
+
+fn host(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.normalized_host().map(str::to_string))
+        .unwrap_or_default()
+}
+
+pub struct ScreenshotStore {
+    store: RocksDbStore<String, Screenshot>,
+    quotas: Option<Quotas>,
+    usage: Mutex<Usage>,
+}
+
+impl ScreenshotStore {
+    /// Open the store for writing. The usage of the quotas is counted from the stored screenshots.
+    pub fn open<P: AsRef<Path>>(path: P, quotas: Quotas) -> Self {
+        let store: RocksDbStore<String, Screenshot> = RocksDbStore::open(path);
+        let mut usage = Usage::default();
+
+        for (url, screenshot) in store.iter() {
+            let bytes = screenshot.jpeg.len() as u64;
+            usage.total += bytes;
+            *usage.hosts.entry(host(&url)).or_default() += bytes;
+        }
+
+        Self {
+            store,
+            quotas: Some(quotas),
+            usage: Mutex::new(usage),
+        }
+    }
+
+    /// Open the store for reading while the crawler writes to it.
+    /// New screenshots are seen after [`ScreenshotStore::refresh`].
+    pub fn open_reader<P: AsRef<Path>, S: AsRef<Path>>(path: P, secondary_path: S) -> Self {
+        Self {
+            store: RocksDbStore::open_secondary(path, secondary_path),
+            quotas: None,
+            usage: Mutex::new(Usage::default()),
+        }
+    }
+
+    pub fn refresh(&self) {
+        self.store.catch_up();
+    }
+
+    pub fn get(&self, url: &str) -> Option<Screenshot> {
+        self.store.get(&url.to_string())
+    }
+
+    /// Store the screenshot of the page if it fits in the quotas. Screenshots of older
+    /// generations than the stored screenshot are ignored.
+    pub fn insert(&self, url: &str, screenshot: Screenshot) -> Result<(), Rejection> {
+        let existing = self.get(url);
+
+        if existing.as_ref().map_or(false, |existing| {
+            existing.generation >= screenshot.generation
+        }) {
+            return Ok(());
+        }
+
+        let host = host(url);
+        let old_bytes = existing.map_or(0, |existing| existing.jpeg.len() as u64);
+        let new_bytes = screenshot.jpeg.len() as u64;
+
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        let host_bytes = usage.hosts.get(&host).copied().unwrap_or_default();
+
+        if let Some(quotas) = &self.quotas {
+            if new_bytes > quotas.max_thumbnail_bytes {
+                return Err(Rejection::TooLarge);
+            }
+
+            if host_bytes - old_bytes + new_bytes > quotas.max_host_bytes {
+                return Err(Rejection::HostQuota);
+            }
+
+            if usage.total - old_bytes + new_bytes > quotas.max_total_bytes {
+                return Err(Rejection::TotalQuota);
+            }
+        }
+
+        usage.total = usage.total - old_bytes + new_bytes;
+        usage.hosts.insert(host, host_bytes - old_bytes + new_bytes);
+        self.store.insert(url.to_string(), screenshot);
+
+        Ok(())
+    }
+
+    pub fn flush(&self) {
+        self.store.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quotas() -> Quotas {
+        Quotas {
+            max_thumbnail_bytes: 10,
+            max_host_bytes: 16,
+            max_total_bytes: 24,
+        }
+    }
+
+    fn screenshot(generation: u64, bytes: usize) -> Screenshot {
+        Screenshot {
+            generation,
+            jpeg: vec![0; bytes],
+        }
+    }
+
+    #[test]
+    fn quotas_are_enforced() {
+        let store = ScreenshotStore::open(crate::gen_temp_path(), quotas());
+
+        assert_eq!(
+            store.insert("https://a.com/1", screenshot(1, 11)),
+            Err(Rejection::TooLarge)
+        );
+
+        store.insert("https://a.com/1", screenshot(1, 8)).unwrap();
+        store
+            .insert("https://www.a.com/2", screenshot(1, 8))
+            .unwrap();
+        assert_eq!(
+            store.insert("https://a.com/3", screenshot(1, 1)),
+            Err(Rejection::HostQuota)
+        );
+
+        // a new generation replaces the old screenshot in the quota
+        store.insert("https://a.com/1", screenshot(2, 4)).unwrap();
+        store.insert("https://a.com/3", screenshot(1, 4)).unwrap();
+
+        store.insert("https://b.com/1", screenshot(1, 8)).unwrap();
+        assert_eq!(
+            store.insert("https://c.com/1", screenshot(1, 1)),
+            Err(Rejection::TotalQuota)
+        );
+    }
+
+    #[test]
+    fn latest_generation_is_kept() {
+        let path = crate::gen_temp_path();
+        let store = ScreenshotStore::open(&path, quotas());
+
+        store.insert("https://a.com/", screenshot(2, 4)).unwrap();
+        store.insert("https://a.com/", screenshot(1, 8)).unwrap();
+        assert_eq!(store.get("https://a.com/"), Some(screenshot(2, 4)));
+        store.flush();
+        drop(store);
+
+        // the usage is counted from the stored screenshots when the store is opened
+        let store = ScreenshotStore::open(&path, quotas());
+        store.insert("https://b.com/", screenshot(1, 10)).unwrap();
+        assert_eq!(
+            store.insert("https://c.com/", screenshot(1, 10)),
+            Err(Rejection::TotalQuota)
+        );
+    }
+
+    #[test]
+    fn reader_sees_new_screenshots_after_refresh() {
+        let path = crate::gen_temp_path();
+        let writer = ScreenshotStore::open(&path, quotas());
+        let reader = ScreenshotStore::open_reader(&path, crate::gen_temp_path());
+
+        writer.insert("https://a.com/", screenshot(1, 4)).unwrap();
+        writer.flush();
+        reader.refresh();
+
+        assert_eq!(reader.get("https://a.com/"), Some(screenshot(1, 4)));
+    }
+
+    #[test]
+    fn thumbnails_are_scaled_jpegs() {
+        let image = image::RgbImage::from_pixel(1280, 960, image::Rgb([200, 30, 30]));
+        let mut png = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+            .unwrap();
+
+        let jpeg = thumbnail(&png, 320, 320, 70).unwrap();
+        let decoded = image::load_from_memory(&jpeg).unwrap();
+
+        assert_eq!(
+            image::guess_format(&jpeg).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+        assert_eq!((decoded.width(), decoded.height()), (320, 240));
+        assert!(thumbnail(b"not an image", 320, 320, 70).is_err());
+    }
+}
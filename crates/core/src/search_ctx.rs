@@ -14,10 +14,27 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::fastfield_reader::FastFieldReader;
+use std::sync::Arc;
+
+use crate::{
+    collection_stats::{CollectionStats, StatisticsProvider},
+    fastfield_reader::FastFieldReader,
+    query::filter_cache::FilterCache,
+    tombstone::Tombstones,
+};
 
 #[derive(Clone)]
 pub struct Ctx {
     pub tv_searcher: tantivy::Searcher,
     pub fastfield_reader: FastFieldReader,
+    pub filter_cache: Arc<FilterCache>,
+    pub tombstones: Arc<Tombstones>,
+    /// Statistics of all the shards. The statistics of the local index are used if not set.
+    pub collection_stats: Option<Arc<CollectionStats>>,
+}
+
+impl Ctx {
+    pub fn statistics_provider(&self) -> StatisticsProvider<'_> {
+        StatisticsProvider::new(&self.tv_searcher, self.collection_stats.as_deref())
+    }
 }
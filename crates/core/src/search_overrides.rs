@@ -0,0 +1,216 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Hosts blocked by the operators and the optic used for searches without an
+//! optic of their own. The overrides are applied to every query the search
+//! server handles and are changed through the administrative rpcs, so each
+//! change is recorded in the audit log.
+
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use anyhow::bail;
+use optics::{HostRankings, Optic};
+use serde::{Deserialize, Serialize};
+
+use crate::{searcher::SearchQuery, Result};
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct State {
+    #[serde(default)]
+    blocked_hosts: BTreeSet<String>,
+    #[serde(default)]
+    optic: Option<String>,
+}
+
+struct Loaded {
+    state: State,
+    optic: Option<Optic>,
+}
+
+pub struct SearchOverrides {
+    path: Option<PathBuf>,
+    loaded: RwLock<Loaded>,
+}
+
+impl SearchOverrides {
+    /// Overrides that are kept in memory only and are lost when the server restarts.
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            loaded: RwLock::new(Loaded {
+                state: State::default(),
+                optic: None,
+            }),
+        }
+    }
+
+    /// Load the overrides from `path` if it exists. Every change is written back to `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let state: State = if path.exists() {
+            serde_json::from_str(&std::fs::read_to_string(&path)?)?
+        } else {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            State::default()
+        };
+
+        let optic = state.optic.as_deref().map(Optic::parse).transpose()?;
+
+        Ok(Self {
+            path: Some(path),
+            loaded: RwLock::new(Loaded { state, optic }),
+        })
+    }
+
+    /// Block the host from the results. Responds with whether the host was not already blocked.
+    pub fn block_host(&self, host: &str) -> Result<bool> {
+        let host = normalize_host(host)?;
+        self.update(|state| state.blocked_hosts.insert(host))
+    }
+
+    /// Responds with whether the host was blocked.
+    pub fn unblock_host(&self, host: &str) -> Result<bool> {
+        let host = normalize_host(host)?;
+        self.update(|state| state.blocked_hosts.remove(&host))
+    }
+
+    /// Use the optic for the searches without an optic of their own. `None` removes the override.
+    pub fn set_optic(&self, optic: Option<&str>) -> Result<()> {
+        let parsed = optic.map(Optic::parse).transpose()?;
+
+        let mut loaded = self.loaded.write().unwrap_or_else(|e| e.into_inner());
+        let mut state = loaded.state.clone();
+        state.optic = optic.map(|optic| optic.to_string());
+
+        self.persist(&state)?;
+        loaded.state = state;
+        loaded.optic = parsed;
+
+        Ok(())
+    }
+
+    pub fn apply(&self, query: &mut SearchQuery) {
+        let loaded = self.loaded.read().unwrap_or_else(|e| e.into_inner());
+
+        if query.optic.is_none() {
+            query.optic.clone_from(&loaded.optic);
+        }
+
+        if !loaded.state.blocked_hosts.is_empty() {
+            query
+                .host_rankings
+                .get_or_insert_with(HostRankings::default)
+                .blocked
+                .extend(loaded.state.blocked_hosts.iter().cloned());
+        }
+    }
+
+    fn update<T>(&self, f: impl FnOnce(&mut State) -> T) -> Result<T> {
+        let mut loaded = self.loaded.write().unwrap_or_else(|e| e.into_inner());
+        let mut state = loaded.state.clone();
+        let res = f(&mut state);
+
+        self.persist(&state)?;
+        loaded.state = state;
+
+        Ok(res)
+    }
+
+    fn persist(&self, state: &State) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(serde_json::to_string(state)?.as_bytes())?;
+        file.sync_data()?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+}
+
+fn normalize_host(host: &str) -> Result<String> {
+    let host = host.trim().trim_end_matches('.').to_lowercase();
+
+    if host.is_empty() || host.contains(['/', ' ']) {
+        bail!("invalid host: {host:?}");
+    }
+
+    Ok(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_are_applied_and_persisted() {
+        let path = crate::gen_temp_path().join("overrides.json");
+
+        {
+            let overrides = SearchOverrides::open(&path).unwrap();
+            assert!(overrides.block_host("Spam.com").unwrap());
+            assert!(!overrides.block_host("spam.com.").unwrap());
+            assert!(overrides.block_host("ads.example.com").unwrap());
+            assert!(overrides.unblock_host("ads.example.com").unwrap());
+            assert!(overrides.block_host("").is_err());
+
+            assert!(overrides.set_optic(Some("not an optic")).is_err());
+            overrides
+                .set_optic(Some(
+                    "Rule { Matches { Site(\"a.com\") }, Action(Discard) }",
+                ))
+                .unwrap();
+        }
+
+        let overrides = SearchOverrides::open(&path).unwrap();
+
+        let mut query = SearchQuery::default();
+        overrides.apply(&mut query);
+        assert_eq!(
+            query.host_rankings.unwrap().blocked,
+            vec!["spam.com".to_string()]
+        );
+        assert!(query.optic.is_some());
+
+        // the optic of the query takes precedence
+        let mut query = SearchQuery {
+            optic: Some(Optic::default()),
+            ..Default::default()
+        };
+        overrides.apply(&mut query);
+        assert_eq!(query.optic, Some(Optic::default()));
+
+        overrides.set_optic(None).unwrap();
+        let mut query = SearchQuery::default();
+        overrides.apply(&mut query);
+        assert!(query.optic.is_none());
+    }
+}
@@ -15,6 +15,8 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 mod entity;
+mod rich_result;
+mod site_search;
 mod stack_overflow;
 
 use std::collections::HashMap;
@@ -25,15 +27,24 @@ use url::Url;
 use utoipa::ToSchema;
 
 use crate::{
+    blocklist::ThreatType,
     inverted_index::RetrievedWebpage,
+    product_store::PriceDrop,
     ranking::{Signal, SignalScore},
+    searcher::ResultSource,
+    security::SecurityAnnotations,
     snippet::TextSnippet,
     web_spell::{self, CorrectionTerm},
-    webpage::url_ext::UrlExt,
+    webpage::{
+        topic_classifier::Topic, url_ext::UrlExt, PreviewImage, Product, ReadingLevel,
+        ScholarlyMetadata, VideoMetadata, LIKELY_GENERATED_THRESHOLD, WORDS_PER_MINUTE,
+    },
 };
 
 pub use self::stack_overflow::{create_stackoverflow_sidebar, CodeOrText};
 pub use entity::DisplayedEntity;
+pub use rich_result::{FaqEntry, RichResult};
+pub use site_search::SiteSearch;
 
 pub use self::stack_overflow::{stackoverflow_snippet, StackOverflowAnswer, StackOverflowQuestion};
 
@@ -178,6 +189,34 @@ pub struct DisplayedWebpage {
     pub score: Option<f64>,
     pub likely_has_ads: bool,
     pub likely_has_paywall: bool,
+    pub rich_result: Option<RichResult>,
+    pub site_search: Option<SiteSearch>,
+    pub topics: Vec<Topic>,
+    pub preview_image: Option<PreviewImage>,
+    pub video: Option<VideoMetadata>,
+    pub product: Option<Product>,
+    /// Set if the price of the product has dropped since it was last indexed.
+    pub price_drop: Option<PriceDrop>,
+    pub scholarly: Option<ScholarlyMetadata>,
+    pub authors: Vec<String>,
+    /// How hard the page is to read. Not set for pages that are too short to estimate.
+    pub reading_level: Option<ReadingLevel>,
+    pub num_words: u64,
+    pub reading_time_minutes: u64,
+    /// The text of the page is likely machine generated.
+    pub likely_generated: bool,
+    /// Security properties of the page when it was crawled. Not set if they are unknown.
+    pub security: Option<SecurityAnnotations>,
+    /// Set if the url matches a list of known malware or phishing urls.
+    pub threat: Option<ThreatType>,
+    /// Set to the popular host that the domain of the page is a homograph of.
+    pub resembles_host: Option<String>,
+    /// The latest fetch of the page failed, so it may no longer be available.
+    pub may_be_unavailable: bool,
+    /// Archived copy of the page. Only set if it may be unavailable.
+    pub archive_url: Option<String>,
+    /// The index the page was found in.
+    pub source: ResultSource,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -193,6 +232,8 @@ pub struct DisplayedAnswer {
 impl From<RetrievedWebpage> for DisplayedWebpage {
     fn from(webpage: RetrievedWebpage) -> Self {
         let snippet = generate_snippet(&webpage);
+        let rich_result = rich_result::rich_result(&webpage);
+        let site_search = site_search::site_search(&webpage);
 
         let url = Url::parse(&webpage.url).unwrap();
         let domain = url.root_domain().unwrap_or_default().to_string();
@@ -209,6 +250,28 @@ impl From<RetrievedWebpage> for DisplayedWebpage {
             score: None,
             likely_has_ads: webpage.likely_has_ads,
             likely_has_paywall: webpage.likely_has_paywall,
+            rich_result,
+            site_search,
+            topics: webpage.topics,
+            preview_image: webpage.preview_image,
+            video: webpage.video,
+            product: webpage.product,
+            price_drop: None,
+            scholarly: webpage.scholarly,
+            authors: webpage.authors,
+            reading_level: webpage.reading_ease.map(ReadingLevel::from_reading_ease),
+            num_words: webpage.num_words,
+            reading_time_minutes: webpage.num_words.div_ceil(WORDS_PER_MINUTE),
+            likely_generated: webpage
+                .generated_likelihood
+                .map(|likelihood| likelihood > LIKELY_GENERATED_THRESHOLD)
+                .unwrap_or(false),
+            security: webpage.security,
+            threat: None,
+            resembles_host: None,
+            may_be_unavailable: false,
+            archive_url: None,
+            source: ResultSource::default(),
         }
     }
 }
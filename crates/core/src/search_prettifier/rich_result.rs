@@ -0,0 +1,326 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Expandable rich results for webpages with FAQPage or HowTo
+//! schema.org data. The schema.org items are stored with the document when
+//! the page is indexed, and the rich result is built from them when the
+//! result is displayed, so changes to the limits and the sanitization
+//! apply to pages that are already indexed.
+
+use kuchiki::traits::TendrilSink;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    inverted_index::RetrievedWebpage,
+    webpage::schema_org::{Item, OneOrMany, Property},
+};
+
+const MAX_FAQ_ENTRIES: usize = 5;
+const MAX_HOW_TO_STEPS: usize = 10;
+const MAX_QUESTION_CHARS: usize = 200;
+const MAX_ANSWER_CHARS: usize = 400;
+const MAX_STEP_CHARS: usize = 200;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FaqEntry {
+    pub question: String,
+    pub answer: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RichResult {
+    Faq {
+        entries: Vec<FaqEntry>,
+    },
+    HowTo {
+        name: Option<String>,
+        steps: Vec<String>,
+    },
+}
+
+/// Remove any html from the text, collapse whitespace and truncate
+/// the text to at most `max_chars` characters.
+fn sanitize(text: &str, max_chars: usize) -> String {
+    let text = if text.contains('<') {
+        kuchiki::parse_html().one(text).text_contents()
+    } else {
+        text.to_string()
+    };
+
+    let text = itertools::intersperse(text.split_whitespace(), " ").collect::<String>();
+
+    if text.chars().count() <= max_chars {
+        text
+    } else {
+        let mut res: String = text.chars().take(max_chars).collect();
+        res = res.trim_end().to_string();
+        res.push('…');
+        res
+    }
+}
+
+fn property_text(prop: &Property, max_chars: usize) -> Option<String> {
+    let text = match prop {
+        Property::String(s) => s.clone(),
+        Property::Item(item) => item
+            .properties
+            .get("text")
+            .or_else(|| item.properties.get("name"))
+            .and_then(|p| p.clone().one())
+            .and_then(|p| p.try_into_string())?,
+    };
+
+    let text = sanitize(&text, max_chars);
+
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+fn item_text(item: &Item, key: &str, max_chars: usize) -> Option<String> {
+    item.properties
+        .get(key)
+        .and_then(|p| p.clone().one())
+        .and_then(|p| property_text(&p, max_chars))
+}
+
+fn faq(item: &Item) -> Option<RichResult> {
+    let entries: Vec<_> = item
+        .properties
+        .get("mainEntity")?
+        .clone()
+        .many()
+        .into_iter()
+        .filter_map(|prop| prop.try_into_item())
+        .filter(|question| question.types_contains("Question"))
+        .filter_map(|question| {
+            let name = item_text(&question, "name", MAX_QUESTION_CHARS)?;
+            let answer = question
+                .properties
+                .get("acceptedAnswer")
+                .or_else(|| question.properties.get("suggestedAnswer"))
+                .and_then(|p| p.clone().one())
+                .and_then(|p| property_text(&p, MAX_ANSWER_CHARS))?;
+
+            Some(FaqEntry {
+                question: name,
+                answer,
+            })
+        })
+        .take(MAX_FAQ_ENTRIES)
+        .collect();
+
+    if entries.is_empty() {
+        None
+    } else {
+        Some(RichResult::Faq { entries })
+    }
+}
+
+fn how_to_steps(steps: OneOrMany<Property>, res: &mut Vec<String>) {
+    for step in steps.many() {
+        if res.len() >= MAX_HOW_TO_STEPS {
+            break;
+        }
+
+        match &step {
+            Property::Item(item) if item.types_contains("HowToSection") => {
+                if let Some(inner) = item.properties.get("itemListElement") {
+                    how_to_steps(inner.clone(), res);
+                }
+            }
+            _ => {
+                if let Some(text) = property_text(&step, MAX_STEP_CHARS) {
+                    res.push(text);
+                }
+            }
+        }
+    }
+}
+
+fn how_to(item: &Item) -> Option<RichResult> {
+    let mut steps = Vec::new();
+    how_to_steps(item.properties.get("step")?.clone(), &mut steps);
+
+    if steps.is_empty() {
+        return None;
+    }
+
+    Some(RichResult::HowTo {
+        name: item_text(item, "name", MAX_QUESTION_CHARS),
+        steps,
+    })
+}
+
+/// Build the rich result from the stored schema.org items of the retrieved page.
+pub fn rich_result(webpage: &RetrievedWebpage) -> Option<RichResult> {
+    webpage.schema_org.iter().find_map(|item| {
+        if item.types_contains("FAQPage") {
+            faq(item)
+        } else if item.types_contains("HowTo") {
+            how_to(item)
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::webpage::Html;
+
+    use super::*;
+
+    fn webpage(html: &str) -> RetrievedWebpage {
+        let html = Html::parse(html, "https://example.com/").unwrap();
+
+        RetrievedWebpage {
+            schema_org: html.schema_org(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn faq_page() {
+        let page = webpage(
+            r#"
+            <html>
+                <head>
+                    <script type="application/ld+json">
+                    {
+                        "@context": "https://schema.org",
+                        "@type": "FAQPage",
+                        "mainEntity": [{
+                            "@type": "Question",
+                            "name": "What is stract?",
+                            "acceptedAnswer": {
+                                "@type": "Answer",
+                                "text": "<p>An <b>open source</b> search engine.</p>"
+                            }
+                        }, {
+                            "@type": "Question",
+                            "name": "Is it free?",
+                            "acceptedAnswer": {
+                                "@type": "Answer",
+                                "text": "Yes"
+                            }
+                        }]
+                    }
+                    </script>
+                </head>
+            </html>
+            "#,
+        );
+
+        assert_eq!(
+            rich_result(&page),
+            Some(RichResult::Faq {
+                entries: vec![
+                    FaqEntry {
+                        question: "What is stract?".to_string(),
+                        answer: "An open source search engine.".to_string(),
+                    },
+                    FaqEntry {
+                        question: "Is it free?".to_string(),
+                        answer: "Yes".to_string(),
+                    },
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn how_to_with_sections() {
+        let page = webpage(
+            r#"
+            <html>
+                <head>
+                    <script type="application/ld+json">
+                    {
+                        "@context": "https://schema.org",
+                        "@type": "HowTo",
+                        "name": "How to tie a tie",
+                        "step": [{
+                            "@type": "HowToSection",
+                            "itemListElement": [
+                                {"@type": "HowToStep", "text": "Drape the tie"},
+                                {"@type": "HowToStep", "text": "Cross the wide end"}
+                            ]
+                        }, {
+                            "@type": "HowToStep",
+                            "text": "Tighten the knot"
+                        }]
+                    }
+                    </script>
+                </head>
+            </html>
+            "#,
+        );
+
+        assert_eq!(
+            rich_result(&page),
+            Some(RichResult::HowTo {
+                name: Some("How to tie a tie".to_string()),
+                steps: vec![
+                    "Drape the tie".to_string(),
+                    "Cross the wide end".to_string(),
+                    "Tighten the knot".to_string(),
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let long = "a ".repeat(1000);
+        assert!(sanitize(&long, MAX_ANSWER_CHARS).chars().count() <= MAX_ANSWER_CHARS + 1);
+
+        let questions = (0..20)
+            .map(|i| {
+                format!(
+                    r#"{{"@type": "Question", "name": "Q{i}", "acceptedAnswer": {{"@type": "Answer", "text": "A{i}"}}}}"#
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let page = webpage(&format!(
+            r#"
+            <html>
+                <head>
+                    <script type="application/ld+json">
+                    {{
+                        "@context": "https://schema.org",
+                        "@type": "FAQPage",
+                        "mainEntity": [{questions}]
+                    }}
+                    </script>
+                </head>
+            </html>
+            "#
+        ));
+
+        match rich_result(&page) {
+            Some(RichResult::Faq { entries }) => assert_eq!(entries.len(), MAX_FAQ_ENTRIES),
+            _ => panic!("expected faq"),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Sitelink search box for navigational results. A site supports site search
+//! if its homepage has a schema.org `WebSite` with a `SearchAction`, or if the
+//! host is one of a few well-known sites.
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+use utoipa::ToSchema;
+
+use crate::{
+    inverted_index::RetrievedWebpage,
+    webpage::{schema_org::Item, url_ext::UrlExt},
+};
+
+/// Placeholder used in the url templates we return.
+const SEARCH_TERM_PLACEHOLDER: &str = "{search_term_string}";
+
+const KNOWN_SITE_SEARCH: &[(&str, &str)] = &[
+    (
+        "en.wikipedia.org",
+        "https://en.wikipedia.org/w/index.php?search={search_term_string}",
+    ),
+    (
+        "github.com",
+        "https://github.com/search?q={search_term_string}",
+    ),
+    (
+        "stackoverflow.com",
+        "https://stackoverflow.com/search?q={search_term_string}",
+    ),
+    (
+        "youtube.com",
+        "https://www.youtube.com/results?search_query={search_term_string}",
+    ),
+    (
+        "reddit.com",
+        "https://www.reddit.com/search/?q={search_term_string}",
+    ),
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SiteSearch {
+    /// The site to use for the `site` parameter when re-running a query
+    /// scoped to the site.
+    pub site: String,
+    /// The site's own search url where `{search_term_string}` should be
+    /// replaced by the url-encoded query.
+    pub url_template: String,
+}
+
+fn search_action_template(item: &Item, page: &Url) -> Option<String> {
+    item.properties
+        .get("potentialAction")?
+        .clone()
+        .many()
+        .into_iter()
+        .filter_map(|action| action.try_into_item())
+        .filter(|action| action.types_contains("SearchAction"))
+        .find_map(|action| {
+            let placeholder = action
+                .properties
+                .get("query-input")
+                .and_then(|p| p.clone().one())
+                .and_then(|p| p.try_into_string())
+                .and_then(|input| {
+                    input
+                        .split_whitespace()
+                        .find_map(|part| part.strip_prefix("name=").map(|s| s.to_string()))
+                })
+                .unwrap_or_else(|| "search_term_string".to_string());
+
+            let target = action.properties.get("target")?.clone().one()?;
+            let template = match target.try_into_string() {
+                Some(template) => template,
+                None => target
+                    .try_into_item()?
+                    .properties
+                    .get("urlTemplate")
+                    .and_then(|p| p.clone().one())
+                    .and_then(|p| p.try_into_string())?,
+            };
+
+            let placeholder = format!("{{{placeholder}}}");
+            if !template.contains(&placeholder) {
+                return None;
+            }
+
+            // only allow the site to forward searches to itself
+            let url = Url::parse(&template.replace(&placeholder, "")).ok()?;
+            if !matches!(url.scheme(), "http" | "https")
+                || url.root_domain().is_none()
+                || url.root_domain() != page.root_domain()
+            {
+                return None;
+            }
+
+            Some(template.replace(&placeholder, SEARCH_TERM_PLACEHOLDER))
+        })
+}
+
+fn known_template(url: &Url) -> Option<String> {
+    let host = url.normalized_host()?;
+
+    KNOWN_SITE_SEARCH
+        .iter()
+        .find(|(known, _)| *known == host)
+        .map(|(_, template)| template.to_string())
+}
+
+pub fn site_search(webpage: &RetrievedWebpage) -> Option<SiteSearch> {
+    let url = Url::parse(&webpage.url).ok()?;
+
+    if !url.is_homepage() {
+        return None;
+    }
+
+    let url_template = webpage
+        .schema_org
+        .iter()
+        .filter(|item| item.types_contains("WebSite"))
+        .find_map(|item| search_action_template(item, &url))
+        .or_else(|| known_template(&url))?;
+
+    Some(SiteSearch {
+        site: url.normalized_host()?.to_string(),
+        url_template,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::webpage::Html;
+
+    use super::*;
+
+    fn webpage(html: &str, url: &str) -> RetrievedWebpage {
+        let html = Html::parse(html, url).unwrap();
+
+        RetrievedWebpage {
+            url: url.to_string(),
+            schema_org: html.schema_org(),
+            ..Default::default()
+        }
+    }
+
+    const SEARCH_ACTION: &str = r#"
+        <html>
+            <head>
+                <script type="application/ld+json">
+                {
+                    "@context": "https://schema.org",
+                    "@type": "WebSite",
+                    "url": "https://www.example.com/",
+                    "potentialAction": {
+                        "@type": "SearchAction",
+                        "target": {
+                            "@type": "EntryPoint",
+                            "urlTemplate": "https://query.example.com/search?q={query}"
+                        },
+                        "query-input": "required name=query"
+                    }
+                }
+                </script>
+            </head>
+        </html>
+    "#;
+
+    #[test]
+    fn structured_data() {
+        let page = webpage(SEARCH_ACTION, "https://www.example.com/");
+
+        assert_eq!(
+            site_search(&page),
+            Some(SiteSearch {
+                site: "example.com".to_string(),
+                url_template: "https://query.example.com/search?q={search_term_string}".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn only_homepages() {
+        let page = webpage(SEARCH_ACTION, "https://www.example.com/some/article");
+        assert_eq!(site_search(&page), None);
+    }
+
+    #[test]
+    fn other_domain_rejected() {
+        let html = SEARCH_ACTION.replace("query.example.com", "evil.com");
+        let page = webpage(&html, "https://www.example.com/");
+        assert_eq!(site_search(&page), None);
+    }
+
+    #[test]
+    fn known_site() {
+        let page = webpage("<html></html>", "https://github.com/");
+        assert_eq!(
+            site_search(&page).map(|s| s.url_template),
+            Some("https://github.com/search?q={search_term_string}".to_string())
+        );
+    }
+}
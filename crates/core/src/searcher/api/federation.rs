@@ -0,0 +1,291 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Fusion of the results from the web index, the live index and the verticals.
+//!
+//! The scores of the indexes are not on the same scale, so when federation is configured
+//! the scores of each index are divided by the best score of the index before the results
+//! are fused. The weight of the index is also kept as the source weight of its results, so
+//! it still applies when the ranking stages rescore the candidates. A page that is found
+//! in more than one index is only kept from the index where it scored the highest.
+
+use std::collections::HashMap;
+
+use crate::{config::FederationConfig, ranking::pipeline::AsRankingWebsite};
+
+use super::{distributed, live, ScoredWebsitePointer, Vertical};
+
+/// The initial results of each index that was searched.
+#[derive(Default)]
+pub struct FederatedResults {
+    pub web: Vec<distributed::InitialSearchResultShard>,
+    pub live: Vec<live::InitialSearchResultSplit>,
+    pub verticals: Vec<(Vertical, Vec<distributed::InitialSearchResultShard>)>,
+    pub live_verticals: Vec<(Vertical, Vec<live::InitialSearchResultSplit>)>,
+}
+
+impl FederatedResults {
+    /// Fuse the results. The scores are only normalized and weighted if federation is configured.
+    /// Also returns whether the web or live index has more results.
+    pub fn fuse(self, config: Option<&FederationConfig>) -> (Vec<ScoredWebsitePointer>, bool) {
+        let has_more = self.web.iter().any(|result| result.local_result.has_more)
+            || self.live.iter().any(|result| result.local_result.has_more);
+
+        let normalize = config.is_some();
+        let config = config.cloned().unwrap_or_default();
+
+        let live = split_pointers(self.live);
+
+        let mut sources = vec![
+            (1.0, shard_pointers(self.web, ScoredWebsitePointer::Normal)),
+            (config.live_weight, live),
+        ];
+
+        for (vertical, results) in self.verticals {
+            sources.push((
+                config.vertical_weight(vertical),
+                shard_pointers(results, |pointer| {
+                    ScoredWebsitePointer::Vertical(vertical, pointer)
+                }),
+            ));
+        }
+
+        for (vertical, results) in self.live_verticals {
+            sources.push((
+                config.vertical_weight(vertical) * config.live_weight,
+                split_pointers(results),
+            ));
+        }
+
+        (fuse(sources, normalize), has_more)
+    }
+}
+
+fn split_pointers(results: Vec<live::InitialSearchResultSplit>) -> Vec<ScoredWebsitePointer> {
+    results
+        .into_iter()
+        .flat_map(|result| {
+            let split_id = result.split_id;
+
+            result
+                .local_result
+                .websites
+                .into_iter()
+                .map(move |website| {
+                    ScoredWebsitePointer::Live(live::ScoredWebsitePointer {
+                        website,
+                        split_id: split_id.clone(),
+                    })
+                })
+        })
+        .collect()
+}
+
+fn shard_pointers(
+    results: Vec<distributed::InitialSearchResultShard>,
+    pointer: impl Fn(distributed::ScoredWebsitePointer) -> ScoredWebsitePointer,
+) -> Vec<ScoredWebsitePointer> {
+    results
+        .into_iter()
+        .flat_map(|result| {
+            let shard = result.shard;
+
+            result
+                .local_result
+                .websites
+                .into_iter()
+                .map(move |website| distributed::ScoredWebsitePointer { website, shard })
+        })
+        .map(pointer)
+        .collect()
+}
+
+/// Divide the scores by the best score of the source and apply the weight of the source.
+pub fn normalize<T: AsRankingWebsite>(websites: &mut [T], weight: f64) {
+    let max = websites
+        .iter()
+        .map(|website| website.as_ranking().score)
+        .fold(0.0, f64::max);
+
+    for website in websites {
+        let ranking = website.as_mut_ranking();
+
+        if max > 0.0 {
+            ranking.score /= max;
+        }
+
+        ranking.score *= weight;
+
+        if weight != 1.0 {
+            ranking.source_weight = Some(weight);
+        }
+    }
+}
+
+/// Fuse the weighted results of the sources into a single list of unique pages.
+/// The weights are ignored if the scores are not normalized. The list is not sorted.
+pub fn fuse<T: AsRankingWebsite>(sources: Vec<(f64, Vec<T>)>, normalize_scores: bool) -> Vec<T> {
+    let mut best: HashMap<_, T> = HashMap::new();
+    let mut order = Vec::new();
+
+    for (weight, mut websites) in sources {
+        if normalize_scores {
+            normalize(&mut websites, weight);
+        }
+
+        for website in websites {
+            let url = website.as_ranking().pointer.hashes.url;
+
+            match best.get_mut(&url) {
+                Some(existing) => {
+                    if website.as_ranking().score > existing.as_ranking().score {
+                        *existing = website;
+                    }
+                }
+                None => {
+                    order.push(url);
+                    best.insert(url, website);
+                }
+            }
+        }
+    }
+
+    order
+        .into_iter()
+        .filter_map(|url| best.remove(&url))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        collector::Hashes,
+        inverted_index::{DocAddress, WebsitePointer},
+        prehashed::Prehashed,
+        ranking::{initial::Score, pipeline::RankingWebsite},
+    };
+
+    use super::*;
+
+    fn website(url: u128, score: f64) -> RankingWebsite {
+        RankingWebsite {
+            pointer: WebsitePointer {
+                score: Score { total: score },
+                hashes: Hashes {
+                    site: Prehashed(url),
+                    title: Prehashed(url),
+                    url: Prehashed(url),
+                    url_without_tld: Prehashed(url),
+                    simhash: 0,
+                    doi: 0,
+                },
+                address: DocAddress {
+                    segment: 0,
+                    doc_id: url as u32,
+                },
+            },
+            signals: Default::default(),
+            title: None,
+            snippet: None,
+            optic_boost: None,
+            source_weight: None,
+            score,
+            source: Default::default(),
+        }
+    }
+
+    #[test]
+    fn normalize_scores() {
+        let mut websites = vec![website(1, 20.0), website(2, 5.0)];
+        normalize(&mut websites, 1.0);
+
+        assert_eq!(websites[0].score, 1.0);
+        assert_eq!(websites[1].score, 0.25);
+        assert_eq!(websites[0].source_weight, None);
+
+        let mut websites = vec![website(1, 0.4), website(2, 0.2)];
+        normalize(&mut websites, 0.5);
+
+        assert_eq!(websites[0].score, 0.5);
+        assert_eq!(websites[1].score, 0.25);
+        assert_eq!(websites[1].source_weight, Some(0.5));
+        assert_eq!(websites[1].optic_boost, None);
+    }
+
+    #[test]
+    fn sources_on_different_scales() {
+        let fused = fuse(
+            vec![
+                (1.0, vec![website(1, 100.0), website(2, 50.0)]),
+                (1.0, vec![website(3, 0.8), website(4, 0.2)]),
+            ],
+            true,
+        );
+
+        let mut fused: Vec<_> = fused
+            .into_iter()
+            .map(|website| (website.pointer.hashes.url.0, website.score))
+            .collect();
+        fused.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        assert_eq!(fused, vec![(1, 1.0), (3, 1.0), (2, 0.5), (4, 0.25)]);
+    }
+
+    #[test]
+    fn duplicates_keep_best_source() {
+        let fused = fuse(
+            vec![
+                (1.0, vec![website(1, 10.0), website(2, 2.0)]),
+                (0.5, vec![website(2, 1.0)]),
+            ],
+            true,
+        );
+
+        assert_eq!(fused.len(), 2);
+
+        let page = fused
+            .iter()
+            .find(|website| website.pointer.hashes.url.0 == 2)
+            .unwrap();
+        assert_eq!(page.score, 0.5);
+        assert_eq!(page.source_weight, Some(0.5));
+    }
+
+    #[test]
+    fn scores_are_kept_without_normalization() {
+        let fused = fuse(
+            vec![
+                (1.0, vec![website(1, 10.0), website(2, 2.0)]),
+                (0.5, vec![website(2, 4.0), website(3, 1.0)]),
+            ],
+            false,
+        );
+
+        let mut fused: Vec<_> = fused
+            .into_iter()
+            .map(|website| {
+                (
+                    website.pointer.hashes.url.0,
+                    website.score,
+                    website.source_weight,
+                )
+            })
+            .collect();
+        fused.sort_by(|(_, a, _), (_, b, _)| b.total_cmp(a));
+
+        assert_eq!(fused, vec![(1, 10.0, None), (2, 4.0, None), (3, 1.0, None)]);
+    }
+}
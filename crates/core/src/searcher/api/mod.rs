@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+pub mod federation;
 mod sidebar;
 mod widget;
 
@@ -23,34 +24,73 @@ use std::time::Instant;
 
 use itertools::{intersperse, Itertools};
 use url::Url;
+use utoipa::ToSchema;
 
 use crate::bangs::{Bang, BangHit};
+use crate::blocklist::{BlocklistAction, LiveBlocklist, ThreatType};
 use crate::collector::Doc;
-use crate::config::{ApiConfig, CollectorConfig};
+use crate::config::{ApiConfig, CollectorConfig, FederatedVerticalConfig, FederationConfig};
+use crate::geo_store::{GeoStore, PlacesResult};
+use crate::homograph::{HomographAction, HomographDetector};
+use crate::host_policy::HostPolicyStore;
 use crate::image_store::Image;
-use crate::inverted_index::RetrievedWebpage;
+use crate::inverted_index::{RetrievedWebpage, UrlIndexStatus};
+use crate::liveness::LivenessStore;
+use crate::locale::Locale;
+use crate::product_store::ProductStore;
 use crate::ranking::models::cross_encoder::CrossEncoderModel;
 use crate::ranking::pipeline::{AsRankingWebsite, RankingWebsite, RetrievedWebpageRanking};
 use crate::ranking::ALL_SIGNALS;
-use crate::search_prettifier::{DisplayedSidebar, DisplayedWebpage, HighlightedSpellCorrection};
+use crate::search_prettifier::{
+    DisplayedSidebar, DisplayedWebpage, HighlightedSpellCorrection, Snippet,
+};
+use crate::snippet::{SnippetOptions, TextSnippet};
 use crate::web_spell::SpellChecker;
+use crate::webpage::url_ext::UrlExt;
 use crate::widgets::{Widget, Widgets};
 use crate::{
     bangs::Bangs,
     collector::BucketCollector,
-    ranking::{models::lambdamart::LambdaMART, pipeline::RankingPipeline},
+    ranking::{
+        models::lambdamart::LambdaMART,
+        pipeline::{RankingPipeline, RankingPipelines},
+    },
 };
 use crate::{query, Result};
 
+use self::federation::FederatedResults;
 use self::sidebar::SidebarManager;
 use self::widget::WidgetManager;
 
-use super::{distributed, live, SearchQuery, SearchResult, WebsitesResult};
+use super::{
+    author_facets,
+    cost::SearchCost,
+    distributed, live,
+    planner::{QueryPlan, QueryPlanner, RetrievalStrategy},
+    topic_facets, InstantWebpage, ResultSource, SearchQuery, SearchResult, Vertical,
+    WebsitesResult,
+};
+
+/// Places further away from the location in a local intent query than this are not shown.
+const PLACES_RADIUS_KM: f64 = 10.0;
+const NUM_PLACES: usize = 10;
 
 #[derive(Clone)]
 pub enum ScoredWebsitePointer {
     Normal(distributed::ScoredWebsitePointer),
     Live(live::ScoredWebsitePointer),
+    /// A page from the web index that was found when searching the vertical.
+    Vertical(Vertical, distributed::ScoredWebsitePointer),
+}
+
+impl ScoredWebsitePointer {
+    pub fn source(&self) -> ResultSource {
+        match self {
+            ScoredWebsitePointer::Normal(_) => ResultSource::Web,
+            ScoredWebsitePointer::Live(_) => ResultSource::Live,
+            ScoredWebsitePointer::Vertical(vertical, _) => ResultSource::from(*vertical),
+        }
+    }
 }
 
 impl AsRankingWebsite for ScoredWebsitePointer {
@@ -58,6 +98,7 @@ impl AsRankingWebsite for ScoredWebsitePointer {
         match self {
             ScoredWebsitePointer::Normal(p) => &p.website,
             ScoredWebsitePointer::Live(p) => &p.website,
+            ScoredWebsitePointer::Vertical(_, p) => &p.website,
         }
     }
 
@@ -65,51 +106,30 @@ impl AsRankingWebsite for ScoredWebsitePointer {
         match self {
             ScoredWebsitePointer::Normal(p) => &mut p.website,
             ScoredWebsitePointer::Live(p) => &mut p.website,
+            ScoredWebsitePointer::Vertical(_, p) => &mut p.website,
         }
     }
 }
 
+/// The initial results from the live index for the query and each federated vertical.
+#[derive(Default)]
+struct LiveResults {
+    web: Vec<live::InitialSearchResultSplit>,
+    verticals: Vec<(Vertical, Vec<live::InitialSearchResultSplit>)>,
+}
+
 pub fn combine_results(
     collector_config: CollectorConfig,
-    initial_results: Vec<distributed::InitialSearchResultShard>,
-    live_results: Vec<live::InitialSearchResultSplit>,
+    federation: Option<&FederationConfig>,
+    results: FederatedResults,
     pipeline: RankingPipeline<ScoredWebsitePointer>,
 ) -> (Vec<ScoredWebsitePointer>, bool) {
     let mut collector = BucketCollector::new(pipeline.collector_top_n(), collector_config);
 
-    let mut has_more = false;
-    for result in initial_results {
-        if result.local_result.has_more {
-            has_more = true;
-        }
-
-        for website in result.local_result.websites {
-            let pointer = distributed::ScoredWebsitePointer {
-                website,
-                shard: result.shard,
-            };
-
-            let pointer = ScoredWebsitePointer::Normal(pointer);
-
-            collector.insert(pointer);
-        }
-    }
-
-    for result in live_results {
-        if result.local_result.has_more {
-            has_more = true;
-        }
-
-        for website in result.local_result.websites {
-            let pointer = live::ScoredWebsitePointer {
-                website,
-                split_id: result.split_id.clone(),
-            };
-
-            let pointer = ScoredWebsitePointer::Live(pointer);
+    let (websites, has_more) = results.fuse(federation);
 
-            collector.insert(pointer);
-        }
+    for pointer in websites {
+        collector.insert(pointer);
     }
 
     let top_websites = collector
@@ -136,16 +156,225 @@ pub fn add_ranking_signals(websites: &mut [DisplayedWebpage], pointers: &[Scored
     }
 }
 
+/// Annotate the websites with the threats they match in the blocklist.
+pub fn apply_blocklist(blocklist: &LiveBlocklist, websites: &mut [DisplayedWebpage]) {
+    let current = blocklist.load();
+
+    if current.is_empty() {
+        return;
+    }
+
+    for website in websites.iter_mut() {
+        website.threat = Url::parse(&website.url)
+            .ok()
+            .and_then(|url| current.lookup(&url));
+    }
+}
+
+/// Remove the retrieved webpages that match the blocklist together with their pointers,
+/// if the blocklist is configured to filter. The webpages are filtered as they are
+/// retrieved, so the ranking stages after the retrieval never see them.
+pub fn filter_blocklisted<W, T>(
+    blocklist: &LiveBlocklist,
+    webpages: &mut Vec<W>,
+    pointers: &mut Vec<T>,
+    url: impl Fn(&W) -> &str,
+) {
+    if blocklist.config().action != BlocklistAction::Filter {
+        return;
+    }
+
+    let current = blocklist.load();
+
+    if current.is_empty() {
+        return;
+    }
+
+    let keep: Vec<_> = webpages
+        .iter()
+        .map(|webpage| {
+            Url::parse(url(webpage))
+                .ok()
+                .and_then(|url| current.lookup(&url))
+                .is_none()
+        })
+        .collect();
+
+    let mut keep_webpages = keep.iter();
+    webpages.retain(|_| *keep_webpages.next().unwrap());
+
+    let mut keep_pointers = keep.iter();
+    pointers.retain(|_| *keep_pointers.next().unwrap_or(&true));
+}
+
+/// Annotate the websites with the popular hosts their domains are homographs of. The
+/// annotated websites are moved below the others, together with their pointers, if the
+/// detector is configured to demote.
+pub fn apply_homographs<T>(
+    detector: &HomographDetector,
+    websites: &mut Vec<DisplayedWebpage>,
+    pointers: &mut Vec<T>,
+) {
+    for website in websites.iter_mut() {
+        website.resembles_host = Url::parse(&website.url)
+            .ok()
+            .and_then(|url| detector.resembles(&url).map(str::to_string));
+    }
+
+    if detector.action() == HomographAction::Demote
+        && websites.iter().any(|w| w.resembles_host.is_some())
+    {
+        let (spoofed, rest): (Vec<_>, Vec<_>) = websites
+            .drain(..)
+            .zip(pointers.drain(..))
+            .partition(|(website, _)| website.resembles_host.is_some());
+
+        for (website, pointer) in rest.into_iter().chain(spoofed) {
+            websites.push(website);
+            pointers.push(pointer);
+        }
+    }
+}
+
+/// Mark the websites whose latest fetch failed and link to an archived copy of them.
+pub fn apply_liveness(store: &LivenessStore, websites: &mut [DisplayedWebpage]) {
+    for website in websites.iter_mut() {
+        website.archive_url = store.fallback(&website.url);
+        website.may_be_unavailable = website.archive_url.is_some();
+    }
+}
+
+/// Remove the snippets and archive links of websites from hosts that
+/// have asked for it in their robots meta tags.
+pub fn apply_host_policy(store: &HostPolicyStore, websites: &mut [DisplayedWebpage]) {
+    for website in websites.iter_mut() {
+        let Some(url) = Url::parse(&website.url).ok() else {
+            continue;
+        };
+        let host = url.normalized_host().unwrap_or_default();
+
+        if !store.show_snippet(host) {
+            let date = match &website.snippet {
+                Snippet::Normal { date, .. } => date.clone(),
+                Snippet::StackOverflowQA { .. } => None,
+            };
+
+            website.snippet = Snippet::Normal {
+                date,
+                text: TextSnippet::default(),
+            };
+        }
+
+        if !store.show_archive(host) {
+            website.archive_url = None;
+        }
+    }
+}
+
+/// Why a url is not shown in the search results.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum UrlExclusion {
+    InvalidUrl,
+    /// The page has been deleted from the index.
+    Deleted,
+    /// The page matches the blocklist, which filters the matching results.
+    Blocklisted {
+        threat: ThreatType,
+    },
+}
+
+/// What the index knows about a url.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UrlStatus {
+    pub url: String,
+    pub indexed: bool,
+    /// The url the page is indexed under if it differs from the requested url.
+    pub canonical_url: Option<String>,
+    /// Unix timestamp in milliseconds of the latest crawl of the page.
+    pub last_crawl: Option<u64>,
+    pub last_status_code: Option<u16>,
+    /// Unix timestamp in milliseconds of the latest successful crawl of the page.
+    pub last_success: Option<u64>,
+    pub threat: Option<ThreatType>,
+    pub excluded: Option<UrlExclusion>,
+}
+
+/// Combine the status of the url in the index with its latest crawl and the blocklist.
+pub fn url_status(
+    url: &str,
+    index_status: UrlIndexStatus,
+    liveness: Option<&LivenessStore>,
+    blocklist: Option<&LiveBlocklist>,
+) -> UrlStatus {
+    let mut status = UrlStatus {
+        url: url.to_string(),
+        indexed: false,
+        canonical_url: None,
+        last_crawl: None,
+        last_status_code: None,
+        last_success: None,
+        threat: None,
+        excluded: None,
+    };
+
+    let Ok(parsed) = Url::parse(url) else {
+        status.excluded = Some(UrlExclusion::InvalidUrl);
+        return status;
+    };
+
+    match index_status {
+        UrlIndexStatus::Indexed { url: indexed_url } => {
+            status.indexed = true;
+            if indexed_url != url {
+                status.canonical_url = Some(indexed_url);
+            }
+        }
+        UrlIndexStatus::Deleted => status.excluded = Some(UrlExclusion::Deleted),
+        UrlIndexStatus::NotIndexed => {}
+    }
+
+    let crawled_url = status.canonical_url.as_deref().unwrap_or(url);
+    if let Some(liveness) = liveness.and_then(|store| store.get(crawled_url)) {
+        status.last_crawl = Some(liveness.last_fetch);
+        status.last_status_code = liveness.status_code;
+        status.last_success = liveness.last_success;
+    }
+
+    if let Some(blocklist) = blocklist {
+        status.threat = blocklist.load().lookup(&parsed);
+
+        if let Some(threat) = status.threat {
+            if status.excluded.is_none() && blocklist.config().action == BlocklistAction::Filter {
+                status.excluded = Some(UrlExclusion::Blocklisted { threat });
+            }
+        }
+    }
+
+    status
+}
+
 pub struct ApiSearcher<S, L> {
     distributed_searcher: Arc<S>,
     sidebar_manager: SidebarManager<S>,
     live_searcher: Option<L>,
     cross_encoder: Option<Arc<CrossEncoderModel>>,
     lambda_model: Option<Arc<LambdaMART>>,
+    ranking_pipelines: RankingPipelines,
     bangs: Bangs,
     collector_config: CollectorConfig,
     widget_manager: WidgetManager,
     spell_checker: Option<SpellChecker>,
+    product_store: Option<ProductStore>,
+    geo_store: Option<GeoStore>,
+    blocklist: Option<Arc<LiveBlocklist>>,
+    homographs: Option<HomographDetector>,
+    liveness: Option<Arc<LivenessStore>>,
+    host_policy: Option<Arc<HostPolicyStore>>,
+    federation: Option<FederationConfig>,
+    planner: Option<QueryPlanner>,
+    model_runner: Option<rayon::ThreadPool>,
 }
 
 impl<S, L> ApiSearcher<S, L>
@@ -160,31 +389,80 @@ where
         lambda_model: Option<LambdaMART>,
         bangs: Bangs,
         config: ApiConfig,
-    ) -> Self {
+    ) -> Result<Self> {
         let dist_searcher = Arc::new(dist_searcher);
+        let planner = config
+            .query_planner
+            .clone()
+            .map(|planner| QueryPlanner::new(planner, config.thresholds.min_head_recall));
         let sidebar_manager =
             SidebarManager::new(Arc::clone(&dist_searcher), config.thresholds.clone());
 
         let lambda_model = lambda_model.map(Arc::new);
 
-        let widget_manager = WidgetManager::new(Widgets::new(config.widgets).unwrap());
+        let widget_manager = WidgetManager::new(Widgets::new(config.widgets)?);
+
+        let ranking_pipelines = RankingPipelines::new(
+            &config.ranking,
+            lambda_model.is_some(),
+            cross_encoder.is_some(),
+        )?;
+
+        let spell_checker = config
+            .spell_checker_path
+            .map(|path| SpellChecker::open(path, config.correction_config))
+            .transpose()?;
 
-        Self {
+        Ok(Self {
             distributed_searcher: dist_searcher,
             sidebar_manager,
             live_searcher,
             cross_encoder: cross_encoder.map(Arc::new),
             lambda_model,
+            ranking_pipelines,
             bangs,
             collector_config: config.collector,
             widget_manager,
-            spell_checker: config
-                .spell_checker_path
-                .map(|c| SpellChecker::open(c, config.correction_config).unwrap()),
-        }
+            spell_checker,
+            product_store: config.product_store_path.map(ProductStore::open_read_only),
+            geo_store: config.geo_store_path.map(GeoStore::open_read_only),
+            blocklist: None,
+            homographs: None,
+            liveness: None,
+            host_policy: None,
+            federation: config.federation,
+            planner,
+            model_runner: None,
+        })
+    }
+
+    pub fn set_blocklist(&mut self, blocklist: Arc<LiveBlocklist>) {
+        self.blocklist = Some(blocklist);
+    }
+
+    pub fn set_homograph_detector(&mut self, detector: HomographDetector) {
+        self.homographs = Some(detector);
+    }
+
+    pub fn set_liveness(&mut self, liveness: Arc<LivenessStore>) {
+        self.liveness = Some(liveness);
+    }
+
+    pub fn set_host_policy(&mut self, host_policy: Arc<HostPolicyStore>) {
+        self.host_policy = Some(host_policy);
+    }
+
+    /// Run the reranking models on the pool instead of the global rayon pool.
+    pub fn set_model_runner(&mut self, pool: rayon::ThreadPool) {
+        self.model_runner = Some(pool);
     }
 
     async fn check_bangs(&self, query: &SearchQuery) -> Result<Option<BangHit>> {
+        // structured queries can't contain bangs
+        if query.terms.is_some() {
+            return Ok(None);
+        }
+
         let parsed_terms = query::parser::parse(&query.query);
 
         if parsed_terms.iter().any(|term| match term.as_ref() {
@@ -222,15 +500,24 @@ where
         Ok(self.bangs.get(&parsed_terms))
     }
 
-    pub async fn widget(&self, query: &str) -> Option<Widget> {
-        self.widget_manager.widget(query).await
+    pub async fn widget(&self, query: &str, locale: Locale) -> Option<Widget> {
+        self.widget_manager.widget(query, locale).await
     }
 
     pub async fn sidebar(&self, query: &str) -> Option<DisplayedSidebar> {
         self.sidebar_manager.sidebar(query).await
     }
 
-    pub fn spell_check(&self, query: &str) -> Option<HighlightedSpellCorrection> {
+    /// Places for the map panel if the query has a local intent like `pizza near nørrebro`.
+    pub fn places(&self, query: &str) -> Option<PlacesResult> {
+        self.geo_store
+            .as_ref()?
+            .search(query, PLACES_RADIUS_KM, NUM_PLACES)
+    }
+
+    /// Spelling correction with the spell checker for the language of the locale.
+    /// Queries in languages without a spell checker are never corrected.
+    pub fn spell_check(&self, query: &str, locale: Locale) -> Option<HighlightedSpellCorrection> {
         let query = query.to_lowercase();
 
         let terms = query::parser::parse(&query);
@@ -247,7 +534,7 @@ where
         let corrections = self
             .spell_checker
             .as_ref()
-            .and_then(|s| s.correct(&simple_query, &whatlang::Lang::Eng))?;
+            .and_then(|s| s.correct(&simple_query, &locale.lang()))?;
 
         let correction_map: HashMap<String, String> = corrections
             .terms
@@ -287,20 +574,51 @@ where
         Some(HighlightedSpellCorrection::from(correction))
     }
 
+    /// The query with the terms that were typed without spaces segmented into words,
+    /// if the segmentation is confident enough to search for it instead of the query.
+    pub fn segment_query(&self, query: &str, locale: Locale) -> Option<String> {
+        let spell_checker = self.spell_checker.as_ref()?;
+        let lang = locale.lang();
+
+        let mut segmented = false;
+        let terms: Vec<String> = query::parser::parse(query)
+            .into_iter()
+            .map(|term| match *term {
+                query::parser::Term::Simple(t) => {
+                    let t = String::from(t);
+
+                    match spell_checker.auto_segment(&t, &lang) {
+                        Some(segmentation) => {
+                            segmented = true;
+                            segmentation
+                        }
+                        None => t,
+                    }
+                }
+                term => term.to_string(),
+            })
+            .collect();
+
+        if segmented {
+            Some(terms.join(" "))
+        } else {
+            None
+        }
+    }
+
     async fn retrieve_webpages(
         &self,
-        query: &str,
-        top_websites: &[ScoredWebsitePointer],
+        query: &SearchQuery,
+        top_websites: &mut Vec<ScoredWebsitePointer>,
     ) -> Vec<RetrievedWebpageRanking> {
         let normal: Vec<_> = top_websites
             .iter()
             .enumerate()
-            .filter_map(|(i, pointer)| {
-                if let ScoredWebsitePointer::Normal(p) = pointer {
+            .filter_map(|(i, pointer)| match pointer {
+                ScoredWebsitePointer::Normal(p) | ScoredWebsitePointer::Vertical(_, p) => {
                     Some((i, p.clone()))
-                } else {
-                    None
                 }
+                ScoredWebsitePointer::Live(_) => None,
             })
             .collect();
 
@@ -317,37 +635,140 @@ where
             .collect();
 
         let (retrieved_normal, retrieved_live) = tokio::join!(
-            self.distributed_searcher.retrieve_webpages(&normal, query),
-            self.retrieve_webpages_from_live(&live, query),
+            self.distributed_searcher
+                .retrieve_webpages(&normal, &query.query, &query.snippet),
+            self.retrieve_webpages_from_live(&live, &query.query, &query.snippet),
         );
 
         let mut retrieved_webpages: Vec<_> =
             retrieved_normal.into_iter().chain(retrieved_live).collect();
         retrieved_webpages.sort_by(|(a, _), (b, _)| a.cmp(b));
 
-        retrieved_webpages
+        let mut retrieved_webpages: Vec<_> = retrieved_webpages
             .into_iter()
             .map(|(_, webpage)| webpage)
-            .collect::<Vec<_>>()
+            .collect();
+
+        if let Some(blocklist) = self.blocklist.as_ref() {
+            filter_blocklisted(
+                blocklist,
+                &mut retrieved_webpages,
+                top_websites,
+                |webpage| &webpage.retrieved_webpage().url,
+            );
+        }
+
+        retrieved_webpages
     }
 
-    async fn search_initial_from_live(
+    /// Search the web index with the strategy that the query planner chooses.
+    /// The shards are searched in stages if there is no planner.
+    async fn search_initial_planned(
         &self,
         query: &SearchQuery,
-    ) -> Option<Vec<live::InitialSearchResultSplit>> {
-        match &self.live_searcher {
-            Some(searcher) => Some(searcher.search_initial(query).await),
-            None => None,
-        }
+    ) -> (distributed::InitialSearchResults, Option<QueryPlan>) {
+        let Some(planner) = self.planner.as_ref() else {
+            return (self.distributed_searcher.search_initial(query).await, None);
+        };
+
+        let plan = planner.plan(query);
+        let mut query = query.clone();
+        plan.apply(&mut query);
+
+        let results = match plan.strategy {
+            RetrievalStrategy::HeadOnly => {
+                let results = self.distributed_searcher.search_initial_head(&query).await;
+                planner.record(&query, &plan, &results);
+
+                if planner.is_head_sufficient(&query, &results) {
+                    results
+                } else {
+                    // the first shards usually have enough results for the query, but not this time
+                    self.distributed_searcher.search_initial(&query).await
+                }
+            }
+            RetrievalStrategy::Staged => {
+                let results = self.distributed_searcher.search_initial(&query).await;
+                planner.record(&query, &plan, &results);
+                results
+            }
+            RetrievalStrategy::AllShards => {
+                self.distributed_searcher
+                    .search_initial_all_shards(&query)
+                    .await
+            }
+        };
+
+        (results, Some(plan))
+    }
+
+    /// Search the live index for the query and the federated verticals in one batch.
+    async fn search_initial_from_live(&self, query: &SearchQuery) -> Option<LiveResults> {
+        let searcher = self.live_searcher.as_ref()?;
+
+        let verticals: Vec<_> = self
+            .federated_verticals(query)
+            .map(|config| config.vertical)
+            .collect();
+        let queries: Vec<_> = std::iter::once(query.clone())
+            .chain(verticals.iter().map(|vertical| SearchQuery {
+                vertical: Some(*vertical),
+                ..query.clone()
+            }))
+            .collect();
+
+        let mut results = searcher.search_initial(&queries).await.into_iter();
+
+        Some(LiveResults {
+            web: results.next().unwrap_or_default(),
+            verticals: verticals.into_iter().zip(results).collect(),
+        })
+    }
+
+    fn federated_verticals<'a>(
+        &'a self,
+        query: &SearchQuery,
+    ) -> impl Iterator<Item = &'a FederatedVerticalConfig> {
+        let verticals = if query.vertical.is_some() {
+            &[][..]
+        } else {
+            self.federation
+                .as_ref()
+                .map_or(&[][..], |federation| &federation.verticals[..])
+        };
+
+        verticals.iter()
+    }
+
+    /// Search the federated verticals together with the web index. A query for a vertical
+    /// only searches the vertical itself.
+    async fn search_initial_from_verticals(
+        &self,
+        query: &SearchQuery,
+    ) -> Vec<(Vertical, distributed::InitialSearchResults)> {
+        let searches = self.federated_verticals(query).map(|config| async move {
+            let query = SearchQuery {
+                vertical: Some(config.vertical),
+                ..query.clone()
+            };
+
+            (
+                config.vertical,
+                self.distributed_searcher.search_initial(&query).await,
+            )
+        });
+
+        futures::future::join_all(searches).await
     }
 
     async fn retrieve_webpages_from_live(
         &self,
         pointers: &[(usize, live::ScoredWebsitePointer)],
         query: &str,
+        snippet: &SnippetOptions,
     ) -> Vec<(usize, RetrievedWebpageRanking)> {
         match &self.live_searcher {
-            Some(searcher) => searcher.retrieve_webpages(pointers, query).await,
+            Some(searcher) => searcher.retrieve_webpages(pointers, query, snippet).await,
             None => vec![],
         }
     }
@@ -359,37 +780,84 @@ where
             return Err(distributed::Error::EmptyQuery.into());
         }
 
+        let pipeline = self
+            .ranking_pipelines
+            .get(query.ranking_variant.as_deref())?;
+
         let mut search_query = query.clone();
         let top_n = search_query.num_results;
 
+        // the search servers use the coefficients of the recall stage
+        // when they rank their candidates
+        if !pipeline.recall.coefficients().is_empty() {
+            search_query.signal_coefficients = Some(pipeline.recall.coefficients().clone());
+        }
+        search_query.bm25f = pipeline.bm25f.clone();
+
         // This pipeline should be created before the first search is performed
         // so the query knows how many results to fetch from the indices
-        let recall_pipeline: RankingPipeline<ScoredWebsitePointer> = RankingPipeline::recall_stage(
-            &mut search_query,
-            self.lambda_model.clone(),
-            self.collector_config.clone(),
-            top_n,
-        );
+        let recall_pipeline: RankingPipeline<ScoredWebsitePointer> =
+            RankingPipeline::recall_stage_with_config(
+                &mut search_query,
+                &pipeline.recall,
+                self.lambda_model.clone(),
+                self.collector_config.clone(),
+                top_n,
+            );
 
-        let (initial_results, live_results) = tokio::join!(
-            self.distributed_searcher.search_initial(&search_query),
+        let ((initial_results, plan), live_results, vertical_results) = tokio::join!(
+            self.search_initial_planned(&search_query),
             self.search_initial_from_live(&search_query),
+            self.search_initial_from_verticals(&search_query),
         );
 
         let num_docs = initial_results
+            .shards
             .iter()
             .map(|result| result.local_result.num_websites)
             .sum();
 
-        let (top_websites, has_more_results) = combine_results(
+        let live_results = live_results.unwrap_or_default();
+
+        let cost = initial_results.cost()
+            + live_results
+                .web
+                .iter()
+                .chain(
+                    live_results
+                        .verticals
+                        .iter()
+                        .flat_map(|(_, results)| results),
+                )
+                .map(|result| result.local_result.cost)
+                .sum::<SearchCost>()
+            + vertical_results
+                .iter()
+                .map(|(_, results)| results.cost())
+                .sum::<SearchCost>();
+
+        let is_partial = initial_results.is_partial()
+            || vertical_results
+                .iter()
+                .any(|(_, results)| results.is_partial());
+
+        let (mut top_websites, has_more_results) = combine_results(
             self.collector_config.clone(),
-            initial_results,
-            live_results.unwrap_or_default(),
+            self.federation.as_ref(),
+            FederatedResults {
+                web: initial_results.shards,
+                live: live_results.web,
+                verticals: vertical_results
+                    .into_iter()
+                    .map(|(vertical, results)| (vertical, results.shards))
+                    .collect(),
+                live_verticals: live_results.verticals,
+            },
             recall_pipeline,
         );
 
         let retrieved_webpages = self
-            .retrieve_webpages(&search_query.query, &top_websites)
+            .retrieve_webpages(&search_query, &mut top_websites)
             .await;
 
         let mut search_query = SearchQuery {
@@ -398,15 +866,19 @@ where
         };
 
         let reranking_pipeline: RankingPipeline<RetrievedWebpageRanking> =
-            RankingPipeline::reranker(
+            RankingPipeline::reranker_with_config(
                 &mut search_query,
+                &pipeline.rerank,
                 self.cross_encoder.clone(),
                 self.lambda_model.clone(),
                 self.collector_config.clone(),
                 query.num_results,
             )?;
 
-        let retrieved_webpages = reranking_pipeline.apply(retrieved_webpages);
+        let retrieved_webpages = match &self.model_runner {
+            Some(pool) => pool.install(|| reranking_pipeline.apply(retrieved_webpages)),
+            None => reranking_pipeline.apply(retrieved_webpages),
+        };
 
         let mut retrieved_webpages: Vec<_> = retrieved_webpages
             .into_iter()
@@ -418,24 +890,145 @@ where
             return Err(distributed::Error::SearchFailed.into());
         }
 
+        if let Some(blocklist) = self.blocklist.as_ref() {
+            apply_blocklist(blocklist, &mut retrieved_webpages);
+        }
+
+        if let Some(detector) = self.homographs.as_ref() {
+            apply_homographs(detector, &mut retrieved_webpages, &mut top_websites);
+        }
+
+        if let Some(store) = self.liveness.as_ref() {
+            apply_liveness(store, &mut retrieved_webpages);
+        }
+
+        if let Some(store) = self.host_policy.as_ref() {
+            apply_host_policy(store, &mut retrieved_webpages);
+        }
+
         if query.return_ranking_signals {
             add_ranking_signals(&mut retrieved_webpages, &top_websites);
         }
 
         for (website, pointer) in retrieved_webpages.iter_mut().zip(top_websites.iter()) {
             website.score = Some(pointer.score());
+            website.source = pointer.source();
+        }
+
+        if let Some(product_store) = &self.product_store {
+            for website in retrieved_webpages
+                .iter_mut()
+                .filter(|website| website.product.is_some())
+            {
+                website.price_drop = product_store.price_drop(&website.url);
+            }
         }
 
         let search_duration_ms = start.elapsed().as_millis();
 
+        let topic_facets = topic_facets(&retrieved_webpages);
+        let author_facets = author_facets(&retrieved_webpages);
+
         Ok(WebsitesResult {
             num_hits: num_docs,
             webpages: retrieved_webpages,
             search_duration_ms,
             has_more_results,
+            is_partial,
+            topic_facets,
+            author_facets,
+            plan: plan.filter(|_| query.return_ranking_signals),
+            session: None,
+            cost,
+            segmented_query: None,
         })
     }
 
+    /// Search for results while the user is typing. Only the first shards for the query
+    /// are searched and the results are not reranked, so the results can be returned
+    /// quickly for every keystroke.
+    pub async fn instant(&self, query: &SearchQuery) -> Result<Vec<InstantWebpage>> {
+        if query.is_empty() {
+            return Err(distributed::Error::EmptyQuery.into());
+        }
+
+        let pipeline = self
+            .ranking_pipelines
+            .get(query.ranking_variant.as_deref())?;
+
+        let mut search_query = query.clone();
+        let top_n = search_query.num_results;
+
+        if !pipeline.recall.coefficients().is_empty() {
+            search_query.signal_coefficients = Some(pipeline.recall.coefficients().clone());
+        }
+        search_query.bm25f = pipeline.bm25f.clone();
+
+        let recall_pipeline: RankingPipeline<ScoredWebsitePointer> =
+            RankingPipeline::recall_stage_with_config(
+                &mut search_query,
+                &pipeline.recall,
+                self.lambda_model.clone(),
+                self.collector_config.clone(),
+                top_n,
+            );
+
+        let initial_results = self
+            .distributed_searcher
+            .search_initial_head(&search_query)
+            .await;
+
+        let (mut top_websites, _) = combine_results(
+            self.collector_config.clone(),
+            self.federation.as_ref(),
+            FederatedResults {
+                web: initial_results.shards,
+                ..Default::default()
+            },
+            recall_pipeline,
+        );
+
+        Ok(self
+            .retrieve_webpages(&search_query, &mut top_websites)
+            .await
+            .into_iter()
+            .map(|webpage| {
+                let webpage = webpage.into_retrieved_webpage();
+                let site = Url::parse(&webpage.url)
+                    .ok()
+                    .and_then(|url| url.normalized_host().map(|host| host.to_string()))
+                    .unwrap_or_default();
+
+                InstantWebpage {
+                    title: webpage.title,
+                    url: webpage.url,
+                    site,
+                }
+            })
+            .collect())
+    }
+
+    /// Search a vertical. Only pages of the vertical are returned and they are ranked with
+    /// the ranking pipeline variant named after the vertical (e.g. `videos`), unless the
+    /// query selects another variant. The snippets are tuned for the vertical.
+    pub async fn vertical(
+        &self,
+        query: &SearchQuery,
+        vertical: Vertical,
+    ) -> Result<WebsitesResult> {
+        let query = SearchQuery {
+            vertical: Some(vertical),
+            ranking_variant: query
+                .ranking_variant
+                .clone()
+                .or_else(|| Some(vertical.name().to_string())),
+            snippet: vertical.snippet_options(),
+            ..query.clone()
+        };
+
+        self.search_websites(&query).await
+    }
+
     pub async fn search(&self, query: &SearchQuery) -> Result<SearchResult> {
         if let Some(bang) = self.check_bangs(query).await? {
             return Ok(SearchResult::Bang(Box::new(bang)));
@@ -448,6 +1041,25 @@ where
         self.distributed_searcher.get_webpage(url).await
     }
 
+    /// Look up the status of a batch of urls. The statuses are returned in the
+    /// same order as the urls.
+    pub async fn url_statuses(&self, urls: &[String]) -> Result<Vec<UrlStatus>> {
+        let index_statuses = self.distributed_searcher.url_statuses(urls).await?;
+
+        Ok(urls
+            .iter()
+            .zip_eq(index_statuses)
+            .map(|(url, index_status)| {
+                url_status(
+                    url,
+                    index_status,
+                    self.liveness.as_deref(),
+                    self.blocklist.as_deref(),
+                )
+            })
+            .collect())
+    }
+
     pub async fn get_entity_image(
         &self,
         image_id: &str,
@@ -459,3 +1071,265 @@ where
             .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        blocklist::{expression_hash, ThreatType},
+        config::{BlocklistConfig, BlocklistListConfig, HomographConfig, HostPolicyConfig},
+        crawler::reputation::HostOutcome,
+        host_policy::HostDirectives,
+        liveness::UrlOutcome,
+        snippet::TextSnippetFragment,
+    };
+
+    use super::*;
+
+    fn webpage(url: &str) -> DisplayedWebpage {
+        DisplayedWebpage::from(RetrievedWebpage {
+            url: url.to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn blocklist(action: BlocklistAction) -> LiveBlocklist {
+        let path = crate::gen_temp_path();
+        std::fs::create_dir_all(&path).unwrap();
+        let list_path = path.join("malware.txt");
+        std::fs::write(&list_path, expression_hash("evil.com/")).unwrap();
+
+        LiveBlocklist::open(BlocklistConfig {
+            lists: vec![BlocklistListConfig {
+                path: list_path.to_str().unwrap().to_string(),
+                threat: ThreatType::Malware,
+            }],
+            action,
+            refresh_interval_sec: 60,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn blocklist_actions() {
+        let urls = [
+            "https://a.com/",
+            "https://www.evil.com/page",
+            "https://b.com/",
+        ];
+
+        let mut websites: Vec<_> = urls.iter().map(|url| webpage(url)).collect();
+        apply_blocklist(&blocklist(BlocklistAction::Flag), &mut websites);
+
+        assert_eq!(websites.len(), 3);
+        assert_eq!(websites[1].threat, Some(ThreatType::Malware));
+        assert_eq!(websites[0].threat, None);
+
+        // flagged pages are kept when they are retrieved
+        let mut websites: Vec<_> = urls.iter().map(|url| webpage(url)).collect();
+        let mut pointers = vec![0, 1, 2];
+        filter_blocklisted(
+            &blocklist(BlocklistAction::Flag),
+            &mut websites,
+            &mut pointers,
+            |w| &w.url,
+        );
+        assert_eq!(websites.len(), 3);
+
+        filter_blocklisted(
+            &blocklist(BlocklistAction::Filter),
+            &mut websites,
+            &mut pointers,
+            |w| &w.url,
+        );
+
+        assert_eq!(
+            websites.iter().map(|w| w.url.as_str()).collect::<Vec<_>>(),
+            vec!["https://a.com/", "https://b.com/"]
+        );
+        assert_eq!(pointers, vec![0, 2]);
+    }
+
+    #[test]
+    fn homograph_actions() {
+        let urls = [
+            "https://xn--pple-43d.com/login",
+            "https://apple.com/",
+            "https://b.com/",
+        ];
+
+        let flag = HomographDetector::default();
+        let mut websites: Vec<_> = urls.iter().map(|url| webpage(url)).collect();
+        let mut pointers = vec![0, 1, 2];
+        apply_homographs(&flag, &mut websites, &mut pointers);
+
+        assert_eq!(websites[0].resembles_host.as_deref(), Some("apple.com"));
+        assert_eq!(websites[1].resembles_host, None);
+        assert_eq!(pointers, vec![0, 1, 2]);
+
+        let demote = HomographDetector::open(&HomographConfig {
+            popular_hosts_path: None,
+            action: HomographAction::Demote,
+        })
+        .unwrap();
+        let mut websites: Vec<_> = urls.iter().map(|url| webpage(url)).collect();
+        let mut pointers = vec![0, 1, 2];
+        apply_homographs(&demote, &mut websites, &mut pointers);
+
+        assert_eq!(
+            websites.iter().map(|w| w.url.as_str()).collect::<Vec<_>>(),
+            vec![
+                "https://apple.com/",
+                "https://b.com/",
+                "https://xn--pple-43d.com/login"
+            ]
+        );
+        assert_eq!(pointers, vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn liveness_fallback() {
+        let store = LivenessStore::open(crate::gen_temp_path());
+        store.record([
+            UrlOutcome {
+                url: "https://dead.com/".to_string(),
+                timestamp: 1_000,
+                status_code: Some(404),
+                ok: false,
+            },
+            UrlOutcome {
+                url: "https://alive.com/".to_string(),
+                timestamp: 1_000,
+                status_code: Some(200),
+                ok: true,
+            },
+        ]);
+
+        let mut websites = vec![
+            webpage("https://dead.com/"),
+            webpage("https://alive.com/"),
+            webpage("https://unknown.com/"),
+        ];
+        apply_liveness(&store, &mut websites);
+
+        assert!(websites[0].may_be_unavailable);
+        assert_eq!(
+            websites[0].archive_url.as_deref(),
+            Some("https://web.archive.org/web/https://dead.com/")
+        );
+        assert!(!websites[1].may_be_unavailable);
+        assert_eq!(websites[1].archive_url, None);
+        assert!(!websites[2].may_be_unavailable);
+    }
+
+    #[test]
+    fn url_statuses() {
+        let store = LivenessStore::open(crate::gen_temp_path());
+        store.record([UrlOutcome {
+            url: "https://a.com/page".to_string(),
+            timestamp: 1_000,
+            status_code: Some(200),
+            ok: true,
+        }]);
+        let blocklist = blocklist(BlocklistAction::Filter);
+
+        let status = url_status(
+            "https://a.com/page?utm_source=feed",
+            UrlIndexStatus::Indexed {
+                url: "https://a.com/page".to_string(),
+            },
+            Some(&store),
+            Some(&blocklist),
+        );
+        assert!(status.indexed);
+        assert_eq!(status.canonical_url.as_deref(), Some("https://a.com/page"));
+        assert_eq!(status.last_crawl, Some(1_000));
+        assert_eq!(status.last_status_code, Some(200));
+        assert_eq!(status.last_success, Some(1_000));
+        assert_eq!(status.excluded, None);
+
+        let status = url_status(
+            "https://evil.com/",
+            UrlIndexStatus::Indexed {
+                url: "https://evil.com/".to_string(),
+            },
+            Some(&store),
+            Some(&blocklist),
+        );
+        assert!(status.indexed);
+        assert_eq!(status.canonical_url, None);
+        assert_eq!(status.last_crawl, None);
+        assert_eq!(status.threat, Some(ThreatType::Malware));
+        assert_eq!(
+            status.excluded,
+            Some(UrlExclusion::Blocklisted {
+                threat: ThreatType::Malware
+            })
+        );
+
+        let status = url_status(
+            "https://evil.com/",
+            UrlIndexStatus::Indexed {
+                url: "https://evil.com/".to_string(),
+            },
+            None,
+            Some(&blocklist(BlocklistAction::Flag)),
+        );
+        assert_eq!(status.threat, Some(ThreatType::Malware));
+        assert_eq!(status.excluded, None);
+
+        let status = url_status("https://b.com/", UrlIndexStatus::Deleted, None, None);
+        assert!(!status.indexed);
+        assert_eq!(status.excluded, Some(UrlExclusion::Deleted));
+
+        let status = url_status("not a url", UrlIndexStatus::NotIndexed, None, None);
+        assert_eq!(status.excluded, Some(UrlExclusion::InvalidUrl));
+    }
+
+    #[test]
+    fn host_policy() {
+        let store = HostPolicyStore::open(&HostPolicyConfig {
+            store_path: crate::gen_temp_path().to_str().unwrap().to_string(),
+            min_directive_fraction: 0.5,
+        });
+        store.record([HostOutcome {
+            host: "private.com".to_string(),
+            timestamp: 1,
+            directives: HostDirectives {
+                pages: 2,
+                noarchive: 2,
+                nosnippet: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        }]);
+
+        let mut websites = vec![
+            webpage("https://www.private.com/"),
+            webpage("https://b.com/"),
+        ];
+        for website in websites.iter_mut() {
+            website.snippet = Snippet::Normal {
+                date: None,
+                text: TextSnippet {
+                    fragments: vec![TextSnippetFragment::new_unhighlighted(
+                        "some text".to_string(),
+                    )],
+                },
+            };
+            website.archive_url = Some(format!("https://web.archive.org/web/{}", website.url));
+        }
+
+        apply_host_policy(&store, &mut websites);
+
+        assert_eq!(websites[0].archive_url, None);
+        assert_eq!(
+            websites[0].snippet.text().unwrap().unhighlighted_string(),
+            ""
+        );
+        assert!(websites[1].archive_url.is_some());
+        assert_eq!(
+            websites[1].snippet.text().unwrap().unhighlighted_string(),
+            "some text"
+        );
+    }
+}
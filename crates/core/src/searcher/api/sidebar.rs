@@ -53,6 +53,7 @@ where
             .distributed_searcher
             .search_initial(&query)
             .await
+            .shards
             .into_iter()
             .filter_map(|result| {
                 result
@@ -73,7 +74,7 @@ where
                     vec![(0, distributed::ScoredWebsitePointer { website, shard })];
                 let mut retrieved = self
                     .distributed_searcher
-                    .retrieve_webpages(&scored_websites, &query.query)
+                    .retrieve_webpages(&scored_websites, &query.query, &query.snippet)
                     .await;
 
                 if let Some((_, res)) = retrieved.pop() {
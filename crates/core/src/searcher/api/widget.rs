@@ -16,6 +16,7 @@
 
 use itertools::Itertools;
 
+use crate::locale::Locale;
 use crate::query;
 use crate::widgets::{Widget, Widgets};
 
@@ -28,7 +29,7 @@ impl WidgetManager {
         Self { widgets }
     }
 
-    pub async fn widget(&self, query: &str) -> Option<Widget> {
+    pub async fn widget(&self, query: &str, locale: Locale) -> Option<Widget> {
         let parsed_terms = query::parser::parse(query);
 
         self.widgets.widget(
@@ -43,6 +44,7 @@ impl WidgetManager {
                 })
                 .join(" ")
                 .as_str(),
+            locale,
         )
     }
 }
@@ -0,0 +1,159 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Accounting of the resources a search uses on the search servers. Every shard
+//! reports the cost of its part of the search and the costs are summed at the
+//! coordinator, so they can be returned to the client and exported as metrics.
+
+use std::{
+    iter::Sum,
+    ops::{Add, AddAssign},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::metrics::Counter;
+
+/// Resources used by the search servers to answer a search.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchCost {
+    /// CPU time spent searching the shards, in microseconds.
+    pub cpu_time_us: u64,
+    /// Number of documents that were scored.
+    pub docs_scored: u64,
+    /// Number of bytes of postings read from the indexes.
+    pub bytes_read: u64,
+}
+
+impl SearchCost {
+    pub fn cpu_time(&self) -> Duration {
+        Duration::from_micros(self.cpu_time_us)
+    }
+}
+
+impl Add for SearchCost {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            cpu_time_us: self.cpu_time_us + rhs.cpu_time_us,
+            docs_scored: self.docs_scored + rhs.docs_scored,
+            bytes_read: self.bytes_read + rhs.bytes_read,
+        }
+    }
+}
+
+impl AddAssign for SearchCost {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sum for SearchCost {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
+
+/// Counters with the total cost of all searches.
+#[derive(Clone, Default)]
+pub struct CostCounters {
+    pub cpu_time_us: Counter,
+    pub docs_scored: Counter,
+    pub bytes_read: Counter,
+}
+
+impl CostCounters {
+    pub fn record(&self, cost: &SearchCost) {
+        self.cpu_time_us.add(cost.cpu_time_us);
+        self.docs_scored.add(cost.docs_scored);
+        self.bytes_read.add(cost.bytes_read);
+    }
+}
+
+/// CPU time used by the current thread. The searches run on a single thread,
+/// so the difference between two measurements is the CPU time of the search.
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadCpuTime(Duration);
+
+impl ThreadCpuTime {
+    pub fn now() -> Self {
+        let mut time = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        // SAFETY: `time` is a valid timespec, and the clock is supported on all
+        // the platforms we run on. The time is left at zero if the call fails.
+        unsafe {
+            libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time);
+        }
+
+        Self(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Self::now().0.saturating_sub(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn costs_are_summed() {
+        let costs = [
+            SearchCost {
+                cpu_time_us: 10,
+                docs_scored: 100,
+                bytes_read: 1_000,
+            },
+            SearchCost {
+                cpu_time_us: 5,
+                docs_scored: 50,
+                bytes_read: 500,
+            },
+        ];
+
+        let total: SearchCost = costs.into_iter().sum();
+
+        assert_eq!(
+            total,
+            SearchCost {
+                cpu_time_us: 15,
+                docs_scored: 150,
+                bytes_read: 1_500,
+            }
+        );
+        assert_eq!(total.cpu_time(), Duration::from_micros(15));
+    }
+
+    #[test]
+    fn thread_cpu_time_increases_with_work() {
+        let start = ThreadCpuTime::now();
+
+        let mut x = 0u64;
+        while start.elapsed() < Duration::from_millis(1) {
+            x = std::hint::black_box(x.wrapping_add(1));
+        }
+
+        assert!(x > 0);
+    }
+}
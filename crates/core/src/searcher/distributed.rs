@@ -15,12 +15,19 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::{
+    collection_stats::CollectionStats,
+    config::{defaults, CollectionStatsConfig, HedgingConfig, QuorumConfig, ShardTier},
     distributed::{
         cluster::Cluster,
         member::Service,
-        sonic::replication::{
-            AllShardsSelector, RandomReplicaSelector, RemoteClient, ReplicatedClient, Shard,
-            ShardIdentifier, ShardedClient, SpecificShardSelector,
+        sonic::{
+            self,
+            replication::{
+                AllReplicaSelector, AllShardsSelector, HashShardSelector, HedgedReplicaSelector,
+                LatencyAwareReplicaSelector, LiveShardedClient, QuorumReplicaSelector,
+                RemoteClient, ReplicatedClient, ShardFailure, ShardIdentifier, ShardSelector,
+                ShardedClient, SpecificShardSelector, SpecificShardsSelector,
+            },
         },
     },
     entity_index::EntityMatch,
@@ -29,12 +36,17 @@ use crate::{
         search_server::{self, SearchService},
     },
     image_store::Image,
-    inverted_index::{RetrievedWebpage, WebsitePointer},
+    inverted_index::{RetrievedWebpage, UrlIndexStatus, WebsitePointer},
     ranking::pipeline::{RankingWebsite, RetrievedWebpageRanking},
+    shard_router::ShardRouter,
+    snippet::SnippetOptions,
     Result,
 };
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 
 use fnv::FnvHashMap;
 use futures::future::join_all;
@@ -44,7 +56,7 @@ use std::future::Future;
 use thiserror::Error;
 use url::Url;
 
-use super::{InitialWebsiteResult, SearchQuery};
+use super::{cost::SearchCost, InitialWebsiteResult, SearchQuery};
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -56,6 +68,9 @@ pub enum Error {
 
     #[error("Webpage not found")]
     WebpageNotFound,
+
+    #[error("Unknown ranking variant: {0}")]
+    UnknownRankingVariant(String),
 }
 
 #[derive(Clone, Debug)]
@@ -71,6 +86,10 @@ impl ShardId {
     pub fn new(id: u64) -> Self {
         Self(id)
     }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
 }
 
 impl ShardIdentifier for ShardId {}
@@ -81,33 +100,188 @@ pub struct InitialSearchResultShard {
     pub shard: ShardId,
 }
 
+/// The initial results from the shards that responded to the search, together
+/// with the shards that failed.
+#[derive(Debug, Default)]
+pub struct InitialSearchResults {
+    pub shards: Vec<InitialSearchResultShard>,
+    pub failures: Vec<ShardFailure<ShardId>>,
+    /// Number of stages of shards that were searched one after the other.
+    /// Zero if all shards were searched at once.
+    pub num_stages: usize,
+}
+
+impl InitialSearchResults {
+    /// Whether some of the shards failed or ran out of time, so results might be missing.
+    pub fn is_partial(&self) -> bool {
+        !self.failures.is_empty() || self.num_truncated() > 0
+    }
+
+    /// Number of shards that ran out of time and returned the best websites found until then.
+    pub fn num_truncated(&self) -> usize {
+        self.shards
+            .iter()
+            .filter(|result| result.local_result.truncated)
+            .count()
+    }
+
+    /// Resources used by all the shards that responded.
+    pub fn cost(&self) -> SearchCost {
+        self.shards
+            .iter()
+            .map(|result| result.local_result.cost)
+            .sum()
+    }
+
+    pub fn num_websites(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|result| result.local_result.websites.len())
+            .sum()
+    }
+
+    fn extend(&mut self, other: Self) {
+        self.shards.extend(other.shards);
+        self.failures.extend(other.failures);
+    }
+}
+
 pub struct DistributedSearcher {
     cluster: Arc<Cluster>,
+    shards: LiveShardedClient<SearchService, ShardId>,
+    min_head_recall: f64,
+    router: Option<ShardRouter>,
+    hedging: Option<HedgedReplicaSelector>,
+    quorum: Option<QuorumReplicaSelector>,
 }
 
 impl DistributedSearcher {
     pub fn new(cluster: Arc<Cluster>) -> Self {
-        Self { cluster }
+        let shards = LiveShardedClient::new(&cluster, |member| match member.service {
+            Service::Searcher { host, shard, .. } => Some((shard, host)),
+            _ => None,
+        });
+
+        Self {
+            cluster,
+            shards,
+            min_head_recall: defaults::Api::min_head_recall(),
+            router: None,
+            hedging: None,
+            quorum: None,
+        }
     }
 
-    async fn client(&self) -> ShardedClient<SearchService, ShardId> {
-        let mut shards = HashMap::new();
-        for member in self.cluster.members().await {
-            if let Service::Searcher { host, shard } = member.service {
-                shards.entry(shard).or_insert_with(Vec::new).push(host);
+    /// Send the searches to a second replica of a shard if the first is slow to respond.
+    pub fn set_hedging(&mut self, config: &HedgingConfig) -> Result<()> {
+        self.hedging = Some(HedgedReplicaSelector::new(config)?);
+
+        Ok(())
+    }
+
+    /// Only use the url statuses that a quorum of the replicas of a shard agree on, so a
+    /// replica that lags behind on deletions is outvoted.
+    pub fn set_quorum(&mut self, config: &QuorumConfig) -> Result<()> {
+        self.quorum = Some(QuorumReplicaSelector::new(config)?);
+
+        Ok(())
+    }
+
+    /// Look up webpages by url on the shard that owns the url before the other shards.
+    pub fn set_router(&mut self, router: ShardRouter) {
+        self.router = Some(router);
+    }
+
+    /// Search the archive shards when the head shards return fewer than this
+    /// fraction of the results needed for the requested page.
+    pub fn set_min_head_recall(&mut self, min_head_recall: f64) {
+        self.min_head_recall = min_head_recall;
+    }
+
+    /// Merge the collection statistics of all the shards and send them to every
+    /// replica, so the BM25 scores of the shards are comparable. The statistics are
+    /// not updated if some of the shards fail to respond.
+    pub async fn sync_collection_stats(&self, config: &CollectionStatsConfig) -> Result<()> {
+        let client = self.client();
+
+        let res = client
+            .send(
+                &search_server::GetCollectionStats {
+                    min_doc_freq: config.min_doc_freq,
+                    max_terms_per_field: config.max_terms_per_field,
+                },
+                &AllShardsSelector,
+                &LatencyAwareReplicaSelector,
+            )
+            .await?;
+
+        if res.is_partial() {
+            tracing::warn!(
+                "{} shards failed to report their collection statistics",
+                res.failures.len()
+            );
+            return Ok(());
+        }
+
+        let mut stats = CollectionStats::default();
+        let mut num_shards = 0;
+
+        for (shard, mut res) in res {
+            match res.pop().flatten() {
+                Some(shard_stats) => {
+                    stats.merge(&shard_stats);
+                    num_shards += 1;
+                }
+                None => {
+                    tracing::warn!("shard {} has no collection statistics", shard.as_u64());
+                    return Ok(());
+                }
             }
         }
 
-        let mut shard_clients = Vec::new();
+        if num_shards == 0 {
+            return Ok(());
+        }
+
+        client
+            .send(
+                &search_server::SetCollectionStats { stats },
+                &AllShardsSelector,
+                &AllReplicaSelector,
+            )
+            .await?;
+
+        tracing::info!("synced collection statistics of {} shards", num_shards);
+
+        Ok(())
+    }
+
+    fn client(&self) -> Arc<ShardedClient<SearchService, ShardId>> {
+        self.shards.client()
+    }
 
-        for (id, replicas) in shards {
-            let replicated =
-                ReplicatedClient::new(replicas.into_iter().map(RemoteClient::new).collect());
-            let shard = Shard::new(id, replicated);
-            shard_clients.push(shard);
+    /// The client for all shards, together with the tier and languages of each shard.
+    async fn client_with_shard_info(
+        &self,
+    ) -> (
+        Arc<ShardedClient<SearchService, ShardId>>,
+        HashMap<ShardId, ShardInfo>,
+    ) {
+        let mut infos = HashMap::new();
+
+        for member in self.cluster.members().await {
+            if let Service::Searcher {
+                shard,
+                tier,
+                languages,
+                ..
+            } = member.service
+            {
+                infos.entry(shard).or_insert(ShardInfo { tier, languages });
+            }
         }
 
-        ShardedClient::new(shard_clients)
+        (self.client(), infos)
     }
 
     async fn entity_client(&self) -> ReplicatedClient<entity_search_server::SearchService> {
@@ -121,11 +295,57 @@ impl DistributedSearcher {
         ReplicatedClient::new(replicas)
     }
 
+    async fn search_shards<S: ShardSelector<SearchService, ShardId>>(
+        &self,
+        client: &ShardedClient<SearchService, ShardId>,
+        query: &SearchQuery,
+        selector: &S,
+    ) -> InitialSearchResults {
+        let mut results = InitialSearchResults::default();
+
+        let req = search_server::Search {
+            query: query.clone(),
+        };
+
+        let res = match &self.hedging {
+            Some(hedging) => client.send_hedged(&req, selector, hedging).await,
+            None => {
+                client
+                    .send(&req, selector, &LatencyAwareReplicaSelector)
+                    .await
+            }
+        };
+
+        match res {
+            Ok(res) => {
+                results.failures = res.failures;
+
+                for (shard_id, mut res) in res.responses {
+                    match res.pop() {
+                        Some(Some(res)) => results.shards.push(InitialSearchResultShard {
+                            local_result: res,
+                            shard: shard_id,
+                        }),
+                        Some(None) => results.failures.push(ShardFailure {
+                            shard: shard_id,
+                            error: sonic::Error::BadRequest,
+                        }),
+                        None => {}
+                    }
+                }
+            }
+            Err(e) => tracing::error!("Failed to search shards: {:?}", e),
+        }
+
+        results
+    }
+
     async fn retrieve_webpages_from_shard(
         &self,
         shard: ShardId,
         client: &ShardedClient<SearchService, ShardId>,
         query: &str,
+        snippet: &SnippetOptions,
         pointers: Vec<(usize, WebsitePointer)>,
     ) -> Vec<(usize, RetrievedWebpage)> {
         let (idxs, pointers): (Vec<usize>, Vec<WebsitePointer>) = pointers.into_iter().unzip();
@@ -135,9 +355,10 @@ impl DistributedSearcher {
                 &search_server::RetrieveWebsites {
                     websites: pointers,
                     query: query.to_string(),
+                    snippet: snippet.clone(),
                 },
                 &SpecificShardSelector(shard),
-                &RandomReplicaSelector,
+                &LatencyAwareReplicaSelector,
             )
             .await
         {
@@ -155,37 +376,73 @@ impl DistributedSearcher {
 }
 
 impl SearchClient for DistributedSearcher {
-    async fn search_initial(&self, query: &SearchQuery) -> Vec<InitialSearchResultShard> {
-        let client = self.client().await;
-        let mut results = Vec::new();
+    async fn search_initial(&self, query: &SearchQuery) -> InitialSearchResults {
+        let (client, shards) = self.client_with_shard_info().await;
+        let stages = search_stages(&shards, query_language(query));
+
+        if stages.len() <= 1 {
+            let mut results = self.search_shards(&client, query, &AllShardsSelector).await;
+            results.num_stages = 1;
+            return results;
+        }
 
-        if let Ok(res) = client
-            .send(
-                &search_server::Search {
-                    query: query.clone(),
-                },
-                &AllShardsSelector,
-                &RandomReplicaSelector,
-            )
-            .await
-        {
-            for (shard_id, mut res) in res {
-                if let Some(Some(res)) = res.pop() {
-                    results.push(InitialSearchResultShard {
-                        local_result: res,
-                        shard: shard_id,
-                    });
-                }
+        let num_needed = (query.page + 1) * query.num_results;
+        let mut results = InitialSearchResults::default();
+
+        for stage in stages {
+            let num_results = results.num_websites();
+
+            if !results.shards.is_empty()
+                && (num_results as f64) >= self.min_head_recall * num_needed as f64
+            {
+                break;
             }
+
+            tracing::debug!(
+                "found {} of {} results, searching {} more shards",
+                num_results,
+                num_needed,
+                stage.len()
+            );
+
+            results.extend(
+                self.search_shards(&client, query, &SpecificShardsSelector(stage))
+                    .await,
+            );
+            results.num_stages += 1;
         }
 
         results
     }
 
+    async fn search_initial_head(&self, query: &SearchQuery) -> InitialSearchResults {
+        let (client, shards) = self.client_with_shard_info().await;
+
+        match search_stages(&shards, query_language(query))
+            .into_iter()
+            .next()
+        {
+            Some(stage) => {
+                let mut results = self
+                    .search_shards(&client, query, &SpecificShardsSelector(stage))
+                    .await;
+                results.num_stages = 1;
+                results
+            }
+            None => InitialSearchResults::default(),
+        }
+    }
+
+    async fn search_initial_all_shards(&self, query: &SearchQuery) -> InitialSearchResults {
+        let client = self.client();
+        self.search_shards(&client, query, &AllShardsSelector).await
+    }
+
     async fn retrieve_webpages(
         &self,
         top_websites: &[(usize, ScoredWebsitePointer)],
         query: &str,
+        snippet: &SnippetOptions,
     ) -> Vec<(usize, RetrievedWebpageRanking)> {
         let mut rankings = FnvHashMap::default();
         let mut pointers: HashMap<_, Vec<_>> = HashMap::new();
@@ -199,10 +456,11 @@ impl SearchClient for DistributedSearcher {
             rankings.insert(*i, pointer.website.clone());
         }
 
-        let client = self.client().await;
+        let client = self.client();
         let mut futures = Vec::new();
         for (shard, pointers) in pointers {
-            futures.push(self.retrieve_webpages_from_shard(shard, &client, query, pointers));
+            futures
+                .push(self.retrieve_webpages_from_shard(shard, &client, query, snippet, pointers));
         }
 
         let mut retrieved_webpages = Vec::new();
@@ -221,16 +479,30 @@ impl SearchClient for DistributedSearcher {
     }
 
     async fn get_webpage(&self, url: &str) -> Result<Option<RetrievedWebpage>> {
-        let client = self.client().await;
+        let client = self.client();
+        let req = search_server::GetWebpage {
+            url: url.to_string(),
+        };
+
+        // shards that are not partitioned by url, e.g. the tiers, might still have
+        // the page, so all shards are searched if the owner does not have it.
+        if let Some(router) = &self.router {
+            if let Ok(res) = client
+                .send(
+                    &req,
+                    &HashShardSelector::new(router, url),
+                    &LatencyAwareReplicaSelector,
+                )
+                .await
+            {
+                if let Some(res) = res.into_iter().flat_map(|(_, v)| v).flatten().next() {
+                    return Ok(Some(res));
+                }
+            }
+        }
 
         let res = client
-            .send(
-                &search_server::GetWebpage {
-                    url: url.to_string(),
-                },
-                &AllShardsSelector,
-                &RandomReplicaSelector,
-            )
+            .send(&req, &AllShardsSelector, &LatencyAwareReplicaSelector)
             .await
             .map_err(|_| Error::SearchFailed)?;
 
@@ -241,8 +513,49 @@ impl SearchClient for DistributedSearcher {
         }
     }
 
+    async fn url_statuses(&self, urls: &[String]) -> Result<Vec<UrlIndexStatus>> {
+        let client = self.client();
+
+        // the urls are looked up in all shards, since shards that are not
+        // partitioned by url might also have the pages.
+        let req = search_server::GetUrlStatuses {
+            urls: urls.to_vec(),
+        };
+
+        let res = match &self.quorum {
+            Some(quorum) => client.send_quorum(&req, &AllShardsSelector, quorum).await,
+            None => {
+                client
+                    .send(&req, &AllShardsSelector, &LatencyAwareReplicaSelector)
+                    .await
+            }
+        }
+        .map_err(|_| Error::SearchFailed)?;
+
+        let shard_statuses: Vec<_> = res
+            .into_iter()
+            .flat_map(|(_, v)| v)
+            .flatten()
+            .filter(|statuses| statuses.len() == urls.len())
+            .collect();
+
+        if shard_statuses.is_empty() {
+            return Err(Error::SearchFailed.into());
+        }
+
+        Ok((0..urls.len())
+            .map(|i| {
+                shard_statuses
+                    .iter()
+                    .map(|statuses| statuses[i].clone())
+                    .reduce(merge_url_status)
+                    .unwrap_or(UrlIndexStatus::NotIndexed)
+            })
+            .collect())
+    }
+
     async fn get_homepage_descriptions(&self, urls: &[Url]) -> HashMap<Url, String> {
-        let client = self.client().await;
+        let client = self.client();
 
         let res = client
             .send(
@@ -250,7 +563,7 @@ impl SearchClient for DistributedSearcher {
                     urls: urls.to_vec(),
                 },
                 &AllShardsSelector,
-                &RandomReplicaSelector,
+                &LatencyAwareReplicaSelector,
             )
             .await;
 
@@ -275,7 +588,7 @@ impl SearchClient for DistributedSearcher {
                     max_height,
                     max_width,
                 },
-                &RandomReplicaSelector,
+                &LatencyAwareReplicaSelector,
             )
             .await
             .map_err(|_| Error::SearchFailed)?
@@ -291,7 +604,7 @@ impl SearchClient for DistributedSearcher {
                 &entity_search_server::Search {
                     query: query.to_string(),
                 },
-                &RandomReplicaSelector,
+                &LatencyAwareReplicaSelector,
             )
             .await
             .ok()?
@@ -300,16 +613,85 @@ impl SearchClient for DistributedSearcher {
     }
 }
 
+/// The tier and languages of a search shard.
+#[derive(Debug, Clone, Default)]
+struct ShardInfo {
+    tier: ShardTier,
+    languages: Vec<whatlang::Lang>,
+}
+
+impl ShardInfo {
+    fn has_language(&self, lang: &whatlang::Lang) -> bool {
+        self.languages.is_empty() || self.languages.contains(lang)
+    }
+}
+
+/// The language of the query is the language of the selected region if any,
+/// and otherwise the language detected from the query text.
+fn query_language(query: &SearchQuery) -> Option<whatlang::Lang> {
+    query
+        .selected_region
+        .and_then(|region| region.lang())
+        .or_else(|| {
+            whatlang::detect(&query.query)
+                .filter(|info| info.is_reliable())
+                .map(|info| info.lang())
+        })
+}
+
+/// Groups of shards in the order they should be searched. The shards with the language
+/// of the query are searched before the other shards, and head shards are searched
+/// before archive shards. Empty groups are skipped.
+fn search_stages(
+    shards: &HashMap<ShardId, ShardInfo>,
+    lang: Option<whatlang::Lang>,
+) -> Vec<Vec<ShardId>> {
+    let mut stages: BTreeMap<(bool, bool), Vec<ShardId>> = BTreeMap::new();
+
+    for (shard, info) in shards {
+        let other_language = match &lang {
+            Some(lang) => !info.has_language(lang),
+            None => false,
+        };
+        let archive = info.tier == ShardTier::Archive;
+
+        stages
+            .entry((other_language, archive))
+            .or_default()
+            .push(*shard);
+    }
+
+    stages.into_values().collect()
+}
+
 pub trait SearchClient {
     fn search_initial(
         &self,
         query: &SearchQuery,
-    ) -> impl Future<Output = Vec<InitialSearchResultShard>> + Send;
+    ) -> impl Future<Output = InitialSearchResults> + Send;
+
+    /// Only search the shards that are searched first for the query, without
+    /// searching the rest of the shards if they have too few results.
+    fn search_initial_head(
+        &self,
+        query: &SearchQuery,
+    ) -> impl Future<Output = InitialSearchResults> + Send {
+        self.search_initial(query)
+    }
+
+    /// Search all shards at once instead of searching them in stages.
+    fn search_initial_all_shards(
+        &self,
+        query: &SearchQuery,
+    ) -> impl Future<Output = InitialSearchResults> + Send {
+        self.search_initial(query)
+    }
 
     fn retrieve_webpages(
         &self,
         top_websites: &[(usize, ScoredWebsitePointer)],
         query: &str,
+        snippet: &SnippetOptions,
     ) -> impl Future<Output = Vec<(usize, RetrievedWebpageRanking)>> + Send;
 
     fn search_entity(&self, query: &str) -> impl Future<Output = Option<EntityMatch>> + Send;
@@ -319,6 +701,11 @@ pub trait SearchClient {
         url: &str,
     ) -> impl Future<Output = Result<Option<RetrievedWebpage>>> + Send;
 
+    fn url_statuses(
+        &self,
+        urls: &[String],
+    ) -> impl Future<Output = Result<Vec<UrlIndexStatus>>> + Send;
+
     fn get_homepage_descriptions(
         &self,
         urls: &[Url],
@@ -331,3 +718,82 @@ pub trait SearchClient {
         max_width: Option<u64>,
     ) -> impl Future<Output = Result<Option<Image>>> + Send;
 }
+
+/// A page that is indexed in any shard is indexed, even if it has been deleted
+/// from another shard.
+fn merge_url_status(a: UrlIndexStatus, b: UrlIndexStatus) -> UrlIndexStatus {
+    match (a, b) {
+        (indexed @ UrlIndexStatus::Indexed { .. }, _)
+        | (_, indexed @ UrlIndexStatus::Indexed { .. }) => indexed,
+        (UrlIndexStatus::Deleted, _) | (_, UrlIndexStatus::Deleted) => UrlIndexStatus::Deleted,
+        (UrlIndexStatus::NotIndexed, UrlIndexStatus::NotIndexed) => UrlIndexStatus::NotIndexed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_statuses_are_merged_across_shards() {
+        let indexed = UrlIndexStatus::Indexed {
+            url: "https://example.com/".to_string(),
+        };
+
+        assert_eq!(
+            merge_url_status(UrlIndexStatus::Deleted, indexed.clone()),
+            indexed
+        );
+        assert_eq!(
+            merge_url_status(indexed.clone(), UrlIndexStatus::NotIndexed),
+            indexed
+        );
+        assert_eq!(
+            merge_url_status(UrlIndexStatus::NotIndexed, UrlIndexStatus::Deleted),
+            UrlIndexStatus::Deleted
+        );
+        assert_eq!(
+            merge_url_status(UrlIndexStatus::NotIndexed, UrlIndexStatus::NotIndexed),
+            UrlIndexStatus::NotIndexed
+        );
+    }
+
+    #[test]
+    fn language_stages_before_tiers() {
+        let shards: HashMap<_, _> = [
+            (0, ShardTier::Head, vec![whatlang::Lang::Eng]),
+            (1, ShardTier::Archive, vec![whatlang::Lang::Eng]),
+            (2, ShardTier::Head, vec![whatlang::Lang::Deu]),
+            (3, ShardTier::Head, vec![]),
+        ]
+        .into_iter()
+        .map(|(id, tier, languages)| (ShardId::new(id), ShardInfo { tier, languages }))
+        .collect();
+
+        let sorted = |stages: Vec<Vec<ShardId>>| {
+            stages
+                .into_iter()
+                .map(|stage| {
+                    stage
+                        .into_iter()
+                        .map(|shard| shard.0)
+                        .sorted()
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            sorted(search_stages(&shards, Some(whatlang::Lang::Eng))),
+            vec![vec![0, 3], vec![1], vec![2]]
+        );
+        assert_eq!(
+            sorted(search_stages(&shards, Some(whatlang::Lang::Fra))),
+            vec![vec![3], vec![0, 2], vec![1]]
+        );
+        assert_eq!(
+            sorted(search_stages(&shards, None)),
+            vec![vec![0, 2, 3], vec![1]]
+        );
+    }
+}
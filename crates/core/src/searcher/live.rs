@@ -19,14 +19,15 @@ use crate::{
         cluster::Cluster,
         member::Service,
         sonic::replication::{
-            AllShardsSelector, RandomReplicaSelector, RemoteClient, ReplicatedClient, Shard,
-            ShardIdentifier, ShardedClient, SpecificShardSelector,
+            AllShardsSelector, LatencyAwareReplicaSelector, LiveShardedClient, ShardIdentifier,
+            ShardedClient, SpecificShardSelector,
         },
     },
     entrypoint::search_server::{self, SearchService},
     feed::scheduler::SplitId,
     inverted_index::{RetrievedWebpage, WebsitePointer},
     ranking::pipeline::{RankingWebsite, RetrievedWebpageRanking},
+    snippet::SnippetOptions,
 };
 
 use std::future::Future;
@@ -53,32 +54,21 @@ pub struct InitialSearchResultSplit {
 }
 
 pub struct LiveSearcher {
-    cluster: Arc<Cluster>,
+    splits: LiveShardedClient<SearchService, SplitId>,
 }
 
 impl LiveSearcher {
     pub fn new(cluster: Arc<Cluster>) -> Self {
-        Self { cluster }
-    }
-
-    async fn client(&self) -> ShardedClient<SearchService, SplitId> {
-        let mut shards = HashMap::new();
-        for member in self.cluster.members().await {
-            if let Service::LiveIndex { host, split_id } = member.service {
-                shards.entry(split_id).or_insert_with(Vec::new).push(host);
-            }
-        }
-
-        let mut shard_clients = Vec::new();
+        let splits = LiveShardedClient::new(&cluster, |member| match &member.service {
+            Service::LiveIndex { host, split_id } => Some((split_id.clone(), *host)),
+            _ => None,
+        });
 
-        for (id, replicas) in shards {
-            let replicated =
-                ReplicatedClient::new(replicas.into_iter().map(RemoteClient::new).collect());
-            let shard = Shard::new(id, replicated);
-            shard_clients.push(shard);
-        }
+        Self { splits }
+    }
 
-        ShardedClient::new(shard_clients)
+    fn client(&self) -> Arc<ShardedClient<SearchService, SplitId>> {
+        self.splits.client()
     }
 
     async fn retrieve_webpages_from_shard(
@@ -86,6 +76,7 @@ impl LiveSearcher {
         split: SplitId,
         client: &ShardedClient<SearchService, SplitId>,
         query: &str,
+        snippet: &SnippetOptions,
         pointers: Vec<(usize, WebsitePointer)>,
     ) -> Vec<(usize, RetrievedWebpage)> {
         let (idxs, pointers): (Vec<usize>, Vec<WebsitePointer>) = pointers.into_iter().unzip();
@@ -95,9 +86,10 @@ impl LiveSearcher {
                 &search_server::RetrieveWebsites {
                     websites: pointers,
                     query: query.to_string(),
+                    snippet: snippet.clone(),
                 },
                 &SpecificShardSelector(split),
-                &RandomReplicaSelector,
+                &LatencyAwareReplicaSelector,
             )
             .await
         {
@@ -115,26 +107,34 @@ impl LiveSearcher {
 }
 
 impl SearchClient for LiveSearcher {
-    async fn search_initial(&self, query: &SearchQuery) -> Vec<InitialSearchResultSplit> {
-        let client = self.client().await;
-        let mut results = Vec::new();
-
+    async fn search_initial(&self, queries: &[SearchQuery]) -> Vec<Vec<InitialSearchResultSplit>> {
+        let client = self.client();
+        let mut results: Vec<Vec<_>> = queries.iter().map(|_| Vec::new()).collect();
+
+        let reqs: Vec<_> = queries
+            .iter()
+            .map(|query| search_server::Search {
+                query: query.clone(),
+            })
+            .collect();
+
+        // the searches are pipelined on one connection to each split
         if let Ok(res) = client
-            .send(
-                &search_server::Search {
-                    query: query.clone(),
-                },
-                &AllShardsSelector,
-                &RandomReplicaSelector,
-            )
+            .send_batch(&reqs, &AllShardsSelector, &LatencyAwareReplicaSelector)
             .await
         {
-            for (shard_id, mut res) in res {
-                if let Some(Some(res)) = res.pop() {
-                    results.push(InitialSearchResultSplit {
-                        local_result: res,
-                        split_id: shard_id,
-                    });
+            for (split_id, mut res) in res {
+                let Some(batch) = res.pop() else {
+                    continue;
+                };
+
+                for (results, res) in results.iter_mut().zip(batch) {
+                    if let Some(res) = res {
+                        results.push(InitialSearchResultSplit {
+                            local_result: res,
+                            split_id: split_id.clone(),
+                        });
+                    }
                 }
             }
         }
@@ -146,6 +146,7 @@ impl SearchClient for LiveSearcher {
         &self,
         top_websites: &[(usize, ScoredWebsitePointer)],
         query: &str,
+        snippet: &SnippetOptions,
     ) -> Vec<(usize, RetrievedWebpageRanking)> {
         let mut rankings = FnvHashMap::default();
         let mut pointers: HashMap<_, Vec<_>> = HashMap::new();
@@ -159,10 +160,11 @@ impl SearchClient for LiveSearcher {
             rankings.insert(*i, pointer.website.clone());
         }
 
-        let client = self.client().await;
+        let client = self.client();
         let mut futures = Vec::new();
         for (shard, pointers) in pointers {
-            futures.push(self.retrieve_webpages_from_shard(shard, &client, query, pointers));
+            futures
+                .push(self.retrieve_webpages_from_shard(shard, &client, query, snippet, pointers));
         }
 
         let mut retrieved_webpages = Vec::new();
@@ -182,13 +184,15 @@ impl SearchClient for LiveSearcher {
 }
 
 pub trait SearchClient {
+    /// Search the splits for each of the queries. The results are in the order of the queries.
     fn search_initial(
         &self,
-        query: &SearchQuery,
-    ) -> impl Future<Output = Vec<InitialSearchResultSplit>> + Send;
+        queries: &[SearchQuery],
+    ) -> impl Future<Output = Vec<Vec<InitialSearchResultSplit>>> + Send;
     fn retrieve_webpages(
         &self,
         top_websites: &[(usize, ScoredWebsitePointer)],
         query: &str,
+        snippet: &SnippetOptions,
     ) -> impl Future<Output = Vec<(usize, RetrievedWebpageRanking)>> + Send;
 }
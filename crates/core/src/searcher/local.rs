@@ -16,12 +16,14 @@
 
 use std::collections::HashMap;
 use std::sync::{Arc, RwLockReadGuard};
+use std::time::{Duration, Instant};
 
 use url::Url;
 
+use crate::collection_stats::CollectionStats;
 use crate::config::{CollectorConfig, SnippetConfig};
 use crate::index::Index;
-use crate::inverted_index::{InvertedIndex, RetrievedWebpage};
+use crate::inverted_index::{InvertedIndex, RetrievedWebpage, UrlIndexStatus};
 use crate::query::Query;
 use crate::ranking::inbound_similarity::InboundSimilarity;
 use crate::ranking::models::lambdamart::LambdaMART;
@@ -30,10 +32,14 @@ use crate::ranking::pipeline::{RankingPipeline, RankingWebsite};
 use crate::ranking::{query_centrality, Ranker, Signal, SignalAggregator, ALL_SIGNALS};
 use crate::search_ctx::Ctx;
 use crate::search_prettifier::DisplayedWebpage;
+use crate::signal_store::LiveSignalStore;
+use crate::snippet::SnippetOptions;
 use crate::webgraph::Node;
+use crate::webpage::topic_classifier;
 use crate::{inverted_index, live_index, Error, Result};
 
-use super::WebsitesResult;
+use super::cost::{SearchCost, ThreadCpuTime};
+use super::{author_facets, topic_facets, WebsitesResult};
 use super::{InitialWebsiteResult, SearchQuery};
 
 pub trait SearchableIndex {
@@ -103,6 +109,8 @@ pub struct LocalSearcher<I: SearchableIndex> {
     inbound_similarity: Option<InboundSimilarity>,
     linear_regression: Option<Arc<LinearRegression>>,
     lambda_model: Option<Arc<LambdaMART>>,
+    signal_store: Option<Arc<LiveSignalStore>>,
+    topic_classifier: Option<Arc<topic_classifier::Model>>,
     collector_config: CollectorConfig,
 }
 
@@ -119,6 +127,9 @@ struct InvertedIndexResult {
     webpages: Vec<RankingWebsite>,
     num_hits: Option<usize>,
     has_more: bool,
+    truncated: bool,
+    docs_scored: u64,
+    bytes_read: u64,
 }
 
 impl<I> LocalSearcher<I>
@@ -131,6 +142,8 @@ where
             inbound_similarity: None,
             linear_regression: None,
             lambda_model: None,
+            signal_store: None,
+            topic_classifier: None,
             collector_config: CollectorConfig::default(),
         }
     }
@@ -147,6 +160,15 @@ where
         self.lambda_model = Some(Arc::new(model));
     }
 
+    pub fn set_signal_store(&mut self, signal_store: Arc<LiveSignalStore>) {
+        self.signal_store = Some(signal_store);
+    }
+
+    /// Used to predict the topic of queries without a `topic:` operator.
+    pub fn set_topic_classifier(&mut self, model: topic_classifier::Model) {
+        self.topic_classifier = Some(Arc::new(model));
+    }
+
     pub fn set_collector_config(&mut self, config: CollectorConfig) {
         self.collector_config = config;
     }
@@ -177,6 +199,7 @@ where
         guard: &G,
         de_rank_similar: bool,
         aggregator: SignalAggregator,
+        deadline: Option<Instant>,
     ) -> Result<Ranker> {
         let query_centrality_coeff = aggregator.coefficient(&Signal::QueryCentrality);
 
@@ -184,7 +207,8 @@ where
             aggregator,
             guard.inverted_index().fastfield_reader(),
             self.collector_config.clone(),
-        );
+        )
+        .with_deadline(deadline);
 
         ranker.de_rank_similar(de_rank_similar);
 
@@ -211,7 +235,9 @@ where
 
         Ok(ranker
             .with_max_docs(
-                self.collector_config.max_docs_considered,
+                query
+                    .max_docs_considered()
+                    .unwrap_or(self.collector_config.max_docs_considered),
                 guard.inverted_index().num_segments(),
             )
             .with_num_results(query.num_results())
@@ -224,6 +250,7 @@ where
         guard: &G,
         query: &SearchQuery,
         de_rank_similar: bool,
+        deadline: Option<Instant>,
     ) -> Result<InvertedIndexResult> {
         let mut query = query.clone();
         let pipeline: RankingPipeline<RankingWebsite> = RankingPipeline::recall_stage(
@@ -270,7 +297,28 @@ where
             aggregator.set_linear_model(model.clone());
         }
 
-        let ranker = self.ranker(&parsed_query, ctx, guard, de_rank_similar, aggregator)?;
+        if let Some(signal_store) = self.signal_store.as_ref() {
+            aggregator.set_signal_store(signal_store.load());
+        }
+
+        if aggregator.query_topic().is_none() {
+            if let Some(classifier) = self.topic_classifier.as_ref() {
+                if let Some(topic) =
+                    classifier.predict_query(&parsed_query.simple_terms().join(" "))
+                {
+                    aggregator.set_query_topic(topic);
+                }
+            }
+        }
+
+        let ranker = self.ranker(
+            &parsed_query,
+            ctx,
+            guard,
+            de_rank_similar,
+            aggregator,
+            deadline,
+        )?;
 
         let res = guard.inverted_index().search_initial(
             &parsed_query,
@@ -296,6 +344,9 @@ where
             webpages: ranking_websites,
             num_hits: res.num_websites,
             has_more,
+            truncated: res.truncated,
+            docs_scored: res.docs_scored,
+            bytes_read: res.bytes_read,
         })
     }
 
@@ -304,15 +355,45 @@ where
         query: &SearchQuery,
         de_rank_similar: bool,
     ) -> Result<InitialWebsiteResult> {
+        self.search_initial_with_deadline(query, de_rank_similar, None)
+    }
+
+    /// Search with a time budget. The search stops collecting documents after the time budget
+    /// of the collector config or at the deadline, whichever comes first, and returns the best
+    /// websites found until then with `truncated` set.
+    ///
+    /// The cost of the result is the CPU time, documents scored and postings read by the search.
+    pub fn search_initial_with_deadline(
+        &self,
+        query: &SearchQuery,
+        de_rank_similar: bool,
+        deadline: Option<Instant>,
+    ) -> Result<InitialWebsiteResult> {
+        let budget = self
+            .collector_config
+            .time_budget_ms
+            .map(|ms| Instant::now() + Duration::from_millis(ms));
+        let deadline = match (budget, deadline) {
+            (Some(budget), Some(deadline)) => Some(budget.min(deadline)),
+            (budget, deadline) => budget.or(deadline),
+        };
+
+        let cpu_time = ThreadCpuTime::now();
         let guard = self.index.guard();
         let ctx = guard.inverted_index().local_search_ctx();
         let inverted_index_result =
-            self.search_inverted_index(&ctx, &guard, query, de_rank_similar)?;
+            self.search_inverted_index(&ctx, &guard, query, de_rank_similar, deadline)?;
 
         Ok(InitialWebsiteResult {
             websites: inverted_index_result.webpages,
             num_websites: inverted_index_result.num_hits,
             has_more: inverted_index_result.has_more,
+            truncated: inverted_index_result.truncated,
+            cost: SearchCost {
+                cpu_time_us: cpu_time.elapsed().as_micros() as u64,
+                docs_scored: inverted_index_result.docs_scored,
+                bytes_read: inverted_index_result.bytes_read,
+            },
         })
     }
 
@@ -320,6 +401,7 @@ where
         &self,
         websites: &[inverted_index::WebsitePointer],
         query: &str,
+        snippet: &SnippetOptions,
     ) -> Result<Vec<inverted_index::RetrievedWebpage>> {
         let guard = self.index.guard();
         let ctx = guard.inverted_index().local_search_ctx();
@@ -333,13 +415,26 @@ where
             return Err(Error::EmptyQuery.into());
         }
 
-        guard.inverted_index().retrieve_websites(websites, &query)
+        let mut webpages = guard
+            .inverted_index()
+            .retrieve_websites(websites, &query, snippet)?;
+
+        if let Some(signal_store) = self.signal_store.as_ref() {
+            let signal_store = signal_store.load();
+
+            for webpage in &mut webpages {
+                if let Ok(url) = Url::parse(&webpage.url) {
+                    let node = Node::from(&url);
+                    webpage.security = signal_store.security(&node.id(), &node.into_host().id());
+                }
+            }
+        }
+
+        Ok(webpages)
     }
 
     /// This function is mainly used for tests and benchmarks
     pub fn search(&self, query: &SearchQuery) -> Result<WebsitesResult> {
-        use std::time::Instant;
-
         let start = Instant::now();
         let mut search_query = query.clone();
 
@@ -376,7 +471,8 @@ where
             .map(|website| website.pointer.clone())
             .collect();
 
-        let retrieved_sites = self.retrieve_websites(&pointers, &search_query.query)?;
+        let retrieved_sites =
+            self.retrieve_websites(&pointers, &search_query.query, &search_query.snippet)?;
 
         let mut webpages: Vec<_> = retrieved_sites
             .into_iter()
@@ -395,11 +491,21 @@ where
             webpage.ranking_signals = Some(ranking_signals);
         }
 
+        let topic_facets = topic_facets(&webpages);
+        let author_facets = author_facets(&webpages);
+
         Ok(WebsitesResult {
             num_hits: search_result.num_websites,
             webpages,
             search_duration_ms: start.elapsed().as_millis(),
             has_more_results,
+            is_partial: false,
+            topic_facets,
+            author_facets,
+            plan: None,
+            session: None,
+            cost: search_result.cost,
+            segmented_query: None,
         })
     }
 
@@ -407,9 +513,33 @@ where
         self.index.guard().inverted_index().get_webpage(url)
     }
 
+    pub fn url_status(&self, url: &str) -> Result<UrlIndexStatus> {
+        self.index.guard().inverted_index().url_status(url)
+    }
+
     pub fn get_homepage(&self, url: &Url) -> Option<RetrievedWebpage> {
         self.index.guard().inverted_index().get_homepage(url)
     }
+
+    /// The collection statistics of the local index.
+    pub fn collection_stats(
+        &self,
+        min_doc_freq: u64,
+        max_terms_per_field: usize,
+    ) -> Result<CollectionStats> {
+        self.index
+            .guard()
+            .inverted_index()
+            .local_collection_stats(min_doc_freq, max_terms_per_field)
+    }
+
+    /// Score with the merged statistics of all the shards.
+    pub fn set_collection_stats(&self, stats: Arc<CollectionStats>) {
+        self.index
+            .guard()
+            .inverted_index()
+            .set_collection_stats(stats);
+    }
 }
 
 #[cfg(test)]
@@ -479,4 +609,146 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn time_budget() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for i in 0..10 {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        r#"
+            <html>
+                <head>
+                    <title>Example website</title>
+                </head>
+                <body>
+                    test
+                </body>
+            </html>
+            "#,
+                        &format!("https://www.{i}.com"),
+                    )
+                    .unwrap(),
+                    fetch_time_ms: 500,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().unwrap();
+
+        let mut searcher = LocalSearcher::new(index);
+        let query = SearchQuery {
+            query: "test".to_string(),
+            ..Default::default()
+        };
+
+        let res = searcher.search_initial(&query, true).unwrap();
+        assert!(!res.truncated);
+        assert!(!res.websites.is_empty());
+
+        // the deadline has already passed, so no documents are collected
+        let res = searcher
+            .search_initial_with_deadline(&query, true, Some(Instant::now()))
+            .unwrap();
+        assert!(res.truncated);
+        assert!(res.websites.is_empty());
+
+        searcher.set_collector_config(CollectorConfig {
+            time_budget_ms: Some(0),
+            ..Default::default()
+        });
+        let res = searcher.search_initial(&query, true).unwrap();
+        assert!(res.truncated);
+
+        searcher.set_collector_config(CollectorConfig {
+            time_budget_ms: Some(60_000),
+            ..Default::default()
+        });
+        let res = searcher.search_initial(&query, true).unwrap();
+        assert!(!res.truncated);
+        assert!(!res.websites.is_empty());
+    }
+    #[test]
+    fn search_cost() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for i in 0..10 {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+            <html>
+                <head>
+                    <title>Example website</title>
+                </head>
+                <body>
+                    test {}
+                </body>
+            </html>
+            "#,
+                            if i % 2 == 0 { "even" } else { "odd" }
+                        ),
+                        &format!("https://www.{i}.com"),
+                    )
+                    .unwrap(),
+                    fetch_time_ms: 500,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().unwrap();
+
+        let searcher = LocalSearcher::new(index);
+
+        let all = searcher
+            .search_initial(
+                &SearchQuery {
+                    query: "test".to_string(),
+                    ..Default::default()
+                },
+                true,
+            )
+            .unwrap()
+            .cost;
+        assert_eq!(all.docs_scored, 10);
+        assert!(all.bytes_read > 0);
+
+        let even = searcher
+            .search_initial(
+                &SearchQuery {
+                    query: "test even".to_string(),
+                    ..Default::default()
+                },
+                true,
+            )
+            .unwrap()
+            .cost;
+        assert_eq!(even.docs_scored, 5);
+
+        let missing = searcher
+            .search_initial(
+                &SearchQuery {
+                    query: "doesnotexist".to_string(),
+                    ..Default::default()
+                },
+                true,
+            )
+            .unwrap()
+            .cost;
+        assert_eq!(missing.docs_scored, 0);
+        assert_eq!(missing.bytes_read, 0);
+
+        let result = searcher
+            .search(&SearchQuery {
+                query: "test".to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(result.cost.docs_scored, 10);
+    }
 }
@@ -15,23 +15,35 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 pub mod api;
+pub mod cost;
 pub mod distributed;
 pub mod live;
 pub mod local;
+pub mod planner;
 
+use cost::SearchCost;
 pub use distributed::*;
 pub use local::*;
 use optics::{HostRankings, Optic};
+use planner::QueryPlan;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
 use crate::{
-    bangs::BangHit, config::defaults, ranking::pipeline::RankingWebsite,
-    search_prettifier::DisplayedWebpage, webpage::region::Region,
+    bangs::BangHit,
+    config::defaults,
+    query::parser::Term,
+    ranking::{bm25f::Bm25FParams, pipeline::RankingWebsite, SignalCoefficient},
+    search_prettifier::DisplayedWebpage,
+    snippet::SnippetOptions,
+    webpage::{region::Region, topic_classifier::Topic},
 };
 
 pub const NUM_RESULTS_PER_PAGE: usize = 20;
 
+/// The maximum number of author facets in a result.
+pub const MAX_AUTHOR_FACETS: usize = 10;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum SearchResult {
     Websites(WebsitesResult),
@@ -45,6 +57,157 @@ pub struct WebsitesResult {
     pub num_hits: Option<usize>,
     pub search_duration_ms: u128,
     pub has_more_results: bool,
+    /// Some of the shards failed to respond or ran out of time, so results might be missing.
+    pub is_partial: bool,
+    pub topic_facets: Vec<TopicFacet>,
+    pub author_facets: Vec<AuthorFacet>,
+    /// How the shards were searched. Only set when the ranking signals are returned.
+    pub plan: Option<QueryPlan>,
+    /// Token with the context of this query. Pass it along with the next
+    /// query so follow-ups like `its population` can be resolved.
+    pub session: Option<String>,
+    /// Resources the search servers used to answer the search, summed over all shards.
+    pub cost: SearchCost,
+    /// The query that was searched instead of the original query, because some of its
+    /// terms were typed without spaces, e.g. `rust async runtime` for `rustasyncruntime`.
+    pub segmented_query: Option<String>,
+}
+
+/// A result for search-as-you-type. It only contains what is
+/// needed to show the result below the search bar.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InstantWebpage {
+    pub title: String,
+    pub url: String,
+    pub site: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InstantResult {
+    /// The query that was searched after the last word has been completed.
+    pub query: String,
+    pub webpages: Vec<InstantWebpage>,
+    /// The search did not finish in time, so the results are empty.
+    pub timed_out: bool,
+}
+
+/// Number of results on the page with the topic. Can be used to narrow
+/// down the search with the `topic:` operator.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TopicFacet {
+    pub topic: Topic,
+    pub count: usize,
+}
+
+pub fn topic_facets(webpages: &[DisplayedWebpage]) -> Vec<TopicFacet> {
+    let mut counts = std::collections::BTreeMap::new();
+
+    for topic in webpages.iter().flat_map(|webpage| webpage.topics.iter()) {
+        *counts.entry(*topic).or_insert(0) += 1;
+    }
+
+    let mut facets: Vec<_> = counts
+        .into_iter()
+        .map(|(topic, count)| TopicFacet { topic, count })
+        .collect();
+    facets.sort_by(|a, b| b.count.cmp(&a.count));
+
+    facets
+}
+
+/// Number of results on the page by the author. Can be used to narrow
+/// down the search with the `author:` operator.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorFacet {
+    pub author: String,
+    pub count: usize,
+}
+
+/// The authors with most results on the page. Authors with the same
+/// name in different casing are counted together.
+pub fn author_facets(webpages: &[DisplayedWebpage]) -> Vec<AuthorFacet> {
+    let mut counts: std::collections::BTreeMap<String, (&str, usize)> =
+        std::collections::BTreeMap::new();
+
+    for author in webpages.iter().flat_map(|webpage| webpage.authors.iter()) {
+        counts.entry(author.to_lowercase()).or_insert((author, 0)).1 += 1;
+    }
+
+    let mut facets: Vec<_> = counts
+        .into_values()
+        .map(|(author, count)| AuthorFacet {
+            author: author.to_string(),
+            count,
+        })
+        .collect();
+    facets.sort_by(|a, b| b.count.cmp(&a.count));
+    facets.truncate(MAX_AUTHOR_FACETS);
+
+    facets
+}
+
+/// A vertical restricts the search to a single kind of page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Vertical {
+    Videos,
+    Products,
+    Scholar,
+}
+
+impl Vertical {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Vertical::Videos => "videos",
+            Vertical::Products => "products",
+            Vertical::Scholar => "scholar",
+        }
+    }
+
+    /// The snippets of the vertical. Video and product results show little text next to the
+    /// thumbnail, while the snippets of papers are longer and not adapted to the query.
+    pub fn snippet_options(&self) -> SnippetOptions {
+        match self {
+            Vertical::Videos => SnippetOptions {
+                desired_num_chars: Some(160),
+                ..Default::default()
+            },
+            Vertical::Products => SnippetOptions {
+                desired_num_chars: Some(120),
+                ..Default::default()
+            },
+            Vertical::Scholar => SnippetOptions {
+                desired_num_chars: Some(400),
+                adaptive_length: Some(false),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// The index a result was found in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ResultSource {
+    #[default]
+    Web,
+    Live,
+    Videos,
+    Products,
+    Scholar,
+}
+
+impl From<Vertical> for ResultSource {
+    fn from(vertical: Vertical) -> Self {
+        match vertical {
+            Vertical::Videos => ResultSource::Videos,
+            Vertical::Products => ResultSource::Products,
+            Vertical::Scholar => ResultSource::Scholar,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -57,7 +220,24 @@ pub struct SearchQuery {
     pub host_rankings: Option<HostRankings>,
     pub return_ranking_signals: bool,
     pub safe_search: bool,
+    /// Only return pages from this vertical.
+    pub vertical: Option<Vertical>,
     pub count_results: bool,
+    /// The ranking pipeline variant to use, e.g. for an experiment.
+    pub ranking_variant: Option<String>,
+    /// Coefficients from the ranking pipeline. These are set by the api and
+    /// used by the search servers for signals the optic does not mention.
+    pub signal_coefficients: Option<SignalCoefficient>,
+    /// BM25F parameters from the ranking pipeline. The search servers score the
+    /// fields with BM25F instead of their own BM25 signals if they are set.
+    pub bm25f: Option<Bm25FParams>,
+    /// Options for the snippets of the retrieved pages.
+    pub snippet: SnippetOptions,
+    /// Number of documents each search server scores before it stops early.
+    /// The collector config of the search servers is used if not set.
+    pub max_docs_considered: Option<usize>,
+    /// Terms of a structured query. The text of `query` is not parsed if they are set.
+    pub terms: Option<Vec<Term>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -65,6 +245,10 @@ pub struct InitialWebsiteResult {
     pub num_websites: Option<usize>,
     pub websites: Vec<RankingWebsite>,
     pub has_more: bool,
+    /// The shard ran out of time and returned the best websites it found until then.
+    pub truncated: bool,
+    /// Resources the shard used for the search.
+    pub cost: SearchCost,
 }
 
 impl Default for SearchQuery {
@@ -81,7 +265,14 @@ impl Default for SearchQuery {
             host_rankings: Default::default(),
             return_ranking_signals: defaults::SearchQuery::return_ranking_signals(),
             safe_search: defaults::SearchQuery::safe_search(),
+            vertical: Default::default(),
             count_results: defaults::SearchQuery::count_results(),
+            ranking_variant: Default::default(),
+            signal_coefficients: Default::default(),
+            bm25f: Default::default(),
+            snippet: Default::default(),
+            max_docs_considered: Default::default(),
+            terms: Default::default(),
         }
     }
 }
@@ -0,0 +1,385 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Planner that chooses how the shards are searched for a query.
+//!
+//! The shards are searched in stages, where the head shards for the language of the
+//! query come first. The planner uses the features of the query, and how often the
+//! first stage had enough results for earlier searches of the same query, to choose
+//! between searching only the first stage, searching the stages one after the other,
+//! or searching all shards at once.
+//!
+//! The search servers evaluate the query document-at-a-time and stop early after a
+//! number of documents. Queries that are expected to be rare are scored exhaustively
+//! instead, since they match few documents.
+//!
+//! The index only has the inverted index of the terms, so the plan does not choose
+//! between term-at-a-time and document-at-a-time evaluation or a hybrid of dense
+//! and sparse retrieval.
+
+use std::{sync::Mutex, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    config::QueryPlannerConfig,
+    query::parser::{self, Term},
+    ttl_cache::TTLCache,
+    webpage::region::Region,
+};
+
+use super::{InitialSearchResults, SearchQuery};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum RetrievalStrategy {
+    /// Only search the first stage of shards.
+    HeadOnly,
+    /// Search the stages one after the other until there are enough results.
+    Staged,
+    /// Search all shards at once, which saves the round trips of the stages.
+    AllShards,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryFeatures {
+    /// Number of words in the query, including the words of phrases.
+    pub num_terms: usize,
+    pub has_phrase: bool,
+    /// The query uses operators like `site:` or `intitle:`.
+    pub has_operators: bool,
+}
+
+impl QueryFeatures {
+    pub fn new(query: &str) -> Self {
+        Self::from_terms(parser::parse(query).iter().map(|term| term.as_ref()))
+    }
+
+    /// The features of a structured query, whose terms are not parsed from text.
+    pub fn from_terms<'a>(terms: impl IntoIterator<Item = &'a Term>) -> Self {
+        let mut features = Self::default();
+
+        for term in terms {
+            match term {
+                Term::Simple(_) => features.num_terms += 1,
+                Term::Phrase(phrase) => {
+                    features.has_phrase = true;
+                    features.num_terms += phrase.split_whitespace().count();
+                }
+                Term::PossibleBang(_) => {}
+                _ => features.has_operators = true,
+            }
+        }
+
+        features
+    }
+}
+
+/// Outcomes of the earlier searches of a query.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryHistory {
+    /// Searches where it is known whether the first stage had enough results.
+    pub num_searches: u64,
+    pub num_head_sufficient: u64,
+    /// Searches of all shards at once since the last search in stages.
+    pub num_unobserved: u64,
+}
+
+impl QueryHistory {
+    fn head_sufficient_fraction(&self) -> f64 {
+        if self.num_searches == 0 {
+            return 0.0;
+        }
+
+        self.num_head_sufficient as f64 / self.num_searches as f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryPlan {
+    pub strategy: RetrievalStrategy,
+    /// Number of documents each search server scores before it stops early.
+    /// The search servers use their own limit if not set.
+    pub max_docs_considered: Option<usize>,
+    pub features: QueryFeatures,
+    pub history: Option<QueryHistory>,
+    /// Why the strategy was chosen.
+    pub reason: String,
+}
+
+impl QueryPlan {
+    /// Set the options of the plan on the query that is sent to the search servers.
+    pub fn apply(&self, query: &mut SearchQuery) {
+        if let Some(max_docs) = self.max_docs_considered {
+            query.max_docs_considered = Some(max_docs);
+        }
+    }
+}
+
+type HistoryKey = (String, Option<Region>);
+
+pub struct QueryPlanner {
+    config: QueryPlannerConfig,
+    min_head_recall: f64,
+    history: Mutex<TTLCache<HistoryKey, QueryHistory>>,
+}
+
+impl QueryPlanner {
+    /// `min_head_recall` is the fraction of the needed results that the first stage
+    /// must find for the later stages to be skipped.
+    pub fn new(config: QueryPlannerConfig, min_head_recall: f64) -> Self {
+        let history = TTLCache::with_ttl_and_max_size(
+            Duration::from_secs(config.history_ttl_sec),
+            Some(config.max_history_size),
+        );
+
+        Self {
+            config,
+            min_head_recall,
+            history: Mutex::new(history),
+        }
+    }
+
+    fn key(query: &SearchQuery) -> HistoryKey {
+        (query.query.trim().to_lowercase(), query.selected_region)
+    }
+
+    pub fn plan(&self, query: &SearchQuery) -> QueryPlan {
+        let features = match &query.terms {
+            Some(terms) => QueryFeatures::from_terms(terms),
+            None => QueryFeatures::new(&query.query),
+        };
+        let key = Self::key(query);
+
+        let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        let mut query_history = history.get(&key).copied();
+
+        let (strategy, reason) = self.strategy(query, &features, query_history.as_ref());
+
+        if let Some(query_history) = query_history.as_mut() {
+            if strategy == RetrievalStrategy::AllShards {
+                query_history.num_unobserved += 1;
+                history.insert(key, *query_history);
+            }
+        }
+
+        let max_docs_considered = match strategy {
+            RetrievalStrategy::AllShards => Some(self.config.rare_query_max_docs),
+            RetrievalStrategy::HeadOnly | RetrievalStrategy::Staged => None,
+        };
+
+        QueryPlan {
+            strategy,
+            max_docs_considered,
+            features,
+            history: query_history,
+            reason,
+        }
+    }
+
+    fn strategy(
+        &self,
+        query: &SearchQuery,
+        features: &QueryFeatures,
+        history: Option<&QueryHistory>,
+    ) -> (RetrievalStrategy, String) {
+        if let Some(history) = history.filter(|h| h.num_searches >= self.config.min_searches) {
+            let fraction = history.head_sufficient_fraction();
+
+            if fraction >= self.config.head_only_fraction
+                && query.page == 0
+                && !features.has_operators
+            {
+                return (
+                    RetrievalStrategy::HeadOnly,
+                    format!(
+                        "the first shards had enough results for {:.0}% of the earlier searches",
+                        fraction * 100.0
+                    ),
+                );
+            }
+
+            // search in stages now and then, so the history reflects changes to the index
+            if fraction <= self.config.all_shards_fraction
+                && history.num_unobserved < self.config.explore_interval
+            {
+                return (
+                    RetrievalStrategy::AllShards,
+                    format!(
+                        "the first shards had enough results for only {:.0}% of the earlier searches",
+                        fraction * 100.0
+                    ),
+                );
+            }
+
+            return (
+                RetrievalStrategy::Staged,
+                format!(
+                    "the first shards had enough results for {:.0}% of the earlier searches",
+                    fraction * 100.0
+                ),
+            );
+        }
+
+        if features.has_phrase || features.num_terms >= self.config.long_query_terms {
+            return (
+                RetrievalStrategy::AllShards,
+                "long queries and phrases are expected to match few documents".to_string(),
+            );
+        }
+
+        (
+            RetrievalStrategy::Staged,
+            "the query has not been searched enough to plan from its history".to_string(),
+        )
+    }
+
+    /// Whether the first stage of shards found enough results for the query.
+    pub fn is_head_sufficient(&self, query: &SearchQuery, results: &InitialSearchResults) -> bool {
+        let num_needed = (query.page + 1) * query.num_results;
+        results.num_websites() as f64 >= self.min_head_recall * num_needed as f64
+    }
+
+    /// Record whether the first stage of shards had enough results for the search.
+    /// Nothing is recorded for searches of all shards at once, as the results of the
+    /// first stage are not known.
+    pub fn record(&self, query: &SearchQuery, plan: &QueryPlan, results: &InitialSearchResults) {
+        let head_sufficient = match plan.strategy {
+            RetrievalStrategy::HeadOnly => self.is_head_sufficient(query, results),
+            RetrievalStrategy::Staged => results.num_stages <= 1,
+            RetrievalStrategy::AllShards => return,
+        };
+
+        let key = Self::key(query);
+        let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        let mut query_history = history.get(&key).copied().unwrap_or_default();
+
+        query_history.num_searches += 1;
+        if head_sufficient {
+            query_history.num_head_sufficient += 1;
+        }
+        query_history.num_unobserved = 0;
+
+        history.insert(key, query_history);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> QueryPlannerConfig {
+        QueryPlannerConfig {
+            min_searches: 3,
+            explore_interval: 2,
+            ..Default::default()
+        }
+    }
+
+    fn query(text: &str) -> SearchQuery {
+        SearchQuery {
+            query: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn results(num_stages: usize) -> InitialSearchResults {
+        InitialSearchResults {
+            num_stages,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn features() {
+        let features = QueryFeatures::new("\"the quick brown\" fox site:example.com");
+        assert_eq!(features.num_terms, 4);
+        assert!(features.has_phrase);
+        assert!(features.has_operators);
+
+        assert_eq!(
+            QueryFeatures::new("rust"),
+            QueryFeatures {
+                num_terms: 1,
+                has_phrase: false,
+                has_operators: false,
+            }
+        );
+
+        // the words of structured queries are never operators
+        let terms = vec![Term::Simple("site:example.com".to_string().into())];
+        assert!(!QueryFeatures::from_terms(&terms).has_operators);
+    }
+
+    #[test]
+    fn plan_from_features() {
+        let planner = QueryPlanner::new(config(), 0.5);
+
+        let plan = planner.plan(&query("rust"));
+        assert_eq!(plan.strategy, RetrievalStrategy::Staged);
+        assert_eq!(plan.max_docs_considered, None);
+        assert_eq!(plan.history, None);
+
+        let plan = planner.plan(&query("\"rust borrow checker\""));
+        assert_eq!(plan.strategy, RetrievalStrategy::AllShards);
+        assert!(plan.max_docs_considered.is_some());
+
+        let mut search_query = query("\"rust borrow checker\"");
+        plan.apply(&mut search_query);
+        assert_eq!(search_query.max_docs_considered, plan.max_docs_considered);
+    }
+
+    #[test]
+    fn plan_from_history() {
+        let planner = QueryPlanner::new(config(), 0.5);
+
+        let head = query("Rust");
+        for _ in 0..3 {
+            let plan = planner.plan(&head);
+            assert_eq!(plan.strategy, RetrievalStrategy::Staged);
+            planner.record(&head, &plan, &results(1));
+        }
+
+        // the history is shared by queries that only differ in case
+        let plan = planner.plan(&query("rust"));
+        assert_eq!(plan.strategy, RetrievalStrategy::HeadOnly);
+        assert_eq!(plan.history.unwrap().num_head_sufficient, 3);
+
+        // the first shards found nothing, so the later stages are searched again
+        let plan = planner.plan(&head);
+        planner.record(&head, &plan, &results(1));
+        assert_eq!(planner.plan(&head).strategy, RetrievalStrategy::Staged);
+
+        let tail = query("tail");
+        for _ in 0..3 {
+            let plan = planner.plan(&tail);
+            planner.record(&tail, &plan, &results(3));
+        }
+
+        assert_eq!(planner.plan(&tail).strategy, RetrievalStrategy::AllShards);
+        assert_eq!(planner.plan(&tail).strategy, RetrievalStrategy::AllShards);
+
+        // search in stages again after `explore_interval` searches of all shards
+        let plan = planner.plan(&tail);
+        assert_eq!(plan.strategy, RetrievalStrategy::Staged);
+        planner.record(&tail, &plan, &results(2));
+        assert_eq!(planner.plan(&tail).strategy, RetrievalStrategy::AllShards);
+    }
+}
@@ -0,0 +1,272 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Security properties of pages.
+//!
+//! The crawler records whether a page was served over https, with HSTS and
+//! without mixed content in the warc metadata. The signal store entrypoint
+//! then adds these properties together with matches against a list of known
+//! malware hosts to the signal store, from which the results are annotated.
+
+use std::{collections::HashSet, fs, path::Path};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use url::Url;
+use utoipa::ToSchema;
+
+use crate::Result;
+
+/// Subresources loaded over plain http, e.g. `<script src="http://...">`.
+/// Links to other pages are not mixed content, so only tags that embed content are matched.
+static MIXED_CONTENT_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"(?i)<(?:script|img|iframe|video|audio|source|embed|object)\b[^>]*?\s(?:src|data)\s*=\s*["']?http://"#,
+    )
+    .unwrap()
+});
+
+/// Security properties of a page observed when it was crawled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(proptest_derive::Arbitrary))]
+pub struct SecurityProperties {
+    pub https: bool,
+    /// The page was served with a valid `Strict-Transport-Security` header.
+    pub hsts: bool,
+    /// The page is served over https but embeds resources over http.
+    pub mixed_content: bool,
+}
+
+impl SecurityProperties {
+    pub fn from_response(url: &Url, hsts_header: Option<&str>, body: &str) -> Self {
+        let https = url.scheme() == "https";
+
+        Self {
+            https,
+            // browsers ignore the header when it is not sent over https.
+            hsts: https && hsts_header.map(is_valid_hsts).unwrap_or(false),
+            mixed_content: https && has_mixed_content(body),
+        }
+    }
+}
+
+/// Whether the `Strict-Transport-Security` header enables HSTS, i.e. has a positive `max-age`.
+fn is_valid_hsts(header: &str) -> bool {
+    header.split(';').any(|directive| {
+        let Some((name, value)) = directive.split_once('=') else {
+            return false;
+        };
+
+        name.trim().eq_ignore_ascii_case("max-age")
+            && value
+                .trim()
+                .trim_matches('"')
+                .parse::<u64>()
+                .map(|max_age| max_age > 0)
+                .unwrap_or(false)
+    })
+}
+
+fn has_mixed_content(body: &str) -> bool {
+    MIXED_CONTENT_REGEX.is_match(body)
+}
+
+/// Hosts and urls that are known to serve malware.
+///
+/// The list is a text file with one entry per line. Entries with a scheme are
+/// matched against the full url, while other entries are hosts that match the
+/// host itself and all of its subdomains. Empty lines and lines starting with `#`
+/// are ignored.
+#[derive(Debug, Default)]
+pub struct MalwareList {
+    hosts: HashSet<String>,
+    urls: HashSet<String>,
+}
+
+impl MalwareList {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(list: &str) -> Self {
+        let mut malware = Self::default();
+
+        for line in list.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match Url::parse(line) {
+                Ok(url) if url.has_host() => {
+                    malware.urls.insert(url.to_string());
+                }
+                _ => {
+                    malware
+                        .hosts
+                        .insert(line.trim_end_matches('.').to_ascii_lowercase());
+                }
+            }
+        }
+
+        malware
+    }
+
+    pub fn hosts(&self) -> impl Iterator<Item = &str> {
+        self.hosts.iter().map(String::as_str)
+    }
+
+    pub fn urls(&self) -> impl Iterator<Item = &str> {
+        self.urls.iter().map(String::as_str)
+    }
+
+    pub fn contains(&self, url: &Url) -> bool {
+        if self.urls.contains(url.as_str()) {
+            return true;
+        }
+
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+
+        let mut host = host.to_ascii_lowercase();
+
+        loop {
+            if self.hosts.contains(&host) {
+                return true;
+            }
+
+            match host.split_once('.') {
+                Some((_, parent)) if parent.contains('.') => host = parent.to_string(),
+                _ => return false,
+            }
+        }
+    }
+}
+
+/// Security annotations shown with a search result.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SecurityAnnotations {
+    pub https: bool,
+    pub hsts: bool,
+    pub mixed_content: bool,
+    /// The page or its host is on the list of known malware.
+    pub flagged_malware: bool,
+}
+
+impl SecurityAnnotations {
+    /// How risky it is to visit the page, from 0 (no known issues) to 1 (known malware).
+    pub fn risk(&self) -> f64 {
+        if self.flagged_malware {
+            return 1.0;
+        }
+
+        let mut risk = 0.0;
+
+        if !self.https {
+            risk += 0.5;
+        }
+
+        if self.mixed_content {
+            risk += 0.25;
+        }
+
+        risk
+    }
+
+    pub fn is_insecure(&self) -> bool {
+        self.risk() > 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hsts() {
+        assert!(is_valid_hsts("max-age=31536000; includeSubDomains"));
+        assert!(is_valid_hsts("includeSubDomains; Max-Age=\"600\""));
+        assert!(!is_valid_hsts("max-age=0"));
+        assert!(!is_valid_hsts("includeSubDomains"));
+
+        let url = Url::parse("http://example.com").unwrap();
+        let props = SecurityProperties::from_response(&url, Some("max-age=600"), "");
+        assert!(!props.https);
+        assert!(!props.hsts);
+    }
+
+    #[test]
+    fn mixed_content() {
+        let url = Url::parse("https://example.com").unwrap();
+
+        let props = SecurityProperties::from_response(
+            &url,
+            None,
+            r#"<a href="http://other.com">link</a><img alt="logo" src="https://example.com/logo.png">"#,
+        );
+        assert!(props.https);
+        assert!(!props.mixed_content);
+
+        let props = SecurityProperties::from_response(
+            &url,
+            None,
+            r#"<script type="text/javascript" src='http://cdn.example.com/app.js'></script>"#,
+        );
+        assert!(props.mixed_content);
+    }
+
+    #[test]
+    fn malware_list() {
+        let list = MalwareList::parse(
+            "# known malware\n\nevil.com\nhttps://example.com/download.exe\nBAD.org.\n",
+        );
+
+        assert!(list.contains(&Url::parse("https://evil.com/").unwrap()));
+        assert!(list.contains(&Url::parse("http://www.evil.com/page").unwrap()));
+        assert!(list.contains(&Url::parse("https://bad.org").unwrap()));
+        assert!(list.contains(&Url::parse("https://example.com/download.exe").unwrap()));
+
+        assert!(!list.contains(&Url::parse("https://example.com/").unwrap()));
+        assert!(!list.contains(&Url::parse("https://notevil.com/").unwrap()));
+    }
+
+    #[test]
+    fn risk() {
+        let secure = SecurityAnnotations {
+            https: true,
+            hsts: true,
+            ..Default::default()
+        };
+        assert!(!secure.is_insecure());
+
+        let mixed = SecurityAnnotations {
+            https: true,
+            mixed_content: true,
+            ..Default::default()
+        };
+        let http = SecurityAnnotations::default();
+        let malware = SecurityAnnotations {
+            https: true,
+            flagged_malware: true,
+            ..Default::default()
+        };
+
+        assert!(mixed.risk() < http.risk());
+        assert!(http.risk() < malware.risk());
+    }
+}
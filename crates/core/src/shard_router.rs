@@ -0,0 +1,162 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Decides which shard owns a url. The indexer, the ingest and deletion paths and
+//! the coordinator all route through here, so they agree on where a document lives.
+//!
+//! Urls are normalized the same way as the nodes in the webgraph and routed by
+//! the hash of the normalized url, so `http://www.example.com/a?utm_source=feed` and
+//! `https://example.com/a` are owned by the same shard. Urls that cannot be parsed
+//! are owned by the first shard. The shards are expected to have the ids `0..num_shards`.
+
+use anyhow::{anyhow, bail};
+use url::Url;
+
+use crate::{config::RoutingConfig, searcher::ShardId, webgraph::Node, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShardRouter {
+    num_shards: u64,
+}
+
+impl ShardRouter {
+    pub fn new(num_shards: u64) -> Result<Self> {
+        if num_shards == 0 {
+            return Err(anyhow!("there must be at least one shard"));
+        }
+
+        Ok(Self { num_shards })
+    }
+
+    /// The router and the id of the shard of a component that only handles the urls
+    /// owned by its shard. `None` if routing is not configured.
+    pub fn for_shard(
+        routing: Option<RoutingConfig>,
+        shard_id: Option<ShardId>,
+    ) -> Result<Option<(Self, ShardId)>> {
+        match (routing, shard_id) {
+            (Some(routing), Some(shard_id)) => {
+                let router = Self::try_from(routing)?;
+
+                if shard_id.as_u64() >= router.num_shards {
+                    bail!(
+                        "shard {} is not one of the {} shards",
+                        shard_id.as_u64(),
+                        router.num_shards
+                    );
+                }
+
+                Ok(Some((router, shard_id)))
+            }
+            (Some(_), None) => bail!("routing requires the id of the shard"),
+            (None, _) => Ok(None),
+        }
+    }
+
+    pub fn num_shards(&self) -> u64 {
+        self.num_shards
+    }
+
+    pub fn shards(&self) -> impl Iterator<Item = ShardId> {
+        (0..self.num_shards).map(ShardId::new)
+    }
+
+    /// The shard that owns the url.
+    pub fn shard(&self, url: &Url) -> ShardId {
+        ShardId::new(Node::from(url).id().as_u64() % self.num_shards)
+    }
+
+    /// The shard that owns the url. Urls that cannot be parsed are owned by the first shard.
+    pub fn shard_of_str(&self, url: &str) -> ShardId {
+        Url::parse(url)
+            .map(|url| self.shard(&url))
+            .unwrap_or(ShardId::new(0))
+    }
+
+    pub fn owns(&self, shard: ShardId, url: &str) -> bool {
+        self.shard_of_str(url) == shard
+    }
+}
+
+impl TryFrom<RoutingConfig> for ShardRouter {
+    type Error = anyhow::Error;
+
+    fn try_from(config: RoutingConfig) -> Result<Self> {
+        Self::new(config.num_shards)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized_urls_have_same_shard() {
+        let router = ShardRouter::new(16).unwrap();
+
+        assert_eq!(
+            router.shard_of_str("http://www.example.com/page?utm_source=feed"),
+            router.shard_of_str("https://example.com/page"),
+        );
+    }
+
+    #[test]
+    fn invalid_urls_are_owned_by_first_shard() {
+        let router = ShardRouter::new(16).unwrap();
+
+        assert_eq!(router.shard_of_str("not a url"), ShardId::new(0));
+        assert!(router.owns(ShardId::new(0), "not a url"));
+        assert!(!router.owns(ShardId::new(1), "not a url"));
+    }
+
+    #[test]
+    fn invalid_configs() {
+        assert!(ShardRouter::new(0).is_err());
+        assert!(ShardRouter::for_shard(Some(RoutingConfig { num_shards: 2 }), None).is_err());
+        assert!(ShardRouter::for_shard(
+            Some(RoutingConfig { num_shards: 2 }),
+            Some(ShardId::new(2))
+        )
+        .is_err());
+        assert!(ShardRouter::for_shard(None, Some(ShardId::new(2)))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn urls_are_spread_over_shards() {
+        let router = ShardRouter::new(4).unwrap();
+        let mut counts = [0; 4];
+
+        for i in 0..1_000 {
+            let url = Url::parse(&format!("https://example{i}.com/page")).unwrap();
+            let shard = router.shard(&url);
+
+            assert!(router.owns(shard, url.as_str()));
+            counts[router.shards().position(|s| s == shard).unwrap()] += 1;
+        }
+
+        assert!(counts.iter().all(|count| *count > 150));
+    }
+
+    #[test]
+    fn single_shard_owns_everything() {
+        let router = ShardRouter::new(1).unwrap();
+
+        assert!(router.owns(ShardId::new(0), "https://example.com/"));
+        assert!(!router.owns(ShardId::new(1), "https://example.com/"));
+    }
+}
@@ -0,0 +1,814 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The signal store contains query independent ranking signals for
+//! hosts and pages keyed by their [`NodeID`].
+//!
+//! The store is assembled offline and written as a set of columns so that
+//! all signals for a node can be found with a single binary search in a
+//! memory mapped file instead of a lookup in a separate RocksDB store per signal.
+//!
+//! Searchers use the store for the query independent signals when it is
+//! configured. A new store can therefore be swapped in using [`LiveSignalStore`]
+//! without re-indexing the segments.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    security::{SecurityAnnotations, SecurityProperties},
+    webgraph::{compaction::NodeIdMapping, NodeID},
+    webpage::topic_classifier::{Topic, TopicVector, ALL_TOPICS, NUM_TOPICS},
+    Result,
+};
+
+const FORMAT_VERSION: u64 = 1;
+const META_FILE: &str = "meta.json";
+const KEYS_FILE: &str = "keys.bin";
+const VALUES_FILE: &str = "values.bin";
+/// Stores rewritten to dense node ids contain the mapping from the hashed ids.
+const MAPPING_FILE: &str = "dense_ids.bin";
+/// Name of the folder with the files of the current version of the store. Stores
+/// written before the versions had their own folders have the files in the store folder.
+const CURRENT_FILE: &str = "CURRENT";
+
+const KEY_SIZE: usize = std::mem::size_of::<u64>();
+const VALUE_SIZE: usize = std::mem::size_of::<f64>();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoredSignal {
+    HostCentrality,
+    HostCentralityRank,
+    PageCentrality,
+    PageCentralityRank,
+    SpamScore,
+    CtrPrior,
+    Freshness,
+    /// Weight of the topic in the topic vector of the node (see topic classifier).
+    Topic(Topic),
+    /// Centrality of the host among the hosts about the topic (see topic-sensitive pagerank).
+    TopicCentrality(Topic),
+    /// 1 if the page was served over https when crawled, otherwise 0.
+    Https,
+    /// 1 if the page was served with HSTS when crawled, otherwise 0.
+    Hsts,
+    /// 1 if the page embedded resources over http when crawled, otherwise 0.
+    MixedContent,
+    /// 1 if the page or host is on the list of known malware.
+    Malware,
+}
+
+pub const ALL_STORED_SIGNALS: [StoredSignal; 39] = [
+    StoredSignal::HostCentrality,
+    StoredSignal::HostCentralityRank,
+    StoredSignal::PageCentrality,
+    StoredSignal::PageCentralityRank,
+    StoredSignal::SpamScore,
+    StoredSignal::CtrPrior,
+    StoredSignal::Freshness,
+    StoredSignal::Topic(Topic::Arts),
+    StoredSignal::Topic(Topic::Business),
+    StoredSignal::Topic(Topic::Computers),
+    StoredSignal::Topic(Topic::Games),
+    StoredSignal::Topic(Topic::Health),
+    StoredSignal::Topic(Topic::Home),
+    StoredSignal::Topic(Topic::KidsAndTeens),
+    StoredSignal::Topic(Topic::News),
+    StoredSignal::Topic(Topic::Recreation),
+    StoredSignal::Topic(Topic::Reference),
+    StoredSignal::Topic(Topic::Science),
+    StoredSignal::Topic(Topic::Shopping),
+    StoredSignal::Topic(Topic::Society),
+    StoredSignal::Topic(Topic::Sports),
+    StoredSignal::TopicCentrality(Topic::Arts),
+    StoredSignal::TopicCentrality(Topic::Business),
+    StoredSignal::TopicCentrality(Topic::Computers),
+    StoredSignal::TopicCentrality(Topic::Games),
+    StoredSignal::TopicCentrality(Topic::Health),
+    StoredSignal::TopicCentrality(Topic::Home),
+    StoredSignal::TopicCentrality(Topic::KidsAndTeens),
+    StoredSignal::TopicCentrality(Topic::News),
+    StoredSignal::TopicCentrality(Topic::Recreation),
+    StoredSignal::TopicCentrality(Topic::Reference),
+    StoredSignal::TopicCentrality(Topic::Science),
+    StoredSignal::TopicCentrality(Topic::Shopping),
+    StoredSignal::TopicCentrality(Topic::Society),
+    StoredSignal::TopicCentrality(Topic::Sports),
+    StoredSignal::Https,
+    StoredSignal::Hsts,
+    StoredSignal::MixedContent,
+    StoredSignal::Malware,
+];
+
+const NUM_STORED_SIGNALS: usize = ALL_STORED_SIGNALS.len();
+
+impl From<StoredSignal> for usize {
+    fn from(signal: StoredSignal) -> Self {
+        match signal {
+            StoredSignal::HostCentrality => 0,
+            StoredSignal::HostCentralityRank => 1,
+            StoredSignal::PageCentrality => 2,
+            StoredSignal::PageCentralityRank => 3,
+            StoredSignal::SpamScore => 4,
+            StoredSignal::CtrPrior => 5,
+            StoredSignal::Freshness => 6,
+            StoredSignal::Topic(topic) => 7 + topic as usize,
+            StoredSignal::TopicCentrality(topic) => 7 + NUM_TOPICS + topic as usize,
+            StoredSignal::Https => 7 + 2 * NUM_TOPICS,
+            StoredSignal::Hsts => 8 + 2 * NUM_TOPICS,
+            StoredSignal::MixedContent => 9 + 2 * NUM_TOPICS,
+            StoredSignal::Malware => 10 + 2 * NUM_TOPICS,
+        }
+    }
+}
+
+/// All the stored signals for a single node. Signals that
+/// were not assembled for the node are `None`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SignalVector {
+    values: [f64; NUM_STORED_SIGNALS],
+}
+
+impl Default for SignalVector {
+    fn default() -> Self {
+        Self {
+            values: [f64::NAN; NUM_STORED_SIGNALS],
+        }
+    }
+}
+
+impl SignalVector {
+    pub fn get(&self, signal: StoredSignal) -> Option<f64> {
+        let value = self.values[usize::from(signal)];
+
+        if value.is_nan() {
+            None
+        } else {
+            Some(value)
+        }
+    }
+
+    fn flag(&self, signal: StoredSignal) -> Option<bool> {
+        self.get(signal).map(|value| value > 0.0)
+    }
+
+    pub fn set(&mut self, signal: StoredSignal, value: f64) {
+        self.values[usize::from(signal)] = value;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.iter().all(|v| v.is_nan())
+    }
+
+    /// The topic vector of the node, if topics were assembled for it.
+    pub fn topics(&self) -> Option<TopicVector> {
+        let mut vector = TopicVector::default();
+        let mut found = false;
+
+        for topic in ALL_TOPICS {
+            if let Some(weight) = self.get(StoredSignal::Topic(topic)) {
+                vector.set(topic, weight as f32);
+                found = true;
+            }
+        }
+
+        if found {
+            Some(vector)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Meta {
+    format_version: u64,
+    /// Generation of the assembled signals. Stores with a higher
+    /// version replace stores with a lower version.
+    pub version: u64,
+    pub signals: Vec<StoredSignal>,
+    pub num_rows: u64,
+}
+
+/// The folder with the files of the current version of the store.
+fn current_folder(path: &Path) -> Result<PathBuf> {
+    let current = path.join(CURRENT_FILE);
+
+    if current.exists() {
+        Ok(path.join(fs::read_to_string(current)?.trim()))
+    } else {
+        Ok(path.to_path_buf())
+    }
+}
+
+impl Meta {
+    fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(
+            path.as_ref().join(META_FILE),
+        )?)?)
+    }
+}
+
+pub struct SignalStoreWriter {
+    path: PathBuf,
+    version: u64,
+    rows: HashMap<NodeID, SignalVector>,
+    mapping: Option<NodeIdMapping>,
+}
+
+impl SignalStoreWriter {
+    pub fn new<P: AsRef<Path>>(path: P, version: u64) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            version,
+            rows: HashMap::new(),
+            mapping: None,
+        }
+    }
+
+    /// Key the rows by the dense ids of the nodes. The nodes are still inserted and read
+    /// with their hashed ids, and nodes that are not in the mapping are dropped.
+    pub fn set_mapping(&mut self, mapping: NodeIdMapping) {
+        self.mapping = Some(mapping);
+    }
+
+    pub fn insert(&mut self, node: NodeID, signal: StoredSignal, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+
+        self.rows.entry(node).or_default().set(signal, value);
+    }
+
+    pub fn insert_topics(&mut self, node: NodeID, topics: &TopicVector) {
+        for topic in ALL_TOPICS {
+            self.insert(node, StoredSignal::Topic(topic), topics.get(topic) as f64);
+        }
+    }
+
+    pub fn insert_security(&mut self, node: NodeID, security: &SecurityProperties) {
+        let flag = |value: bool| if value { 1.0 } else { 0.0 };
+
+        self.insert(node, StoredSignal::Https, flag(security.https));
+        self.insert(node, StoredSignal::Hsts, flag(security.hsts));
+        self.insert(
+            node,
+            StoredSignal::MixedContent,
+            flag(security.mixed_content),
+        );
+    }
+
+    pub fn insert_vector(&mut self, node: NodeID, vector: &SignalVector) {
+        for signal in ALL_STORED_SIGNALS {
+            if let Some(value) = vector.get(signal) {
+                self.insert(node, signal, value);
+            }
+        }
+    }
+
+    /// Add all the values from a RocksDB store written by the centrality entrypoint.
+    pub fn insert_from_kv<P: AsRef<Path>>(&mut self, path: P, signal: StoredSignal) {
+        let store: RocksDbStore<NodeID, f64> = RocksDbStore::open_read_only(path);
+
+        for (node, value) in store.iter() {
+            self.insert(node, signal, value);
+        }
+    }
+
+    /// Write the store. The files are written to a new folder, and the `CURRENT` file is
+    /// then replaced by a rename to point to it, so the store at the path is always complete.
+    /// The folder of the previous version is kept for the searchers that are opening it
+    /// while the store is written, and the folders of older versions are removed.
+    pub fn finalize(self) -> Result<SignalStore> {
+        fs::create_dir_all(&self.path)?;
+
+        let previous = fs::read_to_string(self.path.join(CURRENT_FILE))
+            .ok()
+            .map(|name| name.trim().to_string());
+
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let name = format!("{}-{}", self.version, created);
+        let folder = self.path.join(&name);
+
+        fs::create_dir_all(&folder)?;
+
+        let mut rows: Vec<_> = self
+            .rows
+            .into_iter()
+            .filter(|(_, vector)| !vector.is_empty())
+            .filter_map(|(node, vector)| match &self.mapping {
+                Some(mapping) => Some((mapping.dense(&node)?, vector)),
+                None => Some((node, vector)),
+            })
+            .collect();
+        rows.sort_by_key(|(node, _)| *node);
+
+        let signals: Vec<_> = ALL_STORED_SIGNALS
+            .into_iter()
+            .filter(|signal| rows.iter().any(|(_, vector)| vector.get(*signal).is_some()))
+            .collect();
+
+        let mut keys = BufWriter::new(File::create(folder.join(KEYS_FILE))?);
+        for (node, _) in &rows {
+            keys.write_all(&node.as_u64().to_le_bytes())?;
+        }
+        keys.flush()?;
+
+        let mut values = BufWriter::new(File::create(folder.join(VALUES_FILE))?);
+        for signal in &signals {
+            for (_, vector) in &rows {
+                let value = vector.get(*signal).unwrap_or(f64::NAN);
+                values.write_all(&value.to_le_bytes())?;
+            }
+        }
+        values.flush()?;
+
+        let meta = Meta {
+            format_version: FORMAT_VERSION,
+            version: self.version,
+            signals,
+            num_rows: rows.len() as u64,
+        };
+
+        let mut meta_file = File::create(folder.join(META_FILE))?;
+        meta_file.write_all(serde_json::to_string_pretty(&meta)?.as_bytes())?;
+        meta_file.flush()?;
+
+        if let Some(mapping) = &self.mapping {
+            mapping.save(folder.join(MAPPING_FILE))?;
+        }
+
+        let tmp_current = self.path.join(format!("{CURRENT_FILE}.tmp"));
+        fs::write(&tmp_current, &name)?;
+        File::open(&tmp_current)?.sync_all()?;
+        fs::rename(&tmp_current, self.path.join(CURRENT_FILE))?;
+
+        if let Some(previous) = previous {
+            for entry in fs::read_dir(&self.path)? {
+                let entry = entry?;
+                let file_name = entry.file_name();
+
+                if file_name == name.as_str() || file_name == previous.as_str() {
+                    continue;
+                }
+
+                if entry.file_type()?.is_dir() {
+                    fs::remove_dir_all(entry.path())?;
+                } else if [META_FILE, KEYS_FILE, VALUES_FILE, MAPPING_FILE]
+                    .iter()
+                    .any(|legacy| file_name == *legacy)
+                {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+        }
+
+        SignalStore::open(&self.path)
+    }
+}
+
+pub struct SignalStore {
+    meta: Meta,
+    columns: [Option<usize>; NUM_STORED_SIGNALS],
+    keys: Mmap,
+    values: Mmap,
+    mapping: Option<NodeIdMapping>,
+}
+
+impl SignalStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let folder = current_folder(path.as_ref())?;
+        let meta = Meta::open(&folder)?;
+
+        if meta.format_version != FORMAT_VERSION {
+            return Err(anyhow!(
+                "unsupported signal store format: {} (expected {})",
+                meta.format_version,
+                FORMAT_VERSION
+            ));
+        }
+
+        let keys = unsafe { Mmap::map(&File::open(folder.join(KEYS_FILE))?)? };
+        let values = unsafe { Mmap::map(&File::open(folder.join(VALUES_FILE))?)? };
+
+        let num_rows = meta.num_rows as usize;
+        if keys.len() != num_rows * KEY_SIZE
+            || values.len() != num_rows * meta.signals.len() * VALUE_SIZE
+        {
+            return Err(anyhow!("signal store files does not match the metadata"));
+        }
+
+        let mut columns = [None; NUM_STORED_SIGNALS];
+        for (column, signal) in meta.signals.iter().enumerate() {
+            columns[usize::from(*signal)] = Some(column);
+        }
+
+        let mapping_path = folder.join(MAPPING_FILE);
+        let mapping = if mapping_path.exists() {
+            Some(NodeIdMapping::open(mapping_path)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            meta,
+            columns,
+            keys,
+            values,
+            mapping,
+        })
+    }
+
+    pub fn version(&self) -> u64 {
+        self.meta.version
+    }
+
+    pub fn len(&self) -> usize {
+        self.meta.num_rows as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn has_signal(&self, signal: StoredSignal) -> bool {
+        self.columns[usize::from(signal)].is_some()
+    }
+
+    fn key(&self, row: usize) -> u64 {
+        let start = row * KEY_SIZE;
+        u64::from_le_bytes(self.keys[start..start + KEY_SIZE].try_into().unwrap())
+    }
+
+    fn row(&self, node: &NodeID) -> Option<usize> {
+        let needle = match &self.mapping {
+            Some(mapping) => mapping.dense(node)?.as_u64(),
+            None => node.as_u64(),
+        };
+        let mut low = 0;
+        let mut high = self.len();
+
+        while low < high {
+            let mid = low + (high - low) / 2;
+
+            match self.key(mid).cmp(&needle) {
+                std::cmp::Ordering::Equal => return Some(mid),
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+            }
+        }
+
+        None
+    }
+
+    fn value(&self, row: usize, column: usize) -> Option<f64> {
+        let start = (column * self.len() + row) * VALUE_SIZE;
+        let value = f64::from_le_bytes(self.values[start..start + VALUE_SIZE].try_into().unwrap());
+
+        if value.is_nan() {
+            None
+        } else {
+            Some(value)
+        }
+    }
+
+    fn vector(&self, row: usize) -> SignalVector {
+        let mut vector = SignalVector::default();
+
+        for signal in ALL_STORED_SIGNALS {
+            if let Some(value) = self.columns[usize::from(signal)].and_then(|c| self.value(row, c))
+            {
+                vector.set(signal, value);
+            }
+        }
+
+        vector
+    }
+
+    pub fn get(&self, node: &NodeID) -> Option<SignalVector> {
+        let row = self.row(node)?;
+        Some(self.vector(row))
+    }
+
+    /// Iterate all the nodes in the store in order of their id.
+    pub fn iter(&self) -> impl Iterator<Item = (NodeID, SignalVector)> + '_ {
+        (0..self.len()).map(|row| {
+            let key = NodeID::from(self.key(row));
+            let node = match &self.mapping {
+                // dense ids are assigned in the order of the hashed ids
+                Some(mapping) => mapping.original(&key).unwrap_or(key),
+                None => key,
+            };
+
+            (node, self.vector(row))
+        })
+    }
+
+    pub fn get_signal(&self, node: &NodeID, signal: StoredSignal) -> Option<f64> {
+        let column = self.columns[usize::from(signal)]?;
+        let row = self.row(node)?;
+
+        self.value(row, column)
+    }
+
+    pub fn topics(&self, node: &NodeID) -> Option<TopicVector> {
+        if !self.has_signal(StoredSignal::Topic(Topic::Arts)) {
+            return None;
+        }
+
+        self.get(node)?.topics()
+    }
+
+    /// The security annotations of the page from the properties recorded when
+    /// it was crawled. Malware can be flagged for either the page or its host,
+    /// in which case properties that were not recorded for the page are false.
+    pub fn security(&self, page: &NodeID, host: &NodeID) -> Option<SecurityAnnotations> {
+        let page = self.get(page);
+        let host = self.get(host);
+
+        let flagged_malware = [page, host]
+            .into_iter()
+            .flatten()
+            .any(|vector| vector.flag(StoredSignal::Malware).unwrap_or(false));
+
+        let https = page.and_then(|vector| vector.flag(StoredSignal::Https));
+
+        if https.is_none() && !flagged_malware {
+            return None;
+        }
+
+        let page = page.unwrap_or_default();
+
+        Some(SecurityAnnotations {
+            https: https.unwrap_or(false),
+            hsts: page.flag(StoredSignal::Hsts).unwrap_or(false),
+            mixed_content: page.flag(StoredSignal::MixedContent).unwrap_or(false),
+            flagged_malware,
+        })
+    }
+}
+
+/// A signal store that can be refreshed while it is being used.
+///
+/// Readers get a snapshot of the current store which stays valid for as long
+/// as they hold on to it, even if a newer store has been swapped in.
+pub struct LiveSignalStore {
+    path: PathBuf,
+    current: RwLock<Arc<SignalStore>>,
+}
+
+impl LiveSignalStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let store = SignalStore::open(path.as_ref())?;
+
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            current: RwLock::new(Arc::new(store)),
+        })
+    }
+
+    pub fn load(&self) -> Arc<SignalStore> {
+        Arc::clone(&self.current.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    pub fn swap(&self, store: SignalStore) {
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(store);
+    }
+
+    /// Re-open the store from disk if a newer version has been written.
+    /// Returns whether the store was swapped.
+    pub fn refresh(&self) -> Result<bool> {
+        let meta = Meta::open(current_folder(&self.path)?)?;
+
+        if meta.version <= self.load().version() {
+            return Ok(false);
+        }
+
+        let store = SignalStore::open(&self.path)?;
+        tracing::info!(
+            "refreshing signal store from version {} to {}",
+            self.load().version(),
+            store.version()
+        );
+        self.swap(store);
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let path = crate::gen_temp_path();
+        let mut writer = SignalStoreWriter::new(&path, 1);
+
+        for i in 0..1_000_u64 {
+            writer.insert(NodeID::from(i * 2), StoredSignal::HostCentrality, i as f64);
+
+            if i % 2 == 0 {
+                writer.insert(
+                    NodeID::from(i * 2),
+                    StoredSignal::SpamScore,
+                    1.0 / (i + 1) as f64,
+                );
+            }
+        }
+
+        let store = writer.finalize().unwrap();
+
+        assert_eq!(store.len(), 1_000);
+        assert_eq!(store.version(), 1);
+        assert!(store.has_signal(StoredSignal::HostCentrality));
+        assert!(store.has_signal(StoredSignal::SpamScore));
+        assert!(!store.has_signal(StoredSignal::CtrPrior));
+
+        for i in 0..1_000_u64 {
+            let vector = store.get(&NodeID::from(i * 2)).unwrap();
+            assert_eq!(vector.get(StoredSignal::HostCentrality), Some(i as f64));
+
+            if i % 2 == 0 {
+                assert_eq!(
+                    vector.get(StoredSignal::SpamScore),
+                    Some(1.0 / (i + 1) as f64)
+                );
+            } else {
+                assert_eq!(vector.get(StoredSignal::SpamScore), None);
+            }
+
+            assert!(store.get(&NodeID::from(i * 2 + 1)).is_none());
+        }
+
+        let reopened = SignalStore::open(&path).unwrap();
+        assert_eq!(
+            reopened.get_signal(&NodeID::from(10), StoredSignal::HostCentrality),
+            Some(5.0)
+        );
+    }
+
+    #[test]
+    fn refresh_live_store() {
+        let path = crate::gen_temp_path();
+
+        let mut writer = SignalStoreWriter::new(&path, 1);
+        writer.insert(NodeID::from(1_u64), StoredSignal::HostCentrality, 1.0);
+        writer.finalize().unwrap();
+
+        let live = LiveSignalStore::open(&path).unwrap();
+        let old = live.load();
+        assert!(!live.refresh().unwrap());
+
+        let mut writer = SignalStoreWriter::new(&path, 2);
+        writer.insert(NodeID::from(1_u64), StoredSignal::HostCentrality, 2.0);
+        writer.finalize().unwrap();
+
+        assert!(live.refresh().unwrap());
+        assert_eq!(live.load().version(), 2);
+        assert_eq!(
+            live.load()
+                .get_signal(&NodeID::from(1_u64), StoredSignal::HostCentrality),
+            Some(2.0)
+        );
+
+        // snapshots taken before the refresh are still readable
+        assert_eq!(
+            old.get_signal(&NodeID::from(1_u64), StoredSignal::HostCentrality),
+            Some(1.0)
+        );
+    }
+
+    #[test]
+    fn previous_version_is_kept() {
+        let path = crate::gen_temp_path();
+
+        for version in 1..=3 {
+            let mut writer = SignalStoreWriter::new(&path, version);
+            writer.insert(
+                NodeID::from(1_u64),
+                StoredSignal::HostCentrality,
+                version as f64,
+            );
+            writer.finalize().unwrap();
+        }
+
+        let mut folders: Vec<_> = fs::read_dir(&path)
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.file_type().unwrap().is_dir())
+            .map(|entry| entry.file_name().to_str().unwrap().to_string())
+            .collect();
+        folders.sort();
+
+        assert_eq!(folders.len(), 2);
+        assert!(folders[0].starts_with("2-"));
+        assert!(folders[1].starts_with("3-"));
+
+        let store = SignalStore::open(&path).unwrap();
+        assert_eq!(store.version(), 3);
+        assert_eq!(
+            store.get_signal(&NodeID::from(1_u64), StoredSignal::HostCentrality),
+            Some(3.0)
+        );
+    }
+
+    #[test]
+    fn topics() {
+        let path = crate::gen_temp_path();
+        let mut writer = SignalStoreWriter::new(&path, 1);
+
+        let mut topics = TopicVector::default();
+        topics.set(Topic::Science, 0.8);
+        topics.set(Topic::Health, 0.2);
+
+        writer.insert_topics(NodeID::from(1_u64), &topics);
+        writer.insert(NodeID::from(2_u64), StoredSignal::HostCentrality, 1.0);
+
+        let store = writer.finalize().unwrap();
+
+        assert_eq!(store.topics(&NodeID::from(1_u64)), Some(topics));
+        assert_eq!(store.topics(&NodeID::from(2_u64)), None);
+        assert_eq!(
+            store.get_signal(&NodeID::from(1_u64), StoredSignal::Topic(Topic::Science)),
+            Some(0.8_f32 as f64)
+        );
+    }
+
+    #[test]
+    fn security() {
+        let path = crate::gen_temp_path();
+        let mut writer = SignalStoreWriter::new(&path, 1);
+
+        let page = NodeID::from(1_u64);
+        let host = NodeID::from(2_u64);
+        let flagged_host = NodeID::from(3_u64);
+
+        writer.insert_security(
+            page,
+            &SecurityProperties {
+                https: true,
+                hsts: false,
+                mixed_content: true,
+            },
+        );
+        writer.insert(flagged_host, StoredSignal::Malware, 1.0);
+
+        let store = writer.finalize().unwrap();
+
+        assert_eq!(
+            store.security(&page, &host),
+            Some(SecurityAnnotations {
+                https: true,
+                hsts: false,
+                mixed_content: true,
+                flagged_malware: false,
+            })
+        );
+        assert!(
+            store
+                .security(&page, &flagged_host)
+                .unwrap()
+                .flagged_malware
+        );
+        assert_eq!(store.security(&NodeID::from(4_u64), &host), None);
+    }
+
+    #[test]
+    fn empty_store() {
+        let store = SignalStoreWriter::new(crate::gen_temp_path(), 0)
+            .finalize()
+            .unwrap();
+
+        assert!(store.is_empty());
+        assert!(store.get(&NodeID::from(0_u64)).is_none());
+    }
+}
@@ -63,6 +63,11 @@ fn hamming_distance(x: HashType, y: HashType) -> u32 {
     (x ^ y).count_ones()
 }
 
+/// true iff. the two hashes are within `K` distance of each other.
+pub fn is_near_duplicate(x: HashType, y: HashType) -> bool {
+    hamming_distance(x, y) as usize <= K
+}
+
 #[derive(PartialEq, Eq, Hash)]
 struct Prefix(HashType);
 
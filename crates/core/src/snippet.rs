@@ -39,6 +39,145 @@ use whatlang::Lang;
 const K1: f64 = 1.2;
 const B: f64 = 0.75;
 
+/// Options in the retrieval request that let the caller, e.g. a vertical, tune the
+/// snippets. The options that are not set are taken from the snippet config.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SnippetOptions {
+    pub desired_num_chars: Option<usize>,
+    pub sentence_boundaries: Option<bool>,
+    pub adaptive_length: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QueryKind {
+    /// The user wants to know what something is, e.g. `what is rust` or `define entropy`.
+    Definition,
+    /// The user wants to go to a specific site, e.g. `github.com`.
+    Navigational,
+    Other,
+}
+
+impl QueryKind {
+    fn classify(terms: &[String]) -> Self {
+        let lowercased: Vec<_> = terms.iter().map(|term| term.to_lowercase()).collect();
+        let terms: Vec<_> = lowercased.iter().map(String::as_str).collect();
+
+        if terms.len() <= 2 && terms.iter().any(|term| is_url_like(term)) {
+            return QueryKind::Navigational;
+        }
+
+        match terms.as_slice() {
+            ["what" | "who", "is" | "are" | "was" | "were", _, ..]
+            | ["definition" | "meaning", "of", _, ..]
+            | ["define", _, ..]
+            | [_, .., "meaning" | "definition"] => QueryKind::Definition,
+            _ => QueryKind::Other,
+        }
+    }
+}
+
+fn is_url_like(term: &str) -> bool {
+    term.starts_with("http://")
+        || term.starts_with("https://")
+        || term.starts_with("www.")
+        || term.rsplit_once('.').is_some_and(|(host, tld)| {
+            !host.is_empty() && tld.len() >= 2 && tld.chars().all(|c| c.is_ascii_alphabetic())
+        })
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3000}'..='\u{303F}' // punctuation
+        | '\u{3040}'..='\u{30FF}' // hiragana and katakana
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{AC00}'..='\u{D7AF}' // hangul
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FF00}'..='\u{FFEF}' // fullwidth forms
+    )
+}
+
+fn is_cjk_punctuation(c: char) -> bool {
+    "。，、！？；：」』）】》〉．".contains(c)
+}
+
+/// Byte offsets of the ends of the sentences in the text. A period only ends a
+/// sentence if it is followed by whitespace, so numbers and urls are not split.
+fn sentence_ends(text: &str) -> impl Iterator<Item = usize> + '_ {
+    text.char_indices().filter_map(|(i, c)| {
+        let end = i + c.len_utf8();
+
+        match c {
+            '。' | '！' | '？' => Some(end),
+            '.' | '!' | '?'
+                if end == text.len() || text[end..].starts_with(char::is_whitespace) =>
+            {
+                Some(end)
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Whether the text can be cut at the byte offset without splitting a word,
+/// a number, a url or a CJK word.
+fn can_cut(text: &str, offset: usize) -> bool {
+    let (Some(before), Some(after)) = (
+        text[..offset].chars().next_back(),
+        text[offset..].chars().next(),
+    ) else {
+        return true;
+    };
+
+    if after.is_whitespace() {
+        // numbers with spaces between the groups of digits, e.g. `1 000 000`
+        let next = text[offset..].trim_start().chars().next();
+        !(before.is_ascii_digit() && next.is_some_and(|c| c.is_ascii_digit()))
+    } else {
+        // CJK words are not separated by whitespace, so they are only cut after
+        // punctuation or where the text changes between CJK and another script
+        is_cjk_punctuation(before)
+            || (is_cjk(before) != is_cjk(after)
+                && before.is_alphanumeric()
+                && after.is_alphanumeric())
+    }
+}
+
+/// Cut the text to at most `max_chars` characters. If `sentence_boundaries` is set, the text is
+/// cut after the last sentence that ends after `min_chars` characters. Otherwise it is cut at the
+/// last place that does not split a word, a number, a url or a CJK word.
+fn truncate(text: &str, min_chars: usize, max_chars: usize, sentence_boundaries: bool) -> String {
+    let Some((limit, _)) = text.char_indices().nth(max_chars) else {
+        return text.to_string();
+    };
+
+    let min = text
+        .char_indices()
+        .nth(min_chars)
+        .map(|(i, _)| i)
+        .unwrap_or(limit)
+        .min(limit);
+
+    if sentence_boundaries {
+        if let Some(end) = sentence_ends(text)
+            .take_while(|end| *end <= limit)
+            .filter(|end| *end >= min)
+            .last()
+        {
+            return text[..end].trim_end().to_string();
+        }
+    }
+
+    // prefer a cut close to the desired length, but cut earlier rather than inside a word
+    let cut = (1..=limit)
+        .rev()
+        .filter(|offset| text.is_char_boundary(*offset))
+        .find(|offset| can_cut(text, *offset))
+        .unwrap_or(limit);
+
+    text[..cut].trim_end().to_string()
+}
+
 #[derive(Debug)]
 struct PassageCandidate {
     score: f64,
@@ -195,9 +334,19 @@ fn snippet_string_builder(
         })
         .collect();
 
+    let min_num_chars = config
+        .desired_num_chars
+        .saturating_sub(config.delta_num_chars);
+    let max_num_chars = config.desired_num_chars + config.delta_num_chars;
+
     if passages.is_empty() {
         let mut snippet = SnippetBuilder {
-            fragment: text.chars().take(config.desired_num_chars).collect(),
+            fragment: truncate(
+                text,
+                min_num_chars,
+                config.desired_num_chars,
+                config.sentence_boundaries,
+            ),
             highlights: Vec::new(),
         };
 
@@ -256,31 +405,31 @@ fn snippet_string_builder(
         highlights: Vec::new(),
     };
 
-    if snippet.fragment.len() > config.desired_num_chars + config.delta_num_chars {
+    if snippet.fragment.len() > max_num_chars {
         // TODO: find 'desired_num_chars' sized window that contains most highlights
         // instead of taking the prefix of the passage as a snippet
-        snippet.fragment = snippet
-            .fragment
-            .chars()
-            .take(config.desired_num_chars + config.delta_num_chars)
-            .collect();
+        snippet.fragment = truncate(
+            &snippet.fragment,
+            min_num_chars,
+            max_num_chars,
+            config.sentence_boundaries,
+        );
     } else {
         let mut next_passage_idx = best_idx + 1;
 
-        while snippet.fragment.len() < config.desired_num_chars - config.delta_num_chars
-            && next_passage_idx < passages.len()
-        {
+        while snippet.fragment.len() < min_num_chars && next_passage_idx < passages.len() {
             snippet.fragment += " ";
             snippet.fragment += &passages[next_passage_idx].text;
             next_passage_idx += 1;
         }
 
-        if snippet.fragment.len() > config.desired_num_chars + config.delta_num_chars {
-            snippet.fragment = snippet
-                .fragment
-                .chars()
-                .take(config.desired_num_chars + config.delta_num_chars)
-                .collect();
+        if snippet.fragment.len() > max_num_chars {
+            snippet.fragment = truncate(
+                &snippet.fragment,
+                min_num_chars,
+                max_num_chars,
+                config.sentence_boundaries,
+            );
         }
     }
     snippet.highlight(&terms, lang);
@@ -310,7 +459,27 @@ fn snippet_string(
     snippet_string_builder(text, terms, lang, config, tokenizer).build()
 }
 
+/// Longer snippets for definition queries and shorter snippets for navigational queries.
+fn adapt_length(terms: &[String], mut config: SnippetConfig) -> SnippetConfig {
+    let factor = match QueryKind::classify(terms) {
+        QueryKind::Definition => config.definition_length_factor,
+        QueryKind::Navigational => config.navigational_length_factor,
+        QueryKind::Other => return config,
+    };
+
+    config.desired_num_chars = (config.desired_num_chars as f64 * factor).round() as usize;
+    config.delta_num_chars = (config.delta_num_chars as f64 * factor).round() as usize;
+
+    config
+}
+
 pub fn generate(query: &Query, text: &str, region: &Region, config: SnippetConfig) -> TextSnippet {
+    let config = if config.adaptive_length {
+        adapt_length(query.simple_terms(), config)
+    } else {
+        config
+    };
+
     let lang = match region.lang() {
         Some(lang) => lang,
         None => match config.num_words_for_lang_detection {
@@ -574,4 +743,101 @@ Survey in 2016, 2017, and 2018."#;
             "<b>this is</b> a test"
         );
     }
+
+    #[test]
+    fn truncate_at_sentence_boundary() {
+        let text = "First sentence here. Second sentence is a bit longer. Third.";
+
+        assert_eq!(truncate(text, 10, 40, true), "First sentence here.");
+        assert_eq!(truncate(text, 10, 100, true), text);
+
+        // periods in numbers and urls do not end a sentence
+        assert_eq!(
+            truncate("Version 2.0 of example.com was released today", 5, 30, true),
+            "Version 2.0 of example.com was"
+        );
+    }
+
+    #[test]
+    fn truncate_does_not_split_numbers_or_urls() {
+        assert_eq!(
+            truncate("the price is 1 000 000 kr and more", 10, 18, false),
+            "the price is"
+        );
+        assert_eq!(
+            truncate("docs at https://example.com/a/b/c/d are good", 5, 25, false),
+            "docs at"
+        );
+        assert_eq!(
+            truncate("rust is a systems programming language", 0, 12, false),
+            "rust is a"
+        );
+    }
+
+    #[test]
+    fn truncate_cjk() {
+        let text = "我喜欢学习编程语言。我们一起去公园玩吧";
+
+        assert_eq!(truncate(text, 3, 12, false), "我喜欢学习编程语言。");
+        assert_eq!(truncate(text, 3, 12, true), "我喜欢学习编程语言。");
+        assert_eq!(truncate("我用Rust编程很开心", 0, 8, false), "我用Rust");
+    }
+
+    #[test]
+    fn query_kind() {
+        let terms = |query: &str| -> Vec<String> {
+            query.split_whitespace().map(|s| s.to_string()).collect()
+        };
+
+        assert_eq!(
+            QueryKind::classify(&terms("what is rust")),
+            QueryKind::Definition
+        );
+        assert_eq!(
+            QueryKind::classify(&terms("define entropy")),
+            QueryKind::Definition
+        );
+        assert_eq!(
+            QueryKind::classify(&terms("serendipity meaning")),
+            QueryKind::Definition
+        );
+        assert_eq!(
+            QueryKind::classify(&terms("github.com")),
+            QueryKind::Navigational
+        );
+        assert_eq!(
+            QueryKind::classify(&terms("www.example.org login")),
+            QueryKind::Navigational
+        );
+        assert_eq!(
+            QueryKind::classify(&terms("rust language")),
+            QueryKind::Other
+        );
+        assert_eq!(QueryKind::classify(&terms("what")), QueryKind::Other);
+        assert_eq!(QueryKind::classify(&terms("pi 3.14")), QueryKind::Other);
+    }
+
+    #[test]
+    fn adaptive_length() {
+        let config = SnippetConfig::default();
+
+        let definition = adapt_length(&["define".to_string(), "rust".to_string()], config.clone());
+        assert!(definition.desired_num_chars > config.desired_num_chars);
+
+        let navigational = adapt_length(&["github.com".to_string()], config.clone());
+        assert!(navigational.desired_num_chars < config.desired_num_chars);
+
+        let other = adapt_length(&["rust".to_string()], config.clone());
+        assert_eq!(other.desired_num_chars, config.desired_num_chars);
+
+        let options = SnippetOptions {
+            desired_num_chars: Some(100),
+            adaptive_length: Some(false),
+            ..Default::default()
+        };
+        let config = config.with_options(&options);
+        assert_eq!(config.desired_num_chars, 100);
+        assert!(!config.adaptive_length);
+        assert!(config.sentence_boundaries);
+    }
 }
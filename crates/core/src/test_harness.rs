@@ -0,0 +1,478 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! End-to-end harness that runs the whole pipeline on a miniature corpus.
+//!
+//! The pages of the corpus are served over http on localhost, and the crawler fetches them
+//! through the server as a proxy, so the pages keep their own hosts without touching the
+//! network. The crawled pages go through the same steps as in the cluster: the webgraphs are
+//! built from the crawl, the centralities from the webgraphs, and the index from the crawl and
+//! the centralities. The index is served by a local searcher. Everything happens in-process in
+//! a temporary folder, so changes that cut across the steps can be tested without a cluster.
+//!
+//! Available in the tests of the crate, and to other crates with the `test-harness` feature.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    response::{Html, IntoResponse, Response},
+};
+use url::Url;
+
+use crate::{
+    config::{defaults, CrawlerConfig, LocalConfig, S3Config, UserAgent, WarcSource},
+    crawler::{CrawlDatum, DatumStream, Domain, JobExecutor, WeightedUrl},
+    entrypoint::{
+        indexer::{self, IndexingWorker, JobSettings},
+        webgraph::{self, WebgraphWorker},
+        Centrality,
+    },
+    index::Index,
+    ranking::inbound_similarity::InboundSimilarity,
+    searcher::{LocalSearcher, SearchQuery},
+    warc,
+    webgraph::{edge_weight::EdgeWeights, Webgraph, WebgraphBuilder},
+    Result,
+};
+
+const USER_AGENT: &str = "StractHarness";
+
+pub struct Page {
+    pub url: &'static str,
+    pub html: &'static str,
+}
+
+/// Pages and robots.txt files of a handful of hosts.
+pub struct Corpus {
+    pub pages: Vec<Page>,
+    /// robots.txt of each host. Hosts without one respond with 404.
+    pub robots_txt: HashMap<&'static str, &'static str>,
+}
+
+impl Corpus {
+    /// The corpus in `testcases/corpus`. The gardening hosts link to each other, most of them
+    /// to tomatoguide.com, and tomatoguide.com disallows a page in its robots.txt.
+    pub fn bundled() -> Self {
+        Self {
+            pages: vec![
+                Page {
+                    url: "http://gardenhub.com/",
+                    html: include_str!("../testcases/corpus/gardenhub_index.html"),
+                },
+                Page {
+                    url: "http://gardenhub.com/about",
+                    html: include_str!("../testcases/corpus/gardenhub_about.html"),
+                },
+                Page {
+                    url: "http://tomatoguide.com/",
+                    html: include_str!("../testcases/corpus/tomatoguide_index.html"),
+                },
+                Page {
+                    url: "http://tomatoguide.com/growing",
+                    html: include_str!("../testcases/corpus/tomatoguide_growing.html"),
+                },
+                Page {
+                    url: "http://tomatoguide.com/private/drafts",
+                    html: include_str!("../testcases/corpus/tomatoguide_drafts.html"),
+                },
+                Page {
+                    url: "http://basilbasics.com/",
+                    html: include_str!("../testcases/corpus/basilbasics_index.html"),
+                },
+                Page {
+                    url: "http://veggieforum.com/",
+                    html: include_str!("../testcases/corpus/veggieforum_index.html"),
+                },
+            ],
+            robots_txt: [(
+                "tomatoguide.com",
+                include_str!("../testcases/corpus/tomatoguide_robots.txt"),
+            )]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    /// The homepage of each host, which are the seeds of the crawl.
+    fn homepages(&self) -> Vec<Url> {
+        let mut homepages: Vec<_> = self
+            .pages
+            .iter()
+            .filter_map(|page| Url::parse(page.url).ok())
+            .filter_map(|url| url.join("/").ok())
+            .collect();
+
+        homepages.sort();
+        homepages.dedup();
+
+        homepages
+    }
+
+    fn respond(&self, method: &Method, uri: &Uri, headers: &HeaderMap) -> Response {
+        // the crawler tries https first, which is a CONNECT request through the proxy
+        if method != Method::GET {
+            return StatusCode::METHOD_NOT_ALLOWED.into_response();
+        }
+
+        let host = uri
+            .host()
+            .or_else(|| headers.get(header::HOST).and_then(|h| h.to_str().ok()))
+            .unwrap_or_default();
+        let host = host.split(':').next().unwrap_or_default();
+
+        if uri.path() == "/robots.txt" {
+            return match self.robots_txt.get(host) {
+                Some(robots_txt) => {
+                    ([(header::CONTENT_TYPE, "text/plain")], *robots_txt).into_response()
+                }
+                None => StatusCode::NOT_FOUND.into_response(),
+            };
+        }
+
+        let page = self.pages.iter().find(|page| {
+            Url::parse(page.url)
+                .map(|url| url.host_str() == Some(host) && url.path() == uri.path())
+                .unwrap_or(false)
+        });
+
+        match page {
+            Some(page) => Html(page.html).into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+}
+
+/// Serve the corpus on localhost. The returned address is used as an http proxy.
+async fn serve(corpus: Arc<Corpus>) -> Result<SocketAddr> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+
+    let app = axum::Router::new().fallback(move |method: Method, uri: Uri, headers: HeaderMap| {
+        let corpus = Arc::clone(&corpus);
+        async move { corpus.respond(&method, &uri, &headers) }
+    });
+
+    tokio::spawn(async move {
+        axum::serve(listener, app.into_make_service()).await.ok();
+    });
+
+    Ok(addr)
+}
+
+/// Writes the crawled pages to a local warc file.
+struct LocalWarc {
+    writer: Mutex<Option<warc::DeduplicatedWarcWriter>>,
+    path: PathBuf,
+    urls: Mutex<Vec<String>>,
+}
+
+impl DatumStream for LocalWarc {
+    async fn write(&self, datum: CrawlDatum) -> Result<()> {
+        let record = warc::WarcRecord {
+            request: warc::Request {
+                url: datum.url.to_string(),
+            },
+            response: warc::Response {
+                body: datum.body,
+                payload_type: Some(datum.payload_type),
+            },
+            metadata: warc::Metadata {
+                fetch_time_ms: datum.fetch_time_ms,
+                security: Some(datum.security),
+            },
+        };
+
+        if let Some(writer) = self.writer.lock().unwrap().as_mut() {
+            writer.write(&record)?;
+        }
+        self.urls.lock().unwrap().push(datum.url.to_string());
+
+        Ok(())
+    }
+
+    async fn finish(&self) -> Result<()> {
+        if let Some(writer) = self.writer.lock().unwrap().take() {
+            std::fs::write(&self.path, writer.finish()?)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn crawler_config() -> CrawlerConfig {
+    CrawlerConfig {
+        num_worker_threads: 1,
+        user_agent: UserAgent {
+            full: USER_AGENT.to_string(),
+            token: USER_AGENT.to_string(),
+        },
+        robots_txt_cache_sec: defaults::Crawler::robots_txt_cache_sec(),
+        politeness_factor: 0.0,
+        min_crawl_delay_ms: 0,
+        max_crawl_delay_ms: 0,
+        max_politeness_factor: defaults::Crawler::max_politeness_factor(),
+        max_url_slowdown_retry: defaults::Crawler::max_url_slowdown_retry(),
+        max_redirects: defaults::Crawler::max_redirects(),
+        dry_run: false,
+        timeout_seconds: 10,
+        s3: S3Config {
+            bucket: String::new(),
+            folder: String::new(),
+            access_key: String::new(),
+            secret_key: String::new(),
+            endpoint: String::new(),
+        },
+        router_hosts: Vec::new(),
+        trap_detection: Default::default(),
+        max_host_error_streak: defaults::Crawler::max_host_error_streak(),
+        host_outcomes_path: None,
+        url_outcomes_path: None,
+        event_log: None,
+        screenshots: None,
+        tls: None,
+    }
+}
+
+/// Crawl the corpus from the homepages of its hosts and return the crawled urls.
+fn crawl(corpus: Corpus, warc_path: &Path) -> Result<Vec<String>> {
+    let corpus = Arc::new(corpus);
+    let config = Arc::new(crawler_config());
+    let writer = Arc::new(LocalWarc {
+        writer: Mutex::new(Some(warc::DeduplicatedWarcWriter::new())),
+        path: warc_path.to_path_buf(),
+        urls: Mutex::new(Vec::new()),
+    });
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(async {
+            let proxy = serve(Arc::clone(&corpus)).await?;
+
+            let client = reqwest::Client::builder()
+                .proxy(reqwest::Proxy::all(format!("http://{proxy}"))?)
+                .user_agent(USER_AGENT)
+                .timeout(std::time::Duration::from_secs(config.timeout_seconds))
+                .build()?;
+
+            for homepage in corpus.homepages() {
+                let job = crate::crawler::Job {
+                    domain: Domain::from(&homepage),
+                    urls: VecDeque::from([WeightedUrl {
+                        url: homepage,
+                        weight: 1.0,
+                    }]),
+                    wandering_urls: corpus.pages.len() as u64,
+                };
+
+                JobExecutor::new(
+                    job.into(),
+                    client.clone(),
+                    Arc::clone(&config),
+                    Arc::clone(&writer),
+                )
+                .run()
+                .await;
+            }
+
+            writer.finish().await
+        })?;
+
+    let mut urls = writer.urls.lock().unwrap().clone();
+    urls.sort();
+
+    Ok(urls)
+}
+
+fn build_webgraphs(warc_path: &str, host_path: &Path, page_path: &Path) {
+    let job = webgraph::Job {
+        config: webgraph::JobConfig::Local(LocalConfig {
+            folder: ".".to_string(),
+            names: vec![warc_path.to_string()],
+        }),
+        warc_paths: vec![warc_path.to_string()],
+    };
+
+    let mut worker = WebgraphWorker {
+        host_graph: webgraph::open_host_graph_writer(host_path),
+        page_graph: webgraph::open_page_graph_writer(page_path),
+        edge_weights: EdgeWeights::default(),
+    };
+
+    worker.process_job(&job);
+
+    worker.host_graph.finalize();
+    worker.page_graph.finalize();
+}
+
+fn build_index(
+    warc_path: &str,
+    base_path: &Path,
+    centrality_path: &Path,
+    page_centrality_path: &Path,
+    page_graph_path: &Path,
+) -> Index {
+    let job = indexer::Job {
+        source_config: WarcSource::Local(LocalConfig {
+            folder: ".".to_string(),
+            names: vec![warc_path.to_string()],
+        }),
+        warc_paths: vec![warc_path.to_string()],
+        base_path: base_path.to_str().unwrap().to_string(),
+        settings: JobSettings {
+            host_centrality_threshold: None,
+            minimum_clean_words: None,
+        },
+    };
+
+    let worker = IndexingWorker::new(
+        centrality_path.to_str().unwrap().to_string(),
+        Some(page_centrality_path.to_str().unwrap().to_string()),
+        Some(page_graph_path.to_str().unwrap().to_string()),
+        None,
+        None,
+    );
+
+    indexer::process_job(&job, &worker)
+}
+
+/// The output of each step of the pipeline for a corpus.
+pub struct Harness {
+    path: PathBuf,
+    crawled_urls: Vec<String>,
+    searcher: LocalSearcher<Index>,
+}
+
+impl Harness {
+    /// Crawl the corpus, build the webgraphs, centralities and index, and serve the index.
+    pub fn build(corpus: Corpus) -> Result<Self> {
+        let path = crate::gen_temp_path();
+        std::fs::create_dir_all(&path)?;
+
+        let warc_path = path.join("crawl.warc.gz");
+        let crawled_urls = crawl(corpus, &warc_path)?;
+        let warc_path = warc_path.to_str().unwrap().to_string();
+
+        let host_graph_path = path.join("webgraph_host");
+        let page_graph_path = path.join("webgraph_page");
+        build_webgraphs(&warc_path, &host_graph_path, &page_graph_path);
+
+        let centrality_path = path.join("centrality");
+        let page_centrality_path = path.join("centrality_page");
+        Centrality::build_harmonic(&host_graph_path, &centrality_path);
+        Centrality::build_similarity(&host_graph_path, &centrality_path);
+        Centrality::build_approx_harmonic(&page_graph_path, &page_centrality_path)?;
+
+        let mut index = build_index(
+            &warc_path,
+            &path.join("index"),
+            &centrality_path,
+            &page_centrality_path,
+            &page_graph_path,
+        );
+        index.inverted_index.merge_into_max_segments(1)?;
+
+        let mut searcher = LocalSearcher::new(index);
+        searcher.set_inbound_similarity(InboundSimilarity::open(
+            centrality_path.join("inbound_similarity"),
+        )?);
+
+        Ok(Self {
+            path,
+            crawled_urls,
+            searcher,
+        })
+    }
+
+    /// The urls that were fetched by the crawler, sorted.
+    pub fn crawled_urls(&self) -> &[String] {
+        &self.crawled_urls
+    }
+
+    pub fn searcher(&self) -> &LocalSearcher<Index> {
+        &self.searcher
+    }
+
+    /// The urls of the results for the query, in the order they are ranked.
+    pub fn search(&self, query: &str) -> Result<Vec<String>> {
+        Ok(self
+            .searcher
+            .search(&SearchQuery {
+                query: query.to_string(),
+                ..Default::default()
+            })?
+            .webpages
+            .into_iter()
+            .map(|webpage| webpage.url)
+            .collect())
+    }
+
+    pub fn host_graph(&self) -> Webgraph {
+        WebgraphBuilder::new(self.path.join("webgraph_host"))
+            .single_threaded()
+            .open()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::webgraph::Node;
+
+    use super::*;
+
+    #[test]
+    fn end_to_end() {
+        let harness = Harness::build(Corpus::bundled()).unwrap();
+
+        // every page is reachable from a homepage, but the drafts are disallowed in robots.txt
+        assert_eq!(
+            harness.crawled_urls(),
+            &[
+                "http://basilbasics.com/",
+                "http://gardenhub.com/",
+                "http://gardenhub.com/about",
+                "http://tomatoguide.com/",
+                "http://tomatoguide.com/growing",
+                "http://veggieforum.com/",
+            ]
+        );
+        assert!(harness.search("unpublished").unwrap().is_empty());
+
+        let ingoing: Vec<_> = harness
+            .host_graph()
+            .ingoing_edges(Node::from("tomatoguide.com").into_host())
+            .into_iter()
+            .map(|edge| edge.from.name)
+            .collect();
+        assert!(ingoing.contains(&"gardenhub.com".to_string()));
+        assert!(ingoing.contains(&"veggieforum.com".to_string()));
+
+        assert_eq!(
+            harness.search("heirloom").unwrap(),
+            vec!["http://tomatoguide.com/growing".to_string()]
+        );
+
+        let tomatoes = harness.search("tomatoes").unwrap();
+        assert!(tomatoes.len() > 2);
+        assert!(tomatoes[0].starts_with("http://tomatoguide.com/"));
+    }
+}
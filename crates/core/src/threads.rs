@@ -0,0 +1,193 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Construction of the thread pools used by the different services from their
+//! [`ThreadPoolConfig`]. By default every pool uses all the cores of the machine,
+//! which makes pools contend with each other on large machines that run
+//! several services. Each pool can therefore be sized and pinned to a set of
+//! cores.
+
+use std::sync::Arc;
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::{config::ThreadPoolConfig, Result};
+
+pub fn num_threads(config: &ThreadPoolConfig) -> usize {
+    config
+        .num_threads
+        .or_else(|| config.pin_cores.as_ref().map(|cores| cores.len()))
+        .filter(|num_threads| *num_threads > 0)
+        .unwrap_or_else(num_cpus::get)
+}
+
+/// Number of cores that fit in a `cpu_set_t`. `CPU_SET` panics for the cores above it.
+#[cfg(target_os = "linux")]
+const MAX_CORES: usize = libc::CPU_SETSIZE as usize;
+
+#[cfg(target_os = "linux")]
+fn check_cores(cores: &[usize]) -> std::io::Result<()> {
+    match cores.iter().find(|core| **core >= MAX_CORES) {
+        Some(core) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("core {core} is out of range, the core ids must be below {MAX_CORES}"),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Restrict the current thread to run on the given cores.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cores: &[usize]) -> std::io::Result<()> {
+    check_cores(cores)?;
+
+    // SAFETY: `set` is a valid, zero-initialized cpu_set_t that lives for the
+    // duration of the call and pid 0 refers to the calling thread.
+    let res = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+
+        for core in cores {
+            libc::CPU_SET(*core, &mut set);
+        }
+
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+
+    if res != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cores: &[usize]) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "core pinning is only supported on linux",
+    ))
+}
+
+/// Pins the threads of a pool to the configured cores. Fails if a core is out of range,
+/// so a misconfigured pool is not started.
+fn start_handler(config: &ThreadPoolConfig) -> Result<Option<Arc<dyn Fn() + Send + Sync>>> {
+    let Some(cores) = config.pin_cores.clone() else {
+        return Ok(None);
+    };
+
+    if cores.is_empty() {
+        return Ok(None);
+    }
+
+    #[cfg(target_os = "linux")]
+    check_cores(&cores)?;
+
+    Ok(Some(Arc::new(move || {
+        if let Err(e) = pin_current_thread(&cores) {
+            tracing::warn!("failed to pin thread to cores {:?}: {}", cores, e);
+        }
+    })))
+}
+
+/// Multi threaded tokio runtime with the worker threads configured by `config`.
+pub fn tokio_runtime(config: &ThreadPoolConfig) -> Result<tokio::runtime::Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all().worker_threads(num_threads(config));
+
+    if let Some(handler) = start_handler(config)? {
+        builder.on_thread_start(move || handler());
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Rayon thread pool configured by `config`.
+pub fn rayon_pool(config: &ThreadPoolConfig, prefix: &'static str) -> Result<ThreadPool> {
+    let mut builder = ThreadPoolBuilder::new()
+        .num_threads(num_threads(config))
+        .thread_name(move |num| format!("{prefix}{num}"));
+
+    if let Some(handler) = start_handler(config)? {
+        builder = builder.start_handler(move |_| handler());
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Configure the global rayon pool. This must be called before the global
+/// pool is used for the first time.
+pub fn init_global_rayon(config: &ThreadPoolConfig) -> Result<()> {
+    let mut builder = ThreadPoolBuilder::new().num_threads(num_threads(config));
+
+    if let Some(handler) = start_handler(config)? {
+        builder = builder.start_handler(move |_| handler());
+    }
+
+    builder.build_global()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_size() {
+        let config = ThreadPoolConfig {
+            num_threads: Some(3),
+            pin_cores: None,
+        };
+        assert_eq!(num_threads(&config), 3);
+
+        let config = ThreadPoolConfig {
+            num_threads: None,
+            pin_cores: Some(vec![0, 1]),
+        };
+        assert_eq!(num_threads(&config), 2);
+
+        assert_eq!(num_threads(&ThreadPoolConfig::default()), num_cpus::get());
+
+        let pool = rayon_pool(
+            &ThreadPoolConfig {
+                num_threads: Some(2),
+                pin_cores: None,
+            },
+            "test-pool-",
+        )
+        .unwrap();
+        assert_eq!(pool.current_num_threads(), 2);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn out_of_range_cores() {
+        assert_eq!(
+            pin_current_thread(&[MAX_CORES]).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+
+        assert!(rayon_pool(
+            &ThreadPoolConfig {
+                num_threads: None,
+                pin_cores: Some(vec![0, MAX_CORES]),
+            },
+            "test-pool-",
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,231 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Soft-deleted documents of a search index.
+//!
+//! A tombstoned document is excluded from the search results, but it is still in its
+//! segment and can be restored. The tombstones are keyed by the id of the segment, as
+//! the document ids change when segments are merged. The tombstoned documents are deleted
+//! for real before the segments are merged, so the tombstones only live until the next merge.
+//! The index does not merge segments in the background while it has tombstones.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    path::Path,
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use tantivy::{
+    query::{EnableScoring, Explanation, Query, Scorer, Weight},
+    DocId, DocSet, Score, SegmentId, SegmentReader, TERMINATED,
+};
+
+use crate::Result;
+
+const TOMBSTONES_FILE_NAME: &str = "tombstones.json";
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tombstones {
+    segments: BTreeMap<String, Arc<BTreeSet<DocId>>>,
+}
+
+impl Tombstones {
+    /// Open the tombstones of the index in the folder. There are no tombstones if the file does not exist.
+    pub fn open<P: AsRef<Path>>(folder: P) -> Result<Self> {
+        let path = folder.as_ref().join(TOMBSTONES_FILE_NAME);
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, folder: P) -> Result<()> {
+        let path = folder.as_ref().join(TOMBSTONES_FILE_NAME);
+
+        if self.is_empty() {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+
+            return Ok(());
+        }
+
+        fs::write(path, serde_json::to_string(self)?)?;
+
+        Ok(())
+    }
+
+    pub fn insert(&mut self, segment: SegmentId, doc: DocId) {
+        Arc::make_mut(self.segments.entry(segment.uuid_string()).or_default()).insert(doc);
+    }
+
+    /// The tombstoned documents of the segment.
+    pub fn segment(&self, segment: SegmentId) -> Option<Arc<BTreeSet<DocId>>> {
+        self.segments.get(&segment.uuid_string()).cloned()
+    }
+
+    pub fn contains(&self, segment: SegmentId, doc: DocId) -> bool {
+        self.segments
+            .get(&segment.uuid_string())
+            .map(|docs| docs.contains(&doc))
+            .unwrap_or(false)
+    }
+
+    /// Drop the tombstones of segments that are no longer in the index.
+    pub fn retain_segments(&mut self, segments: &[SegmentId]) {
+        let segments: HashSet<_> = segments.iter().map(|id| id.uuid_string()).collect();
+        self.segments.retain(|id, _| segments.contains(id));
+    }
+
+    pub fn extend(&mut self, other: &Tombstones) {
+        for (segment, docs) in &other.segments {
+            Arc::make_mut(self.segments.entry(segment.clone()).or_default())
+                .extend(docs.iter().copied());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.values().map(|docs| docs.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Matches the tombstoned documents, so they can be deleted by their address
+/// with [`tantivy::IndexWriter::delete_query`].
+#[derive(Debug, Clone)]
+pub struct TombstoneQuery {
+    tombstones: Tombstones,
+}
+
+impl TombstoneQuery {
+    pub fn new(tombstones: Tombstones) -> Self {
+        Self { tombstones }
+    }
+}
+
+impl Query for TombstoneQuery {
+    fn weight(&self, _: EnableScoring) -> tantivy::Result<Box<dyn Weight>> {
+        Ok(Box::new(TombstoneWeight {
+            tombstones: self.tombstones.clone(),
+        }))
+    }
+}
+
+struct TombstoneWeight {
+    tombstones: Tombstones,
+}
+
+impl Weight for TombstoneWeight {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> tantivy::Result<Box<dyn Scorer>> {
+        let docs = self
+            .tombstones
+            .segment(reader.segment_id())
+            .map(|docs| {
+                docs.iter()
+                    .copied()
+                    .filter(|doc| *doc < reader.max_doc())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Box::new(DocsScorer {
+            docs,
+            pos: 0,
+            score: boost,
+        }))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> tantivy::Result<Explanation> {
+        if !self.tombstones.contains(reader.segment_id(), doc) {
+            return Err(tantivy::TantivyError::InvalidArgument(format!(
+                "Document #({doc}) is not tombstoned"
+            )));
+        }
+
+        Ok(Explanation::new("Tombstoned", 1.0))
+    }
+}
+
+/// Iterates a sorted list of documents.
+struct DocsScorer {
+    docs: Vec<DocId>,
+    pos: usize,
+    score: Score,
+}
+
+impl Scorer for DocsScorer {
+    fn score(&mut self) -> Score {
+        self.score
+    }
+}
+
+impl DocSet for DocsScorer {
+    fn advance(&mut self) -> DocId {
+        if self.pos < self.docs.len() {
+            self.pos += 1;
+        }
+
+        self.doc()
+    }
+
+    fn doc(&self) -> DocId {
+        self.docs.get(self.pos).copied().unwrap_or(TERMINATED)
+    }
+
+    fn size_hint(&self) -> u32 {
+        self.docs.len() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_open() {
+        let folder = crate::gen_temp_path();
+        fs::create_dir_all(&folder).unwrap();
+
+        let a = SegmentId::generate_random();
+        let b = SegmentId::generate_random();
+
+        let mut tombstones = Tombstones::default();
+        tombstones.insert(a, 1);
+        tombstones.insert(a, 3);
+        tombstones.insert(b, 0);
+        tombstones.save(&folder).unwrap();
+
+        let mut opened = Tombstones::open(&folder).unwrap();
+        assert_eq!(opened, tombstones);
+        assert!(opened.contains(a, 3));
+        assert!(!opened.contains(a, 2));
+        assert_eq!(opened.len(), 3);
+
+        opened.retain_segments(&[b]);
+        assert_eq!(opened.len(), 1);
+        assert!(!opened.contains(a, 1));
+
+        Tombstones::default().save(&folder).unwrap();
+        assert!(Tombstones::open(&folder).unwrap().is_empty());
+    }
+}
@@ -72,6 +72,18 @@ impl<K: Hash + Eq + Clone, V> TTLCache<K, V> {
         })
     }
 
+    /// All the entries, including the entries that have expired but are not pruned yet.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.data.values()
+    }
+
+    /// Remove the oldest entry.
+    pub fn pop_oldest(&mut self) -> Option<V> {
+        let front = self.insertion_order.pop_front()?;
+        self.insertion_times.remove(&front);
+        self.data.remove(&front)
+    }
+
     fn prune_old_entries(&mut self) {
         let current_time = SystemTime::now();
 
@@ -141,4 +153,17 @@ mod tests {
         assert_eq!(cache.get(&0), None);
         assert_eq!(cache.get(&1), Some(&1));
     }
+
+    #[test]
+    fn pop_oldest() {
+        let mut cache = TTLCache::with_ttl(Duration::from_secs(60));
+
+        cache.insert(0, 0);
+        cache.insert(1, 1);
+
+        assert_eq!(cache.pop_oldest(), Some(0));
+        assert_eq!(cache.values().collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(cache.pop_oldest(), Some(1));
+        assert_eq!(cache.pop_oldest(), None);
+    }
 }
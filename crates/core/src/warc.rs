@@ -15,6 +15,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::distributed::retry_strategy::ExponentialBackoff;
+use crate::security::SecurityProperties;
 use crate::{config::S3Config, config::WarcSource, Error, Result};
 use std::collections::BTreeMap;
 use std::fmt::Display;
@@ -81,10 +82,7 @@ impl WarcFile {
     }
 
     pub fn records(&self) -> RecordIterator<&[u8]> {
-        RecordIterator {
-            reader: BufReader::new(MultiGzDecoder::new(&self.bytes[..])),
-            num_reads: 0,
-        }
+        RecordIterator::new(&self.bytes[..])
     }
 
     pub(crate) fn download(source: &WarcSource, warc_path: &str) -> Result<Self> {
@@ -205,11 +203,28 @@ impl WarcFile {
 }
 
 #[derive(Debug)]
-struct RawWarcRecord {
+pub struct RawWarcRecord {
     header: BTreeMap<String, String>,
     content: Vec<u8>,
 }
 
+impl RawWarcRecord {
+    /// Header names are case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.header
+            .get(&name.to_uppercase())
+            .map(|value| value.as_str())
+    }
+
+    pub fn warc_type(&self) -> Option<&str> {
+        self.header("WARC-Type")
+    }
+
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+}
+
 #[derive(Debug)]
 #[cfg_attr(test, derive(Clone, Arbitrary, PartialEq))]
 pub struct WarcRecord {
@@ -237,8 +252,9 @@ impl Request {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(test, derive(Arbitrary))]
+#[serde(rename_all = "snake_case")]
 pub enum PayloadType {
     Html,
     Pdf,
@@ -305,26 +321,46 @@ impl Response {
 pub struct Metadata {
     // fetchTimeMs
     pub fetch_time_ms: u64,
+    // https, hsts and mixedContent
+    pub security: Option<SecurityProperties>,
 }
 
 impl Metadata {
     fn from_raw(record: RawWarcRecord) -> Result<Self> {
         let r = BufReader::new(&record.content[..]);
 
+        let mut fetch_time_ms = None;
+        let mut security: Option<SecurityProperties> = None;
+
         for line in r.lines() {
             let mut line = line?;
             if let Some(semi) = line.find(':') {
                 let value = line.split_off(semi + 1).trim().to_string();
                 line.pop(); // remove colon
                 let key = line;
-                if key == "fetchTimeMs" {
-                    let fetch_time_ms = value.parse::<u64>()?;
-                    return Ok(Self { fetch_time_ms });
+
+                match key.as_str() {
+                    "fetchTimeMs" => fetch_time_ms = Some(value.parse::<u64>()?),
+                    "https" => {
+                        security.get_or_insert_with(Default::default).https = value == "true"
+                    }
+                    "hsts" => security.get_or_insert_with(Default::default).hsts = value == "true",
+                    "mixedContent" => {
+                        security.get_or_insert_with(Default::default).mixed_content =
+                            value == "true"
+                    }
+                    _ => {}
                 }
             }
         }
 
-        Err(Error::WarcParse("Failed to parse metadata".to_string()).into())
+        match fetch_time_ms {
+            Some(fetch_time_ms) => Ok(Self {
+                fetch_time_ms,
+                security,
+            }),
+            None => Err(Error::WarcParse("Failed to parse metadata".to_string()).into()),
+        }
     }
 }
 
@@ -334,6 +370,20 @@ pub struct RecordIterator<R: Read> {
 }
 
 impl<R: Read> RecordIterator<R> {
+    /// Read the records from a gzipped warc file as it is streamed.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(MultiGzDecoder::new(reader)),
+            num_reads: 0,
+        }
+    }
+
+    /// Iterate all the records in the file regardless of their type,
+    /// e.g. the `conversion` records in WET files or `metadata` records in WAT files.
+    pub fn raw(self) -> RawRecordIterator<R> {
+        RawRecordIterator { inner: self }
+    }
+
     fn next_raw(&mut self) -> Option<Result<RawWarcRecord>> {
         let mut version = String::new();
 
@@ -522,6 +572,18 @@ impl<R: Read> Iterator for RecordIterator<R> {
     }
 }
 
+pub struct RawRecordIterator<R: Read> {
+    inner: RecordIterator<R>,
+}
+
+impl<R: Read> Iterator for RawRecordIterator<R> {
+    type Item = Result<RawWarcRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next_raw()
+    }
+}
+
 pub struct DeduplicatedWarcWriter {
     writer: WarcWriter,
     seen_url_hashes: FnvHashSet<md5::Digest>,
@@ -634,7 +696,15 @@ impl WarcWriter {
         self.writer
             .write_all("WARC-Type: metadata\r\n".as_bytes())?;
 
-        let body = format!("fetchTimeMs: {}", record.metadata.fetch_time_ms);
+        let mut body = format!("fetchTimeMs: {}", record.metadata.fetch_time_ms);
+
+        if let Some(security) = &record.metadata.security {
+            body.push_str(&format!(
+                "\r\nhttps: {}\r\nhsts: {}\r\nmixedContent: {}",
+                security.https, security.hsts, security.mixed_content
+            ));
+        }
+
         let content_len = body.len();
 
         self.writer
@@ -756,6 +826,11 @@ mod tests {
             },
             metadata: Metadata {
                 fetch_time_ms: 1337,
+                security: Some(SecurityProperties {
+                    https: true,
+                    hsts: true,
+                    mixed_content: false,
+                }),
             },
         };
         writer.write(&record1).unwrap();
@@ -770,6 +845,7 @@ mod tests {
             },
             metadata: Metadata {
                 fetch_time_ms: 4242,
+                security: None,
             },
         };
         writer.write(&record2).unwrap();
@@ -785,10 +861,19 @@ mod tests {
         assert_eq!(&records[0].request.url, "https://a.com");
         assert_eq!(&records[0].response.body, "body of a");
         assert_eq!(records[0].metadata.fetch_time_ms, 1337);
+        assert_eq!(
+            records[0].metadata.security,
+            Some(SecurityProperties {
+                https: true,
+                hsts: true,
+                mixed_content: false,
+            })
+        );
 
         assert_eq!(&records[1].request.url, "https://b.com");
         assert_eq!(&records[1].response.body, "body of b");
         assert_eq!(records[1].metadata.fetch_time_ms, 4242);
+        assert_eq!(records[1].metadata.security, None);
     }
 
     #[test]
@@ -804,7 +889,10 @@ mod tests {
                 body: utf8.to_string(),
                 payload_type: Some(PayloadType::Html),
             },
-            metadata: Metadata { fetch_time_ms: 0 },
+            metadata: Metadata {
+                fetch_time_ms: 0,
+                security: None,
+            },
         };
         writer.write(&record).unwrap();
 
@@ -836,7 +924,10 @@ mod tests {
                 body: body.to_string(),
                 payload_type: Some(PayloadType::Html),
             },
-            metadata: Metadata { fetch_time_ms: 0 },
+            metadata: Metadata {
+                fetch_time_ms: 0,
+                security: None,
+            },
         };
         writer.write(&record).unwrap();
 
@@ -18,6 +18,7 @@
 //! http://static.googleusercontent.com/media/research.google.com/en/us/pubs/archive/36180.pdf
 //! from google.
 mod error_model;
+mod segmentation;
 pub mod spell_checker;
 mod stupid_backoff;
 mod term_freqs;
@@ -0,0 +1,183 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Segmentation of terms that were typed without spaces, e.g. `rustasyncruntime`.
+//!
+//! The segmentations are found with a beam search from the start of the term, where every
+//! prefix of the rest of the term that is in the dictionary is a candidate for the next word.
+//! The segmentations are scored by the language model. The term itself is scored as an
+//! unknown word, whose probability falls with its length (see http://norvig.com/ngrams/),
+//! so a long run-together term loses to a segmentation into common words.
+
+/// The number of partial segmentations that are kept for each position in the term.
+const BEAM_WIDTH: usize = 8;
+
+/// The longest word in a segmentation, in characters.
+const MAX_WORD_CHARS: usize = 24;
+
+/// Terms with fewer characters are never segmented.
+pub const MIN_SEGMENTED_CHARS: usize = 6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segmentation {
+    pub words: Vec<String>,
+    pub log_prob: f64,
+}
+
+/// The log probability of a word that is not in the dictionary. `min_log_prob` is the
+/// log probability of the rarest word, and each character makes the word 10 times less likely.
+pub fn unknown_word_log_prob(word: &str, min_log_prob: f64) -> f64 {
+    min_log_prob - 10f64.log2() * (word.chars().count() as f64 - 1.0)
+}
+
+/// The most likely segmentation of the term into at least two words.
+///
+/// `log_prob` is the log probability of the last word given the words before it,
+/// which is either a single word or the previous word followed by the word.
+pub fn segment(
+    term: &str,
+    is_word: impl Fn(&str) -> bool,
+    log_prob: impl Fn(&[String]) -> f64,
+) -> Option<Segmentation> {
+    let boundaries: Vec<usize> = term
+        .char_indices()
+        .map(|(idx, _)| idx)
+        .chain(std::iter::once(term.len()))
+        .collect();
+    let num_chars = boundaries.len() - 1;
+
+    // the partial segmentations that end after each character
+    let mut beams: Vec<Vec<Segmentation>> = vec![Vec::new(); num_chars + 1];
+    beams[0].push(Segmentation {
+        words: Vec::new(),
+        log_prob: 0.0,
+    });
+
+    for start in 0..num_chars {
+        let beam = std::mem::take(&mut beams[start]);
+
+        if beam.is_empty() {
+            continue;
+        }
+
+        for end in start + 1..=(start + MAX_WORD_CHARS).min(num_chars) {
+            let word = &term[boundaries[start]..boundaries[end]];
+
+            if !is_word(word) {
+                continue;
+            }
+
+            for segmentation in &beam {
+                let context = match segmentation.words.last() {
+                    Some(prev) => vec![prev.clone(), word.to_string()],
+                    None => vec![word.to_string()],
+                };
+
+                let mut words = segmentation.words.clone();
+                words.push(word.to_string());
+
+                beams[end].push(Segmentation {
+                    words,
+                    log_prob: segmentation.log_prob + log_prob(&context),
+                });
+            }
+
+            beams[end].sort_by(|a, b| b.log_prob.total_cmp(&a.log_prob));
+            beams[end].truncate(BEAM_WIDTH);
+        }
+    }
+
+    beams
+        .pop()?
+        .into_iter()
+        .find(|segmentation| segmentation.words.len() > 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// A unigram model where the log probability of a word is its frequency in a corpus of 1024 words.
+    fn model() -> HashMap<&'static str, f64> {
+        [
+            ("rust", 8.0),
+            ("async", 4.0),
+            ("runtime", 4.0),
+            ("run", 16.0),
+            ("time", 16.0),
+            ("as", 32.0),
+            ("sync", 4.0),
+            ("new", 32.0),
+            ("york", 8.0),
+        ]
+        .into_iter()
+        .map(|(word, freq)| (word, (freq / 1024.0f64).log2()))
+        .collect()
+    }
+
+    fn segment_with(term: &str, model: &HashMap<&'static str, f64>) -> Option<Segmentation> {
+        segment(
+            term,
+            |word| model.contains_key(word),
+            |words| model[words.last().unwrap().as_str()],
+        )
+    }
+
+    #[test]
+    fn run_together_words() {
+        let model = model();
+
+        assert_eq!(
+            segment_with("rustasyncruntime", &model).unwrap().words,
+            vec!["rust", "async", "runtime"]
+        );
+        assert_eq!(
+            segment_with("newyork", &model).unwrap().words,
+            vec!["new", "york"]
+        );
+    }
+
+    #[test]
+    fn no_segmentation() {
+        let model = model();
+
+        // a single word is not a segmentation
+        assert_eq!(segment_with("async", &model), None);
+        assert_eq!(segment_with("rustacean", &model), None);
+        assert_eq!(segment_with("", &model), None);
+
+        // the caller decides whether a known word should be segmented
+        assert_eq!(
+            segment_with("runtime", &model).unwrap().words,
+            vec!["run", "time"]
+        );
+    }
+
+    #[test]
+    fn unknown_words_are_unlikely() {
+        let model = model();
+        let min_log_prob = (1.0 / 1024.0f64).log2();
+
+        let segmentation = segment_with("rustasyncruntime", &model).unwrap();
+        assert!(segmentation.log_prob > unknown_word_log_prob("rustasyncruntime", min_log_prob));
+
+        // short unknown words are more likely than a segmentation into rare words
+        assert!(unknown_word_log_prob("ab", min_log_prob) > 2.0 * model["rust"]);
+        assert_eq!(unknown_word_log_prob("a", min_log_prob), min_log_prob);
+    }
+}
@@ -25,7 +25,11 @@ use crate::{
     web_spell::stupid_backoff::{IntoMiddle, LeftToRight, RightToLeft},
 };
 
-use super::{error_model, Correction, CorrectionTerm, Error, ErrorModel, StupidBackoff, TermDict};
+use super::{
+    error_model,
+    segmentation::{self, MIN_SEGMENTED_CHARS},
+    Correction, CorrectionTerm, Error, ErrorModel, StupidBackoff, TermDict,
+};
 
 struct LangSpellChecker {
     term_dict: TermDict,
@@ -120,6 +124,26 @@ impl LangSpellChecker {
         best_term
     }
 
+    /// The most likely segmentation of a term that was typed without spaces, together with
+    /// the difference between its log probability and the log probability of the term.
+    fn segment(&self, term: &str) -> Option<(String, f64)> {
+        if term.chars().count() < MIN_SEGMENTED_CHARS || self.term_dict.freq(term).is_some() {
+            return None;
+        }
+
+        let segmentation = segmentation::segment(
+            term,
+            |word| self.term_dict.freq(word).is_some(),
+            |words| self.language_model.log_prob(words, LeftToRight),
+        )?;
+
+        let min_log_prob = self.language_model.log_prob(&[], LeftToRight);
+        let diff = segmentation.log_prob - segmentation::unknown_word_log_prob(term, min_log_prob);
+        tracing::debug!(?term, ?segmentation, ?diff);
+
+        Some((segmentation.words.join(" "), diff))
+    }
+
     fn correct_once(&self, text: &str) -> Option<Correction> {
         let orig_terms = super::tokenize(text);
         let mut terms = orig_terms.clone();
@@ -129,6 +153,15 @@ impl LangSpellChecker {
         let num_terms = terms.len();
         for i in 0..num_terms {
             let term = &terms[i];
+
+            if let Some((segmented, diff)) = self.segment(term) {
+                if diff > self.config.segmentation_threshold {
+                    corrections.push((i, segmented.clone()));
+                    terms[i] = segmented;
+                    continue;
+                }
+            }
+
             let candidates = self.candidates(term);
 
             if candidates.is_empty() {
@@ -250,6 +283,19 @@ impl SpellChecker {
             .get(lang)
             .and_then(|s| s.correct(text))
     }
+
+    /// The segmentation of a term that was typed without spaces if it is likely enough
+    /// to be applied to the query without asking the user.
+    pub fn auto_segment(&self, term: &str, lang: &Lang) -> Option<String> {
+        let checker = self.lang_spell_checkers.get(lang)?;
+        let (segmented, diff) = checker.segment(term.to_lowercase().as_str())?;
+
+        if diff > checker.config.auto_segmentation_threshold {
+            Some(segmented)
+        } else {
+            None
+        }
+    }
 }
 
 #[cfg(test)]
@@ -17,6 +17,9 @@
 //! This is a centrality measure that is based on the harmonic centrality.
 //! The idea is to use the harmonic centrality from the domain graph to
 //! derive a centrality measure for the page graph.
+//!
+//! Each host linking to a page votes with its harmonic centrality, weighted by
+//! the highest stored weight of its links to the page.
 
 use anyhow::Result;
 use rayon::prelude::*;
@@ -103,17 +106,26 @@ impl DerivedCentrality {
 
                 if let Some(harmonic) = host_harmonic.get(&host_node) {
                     let mut ingoing: Vec<_> = page_graph
-                        .raw_ingoing_edges(&id)
+                        .raw_ingoing_edges_with_weights(&id)
                         .into_iter()
-                        .filter_map(|e| page_graph.id2node(&e.from))
-                        .map(|n| n.into_host())
+                        .filter_map(|(e, weight)| {
+                            page_graph
+                                .id2node(&e.from)
+                                .map(|n| (n.into_host(), weight as f64))
+                        })
                         .collect();
-                    ingoing.sort();
-                    ingoing.dedup();
+
+                    // keep the highest weight of each host
+                    ingoing.sort_by(|(a, a_weight), (b, b_weight)| {
+                        a.cmp(b).then(b_weight.total_cmp(a_weight))
+                    });
+                    ingoing.dedup_by(|(a, _), (b, _)| a == b);
 
                     let votes = ingoing
                         .into_iter()
-                        .filter_map(|n| host_harmonic.get(&n.id()))
+                        .filter_map(|(n, weight)| {
+                            host_harmonic.get(&n.id()).map(|harmonic| harmonic * weight)
+                        })
                         .sum::<f64>();
                     let page_score = harmonic * votes;
 
@@ -154,6 +154,21 @@ fn calculate_centrality(graph: &Webgraph) -> BTreeMap<NodeID, f64> {
         }
     }
 
+    // the first iteration counted each direct link once, but the direct links
+    // contribute with their stored weights. Links further away are unweighted.
+    for (node, score) in centralities.iter_mut() {
+        let extra = graph
+            .raw_ingoing_edges_with_weights(node)
+            .into_iter()
+            .filter(|(edge, _)| edge.from != *node)
+            .map(|(_, weight)| weight as f64 - 1.0)
+            .sum::<f64>();
+
+        if extra != 0.0 {
+            *score += extra;
+        }
+    }
+
     let res = centralities
         .into_iter()
         .map(|(node_id, sum)| (node_id, f64::from(sum)))
@@ -369,4 +384,32 @@ mod tests {
 
         assert_eq!(centrality.0, centrality_extra.0);
     }
+
+    #[test]
+    fn direct_links_are_weighted() {
+        let centrality = HarmonicCentrality::calculate(&test_graph());
+
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        for (from, to, label) in test_edges() {
+            if from == Node::from("D") {
+                writer.insert_with_weight(from, to, label, 0.5);
+            } else {
+                writer.insert(from, to, label);
+            }
+        }
+
+        let weighted = HarmonicCentrality::calculate(&writer.finalize());
+
+        let c = Node::from("C").id();
+        assert!((centrality.get(&c).unwrap() - weighted.get(&c).unwrap() - 0.5 / 3.0).abs() < 1e-9);
+        assert_eq!(
+            centrality.get(&Node::from("A").id()),
+            weighted.get(&Node::from("A").id())
+        );
+    }
 }
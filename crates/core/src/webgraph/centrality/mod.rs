@@ -22,6 +22,7 @@ pub mod approx_harmonic;
 pub mod betweenness;
 pub mod derived_harmonic;
 pub mod harmonic;
+pub mod topic_centrality;
 
 #[derive(Debug, Clone, Copy)]
 pub enum TopHosts {
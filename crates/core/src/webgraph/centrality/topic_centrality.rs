@@ -0,0 +1,270 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Topic-sensitive PageRank (Haveliwala, 2002).
+//!
+//! A personalized PageRank is calculated for each top level topic where the
+//! random surfer teleports to hosts in proportion to their weight for the topic.
+//! Hosts that are central among the hosts about a topic therefore get a high
+//! score for that topic, even if they are not very central in the graph as a whole.
+//!
+//! The random surfer follows the outgoing links of a host in proportion to their
+//! weights stored in the graph.
+
+use std::collections::{BTreeMap, HashMap};
+
+use tracing::info;
+
+use crate::webgraph::{NodeID, Webgraph};
+use crate::webpage::topic_classifier::{Topic, TopicVector, ALL_TOPICS, NUM_TOPICS};
+
+const DAMPING: f64 = 0.85;
+const MAX_ITERATIONS: usize = 50;
+const TOLERANCE: f64 = 1e-9;
+
+type TopicScores = [f64; NUM_TOPICS];
+
+pub struct TopicCentrality {
+    scores: BTreeMap<NodeID, TopicScores>,
+}
+
+impl TopicCentrality {
+    /// Calculate the centralities on the host graph. The scores of each topic are
+    /// scaled such that the most central host for the topic has a score of 1.
+    pub fn calculate(graph: &Webgraph, host_topics: &HashMap<NodeID, TopicVector>) -> Self {
+        let nodes: Vec<NodeID> = graph.nodes().collect();
+        let index: HashMap<NodeID, usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (*node, i))
+            .collect();
+
+        info!("Found {} nodes in the graph", nodes.len());
+
+        let for_each_edge = |f: &mut dyn FnMut(usize, usize, f64)| {
+            for (edge, weight) in graph.weighted_edges() {
+                if let (Some(from), Some(to)) = (index.get(&edge.from), index.get(&edge.to)) {
+                    f(*from, *to, weight as f64);
+                }
+            }
+        };
+
+        let mut out_weight = vec![0.0; nodes.len()];
+        for_each_edge(&mut |from, _, weight| out_weight[from] += weight);
+
+        let mut teleport = vec![[0.0; NUM_TOPICS]; nodes.len()];
+        let mut total = [0.0; NUM_TOPICS];
+
+        for (node, topics) in host_topics {
+            if let Some(i) = index.get(node) {
+                for topic in ALL_TOPICS {
+                    let weight = topics.get(topic) as f64;
+                    teleport[*i][topic as usize] = weight;
+                    total[topic as usize] += weight;
+                }
+            }
+        }
+
+        for scores in teleport.iter_mut() {
+            for (score, total) in scores.iter_mut().zip(total.iter()) {
+                if *total > 0.0 {
+                    *score /= total;
+                }
+            }
+        }
+
+        let mut ranks = teleport.clone();
+
+        for iteration in 0..MAX_ITERATIONS {
+            let mut new_ranks = vec![[0.0; NUM_TOPICS]; nodes.len()];
+
+            for_each_edge(&mut |from, to, weight| {
+                let share = weight / out_weight[from];
+
+                for (new_rank, rank) in new_ranks[to].iter_mut().zip(ranks[from].iter()) {
+                    *new_rank += DAMPING * rank * share;
+                }
+            });
+
+            // the rank of dangling nodes is redistributed according to the
+            // teleport vector, so the ranks of each topic keep summing to 1.
+            let mut dangling = [0.0; NUM_TOPICS];
+            for (rank, weight) in ranks.iter().zip(out_weight.iter()) {
+                if *weight == 0.0 {
+                    for (dangling, rank) in dangling.iter_mut().zip(rank.iter()) {
+                        *dangling += rank;
+                    }
+                }
+            }
+
+            let mut diff: f64 = 0.0;
+
+            for ((new_rank, rank), teleport) in new_ranks.iter_mut().zip(&ranks).zip(&teleport) {
+                for t in 0..NUM_TOPICS {
+                    new_rank[t] += (1.0 - DAMPING + DAMPING * dangling[t]) * teleport[t];
+                    diff = diff.max((new_rank[t] - rank[t]).abs());
+                }
+            }
+
+            ranks = new_ranks;
+
+            if diff < TOLERANCE {
+                info!("Converged after {} iterations", iteration + 1);
+                break;
+            }
+        }
+
+        let mut max = [0.0_f64; NUM_TOPICS];
+        for rank in &ranks {
+            for (max, rank) in max.iter_mut().zip(rank.iter()) {
+                *max = max.max(*rank);
+            }
+        }
+
+        let scores = nodes
+            .into_iter()
+            .zip(ranks)
+            .map(|(node, mut rank)| {
+                for (rank, max) in rank.iter_mut().zip(max.iter()) {
+                    if *max > 0.0 {
+                        *rank /= max;
+                    }
+                }
+
+                (node, rank)
+            })
+            .collect();
+
+        Self { scores }
+    }
+
+    pub fn get(&self, node: &NodeID, topic: Topic) -> Option<f64> {
+        self.scores
+            .get(node)
+            .map(|scores| scores[topic as usize])
+            .filter(|score| *score > 0.0)
+    }
+
+    /// Iterate the non-zero scores.
+    pub fn iter(&self) -> impl Iterator<Item = (NodeID, Topic, f64)> + '_ {
+        self.scores.iter().flat_map(|(node, scores)| {
+            ALL_TOPICS
+                .into_iter()
+                .map(move |topic| (*node, topic, scores[topic as usize]))
+                .filter(|(_, _, score)| *score > 0.0)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        executor::Executor,
+        webgraph::{Compression, Node, WebgraphWriter},
+    };
+
+    use super::*;
+
+    #[test]
+    fn scores_are_topic_sensitive() {
+        // A <-> B (sports), C <-> D (science), C -> A
+        let mut graph = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+
+        graph.insert(Node::from("A"), Node::from("B"), String::new());
+        graph.insert(Node::from("B"), Node::from("A"), String::new());
+        graph.insert(Node::from("C"), Node::from("D"), String::new());
+        graph.insert(Node::from("D"), Node::from("C"), String::new());
+        graph.insert(Node::from("C"), Node::from("A"), String::new());
+
+        graph.commit();
+        let graph = graph.finalize();
+
+        let id = |name: &str| Node::from(name).id();
+
+        let mut sports = TopicVector::default();
+        sports.set(Topic::Sports, 1.0);
+        let mut science = TopicVector::default();
+        science.set(Topic::Science, 1.0);
+
+        let host_topics: HashMap<_, _> = [
+            (id("A"), sports.clone()),
+            (id("B"), sports),
+            (id("C"), science.clone()),
+            (id("D"), science),
+        ]
+        .into_iter()
+        .collect();
+
+        let centrality = TopicCentrality::calculate(&graph, &host_topics);
+
+        let sports_a = centrality.get(&id("A"), Topic::Sports).unwrap();
+        let sports_b = centrality.get(&id("B"), Topic::Sports).unwrap();
+
+        assert!((sports_a - 1.0).abs() < 1e-6);
+        assert!((sports_b - 1.0).abs() < 1e-6);
+
+        // no sports host links to the science hosts
+        assert_eq!(centrality.get(&id("C"), Topic::Sports), None);
+        assert_eq!(centrality.get(&id("D"), Topic::Sports), None);
+
+        // but the science hosts link to the sports hosts
+        assert!(centrality.get(&id("C"), Topic::Science).unwrap() > 0.0);
+        assert!(centrality.get(&id("A"), Topic::Science).unwrap() > 0.0);
+
+        assert_eq!(centrality.get(&id("A"), Topic::Arts), None);
+        assert_eq!(
+            centrality
+                .iter()
+                .filter(|(_, topic, _)| *topic == Topic::Sports)
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn links_are_followed_by_weight() {
+        // S -> X with weight 4, S -> Y with weight 1
+        let mut graph = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+
+        graph.insert_with_weight(Node::from("S"), Node::from("X"), String::new(), 4.0);
+        graph.insert(Node::from("S"), Node::from("Y"), String::new());
+        graph.insert(Node::from("X"), Node::from("S"), String::new());
+        graph.insert(Node::from("Y"), Node::from("S"), String::new());
+
+        graph.commit();
+        let graph = graph.finalize();
+
+        let id = |name: &str| Node::from(name).id();
+
+        let mut sports = TopicVector::default();
+        sports.set(Topic::Sports, 1.0);
+        let host_topics: HashMap<_, _> = [(id("S"), sports)].into_iter().collect();
+
+        let centrality = TopicCentrality::calculate(&graph, &host_topics);
+        let x = centrality.get(&id("X"), Topic::Sports).unwrap();
+        let y = centrality.get(&id("Y"), Topic::Sports).unwrap();
+        assert!(x > y);
+        assert!((x / y - 4.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,218 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Compaction of node ids into a dense range.
+//!
+//! Node ids are hashes of the node names, so they are spread over the entire `u64`
+//! range and ids of nodes that are no longer in the graph are still present in the
+//! stores that were computed from an older graph. A [`NodeIdMapping`] assigns each
+//! node that is currently in the graph an id in the range `0..num_nodes`, which can be
+//! used to rewrite the stores keyed by node ids so their values can be kept in flat arrays.
+//!
+//! Everything else looks nodes up by their hashed ids, so a rewritten store contains its
+//! mapping and translates the ids when it is read. Only the signal store can be rewritten,
+//! as the centrality stores are read directly as RocksDB stores in many places.
+
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::anyhow;
+
+use crate::{
+    signal_store::{SignalStore, SignalStoreWriter},
+    Result,
+};
+
+use super::{NodeID, Webgraph};
+
+const ID_SIZE: usize = std::mem::size_of::<u64>();
+
+/// Mapping between the hashed node ids and dense node ids. The dense id of
+/// a node is its position among the sorted ids of all the nodes in the graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeIdMapping {
+    ids: Vec<NodeID>,
+}
+
+impl NodeIdMapping {
+    pub fn build(graph: &Webgraph) -> Self {
+        Self::from_ids(graph.nodes())
+    }
+
+    pub fn from_ids(ids: impl IntoIterator<Item = NodeID>) -> Self {
+        let mut ids: Vec<_> = ids.into_iter().collect();
+        ids.sort();
+        ids.dedup();
+
+        Self { ids }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut bytes = Vec::new();
+        BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+
+        if bytes.len() % ID_SIZE != 0 {
+            return Err(anyhow!("node id mapping has an invalid size"));
+        }
+
+        let ids: Vec<_> = bytes
+            .chunks_exact(ID_SIZE)
+            .map(|chunk| NodeID::from(u64::from_le_bytes(chunk.try_into().unwrap())))
+            .collect();
+
+        if ids.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(anyhow!("node ids in the mapping are not sorted"));
+        }
+
+        Ok(Self { ids })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        for id in &self.ids {
+            writer.write_all(&id.as_u64().to_le_bytes())?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// The dense id of the node, or `None` if the node is not in the graph.
+    pub fn dense(&self, id: &NodeID) -> Option<NodeID> {
+        self.ids
+            .binary_search(id)
+            .ok()
+            .map(|pos| NodeID::from(pos as u64))
+    }
+
+    /// The original id of the node with the dense id.
+    pub fn original(&self, dense: &NodeID) -> Option<NodeID> {
+        self.ids.get(dense.as_u64() as usize).copied()
+    }
+}
+
+/// Rewrite a signal store to use the dense ids. The new store keeps the version of the
+/// old store and contains the mapping, so it is read with the hashed ids like any other
+/// signal store. Rows of nodes that are not in the mapping are dropped.
+pub fn remap_signal_store<P: AsRef<Path>, Q: AsRef<Path>>(
+    mapping: &NodeIdMapping,
+    input_path: P,
+    output_path: Q,
+) -> Result<usize> {
+    let input = SignalStore::open(input_path)?;
+    let mut output = SignalStoreWriter::new(output_path, input.version());
+    output.set_mapping(mapping.clone());
+
+    for (id, vector) in input.iter() {
+        output.insert_vector(id, &vector);
+    }
+
+    Ok(output.finalize()?.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::signal_store::StoredSignal;
+
+    use super::*;
+
+    #[test]
+    fn mapping_roundtrip() {
+        let mapping = NodeIdMapping::from_ids([42u64, 7, 1 << 60, 7].map(NodeID::from));
+
+        assert_eq!(mapping.len(), 3);
+        assert_eq!(mapping.dense(&NodeID::from(7u64)), Some(NodeID::from(0u64)));
+        assert_eq!(
+            mapping.dense(&NodeID::from(42u64)),
+            Some(NodeID::from(1u64))
+        );
+        assert_eq!(
+            mapping.dense(&NodeID::from(1u64 << 60)),
+            Some(NodeID::from(2u64))
+        );
+        assert_eq!(mapping.dense(&NodeID::from(8u64)), None);
+        assert_eq!(
+            mapping.original(&NodeID::from(2u64)),
+            Some(NodeID::from(1u64 << 60))
+        );
+
+        let path = crate::gen_temp_path().join("mapping.bin");
+        mapping.save(&path).unwrap();
+        assert_eq!(NodeIdMapping::open(&path).unwrap(), mapping);
+    }
+
+    #[test]
+    fn remap_signal_store_is_read_with_hashed_ids() {
+        let mapping = NodeIdMapping::from_ids([10u64, 20].map(NodeID::from));
+
+        let signals_path = crate::gen_temp_path();
+        let mut writer = SignalStoreWriter::new(&signals_path, 3);
+        writer.insert(NodeID::from(20u64), StoredSignal::SpamScore, 0.5);
+        writer.insert(NodeID::from(30u64), StoredSignal::SpamScore, 0.7);
+        writer.finalize().unwrap();
+
+        let remapped_path = crate::gen_temp_path();
+        assert_eq!(
+            remap_signal_store(&mapping, &signals_path, &remapped_path).unwrap(),
+            1
+        );
+
+        let remapped = SignalStore::open(&remapped_path).unwrap();
+        assert_eq!(remapped.version(), 3);
+        assert_eq!(
+            remapped.get_signal(&NodeID::from(20u64), StoredSignal::SpamScore),
+            Some(0.5)
+        );
+        assert_eq!(
+            remapped.get_signal(&NodeID::from(30u64), StoredSignal::SpamScore),
+            None
+        );
+        assert_eq!(
+            remapped.iter().map(|(id, _)| id).collect::<Vec<_>>(),
+            vec![NodeID::from(20u64)]
+        );
+
+        // remapping a remapped store gives the same store
+        let twice_path = crate::gen_temp_path();
+        assert_eq!(
+            remap_signal_store(&mapping, &remapped_path, &twice_path).unwrap(),
+            1
+        );
+        assert_eq!(
+            SignalStore::open(&twice_path)
+                .unwrap()
+                .get_signal(&NodeID::from(20u64), StoredSignal::SpamScore),
+            Some(0.5)
+        );
+    }
+}
@@ -0,0 +1,200 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Weights of the links in the webgraph.
+//!
+//! Each link is weighted by the number of words in its anchor text, its position
+//! on the page, whether it points to the same host and its rel attributes.
+//! The weights are calculated while the graph is constructed, since the position and
+//! rel attributes are only known there, and stored with the edges. Links with a weight
+//! of 0 are left out of the graph. The centralities read the stored weights, so the
+//! weights are only configured for the construction of the graph.
+
+use crate::config::EdgeWeightConfig;
+use crate::webpage::Link;
+
+/// The rel attributes of a link that affect its weight.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rel {
+    pub nofollow: bool,
+    pub ugc: bool,
+    pub sponsored: bool,
+}
+
+impl Rel {
+    pub fn parse(rel: &str) -> Self {
+        let mut res = Self::default();
+
+        for token in rel.split_ascii_whitespace() {
+            match token.to_ascii_lowercase().as_str() {
+                "nofollow" => res.nofollow = true,
+                "ugc" => res.ugc = true,
+                "sponsored" => res.sponsored = true,
+                _ => {}
+            }
+        }
+
+        res
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EdgeProperties {
+    pub anchor_words: usize,
+    /// Index of the link among the links on the page.
+    pub position: usize,
+    pub same_host: bool,
+    pub rel: Rel,
+}
+
+impl EdgeProperties {
+    pub fn from_link(link: &Link, position: usize) -> Self {
+        Self {
+            anchor_words: link.text.split_whitespace().count(),
+            position,
+            same_host: link.source.host_str() == link.destination.host_str(),
+            rel: link.rel.as_deref().map(Rel::parse).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EdgeWeights {
+    config: EdgeWeightConfig,
+}
+
+impl From<EdgeWeightConfig> for EdgeWeights {
+    fn from(config: EdgeWeightConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl EdgeWeights {
+    pub fn weight(&self, properties: &EdgeProperties) -> f64 {
+        let mut weight = 1.0;
+
+        if let Some(words) = self.config.anchor_words {
+            // links without anchor text (e.g. images) still get some weight
+            weight *= ((1 + properties.anchor_words) as f64 / (1 + words) as f64).min(1.0);
+        }
+
+        weight /= 1.0 + self.config.position_decay * properties.position as f64;
+
+        if properties.same_host {
+            weight *= self.config.same_host;
+        }
+
+        if properties.rel.nofollow {
+            weight *= self.config.nofollow;
+        }
+
+        if properties.rel.ugc {
+            weight *= self.config.ugc;
+        }
+
+        if properties.rel.sponsored {
+            weight *= self.config.sponsored;
+        }
+
+        weight.max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::*;
+
+    fn link(source: &str, destination: &str, text: &str, rel: Option<&str>) -> Link {
+        Link {
+            source: Url::parse(source).unwrap(),
+            destination: Url::parse(destination).unwrap(),
+            text: text.to_string(),
+            rel: rel.map(|rel| rel.to_string()),
+        }
+    }
+
+    #[test]
+    fn default_weights_are_unit() {
+        let weights = EdgeWeights::default();
+
+        let link = link(
+            "https://a.com/",
+            "https://a.com/about",
+            "",
+            Some("nofollow ugc"),
+        );
+
+        assert_eq!(weights.weight(&EdgeProperties::from_link(&link, 100)), 1.0);
+    }
+
+    #[test]
+    fn rel_is_parsed() {
+        assert_eq!(
+            Rel::parse("NoFollow  sponsored noopener"),
+            Rel {
+                nofollow: true,
+                ugc: false,
+                sponsored: true,
+            }
+        );
+        assert_eq!(Rel::parse(""), Rel::default());
+    }
+
+    #[test]
+    fn properties_multiply() {
+        let weights = EdgeWeights::from(EdgeWeightConfig {
+            anchor_words: Some(3),
+            position_decay: 0.5,
+            same_host: 0.5,
+            nofollow: 0.0,
+            ..Default::default()
+        });
+
+        let external = link(
+            "https://a.com/",
+            "https://b.com/",
+            "a long anchor text",
+            None,
+        );
+        assert_eq!(
+            weights.weight(&EdgeProperties::from_link(&external, 0)),
+            1.0
+        );
+        assert_eq!(
+            weights.weight(&EdgeProperties::from_link(&external, 2)),
+            0.5
+        );
+
+        let internal = link("https://a.com/", "https://a.com/about", "about", None);
+        assert_eq!(
+            weights.weight(&EdgeProperties::from_link(&internal, 0)),
+            0.25
+        );
+
+        let nofollow = link(
+            "https://a.com/",
+            "https://b.com/",
+            "a long anchor text",
+            Some("nofollow"),
+        );
+        assert_eq!(
+            weights.weight(&EdgeProperties::from_link(&nofollow, 0)),
+            0.0
+        );
+    }
+}
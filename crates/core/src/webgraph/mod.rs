@@ -15,7 +15,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 mod segment;
 
-use std::collections::{BTreeMap, BinaryHeap};
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::Path;
@@ -32,6 +32,9 @@ use crate::intmap;
 use crate::webpage::url_ext::UrlExt;
 
 pub mod centrality;
+pub mod compaction;
+pub mod edge_weight;
+pub mod sampling;
 mod store;
 use self::segment::{Segment, SegmentWriter};
 
@@ -125,7 +128,7 @@ where
     pub label: L,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct InnerEdge<L>
 where
     L: EdgeLabel,
@@ -133,6 +136,8 @@ where
     pub from: FullNodeID,
     pub to: FullNodeID,
     pub label: L,
+    /// See [`edge_weight::EdgeWeights`].
+    pub weight: f32,
 }
 
 impl<L> From<InnerEdge<L>> for Edge<L>
@@ -326,6 +331,13 @@ impl WebgraphBuilder {
     pub fn open(self) -> Webgraph {
         Webgraph::open(self.path, self.executor, self.compression)
     }
+
+    /// Open an existing graph read-only. Unlike [`WebgraphBuilder::open`] this
+    /// never creates or modifies files and returns an error instead of
+    /// panicking if the graph is missing or corrupt.
+    pub fn try_open(self) -> anyhow::Result<Webgraph> {
+        Webgraph::open_read_only(self.path, self.executor, self.compression)
+    }
 }
 
 pub trait ShortestPaths {
@@ -433,9 +445,19 @@ type SegmentID = String;
 #[derive(serde::Serialize, serde::Deserialize, Default)]
 struct Meta {
     comitted_segments: Vec<SegmentID>,
+    /// The deleted pages by the number of segments when they were deleted. The
+    /// outgoing edges of a deleted page are hidden in those segments.
+    #[serde(default)]
+    deleted_pages: HashMap<u64, usize>,
 }
 
 impl Meta {
+    fn is_deleted(&self, segment: usize, from: &NodeID) -> bool {
+        self.deleted_pages
+            .get(&from.as_u64())
+            .map_or(false, |num_segments| segment < *num_segments)
+    }
+
     fn open<P: AsRef<Path>>(path: P) -> Self {
         let mut reader = BufReader::new(
             File::options()
@@ -450,6 +472,11 @@ impl Meta {
         serde_json::from_str(&buf).unwrap_or_default()
     }
 
+    fn open_read_only<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let buf = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&buf)?)
+    }
+
     fn save<P: AsRef<Path>>(&self, path: P) {
         let mut writer = BufWriter::new(
             File::options()
@@ -471,7 +498,7 @@ struct Id2NodeDb {
 }
 
 impl Id2NodeDb {
-    fn open<P: AsRef<Path>>(path: P) -> Self {
+    fn options() -> rocksdb::Options {
         let mut opts = rocksdb::Options::default();
         opts.create_if_missing(true);
         opts.optimize_for_point_lookup(512);
@@ -495,11 +522,21 @@ impl Id2NodeDb {
         opts.set_block_based_table_factory(&block_opts);
         opts.set_compression_type(rocksdb::DBCompressionType::Lz4);
 
-        let db = rocksdb::DB::open(&opts, path).unwrap();
+        opts
+    }
+
+    fn open<P: AsRef<Path>>(path: P) -> Self {
+        let db = rocksdb::DB::open(&Self::options(), path).unwrap();
 
         Self { db }
     }
 
+    fn open_read_only<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let db = rocksdb::DB::open_for_read_only(&Self::options(), path, false)?;
+
+        Ok(Self { db })
+    }
+
     fn put(&mut self, id: &NodeID, node: &Node) {
         let mut opts = rocksdb::WriteOptions::default();
         opts.disable_wal(true);
@@ -529,6 +566,23 @@ impl Id2NodeDb {
             })
     }
 
+    /// The first key at or after `key`, wrapping around to the first key in
+    /// the db if there are no keys after it.
+    fn key_at_or_after(&self, key: NodeID) -> Option<NodeID> {
+        let bytes = key.as_u64().to_le_bytes();
+
+        self.db
+            .iterator(rocksdb::IteratorMode::From(
+                &bytes,
+                rocksdb::Direction::Forward,
+            ))
+            .chain(self.db.iterator(rocksdb::IteratorMode::Start))
+            .find_map(|r| {
+                let (key, _) = r.ok()?;
+                Some(NodeID(u64::from_le_bytes((*key).try_into().unwrap())))
+            })
+    }
+
     fn estimate_num_keys(&self) -> usize {
         self.db
             .property_int_value("rocksdb.estimate-num-keys")
@@ -635,6 +689,10 @@ impl WebgraphWriter {
     }
 
     pub fn insert(&mut self, from: Node, to: Node, label: String) {
+        self.insert_with_weight(from, to, label, 1.0);
+    }
+
+    pub fn insert_with_weight(&mut self, from: Node, to: Node, label: String, weight: f32) {
         if from == to {
             return;
         }
@@ -648,6 +706,7 @@ impl WebgraphWriter {
             from: from_id,
             to: to_id,
             label: label.chars().take(MAX_LABEL_LENGTH).collect(),
+            weight,
         };
 
         self.insert_batch.push(edge);
@@ -727,9 +786,56 @@ impl Webgraph {
         }
     }
 
+    fn open_read_only<P: AsRef<Path>>(
+        path: P,
+        executor: Executor,
+        compression: Compression,
+    ) -> anyhow::Result<Self> {
+        let meta = Meta::open_read_only(path.as_ref().join("metadata.json"))?;
+
+        let mut segments = Vec::new();
+        for segment in &meta.comitted_segments {
+            segments.push(Segment::open_read_only(
+                path.as_ref().join("segments"),
+                segment.clone(),
+                compression,
+            )?);
+        }
+
+        Ok(Self {
+            path: path
+                .as_ref()
+                .as_os_str()
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("webgraph path is not valid utf-8"))?
+                .to_string(),
+            segments,
+            executor: Arc::new(executor),
+            id2node: Id2NodeDb::open_read_only(path.as_ref().join("id2node"))?,
+            meta,
+            compression,
+        })
+    }
+
+    /// Remove the outgoing edges of the page. Edges from the page in segments
+    /// that are merged into the graph later are kept.
+    pub fn delete_page(&mut self, node: &NodeID) {
+        self.meta
+            .deleted_pages
+            .insert(node.as_u64(), self.segments.len());
+        self.save_metadata();
+    }
+
     pub fn merge(&mut self, other: Webgraph) {
         self.id2node.batch_put(other.id2node.iter());
 
+        let offset = self.segments.len();
+        for (node, num_segments) in &other.meta.deleted_pages {
+            let num_segments = offset + num_segments;
+            let deleted = self.meta.deleted_pages.entry(*node).or_default();
+            *deleted = (*deleted).max(num_segments);
+        }
+
         for segment in other.segments {
             let id = segment.id();
             let new_path = Path::new(&self.path).join("segments");
@@ -809,6 +915,34 @@ impl Webgraph {
         self.inner_edges(|segment| segment.ingoing_edges_with_label(node), dedup)
     }
 
+    /// The ingoing edges of the node with their weights. Edges that are
+    /// present in more than one segment keep their highest weight.
+    pub fn raw_ingoing_edges_with_weights(&self, node: &NodeID) -> Vec<(Edge<()>, f32)> {
+        let meta = &self.meta;
+
+        let mut edges: Vec<_> = self
+            .executor
+            .map(
+                |(i, segment): (usize, &Segment)| {
+                    let mut edges = segment.ingoing_edges_with_weight(node);
+                    edges.retain(|(e, _)| !meta.is_deleted(i, &e.from));
+                    edges
+                },
+                self.segments.iter().enumerate(),
+            )
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
+
+        edges.sort_by(|(a, a_weight), (b, b_weight)| {
+            a.from.cmp(&b.from).then(b_weight.total_cmp(a_weight))
+        });
+        edges.dedup_by_key(|(e, _)| e.from);
+
+        edges
+    }
+
     pub fn outgoing_edges(&self, node: Node) -> Vec<FullEdge> {
         let dedup = |edges: &mut Vec<Edge<String>>| {
             edges.sort_by_key(|e| e.to);
@@ -843,9 +977,18 @@ impl Webgraph {
         F1: Sized + Sync + Fn(&Segment) -> Vec<Edge<L>>,
         F2: Fn(&mut Vec<Edge<L>>),
     {
+        let meta = &self.meta;
+
         let mut edges: Vec<_> = self
             .executor
-            .map(loader, self.segments.iter())
+            .map(
+                |(i, segment): (usize, &Segment)| {
+                    let mut edges = loader(segment);
+                    edges.retain(|e| !meta.is_deleted(i, &e.from));
+                    edges
+                },
+                self.segments.iter().enumerate(),
+            )
             .unwrap()
             .into_iter()
             .flatten()
@@ -887,13 +1030,45 @@ impl Webgraph {
     /// Some edges may be returned multiple times.
     /// This happens if they are present in more than one segment.
     pub fn edges(&self) -> impl Iterator<Item = Edge<()>> + '_ {
-        self.segments.iter().flat_map(|segment| segment.edges())
+        let meta = &self.meta;
+
+        self.segments
+            .iter()
+            .enumerate()
+            .flat_map(move |(i, segment)| {
+                segment
+                    .edges()
+                    .filter(move |e| !meta.is_deleted(i, &e.from))
+            })
+    }
+
+    /// Iterate all edges in the graph with their weights.
+    /// Like [`Webgraph::edges`], some edges may be returned multiple times.
+    pub fn weighted_edges(&self) -> impl Iterator<Item = (Edge<()>, f32)> + '_ {
+        let meta = &self.meta;
+
+        self.segments
+            .iter()
+            .enumerate()
+            .flat_map(move |(i, segment)| {
+                segment
+                    .weighted_edges()
+                    .filter(move |(e, _)| !meta.is_deleted(i, &e.from))
+            })
     }
 
     pub fn par_edges(&self) -> impl ParallelIterator<Item = Edge<()>> + '_ {
+        let meta = &self.meta;
+
         self.segments
             .par_iter()
-            .flat_map(|segment| segment.edges().par_bridge())
+            .enumerate()
+            .flat_map(move |(i, segment)| {
+                segment
+                    .edges()
+                    .filter(move |e| !meta.is_deleted(i, &e.from))
+                    .par_bridge()
+            })
     }
 }
 
@@ -949,6 +1124,60 @@ mod test {
         assert_eq!(distances.get(&Node::from("B")), Some(&3));
     }
 
+    #[test]
+    fn try_open() {
+        let graph = test_graph();
+        let path = graph.path.clone();
+        drop(graph);
+
+        let graph = WebgraphBuilder::new(&path)
+            .single_threaded()
+            .try_open()
+            .unwrap();
+        assert_eq!(graph.edges().count(), test_edges().len());
+
+        assert!(WebgraphBuilder::new(crate::gen_temp_path())
+            .single_threaded()
+            .try_open()
+            .is_err());
+    }
+
+    #[test]
+    fn deleted_page() {
+        let mut graph = test_graph();
+        graph.delete_page(&Node::from("A").id());
+
+        assert!(graph.outgoing_edges(Node::from("A")).is_empty());
+        assert!(!graph
+            .raw_ingoing_edges(&Node::from("C").id())
+            .iter()
+            .any(|e| e.from == Node::from("A").id()));
+        assert_eq!(graph.edges().count(), test_edges().len() - 2);
+
+        // links from the page that are merged after the deletion are kept
+        let mut other = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+        other.insert(Node::from("A"), Node::from("D"), String::new());
+        other.commit();
+        graph.merge(other.finalize());
+
+        let path = graph.path.clone();
+        drop(graph);
+        let graph = WebgraphBuilder::new(path).single_threaded().open();
+
+        assert_eq!(
+            graph
+                .outgoing_edges(Node::from("A"))
+                .into_iter()
+                .map(|e| e.to)
+                .collect::<Vec<_>>(),
+            vec![Node::from("D")]
+        );
+    }
+
     #[test]
     fn nonexisting_node() {
         let graph = test_graph();
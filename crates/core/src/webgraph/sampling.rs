@@ -0,0 +1,209 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Random sampling of nodes, edges and walks in the webgraph. This allows
+//! analytics like in-degree distributions to be estimated without scanning
+//! the entire graph.
+//!
+//! Node ids are hashes of the node names, so they are spread uniformly over the
+//! id space. A node is sampled by picking a random id and seeking to the first
+//! node at or after it. This is approximately uniform, with nodes after a large
+//! gap in the id space being slightly more likely to be picked.
+
+use std::collections::HashMap;
+
+use rand::{seq::SliceRandom, Rng};
+
+use super::{NodeID, Webgraph};
+
+/// Nodes with more outgoing edges than this are sampled as if they had exactly
+/// this many edges when sampling edges.
+pub const MAX_SAMPLED_DEGREE: usize = 256;
+
+/// Maximum number of attempts per requested edge before giving up.
+const MAX_EDGE_ATTEMPTS: usize = 64;
+
+pub fn random_node<R: Rng>(graph: &Webgraph, rng: &mut R) -> Option<NodeID> {
+    graph.id2node.key_at_or_after(NodeID(rng.gen()))
+}
+
+/// Sample `num` nodes with replacement.
+pub fn random_nodes<R: Rng>(graph: &Webgraph, num: usize, rng: &mut R) -> Vec<NodeID> {
+    (0..num).filter_map(|_| random_node(graph, rng)).collect()
+}
+
+/// Sample up to `num` edges with replacement. A random node is picked and
+/// accepted with a probability proportional to its out-degree, after which
+/// one of its outgoing edges is picked uniformly. This makes every edge
+/// equally likely, except for edges from nodes with more than
+/// [`MAX_SAMPLED_DEGREE`] outgoing edges which are under-sampled.
+pub fn random_edges<R: Rng>(graph: &Webgraph, num: usize, rng: &mut R) -> Vec<(NodeID, NodeID)> {
+    let mut edges = Vec::with_capacity(num);
+
+    for _ in 0..num * MAX_EDGE_ATTEMPTS {
+        if edges.len() >= num {
+            break;
+        }
+
+        let Some(node) = random_node(graph, rng) else {
+            break;
+        };
+
+        let outgoing = graph.raw_outgoing_edges(&node);
+
+        if outgoing.is_empty() {
+            continue;
+        }
+
+        let accept_prob = outgoing.len().min(MAX_SAMPLED_DEGREE) as f64 / MAX_SAMPLED_DEGREE as f64;
+
+        if !rng.gen_bool(accept_prob) {
+            continue;
+        }
+
+        let edge = outgoing.choose(rng).unwrap();
+        edges.push((edge.from, edge.to));
+    }
+
+    edges
+}
+
+/// Random walk with restart. At every step the walk jumps back to one of the
+/// `sources` with probability `restart_prob` (or if the current node has no
+/// outgoing edges), and otherwise follows a random outgoing edge. Returns the
+/// fraction of steps spent in each visited node, sorted by the fraction.
+pub fn random_walk_with_restart<R: Rng>(
+    graph: &Webgraph,
+    sources: &[NodeID],
+    restart_prob: f64,
+    num_steps: usize,
+    rng: &mut R,
+) -> Vec<(NodeID, f64)> {
+    let Some(mut current) = sources.choose(rng).copied() else {
+        return Vec::new();
+    };
+
+    let restart_prob = restart_prob.clamp(0.0, 1.0);
+    let mut visits: HashMap<NodeID, u64> = HashMap::new();
+
+    for _ in 0..num_steps {
+        *visits.entry(current).or_default() += 1;
+
+        let next = if rng.gen_bool(restart_prob) {
+            None
+        } else {
+            graph.raw_outgoing_edges(&current).choose(rng).map(|e| e.to)
+        };
+
+        current = match next {
+            Some(next) => next,
+            None => *sources.choose(rng).unwrap(),
+        };
+    }
+
+    let mut res: Vec<_> = visits
+        .into_iter()
+        .map(|(node, count)| (node, count as f64 / num_steps as f64))
+        .collect();
+
+    res.sort_by(|(a_id, a), (b_id, b)| b.total_cmp(a).then(a_id.cmp(b_id)));
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{
+        executor::Executor,
+        webgraph::{Compression, Node, WebgraphWriter},
+    };
+
+    use super::*;
+
+    fn test_graph() -> Webgraph {
+        // A -> B -> C -> A, D -> C
+        let mut graph = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+
+        graph.insert(Node::from("A"), Node::from("B"), String::new());
+        graph.insert(Node::from("B"), Node::from("C"), String::new());
+        graph.insert(Node::from("C"), Node::from("A"), String::new());
+        graph.insert(Node::from("D"), Node::from("C"), String::new());
+
+        graph.commit();
+        graph.finalize()
+    }
+
+    #[test]
+    fn sample_nodes() {
+        let graph = test_graph();
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let nodes = random_nodes(&graph, 100, &mut rng);
+        assert_eq!(nodes.len(), 100);
+
+        let all: Vec<_> = graph.nodes().collect();
+        assert!(nodes.iter().all(|n| all.contains(n)));
+
+        // every node should be hit at least once with this many samples
+        for node in &all {
+            assert!(nodes.contains(node));
+        }
+    }
+
+    #[test]
+    fn sample_edges() {
+        let graph = test_graph();
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let edges = random_edges(&graph, 20, &mut rng);
+        assert!(!edges.is_empty());
+
+        for (from, to) in edges {
+            assert!(graph.raw_outgoing_edges(&from).iter().any(|e| e.to == to));
+        }
+    }
+
+    #[test]
+    fn walk_with_restart() {
+        let graph = test_graph();
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let a = Node::from("A").id();
+        let c = Node::from("C").id();
+        let d = Node::from("D").id();
+
+        let visits = random_walk_with_restart(&graph, &[d], 0.2, 10_000, &mut rng);
+        let total: f64 = visits.iter().map(|(_, f)| f).sum();
+        assert!((total - 1.0).abs() < 1e-6);
+
+        // the walk always restarts in D, and both D and B lead to C.
+        assert_eq!(visits[0].0, c);
+        assert!(visits.iter().any(|(n, _)| *n == d));
+        assert!(visits.iter().any(|(n, _)| *n == a));
+
+        // always restarting means the walk never leaves the source
+        let visits = random_walk_with_restart(&graph, &[a], 1.0, 100, &mut rng);
+        assert_eq!(visits, vec![(a, 1.0)]);
+
+        assert!(random_walk_with_restart(&graph, &[], 0.2, 100, &mut rng).is_empty());
+    }
+}
@@ -112,6 +112,35 @@ impl Segment {
         }
     }
 
+    pub fn open_read_only<P: AsRef<Path>>(
+        folder_path: P,
+        id: String,
+        compression: Compression,
+    ) -> anyhow::Result<Self> {
+        Ok(Segment {
+            adjacency: EdgeStore::open_read_only(
+                folder_path.as_ref().join(&id).join(ADJACENCY_STORE),
+                false,
+                compression,
+            )?,
+            reversed_adjacency: EdgeStore::open_read_only(
+                folder_path
+                    .as_ref()
+                    .join(&id)
+                    .join(REVERSED_ADJACENCY_STORE),
+                true,
+                compression,
+            )?,
+            folder_path: folder_path
+                .as_ref()
+                .as_os_str()
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("segment path is not valid utf-8"))?
+                .to_string(),
+            id,
+        })
+    }
+
     pub fn outgoing_edges_with_label(&self, node: &NodeID) -> Vec<Edge<String>> {
         self.adjacency.get_with_label(node)
     }
@@ -128,6 +157,10 @@ impl Segment {
         self.reversed_adjacency.get_without_label(node)
     }
 
+    pub fn ingoing_edges_with_weight(&self, node: &NodeID) -> Vec<(Edge<()>, f32)> {
+        self.reversed_adjacency.get_with_weight(node)
+    }
+
     pub fn ingoing_edges_by_host(&self, host_node: &NodeID) -> Vec<Edge<()>> {
         self.reversed_adjacency
             .nodes_by_prefix(host_node)
@@ -151,6 +184,10 @@ impl Segment {
     pub fn edges(&self) -> impl Iterator<Item = Edge<()>> + '_ + Send + Sync {
         self.adjacency.iter_without_label()
     }
+
+    pub fn weighted_edges(&self) -> impl Iterator<Item = (Edge<()>, f32)> + '_ + Send + Sync {
+        self.adjacency.iter_with_weight()
+    }
 }
 
 #[cfg(test)]
@@ -193,21 +230,25 @@ mod test {
             from: a.clone(),
             to: b.clone(),
             label: String::new(),
+            weight: 1.0,
         });
         edges.push(InnerEdge {
             from: b.clone(),
             to: c.clone(),
             label: String::new(),
+            weight: 1.0,
         });
         edges.push(InnerEdge {
             from: c.clone(),
             to: a.clone(),
             label: String::new(),
+            weight: 1.0,
         });
         edges.push(InnerEdge {
             from: a.clone(),
             to: c.clone(),
             label: String::new(),
+            weight: 1.0,
         });
 
         writer.insert(&edges);
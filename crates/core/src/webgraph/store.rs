@@ -22,11 +22,12 @@ use rocksdb::BlockBasedOptions;
 
 use super::{Compression, Edge, EdgeLabel, FullNodeID, InnerEdge, NodeID};
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 struct SerializedEdge {
     from_prefix: NodeID,
     to_prefix: NodeID,
     label: Vec<u8>,
+    weight: f32,
 }
 
 pub const MAX_BATCH_SIZE: usize = 100_000;
@@ -91,6 +92,7 @@ impl EdgeStoreWriter {
                 from_prefix: edge.from.prefix,
                 to_prefix: edge.to.prefix,
                 label: value_bytes.clone(),
+                weight: edge.weight,
             })
             .unwrap();
 
@@ -151,6 +153,7 @@ impl EdgeStoreWriter {
                         id: NodeID(to),
                     },
                     label: L::from_bytes(&val.label).unwrap(),
+                    weight: val.weight,
                 })
             })
     }
@@ -181,7 +184,7 @@ struct PrefixDb {
 }
 
 impl PrefixDb {
-    fn open<P: AsRef<Path>>(path: P) -> Self {
+    fn options() -> rocksdb::Options {
         let mut options = rocksdb::Options::default();
         options.create_if_missing(true);
 
@@ -213,11 +216,21 @@ impl PrefixDb {
         options.set_block_based_table_factory(&block_options);
         options.set_compression_type(rocksdb::DBCompressionType::Lz4);
 
-        let db = rocksdb::DB::open(&options, path).unwrap();
+        options
+    }
+
+    fn open<P: AsRef<Path>>(path: P) -> Self {
+        let db = rocksdb::DB::open(&Self::options(), path).unwrap();
 
         Self { db }
     }
 
+    fn open_read_only<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let db = rocksdb::DB::open_for_read_only(&Self::options(), path, false)?;
+
+        Ok(Self { db })
+    }
+
     fn insert(&self, node: &FullNodeID) {
         let mut opts = rocksdb::WriteOptions::default();
         opts.disable_wal(true);
@@ -269,7 +282,7 @@ impl PrefixDb {
 
 pub struct EdgeStore {
     reversed: bool,
-    ranges: rocksdb::DB, // column[nodes] = full_nodeid -> (start, end); column[labels] = nodeid -> (start, end)
+    ranges: rocksdb::DB, // column[nodes] = full_nodeid -> (start, end); column[labels] = nodeid -> (start, end); column[weights] = nodeid -> (start, end)
     prefixes: PrefixDb,
     _cache: rocksdb::Cache,
 
@@ -281,11 +294,15 @@ pub struct EdgeStore {
     edge_nodes_len: usize,
     edge_nodes: Mmap,
 
+    edge_weights_file: File,
+    edge_weights_len: usize,
+    edge_weights: Mmap,
+
     compression: Compression,
 }
 
 impl EdgeStore {
-    pub fn open<P: AsRef<Path>>(path: P, reversed: bool, compression: Compression) -> Self {
+    fn options(cache: &rocksdb::Cache) -> rocksdb::Options {
         let mut options = rocksdb::Options::default();
         options.create_if_missing(true);
 
@@ -302,8 +319,6 @@ impl EdgeStore {
         options.set_level_zero_slowdown_writes_trigger(-1);
         options.set_level_zero_stop_writes_trigger(-1);
 
-        let cache = rocksdb::Cache::new_lru_cache(256 * 1024 * 1024); // 256 mb
-
         // some recommended settings (https://github.com/facebook/rocksdb/wiki/Setup-Options-and-Basic-Tuning)
         options.set_level_compaction_dynamic_level_bytes(true);
         options.set_bytes_per_sync(1048576);
@@ -312,7 +327,7 @@ impl EdgeStore {
         block_options.set_format_version(5);
         block_options.set_cache_index_and_filter_blocks(true);
         block_options.set_pin_l0_filter_and_index_blocks_in_cache(true);
-        block_options.set_block_cache(&cache);
+        block_options.set_block_cache(cache);
 
         block_options.set_ribbon_filter(10.0);
 
@@ -320,17 +335,37 @@ impl EdgeStore {
         options.set_compression_type(rocksdb::DBCompressionType::None);
         options.optimize_for_point_lookup(512);
 
+        options
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, reversed: bool, compression: Compression) -> Self {
+        let cache = rocksdb::Cache::new_lru_cache(256 * 1024 * 1024); // 256 mb
+        let options = Self::options(&cache);
+
         let ranges = match rocksdb::DB::open_cf_with_opts(
             &options,
             path.as_ref().join("ranges"),
-            [("nodes", options.clone()), ("labels", options.clone())],
+            [
+                ("nodes", options.clone()),
+                ("labels", options.clone()),
+                ("weights", options.clone()),
+            ],
         ) {
             Ok(db) => db,
             Err(_) => {
-                let mut ranges = rocksdb::DB::open(&options, path.as_ref().join("ranges")).unwrap();
-
-                ranges.create_cf("nodes", &options).unwrap();
-                ranges.create_cf("labels", &options).unwrap();
+                let mut ranges = rocksdb::DB::open_cf(
+                    &options,
+                    path.as_ref().join("ranges"),
+                    rocksdb::DB::list_cf(&options, path.as_ref().join("ranges"))
+                        .unwrap_or_default(),
+                )
+                .unwrap();
+
+                for cf in ["nodes", "labels", "weights"] {
+                    if ranges.cf_handle(cf).is_none() {
+                        ranges.create_cf(cf, &options).unwrap();
+                    }
+                }
 
                 ranges
             }
@@ -354,6 +389,15 @@ impl EdgeStore {
         let edge_nodes = unsafe { Mmap::map(&edge_nodes_file).unwrap() };
         let edge_nodes_len = edge_nodes.len();
 
+        let edge_weights_file = File::options()
+            .read(true)
+            .create(true)
+            .write(true)
+            .open(path.as_ref().join("weights"))
+            .unwrap();
+        let edge_weights = unsafe { Mmap::map(&edge_weights_file).unwrap() };
+        let edge_weights_len = edge_weights.len();
+
         Self {
             reversed,
             ranges,
@@ -365,10 +409,59 @@ impl EdgeStore {
             edge_nodes,
             edge_nodes_file,
             edge_nodes_len,
+            edge_weights,
+            edge_weights_file,
+            edge_weights_len,
             compression,
         }
     }
 
+    /// Open an existing store without creating or modifying any of its files.
+    pub fn open_read_only<P: AsRef<Path>>(
+        path: P,
+        reversed: bool,
+        compression: Compression,
+    ) -> anyhow::Result<Self> {
+        let cache = rocksdb::Cache::new_lru_cache(256 * 1024 * 1024); // 256 mb
+        let options = Self::options(&cache);
+
+        let ranges = rocksdb::DB::open_cf_for_read_only(
+            &options,
+            path.as_ref().join("ranges"),
+            ["nodes", "labels", "weights"],
+            false,
+        )?;
+
+        let edge_labels_file = File::open(path.as_ref().join("labels"))?;
+        let edge_labels = unsafe { Mmap::map(&edge_labels_file)? };
+        let edge_labels_len = edge_labels.len();
+
+        let edge_nodes_file = File::open(path.as_ref().join("nodes"))?;
+        let edge_nodes = unsafe { Mmap::map(&edge_nodes_file)? };
+        let edge_nodes_len = edge_nodes.len();
+
+        let edge_weights_file = File::open(path.as_ref().join("weights"))?;
+        let edge_weights = unsafe { Mmap::map(&edge_weights_file)? };
+        let edge_weights_len = edge_weights.len();
+
+        Ok(Self {
+            reversed,
+            ranges,
+            prefixes: PrefixDb::open_read_only(path.as_ref().join("prefixes"))?,
+            _cache: cache,
+            edge_labels,
+            edge_labels_len,
+            edge_labels_file,
+            edge_nodes,
+            edge_nodes_file,
+            edge_nodes_len,
+            edge_weights,
+            edge_weights_file,
+            edge_weights_len,
+            compression,
+        })
+    }
+
     /// Insert a batch of edges into the store.
     /// The edges *must* have been de-duplicated by their from/to node.
     /// I.e. if the store is not reversed, there should only ever be a single
@@ -389,15 +482,18 @@ impl EdgeStore {
 
         let node_cf = self.ranges.cf_handle("nodes").unwrap();
         let label_cf = self.ranges.cf_handle("labels").unwrap();
+        let weight_cf = self.ranges.cf_handle("weights").unwrap();
 
         debug_assert!(self.ranges.get_cf(node_cf, node_bytes).unwrap().is_none());
         debug_assert!(self.ranges.get_cf(label_cf, node_bytes).unwrap().is_none());
 
         let mut edge_labels = Vec::new();
         let mut edge_nodes = Vec::new();
+        let mut edge_weights = Vec::new();
 
         for edge in edges {
             edge_labels.push(edge.label.clone());
+            edge_weights.push(edge.weight);
             edge_nodes.push(if self.reversed {
                 edge.from.id
             } else {
@@ -407,18 +503,26 @@ impl EdgeStore {
 
         let edge_labels_bytes = bincode::serialize(&edge_labels).unwrap();
         let edge_nodes_bytes = bincode::serialize(&edge_nodes).unwrap();
+        let edge_weights_bytes = bincode::serialize(&edge_weights).unwrap();
 
         let edge_labels_bytes = self.compression.compress(&edge_labels_bytes);
         let edge_nodes_bytes = self.compression.compress(&edge_nodes_bytes);
+        let edge_weights_bytes = self.compression.compress(&edge_weights_bytes);
 
         let label_range = self.edge_labels_len..(self.edge_labels_len + edge_labels_bytes.len());
         let node_range = self.edge_nodes_len..(self.edge_nodes_len + edge_nodes_bytes.len());
+        let weight_range =
+            self.edge_weights_len..(self.edge_weights_len + edge_weights_bytes.len());
 
         self.edge_labels_len += edge_labels_bytes.len();
         self.edge_nodes_len += edge_nodes_bytes.len();
+        self.edge_weights_len += edge_weights_bytes.len();
 
         self.edge_labels_file.write_all(&edge_labels_bytes).unwrap();
         self.edge_nodes_file.write_all(&edge_nodes_bytes).unwrap();
+        self.edge_weights_file
+            .write_all(&edge_weights_bytes)
+            .unwrap();
 
         let mut opt = rocksdb::WriteOptions::default();
         opt.disable_wal(true);
@@ -440,6 +544,15 @@ impl EdgeStore {
                 &opt,
             )
             .unwrap();
+
+        self.ranges
+            .put_cf_opt(
+                weight_cf,
+                node_bytes,
+                bincode::serialize(&weight_range).unwrap(),
+                &opt,
+            )
+            .unwrap();
     }
 
     /// Build a new edge store from a set of edges. The edges must be sorted by
@@ -495,15 +608,39 @@ impl EdgeStore {
         self.ranges
             .flush_cf(self.ranges.cf_handle("labels").unwrap())
             .unwrap();
+        self.ranges
+            .flush_cf(self.ranges.cf_handle("weights").unwrap())
+            .unwrap();
 
         self.edge_nodes_file.flush().unwrap();
         self.edge_labels_file.flush().unwrap();
+        self.edge_weights_file.flush().unwrap();
 
         self.edge_nodes = unsafe { Mmap::map(&self.edge_nodes_file).unwrap() };
         self.edge_labels = unsafe { Mmap::map(&self.edge_labels_file).unwrap() };
+        self.edge_weights = unsafe { Mmap::map(&self.edge_weights_file).unwrap() };
 
         self.edge_nodes_len = self.edge_nodes.len();
         self.edge_labels_len = self.edge_labels.len();
+        self.edge_weights_len = self.edge_weights.len();
+    }
+
+    /// The weights of the edges of the node. Stores that were built before the
+    /// weights were stored have a weight of 1 for every edge.
+    fn weights(&self, node_bytes: &[u8], num_edges: usize) -> Vec<f32> {
+        let weight_cf = self.ranges.cf_handle("weights").unwrap();
+
+        match self.ranges.get_cf(weight_cf, node_bytes).unwrap() {
+            Some(weight_range_bytes) => {
+                let weight_range =
+                    bincode::deserialize::<Range<usize>>(&weight_range_bytes).unwrap();
+
+                let edge_weights = &self.edge_weights[weight_range];
+                let edge_weights = self.compression.decompress(edge_weights);
+                bincode::deserialize(&edge_weights).unwrap()
+            }
+            None => vec![1.0; num_edges],
+        }
     }
 
     pub fn get_with_label(&self, node: &NodeID) -> Vec<Edge<String>> {
@@ -588,6 +725,13 @@ impl EdgeStore {
         }
     }
 
+    pub fn get_with_weight(&self, node: &NodeID) -> Vec<(Edge<()>, f32)> {
+        let edges = self.get_without_label(node);
+        let weights = self.weights(&node.as_u64().to_le_bytes(), edges.len());
+
+        edges.into_iter().zip_eq(weights).collect()
+    }
+
     pub fn nodes_by_prefix(&self, prefix: &NodeID) -> Vec<NodeID> {
         self.prefixes.get(prefix)
     }
@@ -625,6 +769,46 @@ impl EdgeStore {
                 })
             })
     }
+
+    pub fn iter_with_weight(&self) -> impl Iterator<Item = (Edge<()>, f32)> + '_ + Send + Sync {
+        let node_cf = self.ranges.cf_handle("nodes").unwrap();
+
+        self.ranges
+            .iterator_cf(node_cf, rocksdb::IteratorMode::Start)
+            .flat_map(move |res| {
+                let (key, val) = res.unwrap();
+
+                let node = NodeID(u64::from_le_bytes((*key).try_into().unwrap()));
+
+                let node_range = bincode::deserialize::<Range<usize>>(&val).unwrap();
+                let edge_nodes = &self.edge_nodes[node_range];
+                let edge_nodes = self.compression.decompress(edge_nodes);
+                let edge_nodes: Vec<NodeID> = bincode::deserialize(&edge_nodes).unwrap();
+
+                let weights = self.weights(&key, edge_nodes.len());
+
+                edge_nodes
+                    .into_iter()
+                    .zip_eq(weights)
+                    .map(move |(other, weight)| {
+                        let edge = if self.reversed {
+                            Edge {
+                                from: other,
+                                to: node,
+                                label: (),
+                            }
+                        } else {
+                            Edge {
+                                from: node,
+                                to: other,
+                                label: (),
+                            }
+                        };
+
+                        (edge, weight)
+                    })
+            })
+    }
 }
 
 #[cfg(test)]
@@ -651,6 +835,7 @@ mod tests {
                 prefix: NodeID(0),
             },
             label: "test".to_string(),
+            weight: 1.0,
         };
 
         kv.put([e.clone()].iter());
@@ -685,6 +870,7 @@ mod tests {
                 prefix: NodeID(0),
             },
             label: "test".to_string(),
+            weight: 1.0,
         };
 
         kv.put([e.clone()].iter());
@@ -698,4 +884,50 @@ mod tests {
         assert_eq!(edges.len(), 1);
         assert_eq!(&edges[0], &Edge::from(e.clone()));
     }
+
+    #[test]
+    fn test_weights() {
+        let kv: EdgeStoreWriter = EdgeStoreWriter::open(
+            crate::gen_temp_path().join("test-segment"),
+            Compression::default(),
+            false,
+        );
+
+        let node = |id| FullNodeID {
+            id: NodeID(id),
+            prefix: NodeID(0),
+        };
+
+        let edges = [
+            InnerEdge {
+                from: node(0),
+                to: node(1),
+                label: String::new(),
+                weight: 0.5,
+            },
+            InnerEdge {
+                from: node(0),
+                to: node(2),
+                label: String::new(),
+                weight: 2.0,
+            },
+        ];
+
+        kv.put(edges.iter());
+
+        let store = kv.finalize();
+
+        let weights: Vec<_> = store
+            .get_with_weight(&NodeID(0))
+            .into_iter()
+            .map(|(edge, weight)| (edge.to, weight))
+            .collect();
+        assert_eq!(weights, vec![(NodeID(1), 0.5), (NodeID(2), 2.0)]);
+
+        let weights: Vec<_> = store
+            .iter_with_weight()
+            .map(|(edge, weight)| (edge.to, weight))
+            .collect();
+        assert_eq!(weights, vec![(NodeID(1), 0.5), (NodeID(2), 2.0)]);
+    }
 }
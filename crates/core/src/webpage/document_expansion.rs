@@ -0,0 +1,208 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Sparse document expansion. The model maps a term on a page to weighted terms
+//! that queries for the page are likely to use, e.g. exported from the queries
+//! generated by a docT5query model or the vocabulary projection of a SPLADE model.
+//! The expansion terms of a page are indexed in their own field, so pages can be found
+//! by queries that use a different vocabulary than the page itself.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::anyhow;
+use itertools::Itertools;
+
+use crate::Result;
+
+const MAX_NUM_WORDS: usize = 512;
+
+/// The number of times the expansion term with the highest weight is repeated
+/// in the indexed text. Terms with lower weights are repeated proportionally fewer
+/// times, so the term frequency seen by bm25 follows the weights of the model.
+pub const MAX_TERM_FREQ: usize = 5;
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .take(MAX_NUM_WORDS)
+        .map(|word| word.to_lowercase())
+}
+
+#[derive(Debug, Default)]
+pub struct Model {
+    expansions: HashMap<String, Vec<(String, f32)>>,
+}
+
+impl Model {
+    /// Open a model stored as tab separated `term, expansion, weight` rows.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut model = Self::default();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (term, expansion, weight) = line
+                .split('\t')
+                .collect_tuple()
+                .ok_or_else(|| anyhow!("line {} should have 3 tab separated columns", i + 1))?;
+
+            let weight: f32 = weight.trim().parse()?;
+            model.insert(term.trim(), expansion.trim(), weight);
+        }
+
+        Ok(model)
+    }
+
+    pub fn insert(&mut self, term: &str, expansion: &str, weight: f32) {
+        if weight <= 0.0 || expansion.is_empty() {
+            return;
+        }
+
+        self.expansions
+            .entry(term.to_lowercase())
+            .or_default()
+            .push((expansion.to_lowercase(), weight));
+    }
+
+    /// The `max_terms` expansion terms with the highest weights that are not already in the text.
+    /// The weights are scaled so the highest weight is 1.
+    pub fn expand_text(&self, text: &str, max_terms: usize) -> Vec<(String, f32)> {
+        let terms: HashSet<String> = words(text).collect();
+        let mut weights: HashMap<&str, f32> = HashMap::new();
+
+        for term in &terms {
+            if let Some(expansions) = self.expansions.get(term) {
+                for (expansion, weight) in expansions {
+                    if !terms.contains(expansion) {
+                        *weights.entry(expansion.as_str()).or_default() += weight;
+                    }
+                }
+            }
+        }
+
+        let top = weights
+            .into_iter()
+            .sorted_by(|(a_term, a), (b_term, b)| b.total_cmp(a).then(a_term.cmp(b_term)))
+            .take(max_terms)
+            .collect_vec();
+
+        let max_weight = top.first().map(|(_, weight)| *weight).unwrap_or(1.0);
+
+        top.into_iter()
+            .map(|(term, weight)| (term.to_string(), weight / max_weight))
+            .collect()
+    }
+
+    pub fn expand(&self, page: &super::Webpage, max_terms: usize) -> Vec<(String, f32)> {
+        self.expand_text(&super::topic_classifier::page_text(page), max_terms)
+    }
+}
+
+/// The text that is indexed for the expansion terms, where each term
+/// is repeated according to its weight.
+pub fn indexed_text(terms: &[(String, f32)]) -> String {
+    terms
+        .iter()
+        .flat_map(|(term, weight)| {
+            let freq = (weight.clamp(0.0, 1.0) * MAX_TERM_FREQ as f32).ceil() as usize;
+            std::iter::repeat(term.as_str()).take(freq.max(1))
+        })
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model() -> Model {
+        let mut model = Model::default();
+
+        model.insert("laptop", "notebook", 2.0);
+        model.insert("laptop", "computer", 1.0);
+        model.insert("cheap", "budget", 1.0);
+        model.insert("cheap", "notebook", 0.5);
+
+        model
+    }
+
+    #[test]
+    fn expand() {
+        let model = model();
+
+        assert_eq!(
+            model.expand_text("Cheap laptops and a cheap LAPTOP", 10),
+            vec![
+                ("notebook".to_string(), 1.0),
+                ("budget".to_string(), 0.4),
+                ("computer".to_string(), 0.4),
+            ]
+        );
+
+        assert_eq!(
+            model.expand_text("cheap laptop", 1),
+            vec![("notebook".to_string(), 1.0)]
+        );
+
+        // terms that are already on the page are not expansions
+        assert_eq!(
+            model.expand_text("laptop notebook", 10),
+            vec![("computer".to_string(), 1.0)]
+        );
+
+        assert!(model.expand_text("nothing to see here", 10).is_empty());
+    }
+
+    #[test]
+    fn repeated_by_weight() {
+        assert_eq!(
+            indexed_text(&[("notebook".to_string(), 1.0), ("budget".to_string(), 0.3)]),
+            "notebook notebook notebook notebook notebook budget budget"
+        );
+        assert_eq!(indexed_text(&[]), "");
+    }
+
+    #[test]
+    fn open() {
+        let path = crate::gen_temp_path().join("expansion.tsv");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(
+            &path,
+            "# term\texpansion\tweight\nlaptop\tnotebook\t2\n\nlaptop\tcomputer\t0.5\n",
+        )
+        .unwrap();
+
+        let model = Model::open(&path).unwrap();
+
+        assert_eq!(
+            model.expand_text("laptop", 10),
+            vec![
+                ("notebook".to_string(), 1.0),
+                ("computer".to_string(), 0.25)
+            ]
+        );
+
+        std::fs::write(&path, "laptop notebook 2\n").unwrap();
+        assert!(Model::open(&path).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+
+use super::{schema_org, Html};
+
+const MAX_AUTHORS_PER_PAGE: usize = 16;
+const MAX_AUTHOR_CHARS: usize = 64;
+
+/// Meta tags with the name of an author. `citation_author` is used by scholarly
+/// articles, `dc.creator` is Dublin Core and `parsely-author` is used by news sites.
+const AUTHOR_META: [&str; 5] = [
+    "author",
+    "article:author",
+    "citation_author",
+    "dc.creator",
+    "parsely-author",
+];
+
+/// Meta tag with the byline of news articles, e.g. "By Jane Doe and John Smith".
+const BYLINE_META: &str = "byl";
+
+/// Elements with the byline of an article.
+const BYLINE_SELECTOR: &str = "[rel=author], .byline .author, .author-name, .p-author";
+
+/// Trim a name and remove a leading "By" as in "By Jane Doe". Urls, e.g. in `article:author`,
+/// and text that is too long to be a name are not authors.
+fn normalize_author(name: &str) -> Option<String> {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    let name = name
+        .strip_prefix("By ")
+        .or_else(|| name.strip_prefix("by "))
+        .unwrap_or(&name)
+        .trim_matches(|c: char| c.is_whitespace() || c == ',' || c == '|');
+
+    if name.is_empty()
+        || name.chars().count() > MAX_AUTHOR_CHARS
+        || name.contains("://")
+        || name.starts_with("www.")
+        || name.contains('@')
+    {
+        return None;
+    }
+
+    Some(name.to_string())
+}
+
+/// Split a byline such as "By Jane Doe and John Smith" into the names of the authors.
+fn split_byline(byline: &str) -> Vec<String> {
+    byline
+        .split([',', '&', ';'])
+        .flat_map(|part| part.split(" and "))
+        .filter_map(normalize_author)
+        .collect()
+}
+
+fn schema_authors(item: &schema_org::Item) -> Vec<String> {
+    let Some(authors) = item.properties.get("author") else {
+        return Vec::new();
+    };
+
+    authors
+        .clone()
+        .many()
+        .into_iter()
+        .filter_map(|author| match author {
+            schema_org::Property::String(name) => Some(name),
+            schema_org::Property::Item(author) => author
+                .properties
+                .get("name")
+                .and_then(|name| name.clone().one())
+                .and_then(|name| name.try_into_string()),
+        })
+        .filter_map(|name| normalize_author(&name))
+        .collect()
+}
+
+impl Html {
+    /// The authors of the page, found in the author meta tags, in the `author` property of
+    /// schema.org items and in the byline of the article. The first spelling of a name is kept.
+    pub fn authors(&self) -> Vec<String> {
+        let mut authors = Vec::new();
+
+        for meta in self.metadata() {
+            let Some(content) = meta.get("content") else {
+                continue;
+            };
+
+            let Some(name) = meta.get("name").or_else(|| meta.get("property")) else {
+                continue;
+            };
+
+            let name = name.to_lowercase();
+
+            if name == BYLINE_META {
+                authors.extend(split_byline(content));
+            } else if AUTHOR_META.contains(&name.as_str()) {
+                authors.extend(normalize_author(content));
+            }
+        }
+
+        for item in self.schema_org() {
+            authors.extend(schema_authors(&item));
+        }
+
+        if let Ok(bylines) = self.root.select(BYLINE_SELECTOR) {
+            for byline in bylines {
+                authors.extend(split_byline(&byline.text_contents()));
+            }
+        }
+
+        let mut seen = HashSet::new();
+        authors.retain(|author| seen.insert(author.to_lowercase()));
+        authors.truncate(MAX_AUTHORS_PER_PAGE);
+
+        authors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalization() {
+        assert_eq!(
+            normalize_author("  By   Jane  Doe "),
+            Some("Jane Doe".to_string())
+        );
+        assert_eq!(normalize_author("https://www.facebook.com/janedoe"), None);
+        assert_eq!(normalize_author("jane@example.com"), None);
+        assert_eq!(normalize_author(&"a".repeat(100)), None);
+        assert_eq!(normalize_author(" "), None);
+    }
+
+    #[test]
+    fn bylines() {
+        assert_eq!(
+            split_byline("By Jane Doe and John Smith"),
+            vec!["Jane Doe".to_string(), "John Smith".to_string()]
+        );
+        assert_eq!(
+            split_byline("Jane Doe, John Smith & Alice"),
+            vec![
+                "Jane Doe".to_string(),
+                "John Smith".to_string(),
+                "Alice".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn meta_and_schema_org_authors() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <head>
+                    <meta name="author" content="Jane Doe">
+                    <meta property="article:author" content="https://www.facebook.com/janedoe">
+                    <meta name="byl" content="By JANE DOE and John Smith">
+                    <script type="application/ld+json">
+                    {
+                        "@context": "https://schema.org",
+                        "@type": "NewsArticle",
+                        "headline": "Test",
+                        "author": [
+                            {"@type": "Person", "name": "Alice Example"},
+                            "Bob Example"
+                        ]
+                    }
+                    </script>
+                </head>
+                <body>
+                    <span class="byline">By <a class="author" href="/carol">Carol Example</a></span>
+                </body>
+            </html>
+            "#,
+            "https://news.example.com/article",
+        )
+        .unwrap();
+
+        assert_eq!(
+            html.authors(),
+            vec![
+                "Jane Doe".to_string(),
+                "John Smith".to_string(),
+                "Alice Example".to_string(),
+                "Bob Example".to_string(),
+                "Carol Example".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_authors() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <head><title>Test</title></head>
+                <body>No authors here</body>
+            </html>
+            "#,
+            "https://example.com/",
+        )
+        .unwrap();
+
+        assert!(html.authors().is_empty());
+    }
+}
@@ -0,0 +1,201 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Estimate how likely the text of a page is to be machine generated.
+//!
+//! The estimate is a logistic combination of stylometric features: generated
+//! text tends to use a small set of stock phrases, its sentences have very
+//! uniform lengths and low-effort generated sites repeat the same phrasings
+//! over and over.
+
+use std::collections::HashSet;
+
+use super::Html;
+
+/// The likelihood is not estimated for pages with fewer words than this.
+const MIN_WORDS: usize = 100;
+
+/// Pages with a likelihood above this are annotated as likely generated.
+pub const LIKELY_GENERATED_THRESHOLD: f64 = 0.7;
+
+/// Phrases that are much more common in generated text than in text written by people.
+const STOCK_PHRASES: &[&str] = &[
+    "as an ai language model",
+    "it's important to note",
+    "it is important to note",
+    "it is worth noting",
+    "it's worth noting",
+    "in today's fast-paced",
+    "in today's digital age",
+    "in the realm of",
+    "a testament to",
+    "rich tapestry",
+    "delve into",
+    "delves into",
+    "dive into",
+    "navigating the",
+    "whether you're",
+    "unlock the",
+    "elevate your",
+    "embark on",
+    "in conclusion",
+    "comprehensive guide",
+    "game-changer",
+    "seamlessly",
+    "ever-evolving",
+    "look no further",
+];
+
+const INTERCEPT: f64 = -2.0;
+const STOCK_PHRASE_WEIGHT: f64 = 2.0;
+const UNIFORMITY_WEIGHT: f64 = 5.0;
+const REPETITION_WEIGHT: f64 = 4.0;
+
+/// Sentence lengths with a coefficient of variation below this are more uniform
+/// than what is typical for text written by people.
+const TYPICAL_SENTENCE_VARIATION: f64 = 0.5;
+
+/// Stock phrases per 100 words. Capped so a single phrase-heavy paragraph
+/// cannot dominate the other features.
+fn stock_phrase_rate(text: &str, num_words: usize) -> f64 {
+    let text = text.to_lowercase();
+
+    let count: usize = STOCK_PHRASES
+        .iter()
+        .map(|phrase| text.matches(phrase).count())
+        .sum();
+
+    (count as f64 * 100.0 / num_words as f64).min(3.0)
+}
+
+/// The coefficient of variation of the number of words in each sentence.
+fn sentence_length_variation(text: &str) -> f64 {
+    let lengths: Vec<f64> = text
+        .split(['.', '!', '?', '\n'])
+        .map(|sentence| sentence.split_whitespace().count() as f64)
+        .filter(|len| *len > 0.0)
+        .collect();
+
+    if lengths.len() < 2 {
+        return 0.0;
+    }
+
+    let mean = lengths.iter().sum::<f64>() / lengths.len() as f64;
+    let variance =
+        lengths.iter().map(|len| (len - mean).powi(2)).sum::<f64>() / lengths.len() as f64;
+
+    variance.sqrt() / mean
+}
+
+/// The fraction of word trigrams that have already occurred earlier in the text.
+fn repeated_trigrams(words: &[String]) -> f64 {
+    if words.len() < 3 {
+        return 0.0;
+    }
+
+    let mut seen = HashSet::new();
+    let mut repeated = 0;
+
+    for trigram in words.windows(3) {
+        if !seen.insert(trigram) {
+            repeated += 1;
+        }
+    }
+
+    repeated as f64 / (words.len() - 2) as f64
+}
+
+/// The likelihood from 0 to 1 that the text is machine generated.
+pub fn generated_likelihood(text: &str) -> Option<f64> {
+    let words: Vec<String> = text
+        .split_whitespace()
+        .map(|word| {
+            word.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    if words.len() < MIN_WORDS {
+        return None;
+    }
+
+    let logit = INTERCEPT
+        + STOCK_PHRASE_WEIGHT * stock_phrase_rate(text, words.len())
+        + UNIFORMITY_WEIGHT * (TYPICAL_SENTENCE_VARIATION - sentence_length_variation(text))
+        + REPETITION_WEIGHT * repeated_trigrams(&words);
+
+    Some(1.0 / (1.0 + (-logit).exp()))
+}
+
+impl Html {
+    pub fn generated_likelihood(&self) -> Option<f64> {
+        self.clean_text()
+            .map(String::as_str)
+            .and_then(generated_likelihood)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HUMAN: &str =
+        "I bought the bike in March. It was cheap, maybe too cheap, and the seller \
+        would not tell me where it came from. Fine. The chain slipped on every hill for the first \
+        two weeks until a neighbour, who used to race in the eighties and still owns three frames \
+        hanging in his garage, showed me how to adjust the derailleur with nothing but a \
+        screwdriver and some patience. Now it works. Mostly. Last Sunday I rode out to the lake, \
+        had a coffee at the kiosk that only opens when the weather is good, and came back with \
+        sore legs and a flat rear tyre. Was it worth it? Absolutely, although my wife thinks I \
+        should have bought a new one from the shop in town instead of a mystery bike from a \
+        stranger on the internet.";
+
+    const GENERATED: &str = "In today's fast-paced world, cycling is more than a hobby. \
+        It is important to note that cycling offers many health benefits. \
+        Whether you're a beginner or an expert, cycling can elevate your fitness. \
+        In this comprehensive guide, we delve into the rich tapestry of cycling. \
+        Navigating the world of bikes can seem overwhelming at first. \
+        It is worth noting that the right bike seamlessly fits your lifestyle. \
+        Let us dive into the key factors you should consider today. \
+        Comfort, price and durability are all important factors to consider. \
+        Embark on your cycling journey with confidence and joy today. \
+        In conclusion, cycling is a game-changer for your health and happiness.";
+
+    #[test]
+    fn human_text_is_unlikely_generated() {
+        let human = generated_likelihood(HUMAN).unwrap();
+        let generated = generated_likelihood(GENERATED).unwrap();
+
+        assert!(human < generated);
+        assert!(human < LIKELY_GENERATED_THRESHOLD);
+        assert!(generated > LIKELY_GENERATED_THRESHOLD);
+    }
+
+    #[test]
+    fn repetitive_text_is_likely_generated() {
+        let spun = "Best cheap flights to Paris. Book cheap flights to Paris now. ".repeat(20);
+        assert!(generated_likelihood(&spun).unwrap() > LIKELY_GENERATED_THRESHOLD);
+    }
+
+    #[test]
+    fn short_text() {
+        assert_eq!(
+            generated_likelihood("In conclusion, this is too short."),
+            None
+        );
+    }
+}
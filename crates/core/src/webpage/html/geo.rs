@@ -0,0 +1,341 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use kuchiki::NodeRef;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{schema_org, Html};
+
+/// Pages with more places than this are most likely directories,
+/// so only the first places are kept.
+const MAX_PLACES_PER_PAGE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct GeoCoordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl GeoCoordinates {
+    pub fn new(latitude: f64, longitude: f64) -> Option<Self> {
+        if (-90.0..=90.0).contains(&latitude)
+            && (-180.0..=180.0).contains(&longitude)
+            && !(latitude == 0.0 && longitude == 0.0)
+        {
+            Some(Self {
+                latitude,
+                longitude,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn parse(latitude: &str, longitude: &str) -> Option<Self> {
+        Self::new(
+            latitude.trim().parse().ok()?,
+            longitude.trim().parse().ok()?,
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Place {
+    pub name: String,
+    /// The schema.org types of the place and the cuisines it serves, e.g. `Restaurant` and `Pizza`.
+    pub categories: Vec<String>,
+    pub address: Option<String>,
+    pub coordinates: GeoCoordinates,
+}
+
+fn first_string(item: &schema_org::Item, name: &str) -> Option<String> {
+    item.properties
+        .get(name)?
+        .clone()
+        .many()
+        .into_iter()
+        .find_map(|prop| prop.try_into_string())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+fn first_item(item: &schema_org::Item, name: &str) -> Option<schema_org::Item> {
+    item.properties
+        .get(name)?
+        .clone()
+        .many()
+        .into_iter()
+        .find_map(|prop| prop.try_into_item())
+}
+
+fn schema_address(item: &schema_org::Item) -> Option<String> {
+    if let Some(address) = first_string(item, "address") {
+        return Some(address);
+    }
+
+    let address = first_item(item, "address")?;
+    let parts: Vec<_> = [
+        "streetAddress",
+        "postalCode",
+        "addressLocality",
+        "addressRegion",
+        "addressCountry",
+    ]
+    .into_iter()
+    .filter_map(|part| first_string(&address, part))
+    .collect();
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(", "))
+    }
+}
+
+fn schema_place(item: &schema_org::Item) -> Option<Place> {
+    let geo = first_item(item, "geo")?;
+    let coordinates = GeoCoordinates::parse(
+        &first_string(&geo, "latitude")?,
+        &first_string(&geo, "longitude")?,
+    )?;
+
+    let mut categories: Vec<_> = item
+        .itemtype
+        .clone()
+        .map(|types| types.many())
+        .unwrap_or_default();
+
+    if let Some(cuisines) = item.properties.get("servesCuisine") {
+        categories.extend(
+            cuisines
+                .clone()
+                .many()
+                .into_iter()
+                .filter_map(|cuisine| cuisine.try_into_string()),
+        );
+    }
+
+    Some(Place {
+        name: first_string(item, "name")?,
+        categories,
+        address: schema_address(item),
+        coordinates,
+    })
+}
+
+/// The JSON-LD items of the page with all their numbers converted to strings. The
+/// schema.org parser only converts integers, so items with decimal coordinates (the
+/// common case) are dropped by [`Html::schema_org`].
+fn json_ld_items(root: &NodeRef) -> Vec<schema_org::Item> {
+    use serde_json::Value;
+
+    fn convert_recursively(json: &mut Value) {
+        match json {
+            Value::Number(n) => {
+                *json = Value::String(n.to_string());
+            }
+            Value::Bool(b) => {
+                *json = Value::String(b.to_string());
+            }
+            Value::Array(a) => a.iter_mut().for_each(convert_recursively),
+            Value::Object(o) => o.values_mut().for_each(convert_recursively),
+            _ => (),
+        }
+    }
+
+    let Ok(scripts) = root.select("script[type=\"application/ld+json\"]") else {
+        return Vec::new();
+    };
+
+    scripts
+        .filter_map(|script| {
+            let mut json: Value = serde_json::from_str(script.text_contents().trim()).ok()?;
+            convert_recursively(&mut json);
+
+            serde_json::from_value::<schema_org::RawItem>(json).ok()
+        })
+        .map(schema_org::Item::from)
+        .collect()
+}
+
+fn class_text(root: &NodeRef, class: &str) -> Option<String> {
+    let node = root.select_first(&format!(".{class}")).ok()?;
+
+    // values are often abbreviated with the full value in the title, e.g. <abbr class="latitude" title="55.67">
+    let title = node.attributes.borrow().get("title").map(|s| s.to_string());
+    let text = title.unwrap_or_else(|| node.as_node().text_contents());
+    let text = text.trim();
+
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
+
+fn microformat_place(card: &NodeRef) -> Option<Place> {
+    let coordinates = GeoCoordinates::parse(
+        &class_text(card, "p-latitude").or_else(|| class_text(card, "latitude"))?,
+        &class_text(card, "p-longitude").or_else(|| class_text(card, "longitude"))?,
+    )?;
+
+    let address: Vec<_> = [
+        "p-street-address",
+        "p-postal-code",
+        "p-locality",
+        "p-country-name",
+    ]
+    .into_iter()
+    .filter_map(|class| class_text(card, class))
+    .collect();
+
+    Some(Place {
+        name: class_text(card, "p-name").or_else(|| class_text(card, "fn"))?,
+        categories: class_text(card, "p-category").into_iter().collect(),
+        address: if address.is_empty() {
+            None
+        } else {
+            Some(address.join(", "))
+        },
+        coordinates,
+    })
+}
+
+impl Html {
+    /// The places on the page with geo coordinates, found in schema.org items
+    /// with a `geo` property (e.g. `Place` and `LocalBusiness`) and in `h-card` microformats.
+    pub fn places(&self) -> Vec<Place> {
+        let mut places: Vec<Place> = Vec::new();
+
+        // items without decimals are found by both parsers
+        for place in self
+            .schema_org()
+            .iter()
+            .chain(json_ld_items(&self.root).iter())
+            .filter_map(schema_place)
+        {
+            if !places.contains(&place) {
+                places.push(place);
+            }
+        }
+
+        if let Ok(cards) = self.root.select(".h-card, .vcard") {
+            places.extend(cards.filter_map(|card| microformat_place(card.as_node())));
+        }
+
+        places.truncate(MAX_PLACES_PER_PAGE);
+        places
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_org_place() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <head>
+                    <script type="application/ld+json">
+                    {
+                        "@context": "https://schema.org",
+                        "@type": "Restaurant",
+                        "name": "Pizzeria Luca",
+                        "servesCuisine": "Pizza",
+                        "address": {
+                            "@type": "PostalAddress",
+                            "streetAddress": "Nørrebrogade 1",
+                            "addressLocality": "Copenhagen",
+                            "addressCountry": "DK"
+                        },
+                        "geo": {
+                            "@type": "GeoCoordinates",
+                            "latitude": 55.6867,
+                            "longitude": 12.5578
+                        }
+                    }
+                    </script>
+                </head>
+                <body></body>
+            </html>
+            "#,
+            "https://pizzerialuca.example.com/",
+        )
+        .unwrap();
+
+        assert_eq!(
+            html.places(),
+            vec![Place {
+                name: "Pizzeria Luca".to_string(),
+                categories: vec!["Restaurant".to_string(), "Pizza".to_string()],
+                address: Some("Nørrebrogade 1, Copenhagen, DK".to_string()),
+                coordinates: GeoCoordinates {
+                    latitude: 55.6867,
+                    longitude: 12.5578
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn h_card_place() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <body>
+                    <div class="h-card">
+                        <span class="p-name">Café Central</span>
+                        <span class="p-locality">Vienna</span>
+                        <span class="h-geo">
+                            <data class="p-latitude" value="48.2104">48.2104</data>
+                            <data class="p-longitude" value="16.3655">16.3655</data>
+                        </span>
+                    </div>
+                    <div class="h-card">
+                        <span class="p-name">No coordinates</span>
+                    </div>
+                </body>
+            </html>
+            "#,
+            "https://cafecentral.example.com/",
+        )
+        .unwrap();
+
+        assert_eq!(
+            html.places(),
+            vec![Place {
+                name: "Café Central".to_string(),
+                categories: vec![],
+                address: Some("Vienna".to_string()),
+                coordinates: GeoCoordinates {
+                    latitude: 48.2104,
+                    longitude: 16.3655
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn invalid_coordinates() {
+        assert_eq!(GeoCoordinates::new(91.0, 0.0), None);
+        assert_eq!(GeoCoordinates::new(0.0, 0.0), None);
+        assert!(GeoCoordinates::new(55.0, 12.0).is_some());
+    }
+}
@@ -19,6 +19,7 @@ use crate::{
     prehashed::hash,
     schema::{FastField, TextField},
     simhash, split_u128, tokenizer,
+    webgraph::Node,
     webpage::url_ext::UrlExt,
     Error, Result,
 };
@@ -149,6 +150,52 @@ impl Html {
 
         let schema_json = serde_json::to_string(&schemas).ok().unwrap_or_default();
 
+        let preview_image = self
+            .preview_image()
+            .and_then(|image| serde_json::to_string(&image).ok())
+            .unwrap_or_default();
+
+        let video = self.video();
+        let video_json = video
+            .as_ref()
+            .and_then(|video| serde_json::to_string(video).ok())
+            .unwrap_or_default();
+
+        let product = self.product();
+        let product_json = product
+            .as_ref()
+            .and_then(|product| serde_json::to_string(product).ok())
+            .unwrap_or_default();
+
+        let scholarly = self.scholarly();
+        let scholarly_json = scholarly
+            .as_ref()
+            .and_then(|scholarly| serde_json::to_string(scholarly).ok())
+            .unwrap_or_default();
+        let doi_hash = scholarly
+            .as_ref()
+            .and_then(|scholarly| scholarly.doi.as_ref())
+            .map(|doi| split_u128(hash(doi).0)[0])
+            .unwrap_or_default();
+
+        // the canonical url replaces the url of the page if it is on the same domain,
+        // so only canonical urls on other hosts are kept
+        let canonical_site_hash = self
+            .canonical_url()
+            .and_then(|canonical| canonical.normalized_host().map(str::to_string))
+            .filter(|host| Some(host.as_str()) != self.url().normalized_host())
+            .map(|host| split_u128(hash(host).0))
+            .unwrap_or_default();
+
+        let places = self.places();
+        let places_json = if places.is_empty() {
+            String::new()
+        } else {
+            serde_json::to_string(&places).unwrap_or_default()
+        };
+
+        let authors = self.authors();
+
         let pretokenized_schema_json = match schema_org::flattened_json(schemas) {
             Ok(mut f) => {
                 let mut tokens = Vec::new();
@@ -280,6 +327,25 @@ impl Html {
                         },
                     );
                 }
+                Field::Text(TextField::FetchedUrlNoTokenizer) => {
+                    if let Some(url) = self.canonicalized_from() {
+                        let url = url.to_string();
+
+                        doc.add_pre_tokenized_text(
+                            tantivy_field,
+                            PreTokenizedString {
+                                text: url.clone(),
+                                tokens: vec![tantivy::tokenizer::Token {
+                                    offset_from: 0,
+                                    offset_to: url.len(),
+                                    position: 0,
+                                    text: url,
+                                    position_length: 1,
+                                }],
+                            },
+                        );
+                    }
+                }
                 Field::Text(TextField::SiteWithout) => {
                     doc.add_pre_tokenized_text(tantivy_field, site.clone())
                 }
@@ -395,6 +461,26 @@ impl Html {
                 Field::Text(TextField::SchemaOrgJson) => {
                     doc.add_text(tantivy_field, schema_json.clone());
                 }
+                Field::Text(TextField::PreviewImage) => {
+                    doc.add_text(tantivy_field, preview_image.clone());
+                }
+                Field::Text(TextField::VideoMetadata) => {
+                    doc.add_text(tantivy_field, video_json.clone());
+                }
+                Field::Text(TextField::Product) => {
+                    doc.add_text(tantivy_field, product_json.clone());
+                }
+                Field::Text(TextField::Scholarly) => {
+                    doc.add_text(tantivy_field, scholarly_json.clone());
+                }
+                Field::Text(TextField::Places) => {
+                    doc.add_text(tantivy_field, places_json.clone());
+                }
+                Field::Text(TextField::Author) => {
+                    for author in &authors {
+                        doc.add_text(tantivy_field, author);
+                    }
+                }
                 Field::Text(TextField::FlattenedSchemaOrgJson) => {
                     doc.add_pre_tokenized_text(tantivy_field, pretokenized_schema_json.clone());
                 }
@@ -518,8 +604,57 @@ impl Html {
                         (self.link_density() * FLOAT_SCALING as f64) as u64,
                     );
                 }
+                Field::Fast(FastField::PageNodeID) => {
+                    doc.add_u64(tantivy_field, Node::from(self.url()).id().as_u64());
+                }
+                Field::Fast(FastField::IsVideo) => {
+                    doc.add_u64(tantivy_field, video.is_some() as u64);
+                }
+                Field::Fast(FastField::IsProduct) => {
+                    doc.add_u64(tantivy_field, product.is_some() as u64);
+                }
+                Field::Fast(FastField::IsScholarly) => {
+                    doc.add_u64(tantivy_field, scholarly.is_some() as u64);
+                }
+                Field::Fast(FastField::DoiHash) => {
+                    doc.add_u64(tantivy_field, doi_hash);
+                }
+                Field::Fast(FastField::PublishedTime) => doc.add_u64(
+                    tantivy_field,
+                    self.published_time()
+                        .map_or(0, |time| time.timestamp().max(0) as u64),
+                ),
+                Field::Fast(FastField::CanonicalSiteHash1) => {
+                    doc.add_u64(tantivy_field, canonical_site_hash[0]);
+                }
+                Field::Fast(FastField::CanonicalSiteHash2) => {
+                    doc.add_u64(tantivy_field, canonical_site_hash[1]);
+                }
+                Field::Fast(FastField::HasPlaces) => {
+                    doc.add_u64(tantivy_field, !places.is_empty() as u64);
+                }
+                Field::Fast(FastField::ReadingEase) => {
+                    doc.add_u64(
+                        tantivy_field,
+                        self.reading_ease()
+                            .map(|ease| ease.round() as u64)
+                            .unwrap_or(u64::MAX),
+                    );
+                }
+                Field::Fast(FastField::NumCleanBodyWords) => {
+                    doc.add_u64(tantivy_field, self.num_words() as u64);
+                }
+                Field::Fast(FastField::GeneratedLikelihood) => {
+                    doc.add_u64(
+                        tantivy_field,
+                        self.generated_likelihood()
+                            .map(|likelihood| (likelihood * FLOAT_SCALING as f64) as u64)
+                            .unwrap_or(u64::MAX),
+                    );
+                }
                 Field::Text(TextField::BacklinkText)
                 | Field::Text(TextField::SafetyClassification)
+                | Field::Text(TextField::Topics)
                 | Field::Text(TextField::InsertionTimestamp)
                 | Field::Fast(FastField::HostCentrality)
                 | Field::Fast(FastField::HostCentralityRank)
@@ -529,7 +664,8 @@ impl Html {
                 | Field::Fast(FastField::PreComputedScore)
                 | Field::Fast(FastField::Region)
                 | Field::Fast(FastField::HostNodeID)
-                | Field::Text(TextField::DmozDescription) => {}
+                | Field::Text(TextField::DmozDescription)
+                | Field::Text(TextField::ExpansionTerms) => {}
             }
         }
 
@@ -150,6 +150,7 @@ impl Html {
                                             source: self.url().clone(),
                                             destination: dest,
                                             text: text.trim().to_string(),
+                                            rel: attributes.borrow().get("rel").map(String::from),
                                         });
                                     }
                                 }
@@ -189,6 +190,7 @@ impl Html {
                         source: self.url().clone(),
                         destination: dest,
                         text: text.trim().to_string(),
+                        rel: attributes.borrow().get("rel").map(String::from),
                     });
                 }
             }
@@ -208,6 +210,7 @@ impl Html {
                             source: self.url().clone(),
                             destination: href,
                             text: String::new(),
+                            rel: element.attributes.borrow().get("rel").map(String::from),
                         });
                     }
                 }
@@ -28,19 +28,61 @@ use super::{adservers::AD_SERVERS, schema_org, Meta, Script};
 
 use super::url_ext::UrlExt;
 
+mod author;
+mod generated_content;
+mod geo;
 mod into_tantivy;
 mod links;
 mod microformats;
 mod parse_text;
+mod preview_image;
+mod product;
+mod readability;
 mod robots_meta;
+mod scholarly;
+mod video;
+
+pub use self::generated_content::LIKELY_GENERATED_THRESHOLD;
+pub use self::geo::{GeoCoordinates, Place};
+pub use self::preview_image::PreviewImage;
+pub use self::product::{Availability, Product};
+pub use self::readability::{ReadingLevel, WORDS_PER_MINUTE};
+pub use self::scholarly::{normalize_doi, ScholarlyMetadata};
+pub use self::video::VideoMetadata;
 
 pub static URL_REGEX: once_cell::sync::Lazy<Regex> = once_cell::sync::Lazy::new(|| {
     Regex::new(r"(((http|ftp|https):/{2})+(([0-9a-z_-]+\.)+(aero|asia|biz|cat|com|coop|edu|gov|info|int|jobs|mil|mobi|museum|name|net|org|pro|tel|travel|ac|ad|ae|af|ag|ai|al|am|an|ao|aq|ar|as|at|au|aw|ax|az|ba|bb|bd|be|bf|bg|bh|bi|bj|bm|bn|bo|br|bs|bt|bv|bw|by|bz|ca|cc|cd|cf|cg|ch|ci|ck|cl|cm|cn|co|cr|cu|cv|cx|cy|cz|cz|de|dj|dk|dm|do|dz|ec|ee|eg|er|es|et|eu|fi|fj|fk|fm|fo|fr|ga|gb|gd|ge|gf|gg|gh|gi|gl|gm|gn|gp|gq|gr|gs|gt|gu|gw|gy|hk|hm|hn|hr|ht|hu|id|ie|il|im|in|io|iq|ir|is|it|je|jm|jo|jp|ke|kg|kh|ki|km|kn|kp|kr|kw|ky|kz|la|lb|lc|li|lk|lr|ls|lt|lu|lv|ly|ma|mc|md|me|mg|mh|mk|ml|mn|mn|mo|mp|mr|ms|mt|mu|mv|mw|mx|my|mz|na|nc|ne|nf|ng|ni|nl|no|np|nr|nu|nz|nom|pa|pe|pf|pg|ph|pk|pl|pm|pn|pr|ps|pt|pw|py|qa|re|ra|rs|ru|rw|sa|sb|sc|sd|se|sg|sh|si|sj|sj|sk|sl|sm|sn|so|sr|st|su|sv|sy|sz|tc|td|tf|tg|th|tj|tk|tl|tm|tn|to|tp|tr|tt|tv|tw|tz|ua|ug|uk|us|uy|uz|va|vc|ve|vg|vi|vn|vu|wf|ws|ye|yt|yu|za|zm|zw|arpa)(:[0-9]+)?((/([~0-9a-zA-Z\#\+%@\./_-]+))?(\?[0-9a-zA-Z\+%@/&\[\];=_-]+)?)?))\b").unwrap()
 });
 
+/// Remove the fragment and the tracking parameters from the url, like the urls of the
+/// pages are before they are indexed.
+pub fn normalize_url(url: &mut Url) {
+    url.set_fragment(None);
+
+    let queries: Vec<_> = url
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_"))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+    {
+        let mut query_mut = url.query_pairs_mut();
+        query_mut.clear();
+        if !queries.is_empty() {
+            query_mut.extend_pairs(queries);
+        }
+    }
+
+    if url.query().unwrap_or_default().is_empty() {
+        url.set_query(None);
+    }
+}
+
 #[derive(Debug)]
 pub struct Html {
     url: Url,
+    /// The normalized url the page was fetched from, before it was replaced by the canonical url.
+    fetched_url: Url,
     root: NodeRef, // this is reference counted (cheap to clone)
     all_text: Option<String>,
     clean_text: Option<String>,
@@ -66,32 +108,18 @@ impl Html {
         let root = kuchiki::parse_html().one(html);
 
         let mut url = Url::parse(url)?;
-        url.set_fragment(None); // remove fragment (e.g. #comments
+        normalize_url(&mut url);
 
         let mut res = Self {
             root,
             all_text: None,
             clean_text: None,
             lang: None,
+            fetched_url: url.clone(),
             url,
             robots: None,
         };
 
-        let queries: Vec<_> = res
-            .url
-            .query_pairs()
-            .filter(|(key, _)| !key.starts_with("utm_"))
-            .map(|(key, value)| (key.to_string(), value.to_string()))
-            .collect();
-
-        {
-            let mut query_mut = res.url.query_pairs_mut();
-            query_mut.clear();
-            if !queries.is_empty() {
-                query_mut.extend_pairs(queries);
-            }
-        }
-
         if let Some(canonical) = res.canonical_url() {
             if canonical.root_domain() == res.url.root_domain() {
                 res.url = canonical;
@@ -168,6 +196,11 @@ impl Html {
         &self.url
     }
 
+    /// The url the page was fetched from, if the page is indexed under its canonical url instead.
+    pub fn canonicalized_from(&self) -> Option<&Url> {
+        (self.fetched_url != self.url).then_some(&self.fetched_url)
+    }
+
     pub fn metadata(&self) -> Vec<Meta> {
         let mut metas = Vec::new();
 
@@ -346,6 +379,23 @@ impl Html {
             })
     }
 
+    fn article_published_time(&self) -> Option<DateTime<FixedOffset>> {
+        self.metadata()
+            .into_iter()
+            .find(|metadata| {
+                if let Some(property) = metadata.get("property") {
+                    property == &String::from("article:published_time")
+                } else {
+                    false
+                }
+            })
+            .and_then(|metadata| {
+                metadata
+                    .get("content")
+                    .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            })
+    }
+
     fn og_updated_time(&self) -> Option<DateTime<FixedOffset>> {
         self.metadata()
             .into_iter()
@@ -398,6 +448,12 @@ impl Html {
         }
     }
 
+    /// When the article was first published. Times in the future are ignored.
+    pub fn published_time(&self) -> Option<DateTime<FixedOffset>> {
+        self.article_published_time()
+            .filter(|time| *time <= Utc::now())
+    }
+
     pub fn og_description(&self) -> Option<String> {
         self.metadata()
             .into_iter()
@@ -528,7 +584,8 @@ mod tests {
             vec![Link {
                 source: Url::parse("https://www.example.com/whatever").unwrap(),
                 destination: Url::parse("https://example.com").unwrap(),
-                text: "Link to example".to_string()
+                text: "Link to example".to_string(),
+                rel: None,
             }]
         );
         assert_eq!(webpage.clean_text(), Some(&CONTENT.to_string()));
@@ -866,6 +923,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn published_time() {
+        let html = r#"
+    <html>
+        <head>
+            <meta property="article:published_time" content="2022-06-20T08:00:00+00:00" />
+        </head>
+        <body>
+        </body>
+    </html>
+        "#;
+        let html = Html::parse(html, "http://example.com").unwrap();
+
+        assert_eq!(
+            html.published_time(),
+            Some(DateTime::parse_from_rfc3339("2022-06-20T08:00:00+00:00").unwrap())
+        );
+
+        let html = r#"
+    <html>
+        <head>
+            <meta property="article:published_time" content="2999-01-01T00:00:00+00:00" />
+        </head>
+        <body>
+        </body>
+    </html>
+        "#;
+        let html = Html::parse(html, "http://example.com").unwrap();
+
+        assert_eq!(html.published_time(), None);
+    }
+
     #[test]
     fn trackers() {
         let html = r#"
@@ -971,7 +1060,8 @@ mod tests {
             vec![Link {
                 source: Url::parse("https://www.example.com/whatever").unwrap(),
                 destination: Url::parse("https://example.com").unwrap(),
-                text: "Link to example".to_string()
+                text: "Link to example".to_string(),
+                rel: None,
             },]
         );
     }
@@ -1010,6 +1100,10 @@ mod tests {
             html.url(),
             &Url::parse("https://example.com/canonical.html").unwrap()
         );
+        assert_eq!(
+            html.canonicalized_from(),
+            Some(&Url::parse("https://www.example.com/whatever").unwrap())
+        );
 
         let html = Html::parse(
             r#"
@@ -1029,6 +1123,7 @@ mod tests {
             html.url(),
             &Url::parse("https://www.example.com/whatever").unwrap()
         );
+        assert_eq!(html.canonicalized_from(), None);
 
         let html = Html::parse(
             r#"
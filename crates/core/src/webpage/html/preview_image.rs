@@ -0,0 +1,214 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+use utoipa::ToSchema;
+
+use super::Html;
+
+/// Content images smaller than this (in either dimension) are most likely icons,
+/// avatars or tracking pixels.
+const MIN_IMAGE_SIDE: u32 = 100;
+/// The widest allowed aspect ratio (and its inverse the tallest) of a content image.
+/// Banners and spacers fall outside this range.
+const MAX_ASPECT_RATIO: f64 = 3.0;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewImage {
+    pub url: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl PreviewImage {
+    fn is_sane(&self) -> bool {
+        match (self.width, self.height) {
+            (Some(width), Some(height)) => {
+                if width < MIN_IMAGE_SIDE || height < MIN_IMAGE_SIDE {
+                    return false;
+                }
+
+                let ratio = width as f64 / height as f64;
+                (1.0 / MAX_ASPECT_RATIO..=MAX_ASPECT_RATIO).contains(&ratio)
+            }
+            _ => true,
+        }
+    }
+
+    fn area(&self) -> u64 {
+        self.width.unwrap_or_default() as u64 * self.height.unwrap_or_default() as u64
+    }
+}
+
+fn parse_dimension(value: &str) -> Option<u32> {
+    value
+        .trim()
+        .trim_end_matches("px")
+        .parse()
+        .ok()
+        .filter(|d| *d > 0)
+}
+
+fn resolve(base: &Url, url: &str) -> Option<String> {
+    let url = url.trim();
+
+    if url.is_empty() || url.starts_with("data:") {
+        return None;
+    }
+
+    let url = base.join(url).ok()?;
+
+    if url.scheme() == "http" || url.scheme() == "https" {
+        Some(url.to_string())
+    } else {
+        None
+    }
+}
+
+impl Html {
+    fn og_image(&self) -> Option<PreviewImage> {
+        let mut url = None;
+        let mut width = None;
+        let mut height = None;
+
+        for meta in self.metadata() {
+            let (Some(property), Some(content)) = (
+                meta.get("property").or_else(|| meta.get("name")),
+                meta.get("content"),
+            ) else {
+                continue;
+            };
+
+            match property.as_str() {
+                "og:image" | "og:image:url" | "og:image:secure_url" | "twitter:image" => {
+                    if url.is_none() {
+                        url = resolve(self.url(), content);
+                    }
+                }
+                "og:image:width" => width = width.or_else(|| parse_dimension(content)),
+                "og:image:height" => height = height.or_else(|| parse_dimension(content)),
+                _ => {}
+            }
+        }
+
+        Some(PreviewImage {
+            url: url?,
+            width,
+            height,
+        })
+        .filter(|image| image.is_sane())
+    }
+
+    /// The largest image in the body with explicit dimensions and a sane aspect ratio.
+    fn largest_content_image(&self) -> Option<PreviewImage> {
+        self.root
+            .select("body img")
+            .unwrap()
+            .filter_map(|node| {
+                let attributes = node.attributes.borrow();
+
+                let image = PreviewImage {
+                    url: resolve(self.url(), attributes.get("src")?)?,
+                    width: Some(parse_dimension(attributes.get("width")?)?),
+                    height: Some(parse_dimension(attributes.get("height")?)?),
+                };
+
+                Some(image)
+            })
+            .filter(|image| image.is_sane())
+            .max_by_key(|image| image.area())
+    }
+
+    /// Select a representative image for the page that can be shown next to the result.
+    /// The open graph image is preferred as it has been chosen by the site for exactly
+    /// this purpose.
+    pub fn preview_image(&self) -> Option<PreviewImage> {
+        self.og_image().or_else(|| self.largest_content_image())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_og_image() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <head>
+                    <meta property="og:image" content="/images/cover.jpg" />
+                    <meta property="og:image:width" content="1200" />
+                    <meta property="og:image:height" content="630" />
+                </head>
+                <body>
+                    <img src="large.jpg" width="2000" height="1500" />
+                </body>
+            </html>
+            "#,
+            "https://www.example.com/article/",
+        )
+        .unwrap();
+
+        assert_eq!(
+            html.preview_image(),
+            Some(PreviewImage {
+                url: "https://www.example.com/images/cover.jpg".to_string(),
+                width: Some(1200),
+                height: Some(630),
+            })
+        );
+    }
+
+    #[test]
+    fn largest_sane_content_image() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <body>
+                    <img src="pixel.gif" width="1" height="1" />
+                    <img src="banner.jpg" width="2000" height="100" />
+                    <img src="data:image/png;base64,AAAA" width="800" height="800" />
+                    <img src="small.jpg" width="300" height="200" />
+                    <img src="photo.jpg" width="640px" height="480px" />
+                    <img src="unknown.jpg" />
+                </body>
+            </html>
+            "#,
+            "https://www.example.com/article/",
+        )
+        .unwrap();
+
+        assert_eq!(
+            html.preview_image(),
+            Some(PreviewImage {
+                url: "https://www.example.com/article/photo.jpg".to_string(),
+                width: Some(640),
+                height: Some(480),
+            })
+        );
+
+        let html = Html::parse(
+            r#"<html><body><img src="icon.png" width="32" height="32" /></body></html>"#,
+            "https://www.example.com/",
+        )
+        .unwrap();
+
+        assert_eq!(html.preview_image(), None);
+    }
+}
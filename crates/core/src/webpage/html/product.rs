@@ -0,0 +1,185 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{schema_org, Html};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum Availability {
+    InStock,
+    OutOfStock,
+    PreOrder,
+    BackOrder,
+    LimitedAvailability,
+    Discontinued,
+}
+
+impl Availability {
+    /// Parse the schema.org item availability, e.g. `https://schema.org/InStock`.
+    fn parse(availability: &str) -> Option<Self> {
+        let name = availability.trim().rsplit('/').next()?;
+
+        match name {
+            "InStock" | "InStoreOnly" | "OnlineOnly" => Some(Availability::InStock),
+            "OutOfStock" | "SoldOut" => Some(Availability::OutOfStock),
+            "PreOrder" | "PreSale" => Some(Availability::PreOrder),
+            "BackOrder" => Some(Availability::BackOrder),
+            "LimitedAvailability" => Some(Availability::LimitedAvailability),
+            "Discontinued" => Some(Availability::Discontinued),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Product {
+    pub name: String,
+    pub price: f64,
+    /// ISO 4217 currency code.
+    pub currency: Option<String>,
+    pub availability: Option<Availability>,
+}
+
+fn first_string(item: &schema_org::Item, name: &str) -> Option<String> {
+    item.properties
+        .get(name)?
+        .clone()
+        .many()
+        .into_iter()
+        .find_map(|prop| prop.try_into_string())
+}
+
+/// Prices in schema.org use a period as the decimal separator,
+/// but some sites still include thousands separators or a currency symbol.
+fn parse_price(price: &str) -> Option<f64> {
+    let price: String = price
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+
+    price.parse().ok().filter(|price: &f64| price.is_finite())
+}
+
+fn offer(offers: &schema_org::Item) -> Option<(f64, Option<String>, Option<Availability>)> {
+    let price = first_string(offers, "price")
+        .or_else(|| first_string(offers, "lowPrice"))
+        .and_then(|price| parse_price(&price))?;
+
+    let currency = first_string(offers, "priceCurrency").map(|c| c.trim().to_uppercase());
+    let availability = first_string(offers, "availability")
+        .and_then(|availability| Availability::parse(&availability));
+
+    Some((price, currency, availability))
+}
+
+impl Html {
+    /// The product sold on the page from the schema.org `Product` with a priced offer.
+    pub fn product(&self) -> Option<Product> {
+        self.schema_org()
+            .into_iter()
+            .filter(|item| item.types_contains("Product"))
+            .find_map(|item| {
+                let name = first_string(&item, "name")?.trim().to_string();
+
+                let (price, currency, availability) = item
+                    .properties
+                    .get("offers")?
+                    .clone()
+                    .many()
+                    .into_iter()
+                    .filter_map(|offers| offers.try_into_item())
+                    .find_map(|offers| offer(&offers))?;
+
+                Some(Product {
+                    name,
+                    price,
+                    currency,
+                    availability,
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_ld_product() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <head>
+                    <script type="application/ld+json">
+                    {
+                        "@context": "https://schema.org",
+                        "@type": "Product",
+                        "name": "Espresso machine",
+                        "offers": {
+                            "@type": "Offer",
+                            "price": "1,299.00",
+                            "priceCurrency": "eur",
+                            "availability": "https://schema.org/InStock"
+                        }
+                    }
+                    </script>
+                </head>
+                <body></body>
+            </html>
+            "#,
+            "https://shop.example.com/espresso",
+        )
+        .unwrap();
+
+        assert_eq!(
+            html.product(),
+            Some(Product {
+                name: "Espresso machine".to_string(),
+                price: 1299.0,
+                currency: Some("EUR".to_string()),
+                availability: Some(Availability::InStock),
+            })
+        );
+    }
+
+    #[test]
+    fn product_without_price() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <head>
+                    <script type="application/ld+json">
+                    {
+                        "@context": "https://schema.org",
+                        "@type": "Product",
+                        "name": "Espresso machine"
+                    }
+                    </script>
+                </head>
+                <body></body>
+            </html>
+            "#,
+            "https://shop.example.com/espresso",
+        )
+        .unwrap();
+
+        assert_eq!(html.product(), None);
+    }
+}
@@ -0,0 +1,168 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::Html;
+
+/// The reading ease is not estimated for pages with fewer words than this,
+/// as the estimate is too noisy for short texts.
+const MIN_WORDS_FOR_READING_EASE: usize = 100;
+
+/// Average reading speed used to estimate the reading time.
+pub const WORDS_PER_MINUTE: u64 = 230;
+
+/// How hard the text of a page is to read, from the Flesch reading ease.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ReadingLevel {
+    VeryEasy,
+    Easy,
+    Standard,
+    Difficult,
+    VeryDifficult,
+}
+
+impl ReadingLevel {
+    pub fn from_reading_ease(reading_ease: f64) -> Self {
+        if reading_ease >= 80.0 {
+            ReadingLevel::VeryEasy
+        } else if reading_ease >= 65.0 {
+            ReadingLevel::Easy
+        } else if reading_ease >= 50.0 {
+            ReadingLevel::Standard
+        } else if reading_ease >= 30.0 {
+            ReadingLevel::Difficult
+        } else {
+            ReadingLevel::VeryDifficult
+        }
+    }
+}
+
+/// Rough syllable count from the groups of vowels in the word.
+fn num_syllables(word: &str) -> usize {
+    let word = word.to_lowercase();
+    let mut syllables = 0;
+    let mut prev_vowel = false;
+
+    for c in word.chars() {
+        let is_vowel = matches!(c, 'a' | 'e' | 'i' | 'o' | 'u' | 'y');
+
+        if is_vowel && !prev_vowel {
+            syllables += 1;
+        }
+
+        prev_vowel = is_vowel;
+    }
+
+    // a trailing 'e' is usually silent, as in "make".
+    if word.ends_with('e') && !word.ends_with("le") && syllables > 1 {
+        syllables -= 1;
+    }
+
+    syllables.max(1)
+}
+
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+}
+
+pub fn num_words(text: &str) -> usize {
+    words(text).count()
+}
+
+/// The Flesch reading ease of the text from 0 (very difficult) to 100 (very easy).
+pub fn flesch_reading_ease(text: &str) -> Option<f64> {
+    let mut num_words = 0;
+    let mut num_syllables_total = 0;
+
+    for word in words(text).filter(|word| word.chars().any(|c| c.is_alphabetic())) {
+        num_words += 1;
+        num_syllables_total += num_syllables(word);
+    }
+
+    if num_words < MIN_WORDS_FOR_READING_EASE {
+        return None;
+    }
+
+    let num_sentences = text
+        .split(['.', '!', '?', '\n'])
+        .filter(|sentence| sentence.chars().any(|c| c.is_alphabetic()))
+        .count()
+        .max(1);
+
+    let words_per_sentence = num_words as f64 / num_sentences as f64;
+    let syllables_per_word = num_syllables_total as f64 / num_words as f64;
+
+    Some((206.835 - 1.015 * words_per_sentence - 84.6 * syllables_per_word).clamp(0.0, 100.0))
+}
+
+impl Html {
+    pub fn reading_ease(&self) -> Option<f64> {
+        self.clean_text()
+            .map(String::as_str)
+            .and_then(flesch_reading_ease)
+    }
+
+    pub fn num_words(&self) -> usize {
+        self.clean_text()
+            .map(String::as_str)
+            .map(num_words)
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syllables() {
+        assert_eq!(num_syllables("cat"), 1);
+        assert_eq!(num_syllables("make"), 1);
+        assert_eq!(num_syllables("table"), 2);
+        assert_eq!(num_syllables("reading"), 2);
+        assert_eq!(num_syllables("accessibility"), 6);
+    }
+
+    #[test]
+    fn simple_text_is_easier() {
+        let simple = "The cat sat on the mat. It was a good cat. ".repeat(20);
+        let complex = "Contemporary epistemological considerations necessitate comprehensive methodological reevaluation, particularly regarding institutional accountability mechanisms. ".repeat(20);
+
+        let simple = flesch_reading_ease(&simple).unwrap();
+        let complex = flesch_reading_ease(&complex).unwrap();
+
+        assert!(simple > complex);
+        assert_eq!(
+            ReadingLevel::from_reading_ease(simple),
+            ReadingLevel::VeryEasy
+        );
+        assert_eq!(
+            ReadingLevel::from_reading_ease(complex),
+            ReadingLevel::VeryDifficult
+        );
+    }
+
+    #[test]
+    fn short_text() {
+        assert_eq!(flesch_reading_ease("Too short to say."), None);
+        assert_eq!(num_words("Too short to say - really."), 5);
+    }
+}
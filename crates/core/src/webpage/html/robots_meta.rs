@@ -24,6 +24,8 @@ use super::Html;
 pub enum RobotsMeta {
     NoIndex,
     NoFollow,
+    NoArchive,
+    NoSnippet,
 }
 
 impl FromStr for RobotsMeta {
@@ -33,6 +35,8 @@ impl FromStr for RobotsMeta {
         match s {
             "noindex" => Ok(RobotsMeta::NoIndex),
             "nofollow" => Ok(RobotsMeta::NoFollow),
+            "noarchive" => Ok(RobotsMeta::NoArchive),
+            "nosnippet" => Ok(RobotsMeta::NoSnippet),
             _ => Err(Error::UnknownRobotsMetaTag.into()),
         }
     }
@@ -43,6 +47,8 @@ impl From<RobotsMeta> for usize {
         match val {
             RobotsMeta::NoIndex => 0,
             RobotsMeta::NoFollow => 1,
+            RobotsMeta::NoArchive => 2,
+            RobotsMeta::NoSnippet => 3,
         }
     }
 }
@@ -88,6 +94,20 @@ impl Html {
             .map(|robots| robots.contains(RobotsMeta::NoFollow))
             .unwrap_or(false)
     }
+
+    pub fn is_no_archive(&self) -> bool {
+        self.robots
+            .as_ref()
+            .map(|robots| robots.contains(RobotsMeta::NoArchive))
+            .unwrap_or(false)
+    }
+
+    pub fn is_no_snippet(&self) -> bool {
+        self.robots
+            .as_ref()
+            .map(|robots| robots.contains(RobotsMeta::NoSnippet))
+            .unwrap_or(false)
+    }
 }
 
 #[cfg(test)]
@@ -180,4 +200,26 @@ mod tests {
         assert!(!html.is_no_index());
         assert!(!html.is_no_follow());
     }
+
+    #[test]
+    fn publisher_preferences() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <head>
+                    <meta name="robots" content="noarchive, nosnippet, max-image-preview:large" />
+                </head>
+                <body>
+                </body>
+            </html>
+        "#,
+            "https://www.example.com/whatever",
+        )
+        .unwrap();
+
+        assert!(html.is_no_archive());
+        assert!(html.is_no_snippet());
+        assert!(!html.is_no_index());
+        assert!(!html.is_no_follow());
+    }
 }
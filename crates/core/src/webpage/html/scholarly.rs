@@ -0,0 +1,200 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::Html;
+
+/// Meta tags that contain the DOI of the page. The citation tags are the
+/// ones used by Google Scholar, the rest are Dublin Core and PRISM.
+const DOI_META: [&str; 5] = [
+    "citation_doi",
+    "bepress_citation_doi",
+    "prism.doi",
+    "dc.identifier",
+    "dc.identifier.doi",
+];
+
+const VENUE_META: [&str; 5] = [
+    "citation_journal_title",
+    "citation_conference_title",
+    "citation_inbook_title",
+    "citation_dissertation_institution",
+    "citation_publisher",
+];
+
+const DATE_META: [&str; 4] = [
+    "citation_publication_date",
+    "citation_date",
+    "citation_online_date",
+    "citation_year",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScholarlyMetadata {
+    /// The DOI in lowercase without any resolver prefix, e.g. `10.1000/xyz123`.
+    pub doi: Option<String>,
+    pub authors: Vec<String>,
+    pub venue: Option<String>,
+    pub year: Option<u32>,
+}
+
+/// Extract the DOI from a string such as `doi:10.1000/XYZ` or `https://doi.org/10.1000/xyz`.
+/// DOIs are case insensitive, so they are normalized to lowercase.
+pub fn normalize_doi(doi: &str) -> Option<String> {
+    let doi = doi.trim();
+    let start = doi.find("10.")?;
+    let doi = &doi[start..];
+
+    let (prefix, suffix) = doi.split_once('/')?;
+
+    if prefix.len() < 4
+        || !prefix[3..].chars().all(|c| c.is_ascii_digit() || c == '.')
+        || suffix.is_empty()
+    {
+        return None;
+    }
+
+    let doi = doi
+        .split(|c: char| c.is_whitespace())
+        .next()?
+        .trim_end_matches(['.', ',', ';']);
+
+    Some(doi.to_lowercase())
+}
+
+fn parse_year(date: &str) -> Option<u32> {
+    let date = date.trim();
+
+    date.get(..4)
+        .and_then(|year| year.parse().ok())
+        .filter(|year| (1000..=9999).contains(year))
+}
+
+impl Html {
+    /// Metadata of scholarly articles, found in the `citation_*` meta tags that are
+    /// used by most publishers and preprint servers, or in meta tags with a DOI.
+    pub fn scholarly(&self) -> Option<ScholarlyMetadata> {
+        let mut is_scholarly = false;
+        let mut metadata = ScholarlyMetadata::default();
+
+        for meta in self.metadata() {
+            let (Some(name), Some(content)) = (meta.get("name"), meta.get("content")) else {
+                continue;
+            };
+
+            let name = name.to_lowercase();
+            let content = content.trim();
+
+            if content.is_empty() {
+                continue;
+            }
+
+            if name == "citation_title" {
+                is_scholarly = true;
+            } else if name == "citation_author" {
+                metadata.authors.push(content.to_string());
+            } else if DOI_META.contains(&name.as_str()) {
+                if metadata.doi.is_none() {
+                    metadata.doi = normalize_doi(content);
+                }
+            } else if VENUE_META.contains(&name.as_str()) {
+                if metadata.venue.is_none() {
+                    metadata.venue = Some(content.to_string());
+                }
+            } else if DATE_META.contains(&name.as_str()) && metadata.year.is_none() {
+                metadata.year = parse_year(content);
+            }
+        }
+
+        if is_scholarly || metadata.doi.is_some() {
+            Some(metadata)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doi_normalization() {
+        assert_eq!(
+            normalize_doi("https://doi.org/10.1000/XYZ123"),
+            Some("10.1000/xyz123".to_string())
+        );
+        assert_eq!(
+            normalize_doi("doi:10.48550/arXiv.2301.00001."),
+            Some("10.48550/arxiv.2301.00001".to_string())
+        );
+        assert_eq!(normalize_doi("10.1000"), None);
+        assert_eq!(normalize_doi("10.ab/xyz"), None);
+        assert_eq!(normalize_doi("not a doi"), None);
+    }
+
+    #[test]
+    fn citation_meta() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <head>
+                    <meta name="citation_title" content="Attention is all you need" />
+                    <meta name="citation_author" content="Vaswani, Ashish" />
+                    <meta name="citation_author" content="Shazeer, Noam" />
+                    <meta name="citation_conference_title" content="NeurIPS" />
+                    <meta name="citation_publication_date" content="2017/12/04" />
+                    <meta name="citation_doi" content="10.5555/3295222.3295349" />
+                </head>
+                <body></body>
+            </html>
+            "#,
+            "https://papers.example.com/attention",
+        )
+        .unwrap();
+
+        assert_eq!(
+            html.scholarly(),
+            Some(ScholarlyMetadata {
+                doi: Some("10.5555/3295222.3295349".to_string()),
+                authors: vec!["Vaswani, Ashish".to_string(), "Shazeer, Noam".to_string()],
+                venue: Some("NeurIPS".to_string()),
+                year: Some(2017),
+            })
+        );
+    }
+
+    #[test]
+    fn not_scholarly() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <head>
+                    <meta name="description" content="A blog post about papers" />
+                </head>
+                <body></body>
+            </html>
+            "#,
+            "https://blog.example.com/",
+        )
+        .unwrap();
+
+        assert_eq!(html.scholarly(), None);
+    }
+}
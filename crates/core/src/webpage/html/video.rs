@@ -0,0 +1,266 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use chrono::{DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{schema_org, Html};
+
+/// Prefixes of the player urls for the video hosts we recognize when
+/// they are embedded in an iframe.
+const KNOWN_EMBEDS: [&str; 6] = [
+    "https://www.youtube.com/embed/",
+    "https://www.youtube-nocookie.com/embed/",
+    "https://player.vimeo.com/video/",
+    "https://www.dailymotion.com/embed/video/",
+    "https://player.twitch.tv/",
+    "https://rumble.com/embed/",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoMetadata {
+    pub duration_seconds: Option<u64>,
+    /// The upload date formatted as `YYYY-MM-DD`.
+    pub upload_date: Option<String>,
+    pub thumbnail: Option<String>,
+}
+
+/// Parse an ISO 8601 duration like `PT1H2M3S`. Durations with date
+/// components are rejected as they are never used for videos.
+fn parse_duration(duration: &str) -> Option<u64> {
+    let duration = duration.trim().strip_prefix("PT")?;
+
+    let mut total = 0.0;
+    let mut num = String::new();
+
+    for c in duration.chars() {
+        match c {
+            '0'..='9' | '.' => num.push(c),
+            'H' | 'M' | 'S' => {
+                let value: f64 = num.parse().ok()?;
+                num.clear();
+
+                total += match c {
+                    'H' => value * 3600.0,
+                    'M' => value * 60.0,
+                    _ => value,
+                };
+            }
+            _ => return None,
+        }
+    }
+
+    if !num.is_empty() {
+        return None;
+    }
+
+    Some(total.round() as u64)
+}
+
+fn parse_date(date: &str) -> Option<String> {
+    let date = date.trim();
+
+    DateTime::parse_from_rfc3339(date)
+        .ok()
+        .map(|date| date.date_naive())
+        .or_else(|| NaiveDate::parse_from_str(date.get(..10)?, "%Y-%m-%d").ok())
+        .map(|date| date.format("%Y-%m-%d").to_string())
+}
+
+fn string_property(item: &schema_org::Item, name: &str) -> Option<String> {
+    item.properties
+        .get(name)?
+        .clone()
+        .many()
+        .into_iter()
+        .find_map(|prop| match prop {
+            schema_org::Property::String(s) => Some(s),
+            schema_org::Property::Item(item) => item
+                .properties
+                .get("url")
+                .and_then(|url| url.clone().one())
+                .and_then(|url| url.try_into_string()),
+        })
+}
+
+impl Html {
+    fn video_object(&self) -> Option<VideoMetadata> {
+        let item = self
+            .schema_org()
+            .into_iter()
+            .find(|item| item.types_contains("VideoObject"))?;
+
+        Some(VideoMetadata {
+            duration_seconds: string_property(&item, "duration")
+                .and_then(|duration| parse_duration(&duration)),
+            upload_date: string_property(&item, "uploadDate").and_then(|date| parse_date(&date)),
+            thumbnail: string_property(&item, "thumbnailUrl")
+                .or_else(|| string_property(&item, "thumbnail"))
+                .and_then(|url| self.url().join(&url).ok())
+                .map(|url| url.to_string()),
+        })
+    }
+
+    fn og_video(&self) -> Option<VideoMetadata> {
+        let mut is_video = false;
+        let mut video = VideoMetadata::default();
+
+        for meta in self.metadata() {
+            let (Some(property), Some(content)) = (meta.get("property"), meta.get("content"))
+            else {
+                continue;
+            };
+
+            match property.as_str() {
+                "og:type" if content.starts_with("video") => is_video = true,
+                "og:video" | "og:video:url" | "og:video:secure_url" => is_video = true,
+                "video:duration" => video.duration_seconds = content.trim().parse().ok(),
+                "video:release_date" => video.upload_date = parse_date(content),
+                _ => {}
+            }
+        }
+
+        if is_video {
+            Some(video)
+        } else {
+            None
+        }
+    }
+
+    fn has_known_embed(&self) -> bool {
+        self.root
+            .select("body iframe")
+            .unwrap()
+            .filter_map(|node| node.attributes.borrow().get("src").map(|s| s.to_string()))
+            .filter_map(|src| self.url().join(&src).ok())
+            .any(|src| {
+                KNOWN_EMBEDS
+                    .iter()
+                    .any(|prefix| src.as_str().starts_with(prefix))
+            })
+    }
+
+    /// Metadata of the video if the page is a video watch page. Pages are detected
+    /// from a schema.org `VideoObject`, the open graph video properties or an embedded
+    /// player from a known video host.
+    pub fn video(&self) -> Option<VideoMetadata> {
+        let mut video = self
+            .video_object()
+            .or_else(|| self.og_video())
+            .or_else(|| {
+                if self.has_known_embed() {
+                    Some(VideoMetadata::default())
+                } else {
+                    None
+                }
+            })?;
+
+        if video.thumbnail.is_none() {
+            video.thumbnail = self.preview_image().map(|image| image.url);
+        }
+
+        Some(video)
+    }
+
+    pub fn is_video(&self) -> bool {
+        self.video().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iso_duration() {
+        assert_eq!(parse_duration("PT1H2M3S"), Some(3723));
+        assert_eq!(parse_duration("PT4M"), Some(240));
+        assert_eq!(parse_duration("PT12.6S"), Some(13));
+        assert_eq!(parse_duration("P1D"), None);
+        assert_eq!(parse_duration("PT12"), None);
+    }
+
+    #[test]
+    fn video_object() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <head>
+                    <script type="application/ld+json">
+                    {
+                        "@context": "https://schema.org",
+                        "@type": "VideoObject",
+                        "name": "How to bake bread",
+                        "duration": "PT12M30S",
+                        "uploadDate": "2023-05-17T08:00:00+02:00",
+                        "thumbnailUrl": "/thumbs/bread.jpg"
+                    }
+                    </script>
+                </head>
+                <body></body>
+            </html>
+            "#,
+            "https://videos.example.com/watch/bread",
+        )
+        .unwrap();
+
+        assert_eq!(
+            html.video(),
+            Some(VideoMetadata {
+                duration_seconds: Some(750),
+                upload_date: Some("2023-05-17".to_string()),
+                thumbnail: Some("https://videos.example.com/thumbs/bread.jpg".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn known_embed() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <head>
+                    <meta property="og:image" content="https://example.com/cover.jpg" />
+                </head>
+                <body>
+                    <iframe src="https://www.youtube.com/embed/dQw4w9WgXcQ"></iframe>
+                </body>
+            </html>
+            "#,
+            "https://example.com/post",
+        )
+        .unwrap();
+
+        assert_eq!(
+            html.video(),
+            Some(VideoMetadata {
+                duration_seconds: None,
+                upload_date: None,
+                thumbnail: Some("https://example.com/cover.jpg".to_string()),
+            })
+        );
+
+        let html = Html::parse(
+            r#"<html><body><iframe src="https://maps.example.com/embed"></iframe></body></html>"#,
+            "https://example.com/post",
+        )
+        .unwrap();
+
+        assert!(!html.is_video());
+    }
+}
@@ -30,13 +30,18 @@ use crate::schema::{Field, FLOAT_SCALING};
 use self::region::Region;
 
 mod adservers;
+pub mod document_expansion;
 mod html;
 mod just_text;
 pub mod region;
 pub mod safety_classifier;
 pub mod schema_org;
+pub mod topic_classifier;
 pub mod url_ext;
-pub use self::html::Html;
+pub use self::html::{
+    normalize_doi, normalize_url, Availability, GeoCoordinates, Html, Place, PreviewImage, Product,
+    ReadingLevel, ScholarlyMetadata, VideoMetadata, LIKELY_GENERATED_THRESHOLD, WORDS_PER_MINUTE,
+};
 
 #[derive(Debug)]
 pub struct Webpage {
@@ -51,6 +56,8 @@ pub struct Webpage {
     pub node_id: Option<NodeID>,
     pub dmoz_description: Option<String>,
     pub safety_classification: Option<safety_classifier::Label>,
+    pub topics: Option<topic_classifier::TopicVector>,
+    pub expansion_terms: Vec<(String, f32)>,
     pub inserted_at: DateTime<Utc>,
 }
 
@@ -69,6 +76,8 @@ impl Default for Webpage {
             node_id: Default::default(),
             dmoz_description: Default::default(),
             safety_classification: Default::default(),
+            topics: Default::default(),
+            expansion_terms: Default::default(),
             inserted_at: Utc::now(),
         }
     }
@@ -134,6 +143,13 @@ impl Webpage {
             backlink_text,
         );
 
+        doc.add_text(
+            schema
+                .get_field(Field::Text(TextField::ExpansionTerms).name())
+                .expect("Failed to get expansion_terms field"),
+            document_expansion::indexed_text(&self.expansion_terms),
+        );
+
         doc.add_date(
             schema
                 .get_field(Field::Text(TextField::InsertionTimestamp).name())
@@ -155,6 +171,19 @@ impl Webpage {
             safety,
         );
 
+        let topics_field = schema
+            .get_field(Field::Text(TextField::Topics).name())
+            .expect("Failed to get topics field");
+
+        for topic in self
+            .topics
+            .as_ref()
+            .map(|topics| topics.indexed_topics())
+            .unwrap_or_default()
+        {
+            doc.add_text(topics_field, topic.as_str());
+        }
+
         doc.add_u64(
             schema
                 .get_field(Field::Fast(FastField::HostCentrality).name())
@@ -237,6 +266,8 @@ pub struct Link {
     pub source: Url,
     pub destination: Url,
     pub text: String,
+    /// The `rel` attribute of the link.
+    pub rel: Option<String>,
 }
 
 pub type Meta = HashMap<String, String>;
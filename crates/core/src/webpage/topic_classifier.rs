@@ -0,0 +1,409 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Classifier that assigns a vector of weights over the top level
+//! categories of the DMOZ/Curlie taxonomy to a page.
+
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::path::Path;
+
+use itertools::Itertools;
+use utoipa::ToSchema;
+
+use crate::human_website_annotations;
+use crate::naive_bayes;
+use crate::Result;
+
+const MAX_NUM_WORDS: usize = 200;
+
+/// Topics with a lower weight are not added to the index.
+pub const MIN_INDEXED_WEIGHT: f32 = 0.25;
+pub const MAX_INDEXED_TOPICS: usize = 3;
+
+/// Queries are only assigned a topic if the classifier is at least this confident.
+pub const MIN_QUERY_CONFIDENCE: f32 = 0.5;
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    serde::Serialize,
+    serde::Deserialize,
+    ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    Arts,
+    Business,
+    Computers,
+    Games,
+    Health,
+    Home,
+    KidsAndTeens,
+    News,
+    Recreation,
+    Reference,
+    Science,
+    Shopping,
+    Society,
+    Sports,
+}
+
+pub const ALL_TOPICS: [Topic; 14] = [
+    Topic::Arts,
+    Topic::Business,
+    Topic::Computers,
+    Topic::Games,
+    Topic::Health,
+    Topic::Home,
+    Topic::KidsAndTeens,
+    Topic::News,
+    Topic::Recreation,
+    Topic::Reference,
+    Topic::Science,
+    Topic::Shopping,
+    Topic::Society,
+    Topic::Sports,
+];
+
+pub const NUM_TOPICS: usize = ALL_TOPICS.len();
+
+impl Topic {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Topic::Arts => "arts",
+            Topic::Business => "business",
+            Topic::Computers => "computers",
+            Topic::Games => "games",
+            Topic::Health => "health",
+            Topic::Home => "home",
+            Topic::KidsAndTeens => "kids_and_teens",
+            Topic::News => "news",
+            Topic::Recreation => "recreation",
+            Topic::Reference => "reference",
+            Topic::Science => "science",
+            Topic::Shopping => "shopping",
+            Topic::Society => "society",
+            Topic::Sports => "sports",
+        }
+    }
+}
+
+impl Display for Topic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl TryFrom<&str> for Topic {
+    type Error = String;
+
+    /// Parses both our own names (`kids_and_teens`) and the
+    /// DMOZ category names (`Kids_and_Teens`).
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let normalized = value.trim().to_lowercase().replace(['-', ' '], "_");
+
+        ALL_TOPICS
+            .into_iter()
+            .find(|topic| topic.as_str() == normalized)
+            .ok_or_else(|| format!("invalid topic: {}", value))
+    }
+}
+
+impl naive_bayes::Label for Topic {}
+
+/// Weight of each topic for a page or host. The weights sum to 1
+/// unless the vector is empty.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TopicVector {
+    weights: [f32; NUM_TOPICS],
+}
+
+impl Default for TopicVector {
+    fn default() -> Self {
+        Self {
+            weights: [0.0; NUM_TOPICS],
+        }
+    }
+}
+
+impl TopicVector {
+    pub fn get(&self, topic: Topic) -> f32 {
+        self.weights[topic as usize]
+    }
+
+    pub fn set(&mut self, topic: Topic, weight: f32) {
+        self.weights[topic as usize] = weight;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.iter().all(|w| *w == 0.0)
+    }
+
+    /// Topics sorted by their weight in descending order.
+    pub fn top(&self, n: usize) -> Vec<(Topic, f32)> {
+        ALL_TOPICS
+            .into_iter()
+            .map(|topic| (topic, self.get(topic)))
+            .filter(|(_, weight)| *weight > 0.0)
+            .sorted_by(|(_, a), (_, b)| b.total_cmp(a))
+            .take(n)
+            .collect()
+    }
+
+    /// The topics that should be searchable with the `topic:` operator.
+    pub fn indexed_topics(&self) -> Vec<Topic> {
+        self.top(MAX_INDEXED_TOPICS)
+            .into_iter()
+            .filter(|(_, weight)| *weight >= MIN_INDEXED_WEIGHT)
+            .map(|(topic, _)| topic)
+            .collect()
+    }
+
+    /// The average of the vectors, e.g. to get the topics of a host from its pages.
+    pub fn mean<'a>(vectors: impl IntoIterator<Item = &'a TopicVector>) -> TopicVector {
+        let mut res = TopicVector::default();
+        let mut count = 0;
+
+        for vector in vectors {
+            for (acc, w) in res.weights.iter_mut().zip(vector.weights.iter()) {
+                *acc += w;
+            }
+            count += 1;
+        }
+
+        if count > 0 {
+            for w in res.weights.iter_mut() {
+                *w /= count as f32;
+            }
+        }
+
+        res
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct Datapoint {
+    pub label: Topic,
+    pub text: String,
+}
+
+pub fn load_dataset<P: AsRef<Path>>(path: P) -> Result<Vec<Datapoint>> {
+    let mut datapoints = Vec::new();
+    let mut reader = csv::Reader::from_path(path)?;
+    for result in reader.deserialize() {
+        let datapoint: Datapoint = result?;
+        datapoints.push(datapoint);
+    }
+    Ok(datapoints)
+}
+
+/// Use the descriptions of the hosts in the DMOZ annotations as training data,
+/// labelled with their top level category.
+pub fn dataset_from_annotations(mapper: &human_website_annotations::Mapper) -> Vec<Datapoint> {
+    mapper
+        .iter()
+        .filter_map(|(_, info)| {
+            let label = Topic::try_from(info.topic.top_level()?).ok()?;
+
+            Some(Datapoint {
+                label,
+                text: info.description.clone(),
+            })
+        })
+        .collect()
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .take(MAX_NUM_WORDS)
+        .join(" ")
+        .to_lowercase()
+}
+
+pub fn page_text(page: &crate::webpage::Webpage) -> String {
+    page.html.title().unwrap_or_default()
+        + " "
+        + page.html.description().unwrap_or_default().as_str()
+        + " "
+        + page.html.clean_text().cloned().unwrap_or_default().as_str()
+}
+
+pub struct Evaluation {
+    pub accuracy: f64,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Model {
+    pipeline: naive_bayes::Pipeline<Topic>,
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Model {
+    pub fn new() -> Self {
+        let pipeline = naive_bayes::Pipeline::new();
+        Self { pipeline }
+    }
+
+    pub fn fit(&mut self, datapoints: &[Datapoint]) {
+        let datapoints: Vec<_> = datapoints
+            .iter()
+            .map(|datapoint| (normalize(&datapoint.text), datapoint.label))
+            .collect();
+        self.pipeline.fit(&datapoints);
+    }
+
+    pub fn predict_text(&self, text: &str) -> TopicVector {
+        let text = normalize(text);
+        let mut vector = TopicVector::default();
+
+        for prediction in self.pipeline.predict_all(&text) {
+            vector.set(prediction.label, prediction.confidence);
+        }
+
+        vector
+    }
+
+    pub fn predict(&self, page: &crate::webpage::Webpage) -> TopicVector {
+        self.predict_text(&page_text(page))
+    }
+
+    /// The most likely topic of a query, if the classifier is confident enough.
+    pub fn predict_query(&self, query: &str) -> Option<Topic> {
+        self.predict_text(query)
+            .top(1)
+            .into_iter()
+            .find(|(_, weight)| *weight >= MIN_QUERY_CONFIDENCE)
+            .map(|(topic, _)| topic)
+    }
+
+    pub fn evaluate(&self, datapoints: &[Datapoint]) -> Evaluation {
+        let correct = datapoints
+            .iter()
+            .filter(|datapoint| {
+                self.predict_text(&datapoint.text)
+                    .top(1)
+                    .first()
+                    .map(|(topic, _)| *topic == datapoint.label)
+                    .unwrap_or(false)
+            })
+            .count();
+
+        Evaluation {
+            accuracy: correct as f64 / datapoints.len() as f64,
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(self, path: P) -> Result<()> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        bincode::serialize_into(file, &self)?;
+
+        Ok(())
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new().read(true).open(path)?;
+
+        let model = bincode::deserialize_from(file)?;
+
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_topic() {
+        assert_eq!(Topic::try_from("Kids_and_Teens"), Ok(Topic::KidsAndTeens));
+        assert_eq!(Topic::try_from("sports"), Ok(Topic::Sports));
+        assert!(Topic::try_from("World").is_err());
+
+        for topic in ALL_TOPICS {
+            assert_eq!(Topic::try_from(topic.as_str()), Ok(topic));
+        }
+    }
+
+    #[test]
+    fn vector() {
+        let mut a = TopicVector::default();
+        assert!(a.is_empty());
+        a.set(Topic::Sports, 0.7);
+        a.set(Topic::News, 0.2);
+        a.set(Topic::Arts, 0.1);
+
+        assert_eq!(a.top(2), vec![(Topic::Sports, 0.7), (Topic::News, 0.2)]);
+        assert_eq!(a.indexed_topics(), vec![Topic::Sports]);
+
+        let mut b = TopicVector::default();
+        b.set(Topic::News, 1.0);
+
+        let mean = TopicVector::mean([&a, &b]);
+        assert!((mean.get(Topic::News) - 0.6).abs() < 1e-6);
+        assert!((mean.get(Topic::Sports) - 0.35).abs() < 1e-6);
+    }
+
+    #[test]
+    fn classify() {
+        let datapoints = vec![
+            Datapoint {
+                label: Topic::Sports,
+                text: "football match goal league team".to_string(),
+            },
+            Datapoint {
+                label: Topic::Sports,
+                text: "tennis match player tournament".to_string(),
+            },
+            Datapoint {
+                label: Topic::Computers,
+                text: "linux kernel compiler programming software".to_string(),
+            },
+            Datapoint {
+                label: Topic::Computers,
+                text: "rust programming language compiler".to_string(),
+            },
+        ];
+
+        let mut model = Model::new();
+        model.fit(&datapoints);
+
+        let vector = model.predict_text("the team won the football league");
+        assert_eq!(vector.top(1)[0].0, Topic::Sports);
+
+        let vector = model.predict_text("a compiler for the rust programming language");
+        assert_eq!(vector.top(1)[0].0, Topic::Computers);
+
+        let total: f32 = ALL_TOPICS.iter().map(|t| vector.get(*t)).sum();
+        assert!((total - 1.0).abs() < 1e-4);
+    }
+}
@@ -20,6 +20,7 @@ use utoipa::ToSchema;
 
 use self::thesaurus::ThesaurusWidget;
 use crate::config::WidgetsConfig;
+use crate::locale::Locale;
 
 use self::calculator::{Calculation, Calculator};
 use anyhow::{anyhow, Result};
@@ -62,13 +63,18 @@ impl Widgets {
         })
     }
 
-    pub fn widget(&self, query: &str) -> Option<Widget> {
-        let query = query.to_lowercase();
+    pub fn widget(&self, query: &str, locale: Locale) -> Option<Widget> {
+        let query = locale.canonical_widget_query(&query.to_lowercase());
 
         self.calculator
             .try_calculate(&query)
             .ok()
-            .map(Widget::Calculator)
+            .map(|calculation| {
+                Widget::Calculator(Calculation {
+                    input: locale.localize_numbers(&calculation.input),
+                    result: locale.localize_numbers(&calculation.result),
+                })
+            })
             .or_else(|| {
                 self.thesaurus
                     .as_ref()
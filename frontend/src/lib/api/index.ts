@@ -114,6 +114,23 @@ export const api = {
     requestPlain('POST', `/beta/api/explore/export`, body, options),
   hostsExport: (body: HostsExportOpticParams, options?: ApiOptions) =>
     requestPlain('POST', `/beta/api/hosts/export`, body, options),
+  hostsInfo: (
+    query: {
+      host: string;
+    },
+    options?: ApiOptions,
+  ) =>
+    requestJson<SiteInfo>(
+      'POST',
+      `/beta/api/hosts/info?${new URLSearchParams(query)}`,
+      options,
+    ),
+  screenshot: (
+    query: {
+      url: string;
+    },
+    options?: ApiOptions,
+  ) => requestPlain('GET', `/beta/api/screenshot?${new URLSearchParams(query)}`, options),
   search: (body: ApiSearchQuery, options?: ApiOptions) =>
     requestJson<ApiSearchResult>('POST', `/beta/api/search`, body, options),
   searchSidebar: (body: SidebarQuery, options?: ApiOptions) =>
@@ -129,6 +146,8 @@ export const api = {
     },
     options?: ApiOptions,
   ) => sse<string>('GET', `/beta/api/summarize?${new URLSearchParams(query)}`, options),
+  urlsStatus: (body: UrlStatusParams, options?: ApiOptions) =>
+    requestJson<UrlStatus[]>('POST', `/beta/api/urls/status`, body, options),
   webgraphHostIngoing: (
     query: {
       host: string;
@@ -196,9 +215,11 @@ export type ApiSearchQuery = {
   optic?: string;
   page?: number;
   query: string;
+  rankingVariant?: string;
   returnRankingSignals?: boolean;
   safeSearch?: boolean;
   selectedRegion?: Region;
+  site?: string;
 };
 export type ApiSearchResult =
   | (WebsitesResult & {
@@ -207,6 +228,10 @@ export type ApiSearchResult =
   | (BangHit & {
       type: 'bang';
     });
+export type AuthorFacet = {
+  author: string;
+  count: number;
+};
 export type Bang = {
   c?: string;
   d?: string;
@@ -262,15 +287,22 @@ export type DisplayedSidebar =
       };
     };
 export type DisplayedWebpage = {
+  archiveUrl?: string;
+  authors: string[];
   domain: string;
   likelyHasAds: boolean;
   likelyHasPaywall: boolean;
+  mayBeUnavailable: boolean;
   prettyUrl: string;
   rankingSignals?: {};
+  richResult?: RichResult;
   score?: number;
   site: string;
+  siteSearch?: SiteSearch;
   snippet: Snippet;
+  source: ResultSource;
   title: string;
+  topics: Topic[];
   url: string;
 };
 export type EntitySnippet = {
@@ -291,6 +323,10 @@ export type ExploreExportOpticParams = {
   chosenHosts: string[];
   similarHosts: string[];
 };
+export type FaqEntry = {
+  answer: string;
+  question: string;
+};
 export type FullEdge = {
   from: Node;
   label: string;
@@ -332,13 +368,47 @@ export type PartOfSpeechMeaning = {
   meanings: WordMeaning[];
   pos: PartOfSpeech;
 };
+export type QueryFeatures = {
+  hasOperators: boolean;
+  hasPhrase: boolean;
+  numTerms: number;
+};
+export type QueryHistory = {
+  numHeadSufficient: number;
+  numSearches: number;
+  numUnobserved: number;
+};
+export type QueryPlan = {
+  features: QueryFeatures;
+  history?: QueryHistory;
+  maxDocsConsidered?: number;
+  reason: string;
+  strategy: RetrievalStrategy;
+};
 export type Region = 'All' | 'Denmark' | 'France' | 'Germany' | 'Spain' | 'US';
 export const REGIONS = ['All', 'Denmark', 'France', 'Germany', 'Spain', 'US'] satisfies Region[];
+export type ResultSource = 'web' | 'live' | 'videos' | 'products' | 'scholar';
+export type RetrievalStrategy = 'headOnly' | 'staged' | 'allShards';
+export type RichResult =
+  | {
+      entries: FaqEntry[];
+      type: 'faq';
+    }
+  | {
+      name?: string;
+      steps: string[];
+      type: 'howTo';
+    };
 export type ScoredHost = {
   description?: string;
   host: string;
   score: number;
 };
+export type SearchCost = {
+  bytesRead: number;
+  cpuTimeUs: number;
+  docsScored: number;
+};
 export type SidebarQuery = {
   query: string;
 };
@@ -350,6 +420,18 @@ export type SimilarHostsParams = {
   hosts: string[];
   topN: number;
 };
+export type SiteInfo = {
+  avgQuality: number;
+  centrality: number;
+  crawlErrors: number;
+  host: string;
+  numPages: number;
+  topics: Topic[];
+};
+export type SiteSearch = {
+  site: string;
+  urlTemplate: string;
+};
 export type Snippet =
   | {
       date?: string;
@@ -394,11 +476,77 @@ export type ThesaurusWidget = {
   meanings: PartOfSpeechMeaning[];
   term: Lemma;
 };
+export type ThreatType = 'malware' | 'phishing' | 'unwanted';
+export type Topic =
+  | 'arts'
+  | 'business'
+  | 'computers'
+  | 'games'
+  | 'health'
+  | 'home'
+  | 'kids_and_teens'
+  | 'news'
+  | 'recreation'
+  | 'reference'
+  | 'science'
+  | 'shopping'
+  | 'society'
+  | 'sports';
+export const TOPICS = [
+  'arts',
+  'business',
+  'computers',
+  'games',
+  'health',
+  'home',
+  'kids_and_teens',
+  'news',
+  'recreation',
+  'reference',
+  'science',
+  'shopping',
+  'society',
+  'sports',
+] satisfies Topic[];
+export type TopicFacet = {
+  count: number;
+  topic: Topic;
+};
+export type UrlExclusion =
+  | {
+      type: 'invalidUrl';
+    }
+  | {
+      type: 'deleted';
+    }
+  | {
+      threat: ThreatType;
+      type: 'blocklisted';
+    };
+export type UrlStatus = {
+  canonicalUrl?: string;
+  excluded?: UrlExclusion;
+  indexed: boolean;
+  lastCrawl?: number;
+  lastStatusCode?: number;
+  lastSuccess?: number;
+  threat?: ThreatType;
+  url: string;
+};
+export type UrlStatusParams = {
+  urls: string[];
+};
 export type UrlWrapper = string;
 export type WebsitesResult = {
+  authorFacets: AuthorFacet[];
+  cost: SearchCost;
   hasMoreResults: boolean;
+  isPartial: boolean;
   numHits?: number;
+  plan?: QueryPlan;
   searchDurationMs: number;
+  segmentedQuery?: string;
+  topicFacets: TopicFacet[];
   webpages: DisplayedWebpage[];
 };
 export type Widget =